@@ -1,4 +1,14 @@
-use crate::commands::{config::config::ConfigCommands, remote::commands::RemoteCommands};
+use crate::commands::{
+    bisect::bisect::BisectCommands, bitmap::bitmap::BitmapCommands,
+    bundle::bundle::BundleCommands,
+    commit_graph::commit_graph::CommitGraphCommands,
+    config::config::ConfigCommands, lfs::lfs::LfsCommands, notes::notes::NotesCommands,
+    remote::commands::RemoteCommands, status::status::UntrackedFiles,
+    submodule::submodule::SubmoduleCommands,
+};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::compression::Codec;
+use crate::storage::merge::{MergeFavor, MergeStrategy};
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 use url::Url;
@@ -8,12 +18,39 @@ use url::Url;
 pub struct Cli {
     #[clap(subcommand)]
     pub command: Commands,
+
+    // Hidden to keep the usage line short, it is documented in the README
+    #[clap(
+        long,
+        global = true,
+        hide = true,
+        help = "Read and write the refs of this namespace (defaults to VOX_NAMESPACE)"
+    )]
+    pub namespace: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     #[command(about = "Initialize a new vox repository")]
-    Init,
+    Init {
+        #[clap(
+            long,
+            help = "Hash algorithm used to name objects (sha1 or sha256), sha1 by default"
+        )]
+        object_format: Option<ObjectFormat>,
+
+        #[clap(long, help = "Create a repository without a working tree")]
+        bare: bool,
+
+        #[clap(long, help = "Encrypt the objects with a new key kept in ~/.vox/keys")]
+        encrypt: bool,
+
+        #[clap(
+            long,
+            help = "Codec loose objects are compressed with (zlib or zstd), zlib by default"
+        )]
+        compression: Option<Codec>,
+    },
 
     #[command(about = "Provide content or type and size information for repository objects")]
     CatFile {
@@ -33,7 +70,69 @@ pub enum Commands {
     HashObject { file_path: String },
 
     #[command(about = "Show the working tree status")]
-    Status,
+    Status {
+        #[clap(help = "Only show the files matching these pathspecs")]
+        paths: Vec<String>,
+
+        #[clap(short, long, help = "Show one `XY path` line per changed file")]
+        short: bool,
+
+        #[clap(long, help = "Show the short format in a stable form for scripts")]
+        porcelain: bool,
+
+        #[clap(
+            short = 'z',
+            help = "End lines with NUL instead of newline and don't quote paths, implies --porcelain"
+        )]
+        nul: bool,
+
+        #[clap(
+            short = 'u',
+            long,
+            value_enum,
+            default_value = "normal",
+            help = "Which untracked files to list, `normal` shows untracked directories as `dir/`"
+        )]
+        untracked_files: UntrackedFiles,
+
+        #[clap(long, help = "Also list the files ignored by .voxignore and .vox/info/exclude")]
+        ignored: bool,
+    },
+
+    #[command(name = "check-attr", about = "Show the .voxattributes attributes of paths")]
+    CheckAttr {
+        #[clap(
+            help = "Attributes to show, then the paths unless they come after --, \
+                or only paths with --all"
+        )]
+        args: Vec<String>,
+
+        #[clap(last = true, help = "Paths to show the attributes of")]
+        paths: Vec<String>,
+
+        #[clap(short, long, help = "Show every attribute specified for the paths")]
+        all: bool,
+    },
+
+    #[command(name = "check-ignore", about = "Show which ignore rule matches each path")]
+    CheckIgnore {
+        #[clap(required = true, help = "Paths to check, a trailing / marks a directory")]
+        paths: Vec<String>,
+
+        #[clap(short, long, help = "Print the matching rule as `source:line:pattern`")]
+        verbose: bool,
+
+        #[clap(
+            short,
+            long,
+            requires = "verbose",
+            help = "Also print the paths no rule matches"
+        )]
+        non_matching: bool,
+
+        #[clap(long, help = "Don't leave out tracked files, which are otherwise never ignored")]
+        no_index: bool,
+    },
 
     #[command(about = "Remove files from the working tree and from the index")]
     Rm {
@@ -49,8 +148,16 @@ pub enum Commands {
 
     #[command(about = "Add file contents to the index")]
     Add {
-        #[clap(required_unless_present = "all")]
+        #[clap(required_unless_present = "update")]
         paths: Vec<PathBuf>,
+
+        #[clap(
+            short = 'u',
+            long,
+            conflicts_with = "paths",
+            help = "Stage the changes and deletions of every tracked file"
+        )]
+        update: bool,
     },
 
     #[command(name = "ls-files", about = "Show information about files in the index")]
@@ -67,17 +174,80 @@ pub enum Commands {
 
     #[command(about = "Record changes to the repository")]
     Commit {
-        #[clap(short = 'm', long)]
-        message: String,
+        #[clap(short = 'm', long, required_unless_present_any = ["amend", "fixup"])]
+        message: Option<String>,
 
-        #[clap(short = 'a', long)]
+        #[clap(long)]
         author: Option<String>,
+
+        #[clap(
+            short = 'a',
+            long,
+            help = "Stage modified and deleted tracked files before committing"
+        )]
+        all: bool,
+
+        #[clap(short = 'n', long, help = "Skip the pre-commit and commit-msg hooks")]
+        no_verify: bool,
+
+        #[clap(long, help = "Replace the tip of the current branch")]
+        amend: bool,
+
+        #[clap(short = 'S', long, help = "Sign the commit with the configured signing key")]
+        gpg_sign: bool,
+
+        #[clap(
+            long,
+            value_name = "COMMIT",
+            conflicts_with = "amend",
+            help = "Make a `fixup!` commit for `vox rebase -i --autosquash` to meld into COMMIT"
+        )]
+        fixup: Option<String>,
     },
 
     #[command(about = "Show commit logs")]
     Log {
         #[clap(short = 'n', long, default_value = "10")]
         count: usize,
+
+        #[clap(help = "Revision to start from (defaults to HEAD)")]
+        revision: Option<String>,
+
+        #[clap(long, help = "Draw the branch and merge graph of the history")]
+        graph: bool,
+
+        #[clap(long, help = "Show each commit on a single line")]
+        oneline: bool,
+
+        #[clap(long, help = "Only show commits whose author matches a pattern")]
+        author: Option<String>,
+
+        #[clap(long, alias = "after", help = "Only show commits more recent than a date")]
+        since: Option<String>,
+
+        #[clap(long, alias = "before", help = "Only show commits older than a date")]
+        until: Option<String>,
+
+        #[clap(long, help = "Only show commits whose message matches a pattern")]
+        grep: Option<String>,
+
+        #[clap(long, help = "Continue the history of a single path across renames")]
+        follow: bool,
+
+        #[clap(short = 'S', help = "Only show commits changing how often a string occurs")]
+        pickaxe: Option<String>,
+
+        #[clap(last = true, help = "Only show commits changing files matching these pathspecs")]
+        paths: Vec<String>,
+    },
+
+    #[command(about = "Show the history of updates to HEAD or a branch")]
+    Reflog {
+        #[clap(help = "Ref to show the log of (defaults to HEAD)")]
+        reference: Option<String>,
+
+        #[clap(short = 'n', long, help = "Limit the number of entries shown")]
+        count: Option<usize>,
     },
 
     #[command(about = "Show various types of objects")]
@@ -86,21 +256,487 @@ pub enum Commands {
         commit: String,
     },
 
+    #[command(about = "Summarize the history by author")]
+    Shortlog {
+        #[clap(help = "Revisions or ranges to summarize, e.g. v1.0..HEAD (defaults to HEAD)")]
+        revisions: Vec<String>,
+
+        #[clap(short, long, help = "Sort authors by number of commits")]
+        numbered: bool,
+
+        #[clap(short, long, help = "Only show the number of commits of each author")]
+        summary: bool,
+
+        #[clap(short, long, help = "Show the email of each author")]
+        email: bool,
+    },
+
+    #[command(about = "Export commits as mbox-style patch files")]
+    FormatPatch {
+        #[clap(
+            required = true,
+            help = "Range of commits to export, or a revision to export the commits since it"
+        )]
+        revisions: Vec<String>,
+
+        #[clap(short, long, help = "Directory to write the patch files to")]
+        output_directory: Option<PathBuf>,
+
+        #[clap(long, help = "Print all patches to stdout as a single mailbox")]
+        stdout: bool,
+
+        #[clap(long, help = "Include changed binary files so the patches can be applied")]
+        binary: bool,
+    },
+
+    #[command(about = "Apply mailbox patches as commits")]
+    Am {
+        #[clap(
+            required_unless_present_any = ["resume", "skip", "abort"],
+            help = "Patch files or mailboxes, e.g. written by format-patch"
+        )]
+        mailboxes: Vec<PathBuf>,
+
+        #[clap(
+            long = "continue",
+            conflicts_with_all = ["mailboxes", "skip", "abort"],
+            help = "Commit the staged resolution of the failed patch and go on"
+        )]
+        resume: bool,
+
+        #[clap(long, conflicts_with_all = ["mailboxes", "abort"], help = "Skip the failed patch")]
+        skip: bool,
+
+        #[clap(
+            long,
+            conflicts_with = "mailboxes",
+            help = "Stop and go back to where HEAD was before applying"
+        )]
+        abort: bool,
+    },
+
+    #[command(about = "Apply the changes of existing commits on top of HEAD")]
+    CherryPick {
+        #[clap(
+            required_unless_present_any = ["resume", "abort"],
+            help = "Commits to apply, or ranges such as A..B"
+        )]
+        revisions: Vec<String>,
+
+        #[clap(
+            long = "continue",
+            conflicts_with_all = ["revisions", "abort"],
+            help = "Commit the staged resolution of the conflicting commit and go on"
+        )]
+        resume: bool,
+
+        #[clap(
+            long,
+            conflicts_with = "revisions",
+            help = "Stop and go back to where HEAD was before"
+        )]
+        abort: bool,
+    },
+
+    #[command(about = "Make commits undoing existing commits")]
+    Revert {
+        #[clap(
+            required_unless_present_any = ["resume", "abort"],
+            help = "Commits to undo, or ranges such as A..B"
+        )]
+        revisions: Vec<String>,
+
+        #[clap(
+            long = "continue",
+            conflicts_with_all = ["revisions", "abort"],
+            help = "Commit the staged resolution of the conflicting commit and go on"
+        )]
+        resume: bool,
+
+        #[clap(
+            long,
+            conflicts_with = "revisions",
+            help = "Stop and go back to where HEAD was before"
+        )]
+        abort: bool,
+    },
+
+    #[command(about = "Replay the commits of the current branch on top of another commit")]
+    Rebase {
+        #[clap(
+            required_unless_present_any = ["resume", "abort"],
+            help = "Commit to replay the branch on, e.g. the branch it forked from"
+        )]
+        upstream: Option<String>,
+
+        #[clap(
+            short,
+            long,
+            conflicts_with_all = ["resume", "abort"],
+            help = "Edit the list of commits to pick, reword, edit, squash, fixup or drop first"
+        )]
+        interactive: bool,
+
+        #[clap(
+            long,
+            conflicts_with_all = ["resume", "abort"],
+            help = "Meld `fixup!` and `squash!` commits into the commits they name"
+        )]
+        autosquash: bool,
+
+        #[clap(
+            long = "continue",
+            conflicts_with_all = ["upstream", "abort"],
+            help = "Commit the staged resolution or amended commit and go on"
+        )]
+        resume: bool,
+
+        #[clap(
+            long,
+            conflicts_with = "upstream",
+            help = "Stop and go back to where the branch was before the rebase"
+        )]
+        abort: bool,
+    },
+
+    #[command(
+        name = "fast-export",
+        about = "Write history as a fast-import stream, e.g. for git fast-import"
+    )]
+    FastExport {
+        #[clap(help = "Branches and tags to export (defaults to all of them)")]
+        refs: Vec<String>,
+
+        #[clap(long, help = "Skip the commits listed in this marks file")]
+        import_marks: Option<PathBuf>,
+
+        #[clap(long, help = "Save the marks of the exported objects to this file")]
+        export_marks: Option<PathBuf>,
+    },
+
+    #[command(
+        name = "fast-import",
+        about = "Read a fast-import stream from stdin, e.g. from git fast-export"
+    )]
+    FastImport {
+        #[clap(long, help = "Load marks of earlier imports from this file")]
+        import_marks: Option<PathBuf>,
+
+        #[clap(long, help = "Save the marks of the imported objects to this file")]
+        export_marks: Option<PathBuf>,
+
+        #[clap(short, long, help = "Update refs even if they aren't fast-forwards")]
+        force: bool,
+    },
+
+    #[command(about = "Export the tree of a commit as a tar, tar.gz or zip archive")]
+    Archive {
+        #[clap(default_value = "HEAD", help = "Commit to archive")]
+        revision: String,
+
+        #[clap(help = "Only archive the files under these paths")]
+        paths: Vec<PathBuf>,
+
+        #[clap(long, help = "Archive format: tar, tar.gz or zip (guessed from --output)")]
+        format: Option<String>,
+
+        #[clap(short, long, help = "Write the archive to a file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[clap(long, default_value = "", help = "Prepend this to every path, e.g. 'name-1.0/'")]
+        prefix: String,
+    },
+
+    #[command(about = "Show which commit last changed each line of a file")]
+    Blame {
+        file: PathBuf,
+
+        #[clap(long, help = "Revision to blame the file at (defaults to HEAD)")]
+        revision: Option<String>,
+    },
+
+    #[command(about = "Find the best common ancestor of two commits")]
+    MergeBase {
+        first: String,
+        second: String,
+
+        #[clap(short, long, help = "Print all best common ancestors")]
+        all: bool,
+
+        #[clap(
+            long,
+            help = "Exit with 0 if the first commit is an ancestor of the second, 1 if not"
+        )]
+        is_ancestor: bool,
+    },
+
+    #[command(about = "Merge the changes of another branch into the current one")]
+    Merge {
+        #[clap(
+            required_unless_present = "abort",
+            help = "The branch or commit to merge, octopus merges of several are refused"
+        )]
+        revisions: Vec<String>,
+
+        #[clap(
+            long,
+            help = "Apply the changes to the index and working tree without committing them"
+        )]
+        squash: bool,
+
+        #[clap(long, help = "Stop before committing, as --squash always does")]
+        no_commit: bool,
+
+        #[clap(
+            short = 's',
+            long,
+            value_enum,
+            default_value_t,
+            conflicts_with = "favor",
+            help = "How the branch is merged: resolve merges the trees, ours keeps our tree"
+        )]
+        strategy: MergeStrategy,
+
+        #[clap(
+            short = 'X',
+            long = "strategy-option",
+            value_enum,
+            help = "Resolve conflicts with our version, their version or both"
+        )]
+        favor: Option<MergeFavor>,
+
+        #[clap(
+            long,
+            conflicts_with_all = ["revisions", "squash", "no_commit", "strategy", "favor"],
+            help = "Go back to the index and working tree of before the merge that stopped on conflicts"
+        )]
+        abort: bool,
+    },
+
+    #[command(about = "Resolve the conflicts a merge stopped on with a merge tool")]
+    Mergetool {
+        #[clap(help = "The conflicting paths to resolve, all of them by default")]
+        paths: Vec<PathBuf>,
+
+        #[clap(
+            short = 't',
+            long,
+            help = "Command merging $BASE, $LOCAL and $REMOTE into $MERGED, merge.tool by default"
+        )]
+        tool: Option<String>,
+    },
+
+    #[command(about = "Merge the changes from a base file to another file into a file")]
+    MergeFile {
+        #[clap(help = "The file to merge into")]
+        current: PathBuf,
+
+        #[clap(help = "The common ancestor of both files")]
+        base: PathBuf,
+
+        #[clap(help = "The file whose changes are merged")]
+        other: PathBuf,
+
+        #[clap(short = 'p', long, help = "Print the result instead of overwriting the file")]
+        stdout: bool,
+
+        #[clap(long, help = "Also show the base version in conflicts")]
+        diff3: bool,
+
+        #[clap(long, group = "favor", help = "Resolve conflicts with the current version")]
+        ours: bool,
+
+        #[clap(long, group = "favor", help = "Resolve conflicts with the other version")]
+        theirs: bool,
+
+        #[clap(long, group = "favor", help = "Resolve conflicts by keeping both versions")]
+        union: bool,
+
+        #[clap(
+            short = 'L',
+            value_name = "label",
+            help = "Name of the current, base and other version in the conflict markers"
+        )]
+        labels: Vec<String>,
+    },
+
+    #[command(about = "List commits reachable from the given revisions, newest first")]
+    RevList {
+        #[clap(help = "Revisions to start from, ^<rev> to exclude one, or <from>..<to>")]
+        revisions: Vec<String>,
+
+        #[clap(short = 'n', long, help = "Limit the number of commits listed")]
+        max_count: Option<usize>,
+
+        #[clap(long, help = "Start from every ref and HEAD")]
+        all: bool,
+
+        #[clap(long, help = "Also list the trees and blobs of the listed commits")]
+        objects: bool,
+    },
+
+    #[command(about = "Resolve revisions to object names")]
+    RevParse {
+        #[clap(required = true, help = "Revisions to resolve")]
+        revisions: Vec<String>,
+
+        #[clap(
+            long,
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "7",
+            help = "Print a unique abbreviation of at least this length (default 7)"
+        )]
+        short: Option<usize>,
+
+        #[clap(long, help = "Check that exactly one revision names an existing object")]
+        verify: bool,
+
+        #[clap(long, help = "Print the short name of the ref, e.g. the current branch for HEAD")]
+        abbrev_ref: bool,
+    },
+
+    #[command(about = "Check the signatures of commits")]
+    VerifyCommit {
+        #[clap(required = true, help = "Commits to verify")]
+        commits: Vec<String>,
+    },
+
+    #[command(about = "Check the signatures of annotated tags")]
+    VerifyTag {
+        #[clap(required = true, help = "Tags to verify")]
+        tags: Vec<String>,
+    },
+
+    #[command(about = "Watch the work tree so status only checks the files that changed")]
+    Watch,
+
+    #[command(about = "List, create, or delete tags")]
+    Tag {
+        #[clap(help = "Name of the tag to create or delete")]
+        name: Option<String>,
+
+        #[clap(help = "Commit or object to tag (defaults to HEAD)")]
+        commit: Option<String>,
+
+        #[clap(short, long, help = "Create an annotated tag")]
+        annotate: bool,
+
+        #[clap(short, long, help = "Message of the annotated tag")]
+        message: Option<String>,
+
+        #[clap(short, long, help = "Create a signed annotated tag")]
+        sign: bool,
+
+        #[clap(short, long, help = "Replace an existing tag")]
+        force: bool,
+
+        #[clap(short, long, help = "Delete the tag")]
+        delete: bool,
+
+        #[clap(short, long, help = "List tags")]
+        list: bool,
+    },
+
+    #[command(about = "List, create, or delete replacements of objects")]
+    Replace {
+        #[clap(help = "Object to replace, or to stop replacing with --delete")]
+        object: Option<String>,
+
+        #[clap(help = "Object to read in its place")]
+        replacement: Option<String>,
+
+        #[clap(short, long, help = "Overwrite an existing replacement, or one of another type")]
+        force: bool,
+
+        #[clap(short, long, help = "Delete the replacement of the object")]
+        delete: bool,
+    },
+
+    #[command(about = "Clone a repository into a new directory")]
+    Clone {
+        #[clap(help = "Path or file:// URL of the repository or bundle to clone")]
+        url: String,
+
+        #[clap(help = "Directory to clone into (defaults to the repository name)")]
+        directory: Option<PathBuf>,
+
+        #[clap(long, help = "Create a bare repository without a working tree")]
+        bare: bool,
+
+        #[clap(long, help = "Create a bare repository copying every ref as it is")]
+        mirror: bool,
+
+        #[clap(short, long, help = "Don't report progress")]
+        quiet: bool,
+
+        #[clap(long, help = "Report progress even when stderr isn't a terminal")]
+        progress: bool,
+
+        #[clap(
+            short,
+            long,
+            help = "Read objects from the source through the alternates instead of copying them"
+        )]
+        shared: bool,
+
+        #[clap(
+            short,
+            long,
+            help = "Check out this branch (or tag) instead of the one the source's HEAD points to"
+        )]
+        branch: Option<String>,
+
+        #[clap(long, help = "Only clone the branch that is checked out, and the tags")]
+        single_branch: bool,
+    },
+
     #[command(about = "List, create, or delete branches")]
     Branch {
         #[clap(help = "Branch name")]
         name: Option<String>,
 
+        #[clap(help = "Commit or remote-tracking branch to start the new branch from")]
+        start_point: Option<String>,
+
         #[clap(short, long)]
         delete: bool,
 
         #[clap(short, long)]
         list: bool,
+
+        #[clap(
+            short,
+            long,
+            action = clap::ArgAction::Count,
+            help = "Show commit subjects, twice to also show upstream and ahead/behind counts"
+        )]
+        verbose: u8,
     },
 
     Checkout {
-        #[clap(help = "Branch name ot commit_hash to checkout")]
-        target: String,
+        #[clap(
+            required_unless_present_any = ["new_branch", "reset_branch", "paths"],
+            help = "Branch name ot commit_hash to checkout, or the start point of a new branch"
+        )]
+        target: Option<String>,
+
+        #[clap(
+            short = 'b',
+            conflicts_with = "reset_branch",
+            help = "Create and switch to a new branch"
+        )]
+        new_branch: Option<String>,
+
+        #[clap(short = 'B', help = "Create or reset a branch and switch to it")]
+        reset_branch: Option<String>,
+
+        #[clap(
+            last = true,
+            conflicts_with_all = ["new_branch", "reset_branch"],
+            help = "Restore only these files from the commit (or the index), keeping HEAD"
+        )]
+        paths: Vec<PathBuf>,
 
         #[clap(
             short,
@@ -110,6 +746,62 @@ pub enum Commands {
         force: bool,
     },
 
+    #[command(about = "Switch branches")]
+    Switch {
+        #[clap(help = "Branch to switch to, or the start point of a new branch")]
+        target: Option<String>,
+
+        #[clap(short = 'c', long, conflicts_with = "force_create", help = "Create a new branch")]
+        create: Option<String>,
+
+        #[clap(short = 'C', long, help = "Create or reset a branch")]
+        force_create: Option<String>,
+
+        #[clap(long, help = "Switch to a commit, detaching HEAD")]
+        detach: bool,
+
+        #[clap(short, long, help = "Switch even if there are uncommitted changes")]
+        force: bool,
+    },
+
+    #[command(about = "Reset the current branch to a commit")]
+    Reset {
+        #[clap(help = "Commit to reset to (defaults to HEAD)")]
+        target: Option<String>,
+
+        #[clap(
+            long,
+            conflicts_with_all = ["mixed", "hard"],
+            help = "Keep the index and working tree"
+        )]
+        soft: bool,
+
+        #[clap(
+            long,
+            conflicts_with = "hard",
+            help = "Reset the index but not the working tree (default)"
+        )]
+        mixed: bool,
+
+        #[clap(long, help = "Reset the index and the working tree")]
+        hard: bool,
+    },
+
+    #[command(about = "Restore working tree files or staged content")]
+    Restore {
+        #[clap(required = true, help = "Files or directories to restore")]
+        paths: Vec<PathBuf>,
+
+        #[clap(short = 's', long, help = "Revision to restore the content from")]
+        source: Option<String>,
+
+        #[clap(short = 'S', long, help = "Restore the index")]
+        staged: bool,
+
+        #[clap(short = 'W', long, help = "Restore the working tree (default)")]
+        worktree: bool,
+    },
+
     Config {
         #[clap(long, help = "Use global configuration")]
         global: bool,
@@ -124,6 +816,88 @@ pub enum Commands {
         remote_cmd: RemoteCommands,
     },
 
+    #[command(about = "Add, show or remove notes attached to commits")]
+    Notes {
+        #[command(subcommand)]
+        notes_cmd: NotesCommands,
+    },
+
+    #[command(about = "Push or list the large files stored as LFS pointers")]
+    Lfs {
+        #[command(subcommand)]
+        lfs_cmd: LfsCommands,
+    },
+
+    #[command(
+        name = "commit-graph",
+        about = "Write or verify the changed-path filters of the commits"
+    )]
+    CommitGraph {
+        #[command(subcommand)]
+        graph_cmd: CommitGraphCommands,
+    },
+
+    #[command(about = "Write or verify the sets of objects reachable from each ref")]
+    Bitmap {
+        #[command(subcommand)]
+        bitmap_cmd: BitmapCommands,
+    },
+
+    #[command(about = "Find the commit that introduced a bug by binary search")]
+    Bisect {
+        #[command(subcommand)]
+        bisect_cmd: BisectCommands,
+    },
+
+    #[command(about = "Move refs and objects around as a single file")]
+    Bundle {
+        #[command(subcommand)]
+        bundle_cmd: BundleCommands,
+    },
+
+    #[command(about = "Initialize, update or inspect submodules")]
+    Submodule {
+        #[command(subcommand)]
+        submodule_cmd: SubmoduleCommands,
+    },
+
+    #[command(
+        name = "count-objects",
+        about = "Count loose objects and show repository statistics"
+    )]
+    CountObjects {
+        #[clap(short, long, help = "Show a detailed breakdown")]
+        verbose: bool,
+
+        #[clap(short = 'H', long, help = "Print sizes in human readable format")]
+        human_readable: bool,
+    },
+
+    #[command(about = "Verify the objects, moving corrupt ones to .vox/lost-found and recovering them")]
+    Fsck,
+
+    #[command(about = "Rewrite every object and ref under another hash algorithm")]
+    Migrate {
+        #[clap(
+            long,
+            required_unless_present = "compression",
+            help = "Hash algorithm to name objects with (sha1 or sha256)"
+        )]
+        to: Option<ObjectFormat>,
+
+        #[clap(long, help = "Codec to recompress the loose objects with (zlib or zstd)")]
+        compression: Option<Codec>,
+    },
+
+    #[command(about = "Remove unreachable loose objects from the object database")]
+    Prune {
+        #[clap(short = 'n', long, help = "Only report what would be removed")]
+        dry_run: bool,
+
+        #[clap(long, help = "Only prune objects older than the given date")]
+        expire: Option<String>,
+    },
+
     #[command(about = "Show changes between commits, commit and worktree, etc.")]
     Diff {
         #[clap(help = "The commit or reference to compare from")]
@@ -131,5 +905,109 @@ pub enum Commands {
 
         #[clap(help = "The commit or reference to compare to")]
         to: Option<String>,
+
+        #[clap(
+            long,
+            alias = "staged",
+            help = "Compare the index with HEAD, or the given commit, instead of the work tree"
+        )]
+        cached: bool,
+
+        #[clap(long, help = "Show the changed words inline instead of the changed lines")]
+        word_diff: bool,
+
+        #[clap(
+            short = 'M',
+            long = "find-renames",
+            value_name = "n",
+            num_args = 0..=1,
+            default_missing_value = "50%",
+            help = "Similarity for an added file to be a deleted one renamed (default 50%)"
+        )]
+        find_renames: Option<String>,
+
+        #[clap(
+            short = 'C',
+            long = "find-copies",
+            value_name = "n",
+            num_args = 0..=1,
+            default_missing_value = "50%",
+            help = "Also detect copies of changed files, at this similarity (default 50%)"
+        )]
+        find_copies: Option<String>,
+
+        #[clap(last = true, help = "Only compare the files matching these pathspecs")]
+        paths: Vec<String>,
+
+        #[clap(long, group = "diff_output", help = "Show a histogram of the changed lines")]
+        stat: bool,
+
+        #[clap(
+            long,
+            group = "diff_output",
+            help = "Show inserted and deleted line counts, tab separated"
+        )]
+        numstat: bool,
+
+        #[clap(long, group = "diff_output", help = "Show only the names of changed files")]
+        name_only: bool,
+
+        #[clap(
+            long,
+            group = "diff_output",
+            help = "Show the names and status letters of changed files"
+        )]
+        name_status: bool,
     },
 }
+
+impl Commands {
+    /// Returns true if the command reads or writes the working tree or the
+    /// index, which bare repositories don't have
+    pub fn needs_work_tree(&self) -> bool {
+        matches!(
+            self,
+            Commands::Status { .. }
+                | Commands::CheckAttr { .. }
+                | Commands::CheckIgnore { .. }
+                | Commands::Rm { .. }
+                | Commands::Add { .. }
+                | Commands::LsFiles { .. }
+                | Commands::WriteTree { .. }
+                | Commands::Commit { .. }
+                | Commands::Checkout { .. }
+                | Commands::Switch { .. }
+                | Commands::Restore { .. }
+                | Commands::Reset { .. }
+                | Commands::Submodule { .. }
+                | Commands::Bisect { .. }
+                | Commands::Am { .. }
+                | Commands::CherryPick { .. }
+                | Commands::Revert { .. }
+                | Commands::Rebase { .. }
+                | Commands::Merge { .. }
+                | Commands::Mergetool { .. }
+                | Commands::Watch
+        )
+    }
+
+    /// Commands creating a new repository rather than operating on the
+    /// current one
+    pub fn creates_repository(&self) -> bool {
+        matches!(self, Commands::Init { .. } | Commands::Clone { .. })
+    }
+
+    /// Returns true if the command operates on the current repository,
+    /// rather than creating one or only reading the files it's given
+    pub fn needs_repository(&self) -> bool {
+        !self.creates_repository()
+            && !matches!(
+                self,
+                Commands::HashObject { .. }
+                    | Commands::MergeFile { .. }
+                    | Commands::Bundle {
+                        bundle_cmd: BundleCommands::ListHeads { .. }
+                    }
+            )
+    }
+}
@@ -2,28 +2,84 @@ use std::path::PathBuf;
 
 use crate::cli::Commands;
 use crate::commands::branch::branch::branch_command;
-use crate::commands::branch::checkout::checkout_command;
-use crate::commands::log::log::log_command;
+use crate::commands::branch::checkout::{checkout_command, checkout_new_branch, checkout_paths};
+use crate::commands::branch::switch::switch_command;
+use crate::commands::log::log::{log_command, LogOptions};
+use crate::commands::prune::prune::prune_command;
+use crate::commands::reflog::reflog::reflog_command;
 use crate::commands::show::show::show_command;
 use crate::commands::write_tree::write_tree::write_tree_command;
 use crate::commands::{
-    add::add::add_command,
+    add::add::{add_command, add_tracked_command},
+    am::am::{am_command, AmAction},
+    archive::archive::{archive_command, ArchiveOptions},
+    bisect::bisect::bisect_command,
+    bitmap::bitmap::bitmap_command,
+    blame::blame::blame_command,
+    bundle::bundle::bundle_command,
     cat_file::cat_file::cat_file_command,
+    check_attr::check_attr::check_attr_command,
+    check_ignore::check_ignore::check_ignore_command,
+    cherry_pick::cherry_pick::{cherry_pick_command, revert_command, SequencerAction},
+    clone::{clone::{clone_command, CloneMode}, progress::ProgressMode},
     commit::commit::commit_command,
+    commit_graph::commit_graph::commit_graph_command,
     config::commands::config_command,
-    diff::diff::diff_command,
+    count_objects::count_objects::count_objects_command,
+    diff::diff::{diff_command, parse_similarity, DiffOptions, DiffOutput},
+    fast_export::fast_export::{fast_export_command, FastExportOptions},
+    fast_import::fast_import::{fast_import_command, FastImportOptions},
+    format_patch::format_patch::{format_patch_command, FormatPatchOptions},
+    fsck::fsck::fsck_command,
     hash_object::hash_object::{HashObjectArgs, hash_object_command},
     index::{ls_files::ls_files_command, rm_index::rm_command},
     init::init::init_command,
+    lfs::lfs::lfs_command,
+    merge::merge::{merge_command, MergeAction},
+    merge_base::merge_base::merge_base_command,
+    merge_file::merge_file::{merge_file_command, MergeFileOptions},
+    mergetool::mergetool::mergetool_command,
+    migrate::migrate::{migrate_command, migrate_compression},
+    notes::notes::notes_command,
+    rebase::rebase::{rebase_command, RebaseAction},
     remote::commands::remote_command,
-    status::status::status_command,
+    replace::replace::replace_command,
+    reset::reset::{reset_command, ResetMode},
+    restore::restore::restore_command,
+    rev_list::rev_list::{rev_list_command, RevListOptions},
+    rev_parse::rev_parse::{rev_parse_command, RevParseOptions},
+    shortlog::shortlog::{shortlog_command, ShortlogOptions},
+    status::status::{status_command, StatusFormat, StatusOptions},
+    submodule::submodule::submodule_command,
+    tag::tag::{tag_command, TagOptions},
+    verify::verify::{verify_commit_command, verify_tag_command},
+    watch::watch::watch_command,
 };
+use crate::storage::merge::{ConflictStyle, MergeFavor};
+use crate::storage::objects::tree::{RenameDetection, DEFAULT_SIMILARITY};
+use crate::storage::pathspec::Pathspec;
+use crate::storage::repo_config::{ensure_repository, ensure_repository_format, ensure_work_tree};
 use anyhow::Result;
 
 pub async fn handle_command(command: Commands) -> Result<()> {
+    if command.needs_repository() {
+        ensure_repository()?;
+    }
+    if !command.creates_repository() {
+        ensure_repository_format()?;
+    }
+    if command.needs_work_tree() {
+        ensure_work_tree()?;
+    }
+
     match command {
-        Commands::Init => {
-            init_command().await?;
+        Commands::Init {
+            object_format,
+            bare,
+            encrypt,
+            compression,
+        } => {
+            init_command(object_format, bare, encrypt, compression).await?;
         }
         Commands::CatFile {
             pretty_print,
@@ -36,12 +92,58 @@ pub async fn handle_command(command: Commands) -> Result<()> {
         Commands::HashObject { file_path } => {
             hash_object_command(HashObjectArgs { file_path })?;
         }
-        Commands::Status => {
-            status_command()?;
+        Commands::Status {
+            paths,
+            short,
+            porcelain,
+            nul,
+            untracked_files,
+            ignored,
+        } => {
+            let format = match (short, porcelain || nul) {
+                (true, _) => StatusFormat::Short,
+                (_, true) => StatusFormat::Porcelain,
+                _ => StatusFormat::Long,
+            };
+            let options = StatusOptions {
+                format,
+                nul_terminated: nul,
+                untracked: untracked_files,
+                ignored,
+            };
+            status_command(&Pathspec::new(&paths)?, &options)?;
         }
         Commands::LsFiles { stage } => {
             ls_files_command(stage)?;
         }
+        Commands::CheckAttr {
+            mut args,
+            mut paths,
+            all,
+        } => {
+            // Without --, the first argument is the attribute and the rest
+            // are paths, while --all takes paths only
+            let attrs = match (all, paths.is_empty()) {
+                (true, _) => {
+                    paths.splice(0..0, args);
+                    Vec::new()
+                }
+                (false, true) if !args.is_empty() => {
+                    paths = args.split_off(1);
+                    args
+                }
+                (false, _) => args,
+            };
+            check_attr_command(&attrs, &paths, all)?;
+        }
+        Commands::CheckIgnore {
+            paths,
+            verbose,
+            non_matching,
+            no_index,
+        } => {
+            check_ignore_command(&paths, verbose, non_matching, no_index)?;
+        }
         Commands::Rm {
             cashed,
             forced,
@@ -49,35 +151,478 @@ pub async fn handle_command(command: Commands) -> Result<()> {
         } => {
             rm_command(&paths, cashed, forced)?;
         }
-        Commands::Add { paths } => {
-            add_command(&paths)?;
-        }
+        Commands::Add { paths, update } => match update {
+            true => add_tracked_command()?,
+            false => add_command(&paths)?,
+        },
         Commands::WriteTree { path } => {
             write_tree_command(&path)?;
         }
-        Commands::Commit { message, author } => {
-            commit_command(&message, author)?;
+        Commands::Commit {
+            message,
+            author,
+            all,
+            no_verify,
+            amend,
+            gpg_sign,
+            fixup,
+        } => {
+            commit_command(
+                message.as_deref(),
+                author,
+                no_verify,
+                amend,
+                all,
+                gpg_sign,
+                fixup.as_deref(),
+            )?;
         }
-        Commands::Log { count } => {
-            log_command(count)?;
+        Commands::Log {
+            count,
+            revision,
+            graph,
+            oneline,
+            author,
+            since,
+            until,
+            grep,
+            follow,
+            pickaxe,
+            paths,
+        } => {
+            let options = LogOptions {
+                count,
+                revision,
+                graph,
+                oneline,
+                author,
+                since,
+                until,
+                grep,
+                paths,
+                follow,
+                pickaxe,
+            };
+            log_command(&options)?;
+        }
+        Commands::Reflog { reference, count } => {
+            reflog_command(reference, count)?;
         }
         Commands::Show { commit } => {
             show_command(&commit)?;
         }
-        Commands::Branch { name, delete, list } => {
-            branch_command(name, delete, list)?;
+        Commands::Shortlog {
+            revisions,
+            numbered,
+            summary,
+            email,
+        } => {
+            let options = ShortlogOptions {
+                numbered,
+                summary,
+                email,
+            };
+            shortlog_command(&revisions, &options)?;
+        }
+        Commands::FormatPatch {
+            revisions,
+            output_directory,
+            stdout,
+            binary,
+        } => {
+            let options = FormatPatchOptions {
+                output_directory,
+                stdout,
+                binary,
+            };
+            format_patch_command(&revisions, &options)?;
+        }
+        Commands::Am {
+            mailboxes,
+            resume,
+            skip,
+            abort,
+        } => {
+            let action = match (resume, skip, abort) {
+                (true, _, _) => AmAction::Continue,
+                (_, true, _) => AmAction::Skip,
+                (_, _, true) => AmAction::Abort,
+                _ => AmAction::Apply(mailboxes),
+            };
+            am_command(action)?;
+        }
+        Commands::CherryPick {
+            revisions,
+            resume,
+            abort,
+        } => {
+            let action = match (resume, abort) {
+                (true, _) => SequencerAction::Continue,
+                (_, true) => SequencerAction::Abort,
+                _ => SequencerAction::Start(revisions),
+            };
+            cherry_pick_command(action)?;
         }
-        Commands::Checkout { target, force } => {
-            checkout_command(&target, force, None)?;
+        Commands::Revert {
+            revisions,
+            resume,
+            abort,
+        } => {
+            let action = match (resume, abort) {
+                (true, _) => SequencerAction::Continue,
+                (_, true) => SequencerAction::Abort,
+                _ => SequencerAction::Start(revisions),
+            };
+            revert_command(action)?;
+        }
+        Commands::Rebase {
+            upstream,
+            interactive,
+            autosquash,
+            resume,
+            abort,
+        } => {
+            let action = match (resume, abort, upstream) {
+                (true, _, _) => RebaseAction::Continue,
+                (_, true, _) => RebaseAction::Abort,
+                (_, _, Some(upstream)) => RebaseAction::Start {
+                    upstream,
+                    interactive,
+                    autosquash,
+                },
+                _ => unreachable!("clap requires an upstream"),
+            };
+            rebase_command(action)?;
+        }
+        Commands::FastExport {
+            refs,
+            import_marks,
+            export_marks,
+        } => {
+            let options = FastExportOptions {
+                import_marks,
+                export_marks,
+            };
+            fast_export_command(&refs, &options)?;
         }
+        Commands::FastImport {
+            import_marks,
+            export_marks,
+            force,
+        } => {
+            let options = FastImportOptions {
+                import_marks,
+                export_marks,
+                force,
+            };
+            fast_import_command(&options)?;
+        }
+        Commands::Archive {
+            revision,
+            paths,
+            format,
+            output,
+            prefix,
+        } => {
+            let options = ArchiveOptions {
+                format: format.as_deref().map(str::parse).transpose()?,
+                output,
+                prefix,
+            };
+            archive_command(&revision, &paths, &options)?;
+        }
+        Commands::Blame { file, revision } => {
+            blame_command(&file, revision.as_deref())?;
+        }
+        Commands::MergeBase {
+            first,
+            second,
+            all,
+            is_ancestor,
+        } => {
+            merge_base_command(&first, &second, all, is_ancestor)?;
+        }
+        Commands::Merge {
+            revisions,
+            squash,
+            no_commit,
+            strategy,
+            favor,
+            abort,
+        } => {
+            let action = match abort {
+                true => MergeAction::Abort,
+                false => MergeAction::Start {
+                    revisions,
+                    squash,
+                    no_commit,
+                    strategy,
+                    favor,
+                },
+            };
+            merge_command(action)?;
+        }
+        Commands::Mergetool { paths, tool } => {
+            mergetool_command(tool.as_deref(), &paths)?;
+        }
+        Commands::MergeFile {
+            current,
+            base,
+            other,
+            stdout,
+            diff3,
+            ours,
+            theirs,
+            union,
+            labels,
+        } => {
+            let favor = [
+                (ours, MergeFavor::Ours),
+                (theirs, MergeFavor::Theirs),
+                (union, MergeFavor::Union),
+            ]
+            .into_iter()
+            .find_map(|(set, favor)| set.then_some(favor));
+            let options = MergeFileOptions {
+                stdout,
+                style: diff3.then_some(ConflictStyle::Diff3),
+                favor,
+                labels,
+            };
+            merge_file_command(&current, &base, &other, &options)?;
+        }
+        Commands::RevList {
+            revisions,
+            max_count,
+            all,
+            objects,
+        } => {
+            let options = RevListOptions {
+                max_count,
+                all,
+                objects,
+            };
+            rev_list_command(&revisions, &options)?;
+        }
+        Commands::RevParse {
+            revisions,
+            short,
+            verify,
+            abbrev_ref,
+        } => {
+            let options = RevParseOptions {
+                short,
+                verify,
+                abbrev_ref,
+            };
+            rev_parse_command(&revisions, &options)?;
+        }
+        Commands::VerifyCommit { commits } => {
+            verify_commit_command(&commits)?;
+        }
+        Commands::VerifyTag { tags } => {
+            verify_tag_command(&tags)?;
+        }
+        Commands::Watch => {
+            watch_command()?;
+        }
+        Commands::Tag {
+            name,
+            commit,
+            annotate,
+            message,
+            sign,
+            force,
+            delete,
+            list,
+        } => {
+            let options = TagOptions {
+                annotate,
+                message,
+                sign,
+                force,
+            };
+            tag_command(name.as_deref(), commit.as_deref(), delete, list, &options)?;
+        }
+        Commands::Replace {
+            object,
+            replacement,
+            force,
+            delete,
+        } => {
+            replace_command(object.as_deref(), replacement.as_deref(), delete, force)?;
+        }
+        Commands::Clone {
+            url,
+            directory,
+            bare,
+            mirror,
+            quiet,
+            progress,
+            shared,
+            branch,
+            single_branch,
+        } => {
+            let mode = match (mirror, bare) {
+                (true, _) => CloneMode::Mirror,
+                (false, true) => CloneMode::Bare,
+                (false, false) => CloneMode::Normal,
+            };
+            let progress = ProgressMode::from_flags(quiet, progress);
+            clone_command(
+                &url,
+                directory.as_deref(),
+                mode,
+                progress,
+                shared,
+                branch.as_deref(),
+                single_branch,
+            )?;
+        }
+        Commands::Branch {
+            name,
+            start_point,
+            delete,
+            list,
+            verbose,
+        } => {
+            branch_command(name, start_point, delete, list, verbose)?;
+        }
+        Commands::Checkout {
+            target,
+            new_branch,
+            reset_branch,
+            paths,
+            force,
+        } => match (new_branch, reset_branch, target) {
+            (None, None, source) if !paths.is_empty() => {
+                checkout_paths(source.as_deref(), &paths)?
+            }
+            (Some(name), _, start_point) => {
+                checkout_new_branch(&name, start_point.as_deref(), false, force)?
+            }
+            (None, Some(name), start_point) => {
+                checkout_new_branch(&name, start_point.as_deref(), true, force)?
+            }
+            (None, None, Some(target)) => checkout_command(&target, force, None)?,
+            (None, None, None) => unreachable!("clap requires a target"),
+        },
+        Commands::Switch {
+            target,
+            create,
+            force_create,
+            detach,
+            force,
+        } => switch_command(
+            target.as_deref(),
+            create.as_deref(),
+            force_create.as_deref(),
+            detach,
+            force,
+        )?,
+        Commands::Reset {
+            target,
+            soft,
+            mixed: _,
+            hard,
+        } => {
+            let mode = match (soft, hard) {
+                (true, _) => ResetMode::Soft,
+                (_, true) => ResetMode::Hard,
+                _ => ResetMode::Mixed,
+            };
+            reset_command(target.as_deref(), mode)?;
+        }
+        Commands::Restore {
+            paths,
+            source,
+            staged,
+            worktree,
+        } => restore_command(&paths, source.as_deref(), staged, worktree)?,
         Commands::Config { global, config_cmd } => {
             config_command(global, &config_cmd)?;
         }
         Commands::Remote { remote_cmd } => {
             remote_command(&remote_cmd)?;
         }
-        Commands::Diff { from, to } => {
-            diff_command(from, to)?;
+        Commands::Submodule { submodule_cmd } => {
+            submodule_command(&submodule_cmd)?;
+        }
+        Commands::Notes { notes_cmd } => {
+            notes_command(&notes_cmd)?;
+        }
+        Commands::Lfs { lfs_cmd } => {
+            lfs_command(&lfs_cmd)?;
+        }
+        Commands::CommitGraph { graph_cmd } => {
+            commit_graph_command(&graph_cmd)?;
+        }
+        Commands::Bitmap { bitmap_cmd } => {
+            bitmap_command(&bitmap_cmd)?;
+        }
+        Commands::Bundle { bundle_cmd } => {
+            bundle_command(&bundle_cmd)?;
+        }
+        Commands::Bisect { bisect_cmd } => {
+            bisect_command(&bisect_cmd)?;
+        }
+        Commands::CountObjects {
+            verbose,
+            human_readable,
+        } => {
+            count_objects_command(verbose, human_readable)?;
+        }
+        Commands::Fsck => {
+            fsck_command()?;
+        }
+        Commands::Migrate { to, compression } => {
+            if let Some(format) = to {
+                migrate_command(format)?;
+            }
+            if let Some(codec) = compression {
+                migrate_compression(codec)?;
+            }
+        }
+        Commands::Prune { dry_run, expire } => {
+            prune_command(dry_run, expire)?;
+        }
+        Commands::Diff {
+            from,
+            to,
+            cached,
+            word_diff,
+            find_renames,
+            find_copies,
+            paths,
+            stat,
+            numstat,
+            name_only,
+            name_status,
+        } => {
+            let output = match (stat, numstat, name_only, name_status) {
+                (true, _, _, _) => DiffOutput::Stat,
+                (_, true, _, _) => DiffOutput::Numstat,
+                (_, _, true, _) => DiffOutput::NameOnly,
+                (_, _, _, true) => DiffOutput::NameStatus,
+                _ => DiffOutput::Patch,
+            };
+            diff_command(
+                from,
+                to,
+                &DiffOptions {
+                    output,
+                    cached,
+                    pathspec: Pathspec::new(&paths)?,
+                    word_diff,
+                    renames: RenameDetection {
+                        threshold: match find_renames {
+                            Some(value) => parse_similarity(&value)?,
+                            None => DEFAULT_SIMILARITY,
+                        },
+                        copies: find_copies.as_deref().map(parse_similarity).transpose()?,
+                    },
+                },
+            )?;
         }
     }
     Ok(())
@@ -1,8 +1,17 @@
 use crate::commands::index::index::{Index, IndexEntry};
+use crate::commands::status::status::is_skipped_dir;
+use crate::error::VoxError;
+use crate::storage::convert::Converter;
+use crate::storage::fsmonitor;
+use crate::storage::hash::ObjectId;
+use crate::storage::ignore::IgnoreMatcher;
 use crate::storage::objects::blob::Blob;
+use crate::storage::platform::{self, FileStat, MODE_GITLINK};
+use crate::storage::submodules::{is_nested_repository, submodule_head};
 use anyhow::{Context, Result};
+use rayon::prelude::*;
 use std::{
-    env,
+    env, fs,
     path::{Path, PathBuf},
 };
 use walkdir::WalkDir;
@@ -12,6 +21,7 @@ pub struct AddCommand {
     repo_root: PathBuf,   // Root directory of the repository
     index: Index,         // Staging area index
     current_dir: PathBuf, // Current working directory
+    pending: Vec<(PathBuf, PathBuf)>, // Files to hash, as absolute and relative paths
 }
 
 impl AddCommand {
@@ -19,12 +29,23 @@ impl AddCommand {
     pub fn new() -> Result<Self> {
         let repo_root = Self::find_repository_root()?;
         let current_dir = env::current_dir()?;
+        Self::with_dirs(repo_root, current_dir)
+    }
+
+    /// Stages files of the work tree at `repo_root`, with paths given
+    /// relative to it rather than to the current directory
+    pub fn at(repo_root: &Path) -> Result<Self> {
+        Self::with_dirs(repo_root.to_path_buf(), repo_root.to_path_buf())
+    }
+
+    fn with_dirs(repo_root: PathBuf, current_dir: PathBuf) -> Result<Self> {
         let index = Self::load_or_create_index(&repo_root)?;
 
         Ok(Self {
             repo_root,
             index,
             current_dir,
+            pending: Vec::new(),
         })
     }
 
@@ -42,6 +63,42 @@ impl AddCommand {
             self.add_path(path, &relative_base)?;
         }
 
+        self.stage_pending()?;
+        self.save_index()
+    }
+
+    /// Stages every tracked file that was modified or deleted
+    /// Untracked files are left alone
+    pub fn execute_tracked(mut self) -> Result<()> {
+        let entries: Vec<IndexEntry> = self.index.get_entries().values().cloned().collect();
+        let answer = fsmonitor::query(&self.repo_root.join(".vox"), &self.index.fsmonitor.token);
+
+        for entry in entries {
+            // Entries `vox watch` saw no change to since status checked them
+            if let Some(answer) = &answer
+                && self.index.fsmonitor.is_valid(&entry.path)
+                && !answer.is_changed(&entry.path)
+            {
+                continue;
+            }
+
+            let abs_path = self.repo_root.join(&entry.path);
+
+            if fs::symlink_metadata(&abs_path).is_err() {
+                self.index.remove_entry(&entry.path);
+            } else if entry.stat().tree_mode() == MODE_GITLINK {
+                if is_nested_repository(&abs_path) {
+                    self.create_gitlink_entry(&abs_path, &entry.path)?;
+                }
+            } else if abs_path.is_dir() && !platform::is_symlink(&abs_path) {
+                // A file replaced by a directory isn't tracked anymore
+                self.index.remove_entry(&entry.path);
+            } else if !FileStat::from_path(&abs_path)?.matches(&entry.stat()) {
+                self.create_index_entry(&abs_path, &entry.path)?;
+            }
+        }
+
+        self.stage_pending()?;
         self.save_index()
     }
 
@@ -53,7 +110,7 @@ impl AddCommand {
                 return Ok(current);
             }
             if !current.pop() {
-                return Err(anyhow::anyhow!("Not a vox repository (or any parent)"));
+                return Err(VoxError::NotARepository.into());
             }
         }
     }
@@ -76,36 +133,56 @@ impl AddCommand {
         let absolute_path = self.current_dir.join(path);
         let repo_root = self.repo_root.clone();
 
-        if absolute_path.is_file() {
-            // Handle single file
+        if absolute_path.is_file() || platform::is_symlink(&absolute_path) {
+            // Handle single file or symlink, links are never followed
             let relative_path = if let Ok(rel) = path.strip_prefix(&self.repo_root) {
                 rel.to_path_buf()
             } else {
                 relative_base.join(path)
             };
             self.create_index_entry(&absolute_path, &relative_path)?;
+        } else if absolute_path.is_dir() && self.is_submodule(&absolute_path) {
+            // Handle a nested repository as a submodule
+            let relative_path = relative_base.join(path);
+            self.create_gitlink_entry(&absolute_path, &relative_path)?;
         } else if absolute_path.is_dir() {
             // Handle directory recursively
-            // Filter out VOX directories and build artifacts
-            for entry in WalkDir::new(&absolute_path)
-                .min_depth(1)
-                .into_iter()
-                .filter_entry(move |e| {
-                    !e.path().starts_with(repo_root.join(".vox"))
-                        && !e.path().starts_with(repo_root.join(".git"))
-                        && !e.path().starts_with(repo_root.join("target"))
-                        && !e.path().starts_with(repo_root.join("build"))
-                })
-            {
+            // Untracked files are left out like status leaves them out: the
+            // repository directories and what the ignore rules match
+            let mut ignore = IgnoreMatcher::new(&repo_root)?;
+            let mut walker = WalkDir::new(&absolute_path).min_depth(1).into_iter();
+
+            while let Some(entry) = walker.next() {
                 let entry = entry.context("Failed to read directory entry")?;
-                if !entry.file_type().is_file() {
-                    continue;
-                }
 
                 // Convert to repository-relative path
                 let relative_path = entry.path().strip_prefix(&self.current_dir)?.to_path_buf();
                 let relative_path = relative_base.join(&relative_path);
 
+                if entry.file_type().is_dir() {
+                    // Nested repositories are added as submodules, not walked
+                    if self.is_submodule(entry.path()) {
+                        self.create_gitlink_entry(entry.path(), &relative_path)?;
+                        walker.skip_current_dir();
+                    } else if is_skipped_dir(&repo_root, &relative_path)
+                        || (ignore.is_ignored(&relative_path, true)?
+                            && !self.tracks_below(&relative_path))
+                    {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+                if !entry.file_type().is_file() && !entry.file_type().is_symlink() {
+                    continue;
+                }
+                // Ignoring a file doesn't stop changes to it from being staged
+                // once it's tracked
+                if self.index.get_entry(&relative_path).is_none()
+                    && ignore.is_ignored(&relative_path, false)?
+                {
+                    continue;
+                }
+
                 self.create_index_entry(entry.path(), &relative_path)?;
             }
         } else {
@@ -115,24 +192,77 @@ impl AddCommand {
         Ok(())
     }
 
-    /// Creates an index entry for a file
-    /// Generates blob hash and updates index
+    /// Returns true if the index has entries in the directory `dir`
+    fn tracks_below(&self, dir: &Path) -> bool {
+        self.index.get_entries().keys().any(|path| path.starts_with(dir))
+    }
+
+    /// Queues a file to be staged
+    /// Files are hashed all at once by `stage_pending`
     fn create_index_entry(&mut self, abs_path: &Path, rel_path: &Path) -> Result<()> {
-        // Create blob object from file content
-        let blob_hash = Blob::blob_hash(
-            abs_path
-                .to_str()
-                .ok_or_else(|| anyhow::anyhow!("Invalid path"))?,
-        )?;
-
-        // Convert hex hash to bytes
-        let hash_bytes = hex::decode(blob_hash.as_str())
+        self.pending.push((abs_path.to_path_buf(), rel_path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Hashes and stores the queued files in parallel, then adds their entries
+    /// Each worker has its own converter, as converters cache what they look up
+    fn stage_pending(&mut self) -> Result<()> {
+        let objects_dir = self.repo_root.join(".vox/objects");
+        let pending = std::mem::take(&mut self.pending);
+        let entries = pending
+            .par_iter()
+            .map_init(
+                || Converter::new(&self.repo_root),
+                |converter, (abs_path, rel_path)| {
+                    Self::file_entry(abs_path, rel_path, converter, &objects_dir)
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+
+        for entry in entries {
+            self.index.add_entry(entry);
+        }
+        Ok(())
+    }
+
+    /// Creates the index entry of a file
+    /// Generates the blob hash and stores the blob
+    fn file_entry(
+        abs_path: &Path,
+        rel_path: &Path,
+        converter: &mut Converter,
+        objects_dir: &Path,
+    ) -> Result<IndexEntry> {
+        // Create blob object from file content, or the target of a symlink
+        let blob_hash = Blob::save_worktree(abs_path, rel_path, converter, objects_dir)?;
+
+        let mut entry = IndexEntry::new(abs_path)?;
+        entry.path = rel_path.to_path_buf();
+        entry.hash = ObjectId::from_hex(&blob_hash)
             .with_context(|| format!("Failed to decode blob hash: {}", blob_hash))?;
+        Ok(entry)
+    }
+
+    /// Returns true if `path` is a nested repository other than this one
+    fn is_submodule(&self, path: &Path) -> bool {
+        is_nested_repository(path) && path.canonicalize().ok() != self.repo_root.canonicalize().ok()
+    }
+
+    /// Creates an index entry for a submodule
+    /// Records the commit checked out in the nested repository
+    fn create_gitlink_entry(&mut self, abs_path: &Path, rel_path: &Path) -> Result<()> {
+        let head = submodule_head(abs_path)?.with_context(|| {
+            format!(
+                "Submodule '{}' has no commit checked out",
+                rel_path.display()
+            )
+        })?;
 
-        // Create and update index entry
         let mut entry = IndexEntry::new(abs_path)?;
         entry.path = rel_path.to_path_buf();
-        entry.hash.copy_from_slice(&hash_bytes);
+        entry.mode = MODE_GITLINK;
+        entry.hash = ObjectId::from_hex(&head)
+            .with_context(|| format!("Invalid submodule commit: {}", head))?;
 
         self.index.add_entry(entry);
         Ok(())
@@ -148,3 +278,125 @@ impl AddCommand {
 pub fn add_command(paths: &[PathBuf]) -> Result<()> {
     AddCommand::new()?.execute(paths)
 }
+
+/// Stages the changes of every tracked file, for `vox add -u`
+pub fn add_tracked_command() -> Result<()> {
+    AddCommand::new()?.execute_tracked()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::hash::ObjectFormat;
+    use crate::storage::objects::tree::{create_tree, Tree, TreeEntry};
+    use crate::storage::objects::Storable;
+    use crate::storage::utils::{OBJ_TYPE_BLOB, OBJ_TYPE_TREE, PERM_DIR, PERM_FILE};
+    use crate::vox_repo::VoxRepo;
+    use tempfile::tempdir;
+
+    /// Paths staged in the index of the work tree at `root`
+    fn staged(root: &Path) -> Result<Vec<PathBuf>> {
+        let index = AddCommand::load_or_create_index(root)?;
+        let mut paths: Vec<PathBuf> = index.get_entries().keys().cloned().collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    #[test]
+    fn test_add_leaves_out_ignored_files() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        VoxRepo::init(root, ObjectFormat::Sha1, false)?;
+        for (path, content) in [
+            (".voxignore", "target/\n*.log\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("target/debug/app", "binary"),
+            ("logs/run.log", "log"),
+            ("tracked.log", "log"),
+        ] {
+            fs::create_dir_all(root.join(path).parent().unwrap())?;
+            fs::write(root.join(path), content)?;
+        }
+
+        // Tracked files are staged even when a rule matches them
+        AddCommand::at(root)?.execute(&[PathBuf::from("tracked.log")])?;
+        fs::write(root.join("tracked.log"), "more log")?;
+        AddCommand::at(root)?.execute(&[PathBuf::from(".")])?;
+
+        let expected: Vec<PathBuf> = [".voxignore", "src/main.rs", "tracked.log"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(staged(root)?, expected);
+        Ok(())
+    }
+
+    /// Stores the files below `dir` one at a time, in name order
+    ///
+    /// # Returns
+    ///
+    /// The hash of the tree of `dir`
+    fn sequential_tree(dir: &Path, objects_dir: &Path) -> Result<String> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        paths.sort();
+
+        let mut entries = Vec::new();
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let (mode, object_type, object_hash) = match path.is_dir() {
+                true => (
+                    PERM_DIR,
+                    OBJ_TYPE_TREE,
+                    sequential_tree(&path, objects_dir)?,
+                ),
+                false => {
+                    let blob = Blob {
+                        data: fs::read(&path)?,
+                    };
+                    (PERM_FILE, OBJ_TYPE_BLOB, blob.save(objects_dir)?)
+                }
+            };
+            entries.push(TreeEntry {
+                mode: mode.to_string(),
+                object_type: object_type.to_string(),
+                object_hash,
+                name,
+            });
+        }
+        Tree { entries }.save(objects_dir)
+    }
+
+    #[test]
+    fn test_parallel_hashing_matches_sequential() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        VoxRepo::init(root, ObjectFormat::Sha1, false)?;
+        let objects_dir = root.join(".vox/objects");
+        for dir in ["src/commands/add", "src/storage", "docs", "tests/data"] {
+            fs::create_dir_all(root.join(dir))?;
+            for file in 0..8 {
+                let content = format!("{} {}\n", dir, file).repeat(file * 100 + 1);
+                fs::write(root.join(dir).join(format!("file{}.txt", file)), content)?;
+            }
+        }
+        fs::write(root.join("README"), "readme\n")?;
+        let expected = sequential_tree(root, &objects_dir)?;
+
+        AddCommand::at(root)?.execute(&[PathBuf::from(".")])?;
+        let mut index = AddCommand::load_or_create_index(root)?;
+        assert_eq!(index.get_entries().len(), 33);
+        for (path, entry) in index.get_entries() {
+            let data = fs::read(root.join(path))?;
+            assert_eq!(entry.hash, ObjectFormat::Sha1.digest(&data));
+        }
+        assert_eq!(index.write_tree(&objects_dir)?, expected);
+
+        assert_eq!(create_tree(root, &objects_dir)?.save(&objects_dir)?, expected);
+        Ok(())
+    }
+}
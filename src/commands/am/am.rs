@@ -0,0 +1,351 @@
+use crate::commands::commit::commit::get_current_commit;
+use crate::commands::commit::replay::{commit_index, ReplayState};
+use crate::commands::format_patch::format_patch::split_message;
+use crate::commands::index::index::{Index, IndexEntry};
+use crate::commands::restore::restore::restore_paths;
+use crate::commands::status::status::get_status;
+use crate::error::VoxError;
+use crate::storage::hash::ObjectId;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::Storable;
+use crate::storage::patch::{apply_hunks, parse_diff};
+use crate::storage::pathspec::Pathspec;
+use crate::storage::platform;
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, PERM_EXEC, PERM_FILE, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the patches of an `am` session in progress
+const AM_STATE: ReplayState = ReplayState::new("rebase-apply", "am");
+/// Number of the patch being applied
+const AM_NEXT: &str = "next";
+/// Number of the last patch
+const AM_LAST: &str = "last";
+/// The commit HEAD pointed to when the session started, restored by `--abort`
+const AM_ORIG_HEAD: &str = "orig-head";
+
+/// What `vox am` should do
+#[derive(Debug)]
+pub enum AmAction {
+    /// Apply the patches of these mailboxes
+    Apply(Vec<PathBuf>),
+    /// Commit the staged resolution of the failed patch and go on
+    Continue,
+    /// Drop the failed patch and go on
+    Skip,
+    /// Go back to where HEAD was before the session
+    Abort,
+}
+
+/// A patch mail, as written by `format-patch`
+#[derive(Debug, PartialEq, Eq)]
+pub struct PatchMail {
+    /// The `From` header, `Name <email>`
+    pub author: String,
+    /// The `Date` header, if it could be parsed
+    pub date: Option<DateTime<Utc>>,
+    /// The subject without its `[PATCH]` prefix, followed by the body
+    pub message: String,
+    /// Everything from the first `diff --git` line on
+    pub diff: String,
+}
+
+/// Applies patch mails as commits, keeping their author, date and message
+///
+/// The patches are stored in `.vox/rebase-apply` first, so when one doesn't
+/// apply the session can be resumed with `--continue` once the change is
+/// made and staged by hand, or with `--skip`, or given up with `--abort`.
+pub fn am_command(action: AmAction) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let in_progress = AM_STATE.file(AM_NEXT).exists();
+    match action {
+        AmAction::Apply(_) if in_progress => {
+            bail!("An am session is in progress, use --continue, --skip or --abort")
+        }
+        AmAction::Apply(mailboxes) => start(&mailboxes)?,
+        _ if !in_progress => bail!("No am session in progress"),
+        AmAction::Continue => {
+            let next = read_number(AM_NEXT)?;
+            let mail = parse_mail(&fs::read_to_string(patch_file(next))?)?;
+            commit_mail(&mail)?;
+            write_number(AM_NEXT, next + 1)?;
+        }
+        AmAction::Skip => {
+            restore_paths(&[PathBuf::new()], Some("HEAD"), true, true)?;
+            write_number(AM_NEXT, read_number(AM_NEXT)? + 1)?;
+        }
+        AmAction::Abort => return AM_STATE.abort(AM_ORIG_HEAD),
+    }
+    apply_remaining()
+}
+
+/// Splits a mailbox into its mails, each starting with a `From <hash>` line
+pub fn split_mailbox(mailbox: &str) -> Vec<String> {
+    let mut mails: Vec<String> = Vec::new();
+    for line in mailbox.split_inclusive('\n') {
+        if line.starts_with("From ") || mails.is_empty() {
+            mails.push(String::new());
+        }
+        if let Some(mail) = mails.last_mut() {
+            mail.push_str(line);
+        }
+    }
+    mails.retain(|mail| mail.contains("diff --git "));
+    mails
+}
+
+/// Parses a patch mail into its author, date, message and diff
+pub fn parse_mail(mail: &str) -> Result<PatchMail> {
+    let (headers, rest) = mail
+        .split_once("\n\n")
+        .context("Invalid patch: no blank line after the headers")?;
+
+    // Long headers are folded onto lines starting with whitespace
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in headers.lines() {
+        match (line.starts_with([' ', '\t']), fields.last_mut()) {
+            (true, Some((_, value))) => value.push_str(line),
+            _ => {
+                if let Some((name, value)) = line.split_once(':') {
+                    fields.push((name.to_ascii_lowercase(), value.trim().to_string()));
+                }
+            }
+        }
+    }
+    let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+
+    let author = field("from").context("Invalid patch: no From header")?.to_string();
+    let date = field("date")
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+        .map(|date| date.with_timezone(&Utc));
+    let mut subject = field("subject").unwrap_or_default();
+    while let Some(tag_end) = subject.strip_prefix('[').and_then(|s| s.find(']')) {
+        subject = subject[tag_end + 2..].trim_start();
+    }
+
+    let diff_start = rest.find("diff --git ").context("Invalid patch: no diff")?;
+    let body: Vec<&str> = rest[..diff_start]
+        .lines()
+        .take_while(|line| *line != "---")
+        .collect();
+    let body = body.join("\n");
+    let message = match body.trim() {
+        "" => subject.to_string(),
+        body => format!("{}\n\n{}", subject, body),
+    };
+
+    Ok(PatchMail {
+        author,
+        date,
+        message,
+        diff: rest[diff_start..].to_string(),
+    })
+}
+
+fn patch_file(number: usize) -> PathBuf {
+    AM_STATE.file(&format!("{:04}", number))
+}
+
+fn read_number(name: &str) -> Result<usize> {
+    let content = fs::read_to_string(AM_STATE.file(name))?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("Corrupt am state in {}", name))
+}
+
+fn write_number(name: &str, number: usize) -> Result<()> {
+    fs::write(AM_STATE.file(name), format!("{}\n", number))?;
+    Ok(())
+}
+
+/// Stores the mails of the mailboxes as numbered patches
+fn start(mailboxes: &[PathBuf]) -> Result<()> {
+    let (_added, modified, deleted, _untracked) =
+        get_status(Path::new("."), &Pathspec::default())?;
+    if !modified.is_empty() || !deleted.is_empty() {
+        bail!("You have uncommitted changes. Commit or stash them before applying patches");
+    }
+
+    let mut mails = Vec::new();
+    for mailbox in mailboxes {
+        let content = fs::read_to_string(mailbox)
+            .with_context(|| format!("Failed to read {}", mailbox.display()))?;
+        mails.extend(split_mailbox(&content));
+    }
+    if mails.is_empty() {
+        bail!("No patches found");
+    }
+
+    fs::create_dir_all(AM_STATE.dir()).context("Failed to create the am state")?;
+    for (number, mail) in mails.iter().enumerate() {
+        fs::write(patch_file(number + 1), mail)?;
+    }
+    fs::write(AM_STATE.file(AM_ORIG_HEAD), get_current_commit()?.unwrap_or_default())?;
+    write_number(AM_LAST, mails.len())?;
+    write_number(AM_NEXT, 1)
+}
+
+/// Applies the patches from `next` on, stopping at the first that fails
+fn apply_remaining() -> Result<()> {
+    let last = read_number(AM_LAST)?;
+    loop {
+        let next = read_number(AM_NEXT)?;
+        if next > last {
+            fs::remove_dir_all(AM_STATE.dir())?;
+            return Ok(());
+        }
+
+        let mail = parse_mail(&fs::read_to_string(patch_file(next))?)?;
+        let (subject, _) = split_message(&mail.message);
+        println!("Applying: {}", subject);
+        if let Err(error) = apply_diff(&mail.diff) {
+            bail!(
+                "{:#}\nPatch failed at {:04} {}\n{}",
+                error,
+                next,
+                subject,
+                "Apply the change by hand, stage it and run 'vox am --continue', or use \
+                 'vox am --skip' or 'vox am --abort'"
+                    .yellow()
+            );
+        }
+        commit_mail(&mail)?;
+        write_number(AM_NEXT, next + 1)?;
+    }
+}
+
+/// A change to a file computed from a patch, made once every file applies
+enum FileChange {
+    /// Write the content with the mode
+    Write(PathBuf, Vec<u8>, String),
+    /// Remove the file
+    Remove(PathBuf),
+}
+
+/// Applies a diff to the working tree and the index
+///
+/// Every file is patched in memory first, so a patch that doesn't apply
+/// leaves both untouched.
+fn apply_diff(diff: &str) -> Result<()> {
+    let mut index = Index::new();
+    index.read_from_file(&INDEX_FILE)?;
+
+    let mut changes = Vec::new();
+    for patch in parse_diff(diff)? {
+        let old_path = patch.old_path.as_deref().map(PathBuf::from);
+        let name = patch.new_path.as_deref().or(patch.old_path.as_deref());
+        let name = name.unwrap_or_default().to_string();
+        let new_content = match (patch.binary, patch.binary_content) {
+            (true, Some(content)) => content,
+            // A deleted file needs no content
+            (true, None) if patch.new_path.is_none() => Vec::new(),
+            (true, None) => bail!("{}: binary patches without the content can't be applied", name),
+            (false, _) => {
+                let old_content = match &old_path {
+                    Some(path) => fs::read_to_string(path)
+                        .with_context(|| format!("{}: does not exist in the working tree", name))?,
+                    None => String::new(),
+                };
+                apply_hunks(&old_content, &patch.hunks)
+                    .with_context(|| name.clone())?
+                    .into_bytes()
+            }
+        };
+
+        if let Some(old_path) = &old_path
+            && patch.new_path.as_deref() != old_path.to_str()
+        {
+            changes.push(FileChange::Remove(old_path.clone()));
+        }
+        if let Some(new_path) = patch.new_path {
+            let old_mode = old_path
+                .as_deref()
+                .and_then(|path| index.get_entry(path))
+                .map(|entry| format!("{:o}", entry.stat().tree_mode()));
+            let mode = patch.new_mode.or(old_mode).unwrap_or(PERM_FILE.to_string());
+            changes.push(FileChange::Write(PathBuf::from(new_path), new_content, mode));
+        }
+    }
+
+    for change in changes {
+        match change {
+            FileChange::Write(path, content, mode) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, &content)?;
+                platform::set_executable(&path, mode == PERM_EXEC)?;
+
+                let hash = Blob { data: content }.save(&OBJ_DIR)?;
+                let mut entry = IndexEntry::new(&path)?;
+                entry.hash = ObjectId::from_hex(&hash)?;
+                index.add_entry(entry);
+            }
+            FileChange::Remove(path) => {
+                if path.exists() {
+                    fs::remove_file(&path)?;
+                }
+                index.remove_entry(&path);
+            }
+        }
+    }
+    index.write_to_file(&INDEX_FILE)
+}
+
+/// Commits the index with the author, date and message of `mail`
+fn commit_mail(mail: &PatchMail) -> Result<()> {
+    let (subject, _) = split_message(&mail.message);
+    let head = get_current_commit()?;
+    let committed = commit_index(
+        head.as_deref(),
+        &mail.author,
+        mail.date,
+        mail.message.clone(),
+        &format!("am: {}", subject),
+    )?;
+    if committed.is_none() {
+        println!("No changes -- Patch already applied.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mail() -> Result<()> {
+        let mailbox = "From 1234 Mon Sep 17 00:00:00 2001\n\
+                       From: Ada <ada@example.com>\n\
+                       Date: Thu, 15 Oct 2026 13:40:40 +0200\n\
+                       Subject: [PATCH 2/3] Fix the\n parser\n\
+                       \n\
+                       Details.\n\
+                       ---\n\
+                       \x20f | 2 +-\n\
+                       \n\
+                       diff --git a/f b/f\n\
+                       @@ -1 +1 @@\n-a\n+b\n\
+                       From 5678 Mon Sep 17 00:00:00 2001\n\
+                       From: Bob <bob@example.com>\n\
+                       Subject: Without diff\n\
+                       \n\
+                       Nothing here.\n";
+        let mails = split_mailbox(mailbox);
+        assert_eq!(mails.len(), 1);
+
+        let mail = parse_mail(&mails[0])?;
+        assert_eq!(mail.author, "Ada <ada@example.com>");
+        assert_eq!(mail.message, "Fix the parser\n\nDetails.");
+        assert_eq!(mail.date.map(|date| date.timestamp()), Some(1792064440));
+        assert!(mail.diff.starts_with("diff --git a/f b/f\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use crate::error::VoxError;
+use crate::storage::archive::{archive_writer, ArchiveFormat, ArchiveWriter, EntryKind};
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::read_raw_object;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::Loadable;
+use crate::storage::platform::to_index_path;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_COMMIT, PERM_EXEC, PERM_SYMLINK, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Options of the `archive` command
+#[derive(Debug, Default)]
+pub struct ArchiveOptions {
+    /// Archive format, guessed from the output file name if not given and
+    /// tar otherwise
+    pub format: Option<ArchiveFormat>,
+    /// File to write the archive to instead of stdout
+    pub output: Option<PathBuf>,
+    /// Prepended to every path in the archive, e.g. `project-1.0/`
+    pub prefix: String,
+}
+
+/// Writes the tree of a commit to a tar, tar.gz or zip archive without
+/// checking it out
+///
+/// # Arguments
+/// - `revision`: the commit to archive
+/// - `paths`: only archive the files under these paths (everything if empty)
+/// - `options`: format, output and prefix of the archive
+///
+pub fn archive_command(revision: &str, paths: &[PathBuf], options: &ArchiveOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let commit_hash = resolve_commit(&VOX_DIR, revision)?;
+    let commit = Commit::load(&commit_hash, &OBJ_DIR)?;
+    let entries = read_tree(&commit.tree, &OBJ_DIR)?.flatten(&OBJ_DIR)?.entries;
+
+    let pathspecs: Vec<PathBuf> = paths.iter().map(|path| to_index_path(path)).collect();
+    let selected = |name: &str| -> bool {
+        pathspecs.is_empty() || pathspecs.iter().any(|spec| Path::new(name).starts_with(spec))
+    };
+    for pathspec in &pathspecs {
+        if !entries.iter().any(|entry| Path::new(&entry.name).starts_with(pathspec)) {
+            bail!("pathspec '{}' did not match any files", pathspec.display());
+        }
+    }
+
+    let format = options
+        .format
+        .or_else(|| {
+            let name = options.output.as_ref()?.file_name()?.to_str()?;
+            ArchiveFormat::from_file_name(name)
+        })
+        .unwrap_or(ArchiveFormat::Tar);
+    let out: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let mut archive = archive_writer(format, out, commit.committer_timestamp);
+
+    let mut directories = HashSet::new();
+    for entry in entries.iter().filter(|entry| selected(&entry.name)) {
+        let path = format!("{}{}", options.prefix, entry.name);
+        add_directories(archive.as_mut(), &mut directories, &path)?;
+
+        // Submodules are archived as empty directories
+        if entry.object_type == OBJ_TYPE_COMMIT {
+            add_directories(archive.as_mut(), &mut directories, &format!("{}/", path))?;
+            continue;
+        }
+        let kind = match entry.mode.as_str() {
+            PERM_SYMLINK => EntryKind::Symlink,
+            mode => EntryKind::File {
+                executable: mode == PERM_EXEC,
+            },
+        };
+        let (_, content) = read_raw_object(&entry.object_hash, &OBJ_DIR)?;
+        archive
+            .add(&path, kind, &content)
+            .with_context(|| format!("Failed to archive {}", entry.name))?;
+    }
+    archive.finish()
+}
+
+/// Adds an entry for every directory leading to `path` that isn't in the
+/// archive yet, so directories always come before their contents
+fn add_directories(
+    archive: &mut dyn ArchiveWriter,
+    directories: &mut HashSet<String>,
+    path: &str,
+) -> Result<()> {
+    for (end, _) in path.match_indices('/') {
+        let directory = &path[..=end];
+        if directories.insert(directory.to_string()) {
+            archive.add(directory, EntryKind::Directory, b"")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::commands::branch::checkout::checkout_command;
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::bisect_midpoint;
+use crate::storage::objects::Loadable;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{HEAD_DIR, OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use colored::*;
+use std::fs;
+use std::path::PathBuf;
+
+/// Where HEAD pointed before bisecting, restored by `bisect reset`
+const BISECT_START: &str = "BISECT_START";
+/// The commit known to be bad
+const BISECT_BAD: &str = "BISECT_BAD";
+/// The commits known to be good, one per line
+const BISECT_GOOD: &str = "BISECT_GOOD";
+
+#[derive(Debug, Subcommand)]
+pub enum BisectCommands {
+    #[command(about = "Start bisecting, optionally marking a bad and some good commits")]
+    Start {
+        #[clap(help = "A commit known to be bad")]
+        bad: Option<String>,
+
+        #[clap(help = "Commits known to be good")]
+        good: Vec<String>,
+    },
+
+    #[command(about = "Mark a commit as good (defaults to HEAD)")]
+    Good {
+        revisions: Vec<String>,
+    },
+
+    #[command(about = "Mark a commit as bad (defaults to HEAD)")]
+    Bad {
+        revision: Option<String>,
+    },
+
+    #[command(about = "Stop bisecting and go back to where HEAD was")]
+    Reset,
+}
+
+pub fn bisect_command(command: &BisectCommands) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    match command {
+        BisectCommands::Start { bad, good } => start(bad.as_deref(), good),
+        BisectCommands::Good { revisions } => {
+            ensure_bisecting()?;
+            let revisions = match revisions.is_empty() {
+                true => vec!["HEAD".to_string()],
+                false => revisions.clone(),
+            };
+            mark_good(&revisions)?;
+            next_step()
+        }
+        BisectCommands::Bad { revision } => {
+            ensure_bisecting()?;
+            mark_bad(revision.as_deref().unwrap_or("HEAD"))?;
+            next_step()
+        }
+        BisectCommands::Reset => reset(),
+    }
+}
+
+fn state_file(name: &str) -> PathBuf {
+    VOX_DIR.join(name)
+}
+
+fn ensure_bisecting() -> Result<()> {
+    if !state_file(BISECT_START).exists() {
+        bail!("Not bisecting (use 'vox bisect start')");
+    }
+    Ok(())
+}
+
+fn start(bad: Option<&str>, good: &[String]) -> Result<()> {
+    if state_file(BISECT_START).exists() {
+        bail!("Already bisecting (use 'vox bisect reset' to start over)");
+    }
+
+    // Resolve everything before any state is written, so a typo leaves none
+    let bad = bad.map(|bad| resolve_commit(&VOX_DIR, bad)).transpose()?;
+    let good = good
+        .iter()
+        .map(|good| resolve_commit(&VOX_DIR, good))
+        .collect::<Result<Vec<_>>>()?;
+
+    let head = fs::read_to_string(&*HEAD_DIR).context("Failed to read HEAD")?;
+    fs::write(state_file(BISECT_START), head.trim())?;
+    if let Some(bad) = bad {
+        fs::write(state_file(BISECT_BAD), bad)?;
+    }
+    mark_good(&good)?;
+    next_step()
+}
+
+fn mark_bad(revision: &str) -> Result<()> {
+    let bad = resolve_commit(&VOX_DIR, revision)?;
+    fs::write(state_file(BISECT_BAD), bad)?;
+    Ok(())
+}
+
+fn mark_good(revisions: &[String]) -> Result<()> {
+    let mut good = good_commits()?;
+    for revision in revisions {
+        let hash = resolve_commit(&VOX_DIR, revision)?;
+        if !good.contains(&hash) {
+            good.push(hash);
+        }
+    }
+    if !good.is_empty() {
+        fs::write(state_file(BISECT_GOOD), good.join("\n") + "\n")?;
+    }
+    Ok(())
+}
+
+fn good_commits() -> Result<Vec<String>> {
+    match fs::read_to_string(state_file(BISECT_GOOD)) {
+        Ok(content) => Ok(content.lines().map(str::to_string).collect()),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Checks out the next commit to test, or reports the first bad commit
+fn next_step() -> Result<()> {
+    let bad = fs::read_to_string(state_file(BISECT_BAD)).ok();
+    let good = good_commits()?;
+    let Some(bad) = bad.map(|bad| bad.trim().to_string()) else {
+        println!("Waiting for a bad commit (use 'vox bisect bad')");
+        return Ok(());
+    };
+    if good.is_empty() {
+        println!("Waiting for a good commit (use 'vox bisect good')");
+        return Ok(());
+    }
+
+    match bisect_midpoint(&bad, &good, &OBJ_DIR)? {
+        Some((midpoint, left)) => {
+            let after = left / 2;
+            let steps = after.checked_ilog2().map_or(0, |log| log + 1);
+            println!(
+                "Bisecting: {} revisions left to test after this (roughly {} steps)",
+                after, steps
+            );
+            checkout_command(&midpoint, false, None)
+        }
+        None => {
+            let commit = Commit::load(&bad, &OBJ_DIR)?;
+            println!("{} is the first bad commit", bad.yellow());
+            println!("{} {}", "Author:".cyan(), commit.author);
+            println!();
+            for line in commit.message.lines() {
+                println!("    {}", line);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn reset() -> Result<()> {
+    let Ok(start) = fs::read_to_string(state_file(BISECT_START)) else {
+        println!("Not bisecting");
+        return Ok(());
+    };
+
+    // HEAD was either attached to a branch or a detached commit
+    let target = start.trim();
+    let target = target.strip_prefix("ref: refs/heads/").unwrap_or(target);
+    checkout_command(target, false, None)?;
+
+    for name in [BISECT_START, BISECT_BAD, BISECT_GOOD] {
+        let path = state_file(name);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
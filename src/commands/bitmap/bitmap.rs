@@ -0,0 +1,50 @@
+use crate::error::VoxError;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::bitmap::ReachabilityBitmaps;
+use crate::storage::objects::graph::ref_tips;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use clap::Subcommand;
+
+#[derive(Debug, Subcommand)]
+pub enum BitmapCommands {
+    #[command(about = "Record the objects reachable from HEAD and each ref")]
+    Write,
+
+    #[command(about = "Check that the bitmaps match a walk of the history")]
+    Verify,
+}
+
+pub fn bitmap_command(command: &BitmapCommands) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let format = ObjectFormat::for_objects_dir(&OBJ_DIR)?;
+    let existing = ReachabilityBitmaps::read(&OBJ_DIR, format)?;
+    match command {
+        BitmapCommands::Write => {
+            // Tips already in the bitmaps keep theirs, new ones are walked
+            // down to the first commit with a bitmap
+            let tips = ref_tips(&VOX_DIR)?;
+            let bitmaps = ReachabilityBitmaps::build(&tips, &OBJ_DIR, format, existing.as_ref())?;
+            bitmaps.write(&OBJ_DIR)?;
+            println!(
+                "Wrote bitmaps of {} commit(s) over {} object(s)",
+                bitmaps.bitmaps.len(),
+                bitmaps.objects.len()
+            );
+        }
+        BitmapCommands::Verify => {
+            let Some(bitmaps) = existing else {
+                bail!("No bitmaps, write them with 'vox bitmap write'");
+            };
+            let commits: Vec<String> = bitmaps.bitmaps.keys().map(|id| id.to_hex()).collect();
+            if ReachabilityBitmaps::build(&commits, &OBJ_DIR, format, None)? != bitmaps {
+                bail!("The bitmaps don't match the objects the commits reach");
+            }
+            println!("The bitmaps of {} commit(s) are valid", commits.len());
+        }
+    }
+    Ok(())
+}
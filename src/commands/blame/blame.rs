@@ -0,0 +1,199 @@
+use crate::error::VoxError;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::mailmap::Mailmap;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::change::ChangeType;
+use crate::storage::objects::commit::{commit_changes, Commit};
+use crate::storage::objects::commit_graph::CommitGraph;
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::tree::lookup_path;
+use crate::storage::objects::Loadable;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_BLOB, VOX_DIR};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use colored::*;
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Shows which commit last changed each line of a file
+///
+/// # Arguments
+/// - `file`: the file, relative to the root of the repository
+/// - `revision`: the commit to blame the file at (defaults to HEAD)
+///
+pub fn blame_command(file: &Path, revision: Option<&str>) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let start = resolve_commit(&VOX_DIR, revision.unwrap_or("HEAD"))?;
+    let Some(content) = file_at(&start, file)? else {
+        bail!("No such file {} in {}", file.display(), &start[..7]);
+    };
+    let owners = blame(&start, file, &content)?;
+
+    let mailmap = Mailmap::load(&VOX_DIR, &OBJ_DIR)?;
+    let mut commits: HashMap<&str, Commit> = HashMap::new();
+    for owner in &owners {
+        if !commits.contains_key(owner.as_str()) {
+            let mut commit = Commit::load(owner, &OBJ_DIR)?;
+            commit.author = mailmap.map(&commit.author);
+            commits.insert(owner, commit);
+        }
+    }
+    let name_width = commits
+        .values()
+        .map(|commit| author_name(commit).chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = owners.len().to_string().len();
+
+    for (number, (line, owner)) in content.lines().zip(&owners).enumerate() {
+        let commit = &commits[owner.as_str()];
+        let date: DateTime<Local> = commit.timestamp.with_timezone(&Local);
+        println!(
+            "{} ({} {} {:>width$}) {}",
+            owner[..8].yellow(),
+            format!("{:<name_width$}", author_name(commit)).cyan(),
+            date.format("%Y-%m-%d").to_string().green(),
+            number + 1,
+            line,
+            width = number_width,
+        );
+    }
+    Ok(())
+}
+
+/// Returns the commit that introduced each line of `content`, the version of
+/// `file` in the commit `start`
+///
+/// Walks the first parents backwards, following renames of the file. Lines a
+/// commit carries over unchanged from its parent are passed on to the parent,
+/// the others were introduced by the commit.
+fn blame(start: &str, file: &Path, content: &str) -> Result<Vec<String>> {
+    let line_count = content.lines().count();
+    let mut owners = vec![String::new(); line_count];
+    // (line in the current version, line in the blamed version)
+    let mut pending: Vec<(usize, usize)> = (0..line_count).map(|line| (line, line)).collect();
+
+    let mut hash = start.to_string();
+    let mut path = file.to_path_buf();
+    let mut content = content.to_string();
+    let graph = CommitGraph::read(&OBJ_DIR, ObjectFormat::for_objects_dir(&OBJ_DIR)?)?;
+
+    while !pending.is_empty() {
+        let commit = load_commit(&hash, &OBJ_DIR)?;
+        // A commit that doesn't change the file passes every line on as is
+        if let Some(parent) = &commit.parent
+            && graph.as_ref().is_some_and(|graph| !graph.may_change(&hash, &path))
+        {
+            hash = parent.clone();
+            continue;
+        }
+        let parent = match commit.parents().first() {
+            Some(parent) => previous_version(&commit, parent, &path)?,
+            None => None,
+        };
+        let Some((parent_hash, parent_path, parent_content)) = parent else {
+            // Everything left was added by this commit
+            for (_, line) in pending.drain(..) {
+                owners[line] = hash.clone();
+            }
+            break;
+        };
+
+        let carried = unchanged_lines(&parent_content, &content);
+        let mut still_pending = Vec::new();
+        for (current, line) in pending {
+            match carried.get(&current) {
+                Some(&previous) => still_pending.push((previous, line)),
+                None => owners[line] = hash.clone(),
+            }
+        }
+
+        pending = still_pending;
+        hash = parent_hash;
+        path = parent_path;
+        content = parent_content;
+    }
+    Ok(owners)
+}
+
+/// Returns the parent's version of the file at `path` in `commit`, along
+/// with the hash of the parent and the path of the file there
+///
+/// A file missing from the parent may have been renamed by the commit, in
+/// which case its previous name is used.
+fn previous_version(
+    commit: &Commit,
+    parent: &str,
+    path: &Path,
+) -> Result<Option<(String, PathBuf, String)>> {
+    if let Some(content) = file_at(parent, path)? {
+        return Ok(Some((parent.to_string(), path.to_path_buf(), content)));
+    }
+
+    let renamed_from = commit_changes(commit, &OBJ_DIR)?
+        .get()
+        .into_values()
+        .find_map(|change| match change {
+            ChangeType::RENAMED {
+                old_path, new_path, ..
+            } if new_path == path => Some(old_path),
+            _ => None,
+        });
+    let Some(old_path) = renamed_from else {
+        return Ok(None);
+    };
+    Ok(file_at(parent, &old_path)?.map(|content| (parent.to_string(), old_path, content)))
+}
+
+/// Reads the file at `path` in `commit`, if it's there
+fn file_at(commit: &str, path: &Path) -> Result<Option<String>> {
+    let commit = Commit::load(commit, &OBJ_DIR)?;
+    match lookup_path(&commit.tree, path, &OBJ_DIR)? {
+        Some(entry) if entry.object_type == OBJ_TYPE_BLOB => {
+            let blob = Blob::load(&entry.object_hash, &OBJ_DIR)?;
+            Ok(Some(String::from_utf8_lossy(&blob.data).into_owned()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Maps each line of `new` that's unchanged since `old` to its line in `old`
+fn unchanged_lines(old: &str, new: &str) -> HashMap<usize, usize> {
+    TextDiff::configure()
+        .algorithm(similar::Algorithm::Myers)
+        .diff_lines(old, new)
+        .iter_all_changes()
+        .filter(|change| change.tag() == ChangeTag::Equal)
+        .filter_map(|change| Some((change.new_index()?, change.old_index()?)))
+        .collect()
+}
+
+/// The name part of the commit's author, without the email
+fn author_name(commit: &Commit) -> &str {
+    commit
+        .author
+        .split_once(" <")
+        .map_or(commit.author.as_str(), |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unchanged_lines() {
+        let old = "fn main() {\n    run();\n}\n";
+        let new = "// Entry point\nfn main() {\n    run_all();\n}\n";
+
+        let carried = unchanged_lines(old, new);
+        assert_eq!(carried.get(&0), None);
+        assert_eq!(carried.get(&1), Some(&0));
+        assert_eq!(carried.get(&2), None);
+        assert_eq!(carried.get(&3), Some(&2));
+    }
+}
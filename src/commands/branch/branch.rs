@@ -1,8 +1,33 @@
+use crate::commands::config::commands::{branch_upstream, get_local_config};
+use crate::commands::config::config::{BranchConfig, Config, PersistentConfig};
 use crate::storage::objects::branch::Branch;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::ahead_behind;
+use crate::storage::objects::Loadable;
+use crate::storage::refs::ref_path;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
 use anyhow::Result;
 use colored::*;
+use std::fs;
 
-pub fn branch_command(name: Option<String>, delete: bool, _list: bool) -> Result<()> {
+/// Lists, creates or deletes branches
+///
+/// # Arguments
+/// - `name`: the branch to create or delete, branches are listed without one
+/// - `start_point`: where a new branch starts (defaults to HEAD). Starting
+///   from a remote-tracking branch such as `origin/main` makes it the upstream
+/// - `delete`: delete the branch `name`
+/// - `verbose`: when listing, `-v` shows the subject of each branch's commit
+///   and `-vv` also its upstream and how far ahead and behind it is
+///
+pub fn branch_command(
+    name: Option<String>,
+    start_point: Option<String>,
+    delete: bool,
+    _list: bool,
+    verbose: u8,
+) -> Result<()> {
     // Handle branch deletion
     if delete {
         if let Some(branch_name) = name {
@@ -12,24 +37,36 @@ pub fn branch_command(name: Option<String>, delete: bool, _list: bool) -> Result
                 commit_hash: String::new(), // Empty hash as it's not needed for deletion
             };
             branch.delete()?; // Delete the branch
+            forget_upstream(&branch_name)?;
             println!("Deleted branch '{}'", branch_name.green());
         } else {
             // Error if no branch name provided for deletion
             return Err(anyhow::anyhow!("Branch name required for deletion"));
         }
     } else if let Some(branch_name) = name {
-        // Handle branch creation
-        // Get current branch to use its commit hash as starting point
-        let current_branch =
-            Branch::get_current_branch()?.ok_or_else(|| anyhow::anyhow!("No commits yet"))?;
+        // Handle branch creation, from HEAD unless a start point is given
+        let commit_hash = match start_point.as_deref() {
+            Some(start) => resolve_commit(&VOX_DIR, start)
+                .map_err(|_| anyhow::anyhow!("Not a valid start point: '{}'", start))?,
+            None => resolve_commit(&VOX_DIR, "HEAD")
+                .map_err(|_| anyhow::anyhow!("No commits yet"))?,
+        };
 
-        // Create new branch pointing to current commit
-        Branch::new(&branch_name, &current_branch.commit_hash)?;
+        Branch::new(
+            &branch_name,
+            &commit_hash,
+            start_point.as_deref().unwrap_or("HEAD"),
+        )?;
         println!("Created branch '{}'", branch_name.green());
+
+        if let Some(upstream) = start_point.as_deref().and_then(remote_tracking_branch) {
+            set_upstream(&branch_name, &upstream)?;
+        }
     } else {
         // Handle branch listing (default behavior)
         let branches = Branch::list()?; // Get all branches
         let current = Branch::get_current_branch()?; // Get current branch for marking
+        let width = branches.iter().map(|b| b.name.len()).max().unwrap_or(0);
 
         // Display each branch
         for branch in branches {
@@ -40,14 +77,119 @@ pub fn branch_command(name: Option<String>, delete: bool, _list: bool) -> Result
                 "   ".normal()
             };
 
-            // Print branch info: prefix, name, and abbreviated commit hash
+            if verbose == 0 {
+                // Print branch info: prefix, name, and abbreviated commit hash
+                println!(
+                    "{}{} {}",
+                    prefix,
+                    branch.name.green(),
+                    branch.commit_hash[..7].yellow() // Show first 7 chars of commit hash
+                );
+                continue;
+            }
+
+            let commit = Commit::load(&branch.commit_hash, &OBJ_DIR)?;
+            let subject = commit.message.lines().next().unwrap_or_default();
+            let tracking = match verbose {
+                1 => String::new(),
+                _ => tracking_info(&branch)?,
+            };
             println!(
-                "{}{} {}",
+                "{}{} {} {}{}",
                 prefix,
-                branch.name.green(),
-                branch.commit_hash[..7].yellow() // Show first 7 chars of commit hash
+                format!("{:<width$}", branch.name).green(),
+                branch.commit_hash[..7].yellow(),
+                tracking,
+                subject
             );
         }
     }
     Ok(())
 }
+
+/// Describes the upstream of `branch` and how the two diverged, e.g.
+/// "[origin/main: ahead 1, behind 2] "
+fn tracking_info(branch: &Branch) -> Result<String> {
+    let Some(upstream) = branch_upstream(&branch.name) else {
+        return Ok(String::new());
+    };
+    let upstream_hash = match fs::read_to_string(ref_path(&VOX_DIR, &upstream.tracking_ref())) {
+        Ok(hash) => hash.trim().to_string(),
+        Err(_) => return Ok(format!("[{}: {}] ", upstream.short_name().blue(), "gone".red())),
+    };
+
+    let (ahead, behind) = ahead_behind(&branch.commit_hash, &upstream_hash, &OBJ_DIR)?;
+    let summary = divergence(ahead, behind);
+    match summary.is_empty() {
+        true => Ok(format!("[{}] ", upstream.short_name().blue())),
+        false => Ok(format!("[{}: {}] ", upstream.short_name().blue(), summary)),
+    }
+}
+
+/// Formats how many commits a branch is ahead and behind its upstream, empty
+/// when they point to the same commit
+pub fn divergence(ahead: usize, behind: usize) -> String {
+    match (ahead, behind) {
+        (0, 0) => String::new(),
+        (ahead, 0) => format!("ahead {}", ahead),
+        (0, behind) => format!("behind {}", behind),
+        (ahead, behind) => format!("ahead {}, behind {}", ahead, behind),
+    }
+}
+
+/// Returns the upstream for a start point naming a remote-tracking branch,
+/// such as `origin/main` or `refs/remotes/origin/main`
+pub fn remote_tracking_branch(start_point: &str) -> Option<BranchConfig> {
+    let candidates = [
+        start_point.to_string(),
+        format!("refs/{}", start_point),
+        format!("refs/remotes/{}", start_point),
+    ];
+    let full_name = candidates
+        .into_iter()
+        .filter(|name| name.starts_with("refs/remotes/"))
+        .find(|name| ref_path(&VOX_DIR, name).is_file())?;
+
+    let (remote, branch) = full_name.strip_prefix("refs/remotes/")?.split_once('/')?;
+    Some(BranchConfig::new(remote, format!("refs/heads/{}", branch)))
+}
+
+/// Records `upstream` as the branch `branch` tracks in the local config
+pub fn set_upstream(branch: &str, upstream: &BranchConfig) -> Result<()> {
+    let config_path = get_local_config()?;
+    let mut config = Config::read_from_file(&config_path)?;
+    config.set_branch_upstream(branch, upstream.clone());
+    config.write_to_file(&config_path)?;
+
+    println!(
+        "Branch '{}' set up to track '{}'",
+        branch.green(),
+        upstream.short_name().blue()
+    );
+    Ok(())
+}
+
+fn forget_upstream(branch: &str) -> Result<()> {
+    let config_path = get_local_config()?;
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let mut config = Config::read_from_file(&config_path)?;
+    if config.remove_branch(branch) {
+        config.write_to_file(&config_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_divergence() {
+        assert_eq!(divergence(0, 0), "");
+        assert_eq!(divergence(2, 0), "ahead 2");
+        assert_eq!(divergence(0, 1), "behind 1");
+        assert_eq!(divergence(3, 4), "ahead 3, behind 4");
+    }
+}
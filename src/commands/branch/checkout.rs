@@ -1,46 +1,52 @@
+use crate::commands::branch::branch::{remote_tracking_branch, set_upstream};
+use crate::commands::commit::commit::get_current_commit;
+use crate::commands::index::index::Index;
+use crate::commands::restore::restore::restore_paths;
 use crate::commands::status::status::get_status;
+use crate::storage::convert::Converter;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::blob::Blob;
 use crate::storage::objects::branch::Branch;
 use crate::storage::objects::commit::Commit;
 use crate::storage::objects::tree::read_tree;
 use crate::storage::objects::Loadable;
-use crate::storage::utils::{HEAD_DIR, OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_TREE};
+use crate::storage::pathspec::Pathspec;
+use crate::storage::platform;
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::reflog::record_ref_update;
+use crate::storage::revision::resolve_commit;
+use crate::storage::submodules::is_nested_repository;
+use crate::storage::utils::{
+    HEAD_DIR, INDEX_FILE, OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, PERM_EXEC, PERM_SYMLINK,
+    VOX_DIR,
+};
 use anyhow::{Context, Result};
 use colored::*;
-use flate2::bufread::ZlibDecoder;
 use indicatif::{ProgressBar, ProgressStyle};
-use sha1::*;
+use rayon::prelude::*;
 use std::fs;
-use std::io::Read;
 use std::path::{Path, PathBuf};
 
 /// Main checkout command that switches between branches or commits
 /// Parameters:
-/// - target: branch name or commit hash to checkout
+/// - target: branch name or revision (e.g. a commit hash or "HEAD~2") to checkout
 /// - force: whether to force checkout even with uncommitted changes
 pub fn checkout_command(target: &str, force: bool, workdir: Option<&Path>) -> Result<()> {
     let _workdir = workdir.unwrap_or_else(|| Path::new("."));
 
     // Check for uncommitted changes unless force flag is set
     if !force {
-        let (_added, modified, deleted, untracked) = get_status(Path::new("."))?;
-        if !modified.is_empty() || !deleted.is_empty() || !untracked.is_empty() {
-            return Err(anyhow::anyhow!(
-                "You have uncommitted changes. Commit or stash them first (or use --force)"
-                    .red()
-                    .to_string()
-            ));
-        }
+        ensure_clean_worktree()?;
     }
 
-    // Determine if target is a commit hash (40 chars) or branch name
-    let commit_hash = if target.len() == 40 {
-        target.to_string()
-    } else {
-        // Look up branch and get its commit hash
-        match Branch::list()?.iter().find(|b| b.name == target) {
-            Some(branch) => branch.commit_hash.clone(),
-            None => return Err(anyhow::anyhow!("Branch or commit '{}' not found", target)),
-        }
+    // A branch name attaches HEAD to the branch, any other revision detaches it
+    let (commit_hash, is_commit_hash) = match Branch::list()?.iter().find(|b| b.name == target) {
+        Some(branch) => (branch.commit_hash.clone(), false),
+        None => (
+            resolve_commit(&VOX_DIR, target)
+                .with_context(|| format!("Branch or commit '{}' not found", target))?,
+            true,
+        ),
     };
 
     // Load the target commit
@@ -50,19 +56,115 @@ pub fn checkout_command(target: &str, force: bool, workdir: Option<&Path>) -> Re
     clean_working_directory(Path::new("."));
 
     // Restore files from commit's tree
-    restore_tree(&commit.tree, Path::new("."))?;
+    restore_tree(&commit.tree, Path::new("."), &OBJ_DIR)?;
+
+    // Stage exactly the checked out tree, so the working tree is clean
+    Index::from_tree(&commit.tree, Path::new("."), &OBJ_DIR)?.write_to_file(&INDEX_FILE)?;
+
+    // Remember where HEAD was for the reflog
+    let old_hash = get_current_commit()?;
+    let moving_from = match Branch::get_current_branch()? {
+        Some(branch) => branch.name,
+        None => old_hash.clone().unwrap_or_default(),
+    };
 
     // Update HEAD to point to new commit/branch
-    if target.len() == 40 {
-        fs::write(&*HEAD_DIR, commit_hash)?; // Direct commit reference
+    if is_commit_hash {
+        fs::write(&*HEAD_DIR, &commit_hash)?; // Direct commit reference
     } else {
         fs::write(&*HEAD_DIR, format!("ref: refs/heads/{}\n", target))?; // Branch reference
     }
 
+    record_ref_update(
+        "HEAD",
+        old_hash.as_deref(),
+        &commit_hash,
+        &format!("checkout: moving from {} to {}", moving_from, target),
+    )?;
+
     println!("Succesfully checked out {}", target);
     Ok(())
 }
 
+/// Creates the branch `name` and checks it out (`checkout -b`)
+///
+/// # Arguments
+/// - `name`: the branch to create
+/// - `start_point`: revision the branch starts from, HEAD if not given.
+///   A remote-tracking branch such as `origin/main` becomes its upstream
+/// - `reset`: move the branch to the start point if it already exists (`-B`)
+/// - `force`: whether to force checkout even with uncommitted changes
+pub fn checkout_new_branch(
+    name: &str,
+    start_point: Option<&str>,
+    reset: bool,
+    force: bool,
+) -> Result<()> {
+    // Nothing is created if the checkout would be refused
+    if !force {
+        ensure_clean_worktree()?;
+    }
+
+    let start = start_point.unwrap_or("HEAD");
+    let commit_hash = resolve_commit(&VOX_DIR, start)
+        .with_context(|| format!("Not a valid start point: '{}'", start))?;
+
+    let existing = Branch::list()?.into_iter().find(|b| b.name == name);
+    match existing {
+        Some(branch) if reset => {
+            let mut transaction = RefTransaction::new(&VOX_DIR);
+            transaction.update(
+                &format!("refs/heads/{}", name),
+                &commit_hash,
+                ExpectedValue::Hash(branch.commit_hash),
+                &format!("branch: Reset to {}", start),
+            );
+            transaction.commit()?;
+        }
+        Some(_) => return Err(anyhow::anyhow!("Branch {} already exists", name)),
+        None => {
+            Branch::new(name, &commit_hash, start)?;
+        }
+    }
+
+    if let Some(upstream) = start_point.and_then(remote_tracking_branch) {
+        set_upstream(name, &upstream)?;
+    }
+
+    // The working tree was already checked above
+    checkout_command(name, true, None)
+}
+
+/// Restores `paths` from `source` without moving HEAD (`checkout <rev> -- <paths>`)
+///
+/// Files are taken from the commit `source` into both the index and the
+/// working tree, or from the index into the working tree if no commit is
+/// given. Tracked files under the paths that `source` doesn't have are
+/// removed, uncommitted changes to the paths are overwritten.
+pub fn checkout_paths(source: Option<&str>, paths: &[PathBuf]) -> Result<()> {
+    let restored = restore_paths(paths, source, source.is_some(), true)?;
+    println!(
+        "Updated {} path(s) from {}",
+        restored.to_string().green(),
+        source.unwrap_or("the index")
+    );
+    Ok(())
+}
+
+/// Fails if the working tree has changes a checkout would overwrite
+fn ensure_clean_worktree() -> Result<()> {
+    let (_added, modified, deleted, untracked) =
+        get_status(Path::new("."), &Pathspec::default())?;
+    if !modified.is_empty() || !deleted.is_empty() || !untracked.is_empty() {
+        return Err(anyhow::anyhow!(
+            "You have uncommitted changes. Commit or stash them first (or use --force)"
+                .red()
+                .to_string()
+        ));
+    }
+    Ok(())
+}
+
 /// Cleans the working directory by removing all files and directories
 /// except hidden files and special directories (.vox, .git, target)
 fn clean_working_directory(path: &Path) -> Result<()> {
@@ -80,9 +182,14 @@ fn clean_working_directory(path: &Path) -> Result<()> {
             continue;
         }
 
-        if path.is_dir() {
-            // Skip special directories
-            if path.starts_with(".vox") || path.starts_with(".git") || path.starts_with("target") {
+        // Symlinks are removed as files, never followed
+        if entry.file_type()?.is_dir() {
+            // Skip special directories and submodules
+            if path.starts_with(".vox")
+                || path.starts_with(".git")
+                || path.starts_with("target")
+                || is_nested_repository(&path)
+            {
                 continue;
             }
             fs::remove_dir_all(path)?;
@@ -93,31 +200,28 @@ fn clean_working_directory(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Recursively restores a tree object to the filesystem
+/// Restores a tree object and all its subtrees to the work tree at
+/// `work_tree`, reading the objects from `objects_dir`
+/// Directories are created first, then blobs are decompressed and written
+/// in parallel on the rayon thread pool
 /// Shows progress bar for visual feedback
-fn restore_tree(tree_hash: &str, base_path: &Path) -> Result<()> {
-    let tree = read_tree(tree_hash, &*OBJ_DIR)?;
-    // Setup progress bar
-    let pb = ProgressBar::new(tree.entries.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{bar:40.cyan/blue}] {percent}% • {pos}/{len} files {msg}")
-            .unwrap()
-            .progress_chars("▰▰▱"),
-    );
-
-    // Process each entry in the tree
-    for entry in tree.entries {
-        let path = base_path.join(&entry.name);
-        pb.set_prefix(format!("Processing: {}", entry.name));
+pub fn restore_tree(tree_hash: &str, work_tree: &Path, objects_dir: &Path) -> Result<()> {
+    let entries = read_tree(tree_hash, objects_dir)?.flatten(objects_dir)?.entries;
 
+    // Directories are created up front so the writers never race on them
+    let mut blobs = Vec::new();
+    for entry in &entries {
+        let path = work_tree.join(&entry.name);
         match entry.object_type.as_str() {
-            OBJ_TYPE_TREE => {
-                fs::create_dir_all(&path)?;
-                let _ = restore_tree(&entry.object_hash, &path);
-            }
             OBJ_TYPE_BLOB => {
-                restore_blob(&entry.object_hash, &path)?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                blobs.push((path, entry));
+            }
+            OBJ_TYPE_COMMIT => {
+                // Submodules are only populated by `vox submodule update`
+                fs::create_dir_all(&path)?;
             }
             _ => {
                 return Err(anyhow::anyhow!(
@@ -126,59 +230,143 @@ fn restore_tree(tree_hash: &str, base_path: &Path) -> Result<()> {
                 ));
             }
         }
-        pb.inc(1);
     }
+
+    // Setup progress bar
+    let pb = ProgressBar::new(blobs.len() as u64);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {percent}% • {pos}/{len} files {msg}")
+            .unwrap()
+            .progress_chars("▰▰▱"),
+    );
+
+    blobs.par_iter().try_for_each(|(path, entry)| -> Result<()> {
+        write_blob(&entry.object_hash, path, &entry.mode, work_tree, objects_dir)?;
+        pb.inc(1);
+        Ok(())
+    })?;
     pb.finish_with_message("Files restored Succesfully!");
     Ok(())
 }
 
 /// Restores a blob (file) object to the filesystem
 /// Only updates if file doesn't exist or content has changed
-fn restore_blob(hash: &str, path: &Path) -> Result<()> {
-    if !should_update_file(path, hash) {
+/// Blobs with the symlink mode are recreated as links to their content
+/// The execute bit is set from the mode even if the content is unchanged
+pub fn restore_blob(hash: &str, path: &Path, mode: &str) -> Result<()> {
+    write_blob(hash, path, mode, Path::new("."), &OBJ_DIR)
+}
+
+/// Writes the blob `hash` of `objects_dir` to `path` in the work tree at
+/// `work_tree`, as [`restore_blob`] does for the current repository
+fn write_blob(
+    hash: &str,
+    path: &Path,
+    mode: &str,
+    work_tree: &Path,
+    objects_dir: &Path,
+) -> Result<()> {
+    let is_link = mode == PERM_SYMLINK;
+    if !is_outdated(path, hash, is_link, work_tree, objects_dir) {
+        if !is_link {
+            platform::set_executable(path, mode == PERM_EXEC)?;
+        }
         return Ok(());
     }
 
-    // Construct path to blob object
-    let object_path = PathBuf::from(&*OBJ_DIR).join(&hash[..2]).join(&hash[2..]);
-
-    // Read and decompress blob data
-    let compressed_data =
-        fs::read(&object_path).with_context(|| format!("Failed to read object {}", hash))?;
-
-    let mut decoder = ZlibDecoder::new(&compressed_data[..]);
-    let mut decompressed_data = Vec::new();
-    decoder.read_to_end(&mut decompressed_data)?;
-
-    // Find content after header
-    let content_start = decompressed_data
-        .iter()
-        .position(|&b| b == 0)
-        .ok_or_else(|| anyhow::anyhow!("Invalid blob format"))?;
-
-    let content = &decompressed_data[content_start + 1..];
-
-    // Ensure parent directory exists and write file
+    // Ensure parent directory exists
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::write(path, content)?;
+
+    // Writing through an existing link would change its target instead
+    if platform::is_symlink(path) || (is_link && path.is_file()) {
+        fs::remove_file(path)?;
+    }
+
+    if is_link {
+        let blob = Blob::load(hash, objects_dir)
+            .with_context(|| format!("Failed to read object {}", hash))?;
+        let target = std::str::from_utf8(&blob.data)
+            .with_context(|| format!("Invalid symlink target in object {}", hash))?;
+        platform::create_symlink(target, path)?;
+    } else {
+        // File contents are streamed, so they don't have to fit in memory
+        Blob::write_worktree(hash, objects_dir, path, &mut Converter::new(work_tree))?;
+        platform::set_executable(path, mode == PERM_EXEC)?;
+    }
     Ok(())
 }
 
 /// Determines if a file needs to be updated by comparing its hash
 /// with the expected hash from the repository
-fn should_update_file(path: &Path, expected_hash: &str) -> bool {
-    if !path.exists() {
+/// A file that should be a symlink (or the other way around) is always updated
+pub fn should_update_file(path: &Path, expected_hash: &str, is_link: bool) -> bool {
+    is_outdated(path, expected_hash, is_link, Path::new("."), &OBJ_DIR)
+}
+
+/// [`should_update_file`] for a file of the work tree at `work_tree`, whose
+/// objects are in `objects_dir`
+fn is_outdated(
+    path: &Path,
+    expected_hash: &str,
+    is_link: bool,
+    work_tree: &Path,
+    objects_dir: &Path,
+) -> bool {
+    if fs::symlink_metadata(path).is_err() || platform::is_symlink(path) != is_link {
         return true;
     }
 
-    if let Ok(current_content) = fs::read(path) {
-        let mut hasher = Sha1::new();
-        hasher.update(&current_content);
-        let current_hash = format!("{:x}", hasher.finalize());
-        current_hash != expected_hash
-    } else {
-        true
+    let Ok(format) = ObjectFormat::for_objects_dir(objects_dir) else {
+        return true;
+    };
+    let mut converter = Converter::new(work_tree);
+    match Blob::hash_worktree(path, path, &mut converter, format) {
+        Ok(hash) => hash.to_hex() != expected_hash,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::tree::create_tree;
+    use crate::storage::objects::Storable;
+    use crate::vox_repo::VoxRepo;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_restore_tree_writes_every_file() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        let repo = VoxRepo::init(root, ObjectFormat::Sha1, false)?;
+        let objects_dir = repo.objects_dir();
+        let mut files = Vec::new();
+        for dir in ["a", "a/b/c", "d"] {
+            for file in 0..16 {
+                let path = format!("{}/file{}.txt", dir, file);
+                fs::create_dir_all(root.join(dir))?;
+                fs::write(root.join(&path), format!("{}\n", path))?;
+                files.push(path);
+            }
+        }
+        fs::write(root.join("run.sh"), "#!/bin/sh\n")?;
+        platform::set_executable(&root.join("run.sh"), true)?;
+        let tree = create_tree(root, &objects_dir)?.save(&objects_dir)?;
+
+        // Deleted directories come back, edited files are overwritten
+        fs::remove_dir_all(root.join("a"))?;
+        fs::write(root.join("d/file3.txt"), "edited\n")?;
+        fs::remove_file(root.join("run.sh"))?;
+        restore_tree(&tree, root, &objects_dir)?;
+
+        for path in files {
+            assert_eq!(fs::read_to_string(root.join(&path))?, format!("{}\n", path));
+        }
+        assert_eq!(fs::read_to_string(root.join("run.sh"))?, "#!/bin/sh\n");
+        assert!(platform::is_executable(&fs::metadata(root.join("run.sh"))?));
+        Ok(())
     }
 }
@@ -1,2 +1,3 @@
 pub mod branch;
 pub mod checkout;
+pub mod switch;
@@ -0,0 +1,36 @@
+use crate::commands::branch::checkout::{checkout_command, checkout_new_branch};
+use crate::storage::objects::branch::Branch;
+use anyhow::Result;
+
+/// Switches to a branch, unlike checkout it never touches individual files
+///
+/// # Arguments
+/// - `target`: the branch to switch to, or the start point of a new branch
+/// - `create`: create this branch and switch to it (`-c`)
+/// - `force_create`: like `create`, resetting the branch if it exists (`-C`)
+/// - `detach`: allow `target` to be any revision, detaching HEAD
+/// - `force`: whether to switch even with uncommitted changes
+///
+pub fn switch_command(
+    target: Option<&str>,
+    create: Option<&str>,
+    force_create: Option<&str>,
+    detach: bool,
+    force: bool,
+) -> Result<()> {
+    match (create, force_create, target) {
+        (Some(name), _, start_point) => checkout_new_branch(name, start_point, false, force),
+        (None, Some(name), start_point) => checkout_new_branch(name, start_point, true, force),
+        (None, None, Some(target)) => {
+            let is_branch = Branch::list()?.iter().any(|branch| branch.name == target);
+            if !is_branch && !detach {
+                return Err(anyhow::anyhow!(
+                    "A branch is expected, got '{}'. Use --detach to switch to a commit",
+                    target
+                ));
+            }
+            checkout_command(target, force, None)
+        }
+        (None, None, None) => Err(anyhow::anyhow!("Missing branch or commit argument")),
+    }
+}
@@ -0,0 +1,105 @@
+use crate::error::VoxError;
+use crate::storage::bundle::{read_bundle, write_bundle};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::refs::{list_refs, resolve_head};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use colored::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Subcommand)]
+pub enum BundleCommands {
+    #[command(about = "Write refs and the objects they reach to a bundle file")]
+    Create {
+        #[clap(help = "Bundle file to write")]
+        file: PathBuf,
+
+        #[clap(help = "Refs to bundle, e.g. main or v1.0 (defaults to HEAD and every ref)")]
+        refs: Vec<String>,
+    },
+
+    #[command(name = "list-heads", about = "List the refs a bundle carries")]
+    ListHeads {
+        file: PathBuf,
+    },
+}
+
+/// Creates bundles, single files holding refs and their objects that can be
+/// cloned from like a repository, or lists their refs
+pub fn bundle_command(command: &BundleCommands) -> Result<()> {
+    match command {
+        BundleCommands::Create { file, refs } => create(file, refs),
+        BundleCommands::ListHeads { file } => {
+            for (name, hash) in read_bundle(file)?.refs {
+                println!("{} {}", hash, name);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn create(file: &Path, names: &[String]) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let all_refs = list_refs(&VOX_DIR)?;
+    let mut refs = Vec::new();
+    if names.is_empty() || names.iter().any(|name| name == "HEAD") {
+        refs.extend(resolve_head(&VOX_DIR)?.map(|head| ("HEAD".to_string(), head)));
+    }
+    match names.is_empty() {
+        true => refs.extend(all_refs),
+        false => {
+            for name in names.iter().filter(|name| *name != "HEAD") {
+                refs.push(find_ref(&all_refs, name)?);
+            }
+        }
+    }
+
+    let format = ObjectFormat::for_vox_dir(&VOX_DIR)?;
+    let objects = write_bundle(file, &refs, &OBJ_DIR, format)?;
+    println!(
+        "Created {} with {} ref(s) and {} objects",
+        file.display(),
+        refs.len(),
+        objects.to_string().green()
+    );
+    Ok(())
+}
+
+/// Finds a ref by its full name or its short name, branches first
+pub fn find_ref(refs: &[(String, String)], name: &str) -> Result<(String, String)> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/heads/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/remotes/{}", name),
+    ];
+    for candidate in &candidates {
+        if let Some(found) = refs.iter().find(|(ref_name, _)| ref_name == candidate) {
+            return Ok(found.clone());
+        }
+    }
+    bail!("'{}' is not a ref", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_ref() -> Result<()> {
+        let refs = vec![
+            ("refs/heads/v1".to_string(), "aa".to_string()),
+            ("refs/tags/v1".to_string(), "bb".to_string()),
+            ("refs/tags/v2".to_string(), "cc".to_string()),
+        ];
+        assert_eq!(find_ref(&refs, "v1")?.0, "refs/heads/v1");
+        assert_eq!(find_ref(&refs, "refs/tags/v1")?.1, "bb");
+        assert_eq!(find_ref(&refs, "v2")?.1, "cc");
+        assert!(find_ref(&refs, "v3").is_err());
+        Ok(())
+    }
+}
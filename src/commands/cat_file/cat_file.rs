@@ -1,15 +1,16 @@
-use crate::storage::objects::Object;
+use crate::error::VoxError;
+use crate::storage::encryption::open_object_file;
+use crate::storage::objects::compression::ObjectDecoder;
+use crate::storage::objects::{loose_object_path, Object};
 use crate::storage::objects::VoxObject;
 use crate::storage::utils::OBJ_DIR;
 use crate::storage::utils::OBJ_TYPE_BLOB;
 use crate::storage::utils::OBJ_TYPE_TREE;
 use anyhow::bail;
 use anyhow::{Context, Result};
-use flate2::read::ZlibDecoder;
+use std::io::Read;
 use std::str::FromStr;
-use std::{fs::File, io::Read};
 
-const HASH_PREFIX_LEN: usize = 2;
 const HASH_BYTES_LEN: usize = 20;
 
 struct Entry<'a> {
@@ -41,17 +42,13 @@ pub fn cat_file_command(
 }
 
 fn read_vox_object(hash: &str) -> Result<Vec<u8>> {
-    let object_path = format!(
-        "{}/{}/{}",
-        OBJ_DIR.display(),
-        &hash[..HASH_PREFIX_LEN],
-        &hash[HASH_PREFIX_LEN..]
-    );
-
-    let file = File::open(&object_path)
+    let object_path = loose_object_path(hash, &OBJ_DIR)?
+        .ok_or_else(|| VoxError::ObjectNotFound(hash.to_string()))?;
+
+    let file = open_object_file(hash, &object_path)
         .with_context(|| format!("Failed to open object file: {}", hash))?;
 
-    let mut decoder = ZlibDecoder::new(file);
+    let mut decoder = ObjectDecoder::buffered(file).context("Failed to read object data")?;
     let mut data = Vec::new();
     decoder
         .read_to_end(&mut data)
@@ -0,0 +1,46 @@
+use crate::error::VoxError;
+use crate::storage::attributes::AttributeMatcher;
+use crate::storage::platform::to_index_path;
+use crate::storage::utils::VOX_DIR;
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Prints the attributes of paths as `path: attr: value`
+///
+/// Values are `set`, `unset`, `unspecified` or the value given with
+/// `attr=value`.
+///
+/// # Arguments
+/// - `attrs`: the attributes to show
+/// - `paths`: the paths to show them for
+/// - `all`: show every attribute that is specified for each path instead
+///
+pub fn check_attr_command(attrs: &[String], paths: &[String], all: bool) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+    if paths.is_empty() {
+        bail!("No paths given");
+    }
+    if !all && attrs.is_empty() {
+        bail!("No attributes given, use --all to show every attribute");
+    }
+
+    let mut matcher = AttributeMatcher::new(Path::new("."));
+    for path in paths {
+        let attributes = matcher.attributes(&to_index_path(Path::new(path)));
+        if all {
+            for (name, value) in &attributes {
+                println!("{}: {}: {}", path, name, value);
+            }
+            continue;
+        }
+        for name in attrs {
+            match attributes.get(name) {
+                Some(value) => println!("{}: {}: {}", path, name, value),
+                None => println!("{}: {}: unspecified", path, name),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::ignore::IgnoreMatcher;
+use crate::storage::platform::to_index_path;
+use crate::storage::utils::VOX_DIR;
+use anyhow::Result;
+use std::path::Path;
+
+/// Tells which of `paths` are ignored, and by which rule
+///
+/// # Arguments
+/// - `paths`: the paths to check, a trailing `/` marking a directory
+/// - `verbose`: print the deciding rule as `source:line:pattern`, tab, path
+/// - `non_matching`: with `verbose`, also print paths no rule matches, with
+///   an empty rule
+/// - `no_index`: check tracked paths as well, which are never ignored
+///   otherwise
+///
+/// Exits with status 1 if none of the paths is ignored.
+///
+pub fn check_ignore_command(
+    paths: &[String],
+    verbose: bool,
+    non_matching: bool,
+    no_index: bool,
+) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let repo_path = Path::new(".");
+    let mut index = Index::new();
+    let index_path = VOX_DIR.join("index");
+    if !no_index && index_path.exists() {
+        index.read_from_file(&index_path)?;
+    }
+
+    let mut matcher = IgnoreMatcher::new(repo_path)?;
+    let mut any_ignored = false;
+    for path in paths {
+        let relative = to_index_path(Path::new(path));
+        let is_dir = path.ends_with('/') || repo_path.join(&relative).is_dir();
+
+        // Tracked files stay tracked whatever the rules say
+        let rule = match index.get_entry(&relative) {
+            Some(_) => None,
+            None => matcher.matching_rule(&relative, is_dir)?,
+        };
+
+        let ignored = rule.as_ref().is_some_and(|rule| !rule.is_negated());
+        any_ignored |= ignored;
+        match (&rule, verbose) {
+            (Some(rule), true) => println!(
+                "{}:{}:{}\t{}",
+                rule.source.display(),
+                rule.line,
+                rule.pattern,
+                path
+            ),
+            (None, true) if non_matching => println!("::\t{}", path),
+            (_, false) if ignored => println!("{}", path),
+            _ => {}
+        }
+    }
+
+    // Scripts branch on the exit status, so "nothing ignored" isn't an error
+    if !any_ignored {
+        std::process::exit(1);
+    }
+    Ok(())
+}
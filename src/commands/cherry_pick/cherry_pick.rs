@@ -0,0 +1,359 @@
+use crate::commands::commit::commit::get_current_commit;
+use crate::commands::commit::replay::{commit_index, ReplayState};
+use crate::commands::config::commands::{conflict_style, user_identity};
+use crate::commands::format_patch::format_patch::split_message;
+use crate::commands::merge::merge::{checkout_merge, ensure_clean_index};
+use crate::commands::status::status::get_status;
+use crate::error::VoxError;
+use crate::storage::merge::{merge_trees, MergeLabels, MergeOptions};
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::list_commits;
+use crate::storage::objects::Loadable;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::revision::{abbreviate, resolve_commit};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use std::fs;
+use std::path::Path;
+
+/// Directory holding the commits left to apply by a cherry-pick or revert
+const SEQUENCER: ReplayState = ReplayState::new("sequencer", "cherry-pick");
+/// The remaining commits, one `pick <hash> <subject>` or `revert <hash>
+/// <subject>` line each, the first one being applied
+const SEQ_TODO: &str = "todo";
+/// The commit HEAD pointed to when the run started, restored by `--abort`
+const SEQ_ORIG_HEAD: &str = "head";
+
+/// What is done with a commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// Apply the changes it made
+    Pick,
+    /// Apply the opposite of the changes it made
+    Revert,
+}
+
+impl Replay {
+    fn name(self) -> &'static str {
+        match self {
+            Replay::Pick => "pick",
+            Replay::Revert => "revert",
+        }
+    }
+
+    /// The command replaying commits this way
+    fn command(self) -> &'static str {
+        match self {
+            Replay::Pick => "cherry-pick",
+            Replay::Revert => "revert",
+        }
+    }
+}
+
+/// A commit left to apply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoItem {
+    pub replay: Replay,
+    pub commit: String,
+    /// Subject of the commit, only there for whoever reads the todo list
+    pub subject: String,
+}
+
+/// What `vox cherry-pick` or `vox revert` should do
+#[derive(Debug)]
+pub enum SequencerAction {
+    /// Apply these commits, or ranges of commits such as `A..B`
+    Start(Vec<String>),
+    /// Commit the staged resolution of the conflicting commit and go on
+    Continue,
+    /// Go back to where HEAD was before the run
+    Abort,
+}
+
+/// Applies the changes made by existing commits on top of HEAD, keeping
+/// their author, date and message
+///
+/// The commits are written to a todo list in `.vox/sequencer` first. When a
+/// commit conflicts, the run stops with the conflicts in the working tree,
+/// to be resumed with `--continue` once they are resolved and staged, or
+/// given up with `--abort`.
+pub fn cherry_pick_command(action: SequencerAction) -> Result<()> {
+    sequencer_command(Replay::Pick, action)
+}
+
+/// Makes a commit undoing each of the given commits, newest first
+///
+/// Works like `vox cherry-pick`, conflicts are resolved the same way.
+pub fn revert_command(action: SequencerAction) -> Result<()> {
+    sequencer_command(Replay::Revert, action)
+}
+
+fn sequencer_command(replay: Replay, action: SequencerAction) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let in_progress = SEQUENCER.file(SEQ_TODO).exists();
+    match action {
+        SequencerAction::Start(_) if in_progress => {
+            bail!("A cherry-pick or revert is in progress, use --continue or --abort")
+        }
+        SequencerAction::Start(revisions) => start(replay, &revisions)?,
+        _ if !in_progress => bail!("No cherry-pick or revert in progress"),
+        SequencerAction::Continue => {
+            let (_added, modified, deleted, _untracked) =
+                get_status(Path::new("."), &Pathspec::default())?;
+            if !modified.is_empty() || !deleted.is_empty() {
+                bail!("Resolve the conflicts and stage the result with 'vox add' first");
+            }
+            let todo = read_todo()?;
+            if let Some(item) = todo.first() {
+                commit_item(item)?;
+            }
+            write_todo(todo.get(1..).unwrap_or_default())?;
+        }
+        SequencerAction::Abort => return SEQUENCER.abort(SEQ_ORIG_HEAD),
+    }
+    apply_remaining()
+}
+
+/// Parses a todo list, ignoring blank lines and `#` comments
+pub fn parse_todo(todo: &str) -> Result<Vec<TodoItem>> {
+    let mut items = Vec::new();
+    for line in todo.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ' ');
+        let replay = match fields.next() {
+            Some("pick") => Replay::Pick,
+            Some("revert") => Replay::Revert,
+            _ => bail!("Invalid todo line '{}'", line),
+        };
+        let commit = fields.next().with_context(|| format!("Missing commit in '{}'", line))?;
+        items.push(TodoItem {
+            replay,
+            commit: commit.to_string(),
+            subject: fields.next().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(items)
+}
+
+/// Writes a todo list in the format [`parse_todo`] reads
+pub fn format_todo(items: &[TodoItem]) -> String {
+    items
+        .iter()
+        .map(|item| format!("{} {} {}\n", item.replay.name(), item.commit, item.subject))
+        .collect()
+}
+
+fn read_todo() -> Result<Vec<TodoItem>> {
+    let todo = fs::read_to_string(SEQUENCER.file(SEQ_TODO)).context("Failed to read the todo list")?;
+    parse_todo(&todo)
+}
+
+fn write_todo(items: &[TodoItem]) -> Result<()> {
+    fs::write(SEQUENCER.file(SEQ_TODO), format_todo(items)).context("Failed to write the todo list")
+}
+
+/// Lists the commits of the revisions, each a commit or a range `A..B` of
+/// the commits reachable from `B` but not from `A`, in the order they are
+/// applied: oldest first when picking, newest first when reverting
+fn collect_commits(replay: Replay, revisions: &[String]) -> Result<Vec<String>> {
+    let resolve = |spec: &str| -> Result<String> {
+        let spec = if spec.is_empty() { "HEAD" } else { spec };
+        resolve_commit(&VOX_DIR, spec).with_context(|| format!("Bad revision '{}'", spec))
+    };
+
+    let mut commits = Vec::new();
+    for revision in revisions {
+        match revision.split_once("..") {
+            Some((from, to)) => {
+                let mut range = list_commits(&[resolve(to)?], &[resolve(from)?], &OBJ_DIR)?;
+                if replay == Replay::Pick {
+                    range.reverse();
+                }
+                commits.extend(range);
+            }
+            None => commits.push(resolve(revision)?),
+        }
+    }
+    if commits.is_empty() {
+        bail!("Empty commit set passed");
+    }
+    Ok(commits)
+}
+
+/// Writes the todo list for the commits of the revisions
+fn start(replay: Replay, revisions: &[String]) -> Result<()> {
+    let head = get_current_commit()?.context("Cannot cherry-pick or revert without a commit")?;
+    ensure_clean_index(&head)?;
+
+    let mut todo = Vec::new();
+    for commit in collect_commits(replay, revisions)? {
+        let message = Commit::load(&commit, &OBJ_DIR)?.message;
+        let (subject, _) = split_message(&message);
+        todo.push(TodoItem {
+            replay,
+            commit,
+            subject: subject.to_string(),
+        });
+    }
+
+    fs::create_dir_all(SEQUENCER.dir()).context("Failed to create the sequencer")?;
+    fs::write(SEQUENCER.file(SEQ_ORIG_HEAD), format!("{}\n", head))?;
+    write_todo(&todo)
+}
+
+/// Applies the commits left in the todo list, stopping at the first that
+/// conflicts
+fn apply_remaining() -> Result<()> {
+    loop {
+        let todo = read_todo()?;
+        let Some(item) = todo.first() else {
+            fs::remove_dir_all(SEQUENCER.dir())?;
+            return Ok(());
+        };
+
+        if !apply_commit(item.replay, &item.commit, &item.subject)? {
+            let short = abbreviate(&OBJ_DIR, &item.commit, 7)?;
+            let hint = format!(
+                "Resolve the conflicts, stage them with 'vox add' and run 'vox {0} --continue', \
+                 or use 'vox {0} --abort'",
+                item.replay.command()
+            );
+            bail!(VoxError::MergeConflict(format!(
+                "Could not {} {}... {}\n{}",
+                item.replay.name(),
+                short,
+                item.subject,
+                hint.yellow()
+            )));
+        }
+        commit_item(item)?;
+        write_todo(&todo[1..])?;
+    }
+}
+
+/// Merges the changes of `commit`, or their opposite, into the index and the
+/// working tree, leaving the conflicts in the working tree
+///
+/// # Returns
+///
+/// Whether the changes merged without conflicts
+pub fn apply_commit(replay: Replay, commit_hash: &str, subject: &str) -> Result<bool> {
+    let head = get_current_commit()?.context("HEAD doesn't point to a commit")?;
+    let commit = Commit::load(commit_hash, &OBJ_DIR)?;
+    let parent_tree = match &commit.parent {
+        Some(parent) => Some(Commit::load(parent, &OBJ_DIR)?.tree),
+        None => None,
+    };
+
+    let short = abbreviate(&OBJ_DIR, commit_hash, 7)?;
+    let label = format!("{}... {}", short, subject);
+    let parent_label = format!("parent of {}", label);
+    let (base, theirs, base_label) = match replay {
+        Replay::Pick => (parent_tree, commit.tree, parent_label.as_str()),
+        Replay::Revert => {
+            let parent_tree = parent_tree
+                .with_context(|| format!("Cannot revert the root commit {}", short))?;
+            (Some(commit.tree), parent_tree, label.as_str())
+        }
+    };
+    let labels = MergeLabels {
+        ours: "HEAD",
+        base: base_label,
+        theirs: match replay {
+            Replay::Pick => &label,
+            Replay::Revert => &parent_label,
+        },
+    };
+    let options = MergeOptions {
+        style: conflict_style(),
+        favor: None,
+    };
+
+    let head_tree = Commit::load(&head, &OBJ_DIR)?.tree;
+    let mut merge =
+        merge_trees(base.as_deref(), &head_tree, &theirs, &labels, &options, &OBJ_DIR)?;
+    checkout_merge(&mut merge)?;
+    for conflict in &merge.conflicts {
+        println!("{}", conflict.to_string().red());
+    }
+    Ok(merge.is_clean())
+}
+
+/// Commits the index for a todo item: a picked commit keeps its author,
+/// date and message, a revert is authored by the user
+///
+/// Nothing is committed if the index has no changes left, e.g. when the
+/// commit was already applied.
+fn commit_item(item: &TodoItem) -> Result<()> {
+    let parent = get_current_commit()?.context("HEAD doesn't point to a commit")?;
+    let original = Commit::load(&item.commit, &OBJ_DIR)?;
+    let (author, author_date, message) = match item.replay {
+        Replay::Pick => (original.author, Some(original.timestamp), original.message),
+        Replay::Revert => (
+            user_identity(),
+            None,
+            revert_message(&item.subject, &item.commit),
+        ),
+    };
+
+    let (subject, _) = split_message(&message);
+    let subject = subject.to_string();
+    let reflog_message = format!("{}: {}", item.replay.command(), subject);
+    match commit_index(Some(&parent), &author, author_date, message, &reflog_message)? {
+        Some(hash) => println!("[{}] {}", abbreviate(&OBJ_DIR, &hash, 7)?, subject),
+        None => {
+            let short = abbreviate(&OBJ_DIR, &item.commit, 7)?;
+            println!("Skipping {}... {}: nothing left to commit", short, item.subject);
+        }
+    }
+    Ok(())
+}
+
+/// The message of the commit reverting `commit`
+fn revert_message(subject: &str, commit: &str) -> String {
+    format!("Revert \"{}\"\n\nThis reverts commit {}.", subject, commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_todo_roundtrip() -> Result<()> {
+        let items = vec![
+            TodoItem {
+                replay: Replay::Pick,
+                commit: "a".repeat(40),
+                subject: "Add the parser".to_string(),
+            },
+            TodoItem {
+                replay: Replay::Revert,
+                commit: "b".repeat(40),
+                subject: String::new(),
+            },
+        ];
+        assert_eq!(parse_todo(&format_todo(&items))?, items);
+
+        let edited = format!("# comment\n\n  pick {} subject with  spaces\n", "c".repeat(40));
+        let parsed = parse_todo(&edited)?;
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].subject, "subject with  spaces");
+        assert!(parse_todo("squash abc").is_err());
+        assert!(parse_todo("pick").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_revert_message() {
+        assert_eq!(
+            revert_message("Add the parser", "abc123"),
+            "Revert \"Add the parser\"\n\nThis reverts commit abc123."
+        );
+    }
+}
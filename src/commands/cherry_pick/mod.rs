@@ -0,0 +1 @@
+pub mod cherry_pick;
@@ -1 +1,77 @@
+use crate::commands::branch::checkout::restore_tree;
+use crate::commands::index::index::Index;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::Loadable;
+use anyhow::Result;
+use std::path::Path;
 
+/// Materializes the tree of `commit`, with its subdirectories and file modes,
+/// in the working tree of the clone at `path`
+///
+/// The index is populated from the same tree so `status` is clean right
+/// after the clone. HEAD was already written by the clone, so unlike
+/// `vox checkout` this neither moves it nor records a reflog entry.
+pub fn checkout_workdir(path: &Path, commit: &str) -> Result<()> {
+    let vox_dir = path.join(".vox");
+    let objects_dir = vox_dir.join("objects");
+    let commit = Commit::load(commit, &objects_dir)?;
+    restore_tree(&commit.tree, path, &objects_dir)?;
+    Index::from_tree(&commit.tree, path, &objects_dir)?.write_to_file(&vox_dir.join("index"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::hash::ObjectFormat;
+    use crate::storage::objects::tree::create_tree;
+    use crate::storage::objects::Storable;
+    use crate::storage::platform::FileStat;
+    use crate::vox_repo::VoxRepo;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkout_workdir_materializes_head() -> Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        let repo = VoxRepo::init(root, ObjectFormat::Sha1, false)?;
+        let objects_dir = repo.objects_dir();
+        let files = [
+            ("README", "readme\n"),
+            ("src/lib.rs", "lib\n"),
+            ("src/a/b.rs", "b\n"),
+        ];
+        for (path, content) in files {
+            fs::create_dir_all(root.join(path).parent().unwrap())?;
+            fs::write(root.join(path), content)?;
+        }
+        let tree = create_tree(root, &objects_dir)?.save(&objects_dir)?;
+        let commit =
+            Commit::new(tree, None, "A <a@x>".into(), "initial".into()).save(&objects_dir)?;
+
+        // A fresh clone has the objects and HEAD but no work tree yet
+        fs::remove_file(root.join("README"))?;
+        fs::remove_dir_all(root.join("src"))?;
+        checkout_workdir(root, &commit)?;
+
+        for (path, content) in files {
+            assert_eq!(fs::read_to_string(root.join(path))?, content);
+        }
+        let mut index = Index::new();
+        index.read_from_file(&root.join(".vox/index"))?;
+        let mut paths: Vec<PathBuf> = index.get_entries().keys().cloned().collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["README", "src/a/b.rs", "src/lib.rs"].map(PathBuf::from)
+        );
+        // The entries carry the stat data of the files, so status finds them clean
+        for (path, entry) in index.get_entries() {
+            let data = fs::read(root.join(path))?;
+            assert_eq!(entry.hash, ObjectFormat::Sha1.digest(&data));
+            assert_eq!(entry.mtime, FileStat::from_path(&root.join(path))?.mtime);
+        }
+        Ok(())
+    }
+}
@@ -1 +1,547 @@
+use crate::commands::clone::checkout_phase::checkout_workdir;
+use crate::commands::clone::fetch_phase::{
+    copy_objects, copy_reachable_objects, map_refs, verify_received, RefMapping,
+};
+use crate::commands::clone::progress::ProgressMode;
+use crate::commands::config::config::{BranchConfig, Config, PersistentConfig};
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::hooks::write_hook_samples;
+use crate::storage::bundle::{is_bundle, read_bundle, unbundle, BundleHeader};
+use crate::storage::objects::{alternates, verifies_received, write_alternates};
+use crate::storage::ref_transaction::RefTransaction;
+use crate::storage::refs::{list_refs, ref_path};
+use crate::storage::revision::resolve_commit;
+use crate::storage::repo_config::{check_repository_format, find_vox_dir, RepoConfig};
+use crate::storage::shallow::{read_shallow, write_shallow};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
 
+/// Name of the remote a clone records its source as
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// What kind of repository a clone creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CloneMode {
+    /// A repository with a working tree, the source's branches become
+    /// remote-tracking branches
+    #[default]
+    Normal,
+    /// A bare repository with the source's branches and tags as they are
+    Bare,
+    /// A bare repository with every ref of the source as it is, for hosting
+    /// or backing up the source
+    Mirror,
+}
+
+/// What the HEAD of the repository being cloned points to
+#[derive(Debug, PartialEq, Eq)]
+enum SourceHead {
+    /// A branch, without a commit if the repository is empty
+    Branch(String, Option<String>),
+    /// A detached commit
+    Detached(String),
+}
+
+/// Where a clone takes its objects and refs from
+#[derive(Debug)]
+enum CloneSource {
+    /// The `.vox` directory of a local repository
+    Repository(PathBuf),
+    /// A bundle file, with the header read from it
+    Bundle(PathBuf, BundleHeader),
+}
+
+/// Clones a repository into a new directory
+///
+/// Only local repositories and bundles can be cloned, given as a path or a
+/// `file://` URL. Objects are hardlinked (or copied) or unpacked from the
+/// bundle, and refs are read directly from the source, without going
+/// through a transport.
+pub struct CloneCommand {
+    url: String,
+    source_url: Url,
+    source: CloneSource,
+    destination: PathBuf,
+    mode: CloneMode,
+    progress: ProgressMode,
+    /// Borrow the source's objects through the alternates instead of
+    /// copying them
+    shared: bool,
+    /// Branch or tag to check out instead of the source's HEAD
+    branch: Option<String>,
+    /// Only clone the branch that is checked out, and the tags
+    single_branch: bool,
+}
+
+impl CloneCommand {
+    /// Prepares cloning `url` into `directory`, which defaults to the name of
+    /// the source repository (with a `.vox` suffix for bare clones)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `url` isn't a local vox repository or bundle, is in
+    /// a format this vox doesn't understand, or the destination already exists
+    /// and isn't empty
+    pub fn new(url: &str, directory: Option<&Path>, mode: CloneMode) -> Result<Self> {
+        let path = local_repository_path(url)?;
+        let source = match is_bundle(&path) {
+            true => CloneSource::Bundle(path.clone(), read_bundle(&path)?),
+            false => CloneSource::Repository(
+                find_vox_dir(&path).ok_or_else(|| {
+                    VoxError::NetworkError(format!("'{}' is not a vox repository", url))
+                })?,
+            ),
+        };
+        if let CloneSource::Repository(source_vox) = &source {
+            check_repository_format(source_vox)?;
+        }
+        let path = fs::canonicalize(&path)
+            .with_context(|| format!("Failed to resolve {}", path.display()))?;
+        let source_url = Url::from_file_path(&path)
+            .map_err(|_| anyhow::anyhow!("Invalid repository path: {}", path.display()))?;
+
+        let destination = match directory {
+            Some(directory) => directory.to_path_buf(),
+            None if mode == CloneMode::Normal => PathBuf::from(default_directory(url)?),
+            None => PathBuf::from(format!("{}.vox", default_directory(url)?)),
+        };
+        let is_empty_dir = destination.is_dir() && fs::read_dir(&destination)?.next().is_none();
+        if destination.exists() && !is_empty_dir {
+            bail!(
+                "Destination path '{}' already exists and is not an empty directory",
+                destination.display()
+            );
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            source_url,
+            source,
+            destination,
+            mode,
+            progress: ProgressMode::default(),
+            shared: false,
+            branch: None,
+            single_branch: false,
+        })
+    }
+
+    /// Sets how progress is reported, see [`ProgressMode`]
+    pub fn with_progress(mut self, progress: ProgressMode) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Reads objects from the source repository instead of copying them,
+    /// which makes the clone instant and takes no space for its history
+    ///
+    /// The clone breaks if the source removes objects it still needs, such
+    /// as with `vox prune` after deleting a branch.
+    pub fn with_shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// Checks out `branch` instead of the branch the source's HEAD points to
+    ///
+    /// A tag can be given too, the clone then has a detached HEAD at the
+    /// tagged commit. Bare clones point their HEAD to it instead.
+    pub fn with_branch(mut self, branch: Option<&str>) -> Self {
+        self.branch = branch.map(str::to_string);
+        self
+    }
+
+    /// Only clones the branch that is checked out (the one given to
+    /// [`with_branch`](Self::with_branch) or the source's HEAD), leaving out
+    /// the other branches. Tags are still cloned.
+    pub fn with_single_branch(mut self, single_branch: bool) -> Self {
+        self.single_branch = single_branch;
+        self
+    }
+
+    /// Creates the clone and checks out the branch the source's HEAD points
+    /// to, or the one selected with [`with_branch`](Self::with_branch)
+    ///
+    /// Bare clones keep the source's HEAD and aren't checked out.
+    pub fn run(&self) -> Result<()> {
+        if self.single_branch && self.mode == CloneMode::Mirror {
+            bail!("--single-branch can't be used with --mirror, which copies every ref");
+        }
+        let mapping = match self.mode {
+            CloneMode::Normal => RefMapping::RemoteTracking(DEFAULT_REMOTE),
+            CloneMode::Bare => RefMapping::Branches,
+            CloneMode::Mirror => RefMapping::Mirror,
+        };
+        match self.mode {
+            _ if !self.progress.is_verbose() => {}
+            CloneMode::Normal => println!("Cloning into '{}'...", self.destination.display()),
+            _ => println!("Cloning into bare repository '{}'...", self.destination.display()),
+        }
+        let refs = self.source_refs()?;
+        // An unknown branch is refused before anything is created
+        let head = match &self.branch {
+            Some(branch) => requested_head(&refs, branch)?,
+            None => self.source_head()?,
+        };
+        let refs = match self.single_branch {
+            true => single_branch_refs(refs, &head),
+            false => refs,
+        };
+        let vox_dir = self.init_repository()?;
+        let reflog_message = format!("clone: from {}", self.url);
+
+        let objects_dir = vox_dir.join("objects");
+        let objects = match &self.source {
+            CloneSource::Repository(source_vox) => {
+                let source_objects = source_vox.join("objects");
+                let mut borrowed = alternates(&source_objects)?;
+                let objects = match self.shared {
+                    true => {
+                        borrowed.insert(0, source_objects);
+                        0
+                    }
+                    false => {
+                        let progress = self.progress.receiving_bar(0);
+                        let copied = match self.single_branch {
+                            true => copy_reachable_objects(
+                                &source_objects,
+                                &objects_dir,
+                                clone_roots(&refs, &head),
+                                &progress,
+                            )?,
+                            false => copy_objects(&source_objects, &objects_dir, &progress)?,
+                        };
+                        // Bundles are unpacked by hashing what they hold, copies aren't
+                        if verifies_received() {
+                            verify_received(&objects_dir)?;
+                        }
+                        copied
+                    }
+                };
+                if !borrowed.is_empty() {
+                    write_alternates(&objects_dir, &borrowed)?;
+                }
+                // A clone of a shallow repository has the same missing parents
+                write_shallow(&vox_dir, &read_shallow(source_vox)?)?;
+                objects
+            }
+            CloneSource::Bundle(..) if self.shared => {
+                bail!("--shared needs a repository to borrow objects from, not a bundle")
+            }
+            CloneSource::Bundle(path, header) if self.single_branch => {
+                // The pack of a bundle can't be read from selectively, it's
+                // unpacked aside and what the kept refs reach is taken from it
+                let staging = tempfile::tempdir_in(&vox_dir)
+                    .context("Failed to create a directory to unpack the bundle")?;
+                RepoConfig::load(&vox_dir)?.save(staging.path())?;
+                unbundle(path, &staging.path().join("objects"))?;
+                let progress = self.progress.receiving_bar(0);
+                let objects = copy_reachable_objects(
+                    &staging.path().join("objects"),
+                    &objects_dir,
+                    clone_roots(&refs, &head),
+                    &progress,
+                )?;
+                write_shallow(&vox_dir, &header.shallow)?;
+                objects
+            }
+            CloneSource::Bundle(path, header) => {
+                let (_, objects) = unbundle(path, &objects_dir)?;
+                write_shallow(&vox_dir, &header.shallow)?;
+                objects
+            }
+        };
+        // A tag given to --branch is checked out as the commit it tags
+        let head = match head {
+            SourceHead::Detached(hash) if self.branch.is_some() => {
+                SourceHead::Detached(resolve_commit(&vox_dir, &hash)?)
+            }
+            head => head,
+        };
+        map_refs(refs, &vox_dir, mapping, &reflog_message)?;
+        if self.progress.is_verbose() {
+            println!("Received {} object files", objects.to_string().green());
+        }
+
+        if self.mode != CloneMode::Normal {
+            let head_ref = match &head {
+                SourceHead::Branch(branch, _) => format!("ref: refs/heads/{}\n", branch),
+                SourceHead::Detached(commit) => format!("{}\n", commit),
+            };
+            fs::write(vox_dir.join("HEAD"), head_ref).context("Failed to write HEAD file")?;
+            return self.configure(&vox_dir, &head);
+        }
+
+        let checkout_commit = match &head {
+            SourceHead::Branch(branch, commit) => {
+                fs::write(vox_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch))?;
+                if let Some(commit) = commit {
+                    let mut transaction = RefTransaction::new(&vox_dir);
+                    transaction.create(&format!("refs/heads/{}", branch), commit, &reflog_message);
+                    transaction.commit()?;
+                }
+                commit.clone()
+            }
+            SourceHead::Detached(commit) => {
+                fs::write(vox_dir.join("HEAD"), format!("{}\n", commit))?;
+                Some(commit.clone())
+            }
+        };
+        self.configure(&vox_dir, &head)?;
+
+        match checkout_commit {
+            Some(commit) => checkout_workdir(&self.destination, &commit),
+            None => {
+                println!("{}", "warning: You appear to have cloned an empty repository.".yellow());
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates the `.vox` directory of the clone (or the clone itself if it's
+    /// bare), using the object format of the source
+    fn init_repository(&self) -> Result<PathBuf> {
+        let bare = self.mode != CloneMode::Normal;
+        let vox_dir = match bare {
+            true => self.destination.clone(),
+            false => self.destination.join(".vox"),
+        };
+        for dir in [vox_dir.join("objects"), vox_dir.join("refs")] {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+
+        // A bare source marks itself as bare in its config
+        let config = match &self.source {
+            CloneSource::Repository(source_vox) => RepoConfig::load(source_vox)?,
+            CloneSource::Bundle(_, header) => RepoConfig::new(header.format),
+        };
+        config
+            .with_bare(bare)
+            .save(&vox_dir)
+            .context("Failed to write config file")?;
+        write_hook_samples(&vox_dir)?;
+        if bare {
+            return Ok(vox_dir);
+        }
+        Index::new()
+            .write_to_file(&vox_dir.join("index"))
+            .context("Failed to create index file")?;
+        Ok(vox_dir)
+    }
+
+    /// Returns the `(name, hash)` refs the source has to clone
+    fn source_refs(&self) -> Result<Vec<(String, String)>> {
+        match &self.source {
+            CloneSource::Repository(source_vox) => list_refs(source_vox),
+            CloneSource::Bundle(_, header) => {
+                let refs = header.refs.iter().filter(|(name, _)| name != "HEAD").cloned();
+                Ok(refs.collect())
+            }
+        }
+    }
+
+    fn source_head(&self) -> Result<SourceHead> {
+        let source_vox = match &self.source {
+            CloneSource::Repository(source_vox) => source_vox,
+            CloneSource::Bundle(_, header) => return bundle_head(header),
+        };
+        let head = fs::read_to_string(source_vox.join("HEAD"))
+            .context("Failed to read the HEAD of the repository to clone")?;
+        let head = head.trim();
+
+        match head.strip_prefix("ref: refs/heads/") {
+            Some(branch) => {
+                let branch_ref = ref_path(source_vox, &format!("refs/heads/{}", branch));
+                let commit = fs::read_to_string(branch_ref)
+                    .ok()
+                    .map(|hash| hash.trim().to_string());
+                Ok(SourceHead::Branch(branch.to_string(), commit))
+            }
+            None => Ok(SourceHead::Detached(head.to_string())),
+        }
+    }
+
+    /// Records the source as the `origin` remote and the upstream of the
+    /// checked out branch, bare clones have no branch checked out
+    fn configure(&self, vox_dir: &Path, head: &SourceHead) -> Result<()> {
+        let config_path = vox_dir.join(".voxconfig");
+        let workdir = fs::canonicalize(&self.destination)?;
+
+        let mut config = Config::read_from_file(&config_path)?;
+        config.add_remote(DEFAULT_REMOTE, self.source_url.as_str(), &workdir)?;
+        if let (SourceHead::Branch(branch, Some(_)), CloneMode::Normal) = (head, self.mode) {
+            let upstream = BranchConfig::new(DEFAULT_REMOTE, format!("refs/heads/{}", branch));
+            config.set_branch_upstream(branch, upstream);
+        }
+        config.write_to_file(&config_path)
+    }
+}
+
+/// Guesses the HEAD of the repository a bundle was made from: the branch
+/// pointing to the recorded HEAD, or the first branch if none does
+fn bundle_head(header: &BundleHeader) -> Result<SourceHead> {
+    let head = header.refs.iter().find(|(name, _)| name == "HEAD").map(|(_, hash)| hash);
+    let branches: Vec<(&str, &String)> = header
+        .refs
+        .iter()
+        .filter_map(|(name, hash)| Some((name.strip_prefix("refs/heads/")?, hash)))
+        .collect();
+
+    let branch = match head {
+        Some(head) => branches.iter().find(|(_, hash)| *hash == head),
+        None => branches.first(),
+    };
+    match (branch, head) {
+        (Some((name, hash)), _) => Ok(SourceHead::Branch(name.to_string(), Some(hash.to_string()))),
+        (None, Some(head)) => Ok(SourceHead::Detached(head.clone())),
+        (None, None) => bail!("The bundle has no branch to check out"),
+    }
+}
+
+/// Finds the branch or tag `name` among the `(name, hash)` refs of the
+/// source, for `clone --branch`
+///
+/// A tag is returned as a detached HEAD at the tag, which is peeled once the
+/// objects have been received.
+fn requested_head(refs: &[(String, String)], name: &str) -> Result<SourceHead> {
+    let find = |prefix: &str| {
+        let full_name = format!("{}{}", prefix, name);
+        refs.iter().find(|(ref_name, _)| *ref_name == full_name).map(|(_, hash)| hash)
+    };
+    if let Some(hash) = find("refs/heads/") {
+        return Ok(SourceHead::Branch(name.to_string(), Some(hash.clone())));
+    }
+    match find("refs/tags/") {
+        Some(hash) => Ok(SourceHead::Detached(hash.clone())),
+        None => bail!("Remote branch '{}' not found in upstream {}", name, DEFAULT_REMOTE),
+    }
+}
+
+/// Keeps the checked out branch and the tags of the source's refs, for
+/// `clone --single-branch`
+///
+/// Nothing but tags is kept when the clone checks out a detached commit.
+fn single_branch_refs(refs: Vec<(String, String)>, head: &SourceHead) -> Vec<(String, String)> {
+    let branch_ref = match head {
+        SourceHead::Branch(branch, _) => Some(format!("refs/heads/{}", branch)),
+        SourceHead::Detached(_) => None,
+    };
+    refs.into_iter()
+        .filter(|(name, _)| name.starts_with("refs/tags/") || Some(name) == branch_ref.as_ref())
+        .collect()
+}
+
+/// Returns the objects a clone starts from: its refs, and the commit HEAD
+/// is detached at
+fn clone_roots(refs: &[(String, String)], head: &SourceHead) -> Vec<String> {
+    let mut roots: Vec<String> = refs.iter().map(|(_, hash)| hash.clone()).collect();
+    if let SourceHead::Detached(hash) = head {
+        roots.push(hash.clone());
+    }
+    roots
+}
+
+/// Clones the repository at `url` into `directory`
+///
+/// `branch` is checked out instead of the source's HEAD, and with
+/// `single_branch` the other branches aren't cloned.
+pub fn clone_command(
+    url: &str,
+    directory: Option<&Path>,
+    mode: CloneMode,
+    progress: ProgressMode,
+    shared: bool,
+    branch: Option<&str>,
+    single_branch: bool,
+) -> Result<()> {
+    CloneCommand::new(url, directory, mode)?
+        .with_progress(progress)
+        .with_shared(shared)
+        .with_branch(branch)
+        .with_single_branch(single_branch)
+        .run()
+}
+
+/// Resolves a repository URL to a local path
+///
+/// Accepts `file://` URLs and plain paths, other schemes need a transport
+/// vox doesn't have.
+pub fn local_repository_path(url: &str) -> Result<PathBuf> {
+    if let Ok(parsed) = Url::parse(url)
+        && parsed.scheme().len() > 1
+    {
+        if parsed.scheme() != "file" {
+            bail!(VoxError::NetworkError(format!(
+                "Cloning '{}' isn't supported, only local repositories can be cloned",
+                url
+            )));
+        }
+        return parsed
+            .to_file_path()
+            .map_err(|_| anyhow::anyhow!("Invalid file URL: {}", url));
+    }
+    Ok(PathBuf::from(url))
+}
+
+/// Returns the directory a clone of `url` is created in by default: the last
+/// path component, without a `.vox` suffix
+pub fn default_directory(url: &str) -> Result<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let name = name.strip_suffix(".vox").unwrap_or(name);
+
+    if name.is_empty() || name == "." || name == ".." {
+        bail!("Couldn't guess a directory name from '{}', please pass one", url);
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_repository_path() -> Result<()> {
+        assert_eq!(local_repository_path("../repo")?, PathBuf::from("../repo"));
+        assert_eq!(local_repository_path("file:///srv/repo")?, PathBuf::from("/srv/repo"));
+        assert!(local_repository_path("https://example.com/repo").is_err());
+
+        assert_eq!(default_directory("file:///srv/project.vox/")?, "project");
+        assert!(default_directory("/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_branch() -> Result<()> {
+        let refs: Vec<(String, String)> = [
+            ("refs/heads/main", "aa"),
+            ("refs/heads/topic", "bb"),
+            ("refs/tags/v1", "cc"),
+        ]
+        .iter()
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect();
+
+        let head = requested_head(&refs, "topic")?;
+        assert_eq!(head, SourceHead::Branch("topic".to_string(), Some("bb".to_string())));
+        assert_eq!(requested_head(&refs, "v1")?, SourceHead::Detached("cc".to_string()));
+        assert!(requested_head(&refs, "missing").is_err());
+
+        let kept: Vec<String> = single_branch_refs(refs.clone(), &head)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(kept, vec!["refs/heads/topic", "refs/tags/v1"]);
+        let detached = single_branch_refs(refs, &SourceHead::Detached("cc".to_string()));
+        assert_eq!(detached.len(), 1);
+        Ok(())
+    }
+}
@@ -1 +1,211 @@
+use crate::storage::ref_transaction::RefTransaction;
+use crate::storage::objects::graph::collect_reachable;
+use crate::storage::objects::{verify_loose_object, ObjectStorage, ALTERNATES_FILE};
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
 
+/// Copies every object file (loose objects and packs) from `source` to
+/// `destination`, returning how many files were copied
+///
+/// Files are hardlinked when both directories are on the same file system,
+/// objects are never modified in place so sharing them is safe. `progress`
+/// advances by the size of each file, with the file count as its message.
+pub fn copy_objects(source: &Path, destination: &Path, progress: &ProgressBar) -> Result<usize> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(source).min_depth(1) {
+        let entry = entry.context("Failed to read objects to clone")?;
+        let target = destination.join(entry.path().strip_prefix(source)?);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("Failed to create {}", target.display()))?;
+            continue;
+        }
+        // Lock files belong to writes in progress in the source, and its
+        // alternates may be relative to it
+        if entry.path().extension().is_some_and(|ext| ext == "lock")
+            || entry.path() == source.join(ALTERNATES_FILE)
+        {
+            continue;
+        }
+        files.push((entry.into_path(), target));
+    }
+    link_files(&files, progress)
+}
+
+/// Copies the objects of `source` that `roots` reach to `destination`, for
+/// clones leaving some refs behind, returning how many files were copied
+///
+/// Loose objects are copied like [`copy_objects`] does, and only those stored
+/// in `source` itself, those of its alternates being borrowed. Packs can't be
+/// taken apart and are copied whole.
+pub fn copy_reachable_objects(
+    source: &Path,
+    destination: &Path,
+    roots: Vec<String>,
+    progress: &ProgressBar,
+) -> Result<usize> {
+    let mut files: Vec<(PathBuf, PathBuf)> = collect_reachable(roots, source)?
+        .into_iter()
+        .filter(|hash| hash.len() > 2)
+        .map(|hash| {
+            let name = Path::new(&hash[..2]).join(&hash[2..]);
+            (source.join(&name), destination.join(&name))
+        })
+        .filter(|(path, _)| path.is_file())
+        .collect();
+    files.sort();
+
+    let pack_dir = source.join("pack");
+    if pack_dir.is_dir() {
+        for entry in fs::read_dir(&pack_dir).context("Failed to read packs to clone")? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_none_or(|ext| ext != "lock") {
+                let target = destination.join("pack").join(path.strip_prefix(&pack_dir)?);
+                files.push((path, target));
+            }
+        }
+    }
+    link_files(&files, progress)
+}
+
+/// Hardlinks, or copies, each `(path, target)` of `files`, see
+/// [`copy_objects`]
+fn link_files(files: &[(PathBuf, PathBuf)], progress: &ProgressBar) -> Result<usize> {
+    let size = |path: &Path| fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+    progress.set_length(files.iter().map(|(path, _)| size(path)).sum());
+    for (copied, (path, target)) in files.iter().enumerate() {
+        let dir = target.parent().context("Invalid object path")?;
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        if fs::hard_link(path, target).is_err() {
+            fs::copy(path, target)
+                .with_context(|| format!("Failed to copy {}", path.display()))?;
+        }
+        progress.set_message(format!("{}/{}", copied + 1, files.len()));
+        progress.inc(size(path));
+    }
+    progress.finish();
+    Ok(files.len())
+}
+
+/// Re-hashes every loose object of `objects_dir`, so that an object
+/// corrupted in the source fails the clone instead of being passed on
+pub fn verify_received(objects_dir: &Path) -> Result<()> {
+    ObjectStorage::at(objects_dir)
+        .loose_objects()?
+        .par_iter()
+        .try_for_each(|object| verify_loose_object(&object.hash, objects_dir))
+        .context("Received a corrupt object")
+}
+
+/// How the refs of the source repository are named in a clone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefMapping<'a> {
+    /// Branches become remote-tracking branches of the given remote, under
+    /// `refs/remotes/<remote>/`, and tags are kept as they are
+    RemoteTracking(&'a str),
+    /// Branches and tags are kept as they are (`clone --bare`)
+    Branches,
+    /// Every ref is kept as it is, including remote-tracking branches and
+    /// notes (`clone --mirror`)
+    Mirror,
+}
+
+impl RefMapping<'_> {
+    /// Returns the name `name` has in the clone, if it's copied at all
+    fn local_name(&self, name: &str) -> Option<String> {
+        let is_branch_or_tag = name.starts_with("refs/heads/") || name.starts_with("refs/tags/");
+        match self {
+            RefMapping::RemoteTracking(remote) => match name.strip_prefix("refs/heads/") {
+                Some(branch) => Some(format!("refs/remotes/{}/{}", remote, branch)),
+                None if name.starts_with("refs/tags/") => Some(name.to_string()),
+                None => None,
+            },
+            RefMapping::Branches if is_branch_or_tag => Some(name.to_string()),
+            RefMapping::Branches => None,
+            RefMapping::Mirror => Some(name.to_string()),
+        }
+    }
+}
+
+/// Creates the refs of a new clone from the `(name, hash)` refs of the
+/// source repository
+///
+/// # Returns
+///
+/// The number of refs created
+pub fn map_refs(
+    refs: Vec<(String, String)>,
+    vox_dir: &Path,
+    mapping: RefMapping,
+    message: &str,
+) -> Result<usize> {
+    let mut transaction = RefTransaction::new(vox_dir);
+    let mut created = 0;
+    for (name, hash) in refs {
+        if let Some(local_name) = mapping.local_name(&name) {
+            transaction.create(&local_name, &hash, message);
+            created += 1;
+        }
+    }
+    transaction.commit().context("Failed to create the cloned refs")?;
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::{loose_object_path, Storable};
+    use crate::storage::refs::list_refs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_map_refs() -> Result<()> {
+        let source = tempdir()?;
+        let clone = tempdir()?;
+        let hash = "ab".repeat(20);
+        for name in ["refs/heads/main", "refs/tags/v1", "refs/remotes/upstream/main"] {
+            let path = source.path().join(name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, format!("{}\n", hash))?;
+        }
+
+        let mapping = RefMapping::RemoteTracking("origin");
+        let refs = list_refs(source.path())?;
+        assert_eq!(map_refs(refs, clone.path(), mapping, "clone")?, 2);
+        let refs: Vec<String> = list_refs(clone.path())?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(refs, vec!["refs/remotes/origin/main", "refs/tags/v1"]);
+
+        let branch = RefMapping::Branches.local_name("refs/heads/main");
+        assert_eq!(branch.as_deref(), Some("refs/heads/main"));
+        assert_eq!(RefMapping::Branches.local_name("refs/remotes/upstream/main"), None);
+        let mirrored = RefMapping::Mirror.local_name("refs/remotes/upstream/main");
+        assert_eq!(mirrored.as_deref(), Some("refs/remotes/upstream/main"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_reachable_objects() -> Result<()> {
+        let source = tempdir()?;
+        let clone = tempdir()?;
+        let source_objects = source.path().join("objects");
+        let save = |data: &[u8]| Blob { data: data.to_vec() }.save(&source_objects);
+        let (kept, left) = (save(b"kept")?, save(b"left behind")?);
+
+        let progress = ProgressBar::hidden();
+        let objects = clone.path().join("objects");
+        let copied = copy_reachable_objects(&source_objects, &objects, vec![kept.clone()], &progress)?;
+        assert_eq!(copied, 1);
+        assert!(loose_object_path(&kept, &objects)?.is_some());
+        assert!(loose_object_path(&left, &objects)?.is_none());
+        Ok(())
+    }
+}
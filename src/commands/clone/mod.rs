@@ -1,3 +1,4 @@
 pub mod clone;
 pub mod fetch_phase;
 pub mod checkout_phase;
+pub mod progress;
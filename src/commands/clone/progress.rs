@@ -0,0 +1,65 @@
+use console::Term;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Whether a clone reports its progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProgressMode {
+    /// Progress bars are shown when stderr is a terminal
+    #[default]
+    Auto,
+    /// Progress bars are drawn even when stderr isn't a terminal (`--progress`)
+    Always,
+    /// Nothing but errors and warnings is printed (`--quiet`)
+    Quiet,
+}
+
+impl ProgressMode {
+    /// Picks the mode from the `--quiet` and `--progress` flags, an explicit
+    /// `--progress` wins over `--quiet`
+    pub fn from_flags(quiet: bool, progress: bool) -> Self {
+        match (quiet, progress) {
+            (_, true) => ProgressMode::Always,
+            (true, false) => ProgressMode::Quiet,
+            (false, false) => ProgressMode::Auto,
+        }
+    }
+
+    /// Returns true if informational messages are printed
+    pub fn is_verbose(&self) -> bool {
+        *self != ProgressMode::Quiet
+    }
+
+    /// Creates the bar counting the bytes of received objects
+    ///
+    /// The message is set by the caller to the number of objects received.
+    pub fn receiving_bar(&self, total_bytes: u64) -> ProgressBar {
+        let target = match self {
+            ProgressMode::Auto => ProgressDrawTarget::stderr(),
+            ProgressMode::Always => ProgressDrawTarget::term_like(Box::new(Term::stderr())),
+            ProgressMode::Quiet => ProgressDrawTarget::hidden(),
+        };
+        let bar = ProgressBar::with_draw_target(Some(total_bytes), target);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "Receiving objects: {percent:>3}% ({msg}), {binary_bytes} | \
+                     {binary_bytes_per_sec}",
+                )
+                .unwrap(),
+        );
+        bar
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_mode_from_flags() {
+        assert_eq!(ProgressMode::from_flags(false, false), ProgressMode::Auto);
+        assert_eq!(ProgressMode::from_flags(true, false), ProgressMode::Quiet);
+        assert_eq!(ProgressMode::from_flags(true, true), ProgressMode::Always);
+        assert!(ProgressMode::Quiet.receiving_bar(10).is_hidden());
+    }
+}
@@ -1,17 +1,56 @@
+use crate::commands::add::add::AddCommand;
+use crate::commands::config::commands::{signing_key, user_identity};
+use crate::commands::format_patch::format_patch::split_message;
 use crate::commands::index::index::Index;
+use crate::commands::merge::conflicts::clear_conflicts;
+use crate::commands::merge::merge::clear_merge_state;
+use crate::commands::merge::rerere::record_resolutions;
+use crate::error::VoxError;
+use crate::storage::hooks::{run_hook, Hook};
 use crate::storage::objects::commit::Commit;
-use crate::storage::objects::tree::{create_tree, store_tree};
-use crate::storage::objects::Storable;
-use crate::storage::utils::{HEAD_DIR, INDEX_FILE, OBJ_DIR, VOX_DIR};
+use crate::storage::objects::{Loadable, Storable};
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::resolve_head;
+use crate::storage::revision::resolve_commit;
+use crate::storage::signing::sign;
+use crate::storage::utils::{parse_date, HEAD_DIR, INDEX_FILE, OBJ_DIR, VOX_DIR};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 /// Takes a commit message and optional author information
-pub fn commit_command(message: &String, author: Option<String>) -> Result<()> {
+///
+/// The pre-commit and commit-msg hooks run first and can abort the commit,
+/// unless `no_verify` is set. The post-commit hook runs once it's recorded.
+///
+/// With `amend` the tip commit is replaced instead: the new commit gets its
+/// parent, and its message, author and author date unless new ones are given.
+///
+/// The author and committer default to the configured user. Their dates can
+/// be pinned with `VOX_AUTHOR_DATE` and `VOX_COMMITTER_DATE` for
+/// reproducible commits.
+///
+/// With `all` every tracked file that was modified or deleted is staged
+/// first, untracked files are left alone.
+///
+/// With `gpg_sign` the commit is signed with the configured signing key.
+///
+/// With `fixup` the message is `fixup! ` followed by the subject of that
+/// commit, for `vox rebase -i --autosquash` to meld the commit into it;
+/// a `message` given along is added as the body.
+pub fn commit_command(
+    message: Option<&str>,
+    author: Option<String>,
+    no_verify: bool,
+    amend: bool,
+    all: bool,
+    gpg_sign: bool,
+    fixup: Option<&str>,
+) -> Result<()> {
     // Verify we're in a VOX repository
     if !PathBuf::from(&*VOX_DIR).exists() {
-        return Err(anyhow::anyhow!("Not a vox repository (or any parent)"));
+        return Err(VoxError::NotARepository.into());
     }
 
     // Check if there are any staged changes to commit
@@ -22,81 +61,205 @@ pub fn commit_command(message: &String, author: Option<String>) -> Result<()> {
         ));
     }
 
-    // Create a tree object from the current directory state
-    let tree = create_tree(Path::new("."))?;
-    let tree_hash = store_tree(&tree)?;
+    if all {
+        AddCommand::new()?.execute_tracked()?;
+    }
 
-    // Get the hash of the current commit (if any) as parent
-    let parent_commit = get_current_commit().context("Failed to get current commit")?;
+    // Get the hash of the current commit (if any), the parent of the new
+    // commit unless it's being amended
+    let head_commit = get_current_commit().context("Failed to get current commit")?;
+    let amended = match (amend, &head_commit) {
+        (false, _) => None,
+        (true, Some(head)) => Some(Commit::load(head, &OBJ_DIR)?),
+        (true, None) => return Err(anyhow::anyhow!("You have nothing to amend")),
+    };
 
-    // Use provided author or default to unknown
-    let author = author.unwrap_or_else(|| String::from("Unknown <unknown@example.com>"));
+    let fixup_message = match fixup {
+        Some(revision) => {
+            let target = resolve_commit(&VOX_DIR, revision)
+                .with_context(|| format!("Invalid commit '{}'", revision))?;
+            let (subject, _) = split_message(&Commit::load(&target, &OBJ_DIR)?.message);
+            Some(match message {
+                Some(body) => format!("fixup! {}\n\n{}", subject, body),
+                None => format!("fixup! {}", subject),
+            })
+        }
+        None => None,
+    };
 
-    // Create and save the new commit object
-    let commit = Commit::new(tree_hash, parent_commit, author, message.to_string());
-    let hash = commit.save(&PathBuf::from(&*OBJ_DIR))?;
+    let message = match (fixup_message.as_deref(), message, &amended) {
+        (Some(fixup_message), _, _) => fixup_message,
+        (None, Some(message), _) => message,
+        (None, None, Some(amended)) => amended.message.as_str(),
+        (None, None, None) => {
+            return Err(anyhow::anyhow!("A commit message is required (use -m)"))
+        }
+    };
 
-    // Update the current branch to point to the new commit
-    update_current_branch(&hash)?;
+    if !no_verify {
+        run_hook(&VOX_DIR, Hook::PreCommit, &[], b"").context("Commit aborted")?;
+    }
+    let message = prepare_message(message, no_verify)?;
 
+    // Create the tree objects from the staged content, reusing the cached
+    // hashes of directories that didn't change
     let mut index = Index::new();
     index.read_from_file(&index_path)?;
-    index.write_to_file(&*INDEX_FILE.as_ref())?;
+    let tree_hash = index.write_tree(&OBJ_DIR)?;
+
+    let parent_commit = match &amended {
+        Some(amended) => amended.parent.clone(),
+        None => head_commit.clone(),
+    };
+
+    // Use provided author, the amended commit's or the configured user
+    let author = author
+        .or_else(|| amended.as_ref().map(|commit| commit.author.clone()))
+        .unwrap_or_else(user_identity);
+
+    let kind = if amended.is_some() {
+        " (amend)"
+    } else if parent_commit.is_none() {
+        " (initial)"
+    } else {
+        ""
+    };
+    let reflog_message = format!(
+        "commit{}: {}",
+        kind,
+        message.lines().next().unwrap_or_default()
+    );
+
+    // Create and save the new commit object
+    let author_date = amended.as_ref().map(|commit| commit.timestamp);
+    let mut commit = new_commit(
+        tree_hash,
+        parent_commit.clone(),
+        author,
+        author_date,
+        message.to_string(),
+    )?;
+
+    if gpg_sign {
+        let (format, key) = signing_key();
+        let signature =
+            sign(&commit.payload(), format, key.as_deref()).context("Failed to sign the commit")?;
+        commit.signature = Some(signature);
+    }
+    let hash = commit.save(&PathBuf::from(&*OBJ_DIR))?;
+
+    // Update the current branch to point to the new commit, unless someone
+    // else moved it in the meantime
+    update_current_branch(&hash, head_commit.as_deref(), &reflog_message)?;
+
+    // Persist the refreshed cached tree for the next commit
+    index.write_to_file(&INDEX_FILE)?;
+    // The commit records the resolution of a stopped merge, which is kept
+    // to resolve the same conflicts again
+    record_resolutions()?;
+    clear_conflicts()?;
+    clear_merge_state()?;
 
     // Print commit confirmation (first 7 chars of hash + message)
     println!("[{}] {}", &hash[..7], commit.message);
 
+    // The commit is already recorded, a failing post-commit hook can't undo it
+    if let Err(error) = run_hook(&VOX_DIR, Hook::PostCommit, &[], b"") {
+        eprintln!("warning: {:#}", error);
+    }
+
     Ok(())
 }
 
+/// Writes the message to `COMMIT_EDITMSG` and lets the commit-msg hook
+/// check or rewrite it
+fn prepare_message(message: &str, no_verify: bool) -> Result<String> {
+    let message_path = VOX_DIR.join("COMMIT_EDITMSG");
+    fs::write(&message_path, format!("{}\n", message.trim_end()))
+        .context("Failed to write COMMIT_EDITMSG")?;
+
+    if no_verify {
+        return Ok(message.to_string());
+    }
+
+    let path_arg = message_path.to_str().context("Invalid repository path")?;
+    run_hook(&VOX_DIR, Hook::CommitMsg, &[path_arg], b"").context("Commit aborted")?;
+
+    let message = fs::read_to_string(&message_path).context("Failed to read COMMIT_EDITMSG")?;
+    let message = message.trim_end();
+    if message.is_empty() {
+        return Err(anyhow::anyhow!("Aborting commit due to empty commit message"));
+    }
+    Ok(message.to_string())
+}
+
+/// Creates a commit of `tree_hash`, committed now by the configured user
+///
+/// The commit is authored by `author` at `author_date`, or now. For
+/// reproducible commits, `VOX_AUTHOR_DATE` and `VOX_COMMITTER_DATE` pin the
+/// dates of every commit made this way, whether it's a new commit or one
+/// replayed by am, cherry-pick, revert or rebase.
+pub fn new_commit(
+    tree_hash: String,
+    parent: Option<String>,
+    author: String,
+    author_date: Option<DateTime<Utc>>,
+    message: String,
+) -> Result<Commit> {
+    let mut commit = Commit::new(tree_hash, parent, author, message);
+    commit.committer = user_identity();
+    if let Some(date) = date_override("VOX_AUTHOR_DATE")?.or(author_date) {
+        commit.timestamp = date;
+    }
+    if let Some(date) = date_override("VOX_COMMITTER_DATE")? {
+        commit.committer_timestamp = date;
+    }
+    Ok(commit)
+}
+
+/// Reads a date from the environment variable `name`, if it's set
+fn date_override(name: &str) -> Result<Option<DateTime<Utc>>> {
+    match std::env::var(name) {
+        Ok(value) => parse_date(&value)
+            .map(Some)
+            .with_context(|| format!("Invalid {}", name)),
+        Err(_) => Ok(None),
+    }
+}
+
 /// Retrieves the hash of the current commit from HEAD
 /// Returns None if there's no commit yet
 pub fn get_current_commit() -> Result<Option<String>> {
-    let head_content = fs::read_to_string(&*HEAD_DIR).context("Failed to read HEAD file")?;
-
-    if head_content.starts_with("ref: ") {
-        // HEAD points to a branch reference
-        let branch_ref = head_content.trim_start_matches("ref: ").trim();
-        let ref_path = PathBuf::from(&*VOX_DIR).join(branch_ref);
-
-        if ref_path.exists() {
-            // Read and return the commit hash from the branch reference file
-            let commit_hash = fs::read_to_string(&ref_path)
-                .context("Failed to read branch reference")?
-                .trim()
-                .to_string();
-            Ok(Some(commit_hash))
-        } else {
-            // Branch exists but has no commits yet
-            Ok(None)
-        }
-    } else {
-        // HEAD contains a direct commit hash (detached HEAD state)
-        Ok(Some(head_content.trim().to_string()))
-    }
+    resolve_head(&VOX_DIR)
 }
 
 /// Updates the current branch or HEAD to point to a new commit
-pub fn update_current_branch(commit_hash: &str) -> Result<()> {
+///
+/// The update only succeeds if the branch (or detached HEAD) still points to
+/// `expected_old`, and is recorded in the reflog with `reflog_message`
+/// describing the operation
+pub fn update_current_branch(
+    commit_hash: &str,
+    expected_old: Option<&str>,
+    reflog_message: &str,
+) -> Result<()> {
     let head_content = fs::read_to_string(&*HEAD_DIR).context("Failed to read HEAD file")?;
 
-    if head_content.starts_with("ref: ") {
-        // Update branch reference
-        let branch_ref = head_content.trim_start_matches("ref: ").trim();
-        let ref_path = PathBuf::from(&*VOX_DIR).join(branch_ref);
-
-        // Ensure parent directories exist
-        if let Some(parent) = ref_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        // Write the new commit hash to the branch reference file
-        fs::write(&ref_path, format!("{}\n", commit_hash))
-            .context("Failed to update branch reference")?;
+    // Update the branch reference, or HEAD directly in detached state
+    let ref_name = if head_content.starts_with("ref: ") {
+        head_content.trim_start_matches("ref: ").trim()
     } else {
-        // Update HEAD directly in detached state
-        fs::write(&*HEAD_DIR, format!("{}\n", commit_hash)).context("Failed to update HEAD")?;
-    }
+        "HEAD"
+    };
 
-    Ok(())
+    let expected = match expected_old {
+        Some(hash) => ExpectedValue::Hash(hash.to_string()),
+        None => ExpectedValue::Missing,
+    };
+
+    let mut transaction = RefTransaction::new(&VOX_DIR);
+    transaction.update(ref_name, commit_hash, expected, reflog_message);
+    transaction
+        .commit()
+        .context("Failed to update branch reference")
 }
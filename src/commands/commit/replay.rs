@@ -0,0 +1,105 @@
+use crate::commands::commit::commit::{get_current_commit, new_commit, update_current_branch};
+use crate::commands::index::index::Index;
+use crate::commands::merge::conflicts::clear_conflicts;
+use crate::commands::merge::rerere::record_resolutions;
+use crate::commands::restore::restore::restore_paths;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::{Loadable, Storable};
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, VOX_DIR};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::PathBuf;
+
+/// The state a command applying commits one at a time (`am`, `cherry-pick`
+/// and `revert`, `rebase`) keeps in a directory of `.vox` between its runs
+pub struct ReplayState {
+    /// Directory of the state, relative to `.vox`
+    dir: &'static str,
+    /// The command, named in the reflog when it's aborted
+    command: &'static str,
+}
+
+impl ReplayState {
+    pub const fn new(dir: &'static str, command: &'static str) -> Self {
+        Self { dir, command }
+    }
+
+    /// The directory holding the state
+    pub fn dir(&self) -> PathBuf {
+        VOX_DIR.join(self.dir)
+    }
+
+    /// Path of the state file `name`
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.dir().join(name)
+    }
+
+    /// Moves the branch back to the commit recorded in the state file
+    /// `orig_head`, restores the index and the working tree to it and drops
+    /// the state
+    ///
+    /// A run started on a branch without commits recorded none, the branch
+    /// is left alone then.
+    pub fn abort(&self, orig_head: &str) -> Result<()> {
+        let orig_head = fs::read_to_string(self.file(orig_head))?;
+        let orig_head = orig_head.trim();
+        let current = get_current_commit()?;
+
+        if !orig_head.is_empty() {
+            if current.as_deref() != Some(orig_head) {
+                let reflog_message = format!("{} --abort", self.command);
+                update_current_branch(orig_head, current.as_deref(), &reflog_message)?;
+            }
+            restore_paths(&[PathBuf::new()], Some(orig_head), true, true)?;
+        }
+        clear_conflicts()?;
+        fs::remove_dir_all(self.dir())?;
+        Ok(())
+    }
+}
+
+/// Commits the index on top of `parent`, making it the tip of the branch
+///
+/// The commit is authored by `author` at `author_date`, or now, see
+/// [`new_commit`].
+///
+/// # Returns
+///
+/// The new commit, or `None` if HEAD is still at `parent` and the index has
+/// its tree, nothing is committed then
+pub fn commit_index(
+    parent: Option<&str>,
+    author: &str,
+    author_date: Option<DateTime<Utc>>,
+    message: String,
+    reflog_message: &str,
+) -> Result<Option<String>> {
+    let mut index = Index::new();
+    index.read_from_file(&INDEX_FILE)?;
+    let tree_hash = index.write_tree(&OBJ_DIR)?;
+    let head = get_current_commit()?;
+    if let Some(parent) = parent
+        && head.as_deref() == Some(parent)
+        && tree_hash == Commit::load(parent, &OBJ_DIR)?.tree
+    {
+        record_resolutions()?;
+        clear_conflicts()?;
+        return Ok(None);
+    }
+
+    let commit = new_commit(
+        tree_hash,
+        parent.map(str::to_string),
+        author.to_string(),
+        author_date,
+        message,
+    )?;
+    let hash = commit.save(&OBJ_DIR)?;
+
+    update_current_branch(&hash, head.as_deref(), reflog_message)?;
+    index.write_to_file(&INDEX_FILE)?;
+    record_resolutions()?;
+    clear_conflicts()?;
+    Ok(Some(hash))
+}
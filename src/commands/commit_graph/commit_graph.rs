@@ -0,0 +1,57 @@
+use crate::error::VoxError;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::commit_graph::CommitGraph;
+use crate::storage::objects::graph::{list_commits, ref_tips};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use clap::Subcommand;
+
+#[derive(Debug, Subcommand)]
+pub enum CommitGraphCommands {
+    #[command(about = "Record the paths each commit changes in objects/info/commit-graph")]
+    Write,
+
+    #[command(about = "Check that the commit graph matches the commits")]
+    Verify,
+}
+
+pub fn commit_graph_command(command: &CommitGraphCommands) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let format = ObjectFormat::for_objects_dir(&OBJ_DIR)?;
+    let existing = CommitGraph::read(&OBJ_DIR, format)?;
+    match command {
+        CommitGraphCommands::Write => {
+            // Commits already in the graph keep their filters
+            let commits = reachable_commits()?;
+            let graph = CommitGraph::build(&commits, &OBJ_DIR, format, existing.as_ref())?;
+            graph.write(&OBJ_DIR)?;
+            println!("Wrote changed-path filters for {} commit(s)", graph.filters.len());
+        }
+        CommitGraphCommands::Verify => {
+            let Some(graph) = existing else {
+                bail!("No commit graph, write one with 'vox commit-graph write'");
+            };
+            let commits: Vec<String> = graph.filters.keys().map(|id| id.to_hex()).collect();
+            if CommitGraph::build(&commits, &OBJ_DIR, format, None)? != graph {
+                bail!("The commit graph doesn't match the paths the commits change");
+            }
+            let missing = reachable_commits()?
+                .iter()
+                .filter(|hash| !graph.contains(hash))
+                .count();
+            println!("The commit graph covers {} commit(s)", graph.filters.len());
+            if missing > 0 {
+                println!("{} newer commit(s) aren't in it yet", missing);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every commit reachable from HEAD and the refs
+fn reachable_commits() -> Result<Vec<String>> {
+    list_commits(&ref_tips(&VOX_DIR)?, &[], &OBJ_DIR)
+}
@@ -0,0 +1 @@
+pub mod commit_graph;
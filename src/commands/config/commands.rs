@@ -1,10 +1,16 @@
-use crate::commands::config::config::{Config, ConfigCommands, PersistentConfig};
+use crate::commands::config::config::{
+    BranchConfig, Config, ConfigCommands, FilterConfig, PersistentConfig,
+};
+use crate::storage::convert::AutoCrlf;
+use crate::storage::merge::ConflictStyle;
+use crate::storage::platform::home_dir;
+use crate::storage::signing::{Keyrings, SigningFormat};
 use crate::storage::utils::VOX_DIR;
 use anyhow::{Context, Result};
 use colored::Colorize;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 lazy_static! {
     static ref EMAIL_REGEX: Regex =
@@ -12,8 +18,7 @@ lazy_static! {
 }
 
 pub fn get_global_config() -> Result<PathBuf> {
-    let home_dir = std::env::var("HOME").context("Couldn't find $HOME directory")?;
-    Ok(PathBuf::from(home_dir).join(".voxconfig"))
+    Ok(home_dir()?.join(".voxconfig"))
 }
 
 pub fn get_local_config() -> Result<PathBuf> {
@@ -21,6 +26,124 @@ pub fn get_local_config() -> Result<PathBuf> {
     Ok(curr_dir.join(&*VOX_DIR).join(".voxconfig"))
 }
 
+/// Returns the configured user as "Name <email>"
+///
+/// The local config takes precedence over the global one. Falls back to a
+/// placeholder identity if neither sets a username
+pub fn user_identity() -> String {
+    for path in [get_local_config(), get_global_config()].into_iter().flatten() {
+        if !path.exists() {
+            continue;
+        }
+        if let Ok(config) = Config::read_from_file(&path)
+            && !config.username().is_empty()
+        {
+            return format!("{} <{}>", config.username(), config.email());
+        }
+    }
+    String::from("Unknown <unknown@example.com>")
+}
+
+/// Returns the configured signing format and key, the local config taking
+/// precedence over the global one
+///
+/// Without a configured key commits are signed with gpg's default key
+pub fn signing_key() -> (SigningFormat, Option<String>) {
+    lookup_setting(|config| {
+        config
+            .signing_key()
+            .map(|(format, key)| (format, Some(key.to_string())))
+    })
+    .unwrap_or((SigningFormat::OpenPgp, None))
+}
+
+/// Returns the keyrings signatures are verified against, each taken from
+/// the local config if it sets one and the global config otherwise
+pub fn verification_keyrings() -> Keyrings {
+    Keyrings {
+        gpg: lookup_setting(|config| config.gpg_keyring().map(Path::to_path_buf)),
+        allowed_signers: lookup_setting(|config| config.allowed_signers().map(Path::to_path_buf)),
+    }
+}
+
+/// Returns the upstream `branch` tracks, which is only set in the local config
+pub fn branch_upstream(branch: &str) -> Option<BranchConfig> {
+    let path = get_local_config().ok().filter(|path| path.exists())?;
+    let config = Config::read_from_file(&path).ok()?;
+    config.branch_upstream(branch).cloned()
+}
+
+/// Returns the style conflicts are written in, the local config taking
+/// precedence over the global one
+pub fn conflict_style() -> ConflictStyle {
+    lookup_setting(Config::conflict_style).unwrap_or_default()
+}
+
+/// Returns the command resolving conflicts for `vox mergetool`, the local
+/// config taking precedence over the global one
+pub fn merge_tool() -> Option<String> {
+    lookup_setting(|config| config.merge_tool().map(str::to_string))
+}
+
+/// Returns how line endings are converted for files without `text` or `eol`
+/// attributes, the local config taking precedence over the global one
+pub fn autocrlf() -> AutoCrlf {
+    lookup_setting(Config::autocrlf).unwrap_or_default()
+}
+
+/// Returns the commands of the content filter `name`, the local config
+/// taking precedence over the global one
+pub fn filter_config(name: &str) -> Option<FilterConfig> {
+    lookup_setting(|config| config.filter(name).cloned())
+}
+
+/// Returns the zlib level set with `core.compressionLevel`, the local config
+/// taking precedence over the global one
+pub fn compression_level() -> Option<u32> {
+    lookup_setting(Config::compression_level)
+}
+
+/// Returns whether objects are re-hashed when read, set with
+/// `core.verifyObjects`
+pub fn verify_objects() -> Option<bool> {
+    lookup_setting(Config::verify_objects)
+}
+
+/// Returns the size above which files are stored as LFS pointers, if large
+/// file storage is turned on
+pub fn lfs_threshold() -> Option<u64> {
+    lookup_setting(Config::lfs_threshold).filter(|&threshold| threshold > 0)
+}
+
+/// Returns the store large files are fetched from and pushed to
+pub fn lfs_url() -> Option<String> {
+    lookup_setting(|config| config.lfs_url().map(str::to_string))
+}
+
+/// Returns the file of ignore rules applying to every repository, the local
+/// config taking precedence over the global one and `~/.config/vox/ignore`
+/// being used if neither sets `core.excludesFile`
+pub fn excludes_file() -> Option<PathBuf> {
+    let path = lookup_setting(|config| config.excludes_file().map(Path::to_path_buf));
+    match path {
+        Some(path) => match path.strip_prefix("~") {
+            Ok(relative) => Some(home_dir().ok()?.join(relative)),
+            Err(_) => Some(path),
+        },
+        None => Some(home_dir().ok()?.join(".config").join("vox").join("ignore")),
+    }
+}
+
+/// Returns the first value `get` finds in the local or the global config
+fn lookup_setting<T>(get: impl Fn(&Config) -> Option<T>) -> Option<T> {
+    [get_local_config(), get_global_config()]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.exists())
+        .filter_map(|path| Config::read_from_file(&path).ok())
+        .find_map(|config| get(&config))
+}
+
 fn is_valid_email(email: &str) -> bool {
     EMAIL_REGEX.is_match(email)
 }
@@ -51,6 +174,52 @@ pub fn config_command(global: bool, config_command: &ConfigCommands) -> Result<(
             } else {
                 println!("{}: {}", "API Key".green(), "Not set");
             }
+
+            if let Some((format, key)) = config.signing_key() {
+                println!("{}: {} ({:?})", "Signing key".green(), key, format);
+            } else {
+                println!("{}: Not set", "Signing key".green());
+            }
+
+            if let Some(keyring) = config.gpg_keyring() {
+                println!("{}: {}", "GPG keyring".green(), keyring.display());
+            }
+            if let Some(allowed_signers) = config.allowed_signers() {
+                println!("{}: {}", "Allowed signers".green(), allowed_signers.display());
+            }
+            if let Some(style) = config.conflict_style() {
+                println!("{}: {:?}", "Conflict style".green(), style);
+            }
+            if let Some(tool) = config.merge_tool() {
+                println!("{}: {}", "Merge tool".green(), tool);
+            }
+            if let Some(excludes_file) = config.excludes_file() {
+                println!("{}: {}", "Excludes file".green(), excludes_file.display());
+            }
+            if let Some(autocrlf) = config.autocrlf() {
+                println!("{}: {:?}", "Autocrlf".green(), autocrlf);
+            }
+            if let Some(level) = config.compression_level() {
+                println!("{}: {}", "Compression level".green(), level);
+            }
+            if let Some(enabled) = config.verify_objects() {
+                println!("{}: {}", "Verify objects".green(), enabled);
+            }
+            for (name, filter) in config.filters() {
+                println!(
+                    "{}: clean {}, smudge {}{}",
+                    format!("Filter {}", name).green(),
+                    filter.clean().unwrap_or("not set"),
+                    filter.smudge().unwrap_or("not set"),
+                    if filter.is_required() { " (required)" } else { "" }
+                );
+            }
+            if let Some(threshold) = config.lfs_threshold() {
+                println!("{}: {} bytes", "LFS threshold".green(), threshold);
+            }
+            if let Some(url) = config.lfs_url() {
+                println!("{}: {}", "LFS URL".green(), url);
+            }
         }
         ConfigCommands::SetUsername { username } => {
             config.set_username(username.trim().to_string());
@@ -72,6 +241,57 @@ pub fn config_command(global: bool, config_command: &ConfigCommands) -> Result<(
             config.set_api_key(Some(api_key.trim().to_string()));
             println!("{}", "Updated API key.".green());
         }
+        ConfigCommands::SetSigningKey { key, format } => {
+            config.set_signing_key(*format, key.trim());
+            println!("{}", "Updated signing key.".green());
+        }
+        ConfigCommands::SetKeyring { path, format } => {
+            // Commands may run from other directories, such as submodules
+            config.set_keyring(*format, path.canonicalize().unwrap_or(path.clone()));
+            println!("{}", "Updated keyring.".green());
+        }
+        ConfigCommands::SetConflictStyle { style } => {
+            config.set_conflict_style(*style);
+            println!("{}", "Updated conflict style.".green());
+        }
+        ConfigCommands::SetMergeTool { command } => {
+            config.set_merge_tool(command.trim());
+            println!("{}", "Updated merge tool.".green());
+        }
+        ConfigCommands::SetExcludesFile { path } => {
+            // Like the keyrings, the path must not depend on where vox runs
+            config.set_excludes_file(path.canonicalize().unwrap_or(path.clone()));
+            println!("{}", "Updated excludes file.".green());
+        }
+        ConfigCommands::SetAutocrlf { value } => {
+            config.set_autocrlf(*value);
+            println!("{}", "Updated line ending conversion.".green());
+        }
+        ConfigCommands::SetCompressionLevel { level } => {
+            config.set_compression_level(*level);
+            println!("{}", "Updated compression level.".green());
+        }
+        ConfigCommands::SetVerifyObjects { enabled } => {
+            config.set_verify_objects(*enabled);
+            println!("{}", "Updated object verification.".green());
+        }
+        ConfigCommands::SetFilter {
+            name,
+            clean,
+            smudge,
+            required,
+        } => {
+            config.set_filter(name, FilterConfig::new(clean.clone(), smudge.clone(), *required));
+            println!("{}", format!("Updated filter {}.", name).green());
+        }
+        ConfigCommands::SetLfsThreshold { bytes } => {
+            config.set_lfs_threshold(*bytes);
+            println!("{}", "Updated LFS threshold.".green());
+        }
+        ConfigCommands::SetLfsUrl { url } => {
+            config.set_lfs_url(url);
+            println!("{}", "Updated LFS URL.".green());
+        }
     }
 
     config.write_to_file(&config_path)?;
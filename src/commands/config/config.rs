@@ -2,11 +2,15 @@ use anyhow::{Context, Result};
 use clap::Subcommand;
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use url::Url;
 
+use crate::storage::convert::AutoCrlf;
+use crate::storage::merge::ConflictStyle;
 use crate::storage::repo::Repository;
+use crate::storage::signing::SigningFormat;
 
 #[derive(Subcommand, Debug)]
 pub enum ConfigCommands {
@@ -15,6 +19,68 @@ pub enum ConfigCommands {
     SetEmail { email: String },
     SetUrl { url: String },
     SetApiKey { api_key: String },
+    SetSigningKey {
+        key: String,
+        #[clap(long, value_enum, default_value = "openpgp")]
+        format: SigningFormat,
+    },
+    /// Set the GPG keyring, or with `--format ssh` the allowed signers file,
+    /// signatures are verified against
+    SetKeyring {
+        path: PathBuf,
+        #[clap(long, value_enum, default_value = "openpgp")]
+        format: SigningFormat,
+    },
+    /// Set how conflicts are written into merged files (`merge.conflictStyle`)
+    SetConflictStyle {
+        #[clap(value_enum)]
+        style: ConflictStyle,
+    },
+    /// Set the command `vox mergetool` resolves conflicts with, run by the
+    /// shell with `$BASE`, `$LOCAL`, `$REMOTE` and `$MERGED` set to the
+    /// files to merge (`merge.tool`)
+    SetMergeTool { command: String },
+    /// Set the file of ignore rules applying to every repository
+    /// (`core.excludesFile`, `~/.config/vox/ignore` by default)
+    SetExcludesFile { path: PathBuf },
+    /// Set how line endings are converted for files without `text` or
+    /// `eol` attributes (`core.autocrlf`)
+    SetAutocrlf {
+        #[clap(value_enum)]
+        value: AutoCrlf,
+    },
+    /// Set how hard objects are compressed, from 0 (fastest) to 9 (smallest)
+    /// (`core.compressionLevel`)
+    SetCompressionLevel {
+        #[clap(value_parser = clap::value_parser!(u32).range(0..=9))]
+        level: u32,
+    },
+    /// Set whether objects are re-hashed when read, failing on corrupt ones
+    /// (`core.verifyObjects`); unset, only the objects a clone receives are
+    SetVerifyObjects {
+        #[clap(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Set the commands of the content filter `name`, which files with the
+    /// `filter=<name>` attribute go through (`filter.<name>.*`)
+    SetFilter {
+        name: String,
+        /// Command turning work tree content into the content to store, `%f`
+        /// standing for the path of the file
+        #[clap(long)]
+        clean: Option<String>,
+        /// Command turning stored content into the content to check out
+        #[clap(long)]
+        smudge: Option<String>,
+        /// Fail instead of keeping the content as is when a command fails
+        #[clap(long)]
+        required: bool,
+    },
+    /// Store files larger than `bytes` in `.vox/lfs/objects`, with a pointer
+    /// in their place (`lfs.threshold`, 0 turns it off)
+    SetLfsThreshold { bytes: u64 },
+    /// Set the store large files are fetched from and pushed to (`lfs.url`)
+    SetLfsUrl { url: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -22,6 +88,23 @@ pub struct Config {
     user: UserConfig,
     server: Option<ServerConfig>,
     remotes: Vec<Repository>,
+    /// URLs of the initialized submodules by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    submodules: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signing: Option<SigningConfig>,
+    /// Upstreams of the branches that track one, by branch name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    branches: BTreeMap<String, BranchConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    merge: Option<MergeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    core: Option<CoreConfig>,
+    /// Content filters by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    filters: BTreeMap<String, FilterConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lfs: Option<LfsConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -30,6 +113,81 @@ pub struct UserConfig {
     email: String,
 }
 
+/// Keys used to sign and verify commits and tags
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SigningConfig {
+    #[serde(default)]
+    format: SigningFormat,
+    /// GPG key id, or the path of an SSH private key, used by `vox commit -S`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gpg_keyring: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allowed_signers: Option<PathBuf>,
+}
+
+/// The branch a local branch tracks, as `branch.<name>.remote` and
+/// `branch.<name>.merge`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BranchConfig {
+    /// Name of the remote, e.g. "origin"
+    remote: String,
+    /// The branch on the remote, e.g. "refs/heads/main"
+    merge: String,
+}
+
+/// Settings of three-way merges, as `merge.*`
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConfig {
+    #[serde(default)]
+    conflict_style: ConflictStyle,
+    /// Command resolving a conflicting file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool: Option<String>,
+}
+
+/// Settings of the work tree, as `core.*`
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CoreConfig {
+    /// Ignore rules applying to every repository, below the repository's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    excludes_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    autocrlf: Option<AutoCrlf>,
+    /// zlib level objects and packs are written with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression_level: Option<u32>,
+    /// Re-hash objects when they're read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    verify_objects: Option<bool>,
+}
+
+/// Commands of a content filter, as `filter.<name>.clean`,
+/// `filter.<name>.smudge` and `filter.<name>.required`
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct FilterConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clean: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smudge: Option<String>,
+    #[serde(default)]
+    required: bool,
+}
+
+/// Settings of large file storage, as `lfs.*`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LfsConfig {
+    /// Size above which files are stored as pointers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    threshold: Option<u64>,
+    /// Store large files are fetched from and pushed to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ServerConfig {
     url: String,
@@ -92,6 +250,20 @@ impl Config {
         }
     }
 
+    pub fn set_signing_key(&mut self, format: SigningFormat, key: impl Into<String>) {
+        let signing = self.signing.get_or_insert_with(SigningConfig::default);
+        signing.format = format;
+        signing.key = Some(key.into());
+    }
+
+    pub fn set_keyring(&mut self, format: SigningFormat, path: impl Into<PathBuf>) {
+        let signing = self.signing.get_or_insert_with(SigningConfig::default);
+        match format {
+            SigningFormat::OpenPgp => signing.gpg_keyring = Some(path.into()),
+            SigningFormat::Ssh => signing.allowed_signers = Some(path.into()),
+        }
+    }
+
     pub fn username(&self) -> &str {
         &self.user.username
     }
@@ -110,6 +282,105 @@ impl Config {
             .and_then(|server| server.api_key.as_ref())
     }
 
+    /// Format and key to sign with, if a signing key is configured
+    pub fn signing_key(&self) -> Option<(SigningFormat, &str)> {
+        let signing = self.signing.as_ref()?;
+        Some((signing.format, signing.key.as_deref()?))
+    }
+
+    pub fn gpg_keyring(&self) -> Option<&Path> {
+        self.signing.as_ref()?.gpg_keyring.as_deref()
+    }
+
+    pub fn allowed_signers(&self) -> Option<&Path> {
+        self.signing.as_ref()?.allowed_signers.as_deref()
+    }
+
+    /// Conflict style set with `merge.conflictStyle`, if any
+    pub fn conflict_style(&self) -> Option<ConflictStyle> {
+        self.merge.as_ref().map(|merge| merge.conflict_style)
+    }
+
+    pub fn set_conflict_style(&mut self, style: ConflictStyle) {
+        self.merge.get_or_insert_with(MergeConfig::default).conflict_style = style;
+    }
+
+    /// Merge tool set with `merge.tool`, if any
+    pub fn merge_tool(&self) -> Option<&str> {
+        self.merge.as_ref()?.tool.as_deref()
+    }
+
+    pub fn set_merge_tool(&mut self, command: impl Into<String>) {
+        self.merge.get_or_insert_with(MergeConfig::default).tool = Some(command.into());
+    }
+
+    /// Global ignore file set with `core.excludesFile`, if any
+    pub fn excludes_file(&self) -> Option<&Path> {
+        self.core.as_ref()?.excludes_file.as_deref()
+    }
+
+    pub fn set_excludes_file(&mut self, path: impl Into<PathBuf>) {
+        self.core.get_or_insert_with(CoreConfig::default).excludes_file = Some(path.into());
+    }
+
+    /// Line ending conversion set with `core.autocrlf`, if any
+    pub fn autocrlf(&self) -> Option<AutoCrlf> {
+        self.core.as_ref()?.autocrlf
+    }
+
+    pub fn set_autocrlf(&mut self, autocrlf: AutoCrlf) {
+        self.core.get_or_insert_with(CoreConfig::default).autocrlf = Some(autocrlf);
+    }
+
+    /// Compression level set with `core.compressionLevel`, if any, levels
+    /// past zlib's best read as 9
+    pub fn compression_level(&self) -> Option<u32> {
+        self.core.as_ref()?.compression_level.map(|level| level.min(9))
+    }
+
+    pub fn set_compression_level(&mut self, level: u32) {
+        self.core.get_or_insert_with(CoreConfig::default).compression_level = Some(level);
+    }
+
+    /// Whether objects are verified when read, set with `core.verifyObjects`
+    pub fn verify_objects(&self) -> Option<bool> {
+        self.core.as_ref()?.verify_objects
+    }
+
+    pub fn set_verify_objects(&mut self, enabled: bool) {
+        self.core.get_or_insert_with(CoreConfig::default).verify_objects = Some(enabled);
+    }
+
+    pub fn filter(&self, name: &str) -> Option<&FilterConfig> {
+        self.filters.get(name)
+    }
+
+    pub fn filters(&self) -> &BTreeMap<String, FilterConfig> {
+        &self.filters
+    }
+
+    pub fn set_filter(&mut self, name: impl Into<String>, filter: FilterConfig) {
+        self.filters.insert(name.into(), filter);
+    }
+
+    /// Size above which files go to large file storage, if set
+    pub fn lfs_threshold(&self) -> Option<u64> {
+        self.lfs.as_ref()?.threshold
+    }
+
+    pub fn set_lfs_threshold(&mut self, threshold: u64) {
+        self.lfs.get_or_insert_with(LfsConfig::default).threshold = Some(threshold);
+    }
+
+    /// Store of large files set with `lfs.url`, if any
+    pub fn lfs_url(&self) -> Option<&str> {
+        self.lfs.as_ref()?.url.as_deref()
+    }
+
+    pub fn set_lfs_url(&mut self, url: impl Into<String>) {
+        self.lfs.get_or_insert_with(LfsConfig::default).url = Some(url.into());
+    }
+
     pub fn remotes(&self) -> &[Repository] {
         &self.remotes
     }
@@ -166,6 +437,29 @@ impl Config {
         Ok(())
     }
 
+    /// Returns the URL of an initialized submodule
+    pub fn submodule_url(&self, name: &str) -> Option<&str> {
+        self.submodules.get(name).map(String::as_str)
+    }
+
+    pub fn set_submodule_url(&mut self, name: impl Into<String>, url: impl Into<String>) {
+        self.submodules.insert(name.into(), url.into());
+    }
+
+    /// Returns the upstream of `branch`, if it tracks one
+    pub fn branch_upstream(&self, branch: &str) -> Option<&BranchConfig> {
+        self.branches.get(branch)
+    }
+
+    pub fn set_branch_upstream(&mut self, branch: impl Into<String>, upstream: BranchConfig) {
+        self.branches.insert(branch.into(), upstream);
+    }
+
+    /// Forgets the settings of `branch`, returning false if it had none
+    pub fn remove_branch(&mut self, branch: &str) -> bool {
+        self.branches.remove(branch).is_some()
+    }
+
     pub fn get_remote(&self, name: &str) -> Result<&Repository> {
         self.remotes
             .iter()
@@ -173,3 +467,71 @@ impl Config {
             .ok_or_else(|| anyhow::anyhow!("Remote '{}' doesn't exist", name))
     }
 }
+
+impl FilterConfig {
+    pub fn new(clean: Option<String>, smudge: Option<String>, required: bool) -> Self {
+        Self {
+            clean,
+            smudge,
+            required,
+        }
+    }
+
+    pub fn clean(&self) -> Option<&str> {
+        self.clean.as_deref()
+    }
+
+    pub fn smudge(&self) -> Option<&str> {
+        self.smudge.as_deref()
+    }
+
+    /// Returns true if content can't be kept as is when a command fails
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+}
+
+impl BranchConfig {
+    pub fn new(remote: impl Into<String>, merge: impl Into<String>) -> Self {
+        Self {
+            remote: remote.into(),
+            merge: merge.into(),
+        }
+    }
+
+    /// Short name of the upstream, e.g. "origin/main"
+    pub fn short_name(&self) -> String {
+        let branch = self.merge.strip_prefix("refs/heads/").unwrap_or(&self.merge);
+        format!("{}/{}", self.remote, branch)
+    }
+
+    /// The remote-tracking ref holding the upstream, e.g. "refs/remotes/origin/main"
+    pub fn tracking_ref(&self) -> String {
+        format!("refs/remotes/{}", self.short_name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_compression_level() -> Result<()> {
+        assert_eq!(Config::default().compression_level(), None);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("config");
+        let mut config = Config::default();
+        config.set_compression_level(0);
+        config.write_to_file(&path)?;
+        assert!(fs::read_to_string(&path)?.contains("compressionLevel = 0"));
+        assert_eq!(Config::read_from_file(&path)?.compression_level(), Some(0));
+
+        // Levels past the best one are clamped when read
+        config.set_compression_level(12);
+        config.write_to_file(&path)?;
+        assert_eq!(Config::read_from_file(&path)?.compression_level(), Some(9));
+        Ok(())
+    }
+}
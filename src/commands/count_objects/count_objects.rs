@@ -0,0 +1,135 @@
+use crate::error::VoxError;
+use crate::storage::objects::{read_object_header, ObjectStorage};
+use crate::storage::refs::list_refs;
+use crate::storage::utils::{
+    OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, OBJ_TYPE_TREE, VOX_DIR,
+};
+use anyhow::Result;
+use colored::*;
+use std::fs;
+use std::path::Path;
+
+/// Number of blobs listed in the verbose "biggest blobs" section
+const BIGGEST_BLOBS_SHOWN: usize = 5;
+
+/// Statistics gathered from the object storage and refs
+#[derive(Debug, Default)]
+struct RepoStats {
+    loose_count: usize, // Number of loose objects
+    loose_size: u64,    // On-disk size of loose objects
+    pack_count: usize,  // Number of packfiles
+    pack_size: u64,     // On-disk size of packfiles
+    ref_count: usize,   // Number of refs under refs/
+    commits: usize,
+    trees: usize,
+    blobs: usize,
+    tags: usize,
+    biggest_blobs: Vec<(String, usize)>, // (hash, uncompressed size)
+}
+
+/// Entry point for the `count-objects` command.
+/// Reports how many objects the repository holds and how much disk they use.
+///
+/// # Arguments
+/// - `verbose`: print a detailed breakdown (packs, refs, object types, biggest blobs)
+/// - `human_readable`: print sizes with binary units
+///
+pub fn count_objects_command(verbose: bool, human_readable: bool) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let stats = collect_stats()?;
+    let size = |bytes: u64| {
+        if human_readable {
+            format_size(bytes)
+        } else {
+            format!("{} kilobytes", bytes / 1024)
+        }
+    };
+
+    if !verbose {
+        println!("{} objects, {}", stats.loose_count, size(stats.loose_size));
+        return Ok(());
+    }
+
+    println!("{:<12} {}", "count:".cyan(), stats.loose_count);
+    println!("{:<12} {}", "size:".cyan(), size(stats.loose_size));
+    println!("{:<12} {}", "packs:".cyan(), stats.pack_count);
+    println!("{:<12} {}", "size-pack:".cyan(), size(stats.pack_size));
+    println!("{:<12} {}", "refs:".cyan(), stats.ref_count);
+    println!("{:<12} {}", "commits:".cyan(), stats.commits);
+    println!("{:<12} {}", "trees:".cyan(), stats.trees);
+    println!("{:<12} {}", "blobs:".cyan(), stats.blobs);
+    println!("{:<12} {}", "tags:".cyan(), stats.tags);
+
+    if !stats.biggest_blobs.is_empty() {
+        println!("{}", "biggest blobs:".cyan());
+        for (hash, blob_size) in &stats.biggest_blobs {
+            println!("  {}  {}", hash.yellow(), format_size(*blob_size as u64));
+        }
+    }
+
+    Ok(())
+}
+
+/// Walks the object storage and refs and aggregates the statistics
+fn collect_stats() -> Result<RepoStats> {
+    let storage = ObjectStorage::new(Path::new("."));
+    let mut stats = RepoStats::default();
+    let mut blobs = Vec::new();
+
+    for object in storage.loose_objects()? {
+        stats.loose_count += 1;
+        stats.loose_size += object.size;
+
+        // Unreadable objects still count towards size, but not towards any type
+        let Ok((object_type, size)) = read_object_header(&object.hash, &OBJ_DIR) else {
+            continue;
+        };
+        match object_type.as_str() {
+            OBJ_TYPE_COMMIT => stats.commits += 1,
+            OBJ_TYPE_TREE => stats.trees += 1,
+            OBJ_TYPE_TAG => stats.tags += 1,
+            OBJ_TYPE_BLOB => {
+                stats.blobs += 1;
+                blobs.push((object.hash, size));
+            }
+            _ => {}
+        }
+    }
+
+    for pack in storage.packs()? {
+        stats.pack_count += 1;
+        stats.pack_size += fs::metadata(&pack)?.len();
+    }
+
+    stats.ref_count = list_refs(&VOX_DIR)?.len();
+
+    // Largest first, ties broken by hash for stable output
+    blobs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    blobs.truncate(BIGGEST_BLOBS_SHOWN);
+    stats.biggest_blobs = blobs;
+
+    Ok(stats)
+}
+
+/// Formats a byte count using binary units (e.g. "1.50 KiB")
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = "bytes";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.2} {}", value, unit)
+}
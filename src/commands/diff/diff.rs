@@ -1,9 +1,31 @@
+use crate::commands::index::index::{Index, IndexEntry};
+use crate::error::VoxError;
+use crate::storage::attributes::diff_as_binary;
+use crate::storage::convert::Converter;
+use crate::storage::objects::blob::Blob;
 use crate::storage::objects::change::{ChangeSet, ChangeType};
-use crate::storage::objects::commit::compare_commits;
-use crate::storage::utils::OBJ_DIR;
-use anyhow::{Context, Result};
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::read_raw_object;
+use crate::storage::objects::tree::{read_tree, RenameDetection, Tree, TreeEntry};
+use crate::storage::patch::line_changes;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::platform::{self, MODE_GITLINK};
+use crate::storage::refs::resolve_head;
+use crate::storage::repo_config::ensure_work_tree;
+use crate::storage::revision::resolve_commit;
+use crate::storage::submodules::{is_nested_repository, submodule_head};
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, VOX_DIR};
+use anyhow::{bail, Context, Result};
 use colored::Colorize;
 use similar::{ChangeTag, TextDiff};
+use std::fs;
+use std::path::Path;
+
+/// Width `--stat` lines are fitted to
+const STAT_WIDTH: usize = 80;
+
+/// Narrowest bar `--stat` draws, however long the paths are
+const MIN_BAR_WIDTH: usize = 10;
 
 /// Computes the unified diff using the Mayers algorithm
 ///
@@ -47,43 +69,467 @@ pub fn text_diff(old: &str, new: &str) -> (String, usize, usize) {
     (unified_diff, insertions, deletions)
 }
 
-/// Show difference between two commits or workdir states
+/// How `vox diff` shows the changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffOutput {
+    /// Every changed file with its diff
+    #[default]
+    Patch,
+    /// A `path | count +++--` line per file and the totals
+    Stat,
+    /// Tab separated insertions, deletions and path of each file
+    Numstat,
+    /// Only the paths of the changed files
+    NameOnly,
+    /// The status letter and paths of each changed file
+    NameStatus,
+}
+
+/// Options of the `diff` command
+#[derive(Debug, Default)]
+pub struct DiffOptions {
+    pub output: DiffOutput,
+    /// Compare the index, rather than the work tree, against a commit
+    pub cached: bool,
+    /// Only compare the files it matches
+    pub pathspec: Pathspec,
+    /// Show the changed words inline instead of the changed lines
+    pub word_diff: bool,
+    /// How renamed and copied files are found
+    pub renames: RenameDetection,
+}
+
+/// Shows the differences between commits, the index and the work tree
 ///
 /// # Arguments
 ///
-/// * 'from' - source commit/reference (default: HEAD~)
-/// * 'to' - target commit/reference (default: HEAD)
+/// * 'from' - source commit/reference
+/// * 'to' - target commit/reference
+/// * 'options' - how to show the changes, and which files to compare
 ///
-///  # Examples
+/// Like git, what is compared depends on the revisions given:
 ///
-///  A <- B <- C <- D
-///  where:
-///     'HEAD' = commit D
-///     'HEAD~' = commit C
-///     'HEAD~2' = commit B
-///     'HEAD~3' = commit A
+/// | Command                    | From      | To        |
+/// |----------------------------|-----------|-----------|
+/// | `vox diff`                 | index     | work tree |
+/// | `vox diff --cached [rev]`  | rev, HEAD | index     |
+/// | `vox diff <rev>`           | rev       | work tree |
+/// | `vox diff <rev> <rev>`     | rev       | rev       |
 ///
-/// diff_command(None, None).unwrap(); => comparison between 'HEAD~' and 'HEAD'
+/// The work tree only contributes tracked files, untracked files are never
+/// shown.
 ///
-pub fn diff_command(from: Option<String>, to: Option<String>) -> Result<()> {
-    let from_ref = from.as_deref().unwrap_or("HEAD~");
-    let to_ref = to.as_deref().unwrap_or("HEAD");
+///  # Examples
+///
+/// diff_command(Some("HEAD~".into()), Some("HEAD".into()), &DiffOptions::default()).unwrap();
+/// => the changes made by the last commit
+///
+pub fn diff_command(from: Option<String>, to: Option<String>, options: &DiffOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
 
-    let changes = compare_commits(from_ref, to_ref, &*OBJ_DIR)
-        .with_context(|| format!("Failed to compare commits {}..{}", from_ref, to_ref))?;
+    let (old, new) = match (options.cached, from, to) {
+        (true, _, Some(_)) => bail!("--cached compares the index with a single commit"),
+        (true, from, None) => (commit_side(from.as_deref().unwrap_or("HEAD"))?, index_side()?),
+        (false, None, _) => (index_side()?, work_tree_side()?),
+        (false, Some(from), None) => (commit_side(&from)?, work_tree_side()?),
+        (false, Some(from), Some(to)) => (commit_side(&from)?, commit_side(&to)?),
+    };
 
-    print_changes(&changes).context("Failed to display diff output")?;
+    let (old_label, old_tree) = old;
+    let (new_label, new_tree) = new;
+    let mut changes = Tree::compare_trees_with(
+        &limit_to_pathspec(old_tree, &options.pathspec),
+        &limit_to_pathspec(new_tree, &options.pathspec),
+        &OBJ_DIR,
+        &options.renames,
+    )
+    .context("Failed to compare trees")?;
+    changes.set_from(old_label);
+    changes.set_to(new_label);
+
+    match options.output {
+        DiffOutput::Patch => {
+            print_changes(&changes, options.word_diff).context("Failed to display diff output")?
+        }
+        output => print!("{}", format_summary(&file_stats(&changes)?, output)),
+    }
 
     Ok(())
 }
 
+/// One side of a comparison: its label and its files as a flattened tree
+type DiffSide = (Option<String>, Tree);
+
+/// The files of the commit `revision`, or no files if it's HEAD on an
+/// unborn branch
+fn commit_side(revision: &str) -> Result<DiffSide> {
+    if revision == "HEAD" && resolve_head(&VOX_DIR)?.is_none() {
+        return Ok((Some("initial".to_string()), Tree { entries: Vec::new() }));
+    }
+    let hash = resolve_commit(&VOX_DIR, revision)?;
+    let tree = load_commit(&hash, &OBJ_DIR)?.tree;
+    let files = read_tree(&tree, &OBJ_DIR)
+        .and_then(|tree| tree.flatten(&OBJ_DIR))
+        .with_context(|| format!("Failed to load tree {}", tree))?;
+    Ok((Some(hash), files))
+}
+
+/// The files staged in the index
+fn index_side() -> Result<DiffSide> {
+    ensure_work_tree()?;
+    let index = read_index()?;
+    let entries = index
+        .get_entries()
+        .values()
+        .map(|entry| index_tree_entry(entry, entry.hash.to_hex()))
+        .collect();
+    Ok((Some("index".to_string()), Tree { entries }))
+}
+
+/// The tracked files as they are in the work tree
+///
+/// Files whose metadata still matches the index keep its hash, the others
+/// are hashed and stored so their content can be compared
+fn work_tree_side() -> Result<DiffSide> {
+    ensure_work_tree()?;
+    let index = read_index()?;
+    let mut converter = Converter::new(Path::new("."));
+    let mut entries = Vec::new();
+    for (path, entry) in index.get_entries() {
+        // Submodules are compared by the commit they have checked out
+        if entry.stat().tree_mode() == MODE_GITLINK {
+            let head = match is_nested_repository(path) {
+                true => submodule_head(path)?,
+                false => None,
+            };
+            entries.push(index_tree_entry(entry, head.unwrap_or(entry.hash.to_hex())));
+            continue;
+        }
+
+        // A dangling symlink is still present
+        if fs::symlink_metadata(path).is_err() {
+            continue;
+        }
+        let stat = platform::FileStat::from_path(path)?;
+        let hash = match stat.matches(&entry.stat()) && !stat.is_symlink() {
+            true => entry.hash.to_hex(),
+            false => Blob::save_worktree(path, path, &mut converter, &OBJ_DIR)
+                .with_context(|| format!("Failed to hash {}", path.display()))?,
+        };
+        entries.push(TreeEntry {
+            mode: format!("{:o}", stat.tree_mode()),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: hash,
+            name: path.to_string_lossy().into_owned(),
+        });
+    }
+    // The work tree has no name in the output
+    Ok((None, Tree { entries }))
+}
+
+fn read_index() -> Result<Index> {
+    let mut index = Index::new();
+    if INDEX_FILE.exists() {
+        index.read_from_file(&INDEX_FILE)?;
+    }
+    Ok(index)
+}
+
+/// A flattened tree entry for the index entry `entry`, with the hash `hash`
+fn index_tree_entry(entry: &IndexEntry, hash: String) -> TreeEntry {
+    let mode = entry.stat().tree_mode();
+    TreeEntry {
+        mode: format!("{:o}", mode),
+        object_type: match mode {
+            MODE_GITLINK => OBJ_TYPE_COMMIT,
+            _ => OBJ_TYPE_BLOB,
+        }
+        .to_string(),
+        object_hash: hash,
+        name: entry.path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Keeps the files of a flattened tree matched by `pathspec`
+fn limit_to_pathspec(tree: Tree, pathspec: &Pathspec) -> Tree {
+    if pathspec.is_empty() {
+        return tree;
+    }
+    let entries = tree
+        .entries
+        .into_iter()
+        .filter(|entry| pathspec.matches(Path::new(&entry.name)))
+        .collect();
+    Tree { entries }
+}
+
+/// What changed in one file, for the summary outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileStat {
+    /// `A`, `D`, `M`, `R` or `C`
+    status: char,
+    /// Path before a rename, or of the file copied
+    old_path: Option<String>,
+    /// Similarity to the old path, in percent
+    similarity: Option<u8>,
+    path: String,
+    /// Lines inserted and deleted, `None` for binary files
+    lines: Option<(usize, usize)>,
+}
+
+impl FileStat {
+    /// The path as shown by `--stat` and `--numstat`, `old => new` for renames
+    fn display_path(&self) -> String {
+        match &self.old_path {
+            Some(old_path) => format!("{} => {}", old_path, self.path),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// The content of the blob `hash`, empty for a missing file
+fn blob_content(hash: Option<&str>) -> Vec<u8> {
+    match hash.map(|hash| read_raw_object(hash, &OBJ_DIR)) {
+        Some(Ok((object_type, data))) if object_type == OBJ_TYPE_BLOB => data,
+        // Submodule commits aren't blobs and have no lines
+        _ => Vec::new(),
+    }
+}
+
+/// Counts the changed lines of every file in `changes`, sorted by path
+fn file_stats(changes: &ChangeSet) -> Result<Vec<FileStat>> {
+    let mut stats = Vec::new();
+    for change in changes.get().values() {
+        let status = match change {
+            ChangeType::ADDED { .. } => 'A',
+            ChangeType::DELETED { .. } => 'D',
+            ChangeType::MODIFIED { .. } => 'M',
+            ChangeType::RENAMED { .. } => 'R',
+            ChangeType::COPIED { .. } => 'C',
+        };
+        let old = blob_content(change.old_hash());
+        let new = blob_content(change.new_hash());
+        stats.push(FileStat {
+            status,
+            old_path: change.old_path().map(|path| path.display().to_string()),
+            similarity: change.similarity(),
+            path: change.path().display().to_string(),
+            lines: match diff_as_binary(change.path()) {
+                Some(true) => None,
+                _ => line_changes(&old, &new),
+            },
+        });
+    }
+    stats.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(stats)
+}
+
+/// Renders the changed files in one of the summary outputs
+fn format_summary(stats: &[FileStat], output: DiffOutput) -> String {
+    let mut out = String::new();
+    for stat in stats {
+        let line = match output {
+            DiffOutput::NameOnly => stat.path.clone(),
+            DiffOutput::NameStatus => match (&stat.old_path, stat.similarity) {
+                (Some(old_path), Some(similarity)) => {
+                    format!("{}{:03}\t{}\t{}", stat.status, similarity, old_path, stat.path)
+                }
+                (Some(old_path), None) => format!("{}\t{}\t{}", stat.status, old_path, stat.path),
+                (None, _) => format!("{}\t{}", stat.status, stat.path),
+            },
+            DiffOutput::Numstat => match stat.lines {
+                Some((insertions, deletions)) => {
+                    format!("{}\t{}\t{}", insertions, deletions, stat.display_path())
+                }
+                None => format!("-\t-\t{}", stat.display_path()),
+            },
+            DiffOutput::Stat | DiffOutput::Patch => return format_stat(stats),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Renders `--stat`: one line per file with a bar of `+` and `-` scaled to
+/// fit the terminal width, then the totals
+fn format_stat(stats: &[FileStat]) -> String {
+    let name_width = stats.iter().map(|stat| stat.display_path().chars().count()).max();
+    let Some(name_width) = name_width else {
+        return String::new();
+    };
+    let changed = |stat: &FileStat| stat.lines.map_or(0, |(ins, del)| ins + del);
+    let max_changed = stats.iter().map(changed).max().unwrap_or(0);
+    let count_width = max_changed.to_string().len().max(3);
+    let bar_width = STAT_WIDTH.saturating_sub(name_width + count_width + 4).max(MIN_BAR_WIDTH);
+    // Bars are scaled down when the largest change doesn't fit, keeping at
+    // least one character for every non-empty side
+    let scale = |count: usize| match max_changed > bar_width && count > 0 {
+        true => (count * bar_width / max_changed).max(1),
+        false => count,
+    };
+
+    let mut out = String::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for stat in stats {
+        let (count, bar) = match stat.lines {
+            Some((ins, del)) => {
+                insertions += ins;
+                deletions += del;
+                let bar = match ins + del {
+                    0 => String::new(),
+                    _ => format!(
+                        " {}{}",
+                        "+".repeat(scale(ins)).green(),
+                        "-".repeat(scale(del)).red()
+                    ),
+                };
+                ((ins + del).to_string(), bar)
+            }
+            None => ("Bin".to_string(), String::new()),
+        };
+        let padding = name_width - stat.display_path().chars().count();
+        out.push_str(&format!(
+            " {}{} | {:>count_width$}{}\n",
+            stat.display_path(),
+            " ".repeat(padding),
+            count,
+            bar
+        ));
+    }
+
+    let plural = |count: usize, word: &str| match count {
+        1 => format!("{} {}", count, word),
+        _ => format!("{} {}s", count, word),
+    };
+    out.push_str(&format!(" {} changed", plural(stats.len(), "file")));
+    if insertions > 0 || deletions == 0 {
+        out.push_str(&format!(", {}(+)", plural(insertions, "insertion")));
+    }
+    if deletions > 0 || insertions == 0 {
+        out.push_str(&format!(", {}(-)", plural(deletions, "deletion")));
+    }
+    out.push('\n');
+    out
+}
+
+/// Parses the similarity of `-M<n>` and `-C<n>` the way git does: a
+/// percentage such as `75%`, or the digits of a fraction, `75` or `5` for
+/// 0.75 and 0.5
+pub fn parse_similarity(value: &str) -> Result<u8> {
+    let invalid = || anyhow::anyhow!("Invalid similarity '{}'", value);
+    if let Some(percent) = value.strip_suffix('%') {
+        let percent: u8 = percent.parse().map_err(|_| invalid())?;
+        return match percent <= 100 {
+            true => Ok(percent),
+            false => Err(invalid()),
+        };
+    }
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    // Only the first two digits matter in percent
+    let digits = format!("{:0<2}", &value[..value.len().min(2)]);
+    digits.parse().map_err(|_| invalid())
+}
+
+/// Describes how the size of a binary file changed, e.g. `120 -> 200 bytes, +80`
+fn size_change(old_size: usize, new_size: usize) -> String {
+    let delta = match new_size >= old_size {
+        true => format!("+{}", new_size - old_size),
+        false => format!("-{}", old_size - new_size),
+    };
+    format!("{} -> {} bytes, {}", old_size, new_size, delta)
+}
+
+/// Renders the line diff of two texts, highlighting the changed parts of
+/// the modified lines
+fn line_diff(old: &str, new: &str) -> String {
+    let diff = TextDiff::configure()
+        .algorithm(similar::Algorithm::Myers)
+        .diff_lines(old, new);
+
+    let mut out = String::new();
+    for op in diff.ops() {
+        for change in diff.iter_inline_changes(op) {
+            let sign = match change.tag() {
+                ChangeTag::Delete => "-".red(),
+                ChangeTag::Insert => "+".green(),
+                ChangeTag::Equal => " ".normal(),
+            };
+            out.push_str(&sign.to_string());
+            for (emphasized, value) in change.iter_strings_lossy() {
+                let value = value.strip_suffix('\n').unwrap_or(&value);
+                let span = match (change.tag(), emphasized) {
+                    (ChangeTag::Delete, false) => value.red(),
+                    (ChangeTag::Delete, true) => value.red().reversed(),
+                    (ChangeTag::Insert, false) => value.green(),
+                    (ChangeTag::Insert, true) => value.green().reversed(),
+                    (ChangeTag::Equal, _) => value.normal(),
+                };
+                out.push_str(&span.to_string());
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Renders the changed words of two texts inline, git style: removed words
+/// as `[-word-]` and added ones as `{+word+}`
+///
+/// Only the lines with changes are kept.
+fn word_diff(old: &str, new: &str) -> String {
+    let diff = TextDiff::from_words(old, new);
+
+    // Consecutive words with the same tag share their markers
+    let mut runs: Vec<(ChangeTag, String)> = Vec::new();
+    let mut lines = Vec::new();
+    let mut changed = false;
+    let mut end_line = |runs: &mut Vec<(ChangeTag, String)>, changed: &mut bool| {
+        if *changed && !runs.is_empty() {
+            lines.push(runs.iter().map(|(tag, text)| mark(*tag, text)).collect::<String>());
+        }
+        runs.clear();
+        *changed = false;
+    };
+    for change in diff.iter_all_changes() {
+        for (i, piece) in change.value().split('\n').enumerate() {
+            if i > 0 {
+                end_line(&mut runs, &mut changed);
+            }
+            changed |= change.tag() != ChangeTag::Equal;
+            match runs.last_mut() {
+                _ if piece.is_empty() => {}
+                Some((tag, text)) if *tag == change.tag() => text.push_str(piece),
+                _ => runs.push((change.tag(), piece.to_string())),
+            }
+        }
+    }
+    end_line(&mut runs, &mut changed);
+
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Wraps a run of words in the markers of `--word-diff`
+fn mark(tag: ChangeTag, text: &str) -> String {
+    match tag {
+        ChangeTag::Delete => format!("[-{}-]", text).red().to_string(),
+        ChangeTag::Insert => format!("{{+{}+}}", text).green().to_string(),
+        ChangeTag::Equal => text.to_string(),
+    }
+}
+
 /// Prints the changes in human-readable format
 ///
 /// # Arguments
 ///
 /// * 'changes' - The changes to display
+/// * 'word_diff' - Show the changed words of modified files rather than lines
 ///
-fn print_changes(changes: &ChangeSet) -> Result<()> {
+fn print_changes(changes: &ChangeSet, word_diff: bool) -> Result<()> {
     println!(
         "diff between {} and {}",
         changes.from().unwrap_or("initial").yellow(),
@@ -95,7 +541,9 @@ fn print_changes(changes: &ChangeSet) -> Result<()> {
         return Ok(());
     } else {
         println!("Changes: ");
-        for (_path, changes_type) in &changes.get() {
+        let mut entries: Vec<_> = changes.get().into_iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (_path, changes_type) in &entries {
             match changes_type {
                 ChangeType::ADDED { path, .. } => {
                     println!("{} {}", "A".green(), path.display());
@@ -105,35 +553,55 @@ fn print_changes(changes: &ChangeSet) -> Result<()> {
                 }
                 ChangeType::MODIFIED {
                     path,
-                    old_hash: _,
-                    new_hash: _,
+                    old_hash,
+                    new_hash,
                     summary,
                 } => {
                     println!("{} {}", "M".yellow(), path.display());
 
-                    if let Some(summary) = summary {
+                    let binary_sizes = summary.as_ref().and_then(|summary| summary.binary_sizes());
+                    if let Some((old_size, new_size)) = binary_sizes {
+                        println!("  Binary files differ ({})", size_change(old_size, new_size));
+                    } else if let Some(summary) = summary {
                         println!(
                             "  {} lines added, {} lines deleted",
                             summary.insertions().to_string().green(),
                             summary.removals().to_string().red()
                         );
-                        if let Some(text_diff) = summary.text_diff() {
-                            println!("{}", text_diff);
+                        let old = blob_content(Some(old_hash));
+                        let new = blob_content(Some(new_hash));
+                        let old = String::from_utf8_lossy(&old);
+                        let new = String::from_utf8_lossy(&new);
+                        match word_diff {
+                            true => println!("{}", self::word_diff(&old, &new)),
+                            false => println!("{}", line_diff(&old, &new)),
                         }
                     }
                 }
                 ChangeType::RENAMED {
                     old_path,
                     new_path,
-                    old_hash: _,
-                    new_hash: _,
+                    similarity,
                     summary,
+                    ..
+                }
+                | ChangeType::COPIED {
+                    old_path,
+                    new_path,
+                    similarity,
+                    summary,
+                    ..
                 } => {
+                    let letter = match changes_type {
+                        ChangeType::COPIED { .. } => "C",
+                        _ => "R",
+                    };
                     println!(
-                        "{} {} -> {}",
-                        "R".cyan(),
+                        "{} {} -> {} ({}%)",
+                        letter.cyan(),
                         old_path.display(),
-                        new_path.display()
+                        new_path.display(),
+                        similarity
                     );
 
                     if let Some(summary) = summary {
@@ -150,3 +618,117 @@ fn print_changes(changes: &ChangeSet) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(status: char, path: &str, lines: Option<(usize, usize)>) -> FileStat {
+        FileStat {
+            status,
+            old_path: None,
+            similarity: None,
+            path: path.to_string(),
+            lines,
+        }
+    }
+
+    #[test]
+    fn test_summary_outputs() {
+        colored::control::set_override(false);
+        let mut renamed = stat('R', "src/new.rs", Some((0, 0)));
+        renamed.old_path = Some("old.rs".to_string());
+        renamed.similarity = Some(100);
+        let stats = vec![
+            stat('M', "README.md", Some((3, 1))),
+            stat('A', "logo.png", None),
+            renamed,
+        ];
+
+        assert_eq!(
+            format_summary(&stats, DiffOutput::Numstat),
+            "3\t1\tREADME.md\n-\t-\tlogo.png\n0\t0\told.rs => src/new.rs\n"
+        );
+        assert_eq!(
+            format_summary(&stats, DiffOutput::NameStatus),
+            "M\tREADME.md\nA\tlogo.png\nR100\told.rs\tsrc/new.rs\n"
+        );
+        assert_eq!(
+            format_summary(&stats, DiffOutput::Stat),
+            " README.md            |   4 +++-\n \
+             logo.png             | Bin\n \
+             old.rs => src/new.rs |   0\n \
+             3 files changed, 3 insertions(+), 1 deletion(-)\n"
+        );
+    }
+
+    #[test]
+    fn test_limit_to_pathspec() -> Result<()> {
+        let entry = |name: &str| TreeEntry {
+            mode: "100644".to_string(),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: String::new(),
+            name: name.to_string(),
+        };
+        let tree = || Tree {
+            entries: vec![entry("src/main.rs"), entry("src2/lib.rs"), entry("README.md")],
+        };
+        let names = |specs: &[&str]| -> Result<Vec<String>> {
+            let tree = limit_to_pathspec(tree(), &Pathspec::new(specs)?);
+            Ok(tree.entries.into_iter().map(|entry| entry.name).collect())
+        };
+
+        assert_eq!(names(&[])?.len(), 3);
+        assert_eq!(names(&["src"])?, ["src/main.rs"]);
+        assert_eq!(names(&["./README.md"])?, ["README.md"]);
+        assert_eq!(names(&["*.rs", ":!src2"])?, ["src/main.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_line_and_word_diff() {
+        colored::control::set_override(false);
+        let old = "fn main() {\n    println!(\"hello\");\n}\n";
+        let new = "fn main() {\n    println!(\"hello, world\");\n}\n";
+
+        assert_eq!(
+            line_diff(old, new),
+            " fn main() {\n-    println!(\"hello\");\n+    println!(\"hello, world\");\n }\n"
+        );
+        assert_eq!(
+            word_diff(old, new),
+            "    [-println!(\"hello\");-]{+println!(\"hello, world\");+}\n"
+        );
+        assert_eq!(word_diff("a\nb\n", "a\nb\nc\n"), "{+c+}\n");
+    }
+
+    #[test]
+    fn test_parse_similarity() -> Result<()> {
+        assert_eq!(parse_similarity("75%")?, 75);
+        assert_eq!(parse_similarity("100%")?, 100);
+        assert_eq!(parse_similarity("5")?, 50);
+        assert_eq!(parse_similarity("05")?, 5);
+        assert_eq!(parse_similarity("755")?, 75);
+        assert!(parse_similarity("101%").is_err());
+        assert!(parse_similarity("x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_change() {
+        assert_eq!(size_change(120, 200), "120 -> 200 bytes, +80");
+        assert_eq!(size_change(200, 120), "200 -> 120 bytes, -80");
+    }
+
+    #[test]
+    fn test_stat_scales_bars() {
+        colored::control::set_override(false);
+        let stats = [stat('M', "big", Some((300, 100))), stat('M', "small", Some((1, 0)))];
+        let out = format_stat(&stats);
+        let lines: Vec<&str> = out.lines().collect();
+        // 80 columns minus the path, the count and the separators
+        assert_eq!(lines[0].matches('+').count(), 51);
+        assert_eq!(lines[0].matches('-').count(), 17);
+        assert_eq!(lines[1], " small |   1 +");
+    }
+}
@@ -0,0 +1,252 @@
+use crate::commands::bundle::bundle::find_ref;
+use crate::commands::fast_import::fast_import::{read_marks, write_marks};
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::{read_object_header, read_raw_object, Loadable};
+use crate::storage::refs::list_refs;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, VOX_DIR};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+/// Options of the `fast-export` command
+#[derive(Debug, Default)]
+pub struct FastExportOptions {
+    /// Marks file of a previous export, its commits aren't exported again
+    pub import_marks: Option<PathBuf>,
+    /// File to save the marks to after the export
+    pub export_marks: Option<PathBuf>,
+}
+
+/// Writes refs and their history as a git fast-import stream to stdout
+///
+/// The stream can be fed to `git fast-import` or `vox fast-import` to
+/// recreate the history in another repository.
+///
+/// # Arguments
+/// - `refs`: the branches and tags to export (every branch and tag if empty)
+/// - `options`: the marks files to read and write
+///
+pub fn fast_export_command(refs: &[String], options: &FastExportOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let all_refs = list_refs(&VOX_DIR)?;
+    let selected = if refs.is_empty() {
+        all_refs
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+            .collect()
+    } else {
+        refs.iter()
+            .map(|name| find_ref(&all_refs, name))
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut exporter = Exporter {
+        out: BufWriter::new(io::stdout().lock()),
+        marks: HashMap::new(),
+        next_mark: 1,
+    };
+    if let Some(path) = &options.import_marks {
+        for (mark, hash) in read_marks(path)? {
+            exporter.next_mark = exporter.next_mark.max(mark + 1);
+            exporter.marks.insert(hash, mark);
+        }
+    }
+
+    for (name, hash) in &selected {
+        exporter.export_ref(name, hash)?;
+    }
+    exporter.out.flush()?;
+
+    if let Some(path) = &options.export_marks {
+        write_marks(path, exporter.marks.iter().map(|(hash, &mark)| (mark, hash.as_str())))?;
+    }
+    Ok(())
+}
+
+struct Exporter<W: Write> {
+    out: W,
+    /// Marks of the blobs and commits written so far, by hash
+    marks: HashMap<String, u64>,
+    next_mark: u64,
+}
+
+impl<W: Write> Exporter<W> {
+    /// Exports the commits reachable from `hash` that weren't exported yet
+    /// on `name`, then points the ref at its tip
+    fn export_ref(&mut self, name: &str, hash: &str) -> Result<()> {
+        let (object_type, _) = read_object_header(hash, &OBJ_DIR)?;
+        let (commit, tag) = match object_type.as_str() {
+            OBJ_TYPE_COMMIT => (hash.to_string(), None),
+            OBJ_TYPE_TAG => {
+                let tag = Tag::load(hash, &OBJ_DIR)?;
+                if tag.object_type != OBJ_TYPE_COMMIT {
+                    bail!("Tag {} doesn't point to a commit", name);
+                }
+                (tag.object.clone(), Some(tag))
+            }
+            _ => bail!("{} doesn't point to a commit", name),
+        };
+
+        let commits = self.unexported_history(&commit)?;
+        for hash in &commits {
+            self.export_commit(name, hash)?;
+        }
+
+        match &tag {
+            Some(tag) => self.export_tag(tag)?,
+            // The tip was exported with another ref
+            None if commits.last() != Some(&commit) => {
+                writeln!(self.out, "reset {}", name)?;
+                writeln!(self.out, "from :{}\n", self.marks[&commit])?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn export_commit(&mut self, name: &str, hash: &str) -> Result<()> {
+        // A commit at the shallow boundary is exported as a root commit
+        let commit = load_commit(hash, &OBJ_DIR)?;
+        let new_files = flatten(Some(&commit.tree))?;
+        let parent = match &commit.parent {
+            Some(parent) => Some(Commit::load(parent, &OBJ_DIR)?.tree),
+            None => None,
+        };
+        let old_files = flatten(parent.as_ref())?;
+
+        let mut changes = Vec::new();
+        for path in old_files.keys().filter(|path| !new_files.contains_key(*path)) {
+            changes.push(format!("D {}", quote_path(path)));
+        }
+        for (path, (mode, hash)) in &new_files {
+            if old_files.get(path) == Some(&(mode.clone(), hash.clone())) {
+                continue;
+            }
+            // Submodule commits are referenced by hash, they aren't in the stream
+            let data_ref = match mode.as_str() {
+                "160000" => hash.clone(),
+                _ => format!(":{}", self.export_blob(hash)?),
+            };
+            changes.push(format!("M {} {} {}", mode, data_ref, quote_path(path)));
+        }
+
+        let mark = self.next_mark;
+        self.next_mark += 1;
+        writeln!(self.out, "commit {}", name)?;
+        writeln!(self.out, "mark :{}", mark)?;
+        writeln!(self.out, "author {} {}", commit.author, raw_date(&commit.timestamp))?;
+        writeln!(
+            self.out,
+            "committer {} {}",
+            commit.committer,
+            raw_date(&commit.committer_timestamp)
+        )?;
+        self.write_data(format!("{}\n", commit.message).as_bytes())?;
+        if let Some(parent) = &commit.parent {
+            match self.marks.get(parent) {
+                Some(parent_mark) => writeln!(self.out, "from :{}", parent_mark)?,
+                None => writeln!(self.out, "from {}", parent)?,
+            }
+        }
+        for change in changes {
+            writeln!(self.out, "{}", change)?;
+        }
+        writeln!(self.out)?;
+
+        self.marks.insert(hash.to_string(), mark);
+        Ok(())
+    }
+
+    /// Lists the commits leading to `tip` that have no mark yet, parents first
+    fn unexported_history(&self, tip: &str) -> Result<Vec<String>> {
+        let mut history = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(tip.to_string(), false)];
+        while let Some((hash, parents_done)) = stack.pop() {
+            if parents_done {
+                history.push(hash);
+                continue;
+            }
+            if self.marks.contains_key(&hash) || !visited.insert(hash.clone()) {
+                continue;
+            }
+            let parents = load_commit(&hash, &OBJ_DIR)?.parents();
+            stack.push((hash, true));
+            stack.extend(parents.into_iter().rev().map(|parent| (parent, false)));
+        }
+        Ok(history)
+    }
+
+    /// Writes a blob unless it was already written, returning its mark
+    fn export_blob(&mut self, hash: &str) -> Result<u64> {
+        if let Some(&mark) = self.marks.get(hash) {
+            return Ok(mark);
+        }
+        let (_, content) = read_raw_object(hash, &OBJ_DIR)?;
+        let mark = self.next_mark;
+        self.next_mark += 1;
+        writeln!(self.out, "blob")?;
+        writeln!(self.out, "mark :{}", mark)?;
+        self.write_data(&content)?;
+        writeln!(self.out)?;
+
+        self.marks.insert(hash.to_string(), mark);
+        Ok(mark)
+    }
+
+    fn export_tag(&mut self, tag: &Tag) -> Result<()> {
+        let (tagger, email, date) = &tag.tagger;
+        writeln!(self.out, "tag {}", tag.tag)?;
+        writeln!(self.out, "from :{}", self.marks[&tag.object])?;
+        writeln!(self.out, "tagger {} <{}> {}", tagger, email, raw_date(date))?;
+        // Like git, signatures are dropped as they wouldn't match the new tag
+        self.write_data(format!("{}\n", tag.message).as_bytes())?;
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        writeln!(self.out, "data {}", data.len())?;
+        self.out.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// Lists the files of a tree as `path -> (mode, hash)`
+fn flatten(tree: Option<&String>) -> Result<BTreeMap<String, (String, String)>> {
+    let Some(tree) = tree else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(read_tree(tree, &OBJ_DIR)?
+        .flatten(&OBJ_DIR)?
+        .entries
+        .into_iter()
+        .map(|entry| (entry.name, (entry.mode, entry.object_hash)))
+        .collect())
+}
+
+/// Formats a date the way the `raw` date format of fast-import expects
+fn raw_date(date: &DateTime<Utc>) -> String {
+    format!("{} +0000", date.timestamp())
+}
+
+/// Quotes a path C-style when it couldn't be read back as is
+pub fn quote_path(path: &str) -> String {
+    if !path.starts_with('"') && !path.contains('\n') {
+        return path.to_string();
+    }
+    let escaped = path
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
@@ -0,0 +1,567 @@
+use crate::commands::index::index::{Index, IndexEntry};
+use crate::commands::tag::tag::split_identity;
+use crate::error::VoxError;
+use crate::storage::hash::ObjectId;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::commit::{parse_person, Commit};
+use crate::storage::objects::graph::{is_ancestor, load_commit};
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::Storable;
+use crate::storage::platform::{MODE_EXEC, MODE_FILE, MODE_GITLINK, MODE_SYMLINK};
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::list_refs;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_COMMIT, VOX_DIR};
+use anyhow::{anyhow, bail, Context, Result};
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+
+/// Options of the `fast-import` command
+#[derive(Debug, Default)]
+pub struct FastImportOptions {
+    /// Marks file of a previous import, so the stream can refer to its objects
+    pub import_marks: Option<PathBuf>,
+    /// File to save the marks to after the import
+    pub export_marks: Option<PathBuf>,
+    /// Update refs even when the old value isn't an ancestor of the new one
+    pub force: bool,
+}
+
+/// Reads a git fast-import stream from stdin and stores its blobs, commits
+/// and tags, then updates the refs it wrote
+///
+/// Streams from `git fast-export` and `vox fast-export` are both accepted,
+/// with raw dates. Merge commits keep their first parent only, as vox
+/// commits have a single parent.
+///
+pub fn fast_import_command(options: &FastImportOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let mut importer = Importer {
+        input: StreamReader::new(io::stdin().lock()),
+        marks: HashMap::new(),
+        refs: BTreeMap::new(),
+        existing: list_refs(&VOX_DIR)?.into_iter().collect(),
+        counts: [0; 3],
+    };
+    if let Some(path) = &options.import_marks {
+        importer.marks.extend(read_marks(path)?);
+    }
+    importer.run()?;
+
+    if let Some(path) = &options.export_marks {
+        write_marks(path, importer.marks.iter().map(|(&mark, hash)| (mark, hash.as_str())))?;
+    }
+
+    let [blobs, commits, tags] = importer.counts;
+    println!("Imported {} blobs, {} commits and {} tags", blobs, commits, tags);
+    importer.update_refs(options.force)
+}
+
+/// Reads a marks file, one `:<mark> <hash>` line per object
+pub fn read_marks(path: &Path) -> Result<Vec<(u64, String)>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read marks file {}", path.display()))?;
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (mark, hash) = line
+                .strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .with_context(|| format!("Invalid marks line: {}", line))?;
+            Ok((mark.parse()?, hash.to_string()))
+        })
+        .collect()
+}
+
+/// Writes a marks file, sorted by mark
+pub fn write_marks<'a, I>(path: &Path, marks: I) -> Result<()>
+where
+    I: IntoIterator<Item = (u64, &'a str)>,
+{
+    let mut marks: Vec<_> = marks.into_iter().collect();
+    marks.sort();
+    let content: String = marks
+        .into_iter()
+        .map(|(mark, hash)| format!(":{} {}\n", mark, hash))
+        .collect();
+    fs::write(path, content)
+        .with_context(|| format!("Failed to write marks file {}", path.display()))
+}
+
+struct Importer<R: BufRead> {
+    input: StreamReader<R>,
+    /// Hashes of the objects marked in the stream
+    marks: HashMap<u64, String>,
+    /// New values of the refs written by the stream, `None` after a `reset`
+    /// without a commit
+    refs: BTreeMap<String, Option<String>>,
+    /// Refs of the repository before the import
+    existing: HashMap<String, String>,
+    /// Blobs, commits and tags imported
+    counts: [usize; 3],
+}
+
+impl<R: BufRead> Importer<R> {
+    fn run(&mut self) -> Result<()> {
+        while let Some(line) = self.input.next_line()? {
+            let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+            match command {
+                "" | "checkpoint" => {}
+                _ if command.starts_with('#') => {}
+                "blob" => self.import_blob()?,
+                "commit" => self.import_commit(argument)?,
+                "tag" => self.import_tag(argument)?,
+                "reset" => {
+                    let from = match self.input.optional("from ")? {
+                        Some(from) => Some(self.resolve_commit(&from)?),
+                        None => None,
+                    };
+                    self.refs.insert(argument.to_string(), from);
+                }
+                "progress" => println!("{}", argument),
+                "feature" => match argument {
+                    "done" | "date-format=raw" | "date-format=raw-permissive" => {}
+                    _ => bail!("Unsupported feature: {}", argument),
+                },
+                // Options are hints for a particular importer
+                "option" => {}
+                "done" => break,
+                _ => bail!("Unsupported command in stream: {}", line),
+            }
+        }
+        Ok(())
+    }
+
+    fn import_blob(&mut self) -> Result<()> {
+        let mark = self.input.mark()?;
+        self.input.optional("original-oid ")?;
+        let data = self.input.data()?;
+        let hash = Blob { data }.save(&OBJ_DIR)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash);
+        }
+        self.counts[0] += 1;
+        Ok(())
+    }
+
+    fn import_commit(&mut self, ref_name: &str) -> Result<()> {
+        let mark = self.input.mark()?;
+        self.input.optional("original-oid ")?;
+        let author = self.input.optional("author ")?;
+        let committer = self
+            .input
+            .optional("committer ")?
+            .with_context(|| format!("Commit on {} has no committer", ref_name))?;
+        self.input.optional("encoding ")?;
+        let mut message = String::from_utf8(self.input.data()?)
+            .with_context(|| format!("Commit message on {} isn't valid UTF-8", ref_name))?;
+        if message.ends_with('\n') {
+            message.pop();
+        }
+
+        let parent = match self.input.optional("from ")? {
+            Some(from) => Some(self.resolve_commit(&from)?),
+            None => match self.refs.get(ref_name) {
+                Some(value) => value.clone(),
+                None => self.existing.get(ref_name).cloned(),
+            },
+        };
+        while let Some(merge) = self.input.optional("merge ")? {
+            eprintln!(
+                "{} dropping merge parent {} of a commit on {}, vox commits have one parent",
+                "warning:".yellow(),
+                merge,
+                ref_name
+            );
+        }
+
+        let mut index = Index::new();
+        if let Some(parent) = &parent {
+            let tree = load_commit(parent, &OBJ_DIR)?.tree;
+            for entry in read_tree(&tree, &OBJ_DIR)?.flatten(&OBJ_DIR)?.entries {
+                let mode = u32::from_str_radix(&entry.mode, 8)?;
+                index.add_entry(index_entry(&entry.name, mode, &entry.object_hash)?);
+            }
+        }
+        while let Some(line) = self.input.peek_line()? {
+            let command = line.split_once(' ').map_or(line, |(command, _)| command);
+            if !["M", "D", "C", "R", "deleteall"].contains(&command) {
+                break;
+            }
+            let line = self.input.next_line()?.unwrap_or_default();
+            self.apply_file_command(&mut index, &line)?;
+        }
+
+        let (committer, committer_timestamp) = parse_person(&committer)?;
+        let (author, timestamp) = match author {
+            Some(author) => parse_person(&author)?,
+            None => (committer.clone(), committer_timestamp),
+        };
+        let commit = Commit {
+            tree: index.write_tree(&OBJ_DIR)?,
+            parent,
+            author,
+            timestamp,
+            committer,
+            committer_timestamp,
+            signature: None,
+            message,
+        };
+        let hash = commit.save(&OBJ_DIR)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        self.refs.insert(ref_name.to_string(), Some(hash));
+        self.counts[1] += 1;
+        Ok(())
+    }
+
+    fn apply_file_command(&mut self, index: &mut Index, line: &str) -> Result<()> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "M" => {
+                let mut parts = rest.splitn(3, ' ');
+                let (Some(mode), Some(data_ref), Some(path)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    bail!("Invalid filemodify command: {}", line);
+                };
+                let mode = match mode {
+                    "644" | "100644" => MODE_FILE,
+                    "755" | "100755" => MODE_EXEC,
+                    "120000" => MODE_SYMLINK,
+                    "160000" => MODE_GITLINK,
+                    _ => bail!("Unsupported file mode {} in: {}", mode, line),
+                };
+                let hash = match data_ref {
+                    "inline" => {
+                        self.counts[0] += 1;
+                        Blob { data: self.input.data()? }.save(&OBJ_DIR)?
+                    }
+                    _ => self.resolve_data_ref(data_ref)?,
+                };
+                let (path, _) = parse_path(path, false)?;
+                index.add_entry(index_entry(&path, mode, &hash)?);
+            }
+            "D" => {
+                let (path, _) = parse_path(rest, false)?;
+                for path in matching_paths(index, &path) {
+                    index.remove_entry(&path);
+                }
+            }
+            "C" | "R" => {
+                let (source, rest) = parse_path(rest, true)?;
+                let (target, _) = parse_path(rest, false)?;
+                let sources = matching_paths(index, &source);
+                if sources.is_empty() {
+                    bail!("Path {} not found in: {}", source, line);
+                }
+                for path in sources {
+                    let entry = match command {
+                        "R" => index.remove_entry(&path),
+                        _ => index.get_entry(&path).cloned(),
+                    }
+                    .with_context(|| format!("Path {} not found", path.display()))?;
+                    let relative = path.strip_prefix(&source)?;
+                    let mut entry = entry;
+                    entry.path = Path::new(&target).join(relative);
+                    index.add_entry(entry);
+                }
+            }
+            _ => index.entries.clear(),
+        }
+        Ok(())
+    }
+
+    fn import_tag(&mut self, name: &str) -> Result<()> {
+        let mark = self.input.mark()?;
+        let from = self
+            .input
+            .optional("from ")?
+            .with_context(|| format!("Tag {} has no from command", name))?;
+        self.input.optional("original-oid ")?;
+        let tagger = self
+            .input
+            .optional("tagger ")?
+            .with_context(|| format!("Tag {} has no tagger", name))?;
+        let message = String::from_utf8(self.input.data()?)
+            .with_context(|| format!("Message of tag {} isn't valid UTF-8", name))?;
+
+        let (identity, date) = parse_person(&tagger)?;
+        let (tagger_name, tagger_email) = split_identity(&identity);
+        let tag = Tag {
+            object: self.resolve_commit(&from)?,
+            object_type: OBJ_TYPE_COMMIT.to_string(),
+            tag: name.to_string(),
+            tagger: (tagger_name, tagger_email, date),
+            message: message.trim().to_string(),
+            signature: None,
+        };
+        let hash = tag.save(&OBJ_DIR)?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, hash.clone());
+        }
+        self.refs.insert(format!("refs/tags/{}", name), Some(hash));
+        self.counts[2] += 1;
+        Ok(())
+    }
+
+    /// Resolves the commit a `from` or `reset` refers to: a mark, a ref
+    /// written earlier in the stream or a revision of the repository
+    fn resolve_commit(&self, spec: &str) -> Result<String> {
+        if spec.starts_with(':') {
+            return self.resolve_data_ref(spec);
+        }
+        if let Some(Some(hash)) = self.refs.get(spec) {
+            return Ok(hash.clone());
+        }
+        resolve_commit(&VOX_DIR, spec)
+    }
+
+    fn resolve_data_ref(&self, data_ref: &str) -> Result<String> {
+        match data_ref.strip_prefix(':') {
+            Some(mark) => self
+                .marks
+                .get(&mark.parse()?)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown mark {}", data_ref)),
+            None => Ok(data_ref.to_string()),
+        }
+    }
+
+    /// Points the refs the stream wrote at their new commits
+    ///
+    /// Refs that would lose commits are left alone and reported unless
+    /// `force` is set
+    fn update_refs(&self, force: bool) -> Result<()> {
+        let mut transaction = RefTransaction::new(&VOX_DIR);
+        let mut refused = Vec::new();
+        for (name, hash) in &self.refs {
+            let Some(hash) = hash else {
+                continue;
+            };
+            let expected = match self.existing.get(name) {
+                Some(old) if old == hash => continue,
+                Some(old) => {
+                    let fast_forward = !name.starts_with("refs/tags/")
+                        && is_ancestor(old, hash, &OBJ_DIR).unwrap_or(false);
+                    if !force && !fast_forward {
+                        refused.push(name.as_str());
+                        continue;
+                    }
+                    ExpectedValue::Hash(old.clone())
+                }
+                None => ExpectedValue::Missing,
+            };
+            transaction.update(name, hash, expected, "fast-import");
+        }
+        transaction.commit()?;
+
+        if !refused.is_empty() {
+            bail!(
+                "Not updating {} as the new values aren't fast-forwards (use --force)",
+                refused.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Builds an index entry for a file that only exists as an object
+fn index_entry(path: &str, mode: u32, hash: &str) -> Result<IndexEntry> {
+    Ok(IndexEntry {
+        mtime: 0,
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        hash: ObjectId::from_hex(hash)?,
+        flags: 0,
+        extended_flags: 0,
+        path: PathBuf::from(path),
+    })
+}
+
+/// Lists the indexed paths that are `path` itself or are below it
+fn matching_paths(index: &Index, path: &str) -> Vec<PathBuf> {
+    index
+        .entries
+        .keys()
+        .filter(|entry| entry.starts_with(path))
+        .cloned()
+        .collect()
+}
+
+/// Parses a path of a file command, C-style quoted or not
+///
+/// Unquoted paths run to the end of the line, or to the first space when
+/// `followed` is set because another path comes after it
+///
+/// # Returns
+///
+/// The path and what follows it on the line
+fn parse_path(s: &str, followed: bool) -> Result<(String, &str)> {
+    let Some(quoted) = s.strip_prefix('"') else {
+        return Ok(match followed {
+            true => s.split_once(' ').map_or((s.to_string(), ""), |(p, r)| (p.to_string(), r)),
+            false => (s.to_string(), ""),
+        });
+    };
+
+    let mut path = Vec::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let path = String::from_utf8(path).context("Path isn't valid UTF-8")?;
+                return Ok((path, quoted[i + 1..].trim_start_matches(' ')));
+            }
+            '\\' => {
+                let (_, escaped) = chars.next().context("Unterminated escape in path")?;
+                match escaped {
+                    'n' => path.push(b'\n'),
+                    't' => path.push(b'\t'),
+                    '0'..='7' => {
+                        let mut digits = escaped.to_string();
+                        for _ in 0..2 {
+                            digits.push(chars.next().context("Truncated octal escape")?.1);
+                        }
+                        path.push(u8::from_str_radix(&digits, 8)?);
+                    }
+                    c => path.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                }
+            }
+            c => path.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    bail!("Unterminated quoted path: {}", s)
+}
+
+/// Reads a fast-import stream line by line, with data blocks in between
+struct StreamReader<R: BufRead> {
+    reader: R,
+    peeked: Option<String>,
+}
+
+impl<R: BufRead> StreamReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            peeked: None,
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+        String::from_utf8(line).map(Some).context("Stream line isn't valid UTF-8")
+    }
+
+    fn peek_line(&mut self) -> Result<Option<&str>> {
+        if self.peeked.is_none() {
+            self.peeked = self.next_line()?;
+        }
+        Ok(self.peeked.as_deref())
+    }
+
+    /// Consumes the next line if it starts with `prefix`, returning the rest
+    fn optional(&mut self, prefix: &str) -> Result<Option<String>> {
+        match self.peek_line()? {
+            Some(line) if line.starts_with(prefix) => {
+                let line = self.peeked.take().unwrap_or_default();
+                Ok(Some(line[prefix.len()..].to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn mark(&mut self) -> Result<Option<u64>> {
+        match self.optional("mark :")? {
+            Some(mark) => Ok(Some(mark.parse().context("Invalid mark")?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a `data` command, either `data <count>` followed by exactly that
+    /// many bytes or `data <<<delimiter>` followed by lines up to the delimiter
+    fn data(&mut self) -> Result<Vec<u8>> {
+        let line = self.next_line()?.context("Expected a data command")?;
+        let size = line
+            .strip_prefix("data ")
+            .with_context(|| format!("Expected a data command, got: {}", line))?;
+
+        let mut data = Vec::new();
+        if let Some(delimiter) = size.strip_prefix("<<") {
+            loop {
+                let line = self.next_line()?.context("Unterminated data block")?;
+                if line == delimiter {
+                    break;
+                }
+                data.extend_from_slice(line.as_bytes());
+                data.push(b'\n');
+            }
+            return Ok(data);
+        }
+
+        let size: u64 = size.parse().with_context(|| format!("Invalid data size: {}", size))?;
+        self.reader.by_ref().take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            bail!("Stream ended inside a data block");
+        }
+        // The data may be followed by a line feed
+        if self.reader.fill_buf()?.first() == Some(&b'\n') {
+            self.reader.consume(1);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::fast_export::fast_export::quote_path;
+
+    #[test]
+    fn test_stream_reader() -> Result<()> {
+        let stream = b"blob\nmark :1\ndata 6\nab\ncd\n\ndata <<EOF\nline\nEOF\ndone\n";
+        let mut input = StreamReader::new(&stream[..]);
+        assert_eq!(input.next_line()?.as_deref(), Some("blob"));
+        assert_eq!(input.optional("original-oid ")?, None);
+        assert_eq!(input.mark()?, Some(1));
+        // The line feed after the data is optional and skipped
+        assert_eq!(input.data()?, b"ab\ncd\n");
+        assert_eq!(input.data()?, b"line\n");
+        assert_eq!(input.next_line()?.as_deref(), Some("done"));
+        assert_eq!(input.next_line()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_path() -> Result<()> {
+        assert_eq!(parse_path("dir/a file", false)?, ("dir/a file".to_string(), ""));
+        assert_eq!(parse_path("old new name", true)?, ("old".to_string(), "new name"));
+        assert_eq!(parse_path(r#""a \"b\"\303\251" c"#, true)?, ("a \"b\"é".to_string(), "c"));
+
+        let odd = "\"quoted\nname\\";
+        assert_eq!(parse_path(&quote_path(odd), false)?.0, odd);
+        Ok(())
+    }
+}
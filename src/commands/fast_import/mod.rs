@@ -0,0 +1 @@
+pub mod fast_import;
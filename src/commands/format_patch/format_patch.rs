@@ -0,0 +1,143 @@
+use crate::commands::rev_list::rev_list::parse_revisions;
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::{list_commits, load_commit};
+use crate::storage::patch::commit_diff;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Longest subject slug used in patch file names
+const MAX_SLUG_LEN: usize = 52;
+
+/// Options of the `format-patch` command
+#[derive(Debug, Default)]
+pub struct FormatPatchOptions {
+    /// Directory the patch files are written to (defaults to the current one)
+    pub output_directory: Option<PathBuf>,
+    /// Print the patches as a single mailbox instead of writing files
+    pub stdout: bool,
+    /// Include the content of changed binary files, so they can be applied
+    pub binary: bool,
+}
+
+/// Exports commits as mbox-style patches, one file per commit, named after
+/// their position in the series and their subject
+///
+/// # Arguments
+/// - `revisions`: the commits to export, as for `rev-list`. A single revision
+///   without `..` or `^` stands for the commits since it, `<revision>..HEAD`
+/// - `options`: where the patches go
+///
+pub fn format_patch_command(revisions: &[String], options: &FormatPatchOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let revisions = match revisions {
+        [since] if !since.contains("..") && !since.starts_with('^') => {
+            vec![format!("{}..HEAD", since)]
+        }
+        _ => revisions.to_vec(),
+    };
+    let (include, exclude) = parse_revisions(&revisions)?;
+    let mut hashes = list_commits(&include, &exclude, &OBJ_DIR)?;
+    // A series is applied from the oldest commit to the newest
+    hashes.reverse();
+
+    let output_directory = options.output_directory.as_deref().unwrap_or(Path::new(""));
+    if !options.stdout && !output_directory.as_os_str().is_empty() {
+        fs::create_dir_all(output_directory).with_context(|| {
+            format!("Failed to create directory {}", output_directory.display())
+        })?;
+    }
+
+    let total = hashes.len();
+    for (number, hash) in hashes.iter().enumerate() {
+        let commit = load_commit(hash, &OBJ_DIR)?;
+        let patch = format_patch(hash, &commit, number + 1, total, options.binary)?;
+        if options.stdout {
+            print!("{}", patch);
+            continue;
+        }
+
+        let path = output_directory.join(patch_file_name(number + 1, &commit.message));
+        fs::write(&path, patch).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// Renders a commit as a mail: a `From` line with the commit hash, the author,
+/// date and subject as headers, the rest of the message as the body, then the
+/// diff against its parent
+///
+/// The subject is prefixed with `[PATCH n/m]` when the series has more than
+/// one patch. With `binary`, changed binary files are included in full.
+pub fn format_patch(
+    hash: &str,
+    commit: &Commit,
+    number: usize,
+    total: usize,
+    binary: bool,
+) -> Result<String> {
+    let (subject, body) = split_message(&commit.message);
+    let prefix = match total {
+        1 => "[PATCH]".to_string(),
+        _ => format!("[PATCH {}/{}]", number, total),
+    };
+
+    let mut patch = format!("From {} Mon Sep 17 00:00:00 2001\n", hash);
+    patch.push_str(&format!("From: {}\n", commit.author));
+    patch.push_str(&format!("Date: {}\n", commit.timestamp.to_rfc2822()));
+    patch.push_str(&format!("Subject: {} {}\n\n", prefix, subject));
+    if !body.is_empty() {
+        patch.push_str(&format!("{}\n", body));
+    }
+    patch.push_str("---\n\n");
+    patch.push_str(&commit_diff(commit, &OBJ_DIR, binary)?);
+    patch.push_str(&format!("-- \nvox {}\n\n", env!("CARGO_PKG_VERSION")));
+    Ok(patch)
+}
+
+/// Splits a commit message into its subject (the first paragraph, joined into
+/// one line) and its body
+pub fn split_message(message: &str) -> (String, String) {
+    let message = message.trim();
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = subject.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    (subject, body.trim().to_string())
+}
+
+/// Returns the file name of a patch, e.g. `0001-fix-the-parser.patch`
+fn patch_file_name(number: usize, message: &str) -> String {
+    let (subject, _) = split_message(message);
+    let mut slug = String::new();
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() >= MAX_SLUG_LEN {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches(['-', '.']);
+    format!("{:04}-{}.patch", number, slug)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_file_name() {
+        assert_eq!(patch_file_name(1, "Fix the parser!\n\nDetails"), "0001-Fix-the-parser.patch");
+        assert_eq!(patch_file_name(12, "  [core] v2.0: done..."), "0012-core-v2.0-done.patch");
+
+        let (subject, body) = split_message("Wrapped\nsubject\n\nBody line\n");
+        assert_eq!((subject.as_str(), body.as_str()), ("Wrapped subject", "Body line"));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::error::VoxError;
+use crate::storage::objects::{verify_loose_object, ObjectStorage};
+use crate::storage::quarantine::{affected_refs, quarantine, recover};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use colored::*;
+use rayon::prelude::*;
+
+/// Re-hashes every loose object of the repository
+///
+/// Corrupt objects are moved to `.vox/lost-found` and recovered from the
+/// alternates, the packs or the remotes when one of them has a good copy.
+/// Those that can't be recovered are listed with the refs reaching them.
+pub fn fsck_command() -> Result<()> {
+    if !VOX_DIR.exists() {
+        bail!(VoxError::NotARepository);
+    }
+
+    let objects = ObjectStorage::at(&OBJ_DIR).loose_objects()?;
+    let corrupt: Vec<(String, String)> = objects
+        .par_iter()
+        .filter_map(|object| {
+            let error = verify_loose_object(&object.hash, &OBJ_DIR).err()?;
+            Some((object.hash.clone(), format!("{:#}", error)))
+        })
+        .collect();
+
+    let mut lost = 0;
+    for (hash, problem) in &corrupt {
+        let moved = quarantine(hash, &OBJ_DIR)?;
+        if let Some(source) = recover(hash, &OBJ_DIR)? {
+            println!("{} {} from {}", "recovered".green(), hash, source);
+            continue;
+        }
+
+        lost += 1;
+        println!("{} {}", "error:".red(), problem);
+        if let Some(moved) = moved {
+            println!("  moved to {}", moved.display());
+        }
+        let refs = affected_refs(hash, &VOX_DIR, &OBJ_DIR)?;
+        if !refs.is_empty() {
+            println!("  reached from {}", refs.join(", ").yellow());
+        }
+    }
+
+    println!(
+        "Checked {} objects, {} corrupt, {} recovered",
+        objects.len(),
+        corrupt.len(),
+        corrupt.len() - lost
+    );
+    if lost > 0 {
+        bail!("{} corrupt objects couldn't be recovered", lost);
+    }
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use crate::commands::index::extensions::IndexExtension;
+use crate::commands::index::index::IndexEntry;
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::tree::{Tree, TreeEntry};
+use crate::storage::objects::Storable;
+use crate::storage::platform::{MODE_EXEC, MODE_GITLINK, MODE_SYMLINK};
+use crate::storage::utils::{
+    OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TREE, PERM_DIR, PERM_EXEC, PERM_FILE, PERM_GITLINK,
+    PERM_SYMLINK,
+};
+use anyhow::{anyhow, Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+/// Tree hashes of the directories in the index
+///
+/// Every directory remembers the hash of the tree built for it and how many
+/// index entries it contained. Changing an entry invalidates the directories
+/// above it, so writing a tree only hashes the directories that changed and
+/// reuses the stored hashes for everything else.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CacheTree {
+    /// Hash of the tree for this directory, `None` if it has to be rebuilt
+    pub hash: Option<String>,
+    /// Number of index entries below this directory
+    pub entry_count: usize,
+    /// Subdirectories by name
+    pub children: BTreeMap<String, CacheTree>,
+}
+
+impl CacheTree {
+    /// Marks every directory containing `path` as changed
+    pub fn invalidate(&mut self, path: &Path) {
+        let components = path_components(path);
+        let mut node = self;
+        node.hash = None;
+        node.entry_count = 0;
+
+        // The last component is the file itself
+        for name in components.iter().take(components.len().saturating_sub(1)) {
+            match node.children.get_mut(name) {
+                Some(child) => {
+                    child.hash = None;
+                    child.entry_count = 0;
+                    node = child;
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Builds the tree objects for `entries`, reusing every valid cached hash
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The index entries
+    /// * `objects_dir` - Path to the objects directory the trees are stored in
+    ///
+    /// # Returns
+    ///
+    /// The hash of the root tree
+    pub fn write_tree(
+        &mut self,
+        entries: &HashMap<PathBuf, IndexEntry>,
+        objects_dir: &Path,
+    ) -> Result<String> {
+        let mut paths: Vec<(Vec<String>, &IndexEntry)> = entries
+            .values()
+            .map(|entry| (path_components(&entry.path), entry))
+            .collect();
+        paths.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.build(&paths, 0, objects_dir)
+    }
+
+    fn build(
+        &mut self,
+        entries: &[(Vec<String>, &IndexEntry)],
+        depth: usize,
+        objects_dir: &Path,
+    ) -> Result<String> {
+        if let Some(hash) = &self.hash
+            && self.entry_count == entries.len()
+        {
+            return Ok(hash.clone());
+        }
+
+        let mut tree = Tree {
+            entries: Vec::new(),
+        };
+        let mut subdirs = Vec::new();
+
+        let mut i = 0;
+        while i < entries.len() {
+            let (components, entry) = &entries[i];
+            let name = &components[depth];
+
+            if components.len() == depth + 1 {
+                let (object_type, mode) = match entry.stat().tree_mode() {
+                    MODE_GITLINK => (OBJ_TYPE_COMMIT, PERM_GITLINK),
+                    MODE_SYMLINK => (OBJ_TYPE_BLOB, PERM_SYMLINK),
+                    MODE_EXEC => (OBJ_TYPE_BLOB, PERM_EXEC),
+                    _ => (OBJ_TYPE_BLOB, PERM_FILE),
+                };
+                tree.entries.push(TreeEntry {
+                    object_type: object_type.to_string(),
+                    mode: mode.to_string(),
+                    object_hash: entry.hash.to_hex(),
+                    name: name.clone(),
+                });
+                i += 1;
+                continue;
+            }
+
+            // Entries are sorted, so a directory's entries are contiguous
+            let end = i + entries[i..]
+                .iter()
+                .take_while(|(c, _)| c.len() > depth + 1 && &c[depth] == name)
+                .count();
+
+            let child = self.children.entry(name.clone()).or_default();
+            let object_hash = child.build(&entries[i..end], depth + 1, objects_dir)?;
+            tree.entries.push(TreeEntry {
+                object_type: OBJ_TYPE_TREE.to_string(),
+                mode: PERM_DIR.to_string(),
+                object_hash,
+                name: name.clone(),
+            });
+            subdirs.push(name.clone());
+            i = end;
+        }
+
+        // Forget directories that no longer have entries
+        self.children.retain(|name, _| subdirs.contains(name));
+
+        tree.entries.sort_by(|a, b| a.name.cmp(&b.name));
+        let hash = tree.save(objects_dir)?;
+
+        self.hash = Some(hash.clone());
+        self.entry_count = entries.len();
+        Ok(hash)
+    }
+
+    fn serialize_node(&self, name: &str, data: &mut Vec<u8>) -> Result<()> {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+
+        match &self.hash {
+            Some(hash) => {
+                data.extend(format!("{} {}\n", self.entry_count, self.children.len()).as_bytes());
+                data.extend_from_slice(ObjectId::from_hex(hash)?.as_bytes());
+            }
+            None => data.extend(format!("-1 {}\n", self.children.len()).as_bytes()),
+        }
+
+        for (child_name, child) in &self.children {
+            child.serialize_node(child_name, data)?;
+        }
+        Ok(())
+    }
+
+    fn parse_node(data: &[u8], pos: &mut usize, format: ObjectFormat) -> Result<(String, Self)> {
+        let name = read_until(data, pos, 0)?;
+        let counts = read_until(data, pos, b'\n')?;
+
+        let (entry_count, subtree_count) = counts
+            .split_once(' ')
+            .context("Malformed cached tree entry")?;
+        let entry_count: i64 = entry_count.parse().context("Malformed cached tree entry")?;
+        let subtree_count: usize = subtree_count
+            .parse()
+            .context("Malformed cached tree entry")?;
+
+        let mut node = CacheTree::default();
+        if entry_count >= 0 {
+            let raw = data
+                .get(*pos..*pos + format.raw_len())
+                .context("Truncated cached tree entry")?;
+            node.hash = Some(ObjectId::from_bytes(format, raw)?.to_hex());
+            node.entry_count = entry_count as usize;
+            *pos += format.raw_len();
+        }
+
+        for _ in 0..subtree_count {
+            let (child_name, child) = Self::parse_node(data, pos, format)?;
+            node.children.insert(child_name, child);
+        }
+
+        Ok((name, node))
+    }
+}
+
+impl IndexExtension for CacheTree {
+    const SIGNATURE: &'static [u8; 4] = b"TREE";
+
+    /// Returns true if there's nothing cached
+    fn is_empty(&self) -> bool {
+        self.hash.is_none() && self.children.is_empty()
+    }
+
+    /// Serializes the cache into the payload of the `TREE` extension
+    ///
+    /// Directories are written depth-first as
+    /// `<name>\0<entry count> <subtree count>\n<raw hash>`, where an entry
+    /// count of -1 marks an invalid directory that has no hash
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.serialize_node("", &mut data)?;
+        Ok(data)
+    }
+
+    /// Parses the payload of the `TREE` extension
+    fn parse(data: &[u8], format: ObjectFormat) -> Result<Self> {
+        let mut pos = 0;
+        let (_, root) = Self::parse_node(data, &mut pos, format)?;
+        Ok(root)
+    }
+}
+
+fn read_until(data: &[u8], pos: &mut usize, delimiter: u8) -> Result<String> {
+    let rest = data.get(*pos..).unwrap_or_default();
+    let len = rest
+        .iter()
+        .position(|&b| b == delimiter)
+        .ok_or_else(|| anyhow!("Truncated cached tree entry"))?;
+
+    let value = String::from_utf8(rest[..len].to_vec()).context("Invalid cached tree entry")?;
+    *pos += len + 1;
+    Ok(value)
+}
+
+fn path_components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(path: &str, byte: u8) -> (PathBuf, IndexEntry) {
+        let entry = IndexEntry {
+            mtime: 0,
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: ObjectId::Sha1([byte; 20]),
+            flags: 0,
+            extended_flags: 0,
+            path: PathBuf::from(path),
+        };
+        (entry.path.clone(), entry)
+    }
+
+    #[test]
+    fn test_invalidation_rebuilds_only_changed_dirs() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        let mut entries: HashMap<_, _> = [
+            entry("README.md", 1),
+            entry("src/main.rs", 2),
+            entry("docs/guide.md", 3),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut cache = CacheTree::default();
+        let first_root = cache.write_tree(&entries, &objects_dir)?;
+        let docs_hash = cache.children["docs"].hash.clone();
+        assert!(docs_hash.is_some());
+
+        let (path, changed) = entry("src/main.rs", 4);
+        entries.insert(path.clone(), changed);
+        cache.invalidate(&path);
+        assert!(cache.hash.is_none());
+        assert!(cache.children["src"].hash.is_none());
+        assert_eq!(cache.children["docs"].hash, docs_hash);
+
+        let second_root = cache.write_tree(&entries, &objects_dir)?;
+        assert_ne!(first_root, second_root);
+
+        // A cache built from scratch must agree with the incremental one
+        assert_eq!(
+            CacheTree::default().write_tree(&entries, &objects_dir)?,
+            second_root
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let entries: HashMap<_, _> = [entry("a/b/c.txt", 1), entry("a/d.txt", 2)]
+            .into_iter()
+            .collect();
+
+        let mut cache = CacheTree::default();
+        cache.write_tree(&entries, &dir.path().join("objects"))?;
+        cache.invalidate(Path::new("a/d.txt"));
+
+        let parsed = CacheTree::parse(&cache.serialize()?, ObjectFormat::Sha1)?;
+        assert_eq!(parsed, cache);
+        Ok(())
+    }
+}
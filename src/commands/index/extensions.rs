@@ -0,0 +1,119 @@
+use crate::storage::hash::ObjectFormat;
+use anyhow::{anyhow, Context, Result};
+
+/// Optional data stored after the entries of the index file
+///
+/// Each extension is written as a 4-byte signature, the payload size as a
+/// big-endian u32 and the payload itself. Readers skip extensions they don't
+/// know if the signature starts with an uppercase letter; any other unknown
+/// extension is required and makes the index unreadable.
+pub trait IndexExtension: Sized {
+    /// Signature identifying the extension
+    const SIGNATURE: &'static [u8; 4];
+
+    /// Returns true if there's nothing worth writing
+    fn is_empty(&self) -> bool;
+
+    /// Serializes the extension payload
+    fn serialize(&self) -> Result<Vec<u8>>;
+
+    /// Parses an extension payload
+    fn parse(data: &[u8], format: ObjectFormat) -> Result<Self>;
+}
+
+/// An extension as found in the index file, kept as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawExtension {
+    pub signature: [u8; 4],
+    pub payload: Vec<u8>,
+}
+
+impl RawExtension {
+    /// Returns true if readers may ignore the extension
+    pub fn is_optional(&self) -> bool {
+        self.signature[0].is_ascii_uppercase()
+    }
+
+    /// Parses the extension as `E` if the signatures match
+    pub fn parse_as<E: IndexExtension>(&self, format: ObjectFormat) -> Result<Option<E>> {
+        if &self.signature != E::SIGNATURE {
+            return Ok(None);
+        }
+
+        E::parse(&self.payload, format)
+            .map(Some)
+            .with_context(|| {
+                format!(
+                    "Failed to parse index extension {}",
+                    String::from_utf8_lossy(E::SIGNATURE)
+                )
+            })
+    }
+}
+
+/// Appends `extension` to `data` unless it's empty
+pub fn write_extension<E: IndexExtension>(data: &mut Vec<u8>, extension: &E) -> Result<()> {
+    if extension.is_empty() {
+        return Ok(());
+    }
+
+    write_raw_extension(data, E::SIGNATURE, &extension.serialize()?);
+    Ok(())
+}
+
+/// Appends an extension with the given signature and payload to `data`
+pub fn write_raw_extension(data: &mut Vec<u8>, signature: &[u8; 4], payload: &[u8]) {
+    data.extend_from_slice(signature);
+    data.extend((payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(payload);
+}
+
+/// Splits the extension section of the index file into single extensions
+pub fn read_extensions(data: &[u8]) -> Result<Vec<RawExtension>> {
+    let mut extensions = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let header = data
+            .get(pos..pos + 8)
+            .ok_or_else(|| anyhow!("Truncated index extension header"))?;
+        let signature: [u8; 4] = header[..4].try_into()?;
+        let size = u32::from_be_bytes(header[4..].try_into()?) as usize;
+
+        let payload = data
+            .get(pos + 8..pos + 8 + size)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Truncated index extension {}",
+                    String::from_utf8_lossy(&signature)
+                )
+            })?
+            .to_vec();
+
+        extensions.push(RawExtension { signature, payload });
+        pos += 8 + size;
+    }
+
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_extensions() -> Result<()> {
+        let mut data = Vec::new();
+        write_raw_extension(&mut data, b"TREE", b"payload");
+        write_raw_extension(&mut data, b"link", b"");
+
+        let extensions = read_extensions(&data)?;
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions[0].payload, b"payload");
+        assert!(extensions[0].is_optional());
+        assert!(!extensions[1].is_optional());
+
+        assert!(read_extensions(&data[..data.len() - 3]).is_err());
+        Ok(())
+    }
+}
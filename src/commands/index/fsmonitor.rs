@@ -0,0 +1,90 @@
+use crate::commands::index::extensions::IndexExtension;
+use crate::storage::hash::ObjectFormat;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Entries known to match the working tree as of a `vox watch` token
+///
+/// `status` asks the watcher what changed since the token and only checks
+/// the files it names, along with any entry that isn't in the set. Staging
+/// or removing an entry drops it from the set, since the watcher wouldn't
+/// see that change.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FsMonitor {
+    /// Token of the watcher's answer the entries were checked against
+    pub token: String,
+    /// Entries that were unchanged at the time of the token
+    pub valid: BTreeSet<PathBuf>,
+}
+
+impl FsMonitor {
+    /// Returns true if `path` was unchanged as of the token
+    pub fn is_valid(&self, path: &Path) -> bool {
+        self.valid.contains(path)
+    }
+
+    /// Forgets that `path` was unchanged
+    pub fn invalidate(&mut self, path: &Path) {
+        self.valid.remove(path);
+    }
+}
+
+impl IndexExtension for FsMonitor {
+    const SIGNATURE: &'static [u8; 4] = b"FSMN";
+
+    /// Returns true if there's no token to query the watcher with
+    fn is_empty(&self) -> bool {
+        self.token.is_empty()
+    }
+
+    /// Serializes the `FSMN` extension payload: the token and the valid
+    /// entries, each followed by a NUL
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(self.token.as_bytes());
+        data.push(0);
+        for path in &self.valid {
+            data.extend_from_slice(path.to_str().context("Invalid entry path")?.as_bytes());
+            data.push(0);
+        }
+        Ok(data)
+    }
+
+    /// Parses the payload of the `FSMN` extension
+    fn parse(data: &[u8], _format: ObjectFormat) -> Result<Self> {
+        let mut names = data.split(|&byte| byte == 0);
+        let token = names.next().context("Missing fsmonitor token")?;
+        let mut monitor = FsMonitor {
+            token: String::from_utf8(token.to_vec()).context("Invalid fsmonitor token")?,
+            ..Default::default()
+        };
+        for name in names.filter(|name| !name.is_empty()) {
+            monitor
+                .valid
+                .insert(PathBuf::from(String::from_utf8_lossy(name).into_owned()));
+        }
+        Ok(monitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fsmonitor_roundtrip() -> Result<()> {
+        let mut monitor = FsMonitor {
+            token: "4242:17".to_string(),
+            valid: ["a.txt", "src/main.rs"].into_iter().map(PathBuf::from).collect(),
+        };
+        let parsed = FsMonitor::parse(&monitor.serialize()?, ObjectFormat::Sha1)?;
+        assert_eq!(parsed, monitor);
+        assert!(parsed.is_valid(Path::new("src/main.rs")));
+
+        monitor.invalidate(Path::new("src/main.rs"));
+        assert!(!monitor.is_valid(Path::new("src/main.rs")));
+        assert!(FsMonitor::default().is_empty());
+        Ok(())
+    }
+}
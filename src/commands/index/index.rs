@@ -1,15 +1,34 @@
+use crate::commands::index::cache_tree::CacheTree;
+use crate::commands::index::extensions::{
+    read_extensions, write_extension, write_raw_extension, RawExtension,
+};
+use crate::commands::index::fsmonitor::FsMonitor;
+use crate::commands::index::untracked_cache::UntrackedCache;
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::tree::read_tree;
+use crate::storage::platform::{to_index_path, FileStat, MODE_GITLINK, MODE_TYPE_MASK};
+use crate::storage::utils::PERM_GITLINK;
 use anyhow::{Context, Ok, Result};
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{Read, Write};
-use std::os::unix::fs::MetadataExt;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Signature for the index file format.
 const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
 
-/// Version of the index file format.
-const INDEX_VERSION: u32 = 2;
+/// Version of the index file format written by vox.
+///
+/// - 2: fixed size entries with full paths
+/// - 3: adds extended flags
+/// - 4: paths are prefix compressed against the previous entry
+const INDEX_VERSION: u32 = 4;
+
+/// Versions of the index file format vox can read.
+const SUPPORTED_VERSIONS: [u32; 3] = [2, 3, 4];
+
+/// Flag bit marking that extended flags follow (version 3 and later).
+const EXTENDED_FLAG: u16 = 0x4000;
 
 /// Represents an entry in the index file.
 /// Each entry corresponds to a file in the working directory and stores metadata about it.
@@ -22,15 +41,20 @@ pub struct IndexEntry {
     pub uid: u32,
     pub gid: u32,       // Group ID of the file owner
     pub size: u32,      // Size of the file in bytes
-    pub hash: [u8; 20], // SHA-1 hash of the file content
+    pub hash: ObjectId, // Hash of the file content
     pub flags: u16,
-    pub path: PathBuf, // Path to the file (relative to the repository root)
+    pub extended_flags: u16, // Extra flags stored since version 3
+    pub path: PathBuf,       // Path to the file (relative to the repository root)
 }
 
 /// Represents the index file, which tracks the state of files in the working directory.
 #[derive(Debug, Default)]
-pub(crate) struct Index {
+pub struct Index {
     pub entries: HashMap<PathBuf, IndexEntry>, // Map of file paths to their index entries
+    pub cache_tree: CacheTree,                 // Tree hashes of unchanged directories
+    pub untracked_cache: UntrackedCache,       // Working tree listings keyed by directory mtime
+    pub fsmonitor: FsMonitor,                  // Entries unchanged as of a `vox watch` token
+    pub unknown_extensions: Vec<RawExtension>, // Optional extensions kept for writing back
 }
 
 impl IndexEntry {
@@ -43,47 +67,117 @@ impl IndexEntry {
     /// A new `IndexEntry` with metadata populated from the file.
     ///
     pub fn new(path: &Path) -> Result<Self> {
-        let metadata = fs::metadata(path)?; // Read file metadata
+        let stat = FileStat::from_path(path)?; // Read file metadata
 
         Ok(IndexEntry {
-            mtime: metadata.mtime() as u64, // Last modification time
-            dev: metadata.dev() as u32,
-            ino: metadata.ino() as u32,
-            mode: metadata.mode(),
-            uid: metadata.uid(),
-            gid: metadata.gid(),
-            size: metadata.size() as u32,
-            hash: [0; 20],
+            mtime: stat.mtime, // Last modification time
+            dev: stat.dev,
+            ino: stat.ino,
+            mode: stat.mode,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            hash: ObjectFormat::current()?.null_id(),
             flags: 0,
+            extended_flags: 0,
             path: path.to_path_buf(),
         })
     }
+
+    /// Returns the metadata recorded for this entry
+    pub fn stat(&self) -> FileStat {
+        FileStat {
+            mtime: self.mtime,
+            dev: self.dev,
+            ino: self.ino,
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            size: self.size,
+        }
+    }
+
+    /// Returns true if the file may have changed after being staged without
+    /// its metadata showing it: mtimes have a granularity of a second, so a
+    /// file modified in the second the index was written at, and kept at the
+    /// same size, looks unchanged
+    pub fn is_racy(&self, index_mtime: u64) -> bool {
+        self.mode & MODE_TYPE_MASK != MODE_GITLINK && self.mtime >= index_mtime
+    }
 }
 
 impl Index {
     pub fn new() -> Self {
         Index {
             entries: HashMap::new(),
+            cache_tree: CacheTree::default(),
+            untracked_cache: UntrackedCache::default(),
+            fsmonitor: FsMonitor::default(),
+            unknown_extensions: Vec::new(),
         }
     }
 
-    pub fn add_entry(&mut self, entry: IndexEntry) {
+    /// Builds an index matching the tree `tree_hash`, as checked out in `work_tree`
+    ///
+    /// Metadata is read from the checked out files, so they aren't reported as
+    /// modified afterwards. Submodules that aren't populated get empty metadata.
+    ///
+    pub fn from_tree(tree_hash: &str, work_tree: &Path, objects_dir: &Path) -> Result<Self> {
+        let mut index = Index::new();
+        for entry in read_tree(tree_hash, objects_dir)?.flatten(objects_dir)?.entries {
+            let stat = FileStat::from_path(&work_tree.join(&entry.name)).unwrap_or_default();
+            let mode = match entry.mode.as_str() {
+                PERM_GITLINK => MODE_GITLINK,
+                _ if stat.mode != 0 => stat.mode,
+                mode => u32::from_str_radix(mode, 8)
+                    .with_context(|| format!("Invalid mode {} of {}", mode, entry.name))?,
+            };
+
+            index.add_entry(IndexEntry {
+                mtime: stat.mtime,
+                dev: stat.dev,
+                ino: stat.ino,
+                mode,
+                uid: stat.uid,
+                gid: stat.gid,
+                size: stat.size,
+                hash: ObjectId::from_hex(&entry.object_hash)?,
+                flags: 0,
+                extended_flags: 0,
+                path: PathBuf::from(entry.name),
+            });
+        }
+        Ok(index)
+    }
+
+    pub fn add_entry(&mut self, mut entry: IndexEntry) {
+        entry.path = to_index_path(&entry.path);
+        self.cache_tree.invalidate(&entry.path);
+        self.fsmonitor.invalidate(&entry.path);
         self.entries.insert(entry.path.clone(), entry);
     }
 
     pub fn remove_entry(&mut self, path: &Path) -> Option<IndexEntry> {
-        self.entries.remove(path)
+        let path = to_index_path(path);
+        self.cache_tree.invalidate(&path);
+        self.fsmonitor.invalidate(&path);
+        self.entries.remove(&path)
+    }
+
+    /// Stores the tree objects for the staged content and returns the root tree hash
+    ///
+    /// Directories whose entries didn't change since the last call reuse the
+    /// hash kept in the cached tree extension instead of being rebuilt.
+    ///
+    pub fn write_tree(&mut self, objects_dir: &Path) -> Result<String> {
+        self.cache_tree.write_tree(&self.entries, objects_dir)
     }
 
     /// Retrieves an entry from the index.
     ///
     pub fn get_entry(&self, path: &Path) -> Option<&IndexEntry> {
-        let normalized_path = if path.starts_with("./") {
-            path.strip_prefix("./").unwrap_or(path) // Normalize path by removing "./"
-        } else {
-            path
-        };
-        self.entries.get(normalized_path)
+        // Normalize path by removing "./" and using "/" separators
+        self.entries.get(&to_index_path(path))
     }
 
     /// Returns a reference to all entries in the index.
@@ -91,8 +185,29 @@ impl Index {
         &self.entries
     }
 
+    /// Records new metadata for a file whose content is still the staged one
+    pub fn refresh_entry(&mut self, path: &Path, stat: &FileStat) {
+        if let Some(entry) = self.entries.get_mut(&to_index_path(path)) {
+            entry.mtime = stat.mtime;
+            entry.dev = stat.dev;
+            entry.ino = stat.ino;
+            entry.mode = stat.mode;
+            entry.uid = stat.uid;
+            entry.gid = stat.gid;
+            entry.size = stat.size;
+        }
+    }
+
     /// Writes the index to a file.
     ///
+    /// The index is always written in the latest format version, followed by
+    /// the extensions and any unknown optional extensions read earlier.
+    ///
+    /// Entries modified in the current second are racily clean: a change
+    /// made later within the same second wouldn't alter their metadata. Their
+    /// size is written as 0 ("smudged"), so the next status compares their
+    /// content instead of trusting the metadata.
+    ///
     pub fn write_to_file(&self, path: &Path) -> Result<()> {
         // Create the parent directory if it doesn't exist
         if let Some(parent) = path.parent() {
@@ -100,159 +215,272 @@ impl Index {
                 .with_context(|| format!("Failed to create directory at {:?}", parent))?;
         }
 
-        let mut file = File::create(path)
-            .with_context(|| format!("Failed to create index file at {:?}", path))?;
-
-        // Write the index signature and version
-        file.write_all(INDEX_SIGNATURE)
-            .context("Failed to write index signature")?;
-        file.write_all(&INDEX_VERSION.to_be_bytes())
-            .context("Failed to write index version")?;
+        let mut data = Vec::new();
 
-        // Write the number of entries
-        file.write_all(&(self.entries.len() as u32).to_be_bytes())
-            .context("Failed to write entries count")?;
+        // Write the index signature, version and the number of entries
+        data.extend_from_slice(INDEX_SIGNATURE);
+        data.extend(INDEX_VERSION.to_be_bytes());
+        data.extend((self.entries.len() as u32).to_be_bytes());
 
         // Sort entries by path for consistent ordering
         let mut entries: Vec<_> = self.entries.values().collect();
         entries.sort_by(|a, b| a.path.cmp(&b.path));
 
-        // Write each entry to the file
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        // Write each entry
+        let mut previous_path = "";
         for entry in entries {
-            file.write_all(&entry.mtime.to_be_bytes())
-                .context("Failed to write entry mtime")?;
-            file.write_all(&entry.dev.to_be_bytes())
-                .context("Failed to write entry dev")?;
-            file.write_all(&entry.ino.to_be_bytes())
-                .context("Failed to write entry ino")?;
-            file.write_all(&entry.uid.to_be_bytes())
-                .context("Failed to write entry uid")?;
-            file.write_all(&entry.gid.to_be_bytes())
-                .context("Failed to write entry gid")?;
-            file.write_all(&entry.mode.to_be_bytes())
-                .context("Failed to write entry mode")?;
-            file.write_all(&entry.size.to_be_bytes())
-                .context("Failed to write entry size")?;
-            file.write_all(&entry.hash)
-                .context("Failed to write entry hash")?;
-            file.write_all(&entry.flags.to_be_bytes())
-                .context("Failed to write entry flags")?;
-
-            // Write the file path as a null-terminated string
+            let size = match entry.is_racy(now) {
+                true => 0,
+                false => entry.size,
+            };
+            data.extend(entry.mtime.to_be_bytes());
+            data.extend(entry.dev.to_be_bytes());
+            data.extend(entry.ino.to_be_bytes());
+            data.extend(entry.mode.to_be_bytes());
+            data.extend(entry.uid.to_be_bytes());
+            data.extend(entry.gid.to_be_bytes());
+            data.extend(size.to_be_bytes());
+            data.extend_from_slice(entry.hash.as_bytes());
+
+            // Extended flags only follow if there are any (version 3 and later)
+            let mut flags = entry.flags & !EXTENDED_FLAG;
+            if entry.extended_flags != 0 {
+                flags |= EXTENDED_FLAG;
+            }
+            data.extend(flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                data.extend(entry.extended_flags.to_be_bytes());
+            }
+
+            // Write the path relative to the previous one (version 4): the
+            // number of bytes to drop from its end, then the new suffix
             let path_str = entry
                 .path
                 .to_str()
                 .context("Failed to convert path to string")?;
-            file.write_all(path_str.as_bytes())
-                .context("Failed to write entry path")?;
-            file.write_all(&[0])
-                .context("Failed to write path terminator")?;
+            let common = common_prefix_len(previous_path, path_str);
+            write_varint(&mut data, (previous_path.len() - common) as u64);
+            data.extend_from_slice(&path_str.as_bytes()[common..]);
+            data.push(0);
+            previous_path = path_str;
         }
 
-        Ok(())
+        write_extension(&mut data, &self.cache_tree)?;
+        write_extension(&mut data, &self.untracked_cache)?;
+        write_extension(&mut data, &self.fsmonitor)?;
+        for extension in &self.unknown_extensions {
+            write_raw_extension(&mut data, &extension.signature, &extension.payload);
+        }
+
+        fs::write(path, data).with_context(|| format!("Failed to write index file at {:?}", path))
     }
 
     /// Reads the index from a file.
     ///
+    /// Versions 2 to 4 are supported. Entry hashes are read using the object
+    /// format of the repository the index file belongs to.
+    ///
     pub fn read_from_file(&mut self, path: &Path) -> Result<()> {
-        // Open the index file
-        let mut file =
-            File::open(path).with_context(|| format!("Failed to open index file at {:?}", path))?;
+        let format = match path.parent() {
+            Some(vox_dir) => ObjectFormat::for_vox_dir(vox_dir)?,
+            None => ObjectFormat::default(),
+        };
+
+        let data =
+            fs::read(path).with_context(|| format!("Failed to open index file at {:?}", path))?;
+        let mut reader = IndexReader { data: &data, pos: 0 };
 
         // Read and validate the signature
-        let mut signature = [0u8; 4];
-        file.read_exact(&mut signature)
-            .context("Failed to read index signature")?;
-        if &signature != INDEX_SIGNATURE {
+        if reader.take(4).context("Failed to read index signature")? != INDEX_SIGNATURE {
             return Err(anyhow::anyhow!("Invalid index file signature"));
         }
 
         // Read and validate the version
-        let mut version_bytes = [0u8; 4];
-        file.read_exact(&mut version_bytes)
-            .context("Failed to read index version")?;
-        let version = u32::from_be_bytes(version_bytes);
-        if version != INDEX_VERSION {
+        let version = reader.u32().context("Failed to read index version")?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
             return Err(anyhow::anyhow!("Unsupported index version"));
         }
 
         // Read the number of entries
-        let mut count_bytes = [0u8; 4];
-        file.read_exact(&mut count_bytes)?;
-        let count = u32::from_be_bytes(count_bytes);
+        let count = reader.u32().context("Failed to read entries count")?;
 
         self.entries.clear();
+        let mut previous_path = Vec::new();
         for _ in 0..count {
-            let mut entry = IndexEntry {
-                mtime: 0,
-                dev: 0,
-                ino: 0,
-                mode: 0,
-                uid: 0,
-                gid: 0,
-                size: 0,
-                hash: [0; 20],
-                flags: 0,
-                path: PathBuf::new(),
+            let mtime = reader.u64()?;
+            let dev = reader.u32()?;
+            let ino = reader.u32()?;
+            let mode = reader.u32()?;
+            let uid = reader.u32()?;
+            let gid = reader.u32()?;
+            let size = reader.u32()?;
+            let hash = ObjectId::from_bytes(format, reader.take(format.raw_len())?)?;
+            let flags = reader.u16()?;
+
+            let extended_flags = if version >= 3 && flags & EXTENDED_FLAG != 0 {
+                reader.u16()?
+            } else {
+                0
             };
 
-            // Read metadata fields
-            let mut buffer_u64 = [0u8; 8];
-            file.read_exact(&mut buffer_u64)?;
-            entry.mtime = u64::from_be_bytes(buffer_u64);
+            // Read the file path, prefix compressed since version 4
+            let mut path_bytes = if version >= 4 {
+                let strip = reader.varint()? as usize;
+                let keep = previous_path
+                    .len()
+                    .checked_sub(strip)
+                    .context("Invalid path compression in index entry")?;
+                previous_path[..keep].to_vec()
+            } else {
+                Vec::new()
+            };
+            path_bytes.extend_from_slice(reader.until_nul()?);
+            previous_path = path_bytes.clone();
+
+            let entry = IndexEntry {
+                mtime,
+                dev,
+                ino,
+                mode,
+                uid,
+                gid,
+                size,
+                hash,
+                flags: flags & !EXTENDED_FLAG,
+                extended_flags,
+                path: PathBuf::from(String::from_utf8_lossy(&path_bytes).into_owned()),
+            };
 
-            let mut buffer = [0u8; 4];
-            file.read_exact(&mut buffer)?;
-            entry.dev = u32::from_be_bytes(buffer);
+            // Add the entry to the index
+            self.entries.insert(entry.path.clone(), entry);
+        }
 
-            file.read_exact(&mut buffer)?;
-            entry.ino = u32::from_be_bytes(buffer);
+        // Whatever follows the entries are extensions
+        self.cache_tree = CacheTree::default();
+        self.untracked_cache = UntrackedCache::default();
+        self.fsmonitor = FsMonitor::default();
+        self.unknown_extensions.clear();
+
+        for extension in read_extensions(&data[reader.pos..])? {
+            if let Some(cache_tree) = extension.parse_as(format)? {
+                self.cache_tree = cache_tree;
+            } else if let Some(untracked_cache) = extension.parse_as(format)? {
+                self.untracked_cache = untracked_cache;
+            } else if let Some(fsmonitor) = extension.parse_as(format)? {
+                self.fsmonitor = fsmonitor;
+            } else if extension.is_optional() {
+                self.unknown_extensions.push(extension);
+            } else {
+                return Err(anyhow::anyhow!(
+                    "Unsupported index extension: {}",
+                    String::from_utf8_lossy(&extension.signature)
+                ));
+            }
+        }
 
-            file.read_exact(&mut buffer)?;
-            entry.mode = u32::from_be_bytes(buffer);
+        Ok(())
+    }
+}
 
-            file.read_exact(&mut buffer)?;
-            entry.uid = u32::from_be_bytes(buffer);
+/// Cursor over the raw bytes of an index file
+struct IndexReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
 
-            file.read_exact(&mut buffer)?;
-            entry.gid = u32::from_be_bytes(buffer);
+impl<'a> IndexReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .context("Unexpected end of index file")?;
+        self.pos += len;
+        Ok(bytes)
+    }
 
-            file.read_exact(&mut buffer)?;
-            entry.size = u32::from_be_bytes(buffer);
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
 
-            // Read the SHA-1 hash
-            file.read_exact(&mut entry.hash)?;
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
 
-            // Read the flags
-            let mut flag_bytes = [0u8; 2];
-            file.read_exact(&mut flag_bytes)?;
-            entry.flags = u16::from_be_bytes(flag_bytes);
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into()?))
+    }
 
-            // Read the file path
-            let mut path_bytes = Vec::new();
-            let mut byte = [0u8; 1];
-            loop {
-                file.read_exact(&mut byte)?;
-                if byte[0] == 0 {
-                    break;
-                }
-                path_bytes.push(byte[0]);
-            }
-            entry.path = PathBuf::from(String::from_utf8_lossy(&path_bytes).into_owned());
+    fn until_nul(&mut self) -> Result<&'a [u8]> {
+        let rest = &self.data[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .context("Unterminated path in index entry")?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
 
-            // Add the entry to the index
-            self.entries.insert(entry.path.clone(), entry);
+    /// Reads a variable length integer, see [`write_varint`]
+    fn varint(&mut self) -> Result<u64> {
+        let mut byte = self.take(1)?[0];
+        let mut value = u64::from(byte & 0x7f);
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | u64::from(byte & 0x7f);
         }
+        Ok(value)
+    }
+}
 
-        Ok(())
+/// Writes `value` as a big-endian base-128 integer where every byte but the
+/// last has the high bit set. Each continuation adds one to the value, so
+/// there's exactly one encoding per number.
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >= 0x80 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
     }
+    data.extend(bytes.iter().rev());
+}
+
+/// Length of the common prefix of `a` and `b` in bytes, on a char boundary
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, ca), cb)| ca != cb)
+        .map(|((i, _), _)| i)
+        .unwrap_or_else(|| a.len().min(b.len()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
+    use std::io::Write;
     use tempfile::tempdir;
 
+    fn test_entry(path: &str) -> IndexEntry {
+        IndexEntry {
+            mtime: 12345,
+            dev: 1,
+            ino: 2,
+            mode: 0o100644,
+            uid: 1000,
+            gid: 1000,
+            size: 100,
+            hash: ObjectId::Sha1([1; 20]),
+            flags: 0,
+            extended_flags: 0,
+            path: PathBuf::from(path),
+        }
+    }
+
     #[test]
     fn test_create_new_index() {
         let index = Index::new();
@@ -270,8 +498,9 @@ mod tests {
             uid: 1000,
             gid: 1000,
             size: 100,
-            hash: [1; 20],
+            hash: ObjectId::Sha1([1; 20]),
             flags: 0,
+            extended_flags: 0,
             path: PathBuf::from("test.txt"),
         };
 
@@ -293,8 +522,9 @@ mod tests {
             uid: 1000,
             gid: 1000,
             size: 100,
-            hash: [1; 20],
+            hash: ObjectId::Sha1([1; 20]),
             flags: 0,
+            extended_flags: 0,
             path: PathBuf::from("test.txt"),
         };
 
@@ -319,8 +549,9 @@ mod tests {
             uid: 1000,
             gid: 1000,
             size: 100,
-            hash: [1; 20],
+            hash: ObjectId::Sha1([1; 20]),
             flags: 0,
+            extended_flags: 0,
             path: PathBuf::from("test.txt"),
         };
 
@@ -338,6 +569,8 @@ mod tests {
         let read_entry = read_index.get_entry(&PathBuf::from("test.txt")).unwrap();
 
         assert_eq!(original_entry.mtime, read_entry.mtime);
+        assert_eq!(original_entry.mode, read_entry.mode);
+        assert_eq!(original_entry.uid, read_entry.uid);
         assert_eq!(original_entry.hash, read_entry.hash);
         assert_eq!(original_entry.path, read_entry.path);
 
@@ -384,4 +617,120 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_prefix_compressed_paths() -> Result<()> {
+        let dir = tempdir()?;
+        let index_path = dir.path().join("index");
+
+        let mut original_index = Index::new();
+        for path in ["src/main.rs", "src/index/mod.rs", "src/index/entry.rs", "README.md"] {
+            original_index.add_entry(test_entry(path));
+        }
+        let mut flagged = test_entry("src/lib.rs");
+        flagged.extended_flags = 0x2000;
+        original_index.add_entry(flagged);
+        original_index.write_to_file(&index_path)?;
+
+        let mut read_index = Index::new();
+        read_index.read_from_file(&index_path)?;
+
+        let mut paths: Vec<_> = read_index.entries.keys().cloned().collect();
+        paths.sort();
+        let mut expected: Vec<_> = original_index.entries.keys().cloned().collect();
+        expected.sort();
+        assert_eq!(paths, expected);
+        assert_eq!(
+            read_index.get_entry(Path::new("src/lib.rs")).unwrap().extended_flags,
+            0x2000
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_racy_entries_are_smudged() -> Result<()> {
+        let dir = tempdir()?;
+        let index_path = dir.path().join("index");
+
+        let mut index = Index::new();
+        index.add_entry(test_entry("old.txt"));
+        let mut racy = test_entry("racy.txt");
+        racy.mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        index.add_entry(racy);
+        index.write_to_file(&index_path)?;
+
+        let mut read_index = Index::new();
+        read_index.read_from_file(&index_path)?;
+        assert_eq!(read_index.get_entry(Path::new("old.txt")).unwrap().size, 100);
+        assert_eq!(read_index.get_entry(Path::new("racy.txt")).unwrap().size, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_version_2_index() -> Result<()> {
+        let dir = tempdir()?;
+        let index_path = dir.path().join("index");
+
+        // A version 2 index has full, NUL-terminated paths and no extensions
+        let mut data = Vec::new();
+        data.extend_from_slice(INDEX_SIGNATURE);
+        data.extend(2u32.to_be_bytes());
+        data.extend(2u32.to_be_bytes());
+        for path in ["a/one.txt", "a/two.txt"] {
+            data.extend(12345u64.to_be_bytes());
+            for field in [1u32, 2, 0o100644, 1000, 1000, 100] {
+                data.extend(field.to_be_bytes());
+            }
+            data.extend([1u8; 20]);
+            data.extend(0u16.to_be_bytes());
+            data.extend_from_slice(path.as_bytes());
+            data.push(0);
+        }
+        fs::write(&index_path, data)?;
+
+        let mut index = Index::new();
+        index.read_from_file(&index_path)?;
+        assert_eq!(index.entries.len(), 2);
+        assert!(index.get_entry(Path::new("a/two.txt")).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_varint_roundtrip() -> Result<()> {
+        for value in [0u64, 1, 127, 128, 255, 16511, 16512, u32::MAX as u64] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut reader = IndexReader { data: &data, pos: 0 };
+            assert_eq!(reader.varint()?, value);
+            assert_eq!(reader.pos, data.len());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_extensions() -> Result<()> {
+        let dir = tempdir()?;
+        let index_path = dir.path().join("index");
+
+        let mut index = Index::new();
+        index.add_entry(test_entry("a.txt"));
+        index.write_to_file(&index_path)?;
+
+        // Optional extensions survive a rewrite, required ones are rejected
+        let mut data = fs::read(&index_path)?;
+        write_raw_extension(&mut data, b"ZZZZ", b"opaque");
+        fs::write(&index_path, &data)?;
+
+        let mut read_index = Index::new();
+        read_index.read_from_file(&index_path)?;
+        read_index.write_to_file(&index_path)?;
+        read_index.read_from_file(&index_path)?;
+        assert_eq!(read_index.unknown_extensions.len(), 1);
+        assert_eq!(read_index.unknown_extensions[0].payload, b"opaque");
+
+        write_raw_extension(&mut data, b"link", b"");
+        fs::write(&index_path, &data)?;
+        assert!(Index::new().read_from_file(&index_path).is_err());
+        Ok(())
+    }
 }
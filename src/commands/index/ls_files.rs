@@ -17,7 +17,7 @@ pub fn ls_files_command(stage: bool) -> Result<()> {
             println!(
                 "{} {} {}\t{}",
                 format!("{:o}", entry.mode),
-                hex::encode(&entry.hash),
+                entry.hash.to_hex(),
                 entry.flags,
                 entry.path.display()
             );
@@ -1,3 +1,7 @@
+pub mod cache_tree;
+pub mod extensions;
+pub mod fsmonitor;
 pub mod index;
 pub mod ls_files;
 pub mod rm_index;
+pub mod untracked_cache;
@@ -0,0 +1,323 @@
+use crate::commands::index::extensions::IndexExtension;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::platform::to_index_path;
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Directories modified more recently than this aren't cached, since a change
+/// within the same mtime tick wouldn't be noticed
+const RACY_WINDOW: Duration = Duration::from_secs(1);
+
+/// Listing of a single directory as of its last scan
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CachedDir {
+    /// Modification time of the directory when it was scanned
+    pub mtime: Duration,
+    /// Regular files and symlinks directly inside the directory
+    pub files: Vec<String>,
+    /// Subdirectories that are scanned as well
+    pub subdirs: Vec<String>,
+}
+
+/// Directory listings of the working tree keyed by directory mtime
+///
+/// Adding or removing an entry updates the mtime of its directory, so a
+/// directory whose mtime didn't change since the last scan still has the same
+/// files and doesn't have to be read again. Only the directories themselves
+/// are stat'ed on every run.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UntrackedCache {
+    /// Listings by directory, relative to the repository root
+    pub dirs: BTreeMap<PathBuf, CachedDir>,
+    dirty: bool,
+}
+
+impl UntrackedCache {
+    /// Returns true if the last scan changed the cache
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Lists every file in the working tree, relative to `repo_path`
+    ///
+    /// The directories of each level of the tree are stat'ed and read in
+    /// parallel, and their listings merged into the cache once they are all
+    /// done, so the workers share nothing but read access to the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo_path` - The repository root
+    /// * `skip` - Returns true for directories that shouldn't be scanned,
+    ///   given their path relative to the root
+    pub fn scan<F>(&mut self, repo_path: &Path, skip: F) -> Result<Vec<PathBuf>>
+    where
+        F: Fn(&Path) -> bool + Sync,
+    {
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+        let mut visited = Vec::new();
+        let mut pending = vec![PathBuf::new()];
+
+        while !pending.is_empty() {
+            let listings = pending
+                .par_iter()
+                .map(|dir| self.list_dir(repo_path, dir, now, &skip))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut next = Vec::new();
+            for (dir, listing) in pending.into_iter().zip(listings) {
+                let listing = match listing {
+                    DirListing::Cached(listing) => listing,
+                    DirListing::Read { listing, racy } => {
+                        if racy {
+                            self.dirs.remove(&dir);
+                        } else {
+                            self.dirs.insert(dir.clone(), listing.clone());
+                        }
+                        self.dirty = true;
+                        listing
+                    }
+                };
+
+                files.extend(listing.files.iter().map(|name| to_index_path(&dir.join(name))));
+                // Cached listings may predate a directory becoming skipped
+                next.extend(
+                    listing
+                        .subdirs
+                        .iter()
+                        .map(|name| dir.join(name))
+                        .filter(|subdir| !skip(subdir)),
+                );
+                visited.push(dir);
+            }
+            pending = next;
+        }
+
+        // Drop directories that don't exist anymore
+        let visited: HashSet<PathBuf> = visited.into_iter().collect();
+        let before = self.dirs.len();
+        self.dirs.retain(|dir, _| visited.contains(dir));
+        self.dirty |= self.dirs.len() != before;
+
+        files.sort();
+        Ok(files)
+    }
+
+    /// Lists the directory `dir`, from the cache if its mtime didn't change
+    fn list_dir<F>(
+        &self,
+        repo_path: &Path,
+        dir: &Path,
+        now: SystemTime,
+        skip: &F,
+    ) -> Result<DirListing>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let full_path = repo_path.join(dir);
+        let modified = fs::metadata(&full_path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to stat directory {}", full_path.display()))?;
+        let mtime = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        match self.dirs.get(dir) {
+            Some(cached) if cached.mtime == mtime => Ok(DirListing::Cached(cached.clone())),
+            _ => Ok(DirListing::Read {
+                listing: read_listing(&full_path, dir, mtime, skip)?,
+                racy: now.duration_since(modified).unwrap_or_default() < RACY_WINDOW,
+            }),
+        }
+    }
+}
+
+/// Listing of a directory found by a scan
+enum DirListing {
+    /// The cached listing, still up to date
+    Cached(CachedDir),
+    /// A fresh listing, which isn't cached if `racy`
+    Read { listing: CachedDir, racy: bool },
+}
+
+impl IndexExtension for UntrackedCache {
+    const SIGNATURE: &'static [u8; 4] = b"UNTR";
+
+    /// Returns true if nothing is cached
+    fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    /// Serializes the cache into the payload of the `UNTR` extension
+    ///
+    /// The payload is the number of directories followed by, for each one,
+    /// `<path>\0<mtime secs><mtime nanos><file count><files\0...><subdir count><subdirs\0...>`
+    fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend((self.dirs.len() as u32).to_be_bytes());
+
+        for (dir, cached) in &self.dirs {
+            write_name(&mut data, dir.to_str().context("Invalid directory path")?);
+            data.extend(cached.mtime.as_secs().to_be_bytes());
+            data.extend(cached.mtime.subsec_nanos().to_be_bytes());
+
+            for names in [&cached.files, &cached.subdirs] {
+                data.extend((names.len() as u32).to_be_bytes());
+                for name in names {
+                    write_name(&mut data, name);
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Parses the payload of the `UNTR` extension
+    fn parse(data: &[u8], _format: ObjectFormat) -> Result<Self> {
+        let mut pos = 0;
+        let mut cache = UntrackedCache::default();
+
+        let count = read_u32(data, &mut pos)?;
+        for _ in 0..count {
+            let dir = PathBuf::from(read_name(data, &mut pos)?);
+            let secs = u64::from_be_bytes(read_bytes(data, &mut pos)?);
+            let nanos = read_u32(data, &mut pos)?;
+
+            let mut cached = CachedDir {
+                mtime: Duration::new(secs, nanos),
+                ..Default::default()
+            };
+            for names in [&mut cached.files, &mut cached.subdirs] {
+                for _ in 0..read_u32(data, &mut pos)? {
+                    names.push(read_name(data, &mut pos)?);
+                }
+            }
+
+            cache.dirs.insert(dir, cached);
+        }
+
+        Ok(cache)
+    }
+}
+
+fn read_listing<F>(full_path: &Path, dir: &Path, mtime: Duration, skip: &F) -> Result<CachedDir>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut listing = CachedDir {
+        mtime,
+        ..Default::default()
+    };
+
+    for entry in fs::read_dir(full_path)
+        .with_context(|| format!("Failed to read directory {}", full_path.display()))?
+    {
+        let entry = entry.context("Failed to read directory entry")?;
+        let file_type = entry.file_type()?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| anyhow!("Invalid UTF-8 file name: {:?}", name))?;
+
+        if file_type.is_file() || file_type.is_symlink() {
+            listing.files.push(name);
+        } else if file_type.is_dir() && !skip(&dir.join(&name)) {
+            listing.subdirs.push(name);
+        }
+    }
+
+    listing.files.sort();
+    listing.subdirs.sort();
+    Ok(listing)
+}
+
+fn write_name(data: &mut Vec<u8>, name: &str) {
+    data.extend_from_slice(name.as_bytes());
+    data.push(0);
+}
+
+fn read_name(data: &[u8], pos: &mut usize) -> Result<String> {
+    let rest = data.get(*pos..).unwrap_or_default();
+    let len = rest
+        .iter()
+        .position(|&b| b == 0)
+        .context("Truncated untracked cache")?;
+
+    let name = String::from_utf8(rest[..len].to_vec()).context("Invalid untracked cache entry")?;
+    *pos += len + 1;
+    Ok(name)
+}
+
+fn read_bytes<const N: usize>(data: &[u8], pos: &mut usize) -> Result<[u8; N]> {
+    let bytes = data
+        .get(*pos..*pos + N)
+        .context("Truncated untracked cache")?
+        .try_into()?;
+    *pos += N;
+    Ok(bytes)
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(read_bytes(data, pos)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_scan_uses_cached_listing() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::create_dir_all(dir.path().join(".vox"))?;
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}")?;
+
+        let skip = |path: &Path| path == Path::new(".vox");
+        let mut cache = UntrackedCache::default();
+        let files = cache.scan(dir.path(), skip)?;
+        assert_eq!(
+            files,
+            vec![PathBuf::from("a.txt"), PathBuf::from("src/main.rs")]
+        );
+
+        // Pretend the root was scanned long ago: a matching mtime is trusted
+        // even though the listing is out of date
+        let mtime = fs::metadata(dir.path())?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?;
+        cache.dirs.insert(
+            PathBuf::new(),
+            CachedDir {
+                mtime,
+                files: vec!["cached.txt".to_string()],
+                subdirs: Vec::new(),
+            },
+        );
+        assert_eq!(cache.scan(dir.path(), skip)?, vec![PathBuf::from("cached.txt")]);
+
+        // A different mtime forces a rescan
+        cache.dirs.get_mut(Path::new("")).unwrap().mtime = Duration::ZERO;
+        assert_eq!(cache.scan(dir.path(), skip)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_roundtrip() -> Result<()> {
+        let mut cache = UntrackedCache::default();
+        cache.dirs.insert(
+            PathBuf::from("src"),
+            CachedDir {
+                mtime: Duration::new(1_700_000_000, 42),
+                files: vec!["lib.rs".to_string(), "main.rs".to_string()],
+                subdirs: vec!["bin".to_string()],
+            },
+        );
+
+        assert_eq!(UntrackedCache::parse(&cache.serialize()?, ObjectFormat::Sha1)?, cache);
+        Ok(())
+    }
+}
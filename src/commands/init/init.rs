@@ -1,29 +1,66 @@
-use crate::commands::index::index::Index;
-use crate::storage::utils::{HEAD_DIR, INDEX_FILE, OBJ_DIR, REFS_DIR, VOX_DIR};
-use anyhow::{Context, Result};
+use crate::storage::encryption::{generate_key, key_path};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::compression::Codec;
+use crate::storage::repo_config::RepoConfig;
+use crate::vox_repo::VoxRepo;
+use anyhow::{bail, Result};
 use std::path::Path;
-use tokio::fs;
 
-pub async fn init_command() -> Result<()> {
-    fs::create_dir_all(&*VOX_DIR)
-        .await
-        .context("Failed to create .vox directory")?;
-    fs::create_dir_all(&*OBJ_DIR)
-        .await
-        .context("Failed to create .vox/objects directory")?;
-    fs::create_dir_all(&*REFS_DIR)
-        .await
-        .context("Failed to create .vox/refs directory")?;
-    fs::write(&*HEAD_DIR, "ref: refs/heads/main\n")
-        .await
-        .context("Failed to write to .vox/HEAD file")?;
+pub async fn init_command(
+    object_format: Option<ObjectFormat>,
+    bare: bool,
+    encrypt: bool,
+    compression: Option<Codec>,
+) -> Result<()> {
+    // Like `git init`, running it again keeps the config, HEAD and index
+    let vox_dir = match bare {
+        true => Path::new("."),
+        false => Path::new(".vox"),
+    };
+    if RepoConfig::path(vox_dir).exists() {
+        let config = RepoConfig::load(vox_dir)?;
+        if let Some(format) = object_format.filter(|&format| format != config.object_format()) {
+            bail!(
+                "The repository uses {}, convert it with 'vox migrate --to {}' instead",
+                config.object_format(),
+                format
+            );
+        }
+        if let Some(codec) = compression.filter(|&codec| codec != config.compression()) {
+            bail!(
+                "The repository compresses its objects with {}, recompress them with \
+                 'vox migrate --compression {}' instead",
+                config.compression(),
+                codec
+            );
+        }
+        if encrypt && config.encryption().is_none() {
+            bail!("The repository isn't encrypted, reinitializing it can't encrypt its objects");
+        }
+        VoxRepo::reinit(Path::new("."), bare)?;
+        println!("Reinitialized existing vox repository");
+        return Ok(());
+    }
 
-    let index = Index::new();
-    index
-        .write_to_file(Path::new(&*INDEX_FILE))
-        .context("Failed to create index file")?;
+    // The key must exist before anything is stored
+    let key_id = match encrypt {
+        true => Some(generate_key()?),
+        false => None,
+    };
+    // A bare repository has no working tree and keeps everything at the top level
+    let config = RepoConfig::new(object_format.unwrap_or(ObjectFormat::Sha1))
+        .with_bare(bare)
+        .with_encryption(key_id.clone())
+        .with_compression(compression.unwrap_or_default());
+    VoxRepo::create(Path::new("."), config)?;
+    if let Some(key_id) = key_id {
+        println!("Objects are encrypted with the key in {}", key_path(&key_id)?.display());
+    }
 
-    println!("Initialized vox directory");
+    match bare {
+        true => println!("Initialized empty bare vox repository"),
+        false => println!("Initialized vox directory"),
+    }
     Ok(())
 }
 
@@ -31,6 +68,7 @@ pub async fn init_command() -> Result<()> {
 mod tests {
     use super::*;
     use tempfile::TempDir;
+    use tokio::fs;
     use tokio::runtime::Runtime;
 
     #[test]
@@ -45,7 +83,7 @@ mod tests {
             let original_dir = std::env::current_dir().unwrap();
             std::env::set_current_dir(&repo_path).unwrap();
             
-            init_command().await.unwrap();
+            init_command(Some(ObjectFormat::Sha1), false, false, None).await.unwrap();
             
             std::env::set_current_dir(original_dir).unwrap();
             
@@ -54,6 +92,7 @@ mod tests {
             assert!(repo_path.join(".vox/refs").exists());
             assert!(repo_path.join(".vox/HEAD").exists());
             assert!(repo_path.join(".vox/index").exists());
+            assert!(repo_path.join(".vox/config").exists());
         });
     }
 }
@@ -0,0 +1,53 @@
+use crate::commands::config::commands::lfs_url;
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::lfs::{LfsStore, Pointer};
+use crate::storage::objects::blob::Blob;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use std::path::Path;
+
+#[derive(Debug, Subcommand)]
+pub enum LfsCommands {
+    #[command(about = "Copy the large files lfs.url doesn't have to it")]
+    Push,
+
+    #[command(name = "ls-files", about = "List the staged files stored as LFS pointers")]
+    LsFiles,
+}
+
+pub fn lfs_command(command: &LfsCommands) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    match command {
+        LfsCommands::Push => {
+            let copied = LfsStore::new(&VOX_DIR, lfs_url()).push()?;
+            println!("Pushed {} LFS object(s)", copied);
+        }
+        LfsCommands::LsFiles => {
+            let mut index = Index::new();
+            let index_path = Path::new(".vox/index");
+            if index_path.exists() {
+                index.read_from_file(index_path).context("Failed to read index")?;
+            }
+
+            let mut entries: Vec<_> = index.entries.values().collect();
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            for entry in entries {
+                let blob = Blob::load(&entry.hash.to_hex(), &OBJ_DIR)?;
+                if let Some(pointer) = Pointer::parse(&blob.data) {
+                    println!(
+                        "{} {}\t{} bytes",
+                        &pointer.oid[..10],
+                        entry.path.display(),
+                        pointer.size
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+/// Draws the rails of `log --graph` one commit at a time
+///
+/// Every column holds the commit expected next on that rail. Commits must be
+/// fed children first, as [`list_commits`](crate::storage::objects::graph::list_commits)
+/// returns them.
+#[derive(Debug, Default)]
+pub struct GraphRenderer {
+    columns: Vec<Option<String>>,
+}
+
+/// The lines drawn for one commit
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GraphRows {
+    /// Rails joining before the commit, such as `|/`
+    pub before: Vec<String>,
+    /// The row of the commit itself, such as `| *`
+    pub commit: String,
+    /// Rails leaving the commit, such as `|\` for a merge
+    pub after: Vec<String>,
+    /// The rails continuing past the commit, to prefix its other lines with
+    pub padding: String,
+}
+
+impl GraphRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Places `hash` on the graph and routes its rails to `parents`
+    pub fn next(&mut self, hash: &str, parents: &[String]) -> GraphRows {
+        let is_here = |column: &Option<String>| column.as_deref() == Some(hash);
+
+        let index = match self.columns.iter().position(is_here) {
+            Some(index) => index,
+            None => self.free_column(),
+        };
+
+        // Other rails waiting for this commit join it: "|/"
+        let mut before = Vec::new();
+        let joining: Vec<usize> = (index + 1..self.columns.len())
+            .filter(|&i| is_here(&self.columns[i]))
+            .collect();
+        if !joining.is_empty() {
+            // A joining rail leans into the gap left of its column
+            let marks = self.columns.iter().enumerate().filter_map(|(i, column)| {
+                match (joining.contains(&i), column) {
+                    (true, _) => Some((2 * i - 1, '/')),
+                    (false, Some(_)) => Some((2 * i, '|')),
+                    (false, None) => None,
+                }
+            });
+            before.push(draw(marks));
+            for &i in &joining {
+                self.columns[i] = None;
+            }
+        }
+
+        let marks = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter_map(|(i, column)| match (i == index, column) {
+                (true, _) => Some((2 * i, '*')),
+                (false, Some(_)) => Some((2 * i, '|')),
+                (false, None) => None,
+            });
+        let commit = draw(marks);
+
+        // The first parent continues on this rail, the others branch off: "|\"
+        self.columns[index] = parents.first().cloned();
+        let mut after = Vec::new();
+        let mut new_columns = Vec::new();
+        for parent in parents.iter().skip(1) {
+            if self.columns.iter().any(|c| c.as_deref() == Some(parent)) {
+                continue;
+            }
+            let position = index + 1 + new_columns.len();
+            self.columns.insert(position, Some(parent.clone()));
+            new_columns.push(position);
+        }
+        if !new_columns.is_empty() {
+            // A new rail leaves from the gap left of its column
+            let marks = self.columns.iter().enumerate().filter_map(|(i, column)| {
+                match (new_columns.contains(&i), column) {
+                    (true, _) => Some((2 * i - 1, '\\')),
+                    (false, Some(_)) => Some((2 * i, '|')),
+                    (false, None) => None,
+                }
+            });
+            after.push(draw(marks));
+        }
+
+        while self.columns.last().is_some_and(Option::is_none) {
+            self.columns.pop();
+        }
+
+        GraphRows {
+            before,
+            commit,
+            after,
+            padding: self.padding(),
+        }
+    }
+
+    /// Rails of the commits still to come, for lines between commits
+    pub fn padding(&self) -> String {
+        let marks = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, column)| column.is_some())
+            .map(|(i, _)| (2 * i, '|'));
+        draw(marks)
+    }
+
+    /// Returns an empty column, adding one if every column is taken
+    fn free_column(&mut self) -> usize {
+        match self.columns.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.columns.push(None);
+                self.columns.len() - 1
+            }
+        }
+    }
+}
+
+/// Draws a row from `(position, character)` marks, rail `i` sitting at
+/// position `2 * i`
+fn draw(marks: impl Iterator<Item = (usize, char)>) -> String {
+    let mut row: Vec<char> = Vec::new();
+    for (position, mark) in marks {
+        if row.len() <= position {
+            row.resize(position + 1, ' ');
+        }
+        row[position] = mark;
+    }
+    row.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parents(hashes: &[&str]) -> Vec<String> {
+        hashes.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    #[test]
+    fn test_branch_and_merge() {
+        // m merges b into a, both branched off base
+        let mut graph = GraphRenderer::new();
+
+        let rows = graph.next("m", &parents(&["a", "b"]));
+        assert_eq!(rows.commit, "*");
+        assert_eq!(rows.after, vec!["|\\"]);
+
+        let rows = graph.next("a", &parents(&["base"]));
+        assert_eq!(rows.commit, "* |");
+
+        let rows = graph.next("b", &parents(&["base"]));
+        assert_eq!(rows.commit, "| *");
+
+        let rows = graph.next("base", &[]);
+        assert_eq!(rows.before, vec!["|/"]);
+        assert_eq!(rows.commit, "*");
+        assert_eq!(rows.padding, "");
+    }
+}
@@ -1,22 +1,269 @@
-use crate::storage::objects::commit::Commit;
-use crate::storage::utils::OBJ_DIR;
-use crate::{commands::commit::commit::get_current_commit, storage::objects::Loadable};
-use anyhow::Result;
-use chrono::{DateTime, Local};
+use crate::commands::commit::commit::get_current_commit;
+use crate::commands::log::graph::{GraphRenderer, GraphRows};
+use crate::commands::verify::verify::signature_status;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::mailmap::Mailmap;
+use crate::storage::notes::Notes;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::change::ChangeType;
+use crate::storage::objects::commit::{commit_changes, Commit};
+use crate::storage::objects::commit_graph::CommitGraph;
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::walk::CommitWalker;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::platform::to_index_path;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{parse_date, OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
 use colored::*;
-use std::path::PathBuf;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Options of the `log` command
+#[derive(Debug)]
+pub struct LogOptions {
+    /// The maximum number of commits to display
+    pub count: usize,
+    /// The revision to start from instead of HEAD (e.g., "main~2")
+    pub revision: Option<String>,
+    /// Draw the commit graph next to the history
+    pub graph: bool,
+    /// Show each commit on a single line
+    pub oneline: bool,
+    /// Only show commits whose author matches this pattern
+    pub author: Option<String>,
+    /// Only show commits committed at or after this date
+    pub since: Option<String>,
+    /// Only show commits committed at or before this date
+    pub until: Option<String>,
+    /// Only show commits whose message matches this pattern
+    pub grep: Option<String>,
+    /// Only show commits changing files matched by these pathspecs
+    pub paths: Vec<String>,
+    /// Keep following the single path in `paths` across renames
+    pub follow: bool,
+    /// Only show commits changing the number of occurrences of this string
+    pub pickaxe: Option<String>,
+}
+
+/// Criteria a commit must meet to be shown
+#[derive(Debug, Default)]
+struct LogFilter {
+    author: Option<Regex>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    grep: Option<Regex>,
+}
+
+impl LogFilter {
+    fn new(options: &LogOptions) -> Result<Self> {
+        let regex = |pattern: &Option<String>, flag: &str| -> Result<Option<Regex>> {
+            pattern
+                .as_deref()
+                .map(|pattern| {
+                    Regex::new(pattern)
+                        .with_context(|| format!("Invalid {} pattern: {}", flag, pattern))
+                })
+                .transpose()
+        };
+        let date = |date: &Option<String>| date.as_deref().map(parse_date).transpose();
+
+        Ok(Self {
+            author: regex(&options.author, "--author")?,
+            since: date(&options.since)?,
+            until: date(&options.until)?,
+            grep: regex(&options.grep, "--grep")?,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        self.author.is_some() || self.since.is_some() || self.until.is_some() || self.grep.is_some()
+    }
+
+    fn matches(&self, commit: &Commit) -> bool {
+        self.author
+            .as_ref()
+            .is_none_or(|author| author.is_match(&commit.author))
+            && self
+                .since
+                .is_none_or(|since| commit.committer_timestamp >= since)
+            && self
+                .until
+                .is_none_or(|until| commit.committer_timestamp <= until)
+            && self
+                .grep
+                .as_ref()
+                .is_none_or(|grep| grep.is_match(&commit.message))
+    }
+}
+
+/// Limits the history to the commits changing some paths, or changing how
+/// often a string occurs in the files
+#[derive(Debug, Default)]
+struct ChangeFilter {
+    pathspec: Pathspec,
+    /// The path followed across renames, under its name in the commits
+    /// seen last
+    followed: Option<PathBuf>,
+    pickaxe: Option<String>,
+    /// Changed-path filters ruling out commits without diffing them
+    graph: Option<CommitGraph>,
+}
+
+impl ChangeFilter {
+    fn new(paths: &[String], follow: bool, pickaxe: Option<String>) -> Result<Self> {
+        if follow && paths.len() != 1 {
+            bail!("--follow requires exactly one path");
+        }
+        // Tree paths are relative to the repository root, without "./"
+        let followed = follow.then(|| to_index_path(Path::new(&paths[0])));
+        let graph = match paths.is_empty() {
+            true => None,
+            false => CommitGraph::read(&OBJ_DIR, ObjectFormat::for_objects_dir(&OBJ_DIR)?)?,
+        };
+        Ok(Self {
+            pathspec: Pathspec::new(paths)?,
+            followed,
+            pickaxe,
+            graph,
+        })
+    }
+
+    fn is_active(&self) -> bool {
+        !self.pathspec.is_empty() || self.pickaxe.is_some()
+    }
+
+    /// Returns true if `commit` changes a file matched by the pathspec, and
+    /// changes the number of occurrences of the pickaxe string in it
+    ///
+    /// Commits must be fed newest first: a rename to the followed path
+    /// switches it to the old name for the older commits.
+    fn matches(&mut self, hash: &str, commit: &Commit) -> Result<bool> {
+        if !self.is_active() {
+            return Ok(true);
+        }
+        if self.is_ruled_out(hash) {
+            return Ok(false);
+        }
+
+        let changes = commit_changes(commit, &OBJ_DIR)?.get();
+        let touches = |path: &Path| match &self.followed {
+            Some(followed) => path.starts_with(followed),
+            None => self.pathspec.matches(path),
+        };
+        let mut touched = changes
+            .values()
+            .filter(|change| touches(change.path()) || change.old_path().is_some_and(touches));
+
+        let matched = match &self.pickaxe {
+            Some(needle) => touched.any(|change| {
+                let count = |hash: Option<&str>| hash.map_or(0, |hash| occurrences(hash, needle));
+                count(change.old_hash()) != count(change.new_hash())
+            }),
+            None => touched.next().is_some(),
+        };
+
+        if let Some(followed) = &mut self.followed {
+            for change in changes.values() {
+                if let ChangeType::RENAMED {
+                    old_path, new_path, ..
+                } = change
+                    && *followed == *new_path
+                {
+                    *followed = old_path.clone();
+                }
+            }
+        }
+        Ok(matched)
+    }
+
+    /// Returns true if the commit graph shows that the commit `hash` changes
+    /// none of the paths
+    ///
+    /// A rename to the followed path changes it, so skipping the commit never
+    /// misses one.
+    fn is_ruled_out(&self, hash: &str) -> bool {
+        let Some(graph) = &self.graph else {
+            return false;
+        };
+        match &self.followed {
+            Some(followed) => !graph.may_change(hash, followed),
+            None => self
+                .pathspec
+                .literal_paths()
+                .is_some_and(|paths| paths.iter().all(|path| !graph.may_change(hash, path))),
+        }
+    }
+}
+
+/// Counts the occurrences of `needle` in a blob, without overlaps
+///
+/// Entries that aren't blobs, such as the commits of submodules, count as none
+fn occurrences(hash: &str, needle: &str) -> usize {
+    match Blob::load(hash, &OBJ_DIR) {
+        Ok(blob) => count_occurrences(&blob.data, needle.as_bytes()),
+        Err(_) => 0,
+    }
+}
+
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut rest = haystack;
+    while let Some(index) = rest.windows(needle.len()).position(|window| window == needle) {
+        count += 1;
+        rest = &rest[index + needle.len()..];
+    }
+    count
+}
 
 /// Displays the commit history, starting from the current commit (HEAD).
 ///
 /// # Arguments
-/// - `count`: The maximum number of commits to display.
+/// - `options`: Where to start, which and how many commits to show and how.
 ///
-pub fn log_command(count: usize) -> Result<()> {
-    let mut current_commit_hash = get_current_commit()?;
+pub fn log_command(options: &LogOptions) -> Result<()> {
+    let count = options.count;
+    let filter = LogFilter::new(options)?;
 
-    if current_commit_hash.is_none() {
+    // `vox log <path>` names a path when it isn't a revision
+    let mut revision = options.revision.as_deref();
+    let mut paths = options.paths.clone();
+    if let Some(spec) = revision
+        && resolve_commit(&VOX_DIR, spec).is_err()
+        && Path::new(spec).exists()
+    {
+        paths.insert(0, spec.to_string());
+        revision = None;
+    }
+    let mut change_filter = ChangeFilter::new(&paths, options.follow, options.pickaxe.clone())?;
+
+    let notes = Notes::load(&VOX_DIR, &OBJ_DIR)?;
+    let mailmap = Mailmap::load(&VOX_DIR, &OBJ_DIR)?;
+
+    let start = match revision {
+        Some(revision) => Some(resolve_commit(&VOX_DIR, revision)?),
+        None => get_current_commit()?,
+    };
+
+    let Some(start) = start else {
         println!("{}", "No commits yet.".yellow());
         return Ok(());
+    };
+
+    if options.graph || options.oneline {
+        return print_compact_log(
+            &start,
+            options,
+            &filter,
+            &mut change_filter,
+            &notes,
+            &mailmap,
+        );
     }
 
     println!("{}", "Commit History".bold().blue());
@@ -25,22 +272,26 @@ pub fn log_command(count: usize) -> Result<()> {
     // Track the number of commits shown
     let mut commits_shown = 0;
 
-    // Traverse the commit history
-    while let Some(commit_hash) = current_commit_hash {
+    // Traverse the commit history, following first parents
+    let mut walker = CommitWalker::new(&OBJ_DIR).with_first_parent(true);
+    walker.push(&start)?;
+    for commit in walker {
         // Stop if the maximum number of commits has been shown
         if commits_shown >= count {
             break;
         }
 
-        // Load the commit object
-        let commit = Commit::load(&commit_hash, &PathBuf::from(&*OBJ_DIR))?;
-
-        // Print the commit details
-        print_commit(&commit_hash, &commit, commits_shown == 0);
+        // Show the author's canonical identity
+        let (commit_hash, mut commit) = commit?;
+        commit.author = mailmap.map(&commit.author);
 
-        // Move to the parent commit
-        current_commit_hash = commit.parent;
-        commits_shown += 1;
+        // Print the commit details, unless it's filtered out. The paths are
+        // checked first so that a followed rename is never skipped.
+        if change_filter.matches(&commit_hash, &commit)? && filter.matches(&commit) {
+            let note = notes.get(&commit_hash, &OBJ_DIR)?;
+            print_commit(&commit_hash, &commit, commit_hash == start, note.as_deref());
+            commits_shown += 1;
+        }
     }
 
     // If there are more commits than the specified count, indicate that
@@ -54,9 +305,120 @@ pub fn log_command(count: usize) -> Result<()> {
     Ok(())
 }
 
+/// Prints the history reachable from `start` through every parent, newest
+/// first, one line per commit with `oneline` and optionally with the graph
+fn print_compact_log(
+    start: &str,
+    options: &LogOptions,
+    filter: &LogFilter,
+    change_filter: &mut ChangeFilter,
+    notes: &Notes,
+    mailmap: &Mailmap,
+) -> Result<()> {
+    let mut commits = Vec::new();
+    let mut walker = CommitWalker::new(&OBJ_DIR);
+    walker.push(start)?;
+    for commit in walker {
+        if commits.len() >= options.count {
+            break;
+        }
+        let (hash, mut commit) = commit?;
+        commit.author = mailmap.map(&commit.author);
+        if change_filter.matches(&hash, &commit)? && filter.matches(&commit) {
+            commits.push((hash, commit));
+        }
+    }
+
+    let shown: HashSet<String> = commits.iter().map(|(hash, _)| hash.clone()).collect();
+    let mut graph = GraphRenderer::new();
+    let mut simplified = HashMap::new();
+    for (hash, commit) in &commits {
+        let subject = commit.message.lines().next().unwrap_or_default();
+
+        let rows = match options.graph {
+            // Rails connect the shown commits, skipping the filtered ones
+            true if filter.is_active() || change_filter.is_active() => {
+                let parents = shown_parents(commit, &shown, &mut simplified)?;
+                graph.next(hash, &parents)
+            }
+            true => graph.next(hash, &commit.parents()),
+            false => GraphRows::default(),
+        };
+        let rail = |row: &str| match row.is_empty() {
+            true => String::new(),
+            false => format!("{} ", row.yellow()),
+        };
+
+        for row in &rows.before {
+            println!("{}", row.yellow());
+        }
+        if options.oneline {
+            println!("{}{} {}", rail(&rows.commit), hash[..7].yellow(), subject);
+        } else {
+            let local_date: DateTime<Local> = commit.timestamp.with_timezone(&Local);
+            let padding = rail(&rows.padding);
+
+            println!("{}{} {}", rail(&rows.commit), "commit".yellow(), hash.yellow());
+            println!("{}{} {}", padding, "Author:".cyan(), commit.author);
+            println!(
+                "{}{} {}",
+                padding,
+                "Date:".cyan(),
+                local_date.format("%Y-%m-%d %H:%M:%S %z")
+            );
+            println!("{}", padding.trim_end());
+            for line in commit.message.lines() {
+                println!("{}    {}", padding, line);
+            }
+            println!("{}", padding.trim_end());
+            if let Some(note) = notes.get(hash, &OBJ_DIR)? {
+                println!("{}{}", padding, "Notes:".cyan());
+                for line in note.lines() {
+                    println!("{}    {}", padding, line);
+                }
+                println!("{}", padding.trim_end());
+            }
+        }
+        for row in &rows.after {
+            println!("{}", row.yellow());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the nearest ancestors of `commit` among the `shown` commits
+///
+/// `cache` remembers the answer for commits that aren't shown, which are
+/// often passed through by several children
+fn shown_parents(
+    commit: &Commit,
+    shown: &HashSet<String>,
+    cache: &mut HashMap<String, Vec<String>>,
+) -> Result<Vec<String>> {
+    let mut parents = Vec::new();
+    for parent in commit.parents() {
+        let nearest = if shown.contains(&parent) {
+            vec![parent]
+        } else if let Some(nearest) = cache.get(&parent) {
+            nearest.clone()
+        } else {
+            let hidden = load_commit(&parent, &OBJ_DIR)?;
+            let nearest = shown_parents(&hidden, shown, cache)?;
+            cache.insert(parent, nearest.clone());
+            nearest
+        };
+        for hash in nearest {
+            if !parents.contains(&hash) {
+                parents.push(hash);
+            }
+        }
+    }
+    Ok(parents)
+}
+
 /// Prints detailed information about a single commit.
 ///
-fn print_commit(hash: &str, commit: &Commit, is_latest: bool) {
+fn print_commit(hash: &str, commit: &Commit, is_latest: bool, note: Option<&str>) {
     let local_date: DateTime<Local> = commit.timestamp.with_timezone(&Local);
     let formatted_date = local_date.format("%Y-%m-%d %H:%M:%S %z");
 
@@ -73,12 +435,98 @@ fn print_commit(hash: &str, commit: &Commit, is_latest: bool) {
     );
     println!("{}  {} {}", "│".yellow(), "Author:".cyan(), commit.author);
     println!("{}  {} {}", "│".yellow(), "Date:".cyan(), formatted_date);
+    if let Some(status) = signature_status(commit) {
+        println!("{}  {} {}", "│".yellow(), "Signature:".cyan(), status);
+    }
     println!("{}", "│".yellow());
 
     // Print the commit message (line by line)
     for line in commit.message.lines() {
         println!("{}      {}", "│".yellow(), line);
     }
+    if let Some(note) = note {
+        println!("{}", "│".yellow());
+        println!("{}  {}", "│".yellow(), "Notes:".cyan());
+        for line in note.lines() {
+            println!("{}      {}", "│".yellow(), line);
+        }
+    }
 
     println!("{}\n", "└".yellow());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> LogOptions {
+        LogOptions {
+            count: 10,
+            revision: None,
+            graph: false,
+            oneline: false,
+            author: None,
+            since: None,
+            until: None,
+            grep: None,
+            paths: Vec::new(),
+            follow: false,
+            pickaxe: None,
+        }
+    }
+
+    #[test]
+    fn test_log_filter() -> Result<()> {
+        let mut commit = Commit::new(
+            "tree".to_string(),
+            None,
+            "Jane Doe <jane@example.com>".to_string(),
+            "Fix the parser\n\nCloses #12".to_string(),
+        );
+        commit.committer_timestamp = parse_date("2024-03-01")?;
+
+        assert!(!LogFilter::new(&options())?.is_active());
+
+        let filter = LogFilter::new(&LogOptions {
+            author: Some("jane@".to_string()),
+            since: Some("2024-01-01".to_string()),
+            grep: Some("#1[0-9]".to_string()),
+            ..options()
+        })?;
+        assert!(filter.matches(&commit));
+
+        let filter = LogFilter::new(&LogOptions {
+            until: Some("2024-02-01".to_string()),
+            ..options()
+        })?;
+        assert!(!filter.matches(&commit));
+
+        assert!(LogFilter::new(&LogOptions {
+            grep: Some("(".to_string()),
+            ..options()
+        })
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_filter() -> Result<()> {
+        let filter = ChangeFilter::new(&["./src/lib.rs".to_string()], true, None)?;
+        assert_eq!(filter.followed, Some(PathBuf::from("src/lib.rs")));
+        assert!(filter.is_active());
+
+        assert!(!ChangeFilter::new(&[], false, None)?.is_active());
+        assert!(ChangeFilter::new(&[], false, Some("fn main".to_string()))?.is_active());
+        assert!(ChangeFilter::new(&[], true, None).is_err());
+        assert!(ChangeFilter::new(&["a".to_string(), "b".to_string()], true, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_occurrences() {
+        assert_eq!(count_occurrences(b"fn a() {}\nfn b() {}", b"fn "), 2);
+        assert_eq!(count_occurrences(b"aaaa", b"aa"), 2);
+        assert_eq!(count_occurrences(b"abc", b"abcd"), 0);
+        assert_eq!(count_occurrences(b"abc", b""), 0);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::commands::merge::rerere::clear_pending;
+use crate::storage::merge::{FileConflict, MergedFile};
+use crate::storage::utils::VOX_DIR;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// File of `.vox` listing the paths a merge, cherry-pick, revert or rebase
+/// left conflicting, one `<base> <ours> <theirs> <path>` line each, a side
+/// without the file written as `-`
+const CONFLICTS_FILE: &str = "MERGE_CONFLICTS";
+
+/// A path left conflicting, with the blob each side of the merge had there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+impl From<&FileConflict> for Conflict {
+    fn from(conflict: &FileConflict) -> Self {
+        let hash = |file: &Option<MergedFile>| file.as_ref().map(|file| file.hash.clone());
+        Self {
+            path: conflict.path.clone(),
+            base: hash(&conflict.base),
+            ours: hash(&conflict.ours),
+            theirs: hash(&conflict.theirs),
+        }
+    }
+}
+
+/// Records the conflicts of the merge just checked out, replacing those of
+/// an earlier one
+pub fn record_conflicts(conflicts: &[FileConflict]) -> Result<()> {
+    let conflicts: Vec<Conflict> = conflicts.iter().map(Conflict::from).collect();
+    write_conflicts(&conflicts)
+}
+
+/// Returns the conflicts left to resolve, none if no merge stopped on one
+pub fn read_conflicts() -> Result<Vec<Conflict>> {
+    let path = VOX_DIR.join(CONFLICTS_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let conflicts = fs::read_to_string(&path).context("Failed to read the merge conflicts")?;
+    parse_conflicts(&conflicts)
+}
+
+/// Forgets the conflicts of `paths`, which were resolved
+pub fn mark_resolved(paths: &[PathBuf]) -> Result<()> {
+    let mut conflicts = read_conflicts()?;
+    conflicts.retain(|conflict| !paths.contains(&conflict.path));
+    write_conflicts(&conflicts)
+}
+
+/// Forgets every conflict, once the merge is committed or given up
+pub fn clear_conflicts() -> Result<()> {
+    clear_pending()?;
+    write_conflicts(&[])
+}
+
+fn write_conflicts(conflicts: &[Conflict]) -> Result<()> {
+    let path = VOX_DIR.join(CONFLICTS_FILE);
+    if conflicts.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove the merge conflicts")?;
+        }
+        return Ok(());
+    }
+    fs::write(&path, format_conflicts(conflicts)).context("Failed to write the merge conflicts")
+}
+
+/// Writes conflicts in the format [`parse_conflicts`] reads
+pub fn format_conflicts(conflicts: &[Conflict]) -> String {
+    let side = |hash: &Option<String>| hash.clone().unwrap_or_else(|| String::from("-"));
+    conflicts
+        .iter()
+        .map(|conflict| {
+            format!(
+                "{} {} {} {}\n",
+                side(&conflict.base),
+                side(&conflict.ours),
+                side(&conflict.theirs),
+                conflict.path.display()
+            )
+        })
+        .collect()
+}
+
+/// Parses the list of conflicts, ignoring blank lines
+pub fn parse_conflicts(conflicts: &str) -> Result<Vec<Conflict>> {
+    let side = |hash: &str| (hash != "-").then(|| hash.to_string());
+    let mut parsed = Vec::new();
+    for line in conflicts.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.splitn(4, ' ').collect();
+        let [base, ours, theirs, path] = fields[..] else {
+            bail!("Invalid conflict line '{}'", line);
+        };
+        parsed.push(Conflict {
+            path: PathBuf::from(path),
+            base: side(base),
+            ours: side(ours),
+            theirs: side(theirs),
+        });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicts_roundtrip() -> Result<()> {
+        let conflicts = vec![
+            Conflict {
+                path: PathBuf::from("src/main.rs"),
+                base: Some("a".repeat(40)),
+                ours: Some("b".repeat(40)),
+                theirs: Some("c".repeat(40)),
+            },
+            Conflict {
+                path: PathBuf::from("docs/a file.md"),
+                base: None,
+                ours: Some("d".repeat(40)),
+                theirs: None,
+            },
+        ];
+        assert_eq!(parse_conflicts(&format_conflicts(&conflicts))?, conflicts);
+        assert!(parse_conflicts("- - path").is_err());
+        Ok(())
+    }
+}
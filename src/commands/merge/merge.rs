@@ -0,0 +1,279 @@
+use crate::commands::branch::checkout::restore_blob;
+use crate::commands::commit::commit::get_current_commit;
+use crate::commands::config::commands::conflict_style;
+use crate::commands::index::index::Index;
+use crate::commands::merge::conflicts::{clear_conflicts, record_conflicts};
+use crate::commands::merge::rerere::replay_resolutions;
+use crate::commands::restore::restore::{index_entry, restore_paths};
+use crate::commands::status::status::get_status;
+use crate::error::VoxError;
+use crate::storage::merge::{
+    merge_trees, MergeFavor, MergeLabels, MergeOptions, MergeStrategy, TreeMerge,
+};
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::{is_ancestor, merge_bases};
+use crate::storage::objects::Loadable;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::revision::{abbreviate, resolve_commit};
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, PERM_GITLINK, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Pseudo-ref of `.vox` naming the commit HEAD pointed to before the last merge
+const ORIG_HEAD: &str = "ORIG_HEAD";
+/// Copy of the index taken before a merge that stopped on conflicts, its
+/// presence meaning the merge is in progress
+const MERGE_INDEX: &str = "MERGE_INDEX";
+
+/// What `vox merge` should do
+#[derive(Debug)]
+pub enum MergeAction {
+    /// Merge `revisions`, see [`merge_command`]
+    Start {
+        revisions: Vec<String>,
+        squash: bool,
+        no_commit: bool,
+        strategy: MergeStrategy,
+        favor: Option<MergeFavor>,
+    },
+    /// Go back to the index and working tree of before a merge that stopped
+    /// on conflicts
+    Abort,
+}
+
+/// Merges the changes made on a revision since it forked from HEAD, or
+/// aborts the merge that stopped on conflicts
+///
+/// Only squash merges are supported: the combined changes are applied to
+/// the index and the working tree and left to be committed as a single
+/// change, since a vox commit can't record the merged branch as a second
+/// parent. Files both sides changed are merged line by line, conflicts are
+/// written into the working tree in the `merge.conflictStyle` style while
+/// the index keeps our version.
+///
+/// HEAD is saved as `ORIG_HEAD` first. When the merge conflicts, the index
+/// it started from is kept too, until the result is committed or
+/// `vox merge --abort` puts it back.
+///
+/// # Arguments of [`MergeAction::Start`]
+/// - `revisions`: the branch or commit to merge. Octopus merges of several
+///   are refused, their commit would need a parent per branch
+/// - `squash`: must be set, merges recording both parents aren't supported
+/// - `no_commit`: stops before committing, which squash merges always do.
+///   Without `squash` it's refused, the merge commit it holds back is one
+///   vox can't make
+/// - `strategy`: `ours` keeps the index and the working tree as they are,
+///   their changes are all dropped
+/// - `favor`: resolves conflicting regions with one side (`-X ours`,
+///   `-X theirs`) or both (`-X union`) instead of writing conflict markers
+///
+pub fn merge_command(action: MergeAction) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+    let in_progress = VOX_DIR.join(MERGE_INDEX).exists();
+    let (revisions, squash, no_commit, strategy, favor) = match action {
+        MergeAction::Abort if !in_progress => bail!("There is no merge to abort"),
+        MergeAction::Abort => return abort_merge(),
+        MergeAction::Start { .. } if in_progress => {
+            bail!("A merge is in progress, commit the result or use 'vox merge --abort'")
+        }
+        MergeAction::Start {
+            revisions,
+            squash,
+            no_commit,
+            strategy,
+            favor,
+        } => (revisions, squash, no_commit, strategy, favor),
+    };
+    let [revision] = revisions.as_slice() else {
+        bail!(
+            "Octopus merges aren't supported: their commit has a parent per merged branch \
+             and vox commits have a single parent. Merge the {} branches one at a time",
+            revisions.len()
+        );
+    };
+    let revision = revision.as_str();
+    if no_commit && !squash {
+        bail!(
+            "--no-commit stops before the merge commit, which would have '{}' as a second \
+             parent and vox commits have a single one. Use --squash, it never commits",
+            revision
+        );
+    }
+    if !squash {
+        bail!(
+            "Merge commits aren't supported, vox commits have a single parent. \
+             Use --squash to apply the changes of '{}' as one commit",
+            revision
+        );
+    }
+
+    let head = get_current_commit()?.context("Cannot merge into a branch without commits")?;
+    let theirs = resolve_commit(&VOX_DIR, revision)
+        .with_context(|| format!("'{}' doesn't point to a commit", revision))?;
+    if is_ancestor(&theirs, &head, &OBJ_DIR)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    // Of several best common ancestors, the most recent one is used
+    let base = merge_bases(&head, &theirs, &OBJ_DIR)?
+        .into_iter()
+        .next()
+        .context("Refusing to merge unrelated histories")?;
+    ensure_clean_index(&head)?;
+    fs::write(VOX_DIR.join(ORIG_HEAD), format!("{}\n", head))
+        .context("Failed to write ORIG_HEAD")?;
+    if strategy == MergeStrategy::Ours {
+        println!("Squash commit -- not updating HEAD");
+        println!("Merged '{}' with the ours strategy, the tree of HEAD is kept", revision);
+        return Ok(());
+    }
+    match INDEX_FILE.exists() {
+        true => fs::copy(&*INDEX_FILE, VOX_DIR.join(MERGE_INDEX))
+            .map(drop)
+            .context("Failed to save the index")?,
+        false => Index::new().write_to_file(&VOX_DIR.join(MERGE_INDEX))?,
+    }
+
+    let base_label = abbreviate(&OBJ_DIR, &base, 7)?;
+    let labels = MergeLabels {
+        ours: "HEAD",
+        base: &base_label,
+        theirs: revision,
+    };
+    let options = MergeOptions {
+        style: conflict_style(),
+        favor,
+    };
+    let mut merge = merge_trees(
+        Some(&Commit::load(&base, &OBJ_DIR)?.tree),
+        &Commit::load(&head, &OBJ_DIR)?.tree,
+        &Commit::load(&theirs, &OBJ_DIR)?.tree,
+        &labels,
+        &options,
+        &OBJ_DIR,
+    )?;
+    checkout_merge(&mut merge)?;
+
+    for path in &merge.merged {
+        println!("Auto-merging {}", path.display());
+    }
+    for conflict in &merge.conflicts {
+        println!("{}", conflict.to_string().red());
+    }
+    println!("Squash commit -- not updating HEAD");
+    if !merge.is_clean() {
+        bail!(VoxError::MergeConflict(
+            "Automatic merge failed; fix conflicts and then commit the result".to_string()
+        ));
+    }
+    clear_merge_state()?;
+    println!("Automatic merge went well; stopped before committing as requested");
+    Ok(())
+}
+
+/// Puts back the index and the working tree of before the merge
+///
+/// The working tree is restored to `ORIG_HEAD`, which the index matched when
+/// the merge started, removing the files the merge added.
+fn abort_merge() -> Result<()> {
+    let orig_head =
+        fs::read_to_string(VOX_DIR.join(ORIG_HEAD)).context("Failed to read ORIG_HEAD")?;
+    let orig_head = orig_head.trim();
+    restore_paths(&[PathBuf::new()], Some(orig_head), true, true)?;
+    fs::rename(VOX_DIR.join(MERGE_INDEX), &*INDEX_FILE).context("Failed to restore the index")?;
+    clear_conflicts()?;
+    let short = abbreviate(&OBJ_DIR, orig_head, 7)?;
+    println!("Merge aborted, the index and working tree are back to {}", short);
+    Ok(())
+}
+
+/// Forgets the index saved by a merge that stopped on conflicts, once its
+/// result is committed
+pub fn clear_merge_state() -> Result<()> {
+    let saved = VOX_DIR.join(MERGE_INDEX);
+    if saved.exists() {
+        fs::remove_file(&saved).context("Failed to remove the saved index")?;
+    }
+    Ok(())
+}
+
+/// Refuses to merge over changes that aren't committed yet
+///
+/// Merging only touches tracked files, but would mix the merged changes
+/// with the uncommitted ones in the index and the working tree.
+pub fn ensure_clean_index(head: &str) -> Result<()> {
+    let (_added, modified, deleted, _untracked) =
+        get_status(Path::new("."), &Pathspec::default())?;
+    let mut index = read_index()?;
+    let staged = index.write_tree(&OBJ_DIR)? != Commit::load(head, &OBJ_DIR)?.tree;
+    if staged || !modified.is_empty() || !deleted.is_empty() {
+        bail!("You have uncommitted changes. Commit them first, the merge would overwrite them");
+    }
+    Ok(())
+}
+
+/// Writes the result of a tree merge to the index and the working tree
+///
+/// The index is expected to match our side of the merge. Files of the merged
+/// tree replace those that differ, files it doesn't have are removed, and
+/// the working tree copy of conflicting files is left to be resolved, unless
+/// a resolution recorded by an earlier merge resolves it. The conflicts left
+/// are recorded for `vox mergetool`.
+pub fn checkout_merge(merge: &mut TreeMerge) -> Result<()> {
+    let mut index = read_index()?;
+
+    // Untracked files are never overwritten
+    for path in merge.files.keys() {
+        if index.get_entry(path).is_none() && fs::symlink_metadata(path).is_ok() {
+            bail!(
+                "The untracked file {} would be overwritten by the merge, move or remove it first",
+                path.display()
+            );
+        }
+    }
+
+    let tracked: Vec<_> = index.get_entries().keys().cloned().collect();
+    for path in tracked.iter().filter(|path| !merge.files.contains_key(*path)) {
+        if fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        index.remove_entry(path);
+    }
+
+    for (path, file) in &merge.files {
+        let unchanged = index.get_entry(path).is_some_and(|entry| {
+            entry.hash.to_hex() == file.hash
+                && format!("{:o}", entry.stat().tree_mode()) == file.mode
+        });
+        if unchanged {
+            continue;
+        }
+        // Submodules are only populated by `vox submodule update`
+        if file.mode != PERM_GITLINK {
+            restore_blob(&file.hash, path, &file.mode)?;
+        }
+        index.add_entry(index_entry(path, &file.hash, &file.mode)?);
+    }
+
+    for conflict in &merge.conflicts {
+        if conflict.worktree.mode != PERM_GITLINK {
+            restore_blob(&conflict.worktree.hash, &conflict.path, &conflict.worktree.mode)?;
+        }
+    }
+
+    replay_resolutions(merge, &mut index)?;
+    record_conflicts(&merge.conflicts)?;
+    index.write_to_file(&INDEX_FILE)
+}
+
+fn read_index() -> Result<Index> {
+    let mut index = Index::new();
+    if INDEX_FILE.exists() {
+        index.read_from_file(&INDEX_FILE)?;
+    }
+    Ok(index)
+}
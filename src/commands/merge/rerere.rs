@@ -0,0 +1,225 @@
+use crate::commands::branch::checkout::restore_blob;
+use crate::commands::index::index::Index;
+use crate::commands::restore::restore::index_entry;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::merge::{ConflictKind, MergedFile, TreeMerge};
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::Storable;
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, VOX_DIR};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+/// Directory of `.vox` keeping the resolutions, one directory per conflict
+/// named by its id, holding the `preimage` with the conflict markers and the
+/// `postimage` resolving it
+const RR_CACHE: &str = "rr-cache";
+/// File of `.vox` listing the conflicts of the merge in progress whose
+/// resolution is recorded once it's committed, one `<id> <path>` line each
+const MERGE_RR: &str = "MERGE_RR";
+
+/// Which part of a conflicting region a line belongs to
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Outside,
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Returns the marker character a conflict marker line starts with, e.g.
+/// `<` for `<<<<<<< HEAD`
+fn marker(line: &str) -> Option<char> {
+    let line = line.trim_end_matches(['\n', '\r']);
+    let first = line.chars().next().filter(|c| "<|=>".contains(*c))?;
+    let (head, rest) = line.split_at(line.len().min(7));
+    let is_marker = head.len() == 7
+        && head.chars().all(|c| c == first)
+        && match first {
+            '=' => rest.is_empty(),
+            _ => rest.is_empty() || rest.starts_with(' '),
+        };
+    is_marker.then_some(first)
+}
+
+/// Rewrites a file with conflict markers so the same conflict always reads
+/// the same: labels and base versions are dropped and the two sides of each
+/// region are put in order, a conflict met while rebasing matching the one
+/// met while merging the other way around
+///
+/// # Returns
+///
+/// The normalized text, or `None` if it has no complete conflicting region
+fn normalize(text: &str) -> Option<String> {
+    let mut normalized = String::new();
+    let (mut ours, mut theirs) = (String::new(), String::new());
+    let mut section = Section::Outside;
+    let mut found = false;
+
+    for line in text.split_inclusive('\n') {
+        match (section, marker(line)) {
+            (Section::Outside, Some('<')) => section = Section::Ours,
+            (Section::Ours, Some('|')) => section = Section::Base,
+            (Section::Ours | Section::Base, Some('=')) => section = Section::Theirs,
+            (Section::Theirs, Some('>')) => {
+                let (first, second) = match ours <= theirs {
+                    true => (&ours, &theirs),
+                    false => (&theirs, &ours),
+                };
+                normalized.push_str(&format!("<<<<<<<\n{}=======\n{}>>>>>>>\n", first, second));
+                ours.clear();
+                theirs.clear();
+                section = Section::Outside;
+                found = true;
+            }
+            (Section::Outside, _) => normalized.push_str(line),
+            (Section::Ours, _) => ours.push_str(line),
+            (Section::Base, _) => {}
+            (Section::Theirs, _) => theirs.push_str(line),
+        }
+    }
+    (found && section == Section::Outside).then_some(normalized)
+}
+
+/// Names a conflict by the hash of its normalized text, `None` for a text
+/// without conflict markers
+pub fn conflict_id(text: &str) -> Option<String> {
+    let normalized = normalize(text)?;
+    Some(ObjectFormat::Sha1.digest(normalized.as_bytes()).to_hex())
+}
+
+/// Resolves the conflicting files of a merge being checked out that were
+/// resolved before, and remembers the others to record their resolution
+/// once it's committed
+///
+/// A file resolved from `.vox/rr-cache` gets the recorded resolution in the
+/// work tree and the index, and isn't counted as a conflict anymore. Only
+/// conflicts inside text files are recorded.
+pub fn replay_resolutions(merge: &mut TreeMerge, index: &mut Index) -> Result<()> {
+    let mut pending = Vec::new();
+    let mut remaining = Vec::new();
+
+    for conflict in std::mem::take(&mut merge.conflicts) {
+        let text = match conflict.kind {
+            ConflictKind::Content => Blob::load(&conflict.worktree.hash, &OBJ_DIR)?.data,
+            _ => Vec::new(),
+        };
+        let text = String::from_utf8(text).unwrap_or_default();
+        let Some(id) = conflict_id(&text) else {
+            remaining.push(conflict);
+            continue;
+        };
+
+        let dir = VOX_DIR.join(RR_CACHE).join(&id);
+        let postimage = dir.join("postimage");
+        if postimage.exists() {
+            let data = fs::read(&postimage).context("Failed to read a recorded resolution")?;
+            let resolved = MergedFile {
+                mode: conflict.worktree.mode.clone(),
+                hash: Blob { data }.save(&OBJ_DIR)?,
+            };
+            restore_blob(&resolved.hash, &conflict.path, &resolved.mode)?;
+            index.add_entry(index_entry(&conflict.path, &resolved.hash, &resolved.mode)?);
+            println!(
+                "Resolved '{}' using previous resolution.",
+                conflict.path.display()
+            );
+            merge.files.insert(conflict.path, resolved);
+            continue;
+        }
+
+        fs::create_dir_all(&dir).context("Failed to create the resolution cache")?;
+        fs::write(dir.join("preimage"), text).context("Failed to record a conflict")?;
+        println!("Recorded preimage for '{}'", conflict.path.display());
+        pending.push((id, conflict.path.clone()));
+        remaining.push(conflict);
+    }
+
+    merge.conflicts = remaining;
+    write_pending(&pending)
+}
+
+/// Records how the conflicts of the merge being committed were resolved,
+/// from the staged version of their files
+///
+/// Files still holding conflict markers aren't recorded.
+pub fn record_resolutions() -> Result<()> {
+    let pending = read_pending()?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let mut index = Index::new();
+    index.read_from_file(&INDEX_FILE)?;
+
+    for (id, path) in pending {
+        let Some(entry) = index.get_entry(&path) else {
+            continue;
+        };
+        let data = Blob::load(&entry.hash.to_hex(), &OBJ_DIR)?.data;
+        let resolved = std::str::from_utf8(&data).is_ok_and(|text| normalize(text).is_none());
+        if resolved {
+            let postimage = VOX_DIR.join(RR_CACHE).join(id).join("postimage");
+            fs::write(postimage, data).context("Failed to record a resolution")?;
+            println!("Recorded resolution for '{}'.", path.display());
+        }
+    }
+    clear_pending()
+}
+
+/// Forgets the conflicts waiting for their resolution to be recorded
+pub fn clear_pending() -> Result<()> {
+    write_pending(&[])
+}
+
+fn read_pending() -> Result<Vec<(String, PathBuf)>> {
+    let path = VOX_DIR.join(MERGE_RR);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let pending = fs::read_to_string(&path).context("Failed to read MERGE_RR")?;
+    Ok(pending
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(id, path)| (id.to_string(), PathBuf::from(path)))
+        .collect())
+}
+
+fn write_pending(pending: &[(String, PathBuf)]) -> Result<()> {
+    let path = VOX_DIR.join(MERGE_RR);
+    if pending.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove MERGE_RR")?;
+        }
+        return Ok(());
+    }
+    let lines: String = pending
+        .iter()
+        .map(|(id, path)| format!("{} {}\n", id, path.display()))
+        .collect();
+    fs::write(&path, lines).context("Failed to write MERGE_RR")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflict_id() {
+        let merged = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> 1234567... pick\nc\n";
+        let rebased = "a\n<<<<<<< onto\ntheirs\n||||||| base\nb\n=======\nours\n>>>>>>> HEAD\nc\n";
+        assert_eq!(
+            normalize(merged).as_deref(),
+            Some("a\n<<<<<<<\nours\n=======\ntheirs\n>>>>>>>\nc\n")
+        );
+        assert_eq!(conflict_id(merged), conflict_id(rebased));
+        assert_ne!(
+            conflict_id(merged),
+            conflict_id(&merged.replace("c\n", "d\n"))
+        );
+
+        // Resolved files, and lines that only look like markers, have none
+        assert_eq!(conflict_id("a\nours\nc\n"), None);
+        assert_eq!(conflict_id("<<<<<<<< not a marker\n"), None);
+        assert_eq!(conflict_id("<<<<<<< HEAD\nunterminated\n=======\n"), None);
+    }
+}
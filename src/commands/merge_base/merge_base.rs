@@ -0,0 +1,47 @@
+use crate::error::VoxError;
+use crate::storage::objects::graph::{is_ancestor, merge_bases};
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::Result;
+
+/// Prints the best common ancestor of two commits
+///
+/// # Arguments
+/// - `first`, `second`: the commits, as revisions
+/// - `all`: print every best common ancestor instead of only one
+/// - `check_ancestor`: print nothing and exit with status 0 if `first` is an
+///   ancestor of `second`, or 1 if it isn't
+///
+pub fn merge_base_command(
+    first: &str,
+    second: &str,
+    all: bool,
+    check_ancestor: bool,
+) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let first = resolve_commit(&VOX_DIR, first)?;
+    let second = resolve_commit(&VOX_DIR, second)?;
+
+    if check_ancestor {
+        // Scripts branch on the exit status, so "no" isn't reported as an error
+        if !is_ancestor(&first, &second, &OBJ_DIR)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let bases = merge_bases(&first, &second, &OBJ_DIR)?;
+    if bases.is_empty() {
+        // Like --is-ancestor, unrelated histories are signalled by the exit status only
+        std::process::exit(1);
+    }
+
+    let shown = if all { bases.len() } else { 1 };
+    for base in bases.iter().take(shown) {
+        println!("{}", base);
+    }
+    Ok(())
+}
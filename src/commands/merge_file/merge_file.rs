@@ -0,0 +1,81 @@
+use crate::commands::config::commands::conflict_style;
+use crate::storage::merge::{merge_text, ConflictStyle, MergeFavor, MergeLabels, MergeOptions};
+use crate::storage::patch::is_binary;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct MergeFileOptions {
+    /// Print the result instead of writing it to the current file
+    pub stdout: bool,
+    /// Conflict style, `merge.conflictStyle` if not given
+    pub style: Option<ConflictStyle>,
+    /// Resolve conflicts in favor of a side instead of leaving markers
+    pub favor: Option<MergeFavor>,
+    /// Names for the current, base and other version in the conflict
+    /// markers, the file names if missing
+    pub labels: Vec<String>,
+}
+
+/// Merges the changes from `base` to `other` into `current`
+///
+/// Like `git merge-file`, conflicts are left in the result between conflict
+/// markers and the command exits with the number of conflicts, 0 meaning
+/// the merge is clean.
+pub fn merge_file_command(
+    current: &Path,
+    base: &Path,
+    other: &Path,
+    options: &MergeFileOptions,
+) -> Result<()> {
+    if options.labels.len() > 3 {
+        bail!("Too many labels: at most one per file can be given");
+    }
+
+    let ours = read_text(current)?;
+    let base_text = read_text(base)?;
+    let theirs = read_text(other)?;
+
+    let names: Vec<String> = [current, base, other]
+        .iter()
+        .enumerate()
+        .map(|(i, path)| match options.labels.get(i) {
+            Some(label) => label.clone(),
+            None => path.display().to_string(),
+        })
+        .collect();
+    let labels = MergeLabels {
+        ours: &names[0],
+        base: &names[1],
+        theirs: &names[2],
+    };
+    let merge_options = MergeOptions {
+        style: options.style.unwrap_or_else(conflict_style),
+        favor: options.favor,
+    };
+    let merged = merge_text(&base_text, &ours, &theirs, &labels, &merge_options);
+
+    if options.stdout {
+        std::io::stdout().write_all(merged.content.as_bytes())?;
+    } else {
+        fs::write(current, &merged.content)
+            .with_context(|| format!("Failed to write {}", current.display()))?;
+    }
+
+    if !merged.is_clean() {
+        eprintln!("warning: conflicts in {}: {}", current.display(), merged.conflicts);
+        // Exit statuses above 127 are taken for signals
+        std::process::exit(merged.conflicts.min(127) as i32);
+    }
+    Ok(())
+}
+
+fn read_text(path: &Path) -> Result<String> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if is_binary(&data) {
+        bail!("Cannot merge binary file {}", path.display());
+    }
+    String::from_utf8(data).with_context(|| format!("{} is not valid UTF-8", path.display()))
+}
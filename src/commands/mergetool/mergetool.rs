@@ -0,0 +1,126 @@
+use crate::commands::add::add::add_command;
+use crate::commands::config::commands::merge_tool;
+use crate::commands::index::index::Index;
+use crate::commands::merge::conflicts::{mark_resolved, read_conflicts, Conflict};
+use crate::error::VoxError;
+use crate::storage::objects::blob::Blob;
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Resolves the conflicts a merge, cherry-pick, revert or rebase stopped on
+/// with a three-way merge tool, one path at a time
+///
+/// The base, our and their version of each path are written to temporary
+/// files, a side without the file as an empty one. The tool is run by the
+/// shell with `BASE`, `LOCAL`, `REMOTE` and `MERGED` set to their paths and
+/// that of the conflicting file, e.g. `meld "$LOCAL" "$MERGED" "$REMOTE"`.
+/// When it exits successfully, the merged file is staged, or its removal if
+/// the tool deleted it, and the path is marked resolved. Otherwise the path
+/// is left conflicting and the other paths are still merged.
+///
+/// # Arguments
+/// - `tool`: the command to run, `merge.tool` if not given
+/// - `paths`: the conflicting paths to resolve, all of them if empty
+pub fn mergetool_command(tool: Option<&str>, paths: &[PathBuf]) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+    let tool = match tool {
+        Some(tool) => tool.to_string(),
+        None => merge_tool().context(
+            "No merge tool configured, pass --tool or set one with 'vox config set-merge-tool'",
+        )?,
+    };
+
+    let conflicts = read_conflicts()?;
+    if conflicts.is_empty() {
+        println!("No files need merging");
+        return Ok(());
+    }
+    for path in paths {
+        if !conflicts.iter().any(|conflict| &conflict.path == path) {
+            bail!("{} has no conflict to resolve", path.display());
+        }
+    }
+
+    let mut unresolved = Vec::new();
+    for conflict in &conflicts {
+        if !paths.is_empty() && !paths.contains(&conflict.path) {
+            continue;
+        }
+        println!("Merging {}", conflict.path.display());
+        if run_tool(&tool, conflict)? {
+            stage_resolution(&conflict.path)?;
+            mark_resolved(std::slice::from_ref(&conflict.path))?;
+        } else {
+            let path = conflict.path.display();
+            let message = format!("The merge tool failed, {} is left conflicting", path);
+            println!("{}", message.yellow());
+            unresolved.push(conflict.path.display().to_string());
+        }
+    }
+
+    if !unresolved.is_empty() {
+        bail!(VoxError::MergeConflict(format!(
+            "The merge tool failed on {}",
+            unresolved.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Runs the merge tool on a conflicting path
+///
+/// # Returns
+///
+/// Whether the tool exited successfully
+fn run_tool(tool: &str, conflict: &Conflict) -> Result<bool> {
+    let dir = tempfile::tempdir().context("Failed to create a directory for the merge tool")?;
+    let name = conflict
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let side_file = |side: &str, hash: &Option<String>| -> Result<PathBuf> {
+        // The extension is kept for tools highlighting the syntax
+        let path = dir.path().join(match name.rsplit_once('.') {
+            Some((stem, extension)) => format!("{}_{}.{}", stem, side, extension),
+            None => format!("{}_{}", name, side),
+        });
+        let data = match hash {
+            Some(hash) => Blob::load(hash, &OBJ_DIR)?.data,
+            None => Vec::new(),
+        };
+        fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    };
+    let base = side_file("BASE", &conflict.base)?;
+    let local = side_file("LOCAL", &conflict.ours)?;
+    let remote = side_file("REMOTE", &conflict.theirs)?;
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(tool)
+        .env("BASE", &base)
+        .env("LOCAL", &local)
+        .env("REMOTE", &remote)
+        .env("MERGED", &conflict.path)
+        .status()
+        .with_context(|| format!("Failed to run the merge tool '{}'", tool))?;
+    Ok(status.success())
+}
+
+/// Stages the merged file, or its removal if there's none left
+fn stage_resolution(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        return add_command(&[path.to_path_buf()]);
+    }
+    let mut index = Index::new();
+    index.read_from_file(&INDEX_FILE)?;
+    index.remove_entry(path);
+    index.write_to_file(&INDEX_FILE)
+}
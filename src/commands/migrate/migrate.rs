@@ -0,0 +1,265 @@
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::encryption::{read_object_file, write_object_file};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::migrate::{migrate_reflogs, write_object_map, Migration, OBJECT_MAP_FILE};
+use crate::storage::objects::compression::{decompress, Codec, ObjectEncoder};
+use crate::storage::objects::graph::repository_roots;
+use crate::storage::objects::{alternates, ObjectStorage};
+use crate::storage::refs::list_all_refs;
+use crate::storage::replace::REPLACE_REFS;
+use crate::storage::repo_config::RepoConfig;
+use crate::storage::shallow::read_shallow;
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Directory of the `.vox` directory the migrated objects are written to
+/// before they replace the old ones
+const MIGRATE_DIR: &str = "migrate";
+
+/// Prefix of the refs holding notes
+const NOTES_DIR: &str = "refs/notes/";
+
+/// State of the operations that can be interrupted, naming commits
+const OPERATION_STATE: [&str; 4] = ["rebase-merge", "rebase-apply", "sequencer", "BISECT_START"];
+
+/// Rewrites every object of the repository under the object format `format`
+///
+/// The loose objects and everything HEAD, the refs, the reflogs and the index
+/// reach are migrated. The renamed refs, reflogs, HEAD and index are staged
+/// next to the new objects, and all of them replace the old ones at the end,
+/// so a failure leaves the repository as it was. The old name of every object
+/// is mapped to its new one in `.vox/object-map`.
+pub fn migrate_command(format: ObjectFormat) -> Result<()> {
+    if !VOX_DIR.exists() {
+        bail!(VoxError::NotARepository);
+    }
+    let config = RepoConfig::load(&VOX_DIR)?;
+    if config.object_format() == format {
+        bail!("The repository already uses {}", format);
+    }
+    if OPERATION_STATE.iter().any(|state| VOX_DIR.join(state).exists()) {
+        bail!("An operation is in progress, finish or abort it before migrating");
+    }
+    if !alternates(&OBJ_DIR)?.is_empty() {
+        bail!("The repository borrows objects from alternates, which can't be migrated");
+    }
+    if !read_shallow(&VOX_DIR)?.is_empty() {
+        bail!("The repository is shallow, the missing history can't be migrated");
+    }
+
+    let staging = VOX_DIR.join(MIGRATE_DIR);
+    if staging.exists() {
+        fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    }
+    fs::create_dir_all(&staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    config.clone().with_object_format(format).save(&staging)?;
+    let mut migration = Migration::new(&OBJ_DIR, &staging.join("objects"))?;
+
+    // Notes trees name their entries after the objects they annotate
+    for (name, hash) in list_all_refs(&VOX_DIR)? {
+        if name.starts_with(NOTES_DIR) || name.contains(&format!("/{}", NOTES_DIR)) {
+            migration.add_notes(&hash)?;
+        }
+    }
+
+    let mut roots = repository_roots(&VOX_DIR)?;
+    roots.extend(
+        ObjectStorage::at(&OBJ_DIR)
+            .loose_objects()?
+            .into_iter()
+            .map(|object| object.hash),
+    );
+    for root in roots {
+        // Reflogs may still mention pruned objects
+        if migration.has_object(&root) {
+            migration.migrate(&root)?;
+        }
+    }
+    let rename = |hash: &str| {
+        migration
+            .name(hash)
+            .map(str::to_string)
+            .with_context(|| format!("Object {} is missing", hash))
+    };
+
+    // Nothing is renamed until every object is migrated
+    let mut index = None;
+    if INDEX_FILE.exists() {
+        let mut staged = Index::new();
+        staged.read_from_file(&INDEX_FILE)?;
+        for entry in staged.entries.values_mut() {
+            entry.hash = rename(&entry.hash.to_hex())?.parse()?;
+        }
+        // The extensions cache old names, they're rebuilt as needed
+        staged.cache_tree = Default::default();
+        staged.untracked_cache = Default::default();
+        staged.unknown_extensions.clear();
+        index = Some(staged);
+    }
+    let mut renamed_logs = Vec::new();
+
+    for (name, hash) in list_all_refs(&VOX_DIR)? {
+        // Symbolic refs name other refs, which keep their names
+        let hash = match hash.starts_with("ref: ") {
+            true => hash,
+            false => rename(&hash)?,
+        };
+        let new_name = match name.split_once(REPLACE_REFS) {
+            Some((namespace, replaced)) => {
+                format!("{}{}{}", namespace, REPLACE_REFS, rename(replaced)?)
+            }
+            None => name.clone(),
+        };
+        stage_file(&staging.join(&new_name), &format!("{}\n", hash))?;
+        if new_name != name {
+            renamed_logs.push((name, new_name));
+        }
+    }
+    for file in ["HEAD", "ORIG_HEAD"] {
+        let Ok(content) = fs::read_to_string(VOX_DIR.join(file)) else {
+            continue;
+        };
+        let content = content.trim();
+        let content = match content.starts_with("ref: ") {
+            true => content.to_string(),
+            false => match migration.name(content) {
+                Some(hash) => hash.to_string(),
+                None if file == "HEAD" => {
+                    bail!("HEAD is detached at {}, which can't be migrated", content)
+                }
+                // ORIG_HEAD is only a hint, it goes if its commit is gone
+                None => continue,
+            },
+        };
+        stage_file(&staging.join(file), &format!("{}\n", content))?;
+    }
+
+    let logs = VOX_DIR.join("logs");
+    if logs.exists() {
+        for entry in WalkDir::new(&logs).min_depth(1) {
+            let entry = entry.context("Failed to read logs directory")?;
+            if entry.file_type().is_file() {
+                let name = entry.path().strip_prefix(&*VOX_DIR)?;
+                let staged = staging.join(name);
+                fs::create_dir_all(staged.parent().context("Invalid log path")?)?;
+                fs::copy(entry.path(), &staged)
+                    .with_context(|| format!("Failed to stage {}", name.display()))?;
+            }
+        }
+        for (name, new_name) in renamed_logs {
+            let log = staging.join("logs").join(&name);
+            if log.exists() {
+                fs::rename(&log, staging.join("logs").join(&new_name))?;
+            }
+        }
+        migrate_reflogs(&staging, &migration, format)?;
+    }
+    if let Some(index) = index {
+        index.write_to_file(&staging.join("index"))?;
+    }
+
+    // Everything is staged, the new objects, refs and logs now replace the
+    // old ones. The packs and the files indexing them go along with the old
+    // objects.
+    let old = staging.join("old");
+    fs::create_dir_all(&old)?;
+    for dir in ["objects", "refs", "logs"] {
+        let current = VOX_DIR.join(dir);
+        if current.exists() {
+            fs::rename(&current, old.join(dir))
+                .with_context(|| format!("Failed to move the old {}", dir))?;
+        }
+        if staging.join(dir).exists() {
+            fs::rename(staging.join(dir), &current)
+                .with_context(|| format!("Failed to move the new {}", dir))?;
+        }
+    }
+    for file in ["HEAD", "ORIG_HEAD", "index"] {
+        if staging.join(file).exists() {
+            fs::rename(staging.join(file), VOX_DIR.join(file))
+                .with_context(|| format!("Failed to update {}", file))?;
+        } else if file == "ORIG_HEAD" && VOX_DIR.join(file).exists() {
+            fs::remove_file(VOX_DIR.join(file))?;
+        }
+    }
+    config.with_object_format(format).save(&VOX_DIR)?;
+    ObjectFormat::forget(&OBJ_DIR);
+    write_object_map(&VOX_DIR, &migration)?;
+    fs::remove_dir_all(&staging)
+        .with_context(|| format!("Failed to remove {}", staging.display()))?;
+
+    println!(
+        "Migrated {} objects to {}, old names are mapped in {}",
+        migration.names().len(),
+        format.to_string().yellow(),
+        VOX_DIR.join(OBJECT_MAP_FILE).display()
+    );
+    Ok(())
+}
+
+/// Recompresses every loose object of the repository with `codec`
+///
+/// Objects read whichever codec compressed them, so the repository stays
+/// usable if this is interrupted and running it again finishes the job. The
+/// `compression` extension is set before the first zstd object is written and
+/// cleared once the last one is gone, so a vox that can't read zstd never
+/// takes the repository for a plain one. Packs are always zlib and aren't
+/// touched.
+pub fn migrate_compression(codec: Codec) -> Result<()> {
+    if !VOX_DIR.exists() {
+        bail!(VoxError::NotARepository);
+    }
+    let config = RepoConfig::load(&VOX_DIR)?;
+    if config.compression() == codec {
+        bail!("The repository already compresses its objects with {}", codec);
+    }
+    if codec == Codec::Zstd {
+        config.clone().with_compression(codec).save(&VOX_DIR)?;
+        Codec::forget(&OBJ_DIR);
+    }
+
+    let mut recompressed = 0;
+    for object in ObjectStorage::at(&OBJ_DIR).loose_objects()? {
+        let compressed = read_object_file(&object.hash, &object.path)?;
+        // Change sets are stored uncompressed, and corrupt objects are left
+        // for fsck to report
+        let Ok(data) = decompress(&compressed) else {
+            continue;
+        };
+        if Codec::detect(&compressed) == codec {
+            continue;
+        }
+        let mut encoder = ObjectEncoder::with_codec(Vec::new(), codec)?;
+        encoder.write_all(&data)?;
+        let temp = object.path.with_extension("tmp");
+        write_object_file(&object.hash, &temp, &encoder.finish()?)?;
+        fs::rename(&temp, &object.path)
+            .with_context(|| format!("Failed to replace object {}", object.hash))?;
+        recompressed += 1;
+    }
+
+    if codec == Codec::Zlib {
+        config.with_compression(codec).save(&VOX_DIR)?;
+        Codec::forget(&OBJ_DIR);
+    }
+    println!(
+        "Recompressed {} objects with {}",
+        recompressed,
+        codec.to_string().yellow()
+    );
+    Ok(())
+}
+
+/// Writes `content` to the staged file at `path`, creating its directories
+fn stage_file(path: &Path, content: &str) -> Result<()> {
+    fs::create_dir_all(path.parent().context("Invalid staged path")?)?;
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
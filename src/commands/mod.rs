@@ -1,15 +1,50 @@
 pub mod add;
+pub mod am;
+pub mod archive;
+pub mod bisect;
+pub mod bitmap;
+pub mod blame;
 pub mod branch;
+pub mod check_attr;
+pub mod check_ignore;
+pub mod bundle;
 pub mod cat_file;
+pub mod cherry_pick;
 pub mod clone;
 pub mod commit;
+pub mod commit_graph;
 pub mod config;
+pub mod count_objects;
 pub mod diff;
+pub mod fast_export;
+pub mod fast_import;
+pub mod format_patch;
+pub mod fsck;
 pub mod hash_object;
 pub mod index;
 pub mod init;
+pub mod lfs;
 pub mod log;
+pub mod merge;
+pub mod merge_base;
+pub mod merge_file;
+pub mod mergetool;
+pub mod migrate;
+pub mod notes;
+pub mod prune;
+pub mod rebase;
+pub mod reflog;
 pub mod remote;
+pub mod replace;
+pub mod reset;
+pub mod restore;
+pub mod rev_list;
+pub mod rev_parse;
+pub mod shortlog;
 pub mod show;
 pub mod status;
+pub mod submodule;
+pub mod tag;
+pub mod verify;
+pub mod watch;
 pub mod write_tree;
@@ -0,0 +1,92 @@
+use crate::commands::config::commands::user_identity;
+use crate::error::VoxError;
+use crate::storage::notes::Notes;
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use clap::Subcommand;
+
+#[derive(Debug, Subcommand)]
+pub enum NotesCommands {
+    #[command(about = "Attach a note to a commit")]
+    Add {
+        #[clap(short, long, help = "The note")]
+        message: String,
+
+        #[clap(short, long, help = "Replace the commit's existing note")]
+        force: bool,
+
+        #[clap(help = "The commit to annotate (defaults to HEAD)")]
+        commit: Option<String>,
+    },
+
+    #[command(about = "Show the note of a commit")]
+    Show {
+        #[clap(help = "The commit (defaults to HEAD)")]
+        commit: Option<String>,
+    },
+
+    #[command(about = "Remove the note of a commit")]
+    Remove {
+        #[clap(help = "The commit (defaults to HEAD)")]
+        commit: Option<String>,
+    },
+}
+
+pub fn notes_command(command: &NotesCommands) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let resolve = |commit: &Option<String>| {
+        resolve_commit(&VOX_DIR, commit.as_deref().unwrap_or("HEAD"))
+    };
+    let mut notes = Notes::load(&VOX_DIR, &OBJ_DIR)?;
+
+    match command {
+        NotesCommands::Add {
+            message,
+            force,
+            commit,
+        } => {
+            let commit = resolve(commit)?;
+            if !force && notes.get(&commit, &OBJ_DIR)?.is_some() {
+                bail!(
+                    "Commit {} already has a note (use --force to replace it)",
+                    &commit[..7]
+                );
+            }
+            notes.set(&commit, message, &OBJ_DIR)?;
+            notes.save(&VOX_DIR, &OBJ_DIR, &user_identity(), "Notes added by 'vox notes add'")?;
+        }
+        NotesCommands::Show { commit } => {
+            let commit = resolve(commit)?;
+            match notes.get(&commit, &OBJ_DIR)? {
+                Some(note) => print!("{}", with_newline(&note)),
+                None => bail!("No note found for commit {}", &commit[..7]),
+            }
+        }
+        NotesCommands::Remove { commit } => {
+            let commit = resolve(commit)?;
+            if !notes.remove(&commit) {
+                bail!("Commit {} has no note to remove", &commit[..7]);
+            }
+            notes.save(
+                &VOX_DIR,
+                &OBJ_DIR,
+                &user_identity(),
+                "Notes removed by 'vox notes remove'",
+            )?;
+            println!("Removed note for commit {}", &commit[..7]);
+        }
+    }
+    Ok(())
+}
+
+/// Terminates the note with a newline unless it already ends with one
+fn with_newline(note: &str) -> String {
+    match note.ends_with('\n') {
+        true => note.to_string(),
+        false => format!("{}\n", note),
+    }
+}
@@ -0,0 +1,82 @@
+use crate::error::VoxError;
+use crate::storage::objects::graph::{collect_reachable, repository_roots};
+use crate::storage::objects::{read_raw_object, ObjectStorage};
+use crate::storage::utils::{parse_date, OBJ_DIR, UNKNOWN_TYPE, VOX_DIR};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use std::fs;
+use std::path::Path;
+
+/// Removes loose objects that can't be reached from any ref or the index
+///
+/// # Arguments
+/// - `dry_run`: only report what would be removed
+/// - `expire`: only remove objects older than this date (e.g. "2.weeks.ago")
+///
+pub fn prune_command(dry_run: bool, expire: Option<String>) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let expire: Option<DateTime<Utc>> = expire
+        .as_deref()
+        .map(parse_date)
+        .transpose()
+        .context("Invalid --expire date")?;
+
+    // Everything reachable from HEAD, refs and the index must be kept
+    let roots = repository_roots(&VOX_DIR)?;
+    let reachable = collect_reachable(roots, &OBJ_DIR)?;
+
+    let storage = ObjectStorage::new(Path::new("."));
+    let mut pruned = 0;
+
+    for object in storage.loose_objects()? {
+        if reachable.contains(&object.hash) {
+            continue;
+        }
+
+        // Keep recent objects when an expiry date is given
+        if let Some(expire) = expire {
+            let modified: DateTime<Utc> = object.modified.into();
+            if modified >= expire {
+                continue;
+            }
+        }
+
+        if dry_run {
+            let object_type = read_raw_object(&object.hash, &OBJ_DIR)
+                .map(|(object_type, _)| object_type)
+                .unwrap_or_else(|_| UNKNOWN_TYPE.to_string());
+            println!("{} {}", object.hash, object_type);
+        } else {
+            fs::remove_file(&object.path)
+                .with_context(|| format!("Failed to remove object {}", object.hash))?;
+
+            // Drop the fan-out directory once it's empty
+            if let Some(parent) = object.path.parent()
+                && fs::read_dir(parent)?.next().is_none()
+            {
+                fs::remove_dir(parent)?;
+            }
+        }
+        pruned += 1;
+    }
+
+    if pruned == 0 {
+        println!("{}", "Nothing to prune".dimmed());
+    } else if dry_run {
+        println!(
+            "{}",
+            format!("Would prune {} unreachable objects", pruned).yellow()
+        );
+    } else {
+        println!(
+            "{}",
+            format!("Pruned {} unreachable objects", pruned).green()
+        );
+    }
+
+    Ok(())
+}
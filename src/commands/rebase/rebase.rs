@@ -0,0 +1,574 @@
+use crate::commands::cherry_pick::cherry_pick::{apply_commit, Replay};
+use crate::commands::commit::commit::{get_current_commit, update_current_branch};
+use crate::commands::commit::replay::{commit_index, ReplayState};
+use crate::commands::format_patch::format_patch::split_message;
+use crate::commands::index::index::Index;
+use crate::commands::merge::merge::ensure_clean_index;
+use crate::commands::restore::restore::restore_paths;
+use crate::commands::status::status::get_status;
+use crate::error::VoxError;
+use crate::storage::editor::{editor, edit_text, sequence_editor};
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::{is_ancestor, list_commits};
+use crate::storage::objects::Loadable;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::revision::{abbreviate, resolve_commit};
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory holding the state of a rebase in progress
+const REBASE_STATE: ReplayState = ReplayState::new("rebase-merge", "rebase");
+/// The steps left, the first one being carried out
+const REBASE_TODO: &str = "todo";
+/// The commit the branch pointed to before the rebase, restored by `--abort`
+const REBASE_ORIG_HEAD: &str = "orig-head";
+/// Present while stopped at an `edit` step, so `--continue` amends HEAD with
+/// the staged changes instead of picking a commit
+const REBASE_AMEND: &str = "amend";
+
+/// What a step of a rebase does with its commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Use the commit
+    Pick,
+    /// Use the commit, but edit its message
+    Reword,
+    /// Use the commit, but stop to amend it
+    Edit,
+    /// Meld the commit into the previous one, editing the combined message
+    Squash,
+    /// Meld the commit into the previous one, keeping the previous message
+    Fixup,
+    /// Leave the commit out
+    Drop,
+}
+
+impl Instruction {
+    fn name(self) -> &'static str {
+        match self {
+            Instruction::Pick => "pick",
+            Instruction::Reword => "reword",
+            Instruction::Edit => "edit",
+            Instruction::Squash => "squash",
+            Instruction::Fixup => "fixup",
+            Instruction::Drop => "drop",
+        }
+    }
+
+    /// Parses an instruction, by its name or its first letter
+    fn parse(word: &str) -> Option<Self> {
+        [
+            Instruction::Pick,
+            Instruction::Reword,
+            Instruction::Edit,
+            Instruction::Squash,
+            Instruction::Fixup,
+            Instruction::Drop,
+        ]
+        .into_iter()
+        .find(|instruction| word == instruction.name() || word == &instruction.name()[..1])
+    }
+
+    /// Whether the commit is melded into the previous one
+    fn melds(self) -> bool {
+        matches!(self, Instruction::Squash | Instruction::Fixup)
+    }
+}
+
+/// A step of a rebase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub instruction: Instruction,
+    pub commit: String,
+    /// Subject of the commit, only there for whoever reads the todo list
+    pub subject: String,
+}
+
+/// What `vox rebase` should do
+#[derive(Debug)]
+pub enum RebaseAction {
+    /// Replay the commits of the current branch missing from `upstream` on
+    /// top of it, letting the user edit the list of steps first if
+    /// `interactive`, with the `fixup!` and `squash!` commits moved after
+    /// their targets if `autosquash`
+    Start {
+        upstream: String,
+        interactive: bool,
+        autosquash: bool,
+    },
+    /// Commit the staged resolution of a conflict, or the amended commit of
+    /// an `edit` step, and go on
+    Continue,
+    /// Go back to where the branch was before the rebase
+    Abort,
+}
+
+/// Replays the commits of the current branch on top of another commit
+///
+/// The branch is moved to `upstream` and the commits it had that `upstream`
+/// doesn't are applied again, oldest first. With `interactive`, the list
+/// of steps is opened in the editor first, where commits can be reordered,
+/// reworded, stopped at, squashed together or dropped. The steps left are
+/// kept in `.vox/rebase-merge`: after a conflict, or at an `edit` step, the
+/// rebase is resumed with `--continue` or given up with `--abort`.
+pub fn rebase_command(action: RebaseAction) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let in_progress = REBASE_STATE.file(REBASE_TODO).exists();
+    match action {
+        RebaseAction::Start { .. } if in_progress => {
+            bail!("A rebase is in progress, use --continue or --abort")
+        }
+        RebaseAction::Start {
+            upstream,
+            interactive,
+            autosquash,
+        } => {
+            if !start(&upstream, interactive, autosquash)? {
+                return Ok(());
+            }
+        }
+        _ if !in_progress => bail!("No rebase in progress"),
+        RebaseAction::Continue => resume()?,
+        RebaseAction::Abort => return REBASE_STATE.abort(REBASE_ORIG_HEAD),
+    }
+    run_steps()
+}
+
+/// Help appended to the todo list opened in the editor
+const TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+# If you remove a line here THAT COMMIT WILL BE LOST.
+";
+
+/// Parses a todo list, ignoring blank lines and `#` comments
+///
+/// Commits are kept as written, they may be abbreviated.
+pub fn parse_steps(todo: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for line in todo.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ' ');
+        let word = fields.next().unwrap_or_default();
+        let instruction = Instruction::parse(word)
+            .with_context(|| format!("Unknown command '{}' in '{}'", word, line))?;
+        let commit = fields.next().with_context(|| format!("Missing commit in '{}'", line))?;
+        steps.push(Step {
+            instruction,
+            commit: commit.to_string(),
+            subject: fields.next().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(steps)
+}
+
+/// Writes a todo list in the format [`parse_steps`] reads
+pub fn format_steps(steps: &[Step]) -> String {
+    steps
+        .iter()
+        .map(|step| format!("{} {} {}\n", step.instruction.name(), step.commit, step.subject))
+        .collect()
+}
+
+/// Moves each `fixup! <subject>` or `squash! <subject>` commit right after
+/// the commit it names, and its previous fixups, turning it into a `fixup`
+/// or `squash` step
+///
+/// The target is named by its subject or a prefix of its hash, and must come
+/// earlier in the list; commits without a target are left where they are.
+pub fn autosquash_steps(steps: Vec<Step>) -> Vec<Step> {
+    let mut ordered: Vec<Step> = Vec::new();
+    // Number of steps melded into each step of `ordered` so far
+    let mut melded: Vec<usize> = Vec::new();
+
+    for mut step in steps {
+        let (instruction, target) = match squash_target(&step.subject) {
+            Some((instruction, target)) => (instruction, target.to_string()),
+            None => {
+                ordered.push(step);
+                melded.push(0);
+                continue;
+            }
+        };
+        let position = ordered.iter().position(|candidate| {
+            squash_target(&candidate.subject).is_none()
+                && (candidate.subject == target
+                    || (target.len() >= 4 && candidate.commit.starts_with(&target)))
+        });
+        match position {
+            Some(position) => {
+                step.instruction = instruction;
+                let at = position + melded[position] + 1;
+                melded[position] += 1;
+                ordered.insert(at, step);
+                melded.insert(at, 0);
+            }
+            None => {
+                ordered.push(step);
+                melded.push(0);
+            }
+        }
+    }
+    ordered
+}
+
+/// Parses the subject of a `fixup!` or `squash!` commit into the instruction
+/// and the subject (or hash) of the commit it is meant for
+///
+/// Repeated prefixes, as in `fixup! fixup! Subject`, all refer to the same
+/// commit.
+fn squash_target(subject: &str) -> Option<(Instruction, &str)> {
+    let (instruction, mut target) = if let Some(target) = subject.strip_prefix("fixup! ") {
+        (Instruction::Fixup, target)
+    } else {
+        (Instruction::Squash, subject.strip_prefix("squash! ")?)
+    };
+    while let Some(rest) = target
+        .strip_prefix("fixup! ")
+        .or_else(|| target.strip_prefix("squash! "))
+    {
+        target = rest;
+    }
+    Some((instruction, target))
+}
+
+fn read_steps() -> Result<Vec<Step>> {
+    let todo = fs::read_to_string(REBASE_STATE.file(REBASE_TODO)).context("Failed to read the todo list")?;
+    parse_steps(&todo)
+}
+
+fn write_steps(steps: &[Step]) -> Result<()> {
+    fs::write(REBASE_STATE.file(REBASE_TODO), format_steps(steps))
+        .context("Failed to write the todo list")
+}
+
+/// Writes the todo list and moves the branch to `upstream`
+///
+/// # Returns
+///
+/// Whether there are steps to run
+fn start(upstream: &str, interactive: bool, autosquash: bool) -> Result<bool> {
+    let head = get_current_commit()?.context("Cannot rebase a branch without commits")?;
+    let onto = resolve_commit(&VOX_DIR, upstream)
+        .with_context(|| format!("Invalid upstream '{}'", upstream))?;
+    ensure_clean_index(&head)?;
+
+    if !interactive && !autosquash && is_ancestor(&onto, &head, &OBJ_DIR)? {
+        println!("Current branch is up to date.");
+        return Ok(false);
+    }
+    let mut commits = list_commits(
+        std::slice::from_ref(&head),
+        std::slice::from_ref(&onto),
+        &OBJ_DIR,
+    )?;
+    commits.reverse();
+
+    let mut steps = Vec::new();
+    for commit in commits {
+        let message = Commit::load(&commit, &OBJ_DIR)?.message;
+        let (subject, _) = split_message(&message);
+        steps.push(Step {
+            instruction: Instruction::Pick,
+            commit,
+            subject,
+        });
+    }
+    if autosquash {
+        steps = autosquash_steps(steps);
+    }
+
+    fs::create_dir_all(REBASE_STATE.dir()).context("Failed to create the rebase state")?;
+    if interactive {
+        for step in &mut steps {
+            step.commit = abbreviate(&OBJ_DIR, &step.commit, 7)?;
+        }
+        let todo = format!(
+            "{}\n# Rebase {}..{} onto {} ({} commands)\n{}",
+            format_steps(&steps),
+            abbreviate(&OBJ_DIR, &onto, 7)?,
+            abbreviate(&OBJ_DIR, &head, 7)?,
+            abbreviate(&OBJ_DIR, &onto, 7)?,
+            steps.len(),
+            TODO_HELP
+        );
+        let edited = edit_text(&sequence_editor(), &REBASE_STATE.file(REBASE_TODO), &todo);
+        steps = match edited.and_then(|edited| parse_steps(&edited)) {
+            Ok(steps) => steps,
+            Err(error) => {
+                fs::remove_dir_all(REBASE_STATE.dir())?;
+                return Err(error);
+            }
+        };
+        if steps.is_empty() {
+            fs::remove_dir_all(REBASE_STATE.dir())?;
+            println!("Nothing to do");
+            return Ok(false);
+        }
+    }
+
+    // The todo list refers to commits by their full hash from here on
+    for step in &mut steps {
+        step.commit = resolve_commit(&VOX_DIR, &step.commit)
+            .with_context(|| format!("Invalid commit '{}' in the todo list", step.commit))?;
+    }
+    if let Some(step) = steps.iter().find(|step| step.instruction != Instruction::Drop)
+        && step.instruction.melds()
+    {
+        fs::remove_dir_all(REBASE_STATE.dir())?;
+        bail!("Cannot '{}' without a previous commit", step.instruction.name());
+    }
+
+    fs::write(REBASE_STATE.file(REBASE_ORIG_HEAD), format!("{}\n", head))?;
+    write_steps(&steps)?;
+    if onto != head {
+        update_current_branch(&onto, Some(&head), &format!("rebase: checkout {}", upstream))?;
+        restore_paths(&[PathBuf::new()], Some(&onto), true, true)?;
+    }
+    Ok(true)
+}
+
+/// Carries on after the rebase stopped
+fn resume() -> Result<()> {
+    let (_added, modified, deleted, _untracked) =
+        get_status(Path::new("."), &Pathspec::default())?;
+    if !modified.is_empty() || !deleted.is_empty() {
+        bail!("Resolve the conflicts and stage the result with 'vox add' first");
+    }
+
+    // Stopped at an `edit` step, whose commit was made already: staged
+    // changes amend it
+    if REBASE_STATE.file(REBASE_AMEND).exists() {
+        fs::remove_file(REBASE_STATE.file(REBASE_AMEND))?;
+        let head = get_current_commit()?.context("HEAD doesn't point to a commit")?;
+        let commit = Commit::load(&head, &OBJ_DIR)?;
+        let mut index = Index::new();
+        index.read_from_file(&INDEX_FILE)?;
+        if index.write_tree(&OBJ_DIR)? != commit.tree
+            && let Some(parent) = &commit.parent
+        {
+            commit_as(parent, &commit, commit.message.clone(), "rebase: amend")?;
+        }
+        return Ok(());
+    }
+
+    // Stopped at a conflict, the step is finished with the staged resolution
+    let steps = read_steps()?;
+    if let Some(step) = steps.first() {
+        commit_step(step)?;
+        write_steps(&steps[1..])?;
+        if step.instruction == Instruction::Edit {
+            return stop_for_edit(step);
+        }
+    }
+    Ok(())
+}
+
+/// Runs the steps left, stopping at a conflict or an `edit` step
+fn run_steps() -> Result<()> {
+    if REBASE_STATE.file(REBASE_AMEND).exists() {
+        return Ok(());
+    }
+    loop {
+        let steps = read_steps()?;
+        let Some(step) = steps.first() else {
+            fs::remove_dir_all(REBASE_STATE.dir())?;
+            println!("Successfully rebased");
+            return Ok(());
+        };
+
+        if step.instruction != Instruction::Drop {
+            if !apply_commit(Replay::Pick, &step.commit, &step.subject)? {
+                let short = abbreviate(&OBJ_DIR, &step.commit, 7)?;
+                bail!(VoxError::MergeConflict(format!(
+                    "Could not apply {}... {}\n{}",
+                    short,
+                    step.subject,
+                    "Resolve the conflicts, stage them with 'vox add' and run \
+                     'vox rebase --continue', or use 'vox rebase --abort'"
+                        .yellow()
+                )));
+            }
+            commit_step(step)?;
+        }
+        write_steps(&steps[1..])?;
+        if step.instruction == Instruction::Edit {
+            return stop_for_edit(step);
+        }
+    }
+}
+
+/// Stops after the commit of an `edit` step, for it to be amended
+fn stop_for_edit(step: &Step) -> Result<()> {
+    fs::write(REBASE_STATE.file(REBASE_AMEND), "")?;
+    let short = abbreviate(&OBJ_DIR, &step.commit, 7)?;
+    println!("Stopped at {}... {}", short, step.subject);
+    println!(
+        "{}",
+        "You can amend the commit now, or stage changes to amend it with. \
+         Once you are satisfied with your changes, run 'vox rebase --continue'"
+            .yellow()
+    );
+    Ok(())
+}
+
+/// Commits the applied changes of a step
+///
+/// Squashed and fixed up commits replace the previous commit, which keeps
+/// its author.
+fn commit_step(step: &Step) -> Result<()> {
+    let head = get_current_commit()?.context("HEAD doesn't point to a commit")?;
+    let original = Commit::load(&step.commit, &OBJ_DIR)?;
+    let reflog_message = format!("rebase ({}): {}", step.instruction.name(), step.subject);
+
+    if step.instruction.melds() {
+        let previous = Commit::load(&head, &OBJ_DIR)?;
+        let parent = previous.parent.clone().context("Cannot squash into a root commit")?;
+        let message = match step.instruction {
+            Instruction::Fixup => previous.message.clone(),
+            // The subject of a `squash!` commit only names its target
+            _ => edit_message(&format!(
+                "{}\n\n{}{}\n\n{}",
+                previous.message.trim_end(),
+                if squash_target(&step.subject).is_some() { "# " } else { "" },
+                original.message.trim_end(),
+                "# This is a combination of two commits. Lines starting with '#' are ignored."
+            ))?,
+        };
+        commit_as(&parent, &previous, message, &reflog_message)?;
+        return Ok(());
+    }
+
+    let message = match step.instruction {
+        Instruction::Reword => edit_message(&original.message)?,
+        _ => original.message.clone(),
+    };
+    if commit_as(&head, &original, message, &reflog_message)?.is_none() {
+        let short = abbreviate(&OBJ_DIR, &step.commit, 7)?;
+        println!("Skipping {}... {}: nothing left to commit", short, step.subject);
+    }
+    Ok(())
+}
+
+/// Lets the user edit a commit message
+fn edit_message(message: &str) -> Result<String> {
+    let message = edit_text(&editor(), &VOX_DIR.join("COMMIT_EDITMSG"), message)?;
+    if message.trim().is_empty() {
+        bail!("Aborting commit due to empty commit message");
+    }
+    Ok(message)
+}
+
+/// Commits the index on top of `parent` with the author and date of
+/// `original` and `message`, making it the tip of the branch
+///
+/// # Returns
+///
+/// The new commit, or `None` if the index has the tree of `parent` and
+/// nothing was committed
+fn commit_as(
+    parent: &str,
+    original: &Commit,
+    message: String,
+    reflog_message: &str,
+) -> Result<Option<String>> {
+    let (subject, _) = split_message(&message);
+    let subject = subject.to_string();
+    let hash = commit_index(
+        Some(parent),
+        &original.author,
+        Some(original.timestamp),
+        message,
+        reflog_message,
+    )?;
+    if let Some(hash) = &hash {
+        println!("[{}] {}", abbreviate(&OBJ_DIR, hash, 7)?, subject);
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_steps() -> Result<()> {
+        let todo = format!(
+            "pick abc1234 First\nr def5678 Second one\n\ns 1234abc\nfixup 99aa00 Fix\n\
+             d 5555555 Gone\nedit 7777777 Stop here\n{}",
+            TODO_HELP
+        );
+        let steps = parse_steps(&todo)?;
+        let instructions: Vec<_> = steps.iter().map(|step| step.instruction).collect();
+        assert_eq!(
+            instructions,
+            [
+                Instruction::Pick,
+                Instruction::Reword,
+                Instruction::Squash,
+                Instruction::Fixup,
+                Instruction::Drop,
+                Instruction::Edit,
+            ]
+        );
+        assert_eq!(steps[1].commit, "def5678");
+        assert_eq!(steps[1].subject, "Second one");
+        assert_eq!(parse_steps(&format_steps(&steps))?, steps);
+
+        assert!(parse_steps("merge abc1234").is_err());
+        assert!(parse_steps("pick").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_autosquash_steps() {
+        let step = |commit: &str, subject: &str| Step {
+            instruction: Instruction::Pick,
+            commit: commit.to_string(),
+            subject: subject.to_string(),
+        };
+        let steps = vec![
+            step("aaaa1111", "Add parser"),
+            step("bbbb2222", "Add lexer"),
+            step("cccc3333", "fixup! Add parser"),
+            step("dddd4444", "squash! bbbb2"),
+            step("eeee5555", "fixup! fixup! Add parser"),
+            step("ffff6666", "fixup! Something else"),
+        ];
+
+        let ordered: Vec<_> = autosquash_steps(steps)
+            .into_iter()
+            .map(|step| (step.instruction, step.commit))
+            .collect();
+        let expected = [
+            (Instruction::Pick, "aaaa1111"),
+            (Instruction::Fixup, "cccc3333"),
+            (Instruction::Fixup, "eeee5555"),
+            (Instruction::Pick, "bbbb2222"),
+            (Instruction::Squash, "dddd4444"),
+            (Instruction::Pick, "ffff6666"),
+        ];
+        assert_eq!(ordered.len(), expected.len());
+        for ((instruction, commit), (expected_instruction, expected_commit)) in
+            ordered.iter().zip(expected)
+        {
+            assert_eq!((*instruction, commit.as_str()), (expected_instruction, expected_commit));
+        }
+    }
+}
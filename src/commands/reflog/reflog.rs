@@ -0,0 +1,42 @@
+use crate::error::VoxError;
+use crate::storage::reflog::{full_ref_name, read_reflog};
+use crate::storage::utils::VOX_DIR;
+use anyhow::Result;
+use colored::*;
+
+/// Lists the recorded updates of a ref, newest first
+///
+/// # Arguments
+/// - `reference`: the ref to show the log of (defaults to "HEAD")
+/// - `count`: the maximum number of entries to display
+///
+pub fn reflog_command(reference: Option<String>, count: Option<usize>) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let name = reference.unwrap_or_else(|| "HEAD".to_string());
+    let entries = read_reflog(&VOX_DIR, &full_ref_name(&name))?;
+
+    if entries.is_empty() {
+        println!("{}", format!("No reflog entries for '{}'", name).yellow());
+        return Ok(());
+    }
+
+    for (index, entry) in entries
+        .iter()
+        .rev()
+        .take(count.unwrap_or(usize::MAX))
+        .enumerate()
+    {
+        let short_hash = &entry.new_hash[..7.min(entry.new_hash.len())];
+        println!(
+            "{} {}: {}",
+            short_hash.yellow(),
+            format!("{}@{{{}}}", name, index).green(),
+            entry.message
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use crate::error::VoxError;
+use crate::storage::objects::read_object_header;
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::{list_refs, ref_path};
+use crate::storage::replace::{without_replacements, REPLACE_REFS};
+use crate::storage::revision::resolve_revision;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use colored::*;
+
+/// Lists, creates or deletes replace refs
+///
+/// # Arguments
+/// - `object`: the object to replace, or to stop replacing with `delete`
+/// - `replacement`: the object read in its place
+/// - `delete`: remove the replacement of `object`
+/// - `force`: overwrite an existing replacement, and allow one of another type
+///
+pub fn replace_command(
+    object: Option<&str>,
+    replacement: Option<&str>,
+    delete: bool,
+    force: bool,
+) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    match (object, replacement) {
+        (None, _) if delete => bail!("Object required for deletion"),
+        (None, _) => list_replacements(),
+        (Some(object), _) if delete => delete_replacement(object),
+        (Some(_), None) => bail!("Replacement object required"),
+        (Some(object), Some(replacement)) => create_replacement(object, replacement, force),
+    }
+}
+
+fn list_replacements() -> Result<()> {
+    for (name, hash) in list_refs(&VOX_DIR)? {
+        if let Some(object) = name.strip_prefix(REPLACE_REFS) {
+            println!("{} -> {}", object.yellow(), hash);
+        }
+    }
+    Ok(())
+}
+
+fn create_replacement(object: &str, replacement: &str, force: bool) -> Result<()> {
+    // Names are resolved through the real history, not a replaced one
+    let (object, replacement) = without_replacements(|| -> Result<_> {
+        Ok((
+            resolve_revision(&VOX_DIR, object)?,
+            resolve_revision(&VOX_DIR, replacement)?,
+        ))
+    })?;
+    if object == replacement {
+        bail!("An object can't replace itself");
+    }
+
+    let ref_name = format!("{}{}", REPLACE_REFS, object);
+    let expected = match force {
+        true => ExpectedValue::Any,
+        false if ref_path(&VOX_DIR, &ref_name).exists() => {
+            bail!("Replace ref for {} already exists (use -f to overwrite it)", &object[..7])
+        }
+        false => ExpectedValue::Missing,
+    };
+    let (object_type, _) = without_replacements(|| read_object_header(&object, &OBJ_DIR))?;
+    let (replacement_type, _) = read_object_header(&replacement, &OBJ_DIR)?;
+    if object_type != replacement_type && !force {
+        bail!(
+            "Objects must be of the same type: {} is a {}, {} a {}",
+            &object[..7],
+            object_type,
+            &replacement[..7],
+            replacement_type
+        );
+    }
+
+    let mut transaction = RefTransaction::new(&VOX_DIR);
+    transaction.update(&ref_name, &replacement, expected, "replace: creating replacement");
+    transaction.commit()?;
+
+    println!("Replaced {} with {}", object[..7].yellow(), &replacement[..7]);
+    Ok(())
+}
+
+fn delete_replacement(object: &str) -> Result<()> {
+    let object = without_replacements(|| resolve_revision(&VOX_DIR, object))?;
+    let ref_name = format!("{}{}", REPLACE_REFS, object);
+    let path = ref_path(&VOX_DIR, &ref_name);
+    if !path.exists() {
+        bail!("{} isn't replaced", &object[..7]);
+    }
+    let hash = std::fs::read_to_string(&path)?.trim().to_string();
+
+    let mut transaction = RefTransaction::new(&VOX_DIR);
+    transaction.delete(&ref_name, ExpectedValue::Hash(hash.clone()));
+    transaction.commit()?;
+
+    println!("Deleted replacement of {} (was {})", &object[..7], &hash[..7]);
+    Ok(())
+}
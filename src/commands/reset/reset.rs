@@ -0,0 +1,78 @@
+use crate::commands::commit::commit::{get_current_commit, update_current_branch};
+use crate::commands::merge::conflicts::clear_conflicts;
+use crate::commands::merge::merge::clear_merge_state;
+use crate::commands::restore::restore::restore_paths;
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::Loadable;
+use crate::storage::revision::{abbreviate, resolve_commit};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{Context, Result};
+use colored::*;
+use std::fs;
+use std::path::PathBuf;
+
+/// What `vox reset` resets besides the current branch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetMode {
+    /// Only move the branch, the index and working tree are kept
+    Soft,
+    /// Also reset the index, working tree changes become unstaged
+    #[default]
+    Mixed,
+    /// Also reset the index and the working tree, discarding every change to
+    /// tracked files
+    Hard,
+}
+
+/// Moves the current branch (or detached HEAD) to `target`
+///
+/// The previous commit is saved to `ORIG_HEAD`, so the reset can be undone
+/// with `vox reset ORIG_HEAD`. Resetting the index also ends a merge that
+/// stopped on conflicts.
+///
+/// # Arguments
+/// - `target`: revision to reset to, defaults to HEAD
+/// - `mode`: whether the index and working tree are reset as well
+///
+pub fn reset_command(target: Option<&str>, mode: ResetMode) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let target = target.unwrap_or("HEAD");
+    let commit_hash = resolve_commit(&VOX_DIR, target)
+        .with_context(|| format!("Failed to resolve '{}'", target))?;
+    let old_hash = get_current_commit()?;
+
+    if let Some(old_hash) = &old_hash {
+        fs::write(VOX_DIR.join("ORIG_HEAD"), format!("{}\n", old_hash))?;
+    }
+    update_current_branch(
+        &commit_hash,
+        old_hash.as_deref(),
+        &format!("reset: moving to {}", target),
+    )?;
+
+    // Every tracked path, from the commit reset to
+    let everything = [PathBuf::new()];
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => {
+            restore_paths(&everything, Some(&commit_hash), true, false)?;
+        }
+        ResetMode::Hard => {
+            restore_paths(&everything, Some(&commit_hash), true, true)?;
+        }
+    }
+    if mode != ResetMode::Soft {
+        clear_merge_state()?;
+        clear_conflicts()?;
+    }
+
+    let short_hash = abbreviate(&OBJ_DIR, &commit_hash, 7)?;
+    let commit = Commit::load(&commit_hash, &OBJ_DIR)?;
+    let subject = commit.message.lines().next().unwrap_or_default();
+    println!("HEAD is now at {} {}", short_hash.yellow(), subject);
+    Ok(())
+}
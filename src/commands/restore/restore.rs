@@ -0,0 +1,199 @@
+use crate::commands::branch::checkout::{restore_blob, should_update_file};
+use crate::commands::index::index::{Index, IndexEntry};
+use crate::error::VoxError;
+use crate::storage::hash::ObjectId;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::Loadable;
+use crate::storage::platform::{to_index_path, FileStat, MODE_GITLINK};
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{INDEX_FILE, OBJ_DIR, PERM_GITLINK, PERM_SYMLINK, VOX_DIR};
+use anyhow::{Context, Result};
+use colored::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Restores files in the working tree and/or the index
+///
+/// # Arguments
+/// - `paths`: files or directories to restore
+/// - `source`: revision to take the content from. Defaults to the index when
+///   restoring the working tree, and to HEAD when restoring the index
+/// - `staged`: restore the index
+/// - `worktree`: restore the working tree, implied unless `staged` is given
+///
+pub fn restore_command(
+    paths: &[PathBuf],
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+    let worktree = worktree || !staged;
+    let source = match (source, staged) {
+        (None, true) => Some("HEAD"),
+        (source, _) => source,
+    };
+
+    let restored = restore_paths(paths, source, staged, worktree)?;
+    println!("Restored {} path(s)", restored.to_string().green());
+    Ok(())
+}
+
+/// Copies the files under `paths` from `source` (a revision, or the index if
+/// `None`) into the index and/or the working tree
+///
+/// Tracked files under the paths that don't exist in the source are removed.
+/// Returns the number of files restored or removed.
+///
+pub fn restore_paths(
+    paths: &[PathBuf],
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+) -> Result<usize> {
+    let pathspecs: Vec<PathBuf> = paths.iter().map(|path| to_index_path(path)).collect();
+    let mut index = Index::new();
+    if INDEX_FILE.exists() {
+        index.read_from_file(&INDEX_FILE)?;
+    }
+
+    // path -> (blob hash, mode) for every file the source has under the paths
+    let files: BTreeMap<PathBuf, (String, String)> = match source {
+        Some(revision) => {
+            let commit_hash = resolve_commit(&VOX_DIR, revision)
+                .with_context(|| format!("Invalid source '{}'", revision))?;
+            let commit = Commit::load(&commit_hash, &OBJ_DIR)?;
+            read_tree(&commit.tree, &OBJ_DIR)?
+                .flatten(&OBJ_DIR)?
+                .entries
+                .into_iter()
+                .map(|entry| (PathBuf::from(entry.name), (entry.object_hash, entry.mode)))
+                .collect()
+        }
+        None => index
+            .get_entries()
+            .iter()
+            .map(|(path, entry)| {
+                let mode = format!("{:o}", entry.stat().tree_mode());
+                (path.clone(), (entry.hash.to_hex(), mode))
+            })
+            .collect(),
+    };
+    let files: BTreeMap<_, _> = files
+        .into_iter()
+        .filter(|(path, _)| is_selected(path, &pathspecs))
+        .collect();
+    let tracked: Vec<PathBuf> = index
+        .get_entries()
+        .keys()
+        .filter(|path| is_selected(path, &pathspecs))
+        .cloned()
+        .collect();
+
+    for pathspec in pathspecs.iter().filter(|pathspec| !pathspec.as_os_str().is_empty()) {
+        let known = files.keys().chain(&tracked).any(|path| is_selected(path, &[pathspec]));
+        if !known {
+            return Err(anyhow::anyhow!(
+                "pathspec '{}' did not match any file(s) known to vox",
+                pathspec.display()
+            ));
+        }
+    }
+
+    let mut restored = 0;
+    for (path, (hash, mode)) in &files {
+        // Submodules are only populated by `vox submodule update`
+        if mode == PERM_GITLINK {
+            continue;
+        }
+        if worktree {
+            restore_blob(hash, path, mode)?;
+        }
+
+        let in_index = index.get_entry(path).map(|entry| entry.hash.to_hex());
+        if staged || (worktree && in_index.as_deref() == Some(hash)) {
+            index.add_entry(index_entry(path, hash, mode)?);
+        }
+        restored += 1;
+    }
+
+    // Tracked files the source doesn't have
+    for path in tracked.iter().filter(|path| !files.contains_key(*path)) {
+        let is_submodule = index
+            .get_entry(path)
+            .is_some_and(|entry| entry.stat().tree_mode() == MODE_GITLINK);
+        if is_submodule {
+            continue;
+        }
+        if worktree && fs::symlink_metadata(path).is_ok() {
+            fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        if staged {
+            index.remove_entry(path);
+        }
+        restored += 1;
+    }
+
+    index.write_to_file(&INDEX_FILE)?;
+    Ok(restored)
+}
+
+/// Returns true if `path` is one of `pathspecs` or inside one of them
+fn is_selected<P: AsRef<Path>>(path: &Path, pathspecs: &[P]) -> bool {
+    pathspecs.iter().any(|pathspec| {
+        let pathspec = pathspec.as_ref();
+        pathspec.as_os_str().is_empty() || path.starts_with(pathspec)
+    })
+}
+
+/// Builds the index entry for a restored file
+///
+/// The metadata of the working tree file is only recorded if it holds the
+/// restored content, otherwise the file shows up as modified.
+pub fn index_entry(path: &Path, hash: &str, mode: &str) -> Result<IndexEntry> {
+    let is_link = mode == PERM_SYMLINK;
+    let stat = match should_update_file(path, hash, is_link) {
+        false => FileStat::from_path(path)?,
+        true => FileStat::default(),
+    };
+    let mode = match stat.mode {
+        0 => u32::from_str_radix(mode, 8)
+            .with_context(|| format!("Invalid mode {} of {}", mode, path.display()))?,
+        mode => mode,
+    };
+
+    Ok(IndexEntry {
+        mtime: stat.mtime,
+        dev: stat.dev,
+        ino: stat.ino,
+        mode,
+        uid: stat.uid,
+        gid: stat.gid,
+        size: stat.size,
+        hash: ObjectId::from_hex(hash)?,
+        flags: 0,
+        extended_flags: 0,
+        path: path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_selected() {
+        let pathspecs = [PathBuf::from("src"), PathBuf::from("README.md")];
+        assert!(is_selected(Path::new("src/main.rs"), &pathspecs));
+        assert!(is_selected(Path::new("README.md"), &pathspecs));
+        assert!(!is_selected(Path::new("srcs/main.rs"), &pathspecs));
+        assert!(!is_selected(Path::new("docs/README.md"), &pathspecs));
+
+        // "." selects everything
+        assert!(is_selected(Path::new("docs/README.md"), &[to_index_path(Path::new("."))]));
+    }
+}
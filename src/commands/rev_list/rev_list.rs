@@ -0,0 +1,113 @@
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::{list_commits, list_tree_objects};
+use crate::storage::objects::walk::CommitWalker;
+use crate::storage::objects::Loadable;
+use crate::storage::refs::{list_refs, resolve_head};
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+
+/// Options of the `rev-list` command
+#[derive(Debug, Default)]
+pub struct RevListOptions {
+    /// Stop after this many commits
+    pub max_count: Option<usize>,
+    /// Start from every ref and HEAD, in addition to the given revisions
+    pub all: bool,
+    /// Also list the trees and blobs of the listed commits
+    pub objects: bool,
+}
+
+/// Prints the commits reachable from the given revisions, newest first
+///
+/// # Arguments
+/// - `revisions`: where to start; `^A` excludes the commits reachable from
+///   `A`, and `A..B` lists the commits reachable from `B` but not from `A`
+/// - `options`: what to list
+///
+pub fn rev_list_command(revisions: &[String], options: &RevListOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let (mut include, exclude) = parse_revisions(revisions)?;
+    if options.all {
+        include.extend(all_tips()?);
+    }
+    if include.is_empty() {
+        bail!("No revisions given (use a revision or --all)");
+    }
+
+    let mut walker = CommitWalker::new(&OBJ_DIR);
+    for hash in &exclude {
+        walker.hide(hash)?;
+    }
+    for hash in &include {
+        walker.push(hash)?;
+    }
+    let commits = walker
+        .take(options.max_count.unwrap_or(usize::MAX))
+        .map(|commit| Ok(commit?.0))
+        .collect::<Result<Vec<String>>>()?;
+    for commit in &commits {
+        println!("{}", commit);
+    }
+
+    if options.objects {
+        // Objects that the excluded side already has aren't listed
+        let mut seen = HashSet::new();
+        for hash in list_commits(&exclude, &[], &OBJ_DIR)? {
+            let tree = Commit::load(&hash, &OBJ_DIR)?.tree;
+            list_tree_objects(&tree, "", &OBJ_DIR, &mut seen)?;
+        }
+
+        for hash in &commits {
+            let tree = Commit::load(hash, &OBJ_DIR)?.tree;
+            for (object, path) in list_tree_objects(&tree, "", &OBJ_DIR, &mut seen)? {
+                if path.is_empty() {
+                    println!("{}", object);
+                } else {
+                    println!("{} {}", object, path);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the revisions into the commits to start from and the commits
+/// whose history is excluded
+pub fn parse_revisions(revisions: &[String]) -> Result<(Vec<String>, Vec<String>)> {
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+
+    for revision in revisions {
+        if let Some((from, to)) = revision.split_once("..") {
+            exclude.push(resolve_or_head(from)?);
+            include.push(resolve_or_head(to)?);
+        } else if let Some(excluded) = revision.strip_prefix('^') {
+            exclude.push(resolve_commit(&VOX_DIR, excluded)?);
+        } else {
+            include.push(resolve_commit(&VOX_DIR, revision)?);
+        }
+    }
+    Ok((include, exclude))
+}
+
+/// An empty side of a range stands for HEAD
+fn resolve_or_head(revision: &str) -> Result<String> {
+    resolve_commit(&VOX_DIR, if revision.is_empty() { "HEAD" } else { revision })
+}
+
+/// Returns the commits every ref and HEAD point to
+fn all_tips() -> Result<Vec<String>> {
+    let mut tips = Vec::new();
+    for (name, _) in list_refs(&VOX_DIR)? {
+        tips.push(resolve_commit(&VOX_DIR, &name)?);
+    }
+    tips.extend(resolve_head(&VOX_DIR)?);
+    Ok(tips)
+}
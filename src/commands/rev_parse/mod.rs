@@ -0,0 +1 @@
+pub mod rev_parse;
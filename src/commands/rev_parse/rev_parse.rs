@@ -0,0 +1,60 @@
+use crate::error::VoxError;
+use crate::storage::objects::ObjectStorage;
+use crate::storage::revision::{abbreviate, abbreviated_ref_name, resolve_revision};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+
+/// Options of the `rev-parse` command
+#[derive(Debug, Default)]
+pub struct RevParseOptions {
+    /// Print the shortest unique abbreviation of at least this many characters
+    pub short: Option<usize>,
+    /// Require exactly one revision naming an existing object
+    pub verify: bool,
+    /// Print the short name of the ref instead of a hash
+    pub abbrev_ref: bool,
+}
+
+/// Prints the object name of every revision, one per line
+///
+/// # Arguments
+/// - `revisions`: the revisions to resolve (e.g., "HEAD", "main~2", "v1.0^")
+/// - `options`: how to check and print them
+///
+pub fn rev_parse_command(revisions: &[String], options: &RevParseOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+    if options.verify && revisions.len() != 1 {
+        bail!("Needed a single revision");
+    }
+
+    for revision in revisions {
+        println!("{}", rev_parse(revision, options)?);
+    }
+    Ok(())
+}
+
+fn rev_parse(revision: &str, options: &RevParseOptions) -> Result<String> {
+    if options.abbrev_ref {
+        return abbreviated_ref_name(&VOX_DIR, revision);
+    }
+
+    let hash = resolve_revision(&VOX_DIR, revision)?;
+
+    // Refs can point to objects that were never fetched or were pruned
+    if options.verify {
+        let storage = ObjectStorage {
+            dir: OBJ_DIR.to_path_buf(),
+        };
+        if !storage.find_by_prefix(&hash)?.contains(&hash) {
+            bail!("Needed a single revision: object {} is missing", hash);
+        }
+    }
+
+    match options.short {
+        Some(min_len) => abbreviate(&OBJ_DIR, &hash, min_len)
+            .with_context(|| format!("Failed to abbreviate {}", hash)),
+        None => Ok(hash),
+    }
+}
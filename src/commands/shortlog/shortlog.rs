@@ -0,0 +1,131 @@
+use crate::commands::rev_list::rev_list::parse_revisions;
+use crate::error::VoxError;
+use crate::storage::mailmap::Mailmap;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::list_commits;
+use crate::storage::objects::Loadable;
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::Result;
+use colored::*;
+use std::collections::BTreeMap;
+
+/// Options of the `shortlog` command
+#[derive(Debug, Default)]
+pub struct ShortlogOptions {
+    /// Order the authors by their number of commits instead of by name
+    pub numbered: bool,
+    /// Only print the number of commits of each author
+    pub summary: bool,
+    /// Show the email of each author next to the name
+    pub email: bool,
+}
+
+/// Summarizes the history by author, listing the subject of each commit
+///
+/// # Arguments
+/// - `revisions`: the history to summarize, as for `rev-list` (defaults to HEAD)
+/// - `options`: how to group and what to print
+///
+pub fn shortlog_command(revisions: &[String], options: &ShortlogOptions) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    let revisions = match revisions.is_empty() {
+        true => vec!["HEAD".to_string()],
+        false => revisions.to_vec(),
+    };
+    let (include, exclude) = parse_revisions(&revisions)?;
+
+    // Authors are grouped by their canonical identity
+    let mailmap = Mailmap::load(&VOX_DIR, &OBJ_DIR)?;
+    let mut commits = Vec::new();
+    for hash in list_commits(&include, &exclude, &OBJ_DIR)? {
+        let mut commit = Commit::load(&hash, &OBJ_DIR)?;
+        commit.author = mailmap.map(&commit.author);
+        commits.push(commit);
+    }
+    // Release notes read from the oldest change to the newest
+    commits.reverse();
+
+    for (author, subjects) in group_by_author(&commits, options) {
+        if options.summary {
+            println!("{:>6}\t{}", subjects.len(), author);
+            continue;
+        }
+        println!("{} ({}):", author.bold(), subjects.len());
+        for subject in subjects {
+            println!("      {}", subject);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Groups the commit subjects by author, sorted by name or, when `numbered`,
+/// by the number of commits
+fn group_by_author<'a>(
+    commits: &'a [Commit],
+    options: &ShortlogOptions,
+) -> Vec<(&'a str, Vec<&'a str>)> {
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for commit in commits {
+        let author = match options.email {
+            true => commit.author.as_str(),
+            false => commit
+                .author
+                .split_once(" <")
+                .map_or(commit.author.as_str(), |(name, _)| name),
+        };
+        let subject = commit.message.lines().next().unwrap_or_default();
+        groups.entry(author).or_default().push(subject);
+    }
+
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    if options.numbered {
+        // Stable, so authors with as many commits stay sorted by name
+        groups.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(author: &str, message: &str) -> Commit {
+        Commit::new(
+            "tree".to_string(),
+            None,
+            author.to_string(),
+            message.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_group_by_author() {
+        let commits = vec![
+            commit("Bob <bob@example.com>", "Add parser\n\nDetails"),
+            commit("Alice <alice@example.com>", "Fix typo"),
+            commit("Bob <bob@example.com>", "Add lexer"),
+        ];
+
+        let groups = group_by_author(&commits, &ShortlogOptions::default());
+        assert_eq!(
+            groups,
+            vec![
+                ("Alice", vec!["Fix typo"]),
+                ("Bob", vec!["Add parser", "Add lexer"]),
+            ]
+        );
+
+        let options = ShortlogOptions {
+            numbered: true,
+            email: true,
+            ..Default::default()
+        };
+        let groups = group_by_author(&commits, &options);
+        assert_eq!(groups[0].0, "Bob <bob@example.com>");
+        assert_eq!(groups[1].0, "Alice <alice@example.com>");
+    }
+}
@@ -1,7 +1,11 @@
+use crate::commands::verify::verify::signature_status;
 use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::load_commit;
 use crate::storage::objects::tree::read_tree;
-use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_TREE};
-use crate::{commands::commit::commit::get_current_commit, storage::objects::Loadable};
+use crate::storage::revision::resolve_commit;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_TREE, VOX_DIR};
+use crate::storage::notes::Notes;
+use crate::storage::objects::Loadable;
 use anyhow::Result;
 use chrono::{DateTime, Local};
 use colored::*;
@@ -17,23 +21,19 @@ use std::path::PathBuf;
 /// - Parent commit (if available)
 ///
 /// # Arguments
-/// - `commit_ref`: The commit reference (e.g., "HEAD" or a commit hash).
+/// - `commit_ref`: The commit reference (e.g., "HEAD", "main~2", "HEAD@{1}" or a commit hash).
 ///
 pub fn show_command(commit_ref: &str) -> Result<()> {
     // Resolve the commit hash
-    let commit_hash = if commit_ref == "HEAD" {
-        // If "HEAD" is provided, get the current commit hash
-        get_current_commit()?.ok_or_else(|| anyhow::anyhow!("No commits yet!"))?
-    } else {
-        // Otherwise, use the provided commit reference
-        commit_ref.to_string()
-    };
+    let commit_hash = resolve_commit(&VOX_DIR, commit_ref)?;
 
     // Load the commit object
-    let commit = Commit::load(&commit_hash, &PathBuf::from(&*OBJ_DIR))?;
+    let commit = load_commit(&commit_hash, &OBJ_DIR)?;
+
+    let note = Notes::load(&VOX_DIR, &OBJ_DIR)?.get(&commit_hash, &OBJ_DIR)?;
 
     // Print the commit details
-    print_commit_details(&commit_hash, &commit)?;
+    print_commit_details(&commit_hash, &commit, note.as_deref())?;
 
     Ok(())
 }
@@ -43,8 +43,9 @@ pub fn show_command(commit_ref: &str) -> Result<()> {
 /// # Arguments
 /// - `hash`: The commit hash.
 /// - `commit`: The commit object.
+/// - `note`: The note attached to the commit, if any.
 ///
-fn print_commit_details(hash: &str, commit: &Commit) -> Result<()> {
+fn print_commit_details(hash: &str, commit: &Commit, note: Option<&str>) -> Result<()> {
     let local_date: DateTime<Local> = commit.timestamp.with_timezone(&Local);
     let formatted_date = local_date.format("%Y-%m-%d %H:%M:%S %z");
 
@@ -53,7 +54,19 @@ fn print_commit_details(hash: &str, commit: &Commit) -> Result<()> {
     println!("{} {}", "Commit:".yellow(), hash.bright_purple()); // Commit hash
     println!("{} {}", "Author:".cyan(), commit.author); // Author
     println!("{} {}", "Date:".cyan(), formatted_date); // Commit date
+    if commit.committer != commit.author {
+        println!("{} {}", "Committer:".cyan(), commit.committer);
+    }
+    if let Some(status) = signature_status(commit) {
+        println!("{} {}", "Signature:".cyan(), status);
+    }
     println!("\n{}", commit.message.bright_white()); // Commit message
+    if let Some(note) = note {
+        println!("{}", "Notes:".cyan());
+        for line in note.lines() {
+            println!("    {}", line);
+        }
+    }
     println!("{}", "=".repeat(70).blue());
 
     // Print changes
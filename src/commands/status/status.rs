@@ -1,11 +1,63 @@
 use crate::commands::commit::commit::get_current_commit;
-use crate::commands::index::index::Index;
+use crate::commands::config::commands::branch_upstream;
+use crate::commands::index::fsmonitor::FsMonitor;
+use crate::commands::index::index::{Index, IndexEntry};
+use crate::storage::convert::Converter;
+use crate::storage::fsmonitor;
+use crate::storage::ignore::IgnoreMatcher;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::graph::{ahead_behind, load_commit};
+use crate::storage::objects::tree::read_tree;
+use crate::storage::pathspec::Pathspec;
+use crate::storage::platform::{self, FileStat, MODE_GITLINK};
+use crate::storage::refs::ref_path;
+use crate::storage::submodules::{is_nested_repository, submodule_head};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
 use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use rayon::prelude::*;
 use std::collections::hash_set::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
-use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+
+/// How `vox status` shows the changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    /// Sections of staged, unstaged and untracked files with hints
+    #[default]
+    Long,
+    /// One `XY path` line per changed file, colored
+    Short,
+    /// The short format without colors, which stays the same across
+    /// versions for scripts to parse
+    Porcelain,
+}
+
+/// Which untracked files `vox status` lists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum UntrackedFiles {
+    /// None of them
+    No,
+    /// Untracked files, with the directories holding no tracked files shown
+    /// as a single `dir/` entry
+    #[default]
+    Normal,
+    /// Every untracked file
+    All,
+}
+
+/// How `vox status` shows the state of the work tree
+#[derive(Debug, Default)]
+pub struct StatusOptions {
+    pub format: StatusFormat,
+    /// End the lines of the short formats with NUL rather than newline
+    pub nul_terminated: bool,
+    pub untracked: UntrackedFiles,
+    /// Also list the ignored files
+    pub ignored: bool,
+}
 
 /// Entry point for the `status` command.
 /// Displays the current status of the working directory, including:
@@ -13,16 +65,261 @@ use walkdir::WalkDir;
 /// - Modified files
 /// - Deleted files
 /// - Untracked files
-pub fn status_command() -> Result<()> {
-    let (added, modified, deleted, untracked) = get_status(Path::new("."))?;
+///
+/// Only the files matched by `pathspec` are shown. Ignored files are left
+/// out unless `options.ignored` is set.
+///
+/// The short and porcelain formats print a line per file instead, ended by
+/// a NUL rather than a newline if `nul_terminated`, see [`format_short`].
+pub fn status_command(pathspec: &Pathspec, options: &StatusOptions) -> Result<()> {
+    if options.format != StatusFormat::Long {
+        let entries = short_status(Path::new("."), pathspec, options)?;
+        let color = options.format == StatusFormat::Short;
+        print!("{}", format_short(&entries, color, options.nul_terminated));
+        return Ok(());
+    }
+
+    let status = scan_status(Path::new("."), pathspec)?;
+    let (untracked, ignored) = listed_untracked(&status, options);
 
     // Retrieve the current commit hash
     let current_commit = get_current_commit()?;
+    let tracking = match (get_current_branch(), &current_commit) {
+        (Ok(branch), Some(commit)) => tracking_status(&branch, commit)?,
+        _ => None,
+    };
 
-    print_status(&added, &modified, &deleted, &untracked, current_commit);
+    print_status(&status, &untracked, &ignored, current_commit, tracking);
     Ok(())
 }
 
+/// The untracked and ignored files to list, as `options` asks
+fn listed_untracked(status: &FileStatus, options: &StatusOptions) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let untracked = match options.untracked {
+        UntrackedFiles::No => Vec::new(),
+        UntrackedFiles::Normal => collapse_dirs(&status.untracked, &status.tracked),
+        UntrackedFiles::All => status.untracked.clone(),
+    };
+    let ignored = match (options.ignored, options.untracked) {
+        (false, _) => Vec::new(),
+        (true, UntrackedFiles::All) => status.ignored.clone(),
+        (true, _) => {
+            let occupied: Vec<PathBuf> =
+                status.tracked.iter().chain(&status.untracked).cloned().collect();
+            collapse_dirs(&status.ignored, &occupied)
+        }
+    };
+    (untracked, ignored)
+}
+
+/// Shows the files of each directory that holds none of the `occupied`
+/// files as the directory alone, e.g. `build/`
+///
+/// `paths` are expected to be sorted.
+fn collapse_dirs(paths: &[PathBuf], occupied: &[PathBuf]) -> Vec<PathBuf> {
+    let occupied_dirs: HashSet<&Path> =
+        occupied.iter().flat_map(|path| path.ancestors().skip(1)).collect();
+
+    let mut collapsed: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        let top_dir = path
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty() && !occupied_dirs.contains(dir))
+            .last();
+        let shown = match top_dir {
+            Some(dir) => PathBuf::from(format!("{}/", dir.display())),
+            None => path.clone(),
+        };
+        if collapsed.last() != Some(&shown) {
+            collapsed.push(shown);
+        }
+    }
+    collapsed
+}
+
+/// Describes how the current branch compares with its upstream, if it has
+/// one, e.g. "Your branch is ahead of 'origin/main' by 2 commits."
+fn tracking_status(branch: &str, commit: &str) -> Result<Option<String>> {
+    let Some(upstream) = branch_upstream(branch) else {
+        return Ok(None);
+    };
+    let upstream_hash = match fs::read_to_string(ref_path(&VOX_DIR, &upstream.tracking_ref())) {
+        Ok(hash) => hash.trim().to_string(),
+        Err(_) => {
+            return Ok(Some(format!(
+                "Your branch is based on '{}', but the upstream is gone.",
+                upstream.short_name()
+            )))
+        }
+    };
+    let (ahead, behind) = ahead_behind(commit, &upstream_hash, &OBJ_DIR)?;
+    Ok(Some(divergence_message(&upstream.short_name(), ahead, behind)))
+}
+
+/// The status message for a branch `ahead` and `behind` its upstream
+fn divergence_message(upstream: &str, ahead: usize, behind: usize) -> String {
+    let commits = |count: usize| match count {
+        1 => "1 commit".to_string(),
+        count => format!("{} commits", count),
+    };
+    match (ahead, behind) {
+        (0, 0) => format!("Your branch is up to date with '{}'.", upstream),
+        (ahead, 0) => format!("Your branch is ahead of '{}' by {}.", upstream, commits(ahead)),
+        (0, behind) => format!(
+            "Your branch is behind '{}' by {}, and can be fast-forwarded.",
+            upstream,
+            commits(behind)
+        ),
+        (ahead, behind) => format!(
+            "Your branch and '{}' have diverged,\n\
+             and have {} and {} different commits each, respectively.",
+            upstream, ahead, behind
+        ),
+    }
+}
+
+/// The state of a changed file, as shown by the short format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortStatus {
+    /// How the index differs from HEAD: `A`dded, `M`odified, `D`eleted or
+    /// a space, `?` for untracked files and `!` for ignored ones
+    pub index: char,
+    /// How the work tree differs from the index: `M`odified, `D`eleted or
+    /// a space, `?` for untracked files and `!` for ignored ones
+    pub work_tree: char,
+    pub path: PathBuf,
+}
+
+/// Lists the files whose staged or unstaged content differs, and the
+/// untracked and ignored files `options` asks for, sorted by path
+pub fn short_status(
+    repo_path: &Path,
+    pathspec: &Pathspec,
+    options: &StatusOptions,
+) -> Result<Vec<ShortStatus>> {
+    let status = scan_status(repo_path, pathspec)?;
+    let (untracked, ignored) = listed_untracked(&status, options);
+
+    let mut index = Index::new();
+    let index_path = repo_path.join(".vox/index");
+    if index_path.exists() {
+        index.read_from_file(&index_path)?;
+    }
+
+    // Staged changes, by comparing the index with the files of HEAD
+    let mut head_files = head_files()?;
+    let mut states: BTreeMap<PathBuf, (char, char)> = BTreeMap::new();
+    for (path, entry) in index.get_entries() {
+        if !pathspec.matches(path) {
+            continue;
+        }
+        let state = match head_files.remove(path) {
+            None => 'A',
+            Some(head) if head == (entry.stat().tree_mode(), entry.hash.to_hex()) => continue,
+            Some(_) => 'M',
+        };
+        states.insert(path.clone(), (state, ' '));
+    }
+    for path in head_files.into_keys().filter(|path| pathspec.matches(path)) {
+        states.insert(path, ('D', ' '));
+    }
+
+    for path in status.modified {
+        states.entry(path).or_insert((' ', ' ')).1 = 'M';
+    }
+    for path in status.deleted {
+        states.entry(path).or_insert((' ', ' ')).1 = 'D';
+    }
+    for path in untracked {
+        states.insert(path, ('?', '?'));
+    }
+    for path in ignored {
+        states.insert(path, ('!', '!'));
+    }
+
+    Ok(states
+        .into_iter()
+        .map(|(path, (index, work_tree))| ShortStatus {
+            index,
+            work_tree,
+            path,
+        })
+        .collect())
+}
+
+/// The mode and hash of every file of HEAD, none on an unborn branch
+fn head_files() -> Result<HashMap<PathBuf, (u32, String)>> {
+    let Some(head) = get_current_commit()? else {
+        return Ok(HashMap::new());
+    };
+    let tree = load_commit(&head, &OBJ_DIR)?.tree;
+    let files = read_tree(&tree, &OBJ_DIR)
+        .and_then(|tree| tree.flatten(&OBJ_DIR))
+        .with_context(|| format!("Failed to load tree {}", tree))?;
+    files
+        .entries
+        .into_iter()
+        .map(|entry| {
+            let mode = u32::from_str_radix(&entry.mode, 8)
+                .with_context(|| format!("Invalid mode {} of {}", entry.mode, entry.name))?;
+            Ok((PathBuf::from(entry.name), (mode, entry.object_hash)))
+        })
+        .collect()
+}
+
+/// Formats file states as `XY path` lines
+///
+/// `X` is the state of the index and `Y` the state of the work tree, e.g.
+/// `M ` for a staged change, ` M` for an unstaged one, `A ` for a new file
+/// and `??` for an untracked one. Paths with quotes, backslashes or control
+/// characters are quoted C-style, unless `nul_terminated`, where each line
+/// ends with a NUL instead of a newline and paths are written as is.
+pub fn format_short(entries: &[ShortStatus], color: bool, nul_terminated: bool) -> String {
+    let mut output = String::new();
+    for entry in entries {
+        let path = entry.path.to_string_lossy();
+        let path = match nul_terminated {
+            true => path.into_owned(),
+            false => quote_path(&path),
+        };
+        let (index, work_tree) = (entry.index.to_string(), entry.work_tree.to_string());
+        match color {
+            true if entry.index == '?' => {
+                output.push_str(&format!("{} {}", "??".red(), path))
+            }
+            true if entry.index == '!' => {
+                output.push_str(&format!("{} {}", "!!".dimmed(), path))
+            }
+            true => output.push_str(&format!("{}{} {}", index.green(), work_tree.red(), path)),
+            false => output.push_str(&format!("{}{} {}", index, work_tree, path)),
+        }
+        output.push(if nul_terminated { '\0' } else { '\n' });
+    }
+    output
+}
+
+/// Quotes a path C-style if it has characters that would break the line
+/// based output apart
+fn quote_path(path: &str) -> String {
+    if !path.chars().any(|c| c == '"' || c == '\\' || c.is_control()) {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
 /// Represents the status of files in the working directory.
 #[derive(Default)]
 struct FileStatus {
@@ -30,14 +327,119 @@ struct FileStatus {
     modified: Vec<PathBuf>,  // Files modified after being staged
     deleted: Vec<PathBuf>,   // Files deleted from the working directory
     untracked: Vec<PathBuf>, // Files not tracked by the index
+    ignored: Vec<PathBuf>,   // Untracked files matched by an ignore rule
+    tracked: Vec<PathBuf>,   // Every file of the index
+}
+
+/// How a tracked file compares with its index entry
+enum EntryState {
+    Unchanged,
+    /// Unchanged, but with metadata that differs from the recorded one, or
+    /// that can't be trusted yet: recording it saves reading the content
+    /// again next time
+    Refreshed(FileStat),
+    Modified,
+    Deleted,
+}
+
+/// Returns true if the directory `dir`, relative to `repo_path`, is left out
+/// of the work tree: the repository itself and the work trees of submodules
+///
+/// Build output is left out by ignoring it in `.voxignore`.
+pub(crate) fn is_skipped_dir(repo_path: &Path, dir: &Path) -> bool {
+    dir == Path::new(".vox")
+        || dir == Path::new(".git")
+        || is_nested_repository(&repo_path.join(dir))
+}
+
+/// Compares the work tree copy of the file of `entry` with it
+fn entry_state(repo_path: &Path, entry: &IndexEntry, index_mtime: u64) -> Result<EntryState> {
+    let full_path = repo_path.join(&entry.path);
+
+    // Submodules are compared by the commit they have checked out, an
+    // uninitialized submodule isn't a change
+    if entry.stat().tree_mode() == MODE_GITLINK {
+        let head = match is_nested_repository(&full_path) {
+            true => submodule_head(&full_path)?,
+            false => None,
+        };
+        return Ok(match head {
+            Some(head) if head != entry.hash.to_hex() => EntryState::Modified,
+            _ => EntryState::Unchanged,
+        });
+    }
+
+    // Check if the file exists in the working directory, a dangling
+    // symlink still counts as present
+    if fs::symlink_metadata(&full_path).is_err() {
+        return Ok(EntryState::Deleted);
+    }
+    let stat = FileStat::from_path(&full_path)?;
+    if file_changed(repo_path, &stat, entry, index_mtime)? {
+        Ok(EntryState::Modified)
+    } else if !stat.matches(&entry.stat()) || entry.is_racy(index_mtime) {
+        Ok(EntryState::Refreshed(stat))
+    } else {
+        Ok(EntryState::Unchanged)
+    }
+}
+
+/// Returns true if the tracked file of `entry` in the work tree at
+/// `repo_path`, with the metadata `stat`, no longer has the content staged
+/// in `entry`
+///
+/// Matching metadata is trusted, unless the entry is racy (see
+/// [`IndexEntry::is_racy`]) or the file is a symlink, which may be
+/// retargeted keeping its size and mtime. A different size is a change,
+/// except for smudged entries whose size was written as 0. Otherwise the
+/// content is hashed and compared with the staged one, so files that were
+/// only touched aren't reported.
+fn file_changed(
+    repo_path: &Path,
+    stat: &FileStat,
+    entry: &IndexEntry,
+    index_mtime: u64,
+) -> Result<bool> {
+    let recorded = entry.stat();
+    if stat.is_symlink() != recorded.is_symlink()
+        || stat.is_executable() != recorded.is_executable()
+    {
+        return Ok(true);
+    }
+    if stat.matches(&recorded) && !stat.is_symlink() && !entry.is_racy(index_mtime) {
+        return Ok(false);
+    }
+    if stat.size != recorded.size && recorded.size != 0 {
+        return Ok(true);
+    }
+    // Line endings are compared as they would be staged
+    let mut converter = Converter::new(repo_path);
+    let full_path = repo_path.join(&entry.path);
+    let hash =
+        Blob::hash_worktree(&full_path, &entry.path, &mut converter, entry.hash.format())?;
+    Ok(hash != entry.hash)
 }
 
 /// Computes the status of the working directory compared to the index.
 /// # Arguments
 /// - `repo_path`: The path to the repository root.
+/// - `pathspec`: Limits the status to the files it matches.
+///
+/// Ignored files aren't counted as untracked.
 pub fn get_status(
     repo_path: &Path,
+    pathspec: &Pathspec,
 ) -> Result<(Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>)> {
+    let status = scan_status(repo_path, pathspec)?;
+    Ok((
+        status.added,
+        status.modified,
+        status.deleted,
+        status.untracked,
+    ))
+}
+
+fn scan_status(repo_path: &Path, pathspec: &Pathspec) -> Result<FileStatus> {
     let mut index = Index::new();
     let index_path = repo_path.join(".vox/index");
 
@@ -46,90 +448,117 @@ pub fn get_status(
         index.read_from_file(&index_path)?;
     }
 
+    // Entries staged no earlier than the index was written may be racy
+    let index_mtime = fs::metadata(&index_path)
+        .map(|metadata| platform::mtime_secs(&metadata))
+        .unwrap_or_default();
+
     let mut status = FileStatus::default();
 
     let mut processed_files = HashSet::new();
+    let mut refreshed = Vec::new();
+
+    // With `vox watch` running, entries that were unchanged at the last
+    // token and that it saw no change to since are skipped
+    let answer = fsmonitor::query(&repo_path.join(".vox"), &index.fsmonitor.token);
+    let watched = |path: &Path| {
+        answer
+            .as_ref()
+            .is_some_and(|answer| index.fsmonitor.is_valid(path) && !answer.is_changed(path))
+    };
 
     // Iterate over files in the index
-    for (path, index_entry) in index.get_entries().iter() {
-        processed_files.insert(path.clone());
-        let full_path = repo_path.join(path);
-
-        // Check if the file exists in the working directory
-        if !full_path.exists() {
-            // File is deleted
-            status.deleted.push(path.clone());
-        } else {
-            // Compare metadata to detect modifications
-            let metadata = fs::metadata(&full_path)?;
-            if metadata.mtime() as u64 != index_entry.mtime
-                || metadata.size() as u32 != index_entry.size
-            {
-                // File is modified
-                status.modified.push(path.clone());
-            } else {
-                // File is added (unchanged)
-                status.added.push(path.clone());
+    // Entries are compared with the work tree in parallel, each worker only
+    // reading the index, and the results are merged afterwards
+    processed_files.extend(index.get_entries().keys().cloned());
+    let mut states = index
+        .get_entries()
+        .par_iter()
+        .filter(|(path, _)| pathspec.matches(path))
+        .map(|(path, entry)| match watched(path) {
+            true => Ok((path.clone(), EntryState::Unchanged)),
+            false => Ok((path.clone(), entry_state(repo_path, entry, index_mtime)?)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    states.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    // Entries outside the pathspec stay valid as long as the watcher saw no
+    // change to them
+    let mut valid: BTreeSet<PathBuf> = index
+        .fsmonitor
+        .valid
+        .iter()
+        .filter(|path| !pathspec.matches(path) && watched(path))
+        .cloned()
+        .collect();
+
+    for (path, state) in states {
+        // Submodules change without their superproject's watcher noticing
+        if matches!(state, EntryState::Unchanged | EntryState::Refreshed(_))
+            && index.get_entries()[&path].stat().tree_mode() != MODE_GITLINK
+        {
+            valid.insert(path.clone());
+        }
+        match state {
+            EntryState::Unchanged => status.added.push(path),
+            EntryState::Refreshed(stat) => {
+                refreshed.push((path.clone(), stat));
+                status.added.push(path);
             }
+            EntryState::Modified => status.modified.push(path),
+            EntryState::Deleted => status.deleted.push(path),
         }
     }
 
-    // Walk through the working directory to find untracked files
-    for entry in WalkDir::new(repo_path)
-        .min_depth(1)
-        .into_iter()
-        .filter_entry(|e| {
-            // Ignore specific directories
-            !e.path().starts_with(repo_path.join(".vox"))
-                && !e.path().starts_with(repo_path.join(".git"))
-                && !e.path().starts_with(repo_path.join("target"))
-        })
-    {
-        let entry = entry.context("Failed to read directory entry")?;
-        if !entry.file_type().is_file() {
-            continue; // Skip non-file entries
-        }
-
-        // Get the relative path of the file
-        let relative_path = entry.path().strip_prefix(repo_path)?.to_path_buf();
+    // List the working directory, re-reading only directories that changed
+    // since the last run
+    let files = index.untracked_cache.scan(repo_path, |dir| is_skipped_dir(repo_path, dir))?;
 
+    let mut ignore = IgnoreMatcher::new(repo_path)?;
+    for relative_path in files {
         // Check if the file is already processed
-        if !processed_files.contains(&relative_path) {
-            if index.get_entries().contains_key(&relative_path) {
-                // File is in the index; check if it's modified
-                let metadata = fs::metadata(entry.path())?;
-                let index_entry = index.get_entries().get(&relative_path).unwrap();
-                if metadata.mtime() as u64 != index_entry.mtime
-                    || metadata.size() as u32 != index_entry.size
-                {
-                    // File is modified
-                    status.modified.push(relative_path);
-                }
-            } else {
-                // File is untracked
-                status.untracked.push(relative_path);
+        if !processed_files.contains(&relative_path) && pathspec.matches(&relative_path) {
+            // File is untracked, unless a rule ignores it
+            match ignore.is_ignored(&relative_path, false)? {
+                true => status.ignored.push(relative_path),
+                false => status.untracked.push(relative_path),
             }
         }
     }
+    status.tracked = processed_files.into_iter().collect();
 
-    // Return the computed status
-    Ok((
-        status.added,
-        status.modified,
-        status.deleted,
-        status.untracked,
-    ))
+    // Keep the refreshed metadata and listings for the next run
+    for (path, stat) in &refreshed {
+        index.refresh_entry(path, stat);
+    }
+    let monitor = answer.map(|answer| FsMonitor {
+        token: answer.token,
+        valid,
+    });
+    let monitor_changed = monitor.as_ref().is_some_and(|monitor| *monitor != index.fsmonitor);
+    if let Some(monitor) = monitor {
+        index.fsmonitor = monitor;
+    }
+    if (index.untracked_cache.is_dirty() || !refreshed.is_empty() || monitor_changed)
+        && index_path.exists()
+    {
+        index.write_to_file(&index_path)?;
+    }
+
+    Ok(status)
 }
 
 /// Prints the status of the working directory to the console.
 ///
 fn print_status(
-    added: &[PathBuf],
-    modified: &[PathBuf],
-    deleted: &[PathBuf],
+    status: &FileStatus,
     untracked: &[PathBuf],
+    ignored: &[PathBuf],
     current_commit: Option<String>,
+    tracking: Option<String>,
 ) {
+    let (added, modified, deleted) = (&status.added, &status.modified, &status.deleted);
+
     // Get the current branch name
     let branch_name = match get_current_branch() {
         Ok(name) => name,
@@ -141,9 +570,17 @@ fn print_status(
     if let Some(commit) = current_commit {
         println!("Current commit [{}]", &commit[..7]); // Display the first 7 characters of the commit hash
     }
+    if let Some(tracking) = tracking {
+        println!("{}\n", tracking);
+    }
 
     // Check if the working tree is clean
-    if added.is_empty() && modified.is_empty() && deleted.is_empty() && untracked.is_empty() {
+    if added.is_empty()
+        && modified.is_empty()
+        && deleted.is_empty()
+        && untracked.is_empty()
+        && ignored.is_empty()
+    {
         println!("✓ Working tree clean");
         return;
     }
@@ -183,6 +620,15 @@ fn print_status(
         println!();
     }
 
+    // Print ignored files
+    if !ignored.is_empty() {
+        println!("Ignored files:\n");
+        for path in ignored {
+            println!("\t{}", path.display().to_string().dimmed());
+        }
+        println!();
+    }
+
     // Print a summary message
     if !modified.is_empty() || !untracked.is_empty() {
         println!("no changes added to commit (use \"vox add\" and/or \"vox commit -a\")");
@@ -202,3 +648,95 @@ fn get_current_branch() -> Result<String> {
 
     Ok(branch.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_short() {
+        let entry = |index, work_tree, path: &str| ShortStatus {
+            index,
+            work_tree,
+            path: PathBuf::from(path),
+        };
+        let entries = [
+            entry('M', ' ', "src/main.rs"),
+            entry('A', 'M', "new file.txt"),
+            entry(' ', 'D', "tab\there"),
+            entry('?', '?', "quote\"d"),
+        ];
+
+        assert_eq!(
+            format_short(&entries, false, false),
+            "M  src/main.rs\nAM new file.txt\n D \"tab\\there\"\n?? \"quote\\\"d\"\n"
+        );
+        assert_eq!(
+            format_short(&entries, false, true),
+            "M  src/main.rs\0AM new file.txt\0 D tab\there\0?? quote\"d\0"
+        );
+    }
+
+    #[test]
+    fn test_file_changed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "before")?;
+        let stat = FileStat::from_path(&path)?;
+        let mut entry = IndexEntry::new(&path)?;
+        entry.path = PathBuf::from("file.txt");
+        entry.hash = entry.hash.format().digest(b"before");
+        let repo_path = dir.path();
+
+        // Written after the index, the entry is trusted
+        assert!(!file_changed(repo_path, &stat, &entry, stat.mtime + 1)?);
+        // Racy, a same-size edit is found by content
+        fs::write(&path, "after!")?;
+        let edited = FileStat::from_path(&path)?;
+        assert!(file_changed(repo_path, &edited, &entry, stat.mtime)?);
+
+        // Touched or smudged, the same content isn't a change
+        fs::write(&path, "before")?;
+        let mut touched = FileStat::from_path(&path)?;
+        touched.mtime += 10;
+        assert!(!file_changed(repo_path, &touched, &entry, stat.mtime + 1)?);
+        entry.size = 0;
+        assert!(!file_changed(repo_path, &touched, &entry, stat.mtime + 1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collapse_dirs() {
+        let paths = |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(PathBuf::from).collect() };
+        let untracked =
+            paths(&["build/a.o", "build/sub/b.o", "notes.txt", "src/new.rs", "src/x/y.rs"]);
+        let tracked = paths(&["src/main.rs", "Cargo.toml"]);
+
+        assert_eq!(
+            collapse_dirs(&untracked, &tracked),
+            paths(&["build/", "notes.txt", "src/new.rs", "src/x/"])
+        );
+        assert_eq!(collapse_dirs(&untracked, &untracked), untracked);
+    }
+
+    #[test]
+    fn test_divergence_message() {
+        assert_eq!(
+            divergence_message("origin/main", 0, 0),
+            "Your branch is up to date with 'origin/main'."
+        );
+        assert_eq!(
+            divergence_message("origin/main", 1, 0),
+            "Your branch is ahead of 'origin/main' by 1 commit."
+        );
+        assert_eq!(
+            divergence_message("origin/main", 0, 3),
+            "Your branch is behind 'origin/main' by 3 commits, and can be fast-forwarded."
+        );
+        assert_eq!(
+            divergence_message("origin/main", 2, 5),
+            "Your branch and 'origin/main' have diverged,\n\
+             and have 2 and 5 different commits each, respectively."
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use crate::commands::add::add::add_command;
+use crate::commands::branch::checkout::checkout_command;
+use crate::commands::clone::clone::local_repository_path;
+use crate::commands::config::commands::get_local_config;
+use crate::commands::config::config::{Config, PersistentConfig};
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::platform::{to_index_path, MODE_GITLINK};
+use crate::storage::repo_config::{find_vox_dir, RepoConfig};
+use crate::storage::submodules::{
+    is_nested_repository, submodule_head, Submodule, Voxmodules, VOXMODULES_FILE,
+};
+use crate::storage::utils::{in_directory, INDEX_FILE};
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use colored::Colorize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Subcommand)]
+pub enum SubmoduleCommands {
+    #[command(about = "Add a repository as a submodule")]
+    Add {
+        #[clap(help = "URL or path of the repository to add")]
+        url: String,
+
+        #[clap(help = "Where to place the submodule (defaults to the repository name)")]
+        path: Option<PathBuf>,
+
+        #[clap(long, help = "Name of the submodule (defaults to its path)")]
+        name: Option<String>,
+    },
+
+    #[command(about = "Register submodules from .voxmodules in the local config")]
+    Init {
+        #[clap(help = "Only initialize the submodules at these paths")]
+        paths: Vec<PathBuf>,
+    },
+
+    #[command(about = "Clone missing submodules and check out the recorded commits")]
+    Update {
+        #[clap(long, help = "Initialize submodules that aren't initialized yet")]
+        init: bool,
+
+        #[clap(help = "Only update the submodules at these paths")]
+        paths: Vec<PathBuf>,
+    },
+
+    #[command(about = "Show the status of the submodules")]
+    Status {
+        #[clap(help = "Only show the submodules at these paths")]
+        paths: Vec<PathBuf>,
+    },
+}
+
+pub fn submodule_command(command: &SubmoduleCommands) -> Result<()> {
+    match command {
+        SubmoduleCommands::Add { url, path, name } => {
+            add_submodule(url, path.as_deref(), name.as_deref())
+        }
+        SubmoduleCommands::Init { paths } => init_submodules(paths),
+        SubmoduleCommands::Update { init, paths } => {
+            if *init {
+                init_submodules(paths)?;
+            }
+            update_submodules(paths)
+        }
+        SubmoduleCommands::Status { paths } => submodule_status(paths),
+    }
+}
+
+/// Clones `url` into `path` if needed, declares it in `.voxmodules` and
+/// stages both the file and the submodule's commit
+fn add_submodule(url: &str, path: Option<&Path>, name: Option<&str>) -> Result<()> {
+    let path = match path {
+        Some(path) => to_index_path(path),
+        None => PathBuf::from(default_submodule_path(url)?),
+    };
+    let path_str = path.to_str().context("Invalid submodule path")?.to_string();
+    let name = name.unwrap_or(&path_str).to_string();
+
+    let mut modules = Voxmodules::load(Path::new("."))?;
+    modules.add(&name, &path_str, url)?;
+
+    if path.exists() {
+        if !is_nested_repository(&path) {
+            bail!("'{}' already exists and is not a vox repository", path_str);
+        }
+        println!("Adding existing repository at '{}' to the index", path_str);
+    } else {
+        println!("Cloning into '{}'...", path_str);
+        clone_local(url, &path)?;
+        checkout_head(&path)?;
+    }
+
+    modules.save(Path::new("."))?;
+    register_url(&name, url)?;
+    add_command(&[PathBuf::from(VOXMODULES_FILE), path])?;
+
+    println!(
+        "{} Added submodule '{}' -> {}",
+        "✓".green(),
+        name.bold(),
+        url.underline()
+    );
+    Ok(())
+}
+
+/// Copies the URLs of the selected submodules into the local config
+fn init_submodules(paths: &[PathBuf]) -> Result<()> {
+    let modules = Voxmodules::load(Path::new("."))?;
+    let selected = select_submodules(&modules, paths)?;
+
+    let config_path = get_local_config()?;
+    let mut config = Config::read_from_file(&config_path)?;
+    let mut config_changed = false;
+
+    for (name, submodule) in selected {
+        if config.submodule_url(name).is_some() {
+            continue;
+        }
+        config.set_submodule_url(name, &submodule.url);
+        config_changed = true;
+        println!(
+            "Submodule '{}' ({}) registered for path '{}'",
+            name, submodule.url, submodule.path
+        );
+    }
+
+    if config_changed {
+        config.write_to_file(&config_path)?;
+    }
+    Ok(())
+}
+
+/// Clones missing submodules and checks out the commits recorded in the index
+fn update_submodules(paths: &[PathBuf]) -> Result<()> {
+    let modules = Voxmodules::load(Path::new("."))?;
+    let selected = select_submodules(&modules, paths)?;
+    let config = Config::read_from_file(&get_local_config()?)?;
+    let index = read_index()?;
+
+    for (name, submodule) in selected {
+        let Some(url) = config.submodule_url(name) else {
+            println!(
+                "Skipping submodule '{}': not initialized (use \"vox submodule init\")",
+                submodule.path
+            );
+            continue;
+        };
+
+        let recorded = recorded_commit(&index, submodule).with_context(|| {
+            format!(
+                "No submodule commit recorded in the index for path '{}'",
+                submodule.path
+            )
+        })?;
+        let path = Path::new(&submodule.path);
+
+        if !is_nested_repository(path) {
+            println!("Cloning into '{}'...", submodule.path);
+            clone_local(url, path)?;
+        }
+
+        if submodule_head(path)?.as_deref() == Some(recorded.as_str()) {
+            continue;
+        }
+
+        in_directory(path, || checkout_command(&recorded, true, None)).with_context(|| {
+            format!(
+                "Failed to check out '{}' in submodule path '{}'",
+                recorded, submodule.path
+            )
+        })?;
+        println!(
+            "Submodule path '{}': checked out '{}'",
+            submodule.path, recorded
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints every submodule as `<flag><commit> <path>`
+///
+/// The flag is `-` if the submodule isn't cloned, `+` if it has a different
+/// commit checked out than the one recorded in the index, and a space
+/// otherwise. The commit shown is the one checked out, if any.
+fn submodule_status(paths: &[PathBuf]) -> Result<()> {
+    let modules = Voxmodules::load(Path::new("."))?;
+    let index = read_index()?;
+
+    for (_name, submodule) in select_submodules(&modules, paths)? {
+        let recorded = recorded_commit(&index, submodule).unwrap_or_default();
+        let path = Path::new(&submodule.path);
+
+        let head = match is_nested_repository(path) {
+            true => submodule_head(path)?,
+            false => None,
+        };
+        let (flag, hash) = match head {
+            None => ("-", recorded),
+            Some(head) if head != recorded => ("+", head),
+            Some(head) => (" ", head),
+        };
+
+        println!("{}{} {}", flag, hash, submodule.path);
+    }
+
+    Ok(())
+}
+
+/// Returns the submodules at `paths`, or all of them if `paths` is empty
+fn select_submodules<'a>(
+    modules: &'a Voxmodules,
+    paths: &[PathBuf],
+) -> Result<Vec<(&'a String, &'a Submodule)>> {
+    if paths.is_empty() {
+        return Ok(modules.submodule.iter().collect());
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let path = to_index_path(path);
+            let path = path.to_str().context("Invalid submodule path")?;
+            modules
+                .find_by_path(path)
+                .with_context(|| format!("No submodule at path '{}'", path))
+        })
+        .collect()
+}
+
+fn read_index() -> Result<Index> {
+    let mut index = Index::new();
+    if INDEX_FILE.exists() {
+        index.read_from_file(&INDEX_FILE)?;
+    }
+    Ok(index)
+}
+
+/// Returns the commit the index records for `submodule`
+fn recorded_commit(index: &Index, submodule: &Submodule) -> Option<String> {
+    index
+        .get_entry(Path::new(&submodule.path))
+        .filter(|entry| entry.stat().tree_mode() == MODE_GITLINK)
+        .map(|entry| entry.hash.to_hex())
+}
+
+/// Stores the URL of submodule `name` in the local config
+fn register_url(name: &str, url: &str) -> Result<()> {
+    let config_path = get_local_config()?;
+    let mut config = Config::read_from_file(&config_path)?;
+    config.set_submodule_url(name, url);
+    config.write_to_file(&config_path)
+}
+
+/// Derives the default submodule path from the last component of `url`
+fn default_submodule_path(url: &str) -> Result<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    let name = name.strip_suffix(".vox").unwrap_or(name);
+
+    if name.is_empty() || name == "." || name == ".." {
+        bail!(
+            "Couldn't guess a submodule path from '{}', please pass one",
+            url
+        );
+    }
+    Ok(name.to_string())
+}
+
+/// Clones the local repository at `url` into `dest`
+///
+/// The object database and refs are copied, the working tree is left empty
+/// until a commit is checked out
+fn clone_local(url: &str, dest: &Path) -> Result<()> {
+    let source = local_repository_path(url)?;
+    let source_vox = find_vox_dir(&source)
+        .ok_or_else(|| VoxError::NetworkError(format!("'{}' is not a vox repository", url)))?;
+
+    let dest_vox = dest.join(".vox");
+    fs::create_dir_all(&dest_vox)
+        .with_context(|| format!("Failed to create {}", dest_vox.display()))?;
+    for entry in WalkDir::new(&source_vox).min_depth(1) {
+        let entry = entry.context("Failed to read repository to clone")?;
+        let relative = entry.path().strip_prefix(&source_vox)?;
+
+        // The index describes the source's working tree, not the clone's,
+        // and a bare source marks itself as bare in its config
+        if relative == Path::new("index")
+            || relative == Path::new("config")
+            || entry.path().extension().is_some_and(|e| e == "lock")
+        {
+            continue;
+        }
+
+        let target = dest_vox.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+        }
+    }
+
+    RepoConfig::load(&source_vox)?
+        .with_bare(false)
+        .save(&dest_vox)?;
+    Index::new().write_to_file(&dest_vox.join("index"))
+}
+
+/// Checks out the branch HEAD points to in a freshly cloned repository
+fn checkout_head(path: &Path) -> Result<()> {
+    let head = fs::read_to_string(path.join(".vox/HEAD")).context("Failed to read HEAD file")?;
+    let target = match head.trim().strip_prefix("ref: refs/heads/") {
+        Some(branch) => branch.to_string(),
+        None => head.trim().to_string(),
+    };
+
+    if submodule_head(path)?.is_none() {
+        return Ok(());
+    }
+    in_directory(path, || checkout_command(&target, true, None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_submodule_path() -> Result<()> {
+        assert_eq!(default_submodule_path("../libfoo")?, "libfoo");
+        assert_eq!(default_submodule_path("/srv/repos/libfoo.vox/")?, "libfoo");
+        assert_eq!(default_submodule_path("file:///srv/libfoo")?, "libfoo");
+        assert!(default_submodule_path("..").is_err());
+        Ok(())
+    }
+}
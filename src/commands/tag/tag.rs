@@ -0,0 +1,157 @@
+use crate::commands::config::commands::{signing_key, user_identity};
+use crate::error::VoxError;
+use crate::storage::objects::read_object_header;
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::Storable;
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::{list_refs, ref_path};
+use crate::storage::revision::resolve_revision;
+use crate::storage::signing::sign;
+use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_TAG, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use colored::*;
+
+/// Options of the `tag` command
+#[derive(Debug, Default)]
+pub struct TagOptions {
+    /// Create an annotated tag object instead of a plain ref
+    pub annotate: bool,
+    /// Message of the annotated tag, implying `annotate`
+    pub message: Option<String>,
+    /// Sign the annotated tag with the configured signing key
+    pub sign: bool,
+    /// Replace an existing tag of the same name
+    pub force: bool,
+}
+
+/// Lists, creates or deletes tags
+///
+/// # Arguments
+/// - `name`: the tag to create or delete, tags are listed without one
+/// - `target`: what to tag (defaults to HEAD)
+/// - `delete`: delete the tag `name`
+/// - `list`: list the tags
+/// - `options`: how to create the tag
+///
+pub fn tag_command(
+    name: Option<&str>,
+    target: Option<&str>,
+    delete: bool,
+    list: bool,
+    options: &TagOptions,
+) -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    match name {
+        _ if list => list_tags(),
+        None if delete => bail!("Tag name required for deletion"),
+        None => list_tags(),
+        Some(name) if delete => delete_tag(name),
+        Some(name) => create_tag(name, target.unwrap_or("HEAD"), options),
+    }
+}
+
+fn list_tags() -> Result<()> {
+    for (name, hash) in list_refs(&VOX_DIR)? {
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        // Annotated tags show the first line of their message
+        match read_object_header(&hash, &OBJ_DIR) {
+            Ok((object_type, _)) if object_type == OBJ_TYPE_TAG => {
+                let message = Tag::load(&hash, &OBJ_DIR)?.message;
+                let subject = message.lines().next().unwrap_or_default();
+                println!("{:<20} {}", tag.yellow(), subject.dimmed());
+            }
+            _ => println!("{}", tag.yellow()),
+        }
+    }
+    Ok(())
+}
+
+fn create_tag(name: &str, target: &str, options: &TagOptions) -> Result<()> {
+    let object = resolve_revision(&VOX_DIR, target)?;
+    let ref_name = format!("refs/tags/{}", name);
+    let expected = match options.force {
+        true => ExpectedValue::Any,
+        false if ref_path(&VOX_DIR, &ref_name).exists() => bail!("Tag '{}' already exists", name),
+        false => ExpectedValue::Missing,
+    };
+
+    let annotated = options.annotate || options.message.is_some() || options.sign;
+    let hash = match annotated {
+        true => {
+            let message = options
+                .message
+                .as_deref()
+                .context("Annotated tags need a message (use -m)")?;
+            let (object_type, _) = read_object_header(&object, &OBJ_DIR)?;
+            let (tagger_name, tagger_email) = split_identity(&user_identity());
+
+            let mut tag = Tag {
+                object: object.clone(),
+                object_type,
+                tag: name.to_string(),
+                tagger: (tagger_name, tagger_email, Utc::now()),
+                message: message.trim().to_string(),
+                signature: None,
+            };
+            if options.sign {
+                let (format, key) = signing_key();
+                let signature =
+                    sign(&tag.payload()?, format, key.as_deref()).context("Failed to sign the tag")?;
+                tag.signature = Some(signature);
+            }
+            tag.save(&OBJ_DIR)?
+        }
+        false => object.clone(),
+    };
+
+    let mut transaction = RefTransaction::new(&VOX_DIR);
+    transaction.update(&ref_name, &hash, expected, &format!("tag: tagging {}", &object[..7]));
+    transaction.commit()?;
+
+    println!("Created tag {} at {}", name.yellow(), &object[..7]);
+    Ok(())
+}
+
+fn delete_tag(name: &str) -> Result<()> {
+    let ref_name = format!("refs/tags/{}", name);
+    let path = ref_path(&VOX_DIR, &ref_name);
+    if !path.exists() {
+        bail!("Tag '{}' not found", name);
+    }
+    let hash = std::fs::read_to_string(&path)?.trim().to_string();
+
+    let mut transaction = RefTransaction::new(&VOX_DIR);
+    transaction.delete(&ref_name, ExpectedValue::Hash(hash.clone()));
+    transaction.commit()?;
+
+    println!("Deleted tag '{}' (was {})", name, &hash[..7]);
+    Ok(())
+}
+
+/// Splits "Name <email>" into its name and email
+pub fn split_identity(identity: &str) -> (String, String) {
+    match identity.split_once(" <") {
+        Some((name, email)) => (name.to_string(), email.trim_end_matches('>').to_string()),
+        None => (identity.to_string(), String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_identity() {
+        assert_eq!(
+            split_identity("Jane Doe <jane@example.com>"),
+            ("Jane Doe".to_string(), "jane@example.com".to_string())
+        );
+        assert_eq!(split_identity("jane"), ("jane".to_string(), String::new()));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::commands::config::commands::verification_keyrings;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::Loadable;
+use crate::storage::revision::resolve_revision;
+use crate::storage::signing::{verify, Verification};
+use crate::storage::utils::{OBJ_DIR, VOX_DIR};
+use anyhow::{bail, Context, Result};
+use colored::*;
+
+/// Checks the signatures of the given commits
+///
+/// Commits are given as revisions, such as `HEAD~1` or a branch name. Fails
+/// unless every commit carries a good signature.
+pub fn verify_commit_command(commits: &[String]) -> Result<()> {
+    let keyrings = verification_keyrings();
+    let mut all_good = true;
+
+    for name in commits {
+        let hash = resolve_revision(&VOX_DIR, name)?;
+        let commit = Commit::load(&hash, &OBJ_DIR)
+            .with_context(|| format!("'{}' is not a commit", name))?;
+        let Some(signature) = &commit.signature else {
+            bail!("No signature found in commit {}", name);
+        };
+
+        let verification = verify(&commit.payload(), signature, &keyrings)
+            .with_context(|| format!("Couldn't verify commit {}", name))?;
+        all_good &= report(name, &verification);
+    }
+
+    if !all_good {
+        bail!("Signature verification failed");
+    }
+    Ok(())
+}
+
+/// Checks the signatures of the given annotated tags
+///
+/// Tags are given by name or as the hash of the tag object. Fails unless
+/// every tag carries a good signature.
+pub fn verify_tag_command(tags: &[String]) -> Result<()> {
+    let keyrings = verification_keyrings();
+    let mut all_good = true;
+
+    for name in tags {
+        let hash = resolve_revision(&VOX_DIR, name)?;
+        let tag = Tag::load(&hash, &OBJ_DIR)
+            .with_context(|| format!("'{}' is not an annotated tag", name))?;
+        let Some(signature) = &tag.signature else {
+            bail!("No signature found in tag {}", name);
+        };
+
+        let verification = verify(&tag.payload()?, signature, &keyrings)
+            .with_context(|| format!("Couldn't verify tag {}", name))?;
+        all_good &= report(name, &verification);
+    }
+
+    if !all_good {
+        bail!("Signature verification failed");
+    }
+    Ok(())
+}
+
+/// Describes the signature of `commit` for `show` and `log`
+///
+/// Returns `None` for unsigned commits. Verification errors, such as an
+/// unknown key, are described rather than returned.
+pub fn signature_status(commit: &Commit) -> Option<ColoredString> {
+    let signature = commit.signature.as_ref()?;
+    let status = match verify(&commit.payload(), signature, &verification_keyrings()) {
+        Ok(verification) if verification.good => verification.describe().green(),
+        Ok(verification) => verification.describe().red(),
+        Err(error) => format!("Can't check signature: {}", error).yellow(),
+    };
+    Some(status)
+}
+
+/// Prints the outcome of a verification, returns whether it was good
+fn report(name: &str, verification: &Verification) -> bool {
+    let description = verification.describe();
+    if verification.good {
+        println!("{}: {}", name, description.green());
+    } else {
+        println!("{}: {}", name, description.red());
+    }
+    verification.good
+}
@@ -0,0 +1,408 @@
+use crate::error::VoxError;
+use crate::storage::fsmonitor::{Answer, Changes};
+use crate::storage::utils::VOX_DIR;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Prefix of the files a query creates in `.vox` to know when the watcher
+/// caught up with the work tree
+const COOKIE_PREFIX: &str = "fsmonitor-cookie-";
+
+/// Paths changed while the watcher runs
+///
+/// Every change gets the next sequence number, and tokens are the id of the
+/// watcher followed by the last sequence number it handed out, so a query
+/// only has to look for the paths changed after the number in its token.
+#[derive(Debug)]
+struct ChangeLog {
+    /// Tells this watcher's tokens from those of one that ran before
+    id: String,
+    seq: u64,
+    /// Sequence number of the last time changes were lost, earlier tokens
+    /// can't be answered
+    reset_seq: u64,
+    /// Last sequence number at which each path changed
+    changed: HashMap<PathBuf, u64>,
+}
+
+impl ChangeLog {
+    fn new(id: String) -> Self {
+        Self {
+            id,
+            seq: 0,
+            reset_seq: 0,
+            changed: HashMap::new(),
+        }
+    }
+
+    /// Records a change to `path`, relative to the root of the work tree
+    fn record(&mut self, path: PathBuf) {
+        self.seq += 1;
+        self.changed.insert(path, self.seq);
+    }
+
+    /// Forgets every change, as when the kernel dropped events
+    fn reset(&mut self) {
+        self.seq += 1;
+        self.reset_seq = self.seq;
+        self.changed.clear();
+    }
+
+    /// Answers what changed since `token`
+    fn answer(&self, token: &str) -> Answer {
+        let since = token
+            .strip_prefix(self.id.as_str())
+            .and_then(|rest| rest.strip_prefix(':'))
+            .and_then(|seq| seq.parse::<u64>().ok())
+            .filter(|seq| (self.reset_seq..=self.seq).contains(seq));
+
+        let changes = match since {
+            Some(since) => Changes::Paths(
+                self.changed
+                    .iter()
+                    .filter(|(_, seq)| **seq > since)
+                    .map(|(path, _)| path.clone())
+                    .collect(),
+            ),
+            None => Changes::Unknown,
+        };
+
+        Answer {
+            token: format!("{}:{}", self.id, self.seq),
+            changes,
+        }
+    }
+}
+
+/// Watches the work tree and answers `status` and `add -u` about what changed
+///
+/// The daemon runs in the foreground until interrupted, listening on
+/// `.vox/fsmonitor.sock`. Before answering a query it creates a cookie file
+/// in `.vox` and waits for its event, so every change made before the query
+/// is accounted for.
+pub fn watch_command() -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Err(VoxError::NotARepository.into());
+    }
+
+    daemon::run()
+}
+
+#[cfg(not(target_os = "linux"))]
+mod daemon {
+    use anyhow::{bail, Result};
+
+    pub fn run() -> Result<()> {
+        bail!("vox watch only runs on Linux")
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod daemon {
+    use super::{ChangeLog, COOKIE_PREFIX};
+    use crate::commands::status::status::is_skipped_dir;
+    use crate::storage::fsmonitor::SOCKET_FILE;
+    use crate::storage::utils::VOX_DIR;
+    use anyhow::{bail, Context, Result};
+    use rustix::fd::OwnedFd;
+    use rustix::fs::inotify::{self, CreateFlags, ReadFlags, WatchFlags};
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::mem::MaybeUninit;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// How long a query waits for its cookie before answering that anything
+    /// may have changed
+    const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// State shared by the event loop and the threads answering queries
+    struct Shared {
+        log: Mutex<Log>,
+        /// Signaled when a cookie shows up
+        synced: Condvar,
+        next_cookie: AtomicU64,
+    }
+
+    struct Log {
+        changes: ChangeLog,
+        /// Cookies seen and not yet picked up by their query
+        cookies: HashSet<String>,
+    }
+
+    pub fn run() -> Result<()> {
+        let socket_path = VOX_DIR.join(SOCKET_FILE);
+        if socket_path.exists() {
+            if UnixStream::connect(&socket_path).is_ok() {
+                bail!("A watcher is already running for this repository");
+            }
+            // Left behind by a watcher that didn't exit cleanly
+            fs::remove_file(&socket_path)
+                .with_context(|| format!("Failed to remove {}", socket_path.display()))?;
+        }
+
+        let work_tree = fs::canonicalize(".").context("Failed to resolve the work tree")?;
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let shared = Arc::new(Shared {
+            log: Mutex::new(Log {
+                changes: ChangeLog::new(format!("{}.{}", std::process::id(), started.as_nanos())),
+                cookies: HashSet::new(),
+            }),
+            synced: Condvar::new(),
+            next_cookie: AtomicU64::new(0),
+        });
+
+        // Watch before listening, so no query is answered for changes that
+        // happened before the watches were in place
+        let mut watcher = Watcher::new(&work_tree)?;
+        let listener = UnixListener::bind(&socket_path)
+            .with_context(|| format!("Failed to listen on {}", socket_path.display()))?;
+        println!(
+            "Watching {} ({} directories), press Ctrl-C to stop",
+            work_tree.display(),
+            watcher.watches.len()
+        );
+
+        let events_shared = Arc::clone(&shared);
+        thread::spawn(move || watcher.run(&events_shared));
+
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                if let Err(err) = serve(stream, &shared) {
+                    eprintln!("warning: failed to answer a query: {:#}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answers a `query <token>` request
+    fn serve(mut stream: UnixStream, shared: &Shared) -> Result<()> {
+        let mut request = String::new();
+        BufReader::new(&stream).read_line(&mut request)?;
+        let token = request
+            .trim_end_matches('\n')
+            .strip_prefix("query ")
+            .context("Invalid request")?;
+
+        // Without the cookie some changes may still be on their way, and
+        // the empty token gets an answer that anything may have changed
+        let synced = sync(shared).unwrap_or(false);
+        let answer = shared.log.lock().unwrap().changes.answer(if synced { token } else { "" });
+        stream.write_all(&answer.serialize())?;
+        Ok(())
+    }
+
+    /// Waits until the watcher handled every change made so far
+    ///
+    /// # Returns
+    ///
+    /// False if the cookie's event didn't come in time
+    fn sync(shared: &Shared) -> Result<bool> {
+        let cookie = format!(
+            "{}{}",
+            COOKIE_PREFIX,
+            shared.next_cookie.fetch_add(1, Ordering::SeqCst)
+        );
+        let cookie_path = VOX_DIR.join(&cookie);
+        fs::write(&cookie_path, "")
+            .with_context(|| format!("Failed to create {}", cookie_path.display()))?;
+
+        let log = shared.log.lock().unwrap();
+        let (mut log, _) = shared
+            .synced
+            .wait_timeout_while(log, SYNC_TIMEOUT, |log| !log.cookies.contains(&cookie))
+            .unwrap();
+        let seen = log.cookies.remove(&cookie);
+        drop(log);
+
+        let _ = fs::remove_file(&cookie_path);
+        Ok(seen)
+    }
+
+    /// The inotify watches of the work tree
+    struct Watcher {
+        fd: OwnedFd,
+        work_tree: PathBuf,
+        /// Watched directories by watch descriptor, relative to the work tree
+        watches: HashMap<i32, PathBuf>,
+        /// Watch of `.vox`, for the cookies only
+        vox_watch: i32,
+    }
+
+    impl Watcher {
+        fn new(work_tree: &Path) -> Result<Self> {
+            let fd = inotify::init(CreateFlags::CLOEXEC).context("Failed to create inotify")?;
+            let vox_watch = inotify::add_watch(
+                &fd,
+                work_tree.join(VOX_DIR.as_path()),
+                WatchFlags::CREATE | WatchFlags::ONLYDIR,
+            )
+            .context("Failed to watch .vox")?;
+
+            let mut watcher = Self {
+                fd,
+                work_tree: work_tree.to_path_buf(),
+                watches: HashMap::new(),
+                vox_watch,
+            };
+            watcher.watch_tree(Path::new(""))?;
+            Ok(watcher)
+        }
+
+        /// Watches `dir` and every directory below it
+        fn watch_tree(&mut self, dir: &Path) -> Result<()> {
+            let flags = WatchFlags::CREATE
+                | WatchFlags::DELETE
+                | WatchFlags::MODIFY
+                | WatchFlags::ATTRIB
+                | WatchFlags::MOVED_FROM
+                | WatchFlags::MOVED_TO
+                | WatchFlags::ONLYDIR
+                | WatchFlags::DONT_FOLLOW
+                | WatchFlags::EXCL_UNLINK;
+
+            let mut pending = vec![dir.to_path_buf()];
+            while let Some(dir) = pending.pop() {
+                let full_path = self.work_tree.join(&dir);
+                let wd = inotify::add_watch(&self.fd, &full_path, flags).with_context(|| {
+                    format!(
+                        "Failed to watch {} (see fs.inotify.max_user_watches)",
+                        full_path.display()
+                    )
+                })?;
+                self.watches.insert(wd, dir.clone());
+
+                let entries = fs::read_dir(&full_path)
+                    .with_context(|| format!("Failed to read directory {}", full_path.display()))?;
+                for entry in entries.flatten() {
+                    let subdir = dir.join(entry.file_name());
+                    if entry.file_type().is_ok_and(|kind| kind.is_dir())
+                        && !self.is_skipped(&subdir)
+                    {
+                        pending.push(subdir);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Stops watching `dir` and the directories below it
+        fn unwatch_tree(&mut self, dir: &Path) {
+            let fd = &self.fd;
+            self.watches.retain(|wd, watched| {
+                if !watched.starts_with(dir) {
+                    return true;
+                }
+                let _ = inotify::remove_watch(fd, *wd);
+                false
+            });
+        }
+
+        fn is_skipped(&self, dir: &Path) -> bool {
+            dir.file_name().is_some_and(|name| name == ".vox")
+                || is_skipped_dir(&self.work_tree, dir)
+        }
+
+        /// Reads events until the inotify descriptor fails
+        fn run(&mut self, shared: &Shared) {
+            let mut buffer = [MaybeUninit::uninit(); 64 * 1024];
+            let fd = self.fd.try_clone().expect("Failed to duplicate the inotify descriptor");
+            let mut reader = inotify::Reader::new(&fd, &mut buffer);
+            loop {
+                let (wd, flags, name) = match reader.next() {
+                    Ok(event) => (
+                        event.wd(),
+                        event.events(),
+                        event
+                            .file_name()
+                            .map(|name| PathBuf::from(name.to_string_lossy().into_owned())),
+                    ),
+                    Err(err) => {
+                        eprintln!("error: failed to read file system events: {}", err);
+                        std::process::exit(1);
+                    }
+                };
+                self.handle(shared, wd, flags, name);
+            }
+        }
+
+        fn handle(&mut self, shared: &Shared, wd: i32, flags: ReadFlags, name: Option<PathBuf>) {
+            if flags.contains(ReadFlags::QUEUE_OVERFLOW) {
+                shared.log.lock().unwrap().changes.reset();
+                return;
+            }
+            if wd == self.vox_watch {
+                if let Some(name) = name.and_then(|name| name.to_str().map(str::to_string))
+                    && name.starts_with(COOKIE_PREFIX)
+                {
+                    shared.log.lock().unwrap().cookies.insert(name);
+                    shared.synced.notify_all();
+                }
+                return;
+            }
+            if flags.contains(ReadFlags::IGNORED) {
+                self.watches.remove(&wd);
+                return;
+            }
+
+            let Some(dir) = self.watches.get(&wd) else { return };
+            let path = match name {
+                Some(name) => dir.join(name),
+                None => dir.clone(),
+            };
+            let is_dir = flags.contains(ReadFlags::ISDIR);
+            if is_dir && self.is_skipped(&path) {
+                return;
+            }
+
+            if is_dir && flags.contains(ReadFlags::MOVED_FROM) {
+                self.unwatch_tree(&path);
+            }
+            if is_dir && flags.intersects(ReadFlags::CREATE | ReadFlags::MOVED_TO) {
+                // The directory may be gone already, which its event covers
+                let _ = self.watch_tree(&path);
+            }
+            shared.log.lock().unwrap().changes.record(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_change_log_answers() {
+        let mut log = ChangeLog::new("42.7".to_string());
+        let start = log.answer("");
+        assert_eq!(start.changes, Changes::Unknown);
+        assert_eq!(start.token, "42.7:0");
+
+        log.record(PathBuf::from("a.txt"));
+        log.record(PathBuf::from("src"));
+        let answer = log.answer(&start.token);
+        assert!(answer.is_changed(Path::new("a.txt")));
+        assert!(answer.is_changed(Path::new("src/main.rs")));
+        assert!(!answer.is_changed(Path::new("b.txt")));
+
+        log.record(PathBuf::from("b.txt"));
+        let next = log.answer(&answer.token);
+        assert_eq!(next.changes, Changes::Paths([PathBuf::from("b.txt")].into()));
+
+        // Tokens of another watcher or from before lost events can't be
+        // answered
+        assert_eq!(log.answer("41.7:3").changes, Changes::Unknown);
+        log.reset();
+        assert_eq!(log.answer(&next.token).changes, Changes::Unknown);
+    }
+}
@@ -1,9 +1,10 @@
 use crate::storage::objects::tree::{create_tree, store_tree};
+use crate::storage::utils::OBJ_DIR;
 use anyhow::Result;
 use std::path::Path;
 
 pub fn write_tree_command(path: &Path) -> Result<()> {
-    let tree = create_tree(path)?;
+    let tree = create_tree(path, &OBJ_DIR)?;
     let hash = store_tree(&tree)?;
     println!("{}", hash);
     Ok(())
@@ -0,0 +1,114 @@
+use thiserror::Error;
+
+/// Failures worth telling apart, for programs embedding vox and, through the
+/// exit code, for scripts; anything else is a plain [`anyhow::Error`]
+///
+/// They travel inside [`anyhow::Error`] like every other error, under any
+/// context added on the way up, and are found again with [`VoxError::find`].
+#[derive(Debug, Error)]
+pub enum VoxError {
+    #[error("Not a vox repository (or any parent)")]
+    NotARepository,
+
+    #[error("Object {0} not found")]
+    ObjectNotFound(String),
+
+    #[error("Object {hash} is corrupt: {problem}")]
+    CorruptObject { hash: String, problem: String },
+
+    #[error("Unknown revision: '{0}'")]
+    InvalidRevision(String),
+
+    /// The repository is in a format this vox doesn't understand
+    #[error("{0}")]
+    UnsupportedFormat(String),
+
+    /// Changes couldn't be merged, the conflicts are left to resolve
+    #[error("{0}")]
+    MergeConflict(String),
+
+    /// The remote repository couldn't be reached
+    #[error("{0}")]
+    NetworkError(String),
+}
+
+impl VoxError {
+    /// Returns the `VoxError` `error` was raised with, if any
+    pub fn find(error: &anyhow::Error) -> Option<&VoxError> {
+        error.downcast_ref()
+    }
+
+    /// Exit code of the CLI when a command fails with this error
+    ///
+    /// Conflicts and missing repositories exit like they do in git (1 and
+    /// 128), the rest with the matching code of `sysexits.h`.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            VoxError::MergeConflict(_) => 1,
+            VoxError::InvalidRevision(_) => 64,
+            VoxError::CorruptObject { .. } => 65,
+            VoxError::ObjectNotFound(_) => 66,
+            VoxError::NetworkError(_) => 69,
+            VoxError::UnsupportedFormat(_) => 78,
+            VoxError::NotARepository => 128,
+        }
+    }
+}
+
+/// Exit code of the CLI when a command fails with `error`, 1 unless it was
+/// raised as a [`VoxError`]
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    VoxError::find(error).map_or(1, VoxError::exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Context, Result};
+    use assert_cmd::Command;
+    use predicates::prelude::*;
+
+    fn read_missing() -> Result<()> {
+        bail!(VoxError::ObjectNotFound("abc123".to_string()))
+    }
+
+    #[test]
+    fn test_found_under_context() {
+        let error = read_missing().context("Failed to load commit").unwrap_err();
+        assert!(matches!(
+            VoxError::find(&error),
+            Some(VoxError::ObjectNotFound(hash)) if hash == "abc123"
+        ));
+        assert_eq!(exit_code(&error), 66);
+
+        let error = read_missing()
+            .map_err(|error| error.context(VoxError::InvalidRevision("main~9".to_string())))
+            .unwrap_err();
+        assert!(matches!(VoxError::find(&error), Some(VoxError::InvalidRevision(_))));
+        assert_eq!(exit_code(&anyhow::anyhow!("anything else")), 1);
+    }
+
+    #[test]
+    fn test_cli_exit_codes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let vox = |args: &[&str]| -> Result<assert_cmd::assert::Assert> {
+            Ok(Command::cargo_bin("vox")?
+                .args(args)
+                .current_dir(dir.path())
+                .assert())
+        };
+
+        for command in [&["status"][..], &["log"], &["cat-file", "-p", "abc"]] {
+            vox(command)?
+                .code(128)
+                .stderr(predicate::str::contains("Not a vox repository"));
+        }
+
+        vox(&["init"])?.success();
+        let missing = "0123456789abcdef0123456789abcdef01234567";
+        vox(&["cat-file", "-p", missing])?
+            .code(66)
+            .stderr(predicate::str::contains(format!("Object {} not found", missing)));
+        Ok(())
+    }
+}
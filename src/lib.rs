@@ -0,0 +1,26 @@
+//! vox, a version control system with git's object model
+//!
+//! The `vox` binary is a thin wrapper over [`cli`] and [`command_handler`].
+//! Programs embedding vox open repositories with [`VoxRepo`], or use the
+//! storage layer directly.
+
+pub mod cli;
+pub mod command_handler;
+pub mod commands;
+pub mod error;
+pub mod storage;
+pub mod vox_repo;
+
+pub use commands::diff::diff::{text_diff, DiffOptions};
+pub use commands::index::index::{Index, IndexEntry};
+pub use error::VoxError;
+pub use storage::hash::{ObjectFormat, ObjectId};
+pub use storage::objects::blob::Blob;
+pub use storage::objects::change::{ChangeSet, ChangeType};
+pub use storage::objects::commit::Commit;
+pub use storage::objects::tag::Tag;
+pub use storage::objects::tree::{Tree, TreeEntry};
+pub use storage::objects::walk::{CommitWalker, WalkOrder};
+pub use storage::objects::{Loadable, Storable, VoxObject};
+pub use storage::repo::Repository;
+pub use vox_repo::VoxRepo;
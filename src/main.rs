@@ -1,16 +1,22 @@
-mod cli;
-mod command_handler;
-mod commands;
-pub mod storage;
-
 use clap::Parser;
-use cli::Cli;
-
-use command_handler::handle_command;
+use std::process::ExitCode;
+use vox::cli::Cli;
+use vox::command_handler::handle_command;
+use vox::error::exit_code;
+use vox::storage;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
-    handle_command(cli.command).await?;
-    Ok(())
+    let result = match storage::refs::set_namespace(cli.namespace) {
+        Ok(()) => handle_command(cli.command).await,
+        Err(error) => Err(error),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:?}", error);
+            ExitCode::from(exit_code(&error))
+        }
+    }
 }
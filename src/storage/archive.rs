@@ -0,0 +1,301 @@
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression;
+use std::io::Write;
+use std::str::FromStr;
+
+/// Size of tar headers and of the blocks file contents are padded to
+const TAR_BLOCK: usize = 512;
+
+/// Format of the archives written by `vox archive`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// An uncompressed tar archive
+    Tar,
+    /// A gzip compressed tar archive
+    TarGz,
+    /// A zip archive with deflated files
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from the extension of an output file name
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        [".tar.gz", ".tgz", ".tar", ".zip"]
+            .into_iter()
+            .find(|extension| name.ends_with(extension))
+            .and_then(|extension| extension.trim_start_matches('.').parse().ok())
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => bail!("Unknown archive format '{}' (expected tar, tar.gz or zip)", s),
+        }
+    }
+}
+
+/// The kind of an archive entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file, executable or not
+    File { executable: bool },
+    /// A symbolic link, its content is the target
+    Symlink,
+    /// A directory, its path ends with `/` and it has no content
+    Directory,
+}
+
+impl EntryKind {
+    fn unix_mode(&self) -> u32 {
+        match self {
+            EntryKind::File { executable: true } => 0o100755,
+            EntryKind::File { executable: false } => 0o100644,
+            EntryKind::Symlink => 0o120777,
+            EntryKind::Directory => 0o040755,
+        }
+    }
+}
+
+/// Writes entries to an archive one at a time
+pub trait ArchiveWriter {
+    /// Adds an entry, directories must be added before the entries inside them
+    fn add(&mut self, path: &str, kind: EntryKind, content: &[u8]) -> Result<()>;
+
+    /// Writes the end of the archive
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Creates a writer for `format` writing to `out`, every entry having
+/// `mtime` as its modification time
+pub fn archive_writer<'a, W: Write + 'a>(
+    format: ArchiveFormat,
+    out: W,
+    mtime: DateTime<Utc>,
+) -> Box<dyn ArchiveWriter + 'a> {
+    match format {
+        ArchiveFormat::Tar => Box::new(TarWriter { out, mtime }),
+        ArchiveFormat::TarGz => Box::new(TarWriter {
+            out: GzEncoder::new(out, Compression::default()),
+            mtime,
+        }),
+        ArchiveFormat::Zip => Box::new(ZipWriter {
+            out,
+            mtime,
+            offset: 0,
+            central_directory: Vec::new(),
+            entries: 0,
+        }),
+    }
+}
+
+/// Writes POSIX ustar archives
+struct TarWriter<W: Write> {
+    out: W,
+    mtime: DateTime<Utc>,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn header(&self, path: &str, kind: EntryKind, size: usize, link: &[u8]) -> Result<Vec<u8>> {
+        let mut header = vec![0u8; TAR_BLOCK];
+        // Paths over 100 bytes are split into a prefix and a name at a slash
+        let (prefix, name) = match path.len() {
+            0..=100 => ("", path),
+            _ => match path[..path.len().min(156)].rfind('/') {
+                Some(split) if path.len() - split - 1 <= 100 => {
+                    (&path[..split], &path[split + 1..])
+                }
+                _ => bail!("Path too long for a tar archive: {}", path),
+            },
+        };
+        if link.len() > 100 {
+            bail!("Symlink target too long for a tar archive: {}", path);
+        }
+
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value);
+        };
+        field(0, name.as_bytes());
+        field(100, format!("{:07o}\0", kind.unix_mode() & 0o7777).as_bytes());
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", size).as_bytes());
+        field(136, format!("{:011o}\0", self.mtime.timestamp().max(0)).as_bytes());
+        let type_flag = match kind {
+            EntryKind::File { .. } => b'0',
+            EntryKind::Symlink => b'2',
+            EntryKind::Directory => b'5',
+        };
+        field(156, &[type_flag]);
+        field(157, link);
+        field(257, b"ustar\x0000");
+        field(345, prefix.as_bytes());
+
+        // The checksum is computed with its own field filled with spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        Ok(header)
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add(&mut self, path: &str, kind: EntryKind, content: &[u8]) -> Result<()> {
+        let (size, link) = match kind {
+            EntryKind::File { .. } => (content.len(), &[][..]),
+            EntryKind::Symlink => (0, content),
+            EntryKind::Directory => (0, &[][..]),
+        };
+        let header = self.header(path, kind, size, link)?;
+        self.out.write_all(&header)?;
+        if size > 0 {
+            self.out.write_all(content)?;
+            let padding = (TAR_BLOCK - size % TAR_BLOCK) % TAR_BLOCK;
+            self.out.write_all(&vec![0u8; padding])?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // Two empty blocks mark the end of the archive
+        self.out.write_all(&[0u8; TAR_BLOCK * 2])?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes zip archives, deflating file contents
+struct ZipWriter<W: Write> {
+    out: W,
+    mtime: DateTime<Utc>,
+    /// Bytes written so far, local headers are found by their offset
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    /// Returns the modification time as MS-DOS time and date fields
+    fn dos_time(&self) -> (u16, u16) {
+        let time = self.mtime;
+        let year = time.year().clamp(1980, 2107) as u16;
+        let dos_time =
+            (time.hour() as u16) << 11 | (time.minute() as u16) << 5 | (time.second() as u16 / 2);
+        let dos_date = (year - 1980) << 9 | (time.month() as u16) << 5 | time.day() as u16;
+        (dos_time, dos_date)
+    }
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn add(&mut self, path: &str, kind: EntryKind, content: &[u8]) -> Result<()> {
+        let (method, data) = match kind {
+            EntryKind::File { .. } => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                (8u16, encoder.finish()?)
+            }
+            _ => (0u16, content.to_vec()),
+        };
+        if data.len() > u32::MAX as usize || content.len() > u32::MAX as usize {
+            bail!("File too large for a zip archive: {}", path);
+        }
+        if self.offset > u32::MAX as u64 || self.entries >= u16::MAX as usize {
+            bail!("Too much content for a zip archive");
+        }
+
+        let crc = crc32fast::hash(content);
+        let (dos_time, dos_date) = self.dos_time();
+        // Bit 11 marks names as UTF-8
+        let common = |buffer: &mut Vec<u8>| -> Result<()> {
+            buffer.write_u16::<LittleEndian>(20)?; // Version needed to extract
+            buffer.write_u16::<LittleEndian>(1 << 11)?;
+            buffer.write_u16::<LittleEndian>(method)?;
+            buffer.write_u16::<LittleEndian>(dos_time)?;
+            buffer.write_u16::<LittleEndian>(dos_date)?;
+            buffer.write_u32::<LittleEndian>(crc)?;
+            buffer.write_u32::<LittleEndian>(data.len() as u32)?;
+            buffer.write_u32::<LittleEndian>(content.len() as u32)?;
+            buffer.write_u16::<LittleEndian>(path.len() as u16)?;
+            buffer.write_u16::<LittleEndian>(0)?; // Extra field length
+            Ok(())
+        };
+
+        let mut local = Vec::new();
+        local.write_u32::<LittleEndian>(0x04034b50)?;
+        common(&mut local)?;
+        local.extend_from_slice(path.as_bytes());
+
+        let central = &mut self.central_directory;
+        central.write_u32::<LittleEndian>(0x02014b50)?;
+        central.write_u16::<LittleEndian>(3 << 8 | 20)?; // Made by unix, version 2.0
+        common(central)?;
+        central.write_u16::<LittleEndian>(0)?; // Comment length
+        central.write_u16::<LittleEndian>(0)?; // Disk number
+        central.write_u16::<LittleEndian>(0)?; // Internal attributes
+        central.write_u32::<LittleEndian>(kind.unix_mode() << 16)?;
+        central.write_u32::<LittleEndian>(self.offset as u32)?;
+        central.extend_from_slice(path.as_bytes());
+
+        self.out.write_all(&local)?;
+        self.out.write_all(&data)?;
+        self.offset += (local.len() + data.len()) as u64;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let mut end = Vec::new();
+        end.write_u32::<LittleEndian>(0x06054b50)?;
+        end.write_u16::<LittleEndian>(0)?; // Number of this disk
+        end.write_u16::<LittleEndian>(0)?; // Disk with the central directory
+        end.write_u16::<LittleEndian>(self.entries as u16)?;
+        end.write_u16::<LittleEndian>(self.entries as u16)?;
+        end.write_u32::<LittleEndian>(self.central_directory.len() as u32)?;
+        end.write_u32::<LittleEndian>(self.offset as u32)?;
+        end.write_u16::<LittleEndian>(0)?; // Comment length
+
+        self.out.write_all(&self.central_directory)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_from_file_name() {
+        assert_eq!(ArchiveFormat::from_file_name("v1.tar.gz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_file_name("v1.tgz"), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::from_file_name("v1.zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::from_file_name("v1.txt"), None);
+    }
+
+    #[test]
+    fn test_tar_layout() -> Result<()> {
+        let mut out = Vec::new();
+        let mut writer = archive_writer(ArchiveFormat::Tar, &mut out, DateTime::UNIX_EPOCH);
+        writer.add("p/", EntryKind::Directory, b"")?;
+        writer.add("p/run.sh", EntryKind::File { executable: true }, b"echo\n")?;
+        writer.finish()?;
+
+        // Two headers, one content block and the two end blocks
+        assert_eq!(out.len(), TAR_BLOCK * 5);
+        let file_header = &out[TAR_BLOCK..TAR_BLOCK * 2];
+        assert!(file_header.starts_with(b"p/run.sh\0"));
+        assert_eq!(&file_header[100..108], b"0000755\0");
+        assert_eq!(&file_header[124..136], b"00000000005\0");
+        assert_eq!(&out[TAR_BLOCK * 2..TAR_BLOCK * 2 + 5], b"echo\n");
+        Ok(())
+    }
+}
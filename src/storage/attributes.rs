@@ -0,0 +1,273 @@
+use crate::storage::ignore::glob_to_regex;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the files giving attributes to paths, which apply to the
+/// directory they are in and below it
+pub const ATTRIBUTES_FILE: &str = ".voxattributes";
+
+/// Attributes of the repository that aren't shared, relative to `.vox`
+const INFO_ATTRIBUTES: &str = "info/attributes";
+
+/// Attributes `binary` stands for: no text diff, merge or EOL conversion
+const BINARY_MACRO: [&str; 3] = ["-diff", "-merge", "-text"];
+
+/// State of an attribute for a path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    /// Set with `attr`
+    Set,
+    /// Unset with `-attr`
+    Unset,
+    /// Given a value with `attr=value`
+    Value(String),
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttrValue::Set => write!(f, "set"),
+            AttrValue::Unset => write!(f, "unset"),
+            AttrValue::Value(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The attributes of a path, by name
+///
+/// Attributes no rule mentions, or that a rule reset with `!attr`, are
+/// unspecified and missing from the map.
+pub type Attributes = BTreeMap<String, AttrValue>;
+
+/// A line of an attributes file: a pattern followed by the attributes of
+/// the paths it matches
+///
+/// Patterns have the syntax of `.voxignore` ones, except that they can't be
+/// negated. `!attr` makes an attribute unspecified again, overriding earlier
+/// lines, and `binary` stands for `-diff -merge -text`.
+#[derive(Debug, Clone)]
+pub struct AttrRule {
+    /// The pattern as written
+    pub pattern: String,
+    /// The file the rule comes from
+    pub source: PathBuf,
+    /// Line number of the rule in `source`, starting at 1
+    pub line: usize,
+    /// Attributes in order, `None` making an attribute unspecified
+    attrs: Vec<(String, Option<AttrValue>)>,
+    /// Directory the pattern is relative to, from the repository root
+    base: PathBuf,
+    regex: Regex,
+}
+
+impl AttrRule {
+    /// Parses a line of an attributes file
+    ///
+    /// # Returns
+    ///
+    /// The rule, or `None` for blank lines and `#` comments
+    pub fn parse(text: &str, base: &Path, source: &Path, line: usize) -> Result<Option<Self>> {
+        let mut fields = text.split_whitespace();
+        let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with('#')) else {
+            return Ok(None);
+        };
+        if pattern.starts_with('!') {
+            bail!(
+                "Negative patterns are ignored in attributes files: {} ({}:{})",
+                pattern,
+                source.display(),
+                line
+            );
+        }
+
+        let mut attrs = Vec::new();
+        for field in fields {
+            if field == "binary" {
+                attrs.extend(BINARY_MACRO.iter().map(|attr| parse_attr(attr)));
+            }
+            attrs.push(parse_attr(field));
+        }
+
+        let anchored = pattern.trim_end_matches('/').contains('/');
+        let glob = pattern.strip_prefix('/').unwrap_or(pattern);
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let regex = Regex::new(&format!("{}{}$", prefix, glob_to_regex(glob)))
+            .with_context(|| format!("Invalid pattern '{}' in {}", pattern, source.display()))?;
+
+        Ok(Some(Self {
+            pattern: pattern.to_string(),
+            source: source.to_path_buf(),
+            line,
+            attrs,
+            base: base.to_path_buf(),
+            regex,
+        }))
+    }
+
+    /// Returns true if the rule applies to `path`, relative to the
+    /// repository root
+    fn matches(&self, path: &Path) -> bool {
+        match path.strip_prefix(&self.base) {
+            Ok(relative) => self.regex.is_match(&relative.to_string_lossy()),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Parses `attr`, `-attr`, `!attr` or `attr=value`
+fn parse_attr(field: &str) -> (String, Option<AttrValue>) {
+    if let Some(name) = field.strip_prefix('-') {
+        (name.to_string(), Some(AttrValue::Unset))
+    } else if let Some(name) = field.strip_prefix('!') {
+        (name.to_string(), None)
+    } else if let Some((name, value)) = field.split_once('=') {
+        (name.to_string(), Some(AttrValue::Value(value.to_string())))
+    } else {
+        (field.to_string(), Some(AttrValue::Set))
+    }
+}
+
+/// Parses the rules of an attributes file, skipping the invalid lines with
+/// a warning rather than failing every command reading attributes
+fn parse_rules(text: &str, base: &Path, source: &Path) -> Vec<AttrRule> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        match AttrRule::parse(line, base, source, number + 1) {
+            Ok(rule) => rules.extend(rule),
+            Err(err) => eprintln!("warning: {:#}", err),
+        }
+    }
+    rules
+}
+
+/// Tells the attributes of paths in the work tree
+///
+/// Rules come from the `.voxattributes` file of every directory, read when a
+/// path below it is first looked up, and from `.vox/info/attributes`. For
+/// each attribute the last rule mentioning it decides, and rules come in
+/// that order, those of deeper `.voxattributes` files after those of their
+/// parents, so `.vox/info/attributes` overrides every file.
+pub struct AttributeMatcher {
+    repo_path: PathBuf,
+    /// Rules of `.vox/info/attributes`, after those of any directory
+    info: Vec<AttrRule>,
+    /// Rules of the `.voxattributes` file of each directory looked at so far
+    dirs: HashMap<PathBuf, Vec<AttrRule>>,
+}
+
+impl AttributeMatcher {
+    /// Creates a matcher for the work tree at `repo_path`
+    pub fn new(repo_path: &Path) -> Self {
+        let info_path = Path::new(".vox").join(INFO_ATTRIBUTES);
+        let info = match fs::read_to_string(repo_path.join(&info_path)) {
+            Ok(text) => parse_rules(&text, Path::new(""), &info_path),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            repo_path: repo_path.to_path_buf(),
+            info,
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// The attributes of `path`, relative to the repository root
+    pub fn attributes(&mut self, path: &Path) -> Attributes {
+        let mut attributes = Attributes::new();
+        for rule in self.matching_rules(path) {
+            for (name, value) in &rule.attrs {
+                match value {
+                    Some(value) => attributes.insert(name.clone(), value.clone()),
+                    None => attributes.remove(name),
+                };
+            }
+        }
+        attributes
+    }
+
+    /// The rules applying to `path`, in the order they take effect
+    pub fn matching_rules(&mut self, path: &Path) -> Vec<AttrRule> {
+        let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        dirs.reverse();
+
+        let mut rules = Vec::new();
+        for dir in dirs {
+            rules.extend(self.dir_rules(dir).iter().filter(|rule| rule.matches(path)).cloned());
+        }
+        rules.extend(self.info.iter().filter(|rule| rule.matches(path)).cloned());
+        rules
+    }
+
+    /// The rules of the `.voxattributes` file in `dir`, if it has one
+    fn dir_rules(&mut self, dir: &Path) -> &[AttrRule] {
+        if !self.dirs.contains_key(dir) {
+            let source = dir.join(ATTRIBUTES_FILE);
+            let rules = match fs::read_to_string(self.repo_path.join(&source)) {
+                Ok(text) => parse_rules(&text, dir, &source),
+                Err(_) => Vec::new(),
+            };
+            self.dirs.insert(dir.to_path_buf(), rules);
+        }
+        &self.dirs[dir]
+    }
+}
+
+/// Tells how the `diff` attribute wants the file at `path` in the current
+/// work tree to be diffed
+///
+/// # Returns
+///
+/// `Some(true)` if it's unset, as with `binary`, `Some(false)` if it's set,
+/// or `None` to decide by looking at the content
+pub fn diff_as_binary(path: &Path) -> Option<bool> {
+    match AttributeMatcher::new(Path::new(".")).attributes(path).get("diff") {
+        Some(AttrValue::Unset) => Some(true),
+        Some(AttrValue::Set) => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_attribute_precedence() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join(ATTRIBUTES_FILE),
+            "# defaults\n*.rs text eol=lf\n*.png binary\n/docs/*.md -text\n",
+        )?;
+        fs::create_dir_all(dir.path().join("src/vendor"))?;
+        fs::write(dir.path().join("src/vendor").join(ATTRIBUTES_FILE), "*.rs !eol -diff\n")?;
+        fs::create_dir_all(dir.path().join(".vox/info"))?;
+        fs::write(dir.path().join(".vox").join(INFO_ATTRIBUTES), "logo.png diff\n")?;
+
+        let mut matcher = AttributeMatcher::new(dir.path());
+        let attrs = matcher.attributes(Path::new("src/main.rs"));
+        assert_eq!(attrs.get("text"), Some(&AttrValue::Set));
+        assert_eq!(attrs.get("eol"), Some(&AttrValue::Value("lf".to_string())));
+
+        let attrs = matcher.attributes(Path::new("src/vendor/lib.rs"));
+        assert_eq!(attrs.get("eol"), None);
+        assert_eq!(attrs.get("diff"), Some(&AttrValue::Unset));
+
+        let attrs = matcher.attributes(Path::new("img/icon.png"));
+        assert_eq!(attrs.get("binary"), Some(&AttrValue::Set));
+        assert_eq!(attrs.get("merge"), Some(&AttrValue::Unset));
+        assert_eq!(attrs.get("diff"), Some(&AttrValue::Unset));
+        assert_eq!(matcher.attributes(Path::new("logo.png")).get("diff"), Some(&AttrValue::Set));
+
+        assert_eq!(matcher.attributes(Path::new("docs/a.md")).get("text"), Some(&AttrValue::Unset));
+        assert!(matcher.attributes(Path::new("src/docs/a.md")).is_empty());
+
+        let rules = matcher.matching_rules(Path::new("src/vendor/lib.rs"));
+        assert_eq!(rules.len(), 2);
+        assert_eq!((rules[1].pattern.as_str(), rules[1].line), ("*.rs", 1));
+        Ok(())
+    }
+}
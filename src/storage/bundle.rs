@@ -0,0 +1,227 @@
+use crate::storage::encryption::is_encrypted;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::graph::collect_reachable;
+use crate::storage::objects::pack::{ObjectType, PackObject, Packfile};
+use crate::storage::objects::{loose_object_path, read_raw_object};
+use crate::storage::replace::without_replacements;
+use crate::storage::shallow::read_shallow;
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// First line of every bundle file
+pub const BUNDLE_SIGNATURE: &str = "# v2 vox bundle";
+
+/// The refs a bundle carries, read from its header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleHeader {
+    /// Hash algorithm the objects are named with
+    pub format: ObjectFormat,
+    /// `(name, hash)` pairs, `HEAD` included when the bundle records it
+    pub refs: Vec<(String, String)>,
+    /// Commits of the bundle whose parents aren't in it, as the bundle was
+    /// made from a shallow repository
+    pub shallow: BTreeSet<String>,
+}
+
+/// Returns true if `path` is a file starting with the bundle signature
+pub fn is_bundle(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut first_line = String::new();
+    BufReader::new(file).read_line(&mut first_line).is_ok()
+        && first_line.trim_end() == BUNDLE_SIGNATURE
+}
+
+/// Writes a bundle of `refs` and every object reachable from them to `path`
+///
+/// A bundle is a text header listing the refs, a blank line, then a pack of
+/// the objects, so a repository can be moved around as a single file. The
+/// header also names the commits at the shallow boundary the bundle carries,
+/// so the repository it's cloned into knows their parents are missing.
+///
+/// # Returns
+///
+/// The number of objects in the bundle
+pub fn write_bundle(
+    path: &Path,
+    refs: &[(String, String)],
+    objects_dir: &Path,
+    format: ObjectFormat,
+) -> Result<usize> {
+    if refs.is_empty() {
+        bail!("Refusing to create an empty bundle");
+    }
+    if is_encrypted(objects_dir)? {
+        bail!("Refusing to bundle the objects of an encrypted repository in clear");
+    }
+
+    let mut pack = Packfile::with_format(format);
+    let reachable = collect_reachable(refs.iter().map(|(_, hash)| hash.clone()), objects_dir)?;
+    let shallow = match objects_dir.parent() {
+        Some(vox_dir) => read_shallow(vox_dir)?,
+        None => BTreeSet::new(),
+    };
+    let mut hashes: Vec<String> = reachable.into_iter().collect();
+    hashes.sort();
+    for hash in &hashes {
+        // Parents cut off by a shallow boundary aren't stored
+        if loose_object_path(hash, objects_dir)?.is_none() {
+            continue;
+        }
+        // Objects go in as they are, replace refs travel as refs
+        let (object_type, content) = without_replacements(|| read_raw_object(hash, objects_dir))?;
+        pack.objects
+            .push(PackObject::Base(content, ObjectType::from_name(&object_type)?));
+    }
+
+    let mut header = format!("{}\n@object-format={}\n", BUNDLE_SIGNATURE, format.name());
+    for hash in shallow.iter().filter(|hash| hashes.binary_search(hash).is_ok()) {
+        header.push_str(&format!("@shallow={}\n", hash));
+    }
+    for (name, hash) in refs {
+        header.push_str(&format!("{} {}\n", hash, name));
+    }
+    header.push('\n');
+
+    let mut file = File::create(path)
+        .with_context(|| format!("Failed to create bundle {}", path.display()))?;
+    file.write_all(header.as_bytes())?;
+    file.write_all(&pack.serialize()?)?;
+    Ok(pack.objects.len())
+}
+
+/// Reads the header of a bundle, leaving `reader` at the start of its pack
+pub fn read_bundle_header<R: BufRead>(reader: &mut R) -> Result<BundleHeader> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != BUNDLE_SIGNATURE {
+        bail!("Not a vox bundle");
+    }
+
+    let mut format = ObjectFormat::Sha1;
+    let mut refs = Vec::new();
+    let mut shallow = BTreeSet::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("Truncated bundle header");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(name) = line.strip_prefix("@object-format=") {
+            format = name.parse()?;
+            continue;
+        }
+        if let Some(hash) = line.strip_prefix("@shallow=") {
+            shallow.insert(hash.to_string());
+            continue;
+        }
+        let (hash, name) = line
+            .split_once(' ')
+            .with_context(|| format!("Invalid bundle ref line: {}", line))?;
+        refs.push((name.to_string(), hash.to_string()));
+    }
+    Ok(BundleHeader {
+        format,
+        refs,
+        shallow,
+    })
+}
+
+/// Reads the header of the bundle at `path`
+pub fn read_bundle(path: &Path) -> Result<BundleHeader> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    read_bundle_header(&mut BufReader::new(file))
+}
+
+/// Stores the objects of the bundle at `path` in `objects_dir`, streaming
+/// them out of its pack
+///
+/// # Returns
+///
+/// The header of the bundle and the number of objects stored
+pub fn unbundle(path: &Path, objects_dir: &Path) -> Result<(BundleHeader, usize)> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let header = read_bundle_header(&mut reader)?;
+
+    fs::create_dir_all(objects_dir)?;
+    let stored = Packfile::unpack(reader.by_ref(), objects_dir, header.format)
+        .with_context(|| format!("Failed to unpack bundle {}", path.display()))?;
+    Ok((header, stored.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::commit::Commit;
+    use crate::storage::objects::tree::{Tree, TreeEntry};
+    use crate::storage::objects::Storable;
+    use crate::storage::shallow::write_shallow;
+    use crate::storage::utils::{OBJ_TYPE_BLOB, PERM_FILE};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bundle_roundtrip() -> Result<()> {
+        let source = tempdir()?;
+        let blob = Blob { data: b"bundled\n".to_vec() }.save(source.path())?;
+        let entry = TreeEntry {
+            mode: PERM_FILE.to_string(),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: blob.clone(),
+            name: "file".to_string(),
+        };
+        let tree = Tree { entries: vec![entry] }.save(source.path())?;
+        let commit = Commit::new(tree.clone(), None, "A <a@b.c>".to_string(), "msg".to_string())
+            .save(source.path())?;
+
+        let bundle = source.path().join("repo.bundle");
+        let refs = vec![("refs/heads/main".to_string(), commit.clone())];
+        assert_eq!(write_bundle(&bundle, &refs, source.path(), ObjectFormat::Sha1)?, 3);
+        assert!(is_bundle(&bundle));
+        assert!(!is_bundle(source.path()));
+
+        let target = tempdir()?;
+        let (header, stored) = unbundle(&bundle, target.path())?;
+        assert_eq!(header.refs, refs);
+        assert!(header.shallow.is_empty());
+        assert_eq!(stored, 3);
+        // The objects keep their names
+        for hash in [&commit, &tree, &blob] {
+            assert!(target.path().join(&hash[..2]).join(&hash[2..]).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_shallow_bundle() -> Result<()> {
+        let source = tempdir()?;
+        let objects_dir = source.path().join("objects");
+        let tree = Tree { entries: vec![] }.save(&objects_dir)?;
+        let author = || "A <a@b.c>".to_string();
+        let first = Commit::new(tree.clone(), None, author(), "first".into()).save(&objects_dir)?;
+        let second =
+            Commit::new(tree, Some(first.clone()), author(), "second".into()).save(&objects_dir)?;
+        // The repository was cloned with a depth of one
+        fs::remove_file(objects_dir.join(&first[..2]).join(&first[2..]))?;
+        write_shallow(source.path(), &BTreeSet::from([second.clone()]))?;
+
+        let bundle = source.path().join("repo.bundle");
+        let refs = vec![("refs/heads/main".to_string(), second.clone())];
+        let written = write_bundle(&bundle, &refs, &objects_dir, ObjectFormat::Sha1)?;
+        assert_eq!(written, 2);
+
+        let target = tempdir()?;
+        let (header, stored) = unbundle(&bundle, target.path())?;
+        assert_eq!(header.shallow, BTreeSet::from([second]));
+        assert_eq!(stored, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use crate::commands::config::commands::{autocrlf, filter_config, lfs_threshold, lfs_url};
+use crate::commands::config::config::FilterConfig;
+use crate::storage::attributes::{AttrValue, AttributeMatcher, Attributes};
+use crate::storage::lfs::{LfsStore, Pointer, MAX_POINTER_SIZE};
+use crate::storage::patch::is_binary;
+use crate::storage::platform::to_index_path;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// How line endings of files without a `text` or `eol` attribute are
+/// converted, as `core.autocrlf`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoCrlf {
+    /// Text files are stored with LF and checked out with CRLF
+    True,
+    /// Text files are stored with LF and checked out as they are
+    Input,
+    /// Files are stored and checked out as they are
+    #[default]
+    False,
+}
+
+/// Line ending of text files in the work tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// What happens to the line endings of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EolAction {
+    /// Left as they are
+    Keep,
+    /// CRLF turned into LF when stored, and LF into `Eol` when checked out
+    Text(Eol),
+    /// As `Text`, but only if the content doesn't look binary
+    Auto(Eol),
+}
+
+/// Converts files between their form in the work tree and the one stored in
+/// the repository
+///
+/// Line endings are normalized to LF in the repository for files the `text`
+/// and `eol` attributes, or else `core.autocrlf`, mark as text, and may be
+/// turned back into CRLF in the work tree, so files edited on Windows and
+/// Unix don't differ by their line endings only.
+///
+/// Files with a `filter=<name>` attribute also go through the commands of
+/// `filter.<name>`: `clean` before being stored and `smudge` when checked
+/// out, each reading the content on its standard input and writing the
+/// converted one on its standard output.
+///
+/// Last, files larger than `lfs.threshold` are put in the LFS store and a
+/// pointer is stored in their place, which is turned back into the content
+/// when checked out.
+pub struct Converter {
+    attributes: AttributeMatcher,
+    autocrlf: AutoCrlf,
+    /// Filters looked up so far, `None` for those that aren't configured
+    filters: HashMap<String, Option<FilterConfig>>,
+    lfs: LfsStore,
+    lfs_threshold: Option<u64>,
+}
+
+impl Converter {
+    /// Creates a converter for the work tree at `repo_path`, with the
+    /// configured `core.autocrlf`
+    pub fn new(repo_path: &Path) -> Self {
+        Self::with_autocrlf(repo_path, autocrlf())
+    }
+
+    pub fn with_autocrlf(repo_path: &Path, autocrlf: AutoCrlf) -> Self {
+        Self {
+            attributes: AttributeMatcher::new(repo_path),
+            autocrlf,
+            filters: HashMap::new(),
+            lfs: LfsStore::new(&repo_path.join(".vox"), lfs_url()),
+            lfs_threshold: lfs_threshold(),
+        }
+    }
+
+    /// Stores files larger than `threshold` as LFS pointers instead of the
+    /// configured `lfs.threshold`
+    pub fn with_lfs_threshold(mut self, threshold: Option<u64>) -> Self {
+        self.lfs_threshold = threshold;
+        self
+    }
+
+    /// Uses `filter` as the filter `name` instead of the configured one
+    pub fn with_filter(mut self, name: impl Into<String>, filter: FilterConfig) -> Self {
+        self.filters.insert(name.into(), Some(filter));
+        self
+    }
+
+    /// Converts the work tree content of the file at `path`, relative to the
+    /// repository root, into the content to store: the clean filter runs
+    /// first, then line endings are normalized, then large files are
+    /// replaced by LFS pointers
+    pub fn to_repository(&mut self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+        let attributes = self.attributes.attributes(&to_index_path(path));
+        let data = match self.filter(&attributes) {
+            Some(filter) => run_filter(filter.clean(), &filter, "clean", path, data)?,
+            None => data,
+        };
+        let data = match eol_action(&attributes, self.autocrlf) {
+            EolAction::Text(_) => crlf_to_lf(data),
+            EolAction::Auto(_) if !is_binary(&data) => crlf_to_lf(data),
+            _ => data,
+        };
+        match self.lfs_threshold {
+            Some(threshold) if data.len() as u64 > threshold => {
+                Ok(self.lfs.store(&data)?.serialize())
+            }
+            _ => Ok(data),
+        }
+    }
+
+    /// Converts the stored content of the file at `path`, relative to the
+    /// repository root, into the content to check out: LFS pointers are
+    /// replaced by their content, then line endings are converted, then the
+    /// smudge filter runs
+    ///
+    /// Pointers whose content can't be found are checked out as they are,
+    /// with a warning, rather than failing the whole checkout.
+    pub fn to_work_tree(&mut self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+        let data = match Pointer::parse(&data) {
+            Some(pointer) => match self.lfs.load(&pointer) {
+                Ok(content) => content,
+                Err(err) => {
+                    eprintln!(
+                        "warning: checking out the LFS pointer of {}: {:#}",
+                        path.display(),
+                        err
+                    );
+                    data
+                }
+            },
+            None => data,
+        };
+        let attributes = self.attributes.attributes(&to_index_path(path));
+        let data = match eol_action(&attributes, self.autocrlf) {
+            EolAction::Text(Eol::Crlf) => lf_to_crlf(data),
+            EolAction::Auto(Eol::Crlf) if !is_binary(&data) => lf_to_crlf(data),
+            _ => data,
+        };
+        match self.filter(&attributes) {
+            Some(filter) => run_filter(filter.smudge(), &filter, "smudge", path, data),
+            None => Ok(data),
+        }
+    }
+
+    /// Returns true if the work tree content of the file at `path`, `size`
+    /// bytes long, is stored as it is, so it can be streamed rather than read
+    /// in memory to be converted
+    pub fn stores_as_is(&mut self, path: &Path, size: u64) -> bool {
+        let attributes = self.attributes.attributes(&to_index_path(path));
+        self.filter(&attributes).is_none()
+            && eol_action(&attributes, self.autocrlf) == EolAction::Keep
+            && self.lfs_threshold.is_none_or(|threshold| size <= threshold)
+    }
+
+    /// Returns true if the stored content of the file at `path`, `size`
+    /// bytes long, is checked out as it is, see [`Converter::stores_as_is`]
+    pub fn checks_out_as_is(&mut self, path: &Path, size: u64) -> bool {
+        let attributes = self.attributes.attributes(&to_index_path(path));
+        let eol = eol_action(&attributes, self.autocrlf);
+        self.filter(&attributes).is_none()
+            && !matches!(eol, EolAction::Text(Eol::Crlf) | EolAction::Auto(Eol::Crlf))
+            && size > MAX_POINTER_SIZE as u64
+    }
+
+    /// The filter the `filter` attribute names, if it's configured
+    fn filter(&mut self, attributes: &Attributes) -> Option<FilterConfig> {
+        let Some(AttrValue::Value(name)) = attributes.get("filter") else {
+            return None;
+        };
+        self.filters
+            .entry(name.clone())
+            .or_insert_with(|| filter_config(name))
+            .clone()
+    }
+}
+
+/// Runs the `kind` command of `filter` on the content of the file at `path`
+///
+/// Without a command the content is kept as it is, as it is when the command
+/// fails unless the filter is required, so a missing tool doesn't keep
+/// files from being added or checked out.
+fn run_filter(
+    command: Option<&str>,
+    filter: &FilterConfig,
+    kind: &str,
+    path: &Path,
+    data: Vec<u8>,
+) -> Result<Vec<u8>> {
+    let Some(command) = command else {
+        if filter.is_required() {
+            bail!("The {} filter of {} is required but not set", kind, path.display());
+        }
+        return Ok(data);
+    };
+    match run_command(command, path, &data) {
+        Ok(output) => Ok(output),
+        Err(err) if filter.is_required() => {
+            Err(err.context(format!("The {} filter failed on {}", kind, path.display())))
+        }
+        Err(err) => {
+            eprintln!(
+                "warning: the {} filter failed on {}, keeping it as is: {:#}",
+                kind,
+                path.display(),
+                err
+            );
+            Ok(data)
+        }
+    }
+}
+
+/// Runs `command` through the shell with `%f` standing for the quoted
+/// `path`, passing it `input` and returning what it writes
+fn run_command(command: &str, path: &Path, input: &[u8]) -> Result<Vec<u8>> {
+    let quoted = format!("'{}'", to_index_path(path).to_string_lossy().replace('\'', "'\\''"));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace("%f", &quoted))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", command))?;
+
+    // Feed the input from another thread, as the command may not read all
+    // of it before writing more than the pipe holds
+    let mut stdin = child.stdin.take().context("Missing filter input")?;
+    let output = thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(input));
+        let output = child.wait_with_output();
+        // The command may exit without reading everything, which is fine
+        let _ = writer.join();
+        output
+    })
+    .with_context(|| format!("Failed to wait for '{}'", command))?;
+
+    if !output.status.success() {
+        bail!("'{}' exited with {}", command, output.status);
+    }
+    Ok(output.stdout)
+}
+
+/// Decides what happens to the line endings of a file from its attributes:
+/// `-text` leaves them alone, `text` or `eol` normalizes them, `text=auto`
+/// normalizes them unless the file is binary, and without any of these
+/// `core.autocrlf` decides as with `text=auto`
+fn eol_action(attributes: &Attributes, autocrlf: AutoCrlf) -> EolAction {
+    let config_eol = match autocrlf {
+        AutoCrlf::True => Eol::Crlf,
+        _ => Eol::Lf,
+    };
+    let eol = match attributes.get("eol") {
+        Some(AttrValue::Value(value)) if value == "crlf" => Some(Eol::Crlf),
+        Some(AttrValue::Value(value)) if value == "lf" => Some(Eol::Lf),
+        _ => None,
+    };
+
+    match (attributes.get("text"), eol) {
+        (Some(AttrValue::Unset), _) => EolAction::Keep,
+        (Some(AttrValue::Value(value)), _) if value == "auto" => {
+            EolAction::Auto(eol.unwrap_or(config_eol))
+        }
+        (Some(_), _) => EolAction::Text(eol.unwrap_or(config_eol)),
+        (None, Some(eol)) => EolAction::Text(eol),
+        (None, None) if autocrlf == AutoCrlf::False => EolAction::Keep,
+        (None, None) => EolAction::Auto(config_eol),
+    }
+}
+
+/// Turns every CRLF into LF, leaving lone CRs alone
+fn crlf_to_lf(data: Vec<u8>) -> Vec<u8> {
+    if !data.windows(2).any(|pair| pair == b"\r\n") {
+        return data;
+    }
+    let mut converted = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'\r' || bytes.peek() != Some(&&b'\n') {
+            converted.push(byte);
+        }
+    }
+    converted
+}
+
+/// Turns every LF not already part of a CRLF into CRLF
+fn lf_to_crlf(data: Vec<u8>) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(data.len() + data.len() / 32);
+    let mut previous = 0;
+    for &byte in &data {
+        if byte == b'\n' && previous != b'\r' {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+        previous = byte;
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::attributes::ATTRIBUTES_FILE;
+    use anyhow::Result;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_line_ending_conversion() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join(ATTRIBUTES_FILE),
+            "*.bat eol=crlf\n*.sh text eol=lf\n*.dat -text\n*.txt text=auto\n",
+        )?;
+
+        let crlf = b"one\r\ntwo\r\n".to_vec();
+        let lf = b"one\ntwo\n".to_vec();
+        let binary = b"one\0\r\n".to_vec();
+
+        let mut converter = Converter::with_autocrlf(dir.path(), AutoCrlf::False);
+        let mut stored = |path: &str, data: &Vec<u8>| {
+            converter.to_repository(Path::new(path), data.clone())
+        };
+        assert_eq!(stored("run.bat", &crlf)?, lf);
+        assert_eq!(stored("run.sh", &crlf)?, lf);
+        assert_eq!(stored("blob.dat", &crlf)?, crlf);
+        assert_eq!(stored("notes.txt", &crlf)?, lf);
+        assert_eq!(stored("notes.txt", &binary)?, binary);
+        assert_eq!(stored("main.rs", &crlf)?, crlf);
+        assert_eq!(converter.to_work_tree(Path::new("run.bat"), lf.clone())?, crlf);
+        assert_eq!(converter.to_work_tree(Path::new("run.sh"), lf.clone())?, lf);
+        assert_eq!(converter.to_work_tree(Path::new("main.rs"), lf.clone())?, lf);
+
+        let mut converter = Converter::with_autocrlf(dir.path(), AutoCrlf::True);
+        assert_eq!(converter.to_repository(Path::new("main.rs"), crlf.clone())?, lf);
+        assert_eq!(converter.to_work_tree(Path::new("main.rs"), lf.clone())?, crlf);
+        assert_eq!(converter.to_work_tree(Path::new("main.rs"), crlf.clone())?, crlf);
+        assert_eq!(converter.to_work_tree(Path::new("run.sh"), lf.clone())?, lf);
+        assert_eq!(converter.to_work_tree(Path::new("blob.dat"), lf.clone())?, lf);
+
+        let mut converter = Converter::with_autocrlf(dir.path(), AutoCrlf::Input);
+        assert_eq!(converter.to_repository(Path::new("main.rs"), crlf.clone())?, lf);
+        assert_eq!(converter.to_work_tree(Path::new("main.rs"), lf.clone())?, lf);
+
+        assert_eq!(crlf_to_lf(b"a\rb\r\r\n".to_vec()), b"a\rb\r\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_filters() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join(ATTRIBUTES_FILE),
+            "*.txt filter=upper eol=crlf\n*.md filter=broken\n*.key filter=strict\n",
+        )?;
+        let upper = FilterConfig::new(
+            Some("tr a-z A-Z".to_string()),
+            Some("tr A-Z a-z".to_string()),
+            false,
+        );
+        let broken = FilterConfig::new(Some("exit 3".to_string()), None, false);
+        let strict = FilterConfig::new(Some("exit 3".to_string()), None, true);
+        let mut converter = Converter::with_autocrlf(dir.path(), AutoCrlf::False)
+            .with_filter("upper", upper)
+            .with_filter("broken", broken)
+            .with_filter("strict", strict);
+
+        let stored = converter.to_repository(Path::new("a.txt"), b"one\r\ntwo\r\n".to_vec())?;
+        assert_eq!(stored, b"ONE\nTWO\n");
+        let checked_out = converter.to_work_tree(Path::new("a.txt"), stored)?;
+        assert_eq!(checked_out, b"one\r\ntwo\r\n");
+
+        let large = vec![b'a'; 1 << 20];
+        assert_eq!(converter.to_repository(Path::new("big.txt"), large)?, vec![b'A'; 1 << 20]);
+
+        assert_eq!(converter.to_repository(Path::new("a.md"), b"doc".to_vec())?, b"doc");
+        assert_eq!(converter.to_work_tree(Path::new("a.md"), b"doc".to_vec())?, b"doc");
+        assert!(converter.to_repository(Path::new("id.key"), b"key".to_vec()).is_err());
+        assert!(converter.to_work_tree(Path::new("id.key"), b"key".to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lfs_pointers() -> Result<()> {
+        let dir = tempdir()?;
+        let mut converter =
+            Converter::with_autocrlf(dir.path(), AutoCrlf::False).with_lfs_threshold(Some(16));
+
+        let large = vec![0xAB; 64];
+        let stored = converter.to_repository(Path::new("asset.bin"), large.clone())?;
+        let pointer = Pointer::parse(&stored).expect("large files are stored as pointers");
+        assert_eq!(pointer.size, 64);
+        assert_eq!(converter.to_work_tree(Path::new("asset.bin"), stored)?, large);
+        assert_eq!(converter.to_repository(Path::new("a.txt"), b"small".to_vec())?, b"small");
+
+        // Without the content, the pointer itself is checked out
+        let missing = Pointer::for_content(b"elsewhere").serialize();
+        assert_eq!(converter.to_work_tree(Path::new("b.bin"), missing.clone())?, missing);
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Editor for commit messages: `VOX_EDITOR`, `VISUAL`, `EDITOR` or `vi`
+pub fn editor() -> String {
+    ["VOX_EDITOR", "VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.trim().is_empty()))
+        .unwrap_or_else(|| String::from("vi"))
+}
+
+/// Editor for todo lists, `VOX_SEQUENCE_EDITOR` or the message editor
+pub fn sequence_editor() -> String {
+    env::var("VOX_SEQUENCE_EDITOR")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(editor)
+}
+
+/// Opens `path` in `editor` and waits for it to exit
+///
+/// The editor is run by the shell, so it may come with arguments, such as
+/// `code --wait`.
+pub fn edit_file(editor: &str, path: &Path) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run the editor '{}'", editor))?;
+    if !status.success() {
+        bail!("The editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Lets the user edit `text` in the file at `path`
+///
+/// # Returns
+///
+/// The edited text without its `#` comment lines and trailing blank lines
+pub fn edit_text(editor: &str, path: &Path, text: &str) -> Result<String> {
+    fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))?;
+    edit_file(editor, path)?;
+    let edited =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(strip_comments(&edited))
+}
+
+/// Removes the `#` comment lines and the trailing blank lines of a text
+pub fn strip_comments(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+    lines.join("\n").trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        assert_eq!(
+            strip_comments("Subject\n\n# Please enter a message\nBody\n#\n\n"),
+            "Subject\n\nBody"
+        );
+        assert_eq!(strip_comments("# only comments\n"), "");
+    }
+}
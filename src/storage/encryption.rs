@@ -0,0 +1,271 @@
+use crate::error::VoxError;
+use crate::storage::repo_config::RepoConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// First bytes of an encrypted object file, which zlib streams never start with
+const SEALED_MAGIC: &[u8; 8] = b"VOXAEAD1";
+
+/// Length of the repository keys, in bytes
+const KEY_LEN: usize = 32;
+
+lazy_static! {
+    /// Key of every objects directory used so far, `None` for those that
+    /// aren't encrypted, so the config and the key file are read once per
+    /// process rather than for every object
+    static ref KEYS: Mutex<HashMap<PathBuf, Option<Arc<ObjectKey>>>> = Mutex::new(HashMap::new());
+}
+
+/// Key encrypting the objects of a repository with AES-256-GCM
+///
+/// An encrypted object file holds [`SEALED_MAGIC`], a random nonce and the
+/// sealed compressed object. The object's hash is authenticated along, so an
+/// object file can't pass for another.
+pub struct ObjectKey {
+    key: LessSafeKey,
+}
+
+impl ObjectKey {
+    pub fn new(bytes: &[u8]) -> Result<Self> {
+        let key = UnboundKey::new(&AES_256_GCM, bytes)
+            .map_err(|_| anyhow!("Invalid encryption key: expected {} bytes", KEY_LEN))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    /// Returns the key of the repository owning `objects_dir`, `None` if its
+    /// objects aren't encrypted
+    pub fn for_objects_dir(objects_dir: &Path) -> Result<Option<Self>> {
+        let Some(vox_dir) = objects_dir.parent() else {
+            return Ok(None);
+        };
+        match RepoConfig::load(vox_dir)?.encryption() {
+            Some(key_id) => Ok(Some(Self::load(key_id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the key `key_id` from the key directory
+    pub fn load(key_id: &str) -> Result<Self> {
+        let path = key_path(key_id)?;
+        let content = fs::read_to_string(&path).with_context(|| {
+            format!("The objects are encrypted, but key {} isn't in {}", key_id, path.display())
+        })?;
+        Self::new(&hex::decode(content.trim()).context("Invalid encryption key")?)
+    }
+
+    /// Encrypts the compressed object `hash`
+    pub fn seal(&self, hash: &str, compressed: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+
+        let mut sealed = compressed.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(hash.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt object {}", hash))?;
+        Ok([SEALED_MAGIC.as_slice(), &nonce, &sealed].concat())
+    }
+
+    /// Decrypts the object file of `hash`, returning the compressed object
+    pub fn open(&self, hash: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let data = data
+            .strip_prefix(SEALED_MAGIC.as_slice())
+            .with_context(|| format!("Object {} isn't encrypted", hash))?;
+        if data.len() < NONCE_LEN {
+            bail!("Encrypted object {} is truncated", hash);
+        }
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| anyhow!("Invalid nonce in object {}", hash))?;
+
+        let mut opened = sealed.to_vec();
+        let compressed = self
+            .key
+            .open_in_place(nonce, Aad::from(hash.as_bytes()), &mut opened)
+            .map_err(|_| anyhow!("Failed to decrypt object {}: wrong key or damaged", hash))?;
+        Ok(compressed.to_vec())
+    }
+}
+
+/// Creates a random repository key in the key directory
+///
+/// # Returns
+///
+/// The id of the key, recorded in the repository config
+pub fn generate_key() -> Result<String> {
+    let random = SystemRandom::new();
+    let mut key = [0u8; KEY_LEN];
+    let mut id = [0u8; 16];
+    for bytes in [key.as_mut_slice(), id.as_mut_slice()] {
+        random
+            .fill(bytes)
+            .map_err(|_| anyhow!("Failed to generate an encryption key"))?;
+    }
+
+    let key_id = hex::encode(id);
+    let path = key_path(&key_id)?;
+    fs::create_dir_all(path.parent().context("Invalid key path")?)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    fs::write(&path, format!("{}\n", hex::encode(key)))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(key_id)
+}
+
+/// Path of the key `key_id`, kept out of the repository so that copies of
+/// it don't carry the key along
+pub fn key_path(key_id: &str) -> Result<PathBuf> {
+    if key_id.is_empty() || !key_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid encryption key id: {}", key_id);
+    }
+    Ok(key_dir()?.join(key_id))
+}
+
+/// Directory holding the repository keys, `VOX_KEY_DIR` when it's set and
+/// `~/.vox/keys` otherwise
+pub fn key_dir() -> Result<PathBuf> {
+    match std::env::var_os("VOX_KEY_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => default_key_dir(),
+    }
+}
+
+#[cfg(not(test))]
+fn default_key_dir() -> Result<PathBuf> {
+    Ok(crate::storage::platform::home_dir()?.join(".vox").join("keys"))
+}
+
+/// Tests keep the keys they generate out of the home directory
+#[cfg(test)]
+fn default_key_dir() -> Result<PathBuf> {
+    static DIR: std::sync::OnceLock<tempfile::TempDir> = std::sync::OnceLock::new();
+    let dir = DIR.get_or_init(|| tempfile::tempdir().expect("Failed to create the key directory"));
+    Ok(dir.path().to_path_buf())
+}
+
+/// Reads the object file of `hash` at `path`, decrypting it if it's sealed
+///
+/// # Errors
+///
+/// Returns an error if the file isn't sealed but its repository is
+/// encrypted, as it could have been put there by anyone able to write to the
+/// objects directory
+///
+/// # Returns
+///
+/// The compressed object
+pub fn read_object_file(hash: &str, path: &Path) -> Result<Vec<u8>> {
+    let data = match fs::read(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            bail!(VoxError::ObjectNotFound(hash.to_string()))
+        }
+        data => data.with_context(|| format!("Failed to read object {}", hash))?,
+    };
+    match (data.starts_with(SEALED_MAGIC), owner_key(path)?) {
+        (true, Some(key)) => key.open(hash, &data),
+        (true, None) => bail!("{} is encrypted, but its repository has no key", path.display()),
+        (false, Some(_)) => bail!("Object {} isn't encrypted, but its repository is", hash),
+        (false, None) => Ok(data),
+    }
+}
+
+/// Opens the object file of `hash` at `path` to read the compressed object
+///
+/// Plain object files are streamed, sealed ones have to be decrypted whole
+/// first.
+pub fn open_object_file(hash: &str, path: &Path) -> Result<Box<dyn Read + Send>> {
+    let mut file = match File::open(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            bail!(VoxError::ObjectNotFound(hash.to_string()))
+        }
+        file => file.with_context(|| format!("Failed to open object {}", hash))?,
+    };
+    let mut magic = [0u8; SEALED_MAGIC.len()];
+    let read = file.read(&mut magic)?;
+    if (read == magic.len() && &magic == SEALED_MAGIC) || owner_key(path)?.is_some() {
+        return Ok(Box::new(Cursor::new(read_object_file(hash, path)?)));
+    }
+    Ok(Box::new(Cursor::new(magic[..read].to_vec()).chain(file)))
+}
+
+/// Writes the compressed object `hash` to `path`, encrypted if the objects of
+/// its repository are
+pub fn write_object_file(hash: &str, path: &Path, compressed: &[u8]) -> Result<()> {
+    let data = match owner_key(path)? {
+        Some(key) => key.seal(hash, compressed)?,
+        None => compressed.to_vec(),
+    };
+    fs::write(path, data).with_context(|| format!("Failed to write object {}", hash))
+}
+
+/// Returns true if the objects of `objects_dir` are encrypted
+pub fn is_encrypted(objects_dir: &Path) -> Result<bool> {
+    Ok(objects_key(objects_dir)?.is_some())
+}
+
+/// Returns the key of `objects_dir`, `None` if its objects aren't encrypted
+///
+/// Keys are loaded once per objects directory and kept for the rest of the
+/// process.
+pub fn objects_key(objects_dir: &Path) -> Result<Option<Arc<ObjectKey>>> {
+    // Relative paths name another directory once the current one changes,
+    // and a directory that doesn't exist yet has nothing to cache
+    let Ok(dir) = objects_dir.canonicalize() else {
+        return Ok(ObjectKey::for_objects_dir(objects_dir)?.map(Arc::new));
+    };
+    if let Some(key) = KEYS.lock().unwrap_or_else(PoisonError::into_inner).get(&dir) {
+        return Ok(key.clone());
+    }
+    let key = ObjectKey::for_objects_dir(&dir)?.map(Arc::new);
+    KEYS.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(dir, key.clone());
+    Ok(key)
+}
+
+/// Key of the repository owning the object file at `path`, which may be an
+/// alternate with a key of its own
+fn owner_key(path: &Path) -> Result<Option<Arc<ObjectKey>>> {
+    match path.parent().and_then(Path::parent) {
+        Some(objects_dir) => objects_key(objects_dir),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() -> Result<()> {
+        let key = ObjectKey::new(&[7u8; KEY_LEN])?;
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        let sealed = key.seal(hash, b"compressed object")?;
+        assert!(sealed.starts_with(SEALED_MAGIC));
+        assert!(!sealed.windows(10).any(|window| window == b"compressed"));
+        assert_eq!(key.open(hash, &sealed)?, b"compressed object");
+
+        // The file of another object, or from another key, doesn't open
+        assert!(key.open("1123456789abcdef0123456789abcdef01234567", &sealed).is_err());
+        assert!(ObjectKey::new(&[8u8; KEY_LEN])?.open(hash, &sealed).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Socket `vox watch` listens on, relative to `.vox`
+pub const SOCKET_FILE: &str = "fsmonitor.sock";
+
+/// What changed in the work tree since a token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Changes {
+    /// The watcher can't tell, e.g. it wasn't running at the time of the
+    /// token: anything may have changed
+    Unknown,
+    /// Paths of the files and directories that changed, relative to the
+    /// root of the work tree
+    Paths(HashSet<PathBuf>),
+}
+
+/// The reply of the watcher to a query
+///
+/// A query asks what changed since the token of an earlier reply, and the
+/// reply comes with a new token to ask from next time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub token: String,
+    pub changes: Changes,
+}
+
+impl Answer {
+    /// Returns true if `path` may have changed since the token: it changed,
+    /// or one of its directories did, as when it's renamed or removed
+    pub fn is_changed(&self, path: &Path) -> bool {
+        match &self.changes {
+            Changes::Unknown => true,
+            Changes::Paths(paths) => path.ancestors().any(|path| paths.contains(path)),
+        }
+    }
+
+    /// Serializes the reply: `token <token>\n`, then `unknown\n`, or
+    /// `changed\n` and the changed paths each followed by a NUL
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = format!("token {}\n", self.token).into_bytes();
+        match &self.changes {
+            Changes::Unknown => data.extend_from_slice(b"unknown\n"),
+            Changes::Paths(paths) => {
+                data.extend_from_slice(b"changed\n");
+                for path in paths {
+                    data.extend_from_slice(path.to_string_lossy().as_bytes());
+                    data.push(0);
+                }
+            }
+        }
+        data
+    }
+
+    /// Parses a reply written by [`Answer::serialize`]
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let text = String::from_utf8_lossy(data);
+        let (token, rest) = text
+            .strip_prefix("token ")
+            .and_then(|rest| rest.split_once('\n'))
+            .context("Missing token in the watcher's reply")?;
+
+        let changes = if rest == "unknown\n" {
+            Changes::Unknown
+        } else if let Some(paths) = rest.strip_prefix("changed\n") {
+            Changes::Paths(
+                paths
+                    .split_terminator('\0')
+                    .map(PathBuf::from)
+                    .collect(),
+            )
+        } else {
+            bail!("Invalid reply from the watcher");
+        };
+
+        Ok(Self {
+            token: token.to_string(),
+            changes,
+        })
+    }
+}
+
+/// Asks the `vox watch` daemon of the repository at `vox_dir` what changed
+/// since `token`
+///
+/// # Returns
+///
+/// The reply, or `None` if no daemon is running or it couldn't answer, in
+/// which case the work tree has to be checked in full
+#[cfg(unix)]
+pub fn query(vox_dir: &Path, token: &str) -> Option<Answer> {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let mut stream = UnixStream::connect(vox_dir.join(SOCKET_FILE)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    stream.write_all(format!("query {}\n", token).as_bytes()).ok()?;
+    stream.shutdown(std::net::Shutdown::Write).ok()?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).ok()?;
+    Answer::parse(&reply).ok()
+}
+
+#[cfg(not(unix))]
+pub fn query(_vox_dir: &Path, _token: &str) -> Option<Answer> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer_roundtrip() -> Result<()> {
+        let paths: HashSet<PathBuf> = ["src/main.rs", "docs", "name with\nnewline"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let answer = Answer {
+            token: "1234:5".to_string(),
+            changes: Changes::Paths(paths),
+        };
+        assert_eq!(Answer::parse(&answer.serialize())?, answer);
+        assert!(answer.is_changed(Path::new("src/main.rs")));
+        assert!(answer.is_changed(Path::new("docs/guide.md")));
+        assert!(!answer.is_changed(Path::new("src/lib.rs")));
+
+        let unknown = Answer {
+            token: "1234:6".to_string(),
+            changes: Changes::Unknown,
+        };
+        assert_eq!(Answer::parse(&unknown.serialize())?, unknown);
+        assert!(unknown.is_changed(Path::new("src/lib.rs")));
+
+        assert!(Answer::parse(b"garbage").is_err());
+        Ok(())
+    }
+}
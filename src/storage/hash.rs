@@ -0,0 +1,289 @@
+use crate::storage::repo_config::RepoConfig;
+use crate::storage::utils::VOX_DIR;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+
+lazy_static! {
+    /// Format of every objects directory read or written so far, so the
+    /// config is read once per process rather than for every object
+    static ref FORMATS: Mutex<HashMap<PathBuf, ObjectFormat>> = Mutex::new(HashMap::new());
+}
+
+/// Hash algorithm used to name objects in a repository
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+/// Identifier of an object, i.e. the raw digest of its content
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectFormat {
+    /// Returns the object format of the repository in the current directory
+    pub fn current() -> Result<Self> {
+        Self::for_vox_dir(&VOX_DIR)
+    }
+
+    /// Returns the object format configured in `<vox_dir>/config`
+    ///
+    /// Repositories without a config file predate the setting and use SHA-1
+    pub fn for_vox_dir(vox_dir: &Path) -> Result<Self> {
+        Ok(RepoConfig::load(vox_dir)?.object_format())
+    }
+
+    /// Returns the object format of the repository owning `objects_dir`
+    pub fn for_objects_dir(objects_dir: &Path) -> Result<Self> {
+        // Relative paths name another directory once the current one changes,
+        // and a directory that doesn't exist yet has nothing to cache
+        let Ok(dir) = objects_dir.canonicalize() else {
+            return Self::load(objects_dir);
+        };
+        if let Some(format) = FORMATS.lock().unwrap_or_else(PoisonError::into_inner).get(&dir) {
+            return Ok(*format);
+        }
+        let format = Self::load(&dir)?;
+        FORMATS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(dir, format);
+        Ok(format)
+    }
+
+    fn load(objects_dir: &Path) -> Result<Self> {
+        match objects_dir.parent() {
+            Some(vox_dir) => Self::for_vox_dir(vox_dir),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Forgets the format cached for `objects_dir`, once its config changed
+    pub fn forget(objects_dir: &Path) {
+        if let Ok(dir) = objects_dir.canonicalize() {
+            FORMATS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&dir);
+        }
+    }
+
+    /// Name used in the config file and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// Length of a raw digest in bytes
+    pub fn raw_len(&self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    /// Length of a hex-encoded digest
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    /// Hashes `data` with this format's algorithm
+    pub fn digest(&self, data: &[u8]) -> ObjectId {
+        match self {
+            ObjectFormat::Sha1 => ObjectId::Sha1(Sha1::digest(data).into()),
+            ObjectFormat::Sha256 => ObjectId::Sha256(Sha256::digest(data).into()),
+        }
+    }
+
+    /// Returns a hasher to feed data to piece by piece, for content that
+    /// doesn't fit in memory
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            ObjectFormat::Sha1 => Hasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// Returns the all-zero id used for entries that haven't been hashed yet
+    pub fn null_id(&self) -> ObjectId {
+        match self {
+            ObjectFormat::Sha1 => ObjectId::Sha1([0; 20]),
+            ObjectFormat::Sha256 => ObjectId::Sha256([0; 32]),
+        }
+    }
+}
+
+/// Incremental version of [`ObjectFormat::digest`]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the digest of everything fed so far
+    pub fn finish(self) -> ObjectId {
+        match self {
+            Hasher::Sha1(hasher) => ObjectId::Sha1(hasher.finalize().into()),
+            Hasher::Sha256(hasher) => ObjectId::Sha256(hasher.finalize().into()),
+        }
+    }
+}
+
+impl FromStr for ObjectFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => Err(anyhow!("Unknown object format: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ObjectFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl ObjectId {
+    /// Builds an id from a raw digest of the given format
+    pub fn from_bytes(format: ObjectFormat, bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != format.raw_len() {
+            return Err(anyhow!(
+                "Invalid {} digest length: expected {} bytes, got {}",
+                format,
+                format.raw_len(),
+                bytes.len()
+            ));
+        }
+
+        let mut id = format.null_id();
+        match &mut id {
+            ObjectId::Sha1(raw) => raw.copy_from_slice(bytes),
+            ObjectId::Sha256(raw) => raw.copy_from_slice(bytes),
+        }
+        Ok(id)
+    }
+
+    /// Parses a hex-encoded id, inferring the format from its length
+    pub fn from_hex(hex_str: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_str).map_err(|_| anyhow!("Invalid object id: {}", hex_str))?;
+        let format = match bytes.len() {
+            20 => ObjectFormat::Sha1,
+            32 => ObjectFormat::Sha256,
+            _ => return Err(anyhow!("Invalid object id: {}", hex_str)),
+        };
+        Self::from_bytes(format, &bytes)
+    }
+
+    /// Returns the format this id was produced with
+    pub fn format(&self) -> ObjectFormat {
+        match self {
+            ObjectId::Sha1(_) => ObjectFormat::Sha1,
+            ObjectId::Sha256(_) => ObjectFormat::Sha256,
+        }
+    }
+
+    /// Returns the raw digest bytes
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(raw) => raw,
+            ObjectId::Sha256(raw) => raw,
+        }
+    }
+
+    /// Returns the hex-encoded digest
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.as_bytes())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({}:{})", self.format(), self.to_hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_lengths() {
+        let sha1 = ObjectFormat::Sha1.digest(b"hello");
+        let sha256 = ObjectFormat::Sha256.digest(b"hello");
+
+        assert_eq!(sha1.to_hex(), "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d");
+        assert_eq!(
+            sha256.to_hex(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(sha1.as_bytes().len(), ObjectFormat::Sha1.raw_len());
+        assert_eq!(sha256.to_hex().len(), ObjectFormat::Sha256.hex_len());
+    }
+
+    #[test]
+    fn test_hex_roundtrip() -> Result<()> {
+        let id = ObjectFormat::Sha256.digest(b"content");
+        let parsed = ObjectId::from_hex(&id.to_hex())?;
+
+        assert_eq!(id, parsed);
+        assert_eq!(parsed.format(), ObjectFormat::Sha256);
+        assert!(ObjectId::from_hex("abcd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_cached_until_forgotten() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        std::fs::create_dir_all(&objects_dir)?;
+        RepoConfig::new(ObjectFormat::Sha256).save(dir.path())?;
+        assert_eq!(ObjectFormat::for_objects_dir(&objects_dir)?, ObjectFormat::Sha256);
+
+        // As migrate does, the format changes within the process
+        RepoConfig::new(ObjectFormat::Sha1).save(dir.path())?;
+        assert_eq!(ObjectFormat::for_objects_dir(&objects_dir)?, ObjectFormat::Sha256);
+        ObjectFormat::forget(&objects_dir);
+        assert_eq!(ObjectFormat::for_objects_dir(&objects_dir)?, ObjectFormat::Sha1);
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Scripts run by vox at certain points, found in `.vox/hooks/<name>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    /// Runs before a commit is created, without arguments
+    PreCommit,
+    /// Runs with the path of a file holding the commit message, which the
+    /// hook may edit
+    CommitMsg,
+    /// Runs after a commit was created, its exit status is ignored
+    PostCommit,
+    /// Runs before refs are pushed, with the remote name and URL as arguments
+    /// and one `<local ref> <local hash> <remote ref> <remote hash>` line per
+    /// updated ref on stdin
+    PrePush,
+}
+
+impl Hook {
+    pub const ALL: [Hook; 4] = [
+        Hook::PreCommit,
+        Hook::CommitMsg,
+        Hook::PostCommit,
+        Hook::PrePush,
+    ];
+
+    /// File name of the hook
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::CommitMsg => "commit-msg",
+            Hook::PostCommit => "post-commit",
+            Hook::PrePush => "pre-push",
+        }
+    }
+
+    /// Contents of the sample script written by `vox init`
+    fn sample(&self) -> &'static str {
+        match self {
+            Hook::PreCommit => {
+                "#!/bin/sh\n\
+                 # Runs before a commit is created. Exit non-zero to abort the commit.\n\
+                 # Rename this file to 'pre-commit' to enable it.\n"
+            }
+            Hook::CommitMsg => {
+                "#!/bin/sh\n\
+                 # Runs with the path of the commit message file as $1. The message\n\
+                 # may be edited in place. Exit non-zero to abort the commit.\n\
+                 # Rename this file to 'commit-msg' to enable it.\n"
+            }
+            Hook::PostCommit => {
+                "#!/bin/sh\n\
+                 # Runs after a commit is created. The exit status is ignored.\n\
+                 # Rename this file to 'post-commit' to enable it.\n"
+            }
+            Hook::PrePush => {
+                "#!/bin/sh\n\
+                 # Runs before a push with the remote name as $1 and its URL as $2.\n\
+                 # Each updated ref is a line on stdin:\n\
+                 #   <local ref> <local hash> <remote ref> <remote hash>\n\
+                 # Exit non-zero to abort the push.\n\
+                 # Rename this file to 'pre-push' to enable it.\n"
+            }
+        }
+    }
+}
+
+/// Path of the hooks directory
+pub fn hooks_dir(vox_dir: &Path) -> PathBuf {
+    vox_dir.join("hooks")
+}
+
+/// Creates the hooks directory with a disabled sample of every hook
+pub fn write_hook_samples(vox_dir: &Path) -> Result<()> {
+    let dir = hooks_dir(vox_dir);
+    fs::create_dir_all(&dir).context("Failed to create hooks directory")?;
+
+    for hook in Hook::ALL {
+        let path = dir.join(format!("{}.sample", hook.name()));
+        fs::write(&path, hook.sample())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Runs `hook` if it's installed
+///
+/// The hook runs in the current directory with `VOX_DIR` set to the
+/// repository, gets `args` as arguments and `stdin` on its standard input.
+/// Hooks that don't exist or aren't executable are skipped.
+///
+/// # Errors
+///
+/// Returns an error if the hook can't be started or exits with a non-zero
+/// status
+pub fn run_hook(vox_dir: &Path, hook: Hook, args: &[&str], stdin: &[u8]) -> Result<()> {
+    let path = hooks_dir(vox_dir).join(hook.name());
+    if !is_runnable(&path) {
+        return Ok(());
+    }
+
+    let mut command = hook_command(&path);
+    command
+        .args(args)
+        .env(
+            "VOX_DIR",
+            vox_dir.canonicalize().unwrap_or(vox_dir.to_path_buf()),
+        )
+        .stdin(Stdio::piped());
+
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to run the {} hook", hook.name()))?;
+
+    // A hook may exit without reading its input, so a broken pipe is fine
+    if let Some(mut child_stdin) = child.stdin.take() {
+        let _ = child_stdin.write_all(stdin);
+    }
+
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for the {} hook", hook.name()))?;
+    if !status.success() {
+        match status.code() {
+            Some(code) => bail!("The {} hook exited with status {}", hook.name(), code),
+            None => bail!("The {} hook was terminated by a signal", hook.name()),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_runnable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Windows has no execute bit, every hook file is run
+#[cfg(not(unix))]
+fn is_runnable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+fn hook_command(path: &Path) -> Command {
+    Command::new(path)
+}
+
+/// Hooks are shell scripts, which Windows can't start by themselves
+#[cfg(not(unix))]
+fn hook_command(path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command.arg(path);
+    command
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::storage::platform::set_executable;
+    use tempfile::tempdir;
+
+    fn install(vox_dir: &Path, hook: Hook, script: &str) -> Result<()> {
+        let path = hooks_dir(vox_dir).join(hook.name());
+        fs::write(&path, script)?;
+        set_executable(&path, true)
+    }
+
+    #[test]
+    fn test_run_hook() -> Result<()> {
+        let dir = tempdir()?;
+        write_hook_samples(dir.path())?;
+
+        // Missing hooks and the disabled samples are skipped
+        run_hook(dir.path(), Hook::PreCommit, &[], b"")?;
+
+        let output = dir.path().join("output");
+        install(
+            dir.path(),
+            Hook::PrePush,
+            &format!(
+                "#!/bin/sh\necho \"$1 $2\" > {}\ncat >> {}\n",
+                output.display(),
+                output.display()
+            ),
+        )?;
+        run_hook(
+            dir.path(),
+            Hook::PrePush,
+            &["origin", "/srv/repo"],
+            b"refs/heads/main a b c\n",
+        )?;
+        assert_eq!(
+            fs::read_to_string(&output)?,
+            "origin /srv/repo\nrefs/heads/main a b c\n"
+        );
+
+        install(dir.path(), Hook::PreCommit, "#!/bin/sh\nexit 3\n")?;
+        let error = run_hook(dir.path(), Hook::PreCommit, &[], b"").unwrap_err();
+        assert!(error.to_string().contains("exited with status 3"));
+        Ok(())
+    }
+}
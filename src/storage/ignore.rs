@@ -0,0 +1,320 @@
+use crate::commands::config::commands::excludes_file;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the files listing the untracked files to ignore, which apply to
+/// the directory they are in and below it
+pub const IGNORE_FILE: &str = ".voxignore";
+
+/// Ignore rules of the repository that aren't shared, relative to `.vox`
+const INFO_EXCLUDE: &str = "info/exclude";
+
+/// A line of an ignore file
+///
+/// The syntax is the one of `.gitignore`: `*` and `?` don't match `/`, `**`
+/// matches any number of directories, a trailing `/` only matches
+/// directories, a leading `!` re-includes what an earlier rule ignored, and
+/// a pattern with a `/` other than a trailing one is relative to the
+/// directory of its file rather than matching names at any depth.
+#[derive(Debug, Clone)]
+pub struct IgnoreRule {
+    /// The pattern as written
+    pub pattern: String,
+    /// The file the rule comes from
+    pub source: PathBuf,
+    /// Line number of the rule in `source`, starting at 1
+    pub line: usize,
+    negated: bool,
+    dir_only: bool,
+    /// Directory the pattern is relative to, from the repository root
+    base: PathBuf,
+    regex: Regex,
+}
+
+impl IgnoreRule {
+    /// Parses a line of an ignore file
+    ///
+    /// # Returns
+    ///
+    /// The rule, or `None` for blank lines and `#` comments
+    pub fn parse(text: &str, base: &Path, source: &Path, line: usize) -> Result<Option<Self>> {
+        // Trailing spaces are dropped unless escaped
+        let mut pattern = text.trim_end_matches(['\n', '\r']);
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (negated, glob) = match pattern.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, pattern),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+
+        let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+        let regex = Regex::new(&format!("{}{}$", prefix, glob_to_regex(glob)))
+            .with_context(|| format!("Invalid pattern '{}' in {}", pattern, source.display()))?;
+
+        Ok(Some(Self {
+            pattern: pattern.to_string(),
+            source: source.to_path_buf(),
+            line,
+            negated,
+            dir_only,
+            base: base.to_path_buf(),
+            regex,
+        }))
+    }
+
+    /// Returns true if the rule re-includes the paths it matches
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// Returns true if the rule matches `path`, relative to the repository
+    /// root
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match path.strip_prefix(&self.base) {
+            Ok(relative) => self.regex.is_match(&relative.to_string_lossy()),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Parses the rules of an ignore file
+fn parse_rules(text: &str, base: &Path, source: &Path) -> Result<Vec<IgnoreRule>> {
+    let mut rules = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        rules.extend(IgnoreRule::parse(line, base, source, number + 1)?);
+    }
+    Ok(rules)
+}
+
+/// Tells which untracked files are ignored
+///
+/// Rules come from the global excludes file (`core.excludesFile`),
+/// `.vox/info/exclude` and the `.voxignore` file of every directory, read
+/// when a path below it is first looked up. The last rule matching a path
+/// decides, and rules come in that order, those of deeper `.voxignore` files
+/// after those of their parents, so the rules closest to a path win. A file
+/// in an ignored directory is ignored whatever the rules for the file
+/// itself, as the directory isn't searched.
+pub struct IgnoreMatcher {
+    repo_path: PathBuf,
+    /// Rules applying to the whole work tree, before any `.voxignore`
+    global: Vec<IgnoreRule>,
+    /// Rules of the `.voxignore` file of each directory looked at so far
+    dirs: HashMap<PathBuf, Vec<IgnoreRule>>,
+}
+
+impl IgnoreMatcher {
+    /// Creates a matcher for the work tree at `repo_path`, with the
+    /// configured global excludes file
+    pub fn new(repo_path: &Path) -> Result<Self> {
+        Self::with_excludes_file(repo_path, excludes_file().as_deref())
+    }
+
+    /// Creates a matcher for the work tree at `repo_path`, reading global
+    /// rules from `excludes_file` if it exists
+    pub fn with_excludes_file(repo_path: &Path, excludes_file: Option<&Path>) -> Result<Self> {
+        let mut matcher = Self {
+            repo_path: repo_path.to_path_buf(),
+            global: Vec::new(),
+            dirs: HashMap::new(),
+        };
+
+        if let Some(path) = excludes_file
+            && let Ok(text) = fs::read_to_string(path)
+        {
+            matcher.global = parse_rules(&text, Path::new(""), path)?;
+        }
+        let exclude = Path::new(".vox").join(INFO_EXCLUDE);
+        if let Ok(text) = fs::read_to_string(repo_path.join(&exclude)) {
+            matcher.global.extend(parse_rules(&text, Path::new(""), &exclude)?);
+        }
+        Ok(matcher)
+    }
+
+    /// Returns true if the untracked `path`, relative to the repository
+    /// root, is ignored
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> Result<bool> {
+        Ok(self.matching_rule(path, is_dir)?.is_some_and(|rule| !rule.is_negated()))
+    }
+
+    /// Finds the rule deciding whether `path` is ignored: the rule ignoring
+    /// one of its directories, or else the last rule matching the path
+    ///
+    /// # Returns
+    ///
+    /// The rule, which re-includes the path if negated, or `None` if no rule
+    /// matches
+    pub fn matching_rule(&mut self, path: &Path, is_dir: bool) -> Result<Option<IgnoreRule>> {
+        let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        dirs.reverse();
+        for dir in dirs.iter().filter(|dir| !dir.as_os_str().is_empty()) {
+            if let Some(rule) = self.last_match(dir, true)?
+                && !rule.is_negated()
+            {
+                return Ok(Some(rule));
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    /// The last rule matching `path` itself
+    fn last_match(&mut self, path: &Path, is_dir: bool) -> Result<Option<IgnoreRule>> {
+        let mut found = self.global.iter().rev().find(|rule| rule.matches(path, is_dir)).cloned();
+        let mut dirs: Vec<&Path> = path.ancestors().skip(1).collect();
+        dirs.reverse();
+        for dir in dirs {
+            let rules = self.dir_rules(dir)?;
+            if let Some(rule) = rules.iter().rev().find(|rule| rule.matches(path, is_dir)) {
+                found = Some(rule.clone());
+            }
+        }
+        Ok(found)
+    }
+
+    /// The rules of the `.voxignore` file in `dir`, if it has one
+    fn dir_rules(&mut self, dir: &Path) -> Result<&[IgnoreRule]> {
+        if !self.dirs.contains_key(dir) {
+            let source = dir.join(IGNORE_FILE);
+            let rules = match fs::read_to_string(self.repo_path.join(&source)) {
+                Ok(text) => parse_rules(&text, dir, &source)?,
+                Err(_) => Vec::new(),
+            };
+            self.dirs.insert(dir.to_path_buf(), rules);
+        }
+        Ok(&self.dirs[dir])
+    }
+}
+
+/// Translates an ignore pattern into a regular expression, without anchors
+///
+/// `.voxattributes` patterns share the syntax, so they are translated here
+/// as well.
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // `**/` matches any number of directories, a trailing `**`
+            // everything below
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                match chars.next_if_eq(&'/') {
+                    Some(_) => regex.push_str("(?:.*/)?"),
+                    None => regex.push_str(".*"),
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let mut class = String::new();
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    class.push('^');
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' && !class.trim_start_matches('^').is_empty() {
+                        closed = true;
+                        break;
+                    }
+                    if matches!(c, '\\' | '[' | ']' | '&' | '~') {
+                        class.push('\\');
+                    }
+                    class.push(c);
+                }
+                match closed {
+                    true => regex.push_str(&format!("[{}]", class)),
+                    false => regex.push_str(&regex::escape(&format!("[{}", class))),
+                }
+            }
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    regex.push_str(&regex::escape(&escaped.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ignore_rules() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(
+            dir.path().join(IGNORE_FILE),
+            "# build output\n*.log\n!keep.log\n/target\nbuild/\ndocs/**/*.tmp\n\\#notes\n",
+        )?;
+        fs::create_dir_all(dir.path().join("src"))?;
+        fs::write(dir.path().join("src").join(IGNORE_FILE), "generated.rs\n!debug.log\n")?;
+
+        let mut matcher = IgnoreMatcher::new(dir.path())?;
+        let mut ignored = |path: &str, is_dir| matcher.is_ignored(Path::new(path), is_dir);
+
+        assert!(ignored("error.log", false)?);
+        assert!(ignored("logs/error.log", false)?);
+        assert!(!ignored("keep.log", false)?);
+        assert!(ignored("target", true)?);
+        assert!(ignored("target/debug/vox", false)?);
+        assert!(!ignored("src/target", true)?);
+        assert!(ignored("build", true)?);
+        assert!(!ignored("build", false)?);
+        assert!(ignored("src/build/out.o", false)?);
+        assert!(ignored("docs/a/b/page.tmp", false)?);
+        assert!(ignored("docs/page.tmp", false)?);
+        assert!(!ignored("page.tmp", false)?);
+        assert!(ignored("#notes", false)?);
+        assert!(ignored("src/generated.rs", false)?);
+        assert!(!ignored("generated.rs", false)?);
+        assert!(!ignored("src/debug.log", false)?);
+        assert!(!ignored("src/main.rs", false)?);
+
+        let rule = matcher.matching_rule(Path::new("keep.log"), false)?.unwrap();
+        assert!(rule.is_negated());
+        assert_eq!((rule.pattern.as_str(), rule.line), ("!keep.log", 3));
+        assert_eq!(rule.source, Path::new(IGNORE_FILE));
+        Ok(())
+    }
+
+    #[test]
+    fn test_excludes_file_precedence() -> Result<()> {
+        let dir = tempdir()?;
+        let excludes = dir.path().join("global-ignore");
+        fs::write(&excludes, "*.swp\n*.o\n!main.o\n")?;
+        fs::create_dir_all(dir.path().join(".vox/info"))?;
+        fs::write(dir.path().join(".vox").join(INFO_EXCLUDE), "!keep.o\n")?;
+        fs::write(dir.path().join(IGNORE_FILE), "main.o\n")?;
+
+        let mut matcher = IgnoreMatcher::with_excludes_file(dir.path(), Some(&excludes))?;
+        assert!(matcher.is_ignored(Path::new("src/.main.rs.swp"), false)?);
+        assert!(matcher.is_ignored(Path::new("lib.o"), false)?);
+        // The repository's own rules override the global ones
+        assert!(!matcher.is_ignored(Path::new("keep.o"), false)?);
+        assert!(matcher.is_ignored(Path::new("main.o"), false)?);
+
+        let rule = matcher.matching_rule(Path::new("lib.o"), false)?.unwrap();
+        assert_eq!((rule.source.as_path(), rule.line), (excludes.as_path(), 2));
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use crate::commands::clone::clone::local_repository_path;
+use crate::storage::hash::ObjectFormat;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Directory large file contents are kept in, relative to `.vox`
+pub const LFS_OBJECTS: &str = "lfs/objects";
+
+/// First line of pointer files, the same as Git LFS so pointers can move
+/// between both
+const POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Pointers are a few lines of text, anything longer is regular content
+pub const MAX_POINTER_SIZE: usize = 1024;
+
+/// What is stored in place of a large file: the SHA-256 and size of its
+/// content, which lives in the LFS object store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// Hex SHA-256 of the content
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Creates the pointer to `data`
+    pub fn for_content(data: &[u8]) -> Self {
+        Self {
+            oid: ObjectFormat::Sha256.digest(data).to_hex(),
+            size: data.len() as u64,
+        }
+    }
+
+    /// Parses `data` as a pointer file
+    ///
+    /// # Returns
+    ///
+    /// The pointer, or `None` if `data` is regular content
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.lines();
+        if lines.next()? != POINTER_VERSION {
+            return None;
+        }
+
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            if let Some(value) = line.strip_prefix("oid sha256:") {
+                oid = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("size ") {
+                size = value.parse().ok();
+            }
+        }
+        let oid = oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+        Some(Self { oid, size: size? })
+    }
+
+    /// Serializes the pointer file
+    pub fn serialize(&self) -> Vec<u8> {
+        format!("{}\noid sha256:{}\nsize {}\n", POINTER_VERSION, self.oid, self.size).into_bytes()
+    }
+}
+
+/// Path of the content of `oid` in the object store at `objects`, fanned out
+/// by its first two bytes as `ab/cd/abcd...`
+fn object_path(objects: &Path, oid: &str) -> PathBuf {
+    objects.join(&oid[..2]).join(&oid[2..4]).join(oid)
+}
+
+/// Writes `data` to `path` unless it's already there, going through a
+/// temporary file so an interrupted write never leaves partial content
+fn write_object(path: &Path, data: &[u8]) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let parent = path.parent().context("Invalid LFS object path")?;
+    fs::create_dir_all(parent)?;
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to store LFS object {}", path.display()))
+}
+
+/// The large file contents of a repository, along with the store of the
+/// configured `lfs.url` they're fetched from when missing and pushed to
+pub struct LfsStore {
+    objects: PathBuf,
+    url: Option<String>,
+}
+
+impl LfsStore {
+    /// Opens the store of the repository at `vox_dir`, with `url` as the
+    /// remote store
+    pub fn new(vox_dir: &Path, url: Option<String>) -> Self {
+        Self {
+            objects: vox_dir.join(LFS_OBJECTS),
+            url,
+        }
+    }
+
+    /// The object directory of the remote store, if there's one
+    ///
+    /// The remote is another repository, whose `.vox/lfs/objects` is used, or
+    /// a directory laid out like it. As for `clone`, only local paths and
+    /// `file://` URLs can be reached.
+    fn remote(&self) -> Result<Option<PathBuf>> {
+        let Some(url) = &self.url else {
+            return Ok(None);
+        };
+        let path = local_repository_path(url)?;
+        Ok(Some(match path.join(".vox").is_dir() {
+            true => path.join(".vox").join(LFS_OBJECTS),
+            false => path,
+        }))
+    }
+
+    /// Stores `data` and returns the pointer to put in its place
+    pub fn store(&self, data: &[u8]) -> Result<Pointer> {
+        let pointer = Pointer::for_content(data);
+        write_object(&object_path(&self.objects, &pointer.oid), data)?;
+        Ok(pointer)
+    }
+
+    /// Returns the content `pointer` stands for, fetching it from the remote
+    /// store if it isn't here
+    pub fn load(&self, pointer: &Pointer) -> Result<Vec<u8>> {
+        let path = object_path(&self.objects, &pointer.oid);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => {
+                let Some(remote) = self.remote()? else {
+                    bail!("LFS object {} is missing and lfs.url isn't set", pointer.oid);
+                };
+                let data = fs::read(object_path(&remote, &pointer.oid)).with_context(|| {
+                    format!("LFS object {} is missing from {}", pointer.oid, remote.display())
+                })?;
+                write_object(&path, &data)?;
+                data
+            }
+        };
+
+        if Pointer::for_content(&data) != *pointer {
+            bail!("LFS object {} is corrupt", pointer.oid);
+        }
+        Ok(data)
+    }
+
+    /// Copies the objects the remote store doesn't have to it
+    ///
+    /// # Returns
+    ///
+    /// The number of objects copied
+    pub fn push(&self) -> Result<usize> {
+        let remote = self.remote()?.context("lfs.url isn't set")?;
+        if !self.objects.exists() {
+            return Ok(0);
+        }
+
+        let mut copied = 0;
+        for entry in WalkDir::new(&self.objects) {
+            let entry = entry?;
+            let oid = entry.file_name().to_string_lossy();
+            if !entry.file_type().is_file() || oid.len() != 64 {
+                continue;
+            }
+            let destination = object_path(&remote, &oid);
+            if !destination.exists() {
+                write_object(&destination, &fs::read(entry.path())?)?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_store_and_fetch() -> Result<()> {
+        let local = tempdir()?;
+        let remote = tempdir()?;
+        let url = remote.path().to_string_lossy().into_owned();
+        let store = LfsStore::new(local.path(), Some(url.clone()));
+
+        let data = vec![7u8; 4096];
+        let pointer = store.store(&data)?;
+        assert_eq!(Pointer::parse(&pointer.serialize()), Some(pointer.clone()));
+        assert_eq!(Pointer::parse(b"regular content"), None);
+        assert_eq!(store.load(&pointer)?, data);
+
+        assert_eq!(store.push()?, 1);
+        assert_eq!(store.push()?, 0);
+
+        let clone = tempdir()?;
+        let store = LfsStore::new(clone.path(), Some(url));
+        assert_eq!(store.load(&pointer)?, data);
+        assert!(clone.path().join(LFS_OBJECTS).join(&pointer.oid[..2]).exists());
+
+        let missing = Pointer::for_content(b"never stored");
+        assert!(store.load(&missing).is_err());
+        Ok(())
+    }
+}
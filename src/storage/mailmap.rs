@@ -0,0 +1,164 @@
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::tree::lookup_path;
+use crate::storage::refs::resolve_head;
+use crate::storage::repo_config::RepoConfig;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Name of the mailmap file at the root of the work tree
+pub const MAILMAP_FILE: &str = ".mailmap";
+
+/// The canonical name and email for an identity, `None` keeps the one
+/// recorded in the commit
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Replacement {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// Canonical author names and emails, read from `.mailmap`
+///
+/// Each line maps an email, optionally together with a name, as recorded in
+/// commits to the proper name and/or email:
+///
+/// ```text
+/// Proper Name <commit@email>
+/// <proper@email> <commit@email>
+/// Proper Name <proper@email> <commit@email>
+/// Proper Name <proper@email> Commit Name <commit@email>
+/// ```
+///
+/// Emails and names are matched case-insensitively, `#` starts a comment.
+#[derive(Debug, Default)]
+pub struct Mailmap {
+    /// Lowercased commit email -> replacement for any name
+    by_email: HashMap<String, Replacement>,
+    /// Lowercased commit email and name -> replacement for that name only
+    by_identity: HashMap<(String, String), Replacement>,
+}
+
+impl Mailmap {
+    /// Reads the mailmap of the repository at `vox_dir`, empty if it has none
+    ///
+    /// The file is read from the work tree, or from the HEAD commit in a
+    /// bare repository
+    pub fn load(vox_dir: &Path, objects_dir: &Path) -> Result<Self> {
+        if !RepoConfig::load(vox_dir)?.is_bare() {
+            return match fs::read_to_string(MAILMAP_FILE) {
+                Ok(content) => Ok(Self::parse(&content)),
+                Err(_) => Ok(Self::default()),
+            };
+        }
+
+        let Some(head) = resolve_head(vox_dir)? else {
+            return Ok(Self::default());
+        };
+        let tree = load_commit(&head, objects_dir)?.tree;
+        match lookup_path(&tree, Path::new(MAILMAP_FILE), objects_dir)? {
+            Some(entry) => {
+                let blob = Blob::load(&entry.object_hash, objects_dir)
+                    .with_context(|| format!("Failed to read {}", MAILMAP_FILE))?;
+                Ok(Self::parse(&String::from_utf8_lossy(&blob.data)))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parses the content of a mailmap file, skipping malformed lines
+    pub fn parse(content: &str) -> Self {
+        let mut mailmap = Self::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut parts = Vec::new();
+            let mut rest = line;
+            // Every part is an optional name followed by an email in brackets
+            while let Some((name, tail)) = rest.split_once('<') {
+                let Some((email, tail)) = tail.split_once('>') else {
+                    break;
+                };
+                let name = name.trim();
+                parts.push(((!name.is_empty()).then(|| name.to_string()), email.trim()));
+                rest = tail;
+            }
+
+            match parts.as_slice() {
+                [(Some(name), email)] => {
+                    let replacement = mailmap.by_email.entry(email.to_lowercase()).or_default();
+                    replacement.name = Some(name.clone());
+                }
+                [(proper_name, proper_email), (None, email)] => {
+                    let replacement = mailmap.by_email.entry(email.to_lowercase()).or_default();
+                    if proper_name.is_some() {
+                        replacement.name = proper_name.clone();
+                    }
+                    replacement.email = Some(proper_email.to_string());
+                }
+                [(proper_name, proper_email), (Some(name), email)] => {
+                    let key = (email.to_lowercase(), name.to_lowercase());
+                    mailmap.by_identity.insert(
+                        key,
+                        Replacement {
+                            name: proper_name.clone(),
+                            email: Some(proper_email.to_string()),
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+        mailmap
+    }
+
+    /// Maps an identity of the form `Name <email>` to its canonical form
+    ///
+    /// Identities without a mapping, or not of that form, are returned as is
+    pub fn map(&self, identity: &str) -> String {
+        let Some((name, email)) = identity
+            .split_once(" <")
+            .and_then(|(name, rest)| Some((name, rest.strip_suffix('>')?)))
+        else {
+            return identity.to_string();
+        };
+
+        let key = (email.to_lowercase(), name.to_lowercase());
+        let Some(replacement) = self
+            .by_identity
+            .get(&key)
+            .or_else(|| self.by_email.get(&key.0))
+        else {
+            return identity.to_string();
+        };
+        format!(
+            "{} <{}>",
+            replacement.name.as_deref().unwrap_or(name),
+            replacement.email.as_deref().unwrap_or(email)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mailmap_forms() {
+        let mailmap = Mailmap::parse(
+            "# Canonical identities\n\
+             Jane Doe <jane@example.com>\n\
+             <jane@example.com> <jdoe@old.example.com>\n\
+             Joe Dev <joe@example.com> <joe@laptop>  # moved\n\
+             Ann Lee <ann@example.com> ann <shared@example.com>\n\
+             not a mapping\n",
+        );
+
+        assert_eq!(mailmap.map("jane <JANE@example.com>"), "Jane Doe <JANE@example.com>");
+        assert_eq!(mailmap.map("Jane D <jdoe@old.example.com>"), "Jane D <jane@example.com>");
+        assert_eq!(mailmap.map("joe <joe@laptop>"), "Joe Dev <joe@example.com>");
+        assert_eq!(mailmap.map("Ann <shared@example.com>"), "Ann Lee <ann@example.com>");
+        assert_eq!(mailmap.map("Bob <shared@example.com>"), "Bob <shared@example.com>");
+        assert_eq!(mailmap.map("Unknown"), "Unknown");
+    }
+}
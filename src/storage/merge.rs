@@ -0,0 +1,633 @@
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::Storable;
+use crate::storage::patch::is_binary;
+use crate::storage::utils::{PERM_EXEC, PERM_FILE};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// How the two sides of a conflict are written to the merged file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStyle {
+    /// `<<<<<<<`, `=======` and `>>>>>>>` around our and their version
+    #[default]
+    Merge,
+    /// Like `merge`, with the base version between `|||||||` and `=======`
+    Diff3,
+}
+
+/// Side that wins the conflicts, instead of writing conflict markers
+///
+/// Only conflicting regions are affected, changes made by one side are
+/// merged as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeFavor {
+    /// Keep our version
+    Ours,
+    /// Keep their version
+    Theirs,
+    /// Keep both versions, ours first
+    Union,
+}
+
+/// How `vox merge` combines a branch with ours
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MergeStrategy {
+    /// Three-way merge of the trees from their best common ancestor
+    #[default]
+    Resolve,
+    /// Keep our tree as it is, ignoring every change of theirs
+    Ours,
+}
+
+/// How conflicts are handled by a three-way merge
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    pub favor: Option<MergeFavor>,
+}
+
+/// Names written after the conflict markers
+#[derive(Debug, Clone, Copy)]
+pub struct MergeLabels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// Outcome of a three-way merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    /// The merged text, with conflict markers where the sides disagree
+    pub content: String,
+    /// Number of conflicting regions
+    pub conflicts: usize,
+}
+
+impl MergeResult {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+const MARKER_SIZE: usize = 7;
+
+/// A change one side made: `base` lines were replaced by `side` lines
+#[derive(Debug, Clone)]
+struct Hunk {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// Returns the changes from `base` to `side`, adjacent ones joined
+fn hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, base, side) {
+        let (tag, base_range, side_range) = op.as_tag_tuple();
+        if tag == DiffTag::Equal {
+            continue;
+        }
+        match hunks.last_mut() {
+            Some(last)
+                if last.base.end == base_range.start && last.side.end == side_range.start =>
+            {
+                last.base.end = base_range.end;
+                last.side.end = side_range.end;
+            }
+            _ => hunks.push(Hunk {
+                base: base_range,
+                side: side_range,
+            }),
+        }
+    }
+    hunks
+}
+
+/// Returns the lines of a side replacing `base[region]`, given the hunks of
+/// that side falling into the region, or `None` if the side left it alone
+fn side_range(hunks: &[Hunk], region: &Range<usize>) -> Option<Range<usize>> {
+    let (first, last) = (hunks.first()?, hunks.last()?);
+    let start = first.side.start - (first.base.start - region.start);
+    let end = last.side.end + (region.end - last.base.end);
+    Some(start..end)
+}
+
+/// Merges the changes `ours` and `theirs` made to `base`, line by line
+///
+/// Changes to different lines are combined. Where both sides changed the same
+/// or adjacent lines differently, both versions are kept between conflict
+/// markers laid out according to the conflict style, or resolved in favor of
+/// a side if the options name one.
+pub fn merge_text(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+) -> MergeResult {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let (our_hunks, their_hunks) = (hunks(&base, &ours), hunks(&base, &theirs));
+
+    let mut result = MergeResult {
+        content: String::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut j, mut done) = (0, 0, 0);
+    loop {
+        let start = match (our_hunks.get(i), their_hunks.get(j)) {
+            (Some(a), Some(b)) => a.base.start.min(b.base.start),
+            (Some(a), None) => a.base.start,
+            (None, Some(b)) => b.base.start,
+            (None, None) => break,
+        };
+
+        // Grow the region until no change of either side touches its end
+        let (first_ours, first_theirs) = (i, j);
+        let mut end = start;
+        loop {
+            let mut grown = false;
+            while let Some(hunk) = our_hunks.get(i).filter(|hunk| hunk.base.start <= end) {
+                end = end.max(hunk.base.end);
+                i += 1;
+                grown = true;
+            }
+            while let Some(hunk) = their_hunks.get(j).filter(|hunk| hunk.base.start <= end) {
+                end = end.max(hunk.base.end);
+                j += 1;
+                grown = true;
+            }
+            if !grown {
+                break;
+            }
+        }
+
+        push_lines(&mut result.content, &base[done..start]);
+        let region = start..end;
+        let our_side = side_range(&our_hunks[first_ours..i], &region);
+        let their_side = side_range(&their_hunks[first_theirs..j], &region);
+        match (our_side, their_side) {
+            (Some(side), None) => push_lines(&mut result.content, &ours[side]),
+            (None, Some(side)) => push_lines(&mut result.content, &theirs[side]),
+            (Some(a), Some(b)) if ours[a.clone()] == theirs[b.clone()] => {
+                push_lines(&mut result.content, &ours[a])
+            }
+            (Some(a), Some(b)) => match options.favor {
+                Some(MergeFavor::Ours) => push_lines(&mut result.content, &ours[a]),
+                Some(MergeFavor::Theirs) => push_lines(&mut result.content, &theirs[b]),
+                Some(MergeFavor::Union) => {
+                    push_lines(&mut result.content, &ours[a]);
+                    if !result.content.is_empty() && !result.content.ends_with('\n') {
+                        result.content.push('\n');
+                    }
+                    push_lines(&mut result.content, &theirs[b]);
+                }
+                None => {
+                    result.conflicts += 1;
+                    push_conflict(
+                        &mut result.content,
+                        [&ours[a], &base[region], &theirs[b]],
+                        labels,
+                        options.style,
+                    );
+                }
+            },
+            (None, None) => unreachable!("a region always holds a change"),
+        }
+        done = end;
+    }
+    push_lines(&mut result.content, &base[done..]);
+    result
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    lines.iter().for_each(|line| out.push_str(line));
+}
+
+/// Writes a conflicting region, our version, the base and their version
+fn push_conflict(
+    out: &mut String,
+    sides: [&[&str]; 3],
+    labels: &MergeLabels,
+    style: ConflictStyle,
+) {
+    let [mut ours, base, mut theirs] = sides;
+
+    // Lines both sides agree on don't need to be in the conflict, unless the
+    // base is shown, which they are a change from
+    if style == ConflictStyle::Merge {
+        let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+        push_lines(out, &ours[..prefix]);
+        (ours, theirs) = (&ours[prefix..], &theirs[prefix..]);
+        let suffix = (ours.iter().rev())
+            .zip(theirs.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix_lines = &ours[ours.len() - suffix..];
+        (ours, theirs) = (&ours[..ours.len() - suffix], &theirs[..theirs.len() - suffix]);
+        push_section(out, '<', labels.ours, ours);
+        push_section(out, '=', "", theirs);
+        push_marker(out, '>', labels.theirs);
+        push_lines(out, suffix_lines);
+        return;
+    }
+
+    push_section(out, '<', labels.ours, ours);
+    push_section(out, '|', labels.base, base);
+    push_section(out, '=', "", theirs);
+    push_marker(out, '>', labels.theirs);
+}
+
+/// Writes a marker followed by lines, ending the last one if it isn't
+fn push_section(out: &mut String, marker: char, label: &str, lines: &[&str]) {
+    push_marker(out, marker, label);
+    push_lines(out, lines);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn push_marker(out: &mut String, marker: char, label: &str) {
+    out.extend(std::iter::repeat_n(marker, MARKER_SIZE));
+    if !label.is_empty() {
+        out.push(' ');
+        out.push_str(label);
+    }
+    out.push('\n');
+}
+
+/// A file of a tree, by mode and blob hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedFile {
+    pub mode: String,
+    pub hash: String,
+}
+
+/// Why a file couldn't be merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed the same lines
+    Content,
+    /// One side changed the file, the other deleted it
+    ModifyDelete,
+    /// Both sides changed a binary file, a symlink or a submodule
+    Binary,
+}
+
+/// A file both sides changed in ways that couldn't be merged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub path: PathBuf,
+    pub kind: ConflictKind,
+    /// What is left in the work tree to resolve: the file with conflict
+    /// markers, the changed version of a deleted file, or our version
+    pub worktree: MergedFile,
+    /// The file in the base, our and their tree, `None` where it's missing
+    pub base: Option<MergedFile>,
+    pub ours: Option<MergedFile>,
+    pub theirs: Option<MergedFile>,
+}
+
+impl fmt::Display for FileConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match self.kind {
+            ConflictKind::Content => write!(f, "CONFLICT (content): Merge conflict in {}", path),
+            ConflictKind::ModifyDelete => write!(
+                f,
+                "CONFLICT (modify/delete): {} deleted on one side and modified on the other",
+                path
+            ),
+            ConflictKind::Binary => write!(f, "CONFLICT (binary): Merge conflict in {}", path),
+        }
+    }
+}
+
+/// Outcome of merging two trees
+#[derive(Debug, Default)]
+pub struct TreeMerge {
+    /// Files of the merged tree by path, conflicting ones at our version
+    pub files: BTreeMap<PathBuf, MergedFile>,
+    /// Files both sides changed whose contents were merged
+    pub merged: Vec<PathBuf>,
+    pub conflicts: Vec<FileConflict>,
+}
+
+impl TreeMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merges the changes `ours` and `theirs` made to `base`, file by file
+///
+/// Files changed on one side only are taken from that side, files changed on
+/// both are merged with [`merge_text`]. Merged contents, including those with
+/// conflict markers, are stored as blobs in `objects_dir`.
+///
+/// # Arguments
+/// - `base`, `ours`, `theirs`: tree hashes, `base` is `None` for histories
+///   starting from nothing, e.g. when picking a root commit
+/// - `labels`, `options`: how conflicts inside files are written
+pub fn merge_trees(
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+    objects_dir: &Path,
+) -> Result<TreeMerge> {
+    let base = tree_files(base, objects_dir)?;
+    let ours = tree_files(Some(ours), objects_dir)?;
+    let theirs = tree_files(Some(theirs), objects_dir)?;
+    let paths: BTreeSet<&PathBuf> =
+        base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+
+    let mut merge = TreeMerge::default();
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let file = if o == t || b == t {
+            o.cloned()
+        } else if b == o {
+            t.cloned()
+        } else {
+            match (o, t) {
+                (Some(o), Some(t)) => {
+                    let file = merge_files(b, o, t, labels, options, objects_dir)?;
+                    Some(merge.record(path, file, b, o, t))
+                }
+                (changed, deleted) => {
+                    let worktree = changed.or(deleted).cloned().expect("one side has the file");
+                    merge.conflicts.push(FileConflict {
+                        path: path.clone(),
+                        kind: ConflictKind::ModifyDelete,
+                        worktree,
+                        base: b.cloned(),
+                        ours: o.cloned(),
+                        theirs: t.cloned(),
+                    });
+                    o.cloned()
+                }
+            }
+        };
+        if let Some(file) = file {
+            merge.files.insert(path.clone(), file);
+        }
+    }
+    Ok(merge)
+}
+
+/// Result of merging a file changed on both sides
+enum FileMerge {
+    Clean(MergedFile),
+    Conflict(ConflictKind, MergedFile),
+}
+
+impl TreeMerge {
+    /// Records how a file changed on both sides was merged, returning what
+    /// goes into the merged tree
+    fn record(
+        &mut self,
+        path: &Path,
+        file: FileMerge,
+        base: Option<&MergedFile>,
+        ours: &MergedFile,
+        theirs: &MergedFile,
+    ) -> MergedFile {
+        match file {
+            FileMerge::Clean(file) => {
+                self.merged.push(path.to_path_buf());
+                file
+            }
+            FileMerge::Conflict(kind, worktree) => {
+                if kind == ConflictKind::Content {
+                    self.merged.push(path.to_path_buf());
+                }
+                self.conflicts.push(FileConflict {
+                    path: path.to_path_buf(),
+                    kind,
+                    worktree,
+                    base: base.cloned(),
+                    ours: Some(ours.clone()),
+                    theirs: Some(theirs.clone()),
+                });
+                ours.clone()
+            }
+        }
+    }
+}
+
+/// Merges a file both sides changed differently
+fn merge_files(
+    base: Option<&MergedFile>,
+    ours: &MergedFile,
+    theirs: &MergedFile,
+    labels: &MergeLabels,
+    options: &MergeOptions,
+    objects_dir: &Path,
+) -> Result<FileMerge> {
+    // The mode changed by one side wins
+    let mode = match base.is_some_and(|base| base.mode == ours.mode) {
+        true => theirs.mode.clone(),
+        false => ours.mode.clone(),
+    };
+    if ours.hash == theirs.hash {
+        return Ok(FileMerge::Clean(MergedFile {
+            mode,
+            hash: ours.hash.clone(),
+        }));
+    }
+
+    let is_file = |file: &MergedFile| file.mode == PERM_FILE || file.mode == PERM_EXEC;
+    let text = |file: &MergedFile| -> Result<Option<String>> {
+        let data = Blob::load(&file.hash, objects_dir)?.data;
+        Ok((!is_binary(&data)).then(|| String::from_utf8(data).ok()).flatten())
+    };
+    let texts = match (is_file(ours), is_file(theirs)) {
+        (true, true) => {
+            let base = match base.filter(|base| is_file(base)) {
+                Some(base) => text(base)?,
+                None => Some(String::new()),
+            };
+            (base, text(ours)?, text(theirs)?)
+        }
+        _ => (None, None, None),
+    };
+    let (Some(base), Some(our_text), Some(their_text)) = texts else {
+        return Ok(FileMerge::Conflict(ConflictKind::Binary, ours.clone()));
+    };
+
+    let merged = merge_text(&base, &our_text, &their_text, labels, options);
+    let file = MergedFile {
+        mode,
+        hash: Blob {
+            data: merged.content.into_bytes(),
+        }
+        .save(objects_dir)?,
+    };
+    match merged.conflicts {
+        0 => Ok(FileMerge::Clean(file)),
+        _ => Ok(FileMerge::Conflict(ConflictKind::Content, file)),
+    }
+}
+
+/// Returns the files of a tree by path, none for a missing tree
+fn tree_files(hash: Option<&str>, objects_dir: &Path) -> Result<BTreeMap<PathBuf, MergedFile>> {
+    let Some(hash) = hash else {
+        return Ok(BTreeMap::new());
+    };
+    let entries = read_tree(hash, objects_dir)?.flatten(objects_dir)?.entries;
+    Ok(entries
+        .into_iter()
+        .map(|entry| {
+            let file = MergedFile {
+                mode: entry.mode,
+                hash: entry.object_hash,
+            };
+            (PathBuf::from(entry.name), file)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::tree::{Tree, TreeEntry};
+    use crate::storage::utils::OBJ_TYPE_BLOB;
+    use tempfile::tempdir;
+
+    const LABELS: MergeLabels = MergeLabels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+
+    fn style(style: ConflictStyle) -> MergeOptions {
+        MergeOptions { style, favor: None }
+    }
+
+    #[test]
+    fn test_merge_combines_separate_changes() {
+        let base = "one\ntwo\nthree\nfour\nfive\n";
+        let ours = "ONE\ntwo\nthree\nfour\nfive\n";
+        let theirs = "one\ntwo\nthree\nfour\nFIVE\nsix\n";
+
+        let merged = merge_text(base, ours, theirs, &LABELS, &style(ConflictStyle::Merge));
+        assert!(merged.is_clean());
+        assert_eq!(merged.content, "ONE\ntwo\nthree\nfour\nFIVE\nsix\n");
+
+        // The same change on both sides isn't a conflict
+        let merged = merge_text(base, ours, ours, &LABELS, &style(ConflictStyle::Merge));
+        assert!(merged.is_clean());
+        assert_eq!(merged.content, ours);
+    }
+
+    #[test]
+    fn test_conflict_styles() {
+        let base = "one\ntwo\nthree\n";
+        let ours = "one\nmine\nshared\nthree\n";
+        let theirs = "one\nyours\nshared\nthree\n";
+
+        let merged = merge_text(base, ours, theirs, &LABELS, &style(ConflictStyle::Merge));
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.content,
+            "one\n<<<<<<< ours\nmine\n=======\nyours\n>>>>>>> theirs\nshared\nthree\n"
+        );
+
+        let merged = merge_text(base, ours, theirs, &LABELS, &style(ConflictStyle::Diff3));
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.content,
+            "one\n<<<<<<< ours\nmine\nshared\n||||||| base\ntwo\n=======\nyours\nshared\n\
+             >>>>>>> theirs\nthree\n"
+        );
+    }
+
+    #[test]
+    fn test_conflict_without_final_newline() {
+        let merged = merge_text("a", "b", "c", &LABELS, &style(ConflictStyle::Merge));
+        assert_eq!(merged.content, "<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\n");
+    }
+
+    #[test]
+    fn test_merge_favor() {
+        let base = "one\ntwo\nthree\nfour\n";
+        let ours = "ONE\ntwo\nmine\nfour\n";
+        let theirs = "one\ntwo\nyours\nfour\nfive\n";
+        let merge = |favor| {
+            let options = MergeOptions {
+                favor: Some(favor),
+                ..Default::default()
+            };
+            merge_text(base, ours, theirs, &LABELS, &options)
+        };
+
+        // Changes without conflict are merged from both sides either way
+        let merged = merge(MergeFavor::Ours);
+        assert!(merged.is_clean());
+        assert_eq!(merged.content, "ONE\ntwo\nmine\nfour\nfive\n");
+        assert_eq!(merge(MergeFavor::Theirs).content, "ONE\ntwo\nyours\nfour\nfive\n");
+        let union = "ONE\ntwo\nmine\nyours\nfour\nfive\n";
+        assert_eq!(merge(MergeFavor::Union).content, union);
+    }
+
+    #[test]
+    fn test_merge_trees() -> Result<()> {
+        let dir = tempdir()?;
+        let save = |data: &str| Blob { data: data.as_bytes().to_vec() }.save(dir.path());
+        let tree = |files: &[(&str, &str)]| -> Result<String> {
+            let mut entries = Vec::new();
+            for (name, content) in files {
+                entries.push(TreeEntry {
+                    mode: PERM_FILE.to_string(),
+                    object_type: OBJ_TYPE_BLOB.to_string(),
+                    object_hash: save(content)?,
+                    name: name.to_string(),
+                });
+            }
+            Tree { entries }.save(dir.path())
+        };
+
+        let base = tree(&[("kept", "a\nb\nc\n"), ("gone", "x\n"), ("edited", "x\n")])?;
+        let ours = tree(&[("kept", "A\nb\nc\n"), ("edited", "ours\n"), ("new", "n\n")])?;
+        let theirs = tree(&[("kept", "a\nb\nC\n"), ("gone", "x\n")])?;
+
+        let options = MergeOptions::default();
+        let merge = merge_trees(Some(&base), &ours, &theirs, &LABELS, &options, dir.path())?;
+        let paths: Vec<_> = merge.files.keys().map(|path| path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["edited", "kept", "new"]);
+        let kept = Blob::load(&merge.files[Path::new("kept")].hash, dir.path())?;
+        assert_eq!(kept.data, b"A\nb\nC\n");
+        assert_eq!(merge.merged, [PathBuf::from("kept")]);
+
+        // "edited" was changed by us and deleted by them
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].kind, ConflictKind::ModifyDelete);
+        assert_eq!(merge.conflicts[0].worktree, merge.files[Path::new("edited")]);
+        let side = |file: &Option<MergedFile>| file.as_ref().map(|file| file.hash.clone());
+        assert_eq!(side(&merge.conflicts[0].base), Some(save("x\n")?));
+        assert_eq!(side(&merge.conflicts[0].ours), Some(save("ours\n")?));
+        assert_eq!(side(&merge.conflicts[0].theirs), None);
+
+        // Changes to the same or adjacent lines conflict, the tree keeps ours
+        let theirs = tree(&[("kept", "a\nbee\nc\n"), ("edited", "theirs\n")])?;
+        let merge = merge_trees(Some(&base), &ours, &theirs, &LABELS, &options, dir.path())?;
+        assert_eq!(merge.conflicts.len(), 2);
+        assert!(merge.conflicts.iter().all(|c| c.kind == ConflictKind::Content));
+        assert_eq!(merge.files[Path::new("edited")].hash, save("ours\n")?);
+        let edited = merge.conflicts.iter().find(|c| c.path == Path::new("edited")).unwrap();
+        assert_eq!(edited.theirs.as_ref().unwrap().hash, save("theirs\n")?);
+        let marked = Blob::load(&merge.conflicts[0].worktree.hash, dir.path())?;
+        assert!(String::from_utf8(marked.data)?.contains("<<<<<<< ours\nours\n=======\n"));
+        Ok(())
+    }
+}
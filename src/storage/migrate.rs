@@ -0,0 +1,354 @@
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::tree::Tree;
+use crate::storage::objects::{read_object_header, read_raw_object, Storable};
+use crate::storage::utils::{
+    OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, OBJ_TYPE_TREE, PERM_GITLINK,
+};
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// File of the `.vox` directory mapping the old name of every migrated
+/// object to its new one, a `<old> <new>` line each
+pub const OBJECT_MAP_FILE: &str = "object-map";
+
+/// Copies objects into another objects directory, renaming them under its
+/// object format
+///
+/// Objects referencing others are rewritten to reference them by their new
+/// names, so everything an object references is migrated before it.
+/// Signatures cover the old names and are dropped.
+pub struct Migration {
+    source: PathBuf,
+    source_format: ObjectFormat,
+    target: PathBuf,
+    /// Old name -> new name of every object migrated so far
+    names: BTreeMap<String, String>,
+    /// Trees of notes commits, whose entries are named after objects
+    notes_trees: BTreeSet<String>,
+}
+
+impl Migration {
+    /// Prepares migrating the objects of `source` into `target`, whose
+    /// repository config sets the object format to migrate to
+    pub fn new(source: &Path, target: &Path) -> Result<Self> {
+        Ok(Self {
+            source: source.to_path_buf(),
+            source_format: ObjectFormat::for_objects_dir(source)?,
+            target: target.to_path_buf(),
+            names: BTreeMap::new(),
+            notes_trees: BTreeSet::new(),
+        })
+    }
+
+    /// Records that the commit `notes` and its ancestors hold notes, so the
+    /// entries of their trees are renamed along with the commits they annotate
+    ///
+    /// Must be called before anything reaching those commits is migrated.
+    pub fn add_notes(&mut self, notes: &str) -> Result<()> {
+        let mut pending = vec![notes.to_string()];
+        let mut seen = BTreeSet::new();
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash.clone()) || !self.has_object(&hash) {
+                continue;
+            }
+            let (object_type, data) = read_raw_object(&hash, &self.source)
+                .with_context(|| format!("Failed to read object {}", hash))?;
+            if object_type != OBJ_TYPE_COMMIT {
+                bail!("Notes ref points to {} {}, not a commit", object_type, hash);
+            }
+            let commit = Commit::parse(std::str::from_utf8(&data)?)?;
+            pending.extend(commit.parents());
+            self.notes_trees.insert(commit.tree);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the object `hash` can be migrated, false if it's gone
+    /// (e.g. a pruned commit still mentioned by a reflog)
+    pub fn has_object(&self, hash: &str) -> bool {
+        self.names.contains_key(hash) || read_object_header(hash, &self.source).is_ok()
+    }
+
+    /// Migrates the object `hash` and everything it references
+    ///
+    /// # Returns
+    ///
+    /// The new name of the object
+    pub fn migrate(&mut self, hash: &str) -> Result<String> {
+        // Long histories would overflow the stack when walked recursively
+        let mut pending = vec![hash.to_string()];
+        while let Some(next) = pending.last().cloned() {
+            if self.names.contains_key(&next) {
+                pending.pop();
+                continue;
+            }
+
+            let (object_type, data) = read_raw_object(&next, &self.source)
+                .with_context(|| format!("Failed to read object {}", next))?;
+            let missing: Vec<String> = self
+                .references(&next, &object_type, &data)?
+                .into_iter()
+                .filter(|reference| !self.names.contains_key(reference))
+                .collect();
+            if !missing.is_empty() {
+                pending.extend(missing);
+                continue;
+            }
+
+            let name = self.rewrite(&next, &object_type, &data)?;
+            self.names.insert(next, name);
+            pending.pop();
+        }
+        Ok(self.names[hash].clone())
+    }
+
+    /// Returns the new name of the migrated object `hash`
+    pub fn name(&self, hash: &str) -> Option<&str> {
+        self.names.get(hash).map(String::as_str)
+    }
+
+    /// Returns the old name -> new name of every migrated object
+    pub fn names(&self) -> &BTreeMap<String, String> {
+        &self.names
+    }
+
+    /// Hashes of the objects the object `hash` of type `object_type` holding
+    /// `data` references
+    fn references(&self, hash: &str, object_type: &str, data: &[u8]) -> Result<Vec<String>> {
+        Ok(match object_type {
+            OBJ_TYPE_TREE => {
+                let mut references = Vec::new();
+                for entry in Tree::parse(data, self.source_format)?.entries {
+                    if entry.mode == PERM_GITLINK {
+                        bail!(
+                            "'{}' is a submodule, submodule commits can't be migrated",
+                            entry.name
+                        );
+                    }
+                    if self.is_note_name(hash, &entry.name) {
+                        references.push(entry.name);
+                    }
+                    references.push(entry.object_hash);
+                }
+                references
+            }
+            OBJ_TYPE_COMMIT => {
+                let commit = Commit::parse(std::str::from_utf8(data)?)?;
+                let mut references = commit.parents();
+                references.push(commit.tree);
+                references
+            }
+            OBJ_TYPE_TAG => vec![Tag::parse(std::str::from_utf8(data)?)?.object],
+            _ => Vec::new(),
+        })
+    }
+
+    /// Stores the object `hash` of type `object_type` holding `data` in the
+    /// target, once everything it references is migrated
+    fn rewrite(&self, hash: &str, object_type: &str, data: &[u8]) -> Result<String> {
+        match object_type {
+            OBJ_TYPE_BLOB => Blob {
+                data: data.to_vec(),
+            }
+            .save(&self.target),
+            OBJ_TYPE_TREE => {
+                let mut tree = Tree::parse(data, self.source_format)?;
+                let mut renamed = false;
+                for entry in &mut tree.entries {
+                    entry.object_hash = self.names[&entry.object_hash].clone();
+                    // Notes name their blobs after the annotated commit
+                    if self.is_note_name(hash, &entry.name) {
+                        entry.name = self.names[&entry.name].clone();
+                        renamed = true;
+                    }
+                }
+                if renamed {
+                    tree.entries.sort_by(|a, b| a.name.cmp(&b.name));
+                }
+                tree.save(&self.target)
+            }
+            OBJ_TYPE_COMMIT => {
+                let mut commit = Commit::parse(std::str::from_utf8(data)?)?;
+                commit.tree = self.names[&commit.tree].clone();
+                commit.parent = commit.parent.map(|parent| self.names[&parent].clone());
+                if commit.signature.take().is_some() {
+                    eprintln!("warning: dropping the signature of commit {}", hash);
+                }
+                commit.save(&self.target)
+            }
+            OBJ_TYPE_TAG => {
+                let mut tag = Tag::parse(std::str::from_utf8(data)?)?;
+                tag.object = self.names[&tag.object].clone();
+                if tag.signature.take().is_some() {
+                    eprintln!("warning: dropping the signature of tag {} ({})", tag.tag, hash);
+                }
+                tag.save(&self.target)
+            }
+            _ => bail!("Unsupported object type: {}", object_type),
+        }
+    }
+
+    /// Returns true if `name` is an entry of the notes tree `tree` named after
+    /// an annotated object
+    fn is_note_name(&self, tree: &str, name: &str) -> bool {
+        self.notes_trees.contains(tree)
+            && name.len() == self.source_format.hex_len()
+            && name.chars().all(|c| c.is_ascii_hexdigit())
+            && self.has_object(name)
+    }
+}
+
+/// Renames the hashes of every log under `<vox_dir>/logs` with `migration`
+///
+/// Entries mentioning objects that weren't migrated are dropped, null hashes
+/// are written as those of `format`.
+pub fn migrate_reflogs(vox_dir: &Path, migration: &Migration, format: ObjectFormat) -> Result<()> {
+    let logs_dir = vox_dir.join("logs");
+    if !logs_dir.exists() {
+        return Ok(());
+    }
+
+    let null = format.null_id().to_hex();
+    let rename = |hash: &str| match hash.chars().all(|c| c == '0') {
+        true => Some(null.as_str()),
+        false => migration.name(hash),
+    };
+    for file in WalkDir::new(&logs_dir).min_depth(1) {
+        let file = file.context("Failed to read logs directory")?;
+        if !file.file_type().is_file() {
+            continue;
+        }
+
+        let content = fs::read_to_string(file.path())
+            .with_context(|| format!("Failed to read {}", file.path().display()))?;
+        let mut migrated = String::new();
+        for line in content.lines() {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(old), Some(new), Some(rest)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if let (Some(old), Some(new)) = (rename(old), rename(new)) {
+                migrated.push_str(&format!("{} {} {}\n", old, new, rest));
+            }
+        }
+        fs::write(file.path(), migrated)
+            .with_context(|| format!("Failed to write {}", file.path().display()))?;
+    }
+    Ok(())
+}
+
+/// Writes the old name -> new name of every migrated object to
+/// `<vox_dir>/object-map`
+pub fn write_object_map(vox_dir: &Path, migration: &Migration) -> Result<()> {
+    let content: String = migration
+        .names()
+        .iter()
+        .map(|(old, new)| format!("{} {}\n", old, new))
+        .collect();
+    let path = vox_dir.join(OBJECT_MAP_FILE);
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::tree::TreeEntry;
+    use crate::storage::objects::Loadable;
+    use crate::storage::repo_config::RepoConfig;
+    use crate::storage::utils::PERM_FILE;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_migrate_objects() -> Result<()> {
+        let source = tempdir()?;
+        let target = tempdir()?;
+        RepoConfig::new(ObjectFormat::Sha1).save(source.path())?;
+        RepoConfig::new(ObjectFormat::Sha256).save(target.path())?;
+        let source_objects = source.path().join("objects");
+        let target_objects = target.path().join("objects");
+
+        let blob = Blob {
+            data: b"content".to_vec(),
+        }
+        .save(&source_objects)?;
+        let tree = Tree {
+            entries: vec![TreeEntry {
+                mode: PERM_FILE.to_string(),
+                object_type: OBJ_TYPE_BLOB.to_string(),
+                object_hash: blob.clone(),
+                name: "file.txt".to_string(),
+            }],
+        }
+        .save(&source_objects)?;
+        let first = Commit::new(tree.clone(), None, "A <a@x>".into(), "first".into())
+            .save(&source_objects)?;
+        let second = Commit::new(tree, Some(first.clone()), "A <a@x>".into(), "second".into())
+            .save(&source_objects)?;
+
+        let mut migration = Migration::new(&source_objects, &target_objects)?;
+        let migrated = migration.migrate(&second)?;
+        assert_eq!(migrated.len(), 64);
+        assert_eq!(migration.names().len(), 4);
+
+        let commit = Commit::load(&migrated, &target_objects)?;
+        assert_eq!(commit.parent.as_deref(), migration.name(&first));
+        let entries = Tree::load(&commit.tree, &target_objects)?.entries;
+        assert_eq!(entries[0].object_hash, migration.name(&blob).unwrap());
+        assert_eq!(Blob::load(&entries[0].object_hash, &target_objects)?.data, b"content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_notes_entries_renamed() -> Result<()> {
+        let source = tempdir()?;
+        let target = tempdir()?;
+        RepoConfig::new(ObjectFormat::Sha1).save(source.path())?;
+        RepoConfig::new(ObjectFormat::Sha256).save(target.path())?;
+        let source_objects = source.path().join("objects");
+        let target_objects = target.path().join("objects");
+
+        let blob = Blob {
+            data: b"content".to_vec(),
+        }
+        .save(&source_objects)?;
+        let entry = |name: &str| TreeEntry {
+            mode: PERM_FILE.to_string(),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: blob.clone(),
+            name: name.to_string(),
+        };
+        // A tracked file that happens to be named like an object
+        let files = Tree {
+            entries: vec![entry(&blob), entry("readme")],
+        }
+        .save(&source_objects)?;
+        let commit =
+            Commit::new(files, None, "A <a@x>".into(), "files".into()).save(&source_objects)?;
+        let notes_tree = Tree {
+            entries: vec![entry(&blob)],
+        }
+        .save(&source_objects)?;
+        let notes = Commit::new(notes_tree, None, "A <a@x>".into(), "notes".into())
+            .save(&source_objects)?;
+
+        let mut migration = Migration::new(&source_objects, &target_objects)?;
+        migration.add_notes(&notes)?;
+        let names = |commit: &str| -> Result<Vec<String>> {
+            let commit = Commit::load(commit, &target_objects)?;
+            let entries = Tree::load(&commit.tree, &target_objects)?.entries;
+            Ok(entries.into_iter().map(|entry| entry.name).collect())
+        };
+        let migrated = migration.migrate(&commit)?;
+        assert_eq!(names(&migrated)?, vec![blob.clone(), "readme".to_string()]);
+        let migrated = migration.migrate(&notes)?;
+        assert_eq!(names(&migrated)?, vec![migration.name(&blob).unwrap().to_string()]);
+        Ok(())
+    }
+}
@@ -1,4 +1,31 @@
+pub mod archive;
+pub mod attributes;
+pub mod bundle;
+pub mod convert;
+pub mod editor;
+pub mod encryption;
+pub mod hash;
+pub mod hooks;
+pub mod fsmonitor;
+pub mod ignore;
+pub mod lfs;
+pub mod mailmap;
+pub mod merge;
+pub mod migrate;
+pub mod notes;
+pub mod patch;
+pub mod pathspec;
 pub mod objects;
+pub mod platform;
+pub mod quarantine;
+pub mod ref_transaction;
+pub mod reflog;
 pub mod refs;
+pub mod replace;
 pub mod repo;
+pub mod repo_config;
+pub mod revision;
+pub mod shallow;
+pub mod signing;
+pub mod submodules;
 pub mod utils;
@@ -0,0 +1,157 @@
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::tree::{read_tree, Tree, TreeEntry};
+use crate::storage::objects::{Loadable, Storable};
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::ref_path;
+use crate::storage::utils::{OBJ_TYPE_BLOB, PERM_FILE};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Ref holding the notes attached to commits
+pub const NOTES_REF: &str = "refs/notes/commits";
+
+/// Annotations attached to commits without changing them
+///
+/// The notes ref points to a commit whose tree has one blob per annotated
+/// commit, named by the commit's hash. Every change to the notes is a new
+/// commit on the ref, so their history is kept.
+#[derive(Debug, Default)]
+pub struct Notes {
+    /// Annotated commit -> blob holding its note
+    entries: BTreeMap<String, String>,
+    /// The commit the notes ref pointed to when loaded
+    head: Option<String>,
+}
+
+impl Notes {
+    /// Reads the notes of the repository at `vox_dir`, empty if there are none yet
+    pub fn load(vox_dir: &Path, objects_dir: &Path) -> Result<Self> {
+        let Ok(head) = fs::read_to_string(ref_path(vox_dir, NOTES_REF)) else {
+            return Ok(Self::default());
+        };
+        let head = head.trim().to_string();
+
+        let commit = Commit::load(&head, objects_dir)
+            .with_context(|| format!("Failed to load notes commit {}", head))?;
+        let entries = read_tree(&commit.tree, objects_dir)?
+            .entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.object_hash))
+            .collect();
+
+        Ok(Self {
+            entries,
+            head: Some(head),
+        })
+    }
+
+    /// Returns the note attached to `commit`, if any
+    pub fn get(&self, commit: &str, objects_dir: &Path) -> Result<Option<String>> {
+        match self.entries.get(commit) {
+            Some(blob) => {
+                let blob = Blob::load(blob, objects_dir)?;
+                Ok(Some(String::from_utf8_lossy(&blob.data).into_owned()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Attaches `note` to `commit`, replacing any previous note
+    pub fn set(&mut self, commit: &str, note: &str, objects_dir: &Path) -> Result<()> {
+        let blob = Blob {
+            data: note.as_bytes().to_vec(),
+        }
+        .save(objects_dir)?;
+        self.entries.insert(commit.to_string(), blob);
+        Ok(())
+    }
+
+    /// Removes the note of `commit`, returning false if it had none
+    pub fn remove(&mut self, commit: &str) -> bool {
+        self.entries.remove(commit).is_some()
+    }
+
+    /// Records the notes as a new commit on the notes ref
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notes ref was changed since the notes were loaded
+    pub fn save(
+        &mut self,
+        vox_dir: &Path,
+        objects_dir: &Path,
+        author: &str,
+        message: &str,
+    ) -> Result<String> {
+        let tree = Tree {
+            entries: self
+                .entries
+                .iter()
+                .map(|(commit, blob)| TreeEntry {
+                    mode: PERM_FILE.to_string(),
+                    object_type: OBJ_TYPE_BLOB.to_string(),
+                    object_hash: blob.clone(),
+                    name: commit.clone(),
+                })
+                .collect(),
+        };
+        let tree_hash = tree.save(objects_dir)?;
+
+        let commit = Commit::new(
+            tree_hash,
+            self.head.clone(),
+            author.to_string(),
+            message.to_string(),
+        );
+        let hash = commit.save(objects_dir)?;
+
+        let expected = match &self.head {
+            Some(head) => ExpectedValue::Hash(head.clone()),
+            None => ExpectedValue::Missing,
+        };
+        let mut transaction = RefTransaction::new(vox_dir);
+        transaction.update(NOTES_REF, &hash, expected, &format!("notes: {}", message));
+        transaction.commit().context("Failed to update the notes ref")?;
+
+        self.head = Some(hash.clone());
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_notes_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        let objects_dir = vox_dir.join("objects");
+        fs::create_dir_all(&objects_dir)?;
+        let commit = "ab".repeat(20);
+        let author = "Jane Doe <jane@example.com>";
+
+        let mut notes = Notes::load(vox_dir, &objects_dir)?;
+        assert_eq!(notes.get(&commit, &objects_dir)?, None);
+
+        notes.set(&commit, "Reviewed-by: Jane", &objects_dir)?;
+        let first = notes.save(vox_dir, &objects_dir, author, "Notes added")?;
+
+        let mut notes = Notes::load(vox_dir, &objects_dir)?;
+        let note = notes.get(&commit, &objects_dir)?;
+        assert_eq!(note.as_deref(), Some("Reviewed-by: Jane"));
+
+        assert!(notes.remove(&commit));
+        assert!(!notes.remove(&commit));
+        let second = notes.save(vox_dir, &objects_dir, author, "Notes removed")?;
+
+        // The removal is recorded on top of the previous notes
+        assert_eq!(Commit::load(&second, &objects_dir)?.parent, Some(first));
+        assert_eq!(Notes::load(vox_dir, &objects_dir)?.get(&commit, &objects_dir)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::graph::walk_reachable;
+use crate::storage::objects::pack_compression;
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+/// Location of the bitmaps, relative to the objects directory
+pub const BITMAP_FILE: &str = "info/bitmaps";
+
+const BITMAP_MAGIC: &[u8; 7] = b"VOXBMAP";
+const BITMAP_VERSION: u8 = 1;
+
+/// Set of objects, one bit per position in [`ReachabilityBitmaps::objects`]
+type Bitmap = Vec<u64>;
+
+/// Sets of the objects reachable from some commits, so that a walk reaching
+/// one of them takes its whole history at once instead of reading every
+/// commit, tree and blob behind it
+///
+/// Objects never change, so neither does what a commit reaches: bitmaps
+/// never go stale, they only miss the commits made since they were written.
+///
+/// They're written to `objects/info/bitmaps`:
+///
+/// ```text
+/// "VOXBMAP" version:u8 object_count:u32 (raw id)*
+/// bitmap_count:u32 (raw commit id, len:u32, zlib compressed bitmap)* checksum
+/// ```
+///
+/// Objects are sorted by id, bitmaps are big-endian 64-bit words where bit
+/// `n` of word `w` stands for object `64 * w + n`, and the checksum is the
+/// digest of everything before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReachabilityBitmaps {
+    pub format: ObjectFormat,
+    /// Every object of a bitmap, sorted
+    pub objects: Vec<ObjectId>,
+    /// Bitmaps of the commits
+    pub bitmaps: BTreeMap<ObjectId, Bitmap>,
+}
+
+impl ReachabilityBitmaps {
+    /// Builds the bitmaps of `commits`, reusing those of `previous`
+    pub fn build(
+        commits: &[String],
+        objects_dir: &Path,
+        format: ObjectFormat,
+        previous: Option<&ReachabilityBitmaps>,
+    ) -> Result<Self> {
+        let mut sets = BTreeMap::new();
+        for hash in commits {
+            let id = ObjectId::from_hex(hash)?;
+            if sets.contains_key(&id) {
+                continue;
+            }
+            let reachable = match previous.and_then(|bitmaps| bitmaps.reachable_from(hash)) {
+                Some(reachable) => reachable,
+                None => walk_reachable(vec![hash.clone()], objects_dir, previous)?
+                    .into_iter()
+                    .collect(),
+            };
+            // Missing objects may be named by anything, only object ids count
+            let ids: BTreeSet<ObjectId> = reachable
+                .iter()
+                .filter_map(|hash| ObjectId::from_hex(hash).ok())
+                .collect();
+            sets.insert(id, ids);
+        }
+
+        let objects: Vec<ObjectId> = sets
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let bitmaps = sets
+            .into_iter()
+            .map(|(id, set)| {
+                let mut bitmap = vec![0u64; objects.len().div_ceil(64)];
+                for object in set {
+                    if let Ok(position) = objects.binary_search(&object) {
+                        bitmap[position / 64] |= 1 << (position % 64);
+                    }
+                }
+                (id, bitmap)
+            })
+            .collect();
+        Ok(Self {
+            format,
+            objects,
+            bitmaps,
+        })
+    }
+
+    /// Returns the hashes of every object reachable from the commit `hash`,
+    /// if it has a bitmap
+    pub fn reachable_from(&self, hash: &str) -> Option<HashSet<String>> {
+        let bitmap = self.bitmaps.get(&ObjectId::from_hex(hash).ok()?)?;
+        let mut reachable = HashSet::new();
+        for (word_index, &word) in bitmap.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                let bit = word.trailing_zeros() as usize;
+                reachable.insert(self.objects[word_index * 64 + bit].to_hex());
+                word &= word - 1;
+            }
+        }
+        Some(reachable)
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = BITMAP_MAGIC.to_vec();
+        data.write_u8(BITMAP_VERSION)?;
+        data.write_u32::<BigEndian>(self.objects.len() as u32)?;
+        for id in &self.objects {
+            data.extend_from_slice(id.as_bytes());
+        }
+
+        data.write_u32::<BigEndian>(self.bitmaps.len() as u32)?;
+        for (id, bitmap) in &self.bitmaps {
+            let mut encoder = ZlibEncoder::new(Vec::new(), pack_compression());
+            for word in bitmap {
+                encoder.write_u64::<BigEndian>(*word)?;
+            }
+            let compressed = encoder.finish()?;
+            data.extend_from_slice(id.as_bytes());
+            data.write_u32::<BigEndian>(compressed.len() as u32)?;
+            data.extend_from_slice(&compressed);
+        }
+
+        let checksum = self.format.digest(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        Ok(data)
+    }
+
+    pub fn parse(data: &[u8], format: ObjectFormat) -> Result<Self> {
+        let body_len = data
+            .len()
+            .checked_sub(format.raw_len())
+            .context("Bitmap file too short")?;
+        let (body, checksum) = data.split_at(body_len);
+        if format.digest(body).as_bytes() != checksum {
+            bail!("Bitmap file checksum mismatch");
+        }
+
+        let mut reader = Cursor::new(body);
+        let mut magic = [0u8; 7];
+        reader.read_exact(&mut magic)?;
+        if &magic != BITMAP_MAGIC {
+            bail!("Invalid bitmap file");
+        }
+        let version = reader.read_u8()?;
+        if version != BITMAP_VERSION {
+            bail!("Unsupported bitmap file version {}", version);
+        }
+
+        let mut raw = vec![0u8; format.raw_len()];
+        let object_count = reader.read_u32::<BigEndian>()? as usize;
+        let mut objects = Vec::new();
+        for _ in 0..object_count {
+            reader.read_exact(&mut raw)?;
+            objects.push(ObjectId::from_bytes(format, &raw)?);
+        }
+
+        let word_count = object_count.div_ceil(64);
+        let bitmap_count = reader.read_u32::<BigEndian>()?;
+        let mut bitmaps = BTreeMap::new();
+        for _ in 0..bitmap_count {
+            reader.read_exact(&mut raw)?;
+            let id = ObjectId::from_bytes(format, &raw)?;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            if len > body.len() {
+                bail!("Bitmap runs past the end of the file");
+            }
+            let mut compressed = vec![0u8; len];
+            reader.read_exact(&mut compressed)?;
+
+            let mut decoder = ZlibDecoder::new(&compressed[..]);
+            let bitmap = (0..word_count)
+                .map(|_| decoder.read_u64::<BigEndian>())
+                .collect::<std::io::Result<Bitmap>>()
+                .context("Invalid bitmap")?;
+            bitmaps.insert(id, bitmap);
+        }
+
+        Ok(Self {
+            format,
+            objects,
+            bitmaps,
+        })
+    }
+
+    /// Writes the bitmaps to `objects_dir/info`
+    pub fn write(&self, objects_dir: &Path) -> Result<()> {
+        let path = objects_dir.join(BITMAP_FILE);
+        let parent = path.parent().context("Invalid bitmap path")?;
+        fs::create_dir_all(parent)?;
+        let temp_path = path.with_extension("lock");
+        fs::write(&temp_path, self.serialize()?)?;
+        fs::rename(&temp_path, &path).context("Failed to write the bitmaps")
+    }
+
+    /// Reads the bitmaps of `objects_dir`, if there are some
+    pub fn read(objects_dir: &Path, format: ObjectFormat) -> Result<Option<Self>> {
+        let path = objects_dir.join(BITMAP_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).context("Failed to read the bitmaps")?;
+        Ok(Some(Self::parse(&data, format)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::commit::Commit;
+    use crate::storage::objects::graph::collect_reachable;
+    use crate::storage::objects::tree::{Tree, TreeEntry};
+    use crate::storage::objects::Storable;
+    use crate::storage::utils::OBJ_TYPE_BLOB;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_bitmaps() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        let mut commits = Vec::new();
+        for content in ["one", "two"] {
+            let blob = Blob {
+                data: content.as_bytes().to_vec(),
+            }
+            .save(&objects_dir)?;
+            let tree = Tree {
+                entries: vec![TreeEntry {
+                    mode: "100644".to_string(),
+                    object_type: OBJ_TYPE_BLOB.to_string(),
+                    object_hash: blob,
+                    name: "file".to_string(),
+                }],
+            }
+            .save(&objects_dir)?;
+            let commit = Commit::new(
+                tree,
+                commits.last().cloned(),
+                "Author <author@example.com>".to_string(),
+                content.to_string(),
+            );
+            commits.push(commit.save(&objects_dir)?);
+        }
+
+        let bitmaps = ReachabilityBitmaps::build(&commits, &objects_dir, ObjectFormat::Sha1, None)?;
+        assert_eq!(bitmaps.objects.len(), 6);
+        assert_eq!(bitmaps.reachable_from(&commits[0]).map(|set| set.len()), Some(3));
+        assert!(bitmaps.reachable_from(&ObjectFormat::Sha1.digest(b"x").to_hex()).is_none());
+
+        bitmaps.write(&objects_dir)?;
+        let read = ReachabilityBitmaps::read(&objects_dir, ObjectFormat::Sha1)?.unwrap();
+        assert_eq!(read, bitmaps);
+        assert_eq!(
+            collect_reachable(vec![commits[1].clone()], &objects_dir)?,
+            walk_reachable(vec![commits[1].clone()], &objects_dir, None)?
+        );
+        Ok(())
+    }
+}
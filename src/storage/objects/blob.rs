@@ -1,14 +1,24 @@
-use crate::storage::objects::{Storable, VoxObject};
+use crate::error::VoxError;
+use crate::storage::objects::compression::{compress, ObjectDecoder, ObjectEncoder};
+use crate::storage::objects::{loose_object_path, ObjectStorage, Storable, VoxObject};
 use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_BLOB};
-use anyhow::{Context, Result};
-use flate2::bufread::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use sha1::{Digest, Sha1};
+use crate::storage::convert::Converter;
+use crate::storage::encryption::{is_encrypted, open_object_file, write_object_file};
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::platform;
+use crate::storage::replace::replaced;
+use anyhow::{bail, Context, Result};
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// Size of the chunks files are streamed in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Blob headers are `blob <size>`, anything longer is corrupt
+const MAX_HEADER_SIZE: usize = 32;
 
 /// Represents the blob (binary large object)
 /// Blobs store raw file data
@@ -32,7 +42,7 @@ impl Blob {
         let header = format!("{} {}\0", blob.object_type(), blob.serialize()?.len());
 
         // Compress header + content
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut encoder = ObjectEncoder::new(Vec::new(), &OBJ_DIR)?;
         encoder
             .write_all(header.as_bytes())
             .context("Failed to write header to encoder")?;
@@ -47,10 +57,7 @@ impl Blob {
         std::fs::create_dir_all(format!("{}/{}", OBJ_DIR.display(), &object_hash[0..2]))
             .context("Failed to create object directory")?;
 
-        let mut object_file = File::create(&object_path).context("Failed to create object file")?;
-        object_file
-            .write_all(&compressed_data)
-            .context("Failed to write compressed data to file")?;
+        write_object_file(&object_hash, Path::new(&object_path), &compressed_data)?;
 
         Ok(object_hash)
     }
@@ -66,35 +73,251 @@ impl Blob {
         Ok(Blob { data: content })
     }
 
-    pub fn get_content(&self) -> &Vec<u8> {
-        &self.data
+    /// Creates a Blob for a working tree entry: the content of a file, or
+    /// the target of a symlink
+    pub fn from_worktree(path: &Path) -> Result<Self> {
+        if platform::is_symlink(path) {
+            return Ok(Blob {
+                data: platform::read_link_target(path)?.into_bytes(),
+            });
+        }
+
+        let data = fs::read(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Blob { data })
     }
 
-    /// Returns a reference to the blob's raw data
-    pub fn load(hash: &str, obj_dir: &Path) -> Result<Self> {
-        let object_path = obj_dir.join(&hash[0..2]).join(&hash[2..]);
-        let compressed = std::fs::read(object_path)?;
+    /// Creates the Blob to store for a working tree entry, the content of a
+    /// file going through `converter`, see [`Blob::from_worktree`]
+    ///
+    /// # Arguments
+    ///
+    /// * `full_path` - Where the entry is
+    /// * `path` - Path of the entry relative to the repository root, which
+    ///   attributes are looked up for
+    /// * `converter` - Converter of the work tree the entry is in
+    pub fn from_worktree_converted(
+        full_path: &Path,
+        path: &Path,
+        converter: &mut Converter,
+    ) -> Result<Self> {
+        let blob = Self::from_worktree(full_path)?;
+        if platform::is_symlink(full_path) {
+            return Ok(blob);
+        }
+        Ok(Blob {
+            data: converter.to_repository(path, blob.data)?,
+        })
+    }
 
-        // decompress the Zlib data
-        let mut decoder = ZlibDecoder::new(&compressed[..]);
-        let mut data = Vec::new();
-        decoder.read_to_end(&mut data)?;
+    /// Hashes a working tree entry as [`Blob::from_worktree_converted`]
+    /// would, reading files stored as they are a chunk at a time
+    pub fn hash_worktree(
+        full_path: &Path,
+        path: &Path,
+        converter: &mut Converter,
+        format: ObjectFormat,
+    ) -> Result<ObjectId> {
+        if let Some(size) = regular_file_size(full_path)
+            && converter.stores_as_is(path, size)
+        {
+            let mut hasher = format.hasher();
+            stream_file(full_path, size, |chunk| {
+                hasher.update(chunk);
+                Ok(())
+            })?;
+            return Ok(hasher.finish());
+        }
+        let blob = Self::from_worktree_converted(full_path, path, converter)?;
+        Ok(format.digest(&blob.data))
+    }
+
+    /// Stores a working tree entry as [`Blob::from_worktree_converted`]
+    /// would, streaming files stored as they are, see [`Blob::save_file`]
+    ///
+    /// # Returns
+    ///
+    /// The hash of the blob
+    pub fn save_worktree(
+        full_path: &Path,
+        path: &Path,
+        converter: &mut Converter,
+        objects_dir: &Path,
+    ) -> Result<String> {
+        if let Some(size) = regular_file_size(full_path)
+            && converter.stores_as_is(path, size)
+        {
+            return Self::save_file(full_path, objects_dir);
+        }
+        Self::from_worktree_converted(full_path, path, converter)?.save(objects_dir)
+    }
+
+    /// Stores the content of the file at `file_path` as a blob, hashing and
+    /// compressing it a chunk at a time so it never has to fit in memory
+    ///
+    /// The object is written to a temporary file first and moved into place
+    /// once its hash is known. In encrypted repositories the compressed object
+    /// is kept in memory instead, so no clear copy of it touches the disk.
+    pub fn save_file(file_path: &Path, objects_dir: &Path) -> Result<String> {
+        let size = fs::metadata(file_path)
+            .with_context(|| format!("Failed to read {}", file_path.display()))?
+            .len();
+        let mut hasher = ObjectFormat::for_objects_dir(objects_dir)?.hasher();
 
-        // Find the null byte separator between header and content
-        let null_position = data
-            .iter()
-            .position(|&b| b == 0)
-            .context("Invalid blob format: no null byte found")?;
+        // The object can only be sealed whole once its hash is known
+        if is_encrypted(objects_dir)? {
+            let mut encoder = ObjectEncoder::new(Vec::new(), objects_dir)?;
+            encoder.write_all(format!("blob {}\0", size).as_bytes())?;
+            stream_file(file_path, size, |chunk| {
+                hasher.update(chunk);
+                encoder.write_all(chunk)?;
+                Ok(())
+            })?;
+            let compressed = encoder.finish()?;
 
-        // extract jyst the content (after the null byte)
-        let content = &data[null_position + 1..];
+            let hash = hasher.finish().to_hex();
+            if loose_object_path(&hash, objects_dir)?.is_none() {
+                let dir_path = objects_dir.join(&hash[..2]);
+                fs::create_dir_all(&dir_path)?;
+                write_object_file(&hash, &dir_path.join(&hash[2..]), &compressed)?;
+            }
+            return Ok(hash);
+        }
 
+        fs::create_dir_all(objects_dir)?;
+        let temp_file = NamedTempFile::new_in(objects_dir)
+            .context("Failed to create a temporary object file")?;
+        let mut encoder = ObjectEncoder::new(BufWriter::new(temp_file), objects_dir)?;
+        encoder.write_all(format!("blob {}\0", size).as_bytes())?;
+        stream_file(file_path, size, |chunk| {
+            hasher.update(chunk);
+            encoder.write_all(chunk)?;
+            Ok(())
+        })?;
+        let temp_file = encoder
+            .finish()?
+            .into_inner()
+            .map_err(|err| err.into_error())
+            .context("Failed to write the object")?;
+
+        let hash = hasher.finish().to_hex();
+        if loose_object_path(&hash, objects_dir)?.is_some() {
+            return Ok(hash);
+        }
+        let dir_path = objects_dir.join(&hash[..2]);
+        fs::create_dir_all(&dir_path)?;
+        temp_file
+            .persist(dir_path.join(&hash[2..]))
+            .context("Failed to move the object into place")?;
+        Ok(hash)
+    }
+
+    /// Opens the blob `hash` to read its content as it's decompressed
+    ///
+    /// # Returns
+    ///
+    /// The size of the content, from the header, and its reader
+    pub fn reader(hash: &str, obj_dir: &Path) -> Result<(u64, impl Read + use<>)> {
+        // Sealed objects authenticate the hash they're stored under
+        let stored = replaced(hash, obj_dir)?;
+        let object_path = loose_object_path(&stored, obj_dir)?
+            .ok_or_else(|| VoxError::ObjectNotFound(hash.to_string()))?;
+        let file = open_object_file(&stored, &object_path)?;
+        let mut decoder = ObjectDecoder::buffered(file)?;
+
+        let mut header = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            decoder.read_exact(&mut byte).context("Invalid blob format")?;
+            if byte[0] == 0 {
+                break;
+            }
+            if header.len() == MAX_HEADER_SIZE {
+                bail!("Invalid blob format: header too long in {}", hash);
+            }
+            header.push(byte[0]);
+        }
+        let size = std::str::from_utf8(&header)
+            .ok()
+            .and_then(|header| header.split_once(' '))
+            .and_then(|(_, size)| size.parse().ok())
+            .with_context(|| format!("Invalid blob header in {}", hash))?;
+        Ok((size, decoder))
+    }
+
+    /// Writes the blob `hash` to the work tree file at `path`, relative to
+    /// the repository root, as [`Converter::to_work_tree`] turns it
+    ///
+    /// Content checked out as it is goes from the object to the file a chunk
+    /// at a time.
+    pub fn write_worktree(
+        hash: &str,
+        obj_dir: &Path,
+        path: &Path,
+        converter: &mut Converter,
+    ) -> Result<()> {
+        let (size, mut content) = Self::reader(hash, obj_dir)?;
+        if converter.checks_out_as_is(path, size) {
+            let mut file = BufWriter::new(
+                File::create(path).with_context(|| format!("Failed to write {}", path.display()))?,
+            );
+            io::copy(&mut content, &mut file)?;
+            file.flush()?;
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        content.read_to_end(&mut data)?;
+        fs::write(path, converter.to_work_tree(path, data)?)?;
+        Ok(())
+    }
+
+    pub fn get_content(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    /// Returns a reference to the blob's raw data
+    pub fn load(hash: &str, obj_dir: &Path) -> Result<Self> {
+        let object = ObjectStorage::at(obj_dir).read(hash)?;
         Ok(Blob {
-            data: content.to_vec(),
+            data: object.data.to_vec(),
         })
     }
 }
 
+/// Size of the file at `path`, or `None` if it isn't a regular file
+fn regular_file_size(path: &Path) -> Option<u64> {
+    fs::symlink_metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+}
+
+/// Passes the content of the file at `path` to `each` a chunk at a time,
+/// failing if it isn't `size` bytes long as it changed in the meantime
+fn stream_file(
+    path: &Path,
+    size: u64,
+    mut each: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut read = 0;
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        read += count as u64;
+        each(&buffer[..count])?;
+    }
+    if read != size {
+        bail!("{} changed while it was being read", path.display());
+    }
+    Ok(())
+}
+
 impl VoxObject for Blob {
     /// Returns the type identifier for blob objects ("blob")
     fn object_type(&self) -> &str {
@@ -106,11 +329,9 @@ impl VoxObject for Blob {
         Ok(self.get_content().clone())
     }
 
-    /// Computes the SHA-1 hash of the blob's content
+    /// Computes the hash of the blob's content using the repository's object format
     fn hash(&self) -> Result<String> {
-        let mut hasher = Sha1::new();
-        hasher.update(&self.serialize()?);
-        Ok(format!("{:x}", hasher.finalize()))
+        Ok(ObjectFormat::current()?.digest(&self.serialize()?).to_hex())
     }
 
     /// Returns the expected storage path for this blob in the object storage
@@ -128,25 +349,145 @@ impl VoxObject for Blob {
 impl Storable for Blob {
     /// Saves the blob to the object storage and returns its hash
     fn save(&self, objects_dir: &Path) -> Result<String> {
-        let mut hasher = Sha1::new();
-        hasher.update(&self.data);
-        let hash = format!("{:x}", hasher.finalize());
+        let hash = ObjectFormat::for_objects_dir(objects_dir)?
+            .digest(&self.data)
+            .to_hex();
+        // Already stored, here or in an alternate
+        if loose_object_path(&hash, objects_dir)?.is_some() {
+            return Ok(hash);
+        }
 
         // format the header like (type, size, null byte)
         let header = format!("blob {}\0", self.data.len());
         let full_content = [header.as_bytes(), &self.data].concat();
 
         // compress the header + content
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&full_content)?;
-        let compressed_data = encoder.finish()?;
+        let compressed_data = compress(&full_content, objects_dir)?;
 
         // create sharded directory structure and write files
         let dir_path = objects_dir.join(&hash[..2]);
         fs::create_dir_all(&dir_path)?;
-        let object_path = dir_path.join(&hash[2..]);
-        fs::write(&object_path, compressed_data)?;
+        write_object_file(&hash, &dir_path.join(&hash[2..]), &compressed_data)?;
 
         Ok(hash)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::convert::AutoCrlf;
+    use crate::storage::encryption::generate_key;
+    use crate::storage::objects::compression::Codec;
+    use crate::storage::objects::read_object_header;
+    use crate::storage::replace::REPLACE_REFS;
+    use crate::storage::repo_config::RepoConfig;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_streamed_blob_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.path().join("large.bin"), &data)?;
+
+        let hash = Blob::save_file(&dir.path().join("large.bin"), &objects_dir)?;
+        assert_eq!(hash, ObjectFormat::Sha1.digest(&data).to_hex());
+        assert_eq!(Blob::load(&hash, &objects_dir)?.data, data);
+
+        let (size, mut reader) = Blob::reader(&hash, &objects_dir)?;
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        assert_eq!((size, content), (data.len() as u64, data.clone()));
+
+        let mut converter =
+            Converter::with_autocrlf(dir.path(), AutoCrlf::False).with_lfs_threshold(None);
+        let checked_out = dir.path().join("checked_out.bin");
+        Blob::write_worktree(&hash, &objects_dir, &checked_out, &mut converter)?;
+        assert_eq!(fs::read(&checked_out)?, data);
+
+        let path = Path::new("checked_out.bin");
+        let id = Blob::hash_worktree(&checked_out, path, &mut converter, ObjectFormat::Sha1)?;
+        assert_eq!(id.to_hex(), hash);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blobs_in_zstd_repository() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        RepoConfig::new(ObjectFormat::Sha1)
+            .with_compression(Codec::Zstd)
+            .save(dir.path())?;
+
+        let small = Blob {
+            data: b"small".to_vec(),
+        }
+        .save(&objects_dir)?;
+        let streamed = b"streamed".repeat(CHUNK_SIZE / 4);
+        fs::write(dir.path().join("large.bin"), &streamed)?;
+        let large = Blob::save_file(&dir.path().join("large.bin"), &objects_dir)?;
+
+        for (hash, data) in [(&small, b"small".to_vec()), (&large, streamed)] {
+            let stored = fs::read(objects_dir.join(&hash[..2]).join(&hash[2..]))?;
+            assert_eq!(Codec::detect(&stored), Codec::Zstd);
+            assert_eq!(Blob::load(hash, &objects_dir)?.data, data);
+            assert_eq!(read_object_header(hash, &objects_dir)?.1, data.len());
+
+            let (size, mut reader) = Blob::reader(hash, &objects_dir)?;
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)?;
+            assert_eq!((size, content), (data.len() as u64, data));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_replaced_blob_in_encrypted_repository() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        let key_id = generate_key()?;
+        RepoConfig::new(ObjectFormat::Sha1)
+            .with_encryption(Some(key_id.clone()))
+            .save(dir.path())?;
+
+        let original = Blob {
+            data: b"original".to_vec(),
+        }
+        .save(&objects_dir)?;
+        let replacement = Blob {
+            data: b"replacement".to_vec(),
+        }
+        .save(&objects_dir)?;
+        fs::create_dir_all(dir.path().join(REPLACE_REFS))?;
+        fs::write(dir.path().join(REPLACE_REFS).join(&original), format!("{}\n", replacement))?;
+
+        let read = || -> Result<(u64, Vec<u8>, usize)> {
+            let (size, mut reader) = Blob::reader(&original, &objects_dir)?;
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content)?;
+            Ok((size, content, read_object_header(&original, &objects_dir)?.1))
+        };
+        assert_eq!(read()?, (11, b"replacement".to_vec(), 11));
+
+        // Streamed files are sealed too
+        fs::write(dir.path().join("large.bin"), b"streamed")?;
+        let hash = Blob::save_file(&dir.path().join("large.bin"), &objects_dir)?;
+        let stored = fs::read(objects_dir.join(&hash[..2]).join(&hash[2..]))?;
+        assert!(stored.starts_with(b"VOXAEAD1"));
+        assert_eq!(Blob::load(&hash, &objects_dir)?.data, b"streamed");
+
+        // A plain object slipped in among the sealed ones is refused
+        let plain = tempdir()?;
+        let forged = Blob {
+            data: b"forged".to_vec(),
+        }
+        .save(&plain.path().join("objects"))?;
+        let path = |dir: &Path| dir.join(&forged[..2]).join(&forged[2..]);
+        fs::create_dir_all(path(&objects_dir).parent().unwrap())?;
+        fs::copy(path(&plain.path().join("objects")), path(&objects_dir))?;
+        assert!(Blob::load(&forged, &objects_dir).is_err());
+        assert!(Blob::reader(&forged, &objects_dir).is_err());
+        Ok(())
+    }
+}
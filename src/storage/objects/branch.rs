@@ -1,3 +1,5 @@
+use crate::storage::ref_transaction::{ExpectedValue, RefTransaction};
+use crate::storage::refs::ref_path;
 use crate::storage::utils::{HEAD_DIR, VOX_DIR};
 use anyhow::Result;
 use std::fs;
@@ -10,7 +12,10 @@ pub struct Branch {
 
 impl Branch {
     /// Creates a new branch pointing to a specific commit
-    pub fn new(name: &str, commit_hash: &str) -> Result<Self> {
+    ///
+    /// `start_point` is the revision the commit was resolved from and is
+    /// recorded in the branch's reflog
+    pub fn new(name: &str, commit_hash: &str, start_point: &str) -> Result<Self> {
         let branch_path = Self::get_branch_path(name);
 
         // Prevent duplicate branch names
@@ -18,13 +23,14 @@ impl Branch {
             return Err(anyhow::anyhow!("Branch {} already exists", name));
         }
 
-        // Create necessary directories
-        if let Some(parent) = branch_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
         // Write commit hash to branch file
-        fs::write(&branch_path, format!("\n{}", commit_hash))?;
+        let mut transaction = RefTransaction::new(&VOX_DIR);
+        transaction.create(
+            &format!("refs/heads/{}", name),
+            commit_hash,
+            &format!("branch: Created from {}", start_point),
+        );
+        transaction.commit()?;
 
         Ok(Self {
             name: name.to_string(),
@@ -33,7 +39,7 @@ impl Branch {
     }
 
     fn get_branch_path(name: &str) -> PathBuf {
-        PathBuf::from(&*VOX_DIR).join("refs/heads").join(name)
+        ref_path(&VOX_DIR, &format!("refs/heads/{}", name))
     }
 
     /// Deletes a branch if it exists and is not the current branch
@@ -51,7 +57,9 @@ impl Branch {
             }
         }
 
-        fs::remove_file(branch_path)?;
+        let mut transaction = RefTransaction::new(&VOX_DIR);
+        transaction.delete(&format!("refs/heads/{}", self.name), ExpectedValue::Any);
+        transaction.commit()?;
         Ok(())
     }
 
@@ -78,7 +86,7 @@ impl Branch {
     /// Lists all branches in the repository
     pub fn list() -> Result<Vec<Self>> {
         let mut branches = Vec::new();
-        let refs_path = PathBuf::from(&*VOX_DIR).join("refs/heads");
+        let refs_path = ref_path(&VOX_DIR, "refs/heads");
 
         if !refs_path.exists() {
             return Ok(branches);
@@ -88,7 +96,8 @@ impl Branch {
             let entry = entry?;
             let path = entry.path();
 
-            if path.is_file() {
+            // Lock files belong to ref updates in progress
+            if path.is_file() && path.extension().is_none_or(|ext| ext != "lock") {
                 if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
                     let commit_hash = fs::read_to_string(&path)?.trim().to_string();
                     branches.push(Self {
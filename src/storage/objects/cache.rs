@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Bytes of object content kept in memory by default
+pub const DEFAULT_CAPACITY: usize = 64 * 1024 * 1024;
+
+/// Identifies an object across repositories: its objects directory and hash
+type CacheKey = (PathBuf, String);
+
+/// An object's type and content, without the header
+#[derive(Debug, Clone)]
+pub struct CachedObject {
+    pub object_type: String,
+    pub data: Arc<[u8]>,
+}
+
+struct Slot {
+    object: CachedObject,
+    last_used: u64,
+}
+
+/// Least recently used cache of decompressed objects, bounded by the total
+/// size of their content
+///
+/// Objects never change once written, so entries never go stale. Objects
+/// larger than a quarter of the capacity aren't kept, so a single large blob
+/// can't flush every tree and commit out.
+pub struct ObjectCache {
+    capacity: usize,
+    used: usize,
+    tick: u64,
+    slots: HashMap<CacheKey, Slot>,
+    /// Keys by the tick they were last used at, oldest first
+    order: BTreeMap<u64, CacheKey>,
+}
+
+impl ObjectCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            tick: 0,
+            slots: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the object `hash` of `objects_dir` if it's cached, making it
+    /// the most recently used
+    pub fn get(&mut self, objects_dir: &Path, hash: &str) -> Option<CachedObject> {
+        let key = (objects_dir.to_path_buf(), hash.to_string());
+        self.tick += 1;
+        let slot = self.slots.get_mut(&key)?;
+        self.order.remove(&slot.last_used);
+        slot.last_used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(slot.object.clone())
+    }
+
+    /// Caches the object `hash` of `objects_dir`, evicting the least recently
+    /// used objects to make room
+    pub fn insert(&mut self, objects_dir: &Path, hash: &str, object: CachedObject) {
+        let size = object.data.len();
+        if size > self.capacity / 4 {
+            return;
+        }
+        let key = (objects_dir.to_path_buf(), hash.to_string());
+        if let Some(slot) = self.slots.remove(&key) {
+            self.order.remove(&slot.last_used);
+            self.used -= slot.object.data.len();
+        }
+
+        while self.used + size > self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(slot) = self.slots.remove(&oldest) {
+                self.used -= slot.object.data.len();
+            }
+        }
+
+        self.tick += 1;
+        self.used += size;
+        self.order.insert(self.tick, key.clone());
+        self.slots.insert(
+            key,
+            Slot {
+                object,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    /// Total size of the cached content
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(size: usize) -> CachedObject {
+        CachedObject {
+            object_type: "blob".to_string(),
+            data: vec![0; size].into(),
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let dir = Path::new("objects");
+        let mut cache = ObjectCache::new(400);
+        cache.insert(dir, "a", object(100));
+        cache.insert(dir, "b", object(100));
+        cache.insert(dir, "c", object(100));
+        assert!(cache.get(dir, "a").is_some());
+
+        // "b" is now the oldest and goes first
+        cache.insert(dir, "d", object(100));
+        cache.insert(dir, "e", object(100));
+        assert!(cache.get(dir, "b").is_none());
+        assert!(cache.get(dir, "a").is_some());
+        assert!(cache.get(Path::new("other"), "a").is_none());
+        assert_eq!(cache.used(), 400);
+
+        // Too large to be worth keeping
+        cache.insert(dir, "f", object(101));
+        assert!(cache.get(dir, "f").is_none());
+        assert_eq!(cache.used(), 400);
+    }
+}
@@ -1,8 +1,9 @@
-use crate::storage::objects::{Loadable, VoxObject};
+use crate::storage::encryption::read_object_file;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::{loose_object_path, Loadable, VoxObject};
 use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_CHANGE};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use sha1::{Digest, Sha1};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
@@ -44,9 +45,26 @@ pub enum ChangeType {
         old_hash: String,
         /// Hash of the file's content after rename
         new_hash: String,
+        /// How similar the contents are, in percent
+        similarity: u8,
         /// Summary of changes if content was also modified
         summary: Option<DiffSummary>,
     },
+    /// A file was added as a copy of another one, possibly edited
+    COPIED {
+        /// Path of the file it was copied from
+        old_path: PathBuf,
+        /// Path of the new file
+        new_path: PathBuf,
+        /// Hash of the content it was copied from
+        old_hash: String,
+        /// Hash of the new file's content
+        new_hash: String,
+        /// How similar the contents are, in percent
+        similarity: u8,
+        /// Summary of changes if the copy was also modified
+        summary: Option<DiffSummary>,
+    },
 }
 
 /// Summary of changes between two versions of a file
@@ -58,6 +76,9 @@ pub struct DiffSummary {
     removals: usize,
     /// Unified diff text showing changes
     text_diff: Option<String>,
+    /// Sizes in bytes of the old and new content, for binary files which
+    /// have no lines
+    binary_sizes: Option<(usize, usize)>,
 }
 
 /// Collection of changes between two states of a repository
@@ -87,6 +108,7 @@ impl ChangeSet {
             ChangeType::ADDED { path, .. } => path.clone(),
             ChangeType::DELETED { path, .. } => path.clone(),
             ChangeType::RENAMED { new_path, .. } => new_path.clone(),
+            ChangeType::COPIED { new_path, .. } => new_path.clone(),
             ChangeType::MODIFIED { path, .. } => path.clone(),
         };
         self.subchanges.insert(key, change);
@@ -161,9 +183,27 @@ impl DiffSummary {
             insertions,
             removals,
             text_diff,
+            binary_sizes: None,
+        }
+    }
+
+    /// Creates the summary of a change to a binary file
+    pub fn binary(old_size: usize, new_size: usize) -> Self {
+        DiffSummary {
+            binary_sizes: Some((old_size, new_size)),
+            ..Self::default()
         }
     }
 
+    pub fn is_binary(&self) -> bool {
+        self.binary_sizes.is_some()
+    }
+
+    /// Returns the old and new sizes of a binary file
+    pub fn binary_sizes(&self) -> Option<(usize, usize)> {
+        self.binary_sizes
+    }
+
     pub fn insertions(&self) -> usize {
         self.insertions
     }
@@ -205,11 +245,11 @@ impl VoxObject for ChangeSet {
             .context("Failed to serialize ChangeSet to binary")
     }
 
-    /// Computes the SHA-1 hash of the serialized ChangeSet
+    /// Computes the hash of the serialized ChangeSet using the repository's object format
     fn hash(&self) -> Result<String> {
-        let mut hasher = Sha1::new();
-        hasher.update(&VoxObject::serialize(self)?);
-        Ok(format!("{:x}", hasher.finalize()))
+        Ok(ObjectFormat::current()?
+            .digest(&VoxObject::serialize(self)?)
+            .to_hex())
     }
 
     /// Returns the storage path for this ChangeSet in the objects directory
@@ -235,12 +275,14 @@ impl ChangeType {
             ChangeType::DELETED { path, .. } => path,
             ChangeType::MODIFIED { path, .. } => path,
             ChangeType::RENAMED { new_path, .. } => new_path,
+            ChangeType::COPIED { new_path, .. } => new_path,
         }
     }
 
     fn get_old_path(&self) -> Option<&PathBuf> {
         match self {
             ChangeType::RENAMED { old_path, .. } => Some(old_path),
+            ChangeType::COPIED { old_path, .. } => Some(old_path),
             _ => None,
         }
     }
@@ -266,6 +308,7 @@ impl ChangeType {
             ChangeType::ADDED { new_hash, .. } => Some(new_hash),
             ChangeType::MODIFIED { new_hash, .. } => Some(new_hash),
             ChangeType::RENAMED { new_hash, .. } => Some(new_hash),
+            ChangeType::COPIED { new_hash, .. } => Some(new_hash),
             _ => None,
         }
     }
@@ -279,6 +322,7 @@ impl ChangeType {
             ChangeType::DELETED { old_hash, .. } => Some(old_hash),
             ChangeType::MODIFIED { old_hash, .. } => Some(old_hash),
             ChangeType::RENAMED { old_hash, .. } => Some(old_hash),
+            ChangeType::COPIED { old_hash, .. } => Some(old_hash),
             _ => None,
         }
     }
@@ -291,6 +335,7 @@ impl ChangeType {
         match self {
             ChangeType::MODIFIED { summary, .. } => summary.as_ref(),
             ChangeType::RENAMED { summary, .. } => summary.as_ref(),
+            ChangeType::COPIED { summary, .. } => summary.as_ref(),
             _ => None,
         }
     }
@@ -298,12 +343,22 @@ impl ChangeType {
     pub fn summary(&self) -> Option<&DiffSummary> {
         self.get_summary()
     }
+
+    /// Returns how similar a renamed or copied file is to its source, in percent
+    pub fn similarity(&self) -> Option<u8> {
+        match self {
+            ChangeType::RENAMED { similarity, .. } => Some(*similarity),
+            ChangeType::COPIED { similarity, .. } => Some(*similarity),
+            _ => None,
+        }
+    }
 }
 
 impl Loadable for ChangeSet {
     fn load(hash: &str, objects_dir: &Path) -> Result<Self> {
-        let path = objects_dir.join(&hash[..2]).join(&hash[2..]);
-        let data = std::fs::read(path)?;
+        let path = loose_object_path(hash, objects_dir)?
+            .with_context(|| format!("Failed to read object {}", hash))?;
+        let data = read_object_file(hash, &path)?;
 
         bincode::serde::decode_from_slice(&data, bincode::config::standard())
             .map(|(result, _)| result)
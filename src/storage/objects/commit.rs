@@ -1,15 +1,13 @@
 use super::tree::{read_tree, Tree};
+use crate::storage::encryption::write_object_file;
+use crate::storage::hash::ObjectFormat;
 use crate::storage::objects::ChangeSet;
-use crate::storage::objects::{Loadable, Storable, VoxObject};
+use crate::storage::objects::compression::compress;
+use crate::storage::objects::{Loadable, ObjectStorage, Storable, VoxObject};
 use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_COMMIT};
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use sha1::{Digest, Sha1};
 use std::fs;
-use std::io::{Read, Write};
 use std::path::Path;
 
 /// Represents a commit
@@ -25,8 +23,16 @@ pub struct Commit {
     pub parent: Option<String>,
     /// Author of the commit (identifier)
     pub author: String,
-    /// Timestamp when the commit was created
+    /// Timestamp when the changes were authored
     pub timestamp: DateTime<Utc>,
+    /// Who recorded the commit, which differs from the author when a commit
+    /// is amended or applied on someone else's behalf
+    pub committer: String,
+    /// Timestamp when the commit was recorded
+    pub committer_timestamp: DateTime<Utc>,
+    /// Armored detached signature of the commit without this field, stored
+    /// in the `gpgsig` header
+    pub signature: Option<String>,
     /// Commit message describing the changes
     pub message: String,
 }
@@ -43,33 +49,18 @@ impl VoxObject for Commit {
     /// - tree hash
     /// - parent hash (if exists)
     /// - author and timestamp
+    /// - committer and timestamp
+    /// - signature (if signed), continuation lines indented by a space
     /// - commit message
     ///
     fn serialize(&self) -> Result<Vec<u8>> {
-        let mut content = Vec::new();
-
-        content.extend(format!("tree {}\n", self.tree).as_bytes());
-
-        if let Some(parent) = &self.parent {
-            content.extend(format!("parent {}\n", parent).as_bytes());
-        }
-
-        let timestamp = self.timestamp.timestamp().to_string();
-        content.extend(format!("author {} {}\n", self.author, timestamp).as_bytes());
-        content.extend(b"\n");
-
-        content.extend(self.message.as_bytes());
-        content.extend(b"\n");
-
-        Ok(content)
+        Ok(self.encode(true))
     }
 
-    /// Computes the SHA-1 hash of the serialized commit
+    /// Computes the hash of the serialized commit using the repository's object format
     fn hash(&self) -> Result<String> {
         let content = self.serialize()?;
-        let mut hasher = Sha1::new();
-        hasher.update(&content);
-        Ok(format!("{:x}", hasher.finalize()))
+        Ok(ObjectFormat::current()?.digest(&content).to_hex())
     }
 
     /// Returns the storage path for this commit in the objects directory
@@ -91,20 +82,19 @@ impl VoxObject for Commit {
 impl Storable for Commit {
     /// Saves the commit object to the objects directory
     fn save(&self, objects_dir: &Path) -> Result<String> {
-        let hash = self.hash()?;
         let content = self.serialize()?;
+        let hash = ObjectFormat::for_objects_dir(objects_dir)?
+            .digest(&content)
+            .to_hex();
 
         let header = format!("commit {}\0", content.len());
         let full_content = [header.as_bytes(), &content].concat();
 
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&full_content)?;
-        let compressed_data = encoder.finish()?;
+        let compressed_data = compress(&full_content, objects_dir)?;
 
         let dir_path = objects_dir.join(&hash[..2]);
         fs::create_dir_all(&dir_path)?;
-        let object_path = dir_path.join(&hash[2..]);
-        fs::write(&object_path, compressed_data)?;
+        write_object_file(&hash, &dir_path.join(&hash[2..]), &compressed_data)?;
 
         Ok(hash)
     }
@@ -113,31 +103,18 @@ impl Storable for Commit {
 impl Loadable for Commit {
     /// Loads a commit object from the objects directory
     fn load(hash: &str, objects_dir: &Path) -> Result<Self> {
-        let dir_path = objects_dir.join(&hash[..2]);
-        let object_path = dir_path.join(&hash[2..]);
-
-        let compressed_data = fs::read(&object_path)?;
-        let mut decoder = ZlibDecoder::new(&compressed_data[..]);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
-
-        let null_pos = decompressed_data
-            .iter()
-            .position(|&b| b == 0)
-            .context("Invalid format: no null byte found")?;
-
-        let header = std::str::from_utf8(&decompressed_data[..null_pos])?;
-        if !header.starts_with("commit ") {
+        let object = ObjectStorage::at(objects_dir).read(hash)?;
+        if object.object_type != OBJ_TYPE_COMMIT {
             return Err(anyhow::anyhow!("Not a commit object"));
         }
 
-        let content = std::str::from_utf8(&decompressed_data[null_pos + 1..])?;
+        let content = std::str::from_utf8(&object.data)?;
         Self::parse(content)
     }
 }
 
 impl Commit {
-    /// Creates a new commit
+    /// Creates a new commit, authored and committed now by `author`
     pub fn new(
         tree_hash: String,
         parent_hash: Option<String>,
@@ -148,12 +125,52 @@ impl Commit {
         Self {
             tree: tree_hash,
             parent: parent_hash,
+            committer: author.clone(),
             author,
             timestamp,
+            committer_timestamp: timestamp,
+            signature: None,
             message,
         }
     }
 
+    /// Returns the hashes of the parent commits, in order
+    pub fn parents(&self) -> Vec<String> {
+        self.parent.iter().cloned().collect()
+    }
+
+    /// Returns the bytes a signature covers, the commit without its signature
+    pub fn payload(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    fn encode(&self, include_signature: bool) -> Vec<u8> {
+        let mut content = Vec::new();
+
+        content.extend(format!("tree {}\n", self.tree).as_bytes());
+
+        if let Some(parent) = &self.parent {
+            content.extend(format!("parent {}\n", parent).as_bytes());
+        }
+
+        let timestamp = self.timestamp.timestamp().to_string();
+        content.extend(format!("author {} {}\n", self.author, timestamp).as_bytes());
+
+        let committer_timestamp = self.committer_timestamp.timestamp().to_string();
+        content
+            .extend(format!("committer {} {}\n", self.committer, committer_timestamp).as_bytes());
+
+        if include_signature && let Some(signature) = &self.signature {
+            content.extend(format!("gpgsig {}\n", signature.replace('\n', "\n ")).as_bytes());
+        }
+        content.extend(b"\n");
+
+        content.extend(self.message.as_bytes());
+        content.extend(b"\n");
+
+        content
+    }
+
     /// Parses commit content into a Commit object
     ///
     /// # Arguments
@@ -166,6 +183,8 @@ impl Commit {
         let mut parent = None;
         let mut author = None;
         let mut timestamp = None;
+        let mut committer = None;
+        let mut signature: Option<String> = None;
         let mut message = Vec::new();
         let mut reading_message = false;
 
@@ -180,6 +199,15 @@ impl Commit {
                 continue;
             }
 
+            // Continuation of a multi-line signature
+            if let Some(rest) = line.strip_prefix(' ')
+                && let Some(signature) = &mut signature
+            {
+                signature.push('\n');
+                signature.push_str(rest);
+                continue;
+            }
+
             let (key, value) = line
                 .split_once(' ')
                 .ok_or_else(|| anyhow::anyhow!("Invalid commit format"))?;
@@ -187,28 +215,59 @@ impl Commit {
                 "tree" => tree = Some(value.to_string()),
                 "parent" => parent = Some(value.to_string()),
                 "author" => {
-                    let parts: Vec<&str> = value.rsplitn(2, ' ').collect();
-                    author = Some(parts[1].to_string());
-                    timestamp = Some(
-                        DateTime::from_timestamp(parts[0].parse::<i64>()?, 0)
-                            .unwrap()
-                            .with_timezone(&Utc),
-                    );
+                    let (identity, date) = parse_person(value)?;
+                    author = Some(identity);
+                    timestamp = Some(date);
                 }
+                "committer" => committer = Some(parse_person(value)?),
+                "gpgsig" => signature = Some(value.to_string()),
                 _ => return Err(anyhow::anyhow!("Unknown commit field: {}", key)),
             }
         }
 
+        let author = author.context("Missing author")?;
+        let timestamp = timestamp.context("Missing timestamp")?;
+
+        // Commits written before the committer was recorded were committed
+        // by their author
+        let (committer, committer_timestamp) =
+            committer.unwrap_or_else(|| (author.clone(), timestamp));
+
         Ok(Self {
             tree: tree.context("Missing tree hash")?,
             parent,
-            author: author.context("Missing author")?,
-            timestamp: timestamp.context("Missing timestamp")?,
+            author,
+            timestamp,
+            committer,
+            committer_timestamp,
+            signature,
             message: message.join("\n"),
         })
     }
 }
 
+/// Parses the value of an `author` or `committer` line, `<identity> <timestamp>`
+///
+/// A trailing timezone offset such as `+0200` is accepted and ignored, all
+/// timestamps are UTC
+pub fn parse_person(value: &str) -> Result<(String, DateTime<Utc>)> {
+    let mut value = value;
+    if let Some((rest, zone)) = value.rsplit_once(' ')
+        && zone.len() == 5
+        && (zone.starts_with('+') || zone.starts_with('-'))
+    {
+        value = rest;
+    }
+
+    let (identity, seconds) = value
+        .rsplit_once(' ')
+        .ok_or_else(|| anyhow!("Invalid signature: {}", value))?;
+    let timestamp = DateTime::from_timestamp(seconds.parse::<i64>()?, 0)
+        .ok_or_else(|| anyhow!("Invalid timestamp: {}", seconds))?;
+
+    Ok((identity.to_string(), timestamp))
+}
+
 fn parse_identity(s: &str) -> Result<(String, String, chrono::DateTime<chrono::Utc>)> {
     let reg = regex::Regex::new(r"(.*?)<(.*?)> (\d+) ([+-]\d{4})")?;
 
@@ -253,9 +312,12 @@ pub fn compare_commits(from_hash: &str, to_hash: &str, objects_dir: &Path) -> Re
         .with_context(|| format!("Failed to load target commit {}", to_hash))?;
 
     // Load the trees referenced by each commit
+    // Nested files are compared one by one rather than as whole subtrees
     let from_tree = read_tree(&from_commit.tree, objects_dir)
+        .and_then(|tree| tree.flatten(objects_dir))
         .with_context(|| format!("Failed to load tree {}", from_commit.tree))?;
     let to_tree = read_tree(&to_commit.tree, objects_dir)
+        .and_then(|tree| tree.flatten(objects_dir))
         .with_context(|| format!("Failed to load tree {}", to_commit.tree))?;
 
     // Compare the trees to get the change_set of changes
@@ -269,6 +331,23 @@ pub fn compare_commits(from_hash: &str, to_hash: &str, objects_dir: &Path) -> Re
     Ok(change_set)
 }
 
+/// Returns the files changed by `commit` against its first parent, or the
+/// files it adds when it's a root commit
+///
+/// Like [`compare_commits`], nested files are compared one by one, so renames
+/// between directories are detected too
+pub fn commit_changes(commit: &Commit, objects_dir: &Path) -> Result<ChangeSet> {
+    let tree = read_tree(&commit.tree, objects_dir)?.flatten(objects_dir)?;
+    let parent_tree = match commit.parents().first() {
+        Some(parent) => {
+            let parent = Commit::load(parent, objects_dir)?;
+            read_tree(&parent.tree, objects_dir)?.flatten(objects_dir)?
+        }
+        None => Tree { entries: Vec::new() },
+    };
+    Tree::compare_trees(&parent_tree, &tree, objects_dir)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -310,4 +389,53 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_committer_roundtrip() -> Result<()> {
+        let mut commit = Commit::new(
+            "tree-hash".to_string(),
+            None,
+            "Author <author@example.com>".to_string(),
+            "Test message".to_string(),
+        );
+        commit.timestamp = DateTime::from_timestamp(1_600_000_000, 0).unwrap();
+        commit.committer = "Committer <committer@example.com>".to_string();
+        commit.committer_timestamp = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let parsed = Commit::parse(std::str::from_utf8(&commit.serialize()?)?)?;
+        assert!(parsed == commit);
+
+        // Commits without a committer line fall back to the author
+        let legacy = Commit::parse("tree abc\nauthor A <a@x.io> 1600000000\n\nmsg")?;
+        assert_eq!(legacy.committer, "A <a@x.io>");
+        assert_eq!(legacy.committer_timestamp, legacy.timestamp);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_roundtrip() -> Result<()> {
+        let mut commit = Commit::new(
+            "tree-hash".to_string(),
+            Some("parent-hash".to_string()),
+            "Author <author@example.com>".to_string(),
+            "Signed\n\nbody".to_string(),
+        );
+        let unsigned = commit.serialize()?;
+        commit.signature = Some(
+            "-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----".to_string(),
+        );
+
+        // The signature covers the commit as it was before signing
+        assert_eq!(commit.payload(), unsigned);
+
+        let serialized = commit.serialize()?;
+        let text = std::str::from_utf8(&serialized)?;
+        assert!(text.contains("gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n"));
+
+        let parsed = Commit::parse(text)?;
+        assert_eq!(parsed.signature, commit.signature);
+        assert_eq!(parsed.message, commit.message);
+        assert_eq!(parsed.payload(), unsigned);
+        Ok(())
+    }
 }
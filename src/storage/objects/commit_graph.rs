@@ -0,0 +1,268 @@
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::change::ChangeType;
+use crate::storage::objects::commit::commit_changes;
+use crate::storage::objects::graph::load_commit;
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+/// Location of the commit graph, relative to the objects directory
+pub const COMMIT_GRAPH_FILE: &str = "info/commit-graph";
+
+const GRAPH_MAGIC: &[u8; 7] = b"VOXCGPH";
+const GRAPH_VERSION: u8 = 1;
+
+/// Same parameters as git: about 1% false positives
+const BITS_PER_PATH: usize = 10;
+const HASH_COUNT: u32 = 7;
+
+/// Commits changing more paths than this get a filter matching every path,
+/// since their filter would be large and rarely rule anything out
+const MAX_CHANGED_PATHS: usize = 512;
+
+/// Bloom filter of the paths a commit changes against its first parent
+///
+/// A path that isn't in the filter is certainly not changed; one that is may
+/// be. Directories leading to changed files are added too, so that a
+/// directory can be looked up as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    /// No bits at all stands for a commit changing too many paths, which
+    /// may change anything
+    bits: Vec<u8>,
+}
+
+impl BloomFilter {
+    /// Builds the filter of the changed files `paths`
+    pub fn for_paths(paths: &[PathBuf]) -> Self {
+        let mut keys = BTreeSet::new();
+        for path in paths {
+            keys.extend(path.ancestors().filter(|path| !path.as_os_str().is_empty()));
+        }
+        if keys.len() > MAX_CHANGED_PATHS {
+            return Self { bits: Vec::new() };
+        }
+
+        let mut filter = Self {
+            bits: vec![0; (keys.len() * BITS_PER_PATH).div_ceil(8).max(1)],
+        };
+        for key in keys {
+            for bit in filter.bit_positions(key) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// Returns false if `path` is certainly not changed
+    pub fn might_contain(&self, path: &Path) -> bool {
+        self.bits.is_empty()
+            || self
+                .bit_positions(path)
+                .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Bits set for `path`, by double hashing its SHA-1
+    fn bit_positions(&self, path: &Path) -> impl Iterator<Item = usize> + use<> {
+        let key = path.to_string_lossy().replace('\\', "/");
+        let digest = ObjectFormat::Sha1.digest(key.as_bytes());
+        let bytes = digest.as_bytes();
+        let first = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let second = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let len = (self.bits.len() * 8) as u64;
+        (0..HASH_COUNT).map(move |i| {
+            (first.wrapping_add(i.wrapping_mul(second)) as u64 % len) as usize
+        })
+    }
+}
+
+/// Paths changed by the commit `hash` against its first parent, with the
+/// previous names of renamed files
+///
+/// Commits at the shallow boundary count as adding every file, as they do
+/// when walking history.
+pub fn changed_paths(hash: &str, objects_dir: &Path) -> Result<Vec<PathBuf>> {
+    let commit = load_commit(hash, objects_dir)?;
+    let mut paths = Vec::new();
+    for change in commit_changes(&commit, objects_dir)?.get().into_values() {
+        if let ChangeType::RENAMED { old_path, .. } = &change {
+            paths.push(old_path.clone());
+        }
+        paths.push(change.path().to_path_buf());
+    }
+    Ok(paths)
+}
+
+/// Changed-path Bloom filters of the commits, so that a history limited to
+/// some paths can skip the commits not touching them without loading and
+/// diffing their trees
+///
+/// It's written to `objects/info/commit-graph`:
+///
+/// ```text
+/// "VOXCGPH" version:u8 commit_count:u32
+/// (raw commit id, filter_len:u32, filter bytes)* checksum
+/// ```
+///
+/// Commits are sorted by id, and the checksum is the digest of everything
+/// before it. Commits made since the graph was written have no filter, and
+/// are checked the slow way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitGraph {
+    pub format: ObjectFormat,
+    pub filters: BTreeMap<ObjectId, BloomFilter>,
+}
+
+impl CommitGraph {
+    /// Builds the filters of `commits`, reusing those of `previous`
+    pub fn build(
+        commits: &[String],
+        objects_dir: &Path,
+        format: ObjectFormat,
+        previous: Option<&CommitGraph>,
+    ) -> Result<Self> {
+        let filters = commits
+            .par_iter()
+            .map(|hash| {
+                let id = ObjectId::from_hex(hash)?;
+                if let Some(filter) = previous.and_then(|graph| graph.filters.get(&id)) {
+                    return Ok((id, filter.clone()));
+                }
+                let paths = changed_paths(hash, objects_dir)?;
+                Ok((id, BloomFilter::for_paths(&paths)))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        Ok(Self { format, filters })
+    }
+
+    /// Returns false if the commit `hash` certainly doesn't change `path`
+    ///
+    /// Commits without a filter may change anything.
+    pub fn may_change(&self, hash: &str, path: &Path) -> bool {
+        let Ok(id) = ObjectId::from_hex(hash) else {
+            return true;
+        };
+        self.filters
+            .get(&id)
+            .is_none_or(|filter| filter.might_contain(path))
+    }
+
+    /// Returns true if the commit `hash` has a filter
+    pub fn contains(&self, hash: &str) -> bool {
+        ObjectId::from_hex(hash).is_ok_and(|id| self.filters.contains_key(&id))
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut data = GRAPH_MAGIC.to_vec();
+        data.write_u8(GRAPH_VERSION)?;
+        data.write_u32::<BigEndian>(self.filters.len() as u32)?;
+        for (id, filter) in &self.filters {
+            data.extend_from_slice(id.as_bytes());
+            data.write_u32::<BigEndian>(filter.bits.len() as u32)?;
+            data.extend_from_slice(&filter.bits);
+        }
+        let checksum = self.format.digest(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        Ok(data)
+    }
+
+    pub fn parse(data: &[u8], format: ObjectFormat) -> Result<Self> {
+        let body_len = data
+            .len()
+            .checked_sub(format.raw_len())
+            .context("Commit graph too short")?;
+        let (body, checksum) = data.split_at(body_len);
+        if format.digest(body).as_bytes() != checksum {
+            bail!("Commit graph checksum mismatch");
+        }
+
+        let mut reader = Cursor::new(body);
+        let mut magic = [0u8; 7];
+        reader.read_exact(&mut magic)?;
+        if &magic != GRAPH_MAGIC {
+            bail!("Invalid commit graph");
+        }
+        let version = reader.read_u8()?;
+        if version != GRAPH_VERSION {
+            bail!("Unsupported commit graph version {}", version);
+        }
+
+        let commit_count = reader.read_u32::<BigEndian>()?;
+        let mut filters = BTreeMap::new();
+        let mut raw = vec![0u8; format.raw_len()];
+        for _ in 0..commit_count {
+            reader.read_exact(&mut raw)?;
+            let id = ObjectId::from_bytes(format, &raw)?;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            if len > body.len() {
+                bail!("Commit graph filter runs past the end of the file");
+            }
+            let mut bits = vec![0u8; len];
+            reader.read_exact(&mut bits)?;
+            filters.insert(id, BloomFilter { bits });
+        }
+
+        Ok(Self { format, filters })
+    }
+
+    /// Writes the graph to `objects_dir/info`
+    pub fn write(&self, objects_dir: &Path) -> Result<()> {
+        let path = objects_dir.join(COMMIT_GRAPH_FILE);
+        let parent = path.parent().context("Invalid commit graph path")?;
+        fs::create_dir_all(parent)?;
+        let temp_path = path.with_extension("lock");
+        fs::write(&temp_path, self.serialize()?)?;
+        fs::rename(&temp_path, &path).context("Failed to write the commit graph")
+    }
+
+    /// Reads the graph of `objects_dir`, if there's one
+    pub fn read(objects_dir: &Path, format: ObjectFormat) -> Result<Option<Self>> {
+        let path = objects_dir.join(COMMIT_GRAPH_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).context("Failed to read the commit graph")?;
+        Ok(Some(Self::parse(&data, format)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filters() -> Result<()> {
+        let paths = vec![PathBuf::from("src/main.rs"), PathBuf::from("README.md")];
+        let filter = BloomFilter::for_paths(&paths);
+        assert!(filter.might_contain(Path::new("src/main.rs")));
+        assert!(filter.might_contain(Path::new("src")));
+        assert!(filter.might_contain(Path::new("README.md")));
+        let misses = (0..1000)
+            .filter(|i| filter.might_contain(Path::new(&format!("other/{}.rs", i))))
+            .count();
+        assert!(misses < 100);
+
+        let many: Vec<_> = (0..=MAX_CHANGED_PATHS).map(|i| PathBuf::from(i.to_string())).collect();
+        assert!(BloomFilter::for_paths(&many).might_contain(Path::new("anything")));
+
+        let hash = ObjectFormat::Sha1.digest(b"commit").to_hex();
+        let mut graph = CommitGraph {
+            format: ObjectFormat::Sha1,
+            filters: BTreeMap::new(),
+        };
+        graph.filters.insert(ObjectId::from_hex(&hash)?, filter);
+        let parsed = CommitGraph::parse(&graph.serialize()?, ObjectFormat::Sha1)?;
+        assert_eq!(parsed, graph);
+        assert!(!parsed.may_change(&hash, Path::new("Cargo.toml")));
+        assert!(parsed.may_change(&ObjectFormat::Sha1.digest(b"other").to_hex(), Path::new("x")));
+
+        let mut corrupt = graph.serialize()?;
+        corrupt[12] ^= 1;
+        assert!(CommitGraph::parse(&corrupt, ObjectFormat::Sha1).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::storage::objects::{object_compression, zstd_compression};
+use crate::storage::repo_config::RepoConfig;
+use anyhow::Result;
+use clap::ValueEnum;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// First bytes of a zstd frame, which zlib streams never start with
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+lazy_static! {
+    /// Codec of every objects directory written to so far, so the config is
+    /// read once per process rather than for every object
+    static ref CODECS: Mutex<HashMap<PathBuf, Codec>> = Mutex::new(HashMap::new());
+}
+
+/// Algorithm loose objects are compressed with
+///
+/// Objects are decompressed with whichever codec wrote them, told apart by
+/// their first bytes, so a repository can hold objects of both while it's
+/// being migrated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// zlib, as git compresses objects
+    #[default]
+    Zlib,
+    /// Zstandard, faster to compress and decompress at a similar ratio
+    Zstd,
+}
+
+impl Codec {
+    /// Returns the codec of the repository owning `objects_dir`, its
+    /// `compression` extension
+    pub fn for_objects_dir(objects_dir: &Path) -> Result<Self> {
+        // Relative paths name another directory once the current one changes,
+        // and a directory that doesn't exist yet has nothing to cache
+        let Ok(dir) = objects_dir.canonicalize() else {
+            return Self::load(objects_dir);
+        };
+        if let Some(codec) = CODECS.lock().unwrap_or_else(PoisonError::into_inner).get(&dir) {
+            return Ok(*codec);
+        }
+        let codec = Self::load(&dir)?;
+        CODECS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(dir, codec);
+        Ok(codec)
+    }
+
+    fn load(objects_dir: &Path) -> Result<Self> {
+        match objects_dir.parent() {
+            Some(vox_dir) => Ok(RepoConfig::load(vox_dir)?.compression()),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Forgets the codec cached for `objects_dir`, once its config changed
+    pub fn forget(objects_dir: &Path) {
+        if let Ok(dir) = objects_dir.canonicalize() {
+            CODECS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&dir);
+        }
+    }
+
+    /// Name used in the config file and on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Zlib => "zlib",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// Returns the codec that compressed `data`
+    pub fn detect(data: &[u8]) -> Self {
+        match data.starts_with(&ZSTD_MAGIC) {
+            true => Codec::Zstd,
+            false => Codec::Zlib,
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Compresses an object as it's written, with the codec of its repository
+pub enum ObjectEncoder<W: Write> {
+    Zlib(ZlibEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> ObjectEncoder<W> {
+    /// Compresses into `writer` for the repository owning `objects_dir`
+    pub fn new(writer: W, objects_dir: &Path) -> Result<Self> {
+        Ok(Self::with_codec(writer, Codec::for_objects_dir(objects_dir)?)?)
+    }
+
+    pub fn with_codec(writer: W, codec: Codec) -> io::Result<Self> {
+        Ok(match codec {
+            Codec::Zlib => ObjectEncoder::Zlib(ZlibEncoder::new(writer, object_compression())),
+            Codec::Zstd => ObjectEncoder::Zstd(zstd::Encoder::new(writer, zstd_compression())?),
+        })
+    }
+
+    /// Writes what's left of the compressed stream and returns the writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            ObjectEncoder::Zlib(encoder) => encoder.finish(),
+            ObjectEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for ObjectEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ObjectEncoder::Zlib(encoder) => encoder.write(buf),
+            ObjectEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ObjectEncoder::Zlib(encoder) => encoder.flush(),
+            ObjectEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decompresses an object as it's read, whichever codec compressed it
+pub enum ObjectDecoder<R: BufRead> {
+    Zlib(ZlibDecoder<R>),
+    Zstd(zstd::Decoder<'static, R>),
+}
+
+impl<R: BufRead> ObjectDecoder<R> {
+    /// Decompresses the stream `reader`, looking at its first bytes for the
+    /// codec
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        Ok(match Codec::detect(reader.fill_buf()?) {
+            Codec::Zlib => ObjectDecoder::Zlib(ZlibDecoder::new(reader)),
+            Codec::Zstd => ObjectDecoder::Zstd(zstd::Decoder::with_buffer(reader)?.single_frame()),
+        })
+    }
+}
+
+impl<R: Read> ObjectDecoder<BufReader<R>> {
+    /// Decompresses an unbuffered stream, such as a file
+    pub fn buffered(reader: R) -> io::Result<Self> {
+        Self::new(BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> Read for ObjectDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ObjectDecoder::Zlib(decoder) => decoder.read(buf),
+            ObjectDecoder::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Compresses a whole object for the repository owning `objects_dir`
+pub fn compress(data: &[u8], objects_dir: &Path) -> Result<Vec<u8>> {
+    let mut encoder = ObjectEncoder::new(Vec::new(), objects_dir)?;
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompresses a whole object, whichever codec compressed it
+pub fn decompress(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ObjectDecoder::new(compressed)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_roundtrip() -> Result<()> {
+        let data = b"blob 11\0hello world".repeat(10);
+        for codec in [Codec::Zlib, Codec::Zstd] {
+            let mut encoder = ObjectEncoder::with_codec(Vec::new(), codec)?;
+            encoder.write_all(&data)?;
+            let compressed = encoder.finish()?;
+            assert_eq!(Codec::detect(&compressed), codec);
+            assert_eq!(decompress(&compressed)?, data);
+        }
+        Ok(())
+    }
+}
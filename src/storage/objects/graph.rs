@@ -0,0 +1,541 @@
+use crate::commands::index::index::Index;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::bitmap::ReachabilityBitmaps;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::{loose_object_path, read_raw_object, Loadable};
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::tree::read_tree;
+use crate::storage::objects::walk::{CommitWalker, WalkOrder};
+use crate::storage::reflog::reflog_hashes;
+use crate::storage::refs::list_all_refs;
+use crate::storage::replace::without_replacements;
+use crate::storage::revision::resolve_commit;
+use crate::storage::shallow::is_shallow;
+use crate::storage::utils::{OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, OBJ_TYPE_TREE};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Collects the hashes every reachability walk should start from
+///
+/// Roots are:
+/// - a detached `HEAD`
+/// - every ref under `refs/`
+/// - every hash recorded in the reflogs
+/// - every blob staged in the index
+///
+/// # Arguments
+///
+/// * `vox_dir` - Path to the `.vox` directory
+pub fn repository_roots(vox_dir: &Path) -> Result<Vec<String>> {
+    let mut roots = Vec::new();
+
+    let head_path = vox_dir.join("HEAD");
+    if head_path.exists() {
+        let head = fs::read_to_string(&head_path).context("Failed to read HEAD file")?;
+        let head = head.trim();
+        if !head.is_empty() && !head.starts_with("ref: ") {
+            roots.push(head.to_string());
+        }
+    }
+
+    for (_name, hash) in list_all_refs(vox_dir)? {
+        roots.push(hash);
+    }
+    roots.extend(reflog_hashes(vox_dir)?);
+
+    let index_path = vox_dir.join("index");
+    if index_path.exists() {
+        let mut index = Index::new();
+        index.read_from_file(&index_path)?;
+        for entry in index.get_entries().values() {
+            roots.push(entry.hash.to_hex());
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Returns the commits HEAD and the refs of every namespace point to, peeling tags
+///
+/// Refs naming trees or blobs are left out, they have no history.
+pub fn ref_tips(vox_dir: &Path) -> Result<Vec<String>> {
+    let mut tips: Vec<String> = resolve_commit(vox_dir, "HEAD").into_iter().collect();
+    for (_, hash) in list_all_refs(vox_dir)? {
+        if let Ok(commit) = resolve_commit(vox_dir, &hash)
+            && !tips.contains(&commit)
+        {
+            tips.push(commit);
+        }
+    }
+    Ok(tips)
+}
+
+/// Walks the object graph and returns every object reachable from `roots`
+///
+/// Commits lead to their tree and parent, tags to the tagged object and
+/// trees to their entries. Objects that are referenced but missing from the
+/// object storage are still reported as reachable, since nothing can be
+/// removed for them anyway.
+///
+/// Commits with a reachability bitmap aren't walked, their bitmap lists
+/// everything behind them.
+///
+/// # Arguments
+///
+/// * `roots` - Hashes to start the walk from
+/// * `objects_dir` - Path to the objects directory
+///
+/// # Errors
+///
+/// Returns an error if a reachable object exists but can't be read or parsed
+pub fn collect_reachable<I>(roots: I, objects_dir: &Path) -> Result<HashSet<String>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let format = ObjectFormat::for_objects_dir(objects_dir)?;
+    let bitmaps = ReachabilityBitmaps::read(objects_dir, format)?;
+    walk_reachable(roots, objects_dir, bitmaps.as_ref())
+}
+
+/// Same as [`collect_reachable`], taking the whole history of the commits
+/// with a bitmap in `bitmaps` at once
+pub fn walk_reachable<I>(
+    roots: I,
+    objects_dir: &Path,
+    bitmaps: Option<&ReachabilityBitmaps>,
+) -> Result<HashSet<String>>
+where
+    I: IntoIterator<Item = String>,
+{
+    // Replaced objects keep what they reach, the replace refs are roots too
+    without_replacements(|| walk_objects(roots, objects_dir, bitmaps))
+}
+
+fn walk_objects<I>(
+    roots: I,
+    objects_dir: &Path,
+    bitmaps: Option<&ReachabilityBitmaps>,
+) -> Result<HashSet<String>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<String> = roots.into_iter().collect();
+
+    while let Some(hash) = pending.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+
+        if let Some(objects) = bitmaps.and_then(|bitmaps| bitmaps.reachable_from(&hash)) {
+            reachable.extend(objects);
+            continue;
+        }
+
+        if loose_object_path(&hash, objects_dir)?.is_none() {
+            continue;
+        }
+
+        let (object_type, content) = read_raw_object(&hash, objects_dir)?;
+        match object_type.as_str() {
+            OBJ_TYPE_COMMIT => {
+                let commit = Commit::parse(std::str::from_utf8(&content)?)
+                    .with_context(|| format!("Failed to parse commit {}", hash))?;
+                pending.push(commit.tree);
+                pending.extend(commit.parent);
+            }
+            OBJ_TYPE_TREE => {
+                let tree = read_tree(&hash, objects_dir)
+                    .with_context(|| format!("Failed to parse tree {}", hash))?;
+                // Submodule commits live in the nested repository
+                pending.extend(
+                    tree.entries
+                        .into_iter()
+                        .filter(|e| e.object_type != OBJ_TYPE_COMMIT)
+                        .map(|e| e.object_hash),
+                );
+            }
+            OBJ_TYPE_TAG => {
+                let tag = Tag::parse(std::str::from_utf8(&content)?)
+                    .with_context(|| format!("Failed to parse tag {}", hash))?;
+                pending.push(tag.object);
+            }
+            OBJ_TYPE_BLOB => {}
+            _ => {}
+        }
+    }
+
+    Ok(reachable)
+}
+
+/// Returns `start` and every commit reachable from it through parents
+///
+/// # Errors
+///
+/// Returns an error if one of the commits can't be loaded
+pub fn ancestors(start: &str, objects_dir: &Path) -> Result<HashSet<String>> {
+    let mut walker = CommitWalker::new(objects_dir).with_order(WalkOrder::BreadthFirst);
+    walker.push(start)?;
+    walker.map(|commit| Ok(commit?.0)).collect()
+}
+
+/// Returns true if `ancestor` can be reached from `descendant` through
+/// parents, a commit counting as its own ancestor
+///
+/// # Errors
+///
+/// Returns an error if one of the walked commits can't be loaded
+pub fn is_ancestor(ancestor: &str, descendant: &str, objects_dir: &Path) -> Result<bool> {
+    let mut walker = CommitWalker::new(objects_dir).with_order(WalkOrder::BreadthFirst);
+    walker.push(descendant)?;
+    for commit in walker {
+        if commit?.0 == ancestor {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Computes the best common ancestors of `a` and `b`
+///
+/// A common ancestor is one of the best if it isn't an ancestor of another
+/// common ancestor. Histories without criss-cross merges have a single best
+/// common ancestor, or none if they share no commit.
+///
+/// # Returns
+///
+/// The best common ancestors, most recently committed first
+pub fn merge_bases(a: &str, b: &str, objects_dir: &Path) -> Result<Vec<String>> {
+    let of_a = ancestors(a, objects_dir)?;
+
+    // Walk back from b, stopping at the first commits that are also reachable from a
+    let mut common = Vec::new();
+    let mut walker = CommitWalker::new(objects_dir).with_order(WalkOrder::BreadthFirst);
+    walker.push(b)?;
+    while let Some(commit) = walker.next() {
+        let (hash, _) = commit?;
+        if of_a.contains(&hash) {
+            common.push(hash);
+            walker.skip_parents();
+        }
+    }
+
+    // Drop common ancestors that are reachable from another one
+    let mut redundant = HashSet::new();
+    for hash in &common {
+        for parent in load_commit(hash, objects_dir)?.parents() {
+            redundant.extend(ancestors(&parent, objects_dir)?);
+        }
+    }
+    let mut bases = Vec::new();
+    for hash in common {
+        if !redundant.contains(&hash) {
+            let timestamp = load_commit(&hash, objects_dir)?.committer_timestamp;
+            bases.push((timestamp, hash));
+        }
+    }
+
+    bases.sort_by(|x, y| y.cmp(x));
+    Ok(bases.into_iter().map(|(_, hash)| hash).collect())
+}
+
+/// Computes the best common ancestor of `a` and `b`, see [`merge_bases`]
+///
+/// # Returns
+///
+/// `None` if the commits share no history
+pub fn merge_base(a: &str, b: &str, objects_dir: &Path) -> Result<Option<String>> {
+    Ok(merge_bases(a, b, objects_dir)?.into_iter().next())
+}
+
+/// Picks the commit that best halves the commits reachable from `bad` but
+/// not from any of `good`, the next one to test when bisecting
+///
+/// # Returns
+///
+/// The commit along with the number of commits left to search, or `None`
+/// once `bad` is the only one left, and so the first bad commit
+pub fn bisect_midpoint(
+    bad: &str,
+    good: &[String],
+    objects_dir: &Path,
+) -> Result<Option<(String, usize)>> {
+    let candidates = list_commits(&[bad.to_string()], good, objects_dir)?;
+    let total = candidates.len();
+    if total <= 1 {
+        return Ok(None);
+    }
+    let remaining: HashSet<&String> = candidates.iter().collect();
+
+    // If a commit is bad, the search goes on among its ancestors, otherwise
+    // among the others: the best one leaves the fewest either way
+    let mut best: Option<(usize, &String)> = None;
+    for candidate in &candidates[1..] {
+        let below = ancestors(candidate, objects_dir)?
+            .iter()
+            .filter(|hash| remaining.contains(hash))
+            .count();
+        let score = below.min(total - below);
+        if best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, candidate));
+        }
+    }
+    Ok(best.map(|(_, hash)| (hash.clone(), total)))
+}
+
+/// Counts the commits `local` has that `upstream` doesn't, and the other way round
+///
+/// # Returns
+///
+/// The number of commits ahead and behind `upstream`
+pub fn ahead_behind(local: &str, upstream: &str, objects_dir: &Path) -> Result<(usize, usize)> {
+    let ahead = list_commits(&[local.to_string()], &[upstream.to_string()], objects_dir)?;
+    let behind = list_commits(&[upstream.to_string()], &[local.to_string()], objects_dir)?;
+    Ok((ahead.len(), behind.len()))
+}
+
+/// Lists the commits reachable from `include` but not from `exclude`
+///
+/// # Returns
+///
+/// The commits, most recently committed first
+pub fn list_commits(
+    include: &[String],
+    exclude: &[String],
+    objects_dir: &Path,
+) -> Result<Vec<String>> {
+    let mut walker = CommitWalker::new(objects_dir);
+    for hash in exclude {
+        walker.hide(hash)?;
+    }
+    for hash in include {
+        walker.push(hash)?;
+    }
+    walker.map(|commit| Ok(commit?.0)).collect()
+}
+
+/// Lists the trees and blobs reachable from `tree`, as `(hash, path)` pairs
+///
+/// Objects in `seen` are skipped along with everything below them, and every
+/// listed object is added to it. Submodule commits aren't listed.
+pub fn list_tree_objects(
+    tree: &str,
+    path: &str,
+    objects_dir: &Path,
+    seen: &mut HashSet<String>,
+) -> Result<Vec<(String, String)>> {
+    let mut objects = Vec::new();
+    let mut pending = vec![(tree.to_string(), path.to_string())];
+
+    while let Some((hash, path)) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let tree = read_tree(&hash, objects_dir)
+            .with_context(|| format!("Failed to read tree {}", hash))?;
+        objects.push((hash, path.clone()));
+
+        for entry in tree.entries {
+            let entry_path = match path.as_str() {
+                "" => entry.name.clone(),
+                _ => format!("{}/{}", path, entry.name),
+            };
+            match entry.object_type.as_str() {
+                OBJ_TYPE_TREE => pending.push((entry.object_hash, entry_path)),
+                OBJ_TYPE_BLOB if seen.insert(entry.object_hash.clone()) => {
+                    objects.push((entry.object_hash, entry_path))
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Loads a commit for walking history
+///
+/// Commits at the shallow boundary are returned without parents, since those
+/// are intentionally missing from the repository.
+pub fn load_commit(hash: &str, objects_dir: &Path) -> Result<Commit> {
+    let mut commit = Commit::load(hash, objects_dir)
+        .with_context(|| format!("Failed to load commit {}", hash))?;
+    if let Some(vox_dir) = objects_dir.parent()
+        && is_shallow(vox_dir, hash)?
+    {
+        commit.parent = None;
+    }
+    Ok(commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::hash::ObjectFormat;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::Storable;
+    use crate::storage::shallow::write_shallow;
+    use crate::storage::utils::PERM_FILE;
+    use std::collections::BTreeSet;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_tree(objects_dir: &Path, name: &str, blob_hash: &str) -> Result<String> {
+        let mut content = format!("{} {}\0", PERM_FILE, name).into_bytes();
+        content.extend(hex::decode(blob_hash)?);
+        let full = [format!("tree {}\0", content.len()).as_bytes(), &content].concat();
+        let hash = ObjectFormat::Sha1.digest(&full).to_hex();
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&full)?;
+        fs::create_dir_all(objects_dir.join(&hash[..2]))?;
+        fs::write(objects_dir.join(&hash[..2]).join(&hash[2..]), encoder.finish()?)?;
+        Ok(hash)
+    }
+
+    #[test]
+    fn test_collect_reachable() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path();
+
+        let blob_hash = Blob {
+            data: b"hello".to_vec(),
+        }
+        .save(objects_dir)?;
+        let orphan_hash = Blob {
+            data: b"orphan".to_vec(),
+        }
+        .save(objects_dir)?;
+        let tree_hash = write_tree(objects_dir, "hello.txt", &blob_hash)?;
+
+        let first = Commit::new(
+            tree_hash.clone(),
+            None,
+            "Author <author@example.com>".to_string(),
+            "first".to_string(),
+        )
+        .save(objects_dir)?;
+        let second = Commit::new(
+            tree_hash.clone(),
+            Some(first.clone()),
+            "Author <author@example.com>".to_string(),
+            "second".to_string(),
+        )
+        .save(objects_dir)?;
+
+        let reachable = collect_reachable(vec![second.clone()], objects_dir)?;
+
+        assert!(reachable.contains(&second));
+        assert!(reachable.contains(&first));
+        assert!(reachable.contains(&tree_hash));
+        assert!(reachable.contains(&blob_hash));
+        assert!(!reachable.contains(&orphan_hash));
+        Ok(())
+    }
+
+    fn commit(objects_dir: &Path, parent: Option<&str>, message: &str) -> Result<String> {
+        Commit::new(
+            "tree".to_string(),
+            parent.map(str::to_string),
+            "Author <author@example.com>".to_string(),
+            message.to_string(),
+        )
+        .save(objects_dir)
+    }
+
+    #[test]
+    fn test_merge_base() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path();
+
+        // root - base - left
+        //            \- right - right2
+        let root = commit(objects_dir, None, "root")?;
+        let base = commit(objects_dir, Some(&root), "base")?;
+        let left = commit(objects_dir, Some(&base), "left")?;
+        let right = commit(objects_dir, Some(&base), "right")?;
+        let right2 = commit(objects_dir, Some(&right), "right2")?;
+        let unrelated = commit(objects_dir, None, "unrelated")?;
+
+        assert_eq!(merge_base(&left, &right2, objects_dir)?, Some(base.clone()));
+        assert_eq!(merge_base(&right2, &left, objects_dir)?, Some(base.clone()));
+        assert_eq!(merge_base(&right, &right2, objects_dir)?, Some(right.clone()));
+        assert_eq!(merge_base(&left, &unrelated, objects_dir)?, None);
+
+        assert!(is_ancestor(&root, &right2, objects_dir)?);
+        assert!(is_ancestor(&left, &left, objects_dir)?);
+        assert!(!is_ancestor(&left, &right2, objects_dir)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bisect_midpoint() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path();
+
+        let mut history = vec![commit(objects_dir, None, "0")?];
+        for message in ["1", "2", "3", "4", "5", "6"] {
+            let parent = history.last().cloned();
+            history.push(commit(objects_dir, parent.as_deref(), message)?);
+        }
+        let good = vec![history[0].clone()];
+
+        let (midpoint, left) = bisect_midpoint(&history[6], &good, objects_dir)?.unwrap();
+        assert_eq!(left, 6);
+        assert_eq!(midpoint, history[3]);
+
+        let good = vec![history[5].clone()];
+        assert_eq!(bisect_midpoint(&history[6], &good, objects_dir)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_commits() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path();
+
+        let root = commit(objects_dir, None, "root")?;
+        let base = commit(objects_dir, Some(&root), "base")?;
+        let left = commit(objects_dir, Some(&base), "left")?;
+        let right = commit(objects_dir, Some(&base), "right")?;
+
+        let all = list_commits(&[left.clone(), right.clone()], &[], objects_dir)?;
+        assert_eq!(all.len(), 4);
+        assert_eq!(all.last(), Some(&root));
+
+        // left..right
+        let range = list_commits(
+            std::slice::from_ref(&right),
+            std::slice::from_ref(&left),
+            objects_dir,
+        )?;
+        assert_eq!(range, vec![right.clone()]);
+
+        assert_eq!(ahead_behind(&left, &right, objects_dir)?, (1, 1));
+        assert_eq!(ahead_behind(&left, &root, objects_dir)?, (2, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_stops_at_shallow_boundary() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        let objects_dir = vox_dir.join("objects");
+
+        let root = commit(&objects_dir, None, "root")?;
+        let base = commit(&objects_dir, Some(&root), "base")?;
+        let tip = commit(&objects_dir, Some(&base), "tip")?;
+
+        // A depth 2 clone has neither the root commit nor its object
+        fs::remove_file(objects_dir.join(&root[..2]).join(&root[2..]))?;
+        assert!(list_commits(std::slice::from_ref(&tip), &[], &objects_dir).is_err());
+
+        write_shallow(vox_dir, &BTreeSet::from([base.clone()]))?;
+        let history = list_commits(std::slice::from_ref(&tip), &[], &objects_dir)?;
+        assert_eq!(history, vec![tip, base]);
+        Ok(())
+    }
+}
@@ -1,27 +1,49 @@
+use crate::commands::config::commands::{compression_level, verify_objects};
+use crate::error::VoxError;
+use crate::storage::encryption::{open_object_file, read_object_file};
 use crate::storage::objects::blob::Blob;
+use crate::storage::objects::cache::{CachedObject, ObjectCache, DEFAULT_CAPACITY};
 use crate::storage::objects::change::ChangeSet;
+use crate::storage::objects::compression::{decompress, ObjectDecoder};
 use crate::storage::objects::commit::Commit;
 use crate::storage::objects::tag::Tag;
+use crate::storage::objects::pack::PackReader;
 use crate::storage::objects::tree::Tree;
-use anyhow::{anyhow, Result};
-use sha1::{Digest, Sha1};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::quarantine::{affected_refs, is_recovering, quarantine, recover};
+use crate::storage::replace::replaced;
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::Compression;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{BufReader, Read};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::SystemTime;
 
 use crate::storage::utils::{
     OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_CHANGE, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, OBJ_TYPE_TREE,
     UNKNOWN_TYPE,
 };
 
+pub mod bitmap;
 pub mod blob;
 pub mod branch;
+pub mod cache;
 pub mod change;
 pub mod commit;
+pub mod commit_graph;
+pub mod compression;
 pub mod delta;
+pub mod graph;
 pub mod pack;
+pub mod similarity;
 pub mod tag;
 pub mod tree;
+pub mod walk;
 
 pub trait VoxObject {
     fn object_type(&self) -> &str;
@@ -30,7 +52,7 @@ pub trait VoxObject {
     fn object_path(&self) -> Result<String>;
 }
 
-pub(crate) enum Object {
+pub enum Object {
     Blob(Blob),
     Commit(Commit),
     Tree(Tree),
@@ -39,17 +61,476 @@ pub(crate) enum Object {
     Unknown(String),
 }
 
+lazy_static! {
+    /// Objects read so far by the process, shared by every command so walks
+    /// and tree comparisons don't inflate the same objects over and over
+    static ref OBJECT_CACHE: Mutex<ObjectCache> = Mutex::new(ObjectCache::new(DEFAULT_CAPACITY));
+
+    /// Level set with `core.compressionLevel`, read once per process
+    static ref COMPRESSION_LEVEL: Option<u32> = compression_level();
+
+    /// `core.verifyObjects`, read once per process
+    static ref VERIFY_OBJECTS: Option<bool> = verify_objects();
+}
+
+/// Compression loose objects are written with: `core.compressionLevel`, or
+/// zlib's default level
+pub fn object_compression() -> Compression {
+    COMPRESSION_LEVEL.map_or(Compression::default(), Compression::new)
+}
+
+/// zstd level loose objects are written with in repositories using zstd:
+/// `core.compressionLevel` spread over the levels zstd has, or its default
+pub fn zstd_compression() -> i32 {
+    COMPRESSION_LEVEL.map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| 1 + 2 * level as i32)
+}
+
+/// Compression objects are packed with: `core.compressionLevel`, or the best
+/// level as packs are written once and read many times
+pub fn pack_compression() -> Compression {
+    COMPRESSION_LEVEL.map_or(Compression::best(), Compression::new)
+}
+
+/// Whether every object read from disk is re-hashed, off unless
+/// `core.verifyObjects` is set
+pub fn verifies_loads() -> bool {
+    VERIFY_OBJECTS.unwrap_or(false)
+}
+
+/// Whether the objects a clone receives are re-hashed, on unless
+/// `core.verifyObjects` is false
+pub fn verifies_received() -> bool {
+    VERIFY_OBJECTS.unwrap_or(true)
+}
+
+/// Lists other object directories to read objects from, one per line
+pub const ALTERNATES_FILE: &str = "info/alternates";
+
+/// How deep alternates of alternates are followed, as in git
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// Returns the object directories `objects_dir` borrows objects from, listed
+/// in its `info/alternates` and theirs in turn
+///
+/// Lines are absolute paths or paths relative to `objects_dir`; empty lines,
+/// lines starting with `#` and directories that don't exist are skipped.
+pub fn alternates(objects_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    let mut pending = vec![(objects_dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        let path = dir.join(ALTERNATES_FILE);
+        if depth == MAX_ALTERNATE_DEPTH || !path.exists() {
+            continue;
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for line in content.lines().rev() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let alternate = dir.join(line);
+            if alternate.is_dir() && alternate != objects_dir && !found.contains(&alternate) {
+                found.push(alternate.clone());
+                pending.push((alternate, depth + 1));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Writes the `info/alternates` of `objects_dir`, listing `dirs` by their
+/// absolute paths
+pub fn write_alternates(objects_dir: &Path, dirs: &[PathBuf]) -> Result<()> {
+    let mut content = String::new();
+    for dir in dirs {
+        let dir = fs::canonicalize(dir)
+            .with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        content.push_str(&format!("{}\n", dir.display()));
+    }
+    let path = objects_dir.join(ALTERNATES_FILE);
+    fs::create_dir_all(path.parent().context("Invalid alternates path")?)?;
+    fs::write(&path, content).context("Failed to write the alternates")
+}
+
+/// Returns the file of the loose object `hash`, in `objects_dir` or one of
+/// its alternates
+pub fn loose_object_path(hash: &str, objects_dir: &Path) -> Result<Option<PathBuf>> {
+    if hash.len() < 3 {
+        return Ok(None);
+    }
+    let local = objects_dir.join(&hash[..2]).join(&hash[2..]);
+    if local.exists() {
+        return Ok(Some(local));
+    }
+    Ok(alternates(objects_dir)?
+        .into_iter()
+        .map(|dir| dir.join(&hash[..2]).join(&hash[2..]))
+        .find(|path| path.exists()))
+}
+
 #[derive(Debug)]
 pub struct ObjectStorage {
     pub dir: PathBuf,
 }
 
+/// A loose (non-packed) object file found in the object storage
+#[derive(Debug, Clone)]
+pub struct LooseObject {
+    /// Hex-encoded hash of the object
+    pub hash: String,
+    /// Path to the compressed object file
+    pub path: PathBuf,
+    /// Size of the compressed object file in bytes
+    pub size: u64,
+    /// Last modification time of the object file
+    pub modified: SystemTime,
+}
+
 impl ObjectStorage {
     pub fn new(repo_path: &Path) -> Self {
         Self {
             dir: repo_path.join(&*OBJ_DIR),
         }
     }
+
+    /// Opens the storage whose objects are in `objects_dir`
+    pub fn at(objects_dir: &Path) -> Self {
+        Self {
+            dir: objects_dir.to_path_buf(),
+        }
+    }
+
+    /// Reads the loose object `hash`, keeping it in the process-wide cache
+    /// so reading it again doesn't touch the disk
+    ///
+    /// A replaced object reads as its replacement, see
+    /// [`replacement`](crate::storage::replace::replacement).
+    pub fn read(&self, hash: &str) -> Result<CachedObject> {
+        let hash = &replaced(hash, &self.dir)?;
+        let cached = OBJECT_CACHE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&self.dir, hash);
+        if let Some(object) = cached {
+            return Ok(object);
+        }
+
+        let (object_type, data) = read_loose_object(hash, &self.dir)?;
+        let object = CachedObject {
+            object_type,
+            data: data.into(),
+        };
+        OBJECT_CACHE
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(&self.dir, hash, object.clone());
+        Ok(object)
+    }
+
+    /// Lists every loose object stored under the `xx/yyyy...` fan-out directories
+    ///
+    /// Entries that don't look like object files (non-hex names, nested
+    /// directories, etc.) are skipped
+    pub fn loose_objects(&self) -> Result<Vec<LooseObject>> {
+        let mut objects = Vec::new();
+
+        if !self.dir.exists() {
+            return Ok(objects);
+        }
+
+        for fanout in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read objects directory {}", self.dir.display()))?
+        {
+            let fanout = fanout?;
+            let prefix = fanout.file_name().to_string_lossy().into_owned();
+            if !fanout.file_type()?.is_dir() || !is_hex_of_len(&prefix, 2) {
+                continue;
+            }
+
+            for entry in fs::read_dir(fanout.path())? {
+                let entry = entry?;
+                let rest = entry.file_name().to_string_lossy().into_owned();
+                if !entry.file_type()?.is_file() || !is_hex_of_len(&rest, rest.len()) {
+                    continue;
+                }
+
+                let metadata = entry.metadata()?;
+                objects.push(LooseObject {
+                    hash: format!("{}{}", prefix, rest),
+                    path: entry.path(),
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                });
+            }
+        }
+
+        objects.sort_by(|a, b| a.hash.cmp(&b.hash));
+        Ok(objects)
+    }
+
+    /// Lists the packfiles stored under `objects/pack`
+    pub fn packs(&self) -> Result<Vec<PathBuf>> {
+        let pack_dir = self.dir.join("pack");
+        let mut packs = Vec::new();
+
+        if !pack_dir.exists() {
+            return Ok(packs);
+        }
+
+        for entry in fs::read_dir(&pack_dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "pack") {
+                packs.push(path);
+            }
+        }
+
+        packs.sort();
+        Ok(packs)
+    }
+
+    /// Finds every object, loose or packed, whose hash starts with `prefix`
+    ///
+    /// Loose objects of the alternates count too.
+    ///
+    /// # Returns
+    ///
+    /// The matching hashes, sorted and without duplicates
+    pub fn find_by_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut matches = BTreeSet::new();
+
+        // Loose objects sharing the prefix all live in the same fan-out directory
+        if prefix.len() >= 2 {
+            let mut dirs = vec![self.dir.clone()];
+            dirs.extend(alternates(&self.dir)?);
+            for dir in dirs {
+                let fanout = dir.join(&prefix[..2]);
+                if !fanout.is_dir() {
+                    continue;
+                }
+                for entry in fs::read_dir(&fanout)? {
+                    let rest = entry?.file_name().to_string_lossy().into_owned();
+                    if is_hex_of_len(&rest, rest.len()) && rest.starts_with(&prefix[2..]) {
+                        matches.insert(format!("{}{}", &prefix[..2], rest));
+                    }
+                }
+            }
+        }
+
+        let packs = self.packs()?;
+        if !packs.is_empty() {
+            let format = ObjectFormat::for_objects_dir(&self.dir)?;
+            for pack_path in packs {
+                let file = fs::File::open(&pack_path)
+                    .with_context(|| format!("Failed to read pack {}", pack_path.display()))?;
+                let parse_error = || format!("Failed to parse pack {}", pack_path.display());
+                let reader = BufReader::new(file);
+                for entry in PackReader::new(reader, format).with_context(parse_error)? {
+                    let hash = entry.with_context(parse_error)?.hash;
+                    if hash.starts_with(&prefix) {
+                        matches.insert(hash);
+                    }
+                }
+            }
+        }
+
+        Ok(matches.into_iter().collect())
+    }
+}
+
+fn is_hex_of_len(s: &str, len: usize) -> bool {
+    !s.is_empty() && s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Reads only the header of a loose object without inflating its content
+///
+/// # Returns
+///
+/// A tuple of the object type and the uncompressed content size
+pub fn read_object_header(hash: &str, objects_dir: &Path) -> Result<(String, usize)> {
+    if hash.len() < 3 {
+        return Err(anyhow!("Invalid object hash: {}", hash));
+    }
+
+    let stored = replaced(hash, objects_dir)?;
+    let object_path = loose_object_path(&stored, objects_dir)?
+        .ok_or_else(|| VoxError::ObjectNotFound(hash.to_string()))?;
+    let file = open_object_file(&stored, &object_path)?;
+
+    // Inflate small chunks until the header terminator shows up
+    let mut decoder = ObjectDecoder::buffered(file)
+        .with_context(|| format!("Failed to decompress object {}", hash))?;
+    let mut header = Vec::new();
+    let mut chunk = [0u8; 64];
+    loop {
+        let read = decoder
+            .read(&mut chunk)
+            .with_context(|| format!("Failed to decompress object {}", hash))?;
+        if read == 0 {
+            return Err(anyhow!("Invalid object format: no null byte found"));
+        }
+        if let Some(null_pos) = chunk[..read].iter().position(|&b| b == 0) {
+            header.extend_from_slice(&chunk[..null_pos]);
+            break;
+        }
+        header.extend_from_slice(&chunk[..read]);
+    }
+
+    let header = std::str::from_utf8(&header)?;
+    let (object_type, size) = header
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("Invalid object header: {}", header))?;
+
+    Ok((object_type.to_string(), size.parse()?))
+}
+
+/// Reads a loose object and splits it into its type and content
+///
+/// # Arguments
+///
+/// * `hash` - The hash of the object to read
+/// * `objects_dir` - Path to the objects directory
+///
+/// # Returns
+///
+/// A tuple of the object type from the header (e.g. "commit") and the raw
+/// content following the header
+pub fn read_raw_object(hash: &str, objects_dir: &Path) -> Result<(String, Vec<u8>)> {
+    let object = ObjectStorage::at(objects_dir).read(hash)?;
+    Ok((object.object_type, object.data.to_vec()))
+}
+
+/// Reads a loose object from disk, see [`read_raw_object`]
+///
+/// With `core.verifyObjects` the content is re-hashed first, so a corrupt
+/// object fails here instead of spreading to whatever is built from it. A
+/// corrupt object is moved to `.vox/lost-found` and recovered if possible,
+/// see [`recover_corrupt_object`].
+fn read_loose_object(hash: &str, objects_dir: &Path) -> Result<(String, Vec<u8>)> {
+    if hash.len() < 3 {
+        return Err(anyhow!("Invalid object hash: {}", hash));
+    }
+
+    let object_path = loose_object_path(hash, objects_dir)?
+        .ok_or_else(|| VoxError::ObjectNotFound(hash.to_string()))?;
+    let data = match check_loose_object(hash, &object_path, objects_dir, verifies_loads())? {
+        Ok(data) => data,
+        Err(problem) => recover_corrupt_object(hash, objects_dir, &problem)?,
+    };
+
+    let null_pos = data
+        .iter()
+        .position(|&b| b == 0)
+        .context("Invalid object format: no null byte found")?;
+
+    let header = std::str::from_utf8(&data[..null_pos])?;
+    let object_type = header
+        .split(' ')
+        .next()
+        .unwrap_or(UNKNOWN_TYPE)
+        .to_string();
+
+    Ok((object_type, data[null_pos + 1..].to_vec()))
+}
+
+/// Reads the loose object `hash` again and fails if its content doesn't hash
+/// to `hash`, whatever `core.verifyObjects` says
+pub fn verify_loose_object(hash: &str, objects_dir: &Path) -> Result<()> {
+    let object_path = loose_object_path(hash, objects_dir)?
+        .with_context(|| format!("Failed to read object {}", hash))?;
+    check_loose_object(hash, &object_path, objects_dir, true)?
+        .map_err(|problem| anyhow!("Object {} is corrupt: {}", hash, problem))?;
+    Ok(())
+}
+
+/// Decompresses the loose object file at `path`, header included, checking
+/// with `verify` that its content hashes to `hash`
+///
+/// # Returns
+///
+/// The decompressed object, or what's wrong with it if it's corrupt. Files
+/// that can't be read at all are errors.
+fn check_loose_object(
+    hash: &str,
+    path: &Path,
+    objects_dir: &Path,
+    verify: bool,
+) -> Result<std::result::Result<Vec<u8>, String>> {
+    // An object that can't be decrypted may just need another key, it's
+    // not taken for corrupt
+    let compressed = read_object_file(hash, path)?;
+
+    let data = match decompress(&compressed) {
+        Ok(data) => data,
+        Err(error) => return Ok(Err(format!("it can't be decompressed ({})", error))),
+    };
+    let Some(null_pos) = data.iter().position(|&b| b == 0) else {
+        return Ok(Err("it has no header".to_string()));
+    };
+
+    if verify {
+        let header = String::from_utf8_lossy(&data[..null_pos]);
+        let object_type = header.split(' ').next().unwrap_or_default();
+        let format = ObjectFormat::for_objects_dir(objects_dir)?;
+        let actual = object_name(object_type, &data[null_pos + 1..], format);
+        if !actual.eq_ignore_ascii_case(hash) {
+            return Ok(Err(format!("its content hashes to {}", actual)));
+        }
+    }
+    Ok(Ok(data))
+}
+
+/// Name of the object of type `object_type` holding `content`
+///
+/// Blobs and commits are named after their content alone, trees and tags
+/// after their header as well.
+pub fn object_name(object_type: &str, content: &[u8], format: ObjectFormat) -> String {
+    match object_type {
+        OBJ_TYPE_TREE | OBJ_TYPE_TAG => {
+            let header = format!("{} {}\0", object_type, content.len());
+            format.digest(&[header.as_bytes(), content].concat()).to_hex()
+        }
+        _ => format.digest(content).to_hex(),
+    }
+}
+
+/// Moves the corrupt object `hash` to `.vox/lost-found` and stores a good
+/// copy of it from the alternates, the packs or the remotes
+///
+/// # Returns
+///
+/// The recovered object, header included
+///
+/// # Errors
+///
+/// Fails naming the refs whose history reaches the object when there's no
+/// good copy anywhere
+fn recover_corrupt_object(hash: &str, objects_dir: &Path, problem: &str) -> Result<Vec<u8>> {
+    let moved = quarantine(hash, objects_dir)?;
+    if let Some(source) = recover(hash, objects_dir)?
+        && let Some(path) = loose_object_path(hash, objects_dir)?
+        && let Ok(data) = check_loose_object(hash, &path, objects_dir, true)?
+    {
+        eprintln!("warning: object {} was corrupt, {}; recovered from {}", hash, problem, source);
+        return Ok(data);
+    }
+
+    let mut problem = problem.to_string();
+    if let Some(moved) = moved {
+        problem.push_str(&format!("\nIt was moved to {}", moved.display()));
+    }
+    let refs = match objects_dir.parent() {
+        Some(vox_dir) if !is_recovering() => affected_refs(hash, vox_dir, objects_dir)?,
+        _ => Vec::new(),
+    };
+    if !refs.is_empty() {
+        problem.push_str(&format!("\nRefs reaching it: {}", refs.join(", ")));
+    }
+    bail!(VoxError::CorruptObject {
+        hash: hash.to_string(),
+        problem
+    })
 }
 
 pub trait Storable {
@@ -102,11 +583,7 @@ impl VoxObject for Object {
             Object::Tag(tag) => tag.hash(),
             Object::Tree(tree) => tree.hash(),
             Object::ChangeSet(changes) => changes.hash(),
-            Object::Unknown(data) => {
-                let mut hasher = Sha1::new();
-                hasher.update(data.as_bytes());
-                Ok(format!("{:x}", hasher.finalize()))
-            }
+            Object::Unknown(data) => Ok(ObjectFormat::current()?.digest(data.as_bytes()).to_hex()),
         }
     }
 
@@ -168,3 +645,59 @@ impl FromStr for Object {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_alternates() -> Result<()> {
+        let shared = tempdir()?;
+        let clone = tempdir()?;
+        let hash = Blob {
+            data: b"shared".to_vec(),
+        }
+        .save(shared.path())?;
+        assert!(read_raw_object(&hash, clone.path()).is_err());
+
+        write_alternates(clone.path(), &[shared.path().to_path_buf()])?;
+        assert_eq!(alternates(clone.path())?, vec![fs::canonicalize(shared.path())?]);
+        assert_eq!(read_raw_object(&hash, clone.path())?.1, b"shared");
+        assert_eq!(ObjectStorage::at(clone.path()).find_by_prefix(&hash[..6])?, vec![hash.clone()]);
+
+        // Objects the alternates have aren't stored again
+        Blob {
+            data: b"shared".to_vec(),
+        }
+        .save(clone.path())?;
+        assert!(!clone.path().join(&hash[..2]).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_loose_object() -> Result<()> {
+        let dir = tempdir()?;
+        let hash = Blob {
+            data: b"original".to_vec(),
+        }
+        .save(dir.path())?;
+        verify_loose_object(&hash, dir.path())?;
+        // Trees are named after their header too
+        let tree = Tree { entries: Vec::new() }.save(dir.path())?;
+        verify_loose_object(&tree, dir.path())?;
+
+        // Put another object's content under the same name
+        let other = Blob {
+            data: b"tampered".to_vec(),
+        }
+        .save(dir.path())?;
+        let path = dir.path().join(&hash[..2]).join(&hash[2..]);
+        fs::remove_file(&path)?;
+        fs::copy(dir.path().join(&other[..2]).join(&other[2..]), &path)?;
+
+        let error = verify_loose_object(&hash, dir.path()).unwrap_err();
+        assert!(error.to_string().contains(&other));
+        Ok(())
+    }
+}
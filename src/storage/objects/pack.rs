@@ -1,12 +1,16 @@
-use crate::storage::objects::{Blob, Commit, Object, Tag, Tree, VoxObject};
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::{
+    object_name, pack_compression, read_raw_object, Blob, Commit, Object, Storable, Tag, Tree,
+    VoxObject,
+};
 use crate::storage::utils::{OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TAG, OBJ_TYPE_TREE};
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use flate2::bufread::ZlibDecoder;
-use flate2::{write::ZlibEncoder, Compression};
-use sha1::{Digest, Sha1};
+use flate2::write::ZlibEncoder;
 use std::collections::HashMap;
-use std::io::{Cursor, Read, Write};
+use std::io::{self, BufReader, Cursor, Read, Write};
+use std::path::Path;
 
 use super::delta::apply_delta;
 
@@ -19,6 +23,8 @@ pub struct Packfile {
     pub objects: Vec<PackObject>,
     /// Index mapping object hashes to their locations in the packfile
     pub index: HashMap<String, ObjectLocation>,
+    /// Hash algorithm used for the index and delta base references
+    pub format: ObjectFormat,
 }
 
 /// Metadata describing an object's physical location within a packfile
@@ -39,13 +45,26 @@ pub enum PackObject {
     Base(Vec<u8>, ObjectType),
     /// A delta-compressed object referencing a base object
     Delta {
-        /// SHA-1 hash of the base object this delta applies to
+        /// Hash of the base object this delta applies to
         base_hash: String,
         /// Delta instructions needed to reconstruct the object
         data: Vec<u8>,
     },
 }
 
+/// Returns the type and content of a pack object, applying deltas to their
+/// base read from `objects_dir`
+fn resolve_object(object: PackObject, objects_dir: &Path) -> Result<(ObjectType, Vec<u8>)> {
+    match object {
+        PackObject::Base(data, obj_type) => Ok((obj_type, data)),
+        PackObject::Delta { base_hash, data } => {
+            let (base_type, base) = read_raw_object(&base_hash, objects_dir)
+                .with_context(|| format!("Missing base object {}", base_hash))?;
+            Ok((ObjectType::from_name(&base_type)?, apply_delta(&base, &data)?))
+        }
+    }
+}
+
 /// Enum of possible object types in the Vox storage system
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -57,12 +76,166 @@ pub enum ObjectType {
     DeltaRef = 7,
 }
 
+impl ObjectType {
+    fn from_code(code: u8) -> Result<Self> {
+        match code {
+            1 => Ok(ObjectType::Commit),
+            2 => Ok(ObjectType::Tree),
+            3 => Ok(ObjectType::Blob),
+            4 => Ok(ObjectType::Tag),
+            7 => Ok(ObjectType::DeltaRef),
+            _ => bail!("Invalid object type"),
+        }
+    }
+
+    /// Name of the type in loose object headers
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectType::Commit => OBJ_TYPE_COMMIT,
+            ObjectType::Tree => OBJ_TYPE_TREE,
+            ObjectType::Blob => OBJ_TYPE_BLOB,
+            ObjectType::Tag => OBJ_TYPE_TAG,
+            ObjectType::DeltaRef => "delta",
+        }
+    }
+
+    /// Returns the type named `name` in loose object headers
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            OBJ_TYPE_COMMIT => Ok(ObjectType::Commit),
+            OBJ_TYPE_TREE => Ok(ObjectType::Tree),
+            OBJ_TYPE_BLOB => Ok(ObjectType::Blob),
+            OBJ_TYPE_TAG => Ok(ObjectType::Tag),
+            _ => bail!("Unsupported object type: {}", name),
+        }
+    }
+}
+
+/// An object read from a pack stream
+#[derive(Debug)]
+pub struct PackEntry {
+    /// Hash the object is indexed under
+    pub hash: String,
+    /// Where the object is in the pack
+    pub location: ObjectLocation,
+    /// The decompressed object
+    pub object: PackObject,
+}
+
+/// Reads the objects of a pack one at a time from any reader
+///
+/// Unlike [`Packfile::deserialize`], the pack never has to be in memory as a
+/// whole: only the object being read is buffered.
+pub struct PackReader<R: Read> {
+    reader: R,
+    format: ObjectFormat,
+    remaining: u32,
+    offset: u64,
+}
+
+impl<R: Read> PackReader<R> {
+    /// Reads the pack header from `reader`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream doesn't start with a vox pack header
+    pub fn new(mut reader: R, format: ObjectFormat) -> Result<Self> {
+        let mut magic = [0u8; 7];
+        reader.read_exact(&mut magic).context("Failed to read pack header")?;
+        if &magic != b"VOXPACK" {
+            bail!("Invalid pack format");
+        }
+        let remaining = reader.read_u32::<BigEndian>()?;
+
+        Ok(Self {
+            reader,
+            format,
+            remaining,
+            offset: 12, // Header size (7 magic + 4 byte count)
+        })
+    }
+
+    /// Returns how many objects are left to read
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    fn read_entry(&mut self) -> Result<PackEntry> {
+        let first_byte = self.reader.read_u8()?;
+        let type_code = (first_byte >> 4) & 0x07;
+        let compressed_size = self.reader.read_u24::<BigEndian>()?;
+        let obj_type = ObjectType::from_code(type_code)?;
+
+        // Only this object's compressed bytes are taken from the stream
+        let mut decoder =
+            ZlibDecoder::new(BufReader::new((&mut self.reader).take(compressed_size as u64)));
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed)?;
+        // Skip whatever the decoder didn't consume to stay aligned on the next object
+        io::copy(&mut decoder.into_inner(), &mut io::sink())?;
+
+        let (object, hash) = match obj_type {
+            ObjectType::DeltaRef => {
+                // Delta objects store the raw base hash in their first bytes
+                let hash_len = self.format.raw_len();
+                if decompressed.len() < hash_len {
+                    bail!("Delta object too short for base hash");
+                }
+                let base_hash = ObjectId::from_bytes(self.format, &decompressed[..hash_len])?;
+                let data = decompressed[hash_len..].to_vec();
+                let hash = self.format.digest(&data).to_hex();
+                let base_hash = base_hash.to_hex();
+                (PackObject::Delta { base_hash, data }, hash)
+            }
+            _ => {
+                let hash = self.format.digest(&decompressed).to_hex();
+                (PackObject::Base(decompressed, obj_type), hash)
+            }
+        };
+
+        let location = ObjectLocation {
+            offset: self.offset,
+            size: compressed_size,
+            type_code,
+        };
+        self.offset += 4 + compressed_size as u64; // 1 byte type + 3 byte size
+        Ok(PackEntry {
+            hash,
+            location,
+            object,
+        })
+    }
+}
+
+impl<R: Read> Iterator for PackReader<R> {
+    type Item = Result<PackEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let entry = self.read_entry();
+        // A truncated or corrupt stream can't be resynchronized
+        if entry.is_err() {
+            self.remaining = 0;
+        }
+        Some(entry)
+    }
+}
+
 impl Packfile {
-    /// Creates a new empty packfile
+    /// Creates a new empty packfile for SHA-1 repositories
     pub fn new() -> Self {
+        Self::with_format(ObjectFormat::Sha1)
+    }
+
+    /// Creates a new empty packfile using the given object format
+    pub fn with_format(format: ObjectFormat) -> Self {
         Packfile {
             objects: Vec::new(),
             index: HashMap::new(),
+            format,
         }
     }
 
@@ -98,8 +271,9 @@ impl Packfile {
                 }
             };
 
-            // Compress the object data
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            // Compress the object data, always with zlib whatever the codec
+            // of the loose objects, so a pack reads the same in any repository
+            let mut encoder = ZlibEncoder::new(Vec::new(), pack_compression());
             encoder.write_all(&content)?;
             let compressed = encoder.finish()?;
 
@@ -109,9 +283,7 @@ impl Packfile {
             header.write_u24::<BigEndian>(size)?;
 
             // Compute object hash
-            let mut hasher = Sha1::new();
-            hasher.update(&content);
-            let hash = format!("{:x}", hasher.finalize());
+            let hash = self.format.digest(&content).to_hex();
 
             // Write object to packfile
             buffer.write_all(&header)?;
@@ -134,76 +306,85 @@ impl Packfile {
     }
 
     /// Deserializes a packfile from bytes
-    pub fn deserialize(data: &[u8]) -> Result<Self> {
-        let mut cursor = Cursor::new(data);
-        let mut magic = [0u8; 7];
-        cursor.read_exact(&mut magic)?;
-
-        if &magic != b"VOXPACK" {
-            bail!("Invalid pack format");
+    ///
+    /// Delta base references and index hashes are read using `format`
+    pub fn deserialize(data: &[u8], format: ObjectFormat) -> Result<Self> {
+        let mut pack = Packfile::with_format(format);
+        for entry in PackReader::new(Cursor::new(data), format)? {
+            let entry = entry?;
+            pack.index.insert(entry.hash, entry.location);
+            pack.objects.push(entry.object);
         }
+        Ok(pack)
+    }
 
-        let object_count = cursor.read_u32::<BigEndian>()?;
-        let mut pack = Packfile::new();
-        let mut offset = 12;
-
-        for _ in 0..object_count {
-            let first_byte = cursor.read_u8()?;
-            let type_code = (first_byte >> 4) & 0x07;
-            let compressed_size = cursor.read_u24::<BigEndian>()?;
-
-            // Read compressed data
-            let mut compressed = vec![0u8; compressed_size as usize];
-            cursor.read_exact(&mut compressed)?;
-
-            // Decompress the object
-            let mut decoder = ZlibDecoder::new(&compressed[..]);
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed)?;
-
-            let obj_type = match type_code {
-                1 => ObjectType::Commit,
-                2 => ObjectType::Tree,
-                3 => ObjectType::Blob,
-                4 => ObjectType::Tag,
-                7 => ObjectType::DeltaRef,
-                _ => bail!("Invalid object type"),
-            };
+    /// Writes every object of a pack stream to `objects_dir` as a loose
+    /// object, as soon as it's read
+    ///
+    /// Only one object is held in memory at a time, so unpacking a pack that
+    /// doesn't fit in memory works. Delta bases are read back from
+    /// `objects_dir`, they must either come earlier in the stream or already
+    /// be stored.
+    ///
+    /// # Returns
+    ///
+    /// The hashes of the stored objects, in pack order
+    pub fn unpack<R: Read>(
+        reader: R,
+        objects_dir: &Path,
+        format: ObjectFormat,
+    ) -> Result<Vec<String>> {
+        let parser = Packfile::with_format(format);
+        let mut hashes = Vec::new();
+
+        for entry in PackReader::new(reader, format)? {
+            let (obj_type, data) = resolve_object(entry?.object, objects_dir)?;
+            hashes.push(parser.store_object(obj_type, &data, objects_dir)?);
+        }
+        Ok(hashes)
+    }
 
-            let (obj, hash) = match obj_type {
-                ObjectType::DeltaRef => {
-                    // Delta objects store base hash in first 20 bytes
-                    let mut base_hash = [0u8; 20];
-                    base_hash.copy_from_slice(&decompressed[..20]);
-                    let data = decompressed[20..].to_vec();
-                    (
-                        PackObject::Delta {
-                            base_hash: hex::encode(base_hash),
-                            data: data.clone(),
-                        },
-                        hex::encode(Sha1::digest(&data)),
-                    )
-                }
-                _ => {
-                    let hash = hex::encode(Sha1::digest(&decompressed));
-                    (PackObject::Base(decompressed, obj_type), hash)
-                }
+    /// Stores only the object `hash` of the pack read from `reader`, as a
+    /// loose object
+    ///
+    /// # Returns
+    ///
+    /// false if the pack doesn't have the object
+    pub fn unpack_object<R: Read>(
+        reader: R,
+        hash: &str,
+        objects_dir: &Path,
+        format: ObjectFormat,
+    ) -> Result<bool> {
+        let parser = Packfile::with_format(format);
+        for entry in PackReader::new(reader, format)? {
+            // Objects deltified against a missing base can't be the one
+            let Ok((obj_type, data)) = resolve_object(entry?.object, objects_dir) else {
+                continue;
             };
-
-            pack.objects.push(obj);
-            pack.index.insert(
-                hash,
-                ObjectLocation {
-                    offset,
-                    size: compressed_size,
-                    type_code,
-                },
-            );
-
-            offset += 4 + compressed_size as u64; // 1 byte type + 3 byte size
+            if object_name(obj_type.name(), &data, format) == hash {
+                parser.store_object(obj_type, &data, objects_dir)?;
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        Ok(pack)
+    /// Saves an object of the pack as a loose object, returning its hash
+    fn store_object(
+        &self,
+        obj_type: ObjectType,
+        data: &[u8],
+        objects_dir: &Path,
+    ) -> Result<String> {
+        // Each type hashes its loose form its own way, so let it save itself
+        match self.parse_object(obj_type, data)? {
+            Object::Blob(blob) => blob.save(objects_dir),
+            Object::Commit(commit) => commit.save(objects_dir),
+            Object::Tree(tree) => tree.save(objects_dir),
+            Object::Tag(tag) => tag.save(objects_dir),
+            _ => bail!("Unsupported object type"),
+        }
     }
 
     /// Applies delta compression to reconstruct full objects
@@ -212,7 +393,7 @@ impl Packfile {
         for obj in &self.objects {
             match obj {
                 PackObject::Base(data, obj_type) => {
-                    let obj = Self::parse_object(*obj_type, data)?;
+                    let obj = self.parse_object(*obj_type, data)?;
                     results.push(obj);
                 }
                 PackObject::Delta { base_hash, data } => {
@@ -222,7 +403,7 @@ impl Packfile {
 
                     let reconstructed = apply_delta(base_data, data)?;
                     let obj_type = Self::detect_type(&reconstructed)?;
-                    let obj = Self::parse_object(obj_type, &reconstructed)?;
+                    let obj = self.parse_object(obj_type, &reconstructed)?;
                     results.push(obj);
                 }
             }
@@ -231,14 +412,14 @@ impl Packfile {
     }
 
     /// Parses raw object data into the appropriate Object type
-    pub fn parse_object(obj_type: ObjectType, data: &[u8]) -> Result<Object> {
+    pub fn parse_object(&self, obj_type: ObjectType, data: &[u8]) -> Result<Object> {
         match obj_type {
             ObjectType::Commit => {
                 let commit = Commit::parse(&String::from_utf8(data.to_vec())?);
                 Ok(Object::Commit(commit?))
             }
             ObjectType::Tree => {
-                let tree = Tree::parse(data)?;
+                let tree = Tree::parse(data, self.format)?;
                 Ok(Object::Tree(tree))
             }
             ObjectType::Blob => Ok(Object::Blob(Blob {
@@ -265,3 +446,44 @@ impl Packfile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::Loadable;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stream_and_unpack() -> Result<()> {
+        let mut pack = Packfile::new();
+        let blob = Blob {
+            data: b"streamed content\n".to_vec(),
+        };
+        pack.add_object(&blob)?;
+        let commit = Commit::parse(&format!(
+            "tree {}\nauthor A <a@b.c> 0 +0000\ncommitter A <a@b.c> 0 +0000\n\nmsg\n",
+            "ab".repeat(20)
+        ))?;
+        pack.add_object(&commit)?;
+        let data = pack.serialize()?;
+
+        let reader = PackReader::new(Cursor::new(&data), ObjectFormat::Sha1)?;
+        assert_eq!(reader.remaining(), 2);
+        let offsets: Vec<u64> = reader.map(|entry| entry.unwrap().location.offset).collect();
+        assert_eq!(offsets[0], 12);
+        assert_eq!(offsets.len(), 2);
+
+        let dir = tempdir()?;
+        let hashes = Packfile::unpack(Cursor::new(&data), dir.path(), ObjectFormat::Sha1)?;
+        assert_eq!(hashes.len(), 2);
+        let (blob_type, content) = read_raw_object(&hashes[0], dir.path())?;
+        assert_eq!((blob_type.as_str(), content), (OBJ_TYPE_BLOB, blob.data));
+        let loaded = Commit::load(&hashes[1], dir.path())?;
+        assert_eq!(loaded.message, commit.message);
+
+        // A truncated stream fails instead of yielding a partial object
+        let truncated = Cursor::new(&data[..data.len() - 4]);
+        assert!(Packfile::unpack(truncated, dir.path(), ObjectFormat::Sha1).is_err());
+        Ok(())
+    }
+}
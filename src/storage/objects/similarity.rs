@@ -0,0 +1,73 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Lines of a file, hashed, with the number of bytes each line accounts for
+///
+/// Two fingerprints are compared by the bytes of the lines they share, which
+/// tolerates moved lines and works on binary content as well.
+#[derive(Debug, Default)]
+pub struct Fingerprint {
+    size: usize,
+    lines: HashMap<u64, usize>,
+}
+
+impl Fingerprint {
+    pub fn new(content: &[u8]) -> Self {
+        let mut lines = HashMap::new();
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            let mut hasher = DefaultHasher::new();
+            line.hash(&mut hasher);
+            *lines.entry(hasher.finish()).or_default() += line.len();
+        }
+        Fingerprint {
+            size: content.len(),
+            lines,
+        }
+    }
+
+    /// Returns how similar two files are, in percent of the larger one
+    ///
+    /// Returns 0 without comparing the lines when the sizes alone rule out
+    /// a similarity of at least `threshold`.
+    pub fn similarity(&self, other: &Fingerprint, threshold: u8) -> u8 {
+        let (smaller, larger) = match self.size <= other.size {
+            true => (self.size, other.size),
+            false => (other.size, self.size),
+        };
+        if larger == 0 {
+            return 100;
+        }
+        if smaller * 100 < larger * threshold as usize {
+            return 0;
+        }
+
+        let common: usize = self
+            .lines
+            .iter()
+            .filter_map(|(line, &bytes)| other.lines.get(line).map(|&other| bytes.min(other)))
+            .sum();
+        (common * 100 / larger) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        let original = Fingerprint::new(b"one\ntwo\nthree\nfour\n");
+        let edited = Fingerprint::new(b"one\ntwo\nTHREE\nfour\n");
+        let reordered = Fingerprint::new(b"four\nthree\ntwo\none\n");
+        let unrelated = Fingerprint::new(b"something else entirely\n");
+
+        assert_eq!(original.similarity(&original, 50), 100);
+        assert_eq!(original.similarity(&reordered, 50), 100);
+        assert_eq!(original.similarity(&edited, 50), 68);
+        assert_eq!(original.similarity(&unrelated, 0), 0);
+        // Too different in size to reach the threshold
+        assert_eq!(original.similarity(&Fingerprint::new(b"one\n"), 50), 0);
+        assert_eq!(Fingerprint::new(b"").similarity(&Fingerprint::new(b""), 50), 100);
+    }
+}
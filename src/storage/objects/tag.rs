@@ -1,20 +1,19 @@
+use crate::storage::encryption::{read_object_file, write_object_file};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::compression::{compress, decompress};
 use crate::storage::objects::{Storable, VoxObject};
 use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_TAG};
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, FixedOffset, Utc};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
 use regex::Regex;
-use sha1::{Digest, Sha1};
 use std::fs;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
 
 /// Represents a tag object that points to a specific commit
 #[derive(Debug)]
 pub struct Tag {
-    /// The SHA-1 hash of the object being tagged (usually a commit)
+    /// The hash of the object being tagged (usually a commit)
     pub object: String,
     /// The type of object being tagged (typically "commit")
     pub object_type: String,
@@ -24,8 +23,16 @@ pub struct Tag {
     pub tagger: (String, String, DateTime<Utc>),
     /// The annotation message for the tag
     pub message: String,
+    /// Armored detached signature of the tag, appended to the message
+    pub signature: Option<String>,
 }
 
+/// First lines of the signatures that can follow a tag message
+const SIGNATURE_HEADERS: [&str; 2] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+];
+
 impl Tag {
     /// Parses a tag object from raw string data
     ///
@@ -33,13 +40,14 @@ impl Tag {
     /// * `data` - The raw tag object content
     ///
     /// # Format
-    /// ```
+    /// ```text
     /// object <hash>
     /// type <object-type>
     /// tag <name>
     /// tagger <name> <email> <timestamp> <timezone>
     ///
     /// <message>
+    /// [<signature>]
     /// ```
     pub fn parse(data: &str) -> Result<Self> {
         let lines = data.lines();
@@ -76,15 +84,55 @@ impl Tag {
             }
         }
 
+        // A signature starts at the first line that opens an armored block
+        let signature_start = SIGNATURE_HEADERS
+            .iter()
+            .filter_map(|header| {
+                message
+                    .match_indices(header)
+                    .map(|(pos, _)| pos)
+                    .find(|&pos| pos == 0 || message[..pos].ends_with('\n'))
+            })
+            .min();
+        let signature = signature_start.map(|pos| message.split_off(pos).trim_end().to_string());
+
         Ok(Tag {
             object: object.ok_or_else(|| anyhow!("Missing object in tag"))?,
             object_type: object_type.ok_or_else(|| anyhow!("Missing object type in tag"))?,
             tag: tag_name.ok_or_else(|| anyhow!("Missing tag name"))?,
             tagger: tagger.ok_or_else(|| anyhow!("Missing tagger"))?,
             message: message.trim().to_string(),
+            signature,
         })
     }
 
+    /// Returns the bytes a signature covers, the tag without its signature
+    pub fn payload(&self) -> Result<Vec<u8>> {
+        self.encode(false)
+    }
+
+    fn encode(&self, include_signature: bool) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        writeln!(content, "object {}", self.object)?;
+        writeln!(content, "type {}", self.object_type)?;
+        writeln!(content, "tag {}", self.tag)?;
+        writeln!(
+            content,
+            "tagger {} <{}> {} {}",
+            self.tagger.0,
+            self.tagger.1,
+            self.tagger.2.timestamp(),
+            self.tagger.2.format("%z")
+        )?;
+        writeln!(content)?; // Empty line before message
+        write!(content, "{}", self.message)?;
+
+        if include_signature && let Some(signature) = &self.signature {
+            write!(content, "\n{}\n", signature)?;
+        }
+        Ok(content)
+    }
+
     /// Parses the tagger identity line into components
     ///
     /// # Format
@@ -111,19 +159,17 @@ impl Tag {
     /// Loads a tag object from the object database
     ///
     /// # Arguments
-    /// * `hash` - The SHA-1 hash of the tag object
+    /// * `hash` - The hash of the tag object
     /// * `objects_dir` - Path to the objects directory
     pub fn load(hash: &str, objects_dir: &Path) -> Result<Self> {
         let dir_path = objects_dir.join(&hash[..2]);
         let object_path = dir_path.join(&hash[2..]);
 
         // Read and decompress the tag object
-        let compressed_data = fs::read(&object_path)
+        let compressed_data = read_object_file(hash, &object_path)
             .with_context(|| format!("Failed to read tag object at {}", object_path.display()))?;
 
-        let mut decoder = ZlibDecoder::new(&compressed_data[..]);
-        let mut decompressed_data = Vec::new();
-        decoder.read_to_end(&mut decompressed_data)?;
+        let decompressed_data = decompress(&compressed_data)?;
 
         // Skip the header (everything before the first null byte)
         let null_pos = decompressed_data
@@ -144,30 +190,19 @@ impl VoxObject for Tag {
         OBJ_TYPE_TAG
     }
 
-    /// Serializes the tag object to bytes
+    /// Serializes the tag object to bytes, the signature (if any) following
+    /// the message
     fn serialize(&self) -> Result<Vec<u8>> {
-        let mut content = Vec::new();
-        writeln!(content, "object {}", self.object)?;
-        writeln!(content, "type {}", self.object_type)?;
-        writeln!(content, "tag {}", self.tag)?;
-        writeln!(
-            content,
-            "tagger {} <{}> {} {}",
-            self.tagger.0,
-            self.tagger.1,
-            self.tagger.2.timestamp(),
-            self.tagger.2.format("%z")
-        )?;
-        writeln!(content)?; // Empty line before message
-        write!(content, "{}", self.message)?;
-        Ok(content)
+        self.encode(true)
     }
 
-    /// Computes the SHA-1 hash of the serialized tag
+    /// Computes the hash of the serialized tag, with its header, using the
+    /// repository's object format
     fn hash(&self) -> Result<String> {
-        let mut hasher = Sha1::new();
-        hasher.update(&self.serialize()?);
-        Ok(format!("{:x}", hasher.finalize()))
+        let content = self.serialize()?;
+        let header = format!("{} {}\0", self.object_type(), content.len());
+        let full_content = [header.as_bytes(), &content].concat();
+        Ok(ObjectFormat::current()?.digest(&full_content).to_hex())
     }
 
     /// Returns the storage path for this tag in the objects directory
@@ -185,24 +220,62 @@ impl VoxObject for Tag {
 impl Storable for Tag {
     /// Saves the tag object to the object database
     fn save(&self, objects_dir: &Path) -> Result<String> {
-        let hash = self.hash()?;
         let content = self.serialize()?;
 
-        // Create header with object type and size
+        // Create header with object type and size, covered by the hash like
+        // for every other object type
         let header = format!("{} {}\0", self.object_type(), content.len());
         let full_content = [header.as_bytes(), &content].concat();
+        let hash = ObjectFormat::for_objects_dir(objects_dir)?
+            .digest(&full_content)
+            .to_hex();
 
         // Compress the data
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&full_content)?;
-        let compressed_data = encoder.finish()?;
+        let compressed_data = compress(&full_content, objects_dir)?;
 
         // Write to object database
         let dir_path = objects_dir.join(&hash[..2]);
         fs::create_dir_all(&dir_path)?;
-        let object_path = dir_path.join(&hash[2..]);
-        fs::write(&object_path, compressed_data)?;
+        write_object_file(&hash, &dir_path.join(&hash[2..]), &compressed_data)?;
 
         Ok(hash)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_tag_roundtrip() -> Result<()> {
+        let data = "object abc\ntype commit\ntag v1.0\ntagger A <a@x.io> 1600000000 +0000\n\n\
+                    Release 1.0\n-----BEGIN SSH SIGNATURE-----\nU1NI\n-----END SSH SIGNATURE-----\n";
+
+        let tag = Tag::parse(data)?;
+        assert_eq!(tag.message, "Release 1.0");
+        assert_eq!(
+            tag.signature.as_deref(),
+            Some("-----BEGIN SSH SIGNATURE-----\nU1NI\n-----END SSH SIGNATURE-----")
+        );
+        assert_eq!(tag.serialize()?, data.as_bytes());
+        assert!(tag.payload()?.ends_with(b"\n\nRelease 1.0"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_hash_covers_header() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let tag = Tag::parse(
+            "object abc\ntype commit\ntag v1.0\ntagger A <a@x.io> 1600000000 +0000\n\nv1",
+        )?;
+
+        let hash = tag.save(dir.path())?;
+        let compressed = fs::read(dir.path().join(&hash[..2]).join(&hash[2..]))?;
+        let stored = decompress(&compressed)?;
+
+        assert!(stored.starts_with(b"tag "));
+        assert_eq!(ObjectFormat::Sha1.digest(&stored).to_hex(), hash);
+        assert_eq!(Tag::load(&hash, dir.path())?.message, "v1");
+        Ok(())
+    }
+}
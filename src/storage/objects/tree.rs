@@ -1,17 +1,27 @@
 use super::blob::Blob;
 use super::change::{ChangeSet, ChangeType};
 use crate::commands::diff::diff::text_diff;
-use crate::storage::objects::{change::DiffSummary, Loadable, Storable, VoxObject};
-use crate::storage::utils::{OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_TREE, PERM_DIR, PERM_FILE};
+use crate::storage::attributes::diff_as_binary;
+use crate::storage::encryption::write_object_file;
+use crate::storage::hash::{ObjectFormat, ObjectId};
+use crate::storage::objects::similarity::Fingerprint;
+use crate::storage::objects::{
+    change::DiffSummary, compression::compress, loose_object_path, read_raw_object, Loadable,
+    ObjectStorage, Storable, VoxObject,
+};
+use crate::storage::patch::is_binary;
+use crate::storage::platform;
+use crate::storage::submodules::{is_nested_repository, submodule_head};
+use crate::storage::utils::{
+    OBJ_DIR, OBJ_TYPE_BLOB, OBJ_TYPE_COMMIT, OBJ_TYPE_TREE, PERM_DIR, PERM_EXEC, PERM_FILE,
+    PERM_GITLINK, PERM_SYMLINK,
+};
 use anyhow::{bail, Context, Result};
 use byteorder::ReadBytesExt;
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
-use sha1::{Digest, Sha1};
+use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Read};
 use std::path::{Path, PathBuf};
 
 /// Represents a single entry in a tree object
@@ -21,12 +31,80 @@ pub struct TreeEntry {
     pub mode: String,
     /// Type of the object
     pub object_type: String,
-    /// SHA-1 hash of the referenced object
+    /// Hash of the referenced object
     pub object_hash: String,
     /// Name of the file or directory
     pub name: String,
 }
 
+/// Similarity, in percent, a file needs to count as renamed or copied
+/// unless configured otherwise, as in git
+pub const DEFAULT_SIMILARITY: u8 = 50;
+
+/// Most deleted and added files whose contents are compared to find renames,
+/// beyond that only files with identical content are paired
+pub const RENAME_LIMIT: usize = 1000;
+
+/// How [`Tree::compare_trees_with`] detects renamed and copied files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenameDetection {
+    /// Similarity, in percent, for an added file to be a deleted one renamed
+    pub threshold: u8,
+    /// Similarity for an added file to be a copy of a changed one, copies
+    /// aren't detected when `None`
+    pub copies: Option<u8>,
+}
+
+impl Default for RenameDetection {
+    fn default() -> Self {
+        RenameDetection {
+            threshold: DEFAULT_SIMILARITY,
+            copies: None,
+        }
+    }
+}
+
+/// Paths of changed files with the hash of one of their versions
+type FileHashes = Vec<(PathBuf, String)>;
+
+/// Fingerprints of blobs, computed once for all the pairs they're part of
+struct FingerprintCache<'a> {
+    objects_dir: &'a Path,
+    /// `None` for objects that aren't blobs, such as submodule commits
+    fingerprints: HashMap<String, Option<Fingerprint>>,
+}
+
+impl<'a> FingerprintCache<'a> {
+    fn new(objects_dir: &'a Path) -> Self {
+        FingerprintCache {
+            objects_dir,
+            fingerprints: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, hash: &str) {
+        if !self.fingerprints.contains_key(hash) {
+            let fingerprint = match read_raw_object(hash, self.objects_dir) {
+                Ok((object_type, data)) if object_type == OBJ_TYPE_BLOB => {
+                    Some(Fingerprint::new(&data))
+                }
+                _ => None,
+            };
+            self.fingerprints.insert(hash.to_string(), fingerprint);
+        }
+    }
+
+    /// Similarity of two blobs, 0 if either can't be read as a blob
+    fn similarity(&mut self, old_hash: &str, new_hash: &str, threshold: u8) -> u8 {
+        self.load(old_hash);
+        self.load(new_hash);
+        match (&self.fingerprints[old_hash], &self.fingerprints[new_hash]) {
+            (Some(old), Some(new)) => old.similarity(new, threshold),
+            _ => 0,
+        }
+    }
+}
+
 /// Represents a directory tree
 ///
 /// A Tree object contains a list of entries representing files and subdirectories
@@ -54,10 +132,21 @@ impl Tree {
     ///
     /// Returns a [`ChangeSet`] containing all changes or an error if comparison fails
     pub fn compare_trees(from: &Tree, to: &Tree, objects_dir: &Path) -> Result<ChangeSet> {
+        Self::compare_trees_with(from, to, objects_dir, &RenameDetection::default())
+    }
+
+    /// Compares two trees like [`Tree::compare_trees`], detecting renames and
+    /// copies as configured by `detection`
+    pub fn compare_trees_with(
+        from: &Tree,
+        to: &Tree,
+        objects_dir: &Path,
+        detection: &RenameDetection,
+    ) -> Result<ChangeSet> {
         let mut changes = ChangeSet::new(from.hash().ok(), to.hash().ok());
         let all_paths = Self::collect_all_paths(from, to);
         Self::compare_entries(&mut changes, from, to, &all_paths, objects_dir)?;
-        Self::detect_renames(&mut changes, objects_dir)?;
+        Self::detect_renames(&mut changes, objects_dir, detection)?;
         Ok(changes)
     }
 
@@ -158,13 +247,20 @@ impl Tree {
         match (from_entry, to_entry) {
             (None, Some(to)) => Self::handle_added(changes, path, to),
             (Some(from), None) => Self::handle_deleted(changes, path, from),
-            (Some(from), Some(to)) if from.object_hash != to.object_hash => {
+            (Some(from), Some(to))
+                if from.object_hash != to.object_hash || Self::mode_changed(from, to) =>
+            {
                 Self::handle_modified(changes, path, from, to, objects_dir)
             }
             _ => Ok(()),
         }
     }
 
+    /// Returns true if a file's mode changed, e.g. it became executable
+    fn mode_changed(from: &TreeEntry, to: &TreeEntry) -> bool {
+        from.object_type == OBJ_TYPE_BLOB && to.object_type == OBJ_TYPE_BLOB && from.mode != to.mode
+    }
+
     /// Records an added file in the ChangeSet
     ///
     /// # Arguments
@@ -198,7 +294,7 @@ impl Tree {
 
     /// Records a modified file in the ChangeSet
     ///
-    /// For blob files, calculates detailed diff summary including:
+    /// For blob files whose content changed, calculates detailed diff summary including:
     /// - Number of insertions
     /// - Number of deletions
     /// - Unified diff format text
@@ -221,8 +317,11 @@ impl Tree {
         to: &TreeEntry,
         objects_dir: &Path,
     ) -> Result<()> {
-        let summary = if from.object_type == OBJ_TYPE_BLOB && to.object_type == OBJ_TYPE_BLOB {
-            Self::calculate_diff_summary(&from.object_hash, &to.object_hash, objects_dir)?
+        let summary = if from.object_type == OBJ_TYPE_BLOB
+            && to.object_type == OBJ_TYPE_BLOB
+            && from.object_hash != to.object_hash
+        {
+            Self::calculate_diff_summary(path, &from.object_hash, &to.object_hash, objects_dir)?
         } else {
             None
         };
@@ -245,6 +344,8 @@ impl Tree {
     ///
     /// # Arguments
     ///
+    /// * `path` - Path of the file, whose `diff` attribute may say whether
+    ///   it's binary
     /// * `old_hash` - Hash of original blob
     /// * `new_hash` - Hash of modified blob
     /// * `objects_dir` - Path to objects directory
@@ -252,14 +353,20 @@ impl Tree {
     /// # Returns
     ///
     /// Returns [`Option<DiffSummary>`] with diff details if blobs are text files,
-    /// or None for binary files or errors
+    /// or with the sizes of both versions if either is binary
     fn calculate_diff_summary(
+        path: &Path,
         old_hash: &str,
         new_hash: &str,
         objects_dir: &Path,
     ) -> Result<Option<DiffSummary>> {
         let old_blob = Blob::load(old_hash, objects_dir)?;
         let new_blob = Blob::load(new_hash, objects_dir)?;
+        let binary = diff_as_binary(path)
+            .unwrap_or_else(|| is_binary(&old_blob.data) || is_binary(&new_blob.data));
+        if binary {
+            return Ok(Some(DiffSummary::binary(old_blob.data.len(), new_blob.data.len())));
+        }
         let (text_diff, insertions, removals) = text_diff(
             &String::from_utf8_lossy(&old_blob.data),
             &String::from_utf8_lossy(&new_blob.data),
@@ -272,103 +379,193 @@ impl Tree {
         )))
     }
 
-    /// Detects file renames by matching deleted and added files with identical content
+    /// Detects renamed files by pairing deleted and added files with similar
+    /// content, and copied files if `detection` asks for it
     ///
-    /// Scans the ChangeSet for matching hash pairs between deletions and additions,
-    /// converting them to rename operations
+    /// Files with identical content are paired without reading them. The
+    /// others are compared by [`Fingerprint`], the most similar pairs first,
+    /// unless there are too many of them, see [`RENAME_LIMIT`].
     ///
     /// # Arguments
     ///
     /// * `changes` - Mutable reference to ChangeSet being analyzed
-    /// * `objects_dir` - Path to objects directory (unused in current implementation)
+    /// * `objects_dir` - Path to objects directory
+    /// * `detection` - Similarity thresholds for renames and copies
     ///
     /// # Errors
     ///
-    /// Currently always returns Ok, but signature maintained for future error cases
-    fn detect_renames(changes: &mut ChangeSet, _objects_dir: &Path) -> Result<()> {
-        let (deleted, added) = Self::collect_deleted_and_added(changes);
-        let renames = Self::find_rename_candidates(&deleted, &added)?;
-
-        for (old_path, new_path, hash) in renames {
-            changes.get().remove(&old_path);
-            changes.get().remove(&new_path);
+    /// Returns an error if the diff of a file changed while renamed fails
+    fn detect_renames(
+        changes: &mut ChangeSet,
+        objects_dir: &Path,
+        detection: &RenameDetection,
+    ) -> Result<()> {
+        let (deleted, added, modified) = Self::collect_candidates(changes);
+        let mut fingerprints = FingerprintCache::new(objects_dir);
+        let compare_content = deleted.len() * added.len() <= RENAME_LIMIT * RENAME_LIMIT;
+
+        let mut pairs = Vec::new();
+        for (old, (_, old_hash)) in deleted.iter().enumerate() {
+            for (new, (_, new_hash)) in added.iter().enumerate() {
+                let similarity = match old_hash == new_hash {
+                    true => 100,
+                    false if compare_content => {
+                        fingerprints.similarity(old_hash, new_hash, detection.threshold)
+                    }
+                    false => continue,
+                };
+                if similarity >= detection.threshold {
+                    pairs.push((similarity, old, new));
+                }
+            }
+        }
+        // The most similar pairs win, a file is renamed at most once
+        pairs.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
 
+        let (mut renamed_from, mut renamed_to) = (HashSet::new(), HashSet::new());
+        for (similarity, old, new) in pairs {
+            if renamed_from.contains(&old) || renamed_to.contains(&new) {
+                continue;
+            }
+            renamed_from.insert(old);
+            renamed_to.insert(new);
+
+            let (old_path, old_hash) = deleted[old].clone();
+            let (new_path, new_hash) = added[new].clone();
+            changes.remove_change(&old_path);
+            changes.remove_change(&new_path);
+            let summary = Self::renamed_summary(&new_path, &old_hash, &new_hash, objects_dir)?;
             changes.add_change(ChangeType::RENAMED {
                 old_path,
                 new_path,
-                old_hash: hash.clone(),
-                new_hash: hash,
-                summary: None,
+                old_hash,
+                new_hash,
+                similarity,
+                summary,
+            });
+        }
+
+        let Some(threshold) = detection.copies else {
+            return Ok(());
+        };
+        // Copies may come from any file that changed, even one renamed
+        let sources: Vec<&(PathBuf, String)> = modified.iter().chain(&deleted).collect();
+        for (new, (new_path, new_hash)) in added.iter().enumerate() {
+            if renamed_to.contains(&new) {
+                continue;
+            }
+            let mut best: Option<(u8, &(PathBuf, String))> = None;
+            for source in &sources {
+                let similarity = match source.1 == *new_hash {
+                    true => 100,
+                    false if compare_content => {
+                        fingerprints.similarity(&source.1, new_hash, threshold)
+                    }
+                    false => continue,
+                };
+                if similarity >= threshold && best.is_none_or(|(best, _)| similarity > best) {
+                    best = Some((similarity, source));
+                }
+            }
+            let Some((similarity, (old_path, old_hash))) = best else {
+                continue;
+            };
+            let summary = Self::renamed_summary(new_path, old_hash, new_hash, objects_dir)?;
+            changes.add_change(ChangeType::COPIED {
+                old_path: old_path.clone(),
+                new_path: new_path.clone(),
+                old_hash: old_hash.clone(),
+                new_hash: new_hash.clone(),
+                similarity,
+                summary,
             });
         }
         Ok(())
     }
 
-    /// Collects all deleted and added files from ChangeSet, indexed by their content hash
-    ///
-    /// # Arguments
-    ///
-    /// * `changes` - Reference to ChangeSet being analyzed
+    /// Collects the files rename and copy detection works on, sorted by path
     ///
     /// # Returns
     ///
-    /// Returns a tuple of HashMaps:
-    /// - First map: deleted files (hash -> path)
-    /// - Second map: added files (hash -> path)
-    fn collect_deleted_and_added(
-        changes: &ChangeSet,
-    ) -> (HashMap<String, PathBuf>, HashMap<String, PathBuf>) {
-        let mut deleted = HashMap::new();
-        let mut added = HashMap::new();
-
-        for (_, dt) in &changes.get() {
-            match dt {
-                ChangeType::DELETED { path, old_hash } => {
-                    deleted.insert(old_hash.clone(), path.clone());
-                }
-                ChangeType::ADDED { path, new_hash } => {
-                    added.insert(new_hash.clone(), path.clone());
-                }
+    /// Returns the deleted files, the added files and the modified files, as
+    /// `(path, hash)` with the hash of their old content for deleted and
+    /// modified files and of their new content for added ones
+    fn collect_candidates(changes: &ChangeSet) -> (FileHashes, FileHashes, FileHashes) {
+        let (mut deleted, mut added, mut modified) = (Vec::new(), Vec::new(), Vec::new());
+        for change in changes.get().into_values() {
+            match change {
+                ChangeType::DELETED { path, old_hash } => deleted.push((path, old_hash)),
+                ChangeType::ADDED { path, new_hash } => added.push((path, new_hash)),
+                ChangeType::MODIFIED { path, old_hash, .. } => modified.push((path, old_hash)),
                 _ => {}
             }
         }
+        deleted.sort();
+        added.sort();
+        modified.sort();
+        (deleted, added, modified)
+    }
 
-        (deleted, added)
+    /// Summarizes the changes made to a renamed or copied file, if any
+    fn renamed_summary(
+        path: &Path,
+        old_hash: &str,
+        new_hash: &str,
+        objects_dir: &Path,
+    ) -> Result<Option<DiffSummary>> {
+        match old_hash == new_hash {
+            true => Ok(None),
+            false => Self::calculate_diff_summary(path, old_hash, new_hash, objects_dir),
+        }
     }
 
-    /// Identifies potential file renames by matching hashes between deletions and additions
+    /// Lists every file below this tree as a single entry named by its full path
     ///
-    /// # Arguments
+    /// Comparing two flattened trees with [`Tree::compare_trees`] reports changes
+    /// to nested files, and renames between directories, rather than whole subtrees
     ///
-    /// * `deleted` - Map of deleted file hashes to paths
-    /// * `added` - Map of added file hashes to paths
-    ///
-    /// # Returns
+    /// # Errors
     ///
-    /// Returns a vector of rename candidates as tuples:
-    /// (old_path, new_path, content_hash)
-    fn find_rename_candidates(
-        deleted: &HashMap<String, PathBuf>,
-        added: &HashMap<String, PathBuf>,
-    ) -> Result<Vec<(PathBuf, PathBuf, String)>> {
-        let mut candidates = Vec::new();
-
-        for (hash, del_path) in deleted {
-            if let Some(add_path) = added.get(hash) {
-                candidates.push((del_path.clone(), add_path.clone(), hash.clone()));
+    /// Returns an error if a subtree fails to load
+    pub fn flatten(&self, objects_dir: &Path) -> Result<Tree> {
+        let mut entries = Vec::new();
+        self.flatten_into("", objects_dir, &mut entries)?;
+        Ok(Tree { entries })
+    }
+
+    fn flatten_into(
+        &self,
+        prefix: &str,
+        objects_dir: &Path,
+        entries: &mut Vec<TreeEntry>,
+    ) -> Result<()> {
+        for entry in &self.entries {
+            let name = format!("{}{}", prefix, entry.name);
+            if entry.object_type == OBJ_TYPE_TREE {
+                let subtree = read_tree(&entry.object_hash, objects_dir)?;
+                subtree.flatten_into(&format!("{}/", name), objects_dir, entries)?;
+            } else {
+                entries.push(TreeEntry {
+                    mode: entry.mode.clone(),
+                    object_type: entry.object_type.clone(),
+                    object_hash: entry.object_hash.clone(),
+                    name,
+                });
             }
         }
-
-        Ok(candidates)
+        Ok(())
     }
 
     /// Parses a tree object from raw binary data
     ///
     /// # Binary Format
     /// Each entry is formatted as:
-    /// [mode] [name]\0[20-byte hash]
+    /// [mode] [name]\0[raw hash]
     ///
-    pub fn parse(data: &[u8]) -> Result<Self> {
+    /// where the raw hash is 20 bytes for SHA-1 and 32 bytes for SHA-256
+    /// repositories
+    ///
+    pub fn parse(data: &[u8], format: ObjectFormat) -> Result<Self> {
         let mut entries = Vec::new();
         let mut cursor = Cursor::new(data);
 
@@ -394,14 +591,17 @@ impl Tree {
                 name.push(byte);
             }
 
-            // Read 20-byte object hash
-            let mut hash = [0u8; 20];
+            // Read the raw object hash
+            let mut hash = vec![0u8; format.raw_len()];
             cursor.read_exact(&mut hash)?;
 
-            // Determine object type from mode
-            let object_type = if mode.starts_with(&[b'1', b'0']) {
+            // Determine object type from mode: files and symlinks are blobs,
+            // submodules point to a commit
+            let object_type = if mode.starts_with(b"10") || mode == PERM_SYMLINK.as_bytes() {
                 OBJ_TYPE_BLOB.to_string()
-            } else if mode.starts_with(&[b'0', b'4']) {
+            } else if mode == PERM_GITLINK.as_bytes() {
+                OBJ_TYPE_COMMIT.to_string()
+            } else if mode.starts_with(b"04") || mode.starts_with(b"40") {
                 OBJ_TYPE_TREE.to_string()
             } else {
                 bail!("Unknown object type for mode: {:?}", mode);
@@ -411,7 +611,7 @@ impl Tree {
                 mode: String::from_utf8(mode)?,
                 name: String::from_utf8(name)?,
                 object_type,
-                object_hash: hex::encode(hash),
+                object_hash: ObjectId::from_bytes(format, &hash)?.to_hex(),
             });
         }
 
@@ -424,62 +624,98 @@ impl Tree {
 /// # Arguments
 ///
 /// * `path` - The filesystem path to scan
+/// * `objects_dir` - Path to the objects directory the blobs and subtrees
+///   are stored in
 ///
 /// # Errors
 ///
 /// Returns an error if the directory cannot be read or any files cannot be processed
-pub fn create_tree(path: &Path) -> Result<Tree> {
+pub fn create_tree(path: &Path, objects_dir: &Path) -> Result<Tree> {
+    let entries = fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+
+    // Files are hashed and subtrees built in parallel
+    let entries = entries
+        .par_iter()
+        .map(|entry| create_tree_entry(entry, objects_dir))
+        .collect::<Result<Vec<_>>>()?;
     let mut tree = Tree {
-        entries: Vec::new(),
+        entries: entries.into_iter().flatten().collect(),
     };
 
-    let entries = fs::read_dir(path)?;
-    for entry in entries {
-        let entry = entry?;
-        let entry_path = entry.path();
-        let name = entry_path
-            .file_name()
-            .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Invalid UTF-8 file name"))?
-            .to_string();
-
-        // Skip hidden files and target directories
-        if name.starts_with('.') || name == "target" {
-            continue;
-        }
-
-        if entry_path.is_file() {
-            // Create blob for file
-            let blob = Blob::new(entry_path.to_str().context("Invalid file path")?)?;
-            let object_hash = blob.save(&PathBuf::from(&*OBJ_DIR))?;
-            tree.entries.push(TreeEntry {
-                object_type: OBJ_TYPE_BLOB.to_string(),
-                mode: PERM_FILE.to_string(), // Regular file mode
-                object_hash,
-                name,
-            });
-        } else if entry_path.is_dir() {
-            // Recursively create subtree
-            let subtree = create_tree(&entry_path)?;
-            if !subtree.entries.is_empty() {
-                let hash_str = store_tree(&subtree)?;
-                tree.entries.push(TreeEntry {
-                    object_type: OBJ_TYPE_TREE.to_string(),
-                    mode: PERM_DIR.to_string(), // Directory mode
-                    object_hash: hash_str,
-                    name,
-                });
-            }
-        }
-    }
-
     // Sort entries by name for consistent hashing
     tree.entries.sort_by(|a, b| a.name.cmp(&b.name));
 
     Ok(tree)
 }
 
+/// Creates the tree entry of a directory entry for [`create_tree`], storing
+/// its blob or subtree
+///
+/// # Returns
+///
+/// The entry, or `None` if it's skipped or an empty directory
+fn create_tree_entry(entry: &fs::DirEntry, objects_dir: &Path) -> Result<Option<TreeEntry>> {
+    let entry_path = entry.path();
+    let name = entry_path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid file name"))?
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Invalid UTF-8 file name"))?
+        .to_string();
+
+    // Skip hidden files and target directories
+    if name.starts_with('.') || name == "target" {
+        return Ok(None);
+    }
+
+    if platform::is_symlink(&entry_path) {
+        // Store the link target, links are never followed
+        let object_hash = Blob::from_worktree(&entry_path)?.save(objects_dir)?;
+        Ok(Some(TreeEntry {
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            mode: PERM_SYMLINK.to_string(),
+            object_hash,
+            name,
+        }))
+    } else if entry_path.is_file() {
+        // Create blob for file
+        let object_hash = Blob::save_file(&entry_path, objects_dir)?;
+        let mode = if platform::is_executable(&entry.metadata()?) {
+            PERM_EXEC // Executable file mode
+        } else {
+            PERM_FILE // Regular file mode
+        };
+        Ok(Some(TreeEntry {
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            mode: mode.to_string(),
+            object_hash,
+            name,
+        }))
+    } else if is_nested_repository(&entry_path) {
+        // Nested repositories are recorded as the commit they have checked out
+        Ok(submodule_head(&entry_path)?.map(|object_hash| TreeEntry {
+            object_type: OBJ_TYPE_COMMIT.to_string(),
+            mode: PERM_GITLINK.to_string(),
+            object_hash,
+            name,
+        }))
+    } else if entry_path.is_dir() {
+        // Recursively create subtree
+        let subtree = create_tree(&entry_path, objects_dir)?;
+        if subtree.entries.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(TreeEntry {
+            object_type: OBJ_TYPE_TREE.to_string(),
+            mode: PERM_DIR.to_string(), // Directory mode
+            object_hash: subtree.save(objects_dir)?,
+            name,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
 /// Stores a tree object in the object database
 ///
 /// # Arguments
@@ -488,58 +724,28 @@ pub fn create_tree(path: &Path) -> Result<Tree> {
 ///
 /// # Returns
 ///
-/// The SHA-1 hash of the stored tree
+/// The hash of the stored tree
 ///
 pub fn store_tree(tree: &Tree) -> Result<String> {
-    let content = tree.serialize()?;
-    let header = format!("tree {}\0", content.len());
-    let full_content = [header.as_bytes(), &content].concat();
-
-    // Compute hash
-    let mut hasher = Sha1::new();
-    hasher.update(&full_content);
-    let hash = format!("{:x}", hasher.finalize());
-
-    // Create object path
-    let object_path = PathBuf::from(&*OBJ_DIR).join(&hash[..2]).join(&hash[2..]);
-
-    // Compress and write if not exists
-    if !object_path.exists() {
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(&full_content)?;
-        let compressed = encoder.finish()?;
-
-        fs::create_dir_all(object_path.parent().context("Invalid object path")?)?;
-        fs::write(&object_path, compressed)?;
-    }
-
-    Ok(hash)
+    tree.save(&OBJ_DIR)
 }
 
 /// Reads a tree object from the object database
 ///
 /// # Arguments
 ///
-/// * `hash` - The SHA-1 hash of the tree to read
+/// * `hash` - The hash of the tree to read
 /// * `objects_dir` - Path to the objects directory
 ///
 pub fn read_tree(hash: &str, objects_dir: &Path) -> Result<Tree> {
-    let object_path = objects_dir.join(&hash[..2]).join(&hash[2..]);
-
-    // Read and decompress object
-    let compressed = fs::read(&object_path)?;
-    let mut decoder = ZlibDecoder::new(&compressed[..]);
-    let mut data = Vec::new();
-    decoder.read_to_end(&mut data)?;
+    let format = ObjectFormat::for_objects_dir(objects_dir)?;
 
-    // Parse header
-    let null_pos = data
-        .iter()
-        .position(|&b| b == 0)
-        .context("Invalid format: no null byte found")?;
+    // Trees are read over and over by walks and comparisons, so they come
+    // through the cache
+    let object = ObjectStorage::at(objects_dir).read(hash)?;
 
     // Parse entries
-    let content = &data[null_pos + 1..];
+    let content = &object.data[..];
     let mut entries = Vec::new();
     let mut pos = 0;
 
@@ -558,13 +764,17 @@ pub fn read_tree(hash: &str, objects_dir: &Path) -> Result<Tree> {
         pos += null_pos + 1;
 
         // Parse object hash
-        let hash_bytes = &content[pos..pos + 20];
-        let object_hash = hex::encode(hash_bytes);
-        pos += 20;
+        let hash_bytes = content
+            .get(pos..pos + format.raw_len())
+            .context("Invalid format: truncated object hash in entry")?;
+        let object_hash = ObjectId::from_bytes(format, hash_bytes)?.to_hex();
+        pos += format.raw_len();
 
         // Determine object type from mode
         let object_type = if mode.starts_with("40") {
             OBJ_TYPE_TREE.to_string()
+        } else if mode == PERM_GITLINK {
+            OBJ_TYPE_COMMIT.to_string()
         } else {
             OBJ_TYPE_BLOB.to_string()
         };
@@ -580,6 +790,34 @@ pub fn read_tree(hash: &str, objects_dir: &Path) -> Result<Tree> {
     Ok(Tree { entries })
 }
 
+/// Finds the entry at `path` below the tree `hash`, if there is one
+///
+/// # Arguments
+///
+/// * `hash` - The hash of the root tree
+/// * `path` - Path of the entry relative to the root, e.g. `src/main.rs`
+/// * `objects_dir` - Path to the objects directory
+///
+pub fn lookup_path(hash: &str, path: &Path, objects_dir: &Path) -> Result<Option<TreeEntry>> {
+    let mut tree = read_tree(hash, objects_dir)?;
+    let mut components = path.iter().peekable();
+
+    while let Some(name) = components.next() {
+        let Some(index) = tree.entries.iter().position(|entry| *entry.name == *name) else {
+            return Ok(None);
+        };
+        let entry = tree.entries.swap_remove(index);
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+        if entry.object_type != OBJ_TYPE_TREE {
+            return Ok(None);
+        }
+        tree = read_tree(&entry.object_hash, objects_dir)?;
+    }
+    Ok(None)
+}
+
 impl VoxObject for Tree {
     fn object_type(&self) -> &str {
         OBJ_TYPE_TREE
@@ -587,7 +825,7 @@ impl VoxObject for Tree {
 
     /// Serializes the tree to bytes
     ///
-    /// The format is: `[mode] [name]\0[20-byte hash]` for each entry
+    /// The format is: `[mode] [name]\0[raw hash]` for each entry
     fn serialize(&self) -> Result<Vec<u8>> {
         let mut content = Vec::new();
 
@@ -595,18 +833,17 @@ impl VoxObject for Tree {
             let mode_and_name = format!("{} {}\0", entry.mode, entry.name);
             content.extend_from_slice(mode_and_name.as_bytes());
 
-            let hash_bytes = hex::decode(&entry.object_hash).expect("Decoding failed");
-            content.extend_from_slice(&hash_bytes);
+            let object_id = ObjectId::from_hex(&entry.object_hash)
+                .with_context(|| format!("Invalid hash for tree entry {}", entry.name))?;
+            content.extend_from_slice(object_id.as_bytes());
         }
         Ok(content)
     }
 
-    /// Computes the SHA-1 hash of the serialized tree
+    /// Computes the hash of the serialized tree using the repository's object format
     fn hash(&self) -> Result<String> {
         let content = self.serialize()?;
-        let mut hasher = Sha1::new();
-        hasher.update(&content);
-        Ok(format!("{:x}", hasher.finalize()))
+        Ok(ObjectFormat::current()?.digest(&content).to_hex())
     }
 
     /// Returns the storage path for this tree in the objects directory
@@ -624,8 +861,149 @@ impl VoxObject for Tree {
     }
 }
 
+impl Storable for Tree {
+    /// Stores the tree in `objects_dir` unless it's already there
+    ///
+    /// Unlike [`VoxObject::hash`], the object name covers the header as well
+    fn save(&self, objects_dir: &Path) -> Result<String> {
+        let content = self.serialize()?;
+        let header = format!("tree {}\0", content.len());
+        let full_content = [header.as_bytes(), &content].concat();
+
+        // Compute hash
+        let hash = ObjectFormat::for_objects_dir(objects_dir)?
+            .digest(&full_content)
+            .to_hex();
+
+        // Create object path
+        let object_path = objects_dir.join(&hash[..2]).join(&hash[2..]);
+
+        // Compress and write if not exists, here or in an alternate
+        if loose_object_path(&hash, objects_dir)?.is_none() {
+            let compressed = compress(&full_content, objects_dir)?;
+
+            fs::create_dir_all(object_path.parent().context("Invalid object path")?)?;
+            write_object_file(&hash, &object_path, &compressed)?;
+        }
+
+        Ok(hash)
+    }
+}
+
 impl Loadable for Tree {
     fn load(hash: &str, objects_dir: &Path) -> Result<Self> {
         read_tree(hash, objects_dir)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn blob_entry(name: &str, mode: &str) -> TreeEntry {
+        TreeEntry {
+            mode: mode.to_string(),
+            name: name.to_string(),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: "ab".repeat(20),
+        }
+    }
+
+    #[test]
+    fn test_mode_only_change_is_modified() -> Result<()> {
+        let dir = tempdir()?;
+        let from = Tree {
+            entries: vec![blob_entry("run.sh", PERM_FILE)],
+        };
+        let to = Tree {
+            entries: vec![blob_entry("run.sh", PERM_EXEC)],
+        };
+
+        let changes = Tree::compare_trees(&from, &to, dir.path())?;
+        assert!(matches!(
+            changes.get_entry(Path::new("run.sh")),
+            Some(ChangeType::MODIFIED { summary: None, .. })
+        ));
+
+        assert!(Tree::compare_trees(&from, &from, dir.path())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flatten_finds_moves_between_directories() -> Result<()> {
+        let dir = tempdir()?;
+        let src = Tree {
+            entries: vec![blob_entry("lib.rs", PERM_FILE)],
+        };
+        let src_hash = src.save(dir.path())?;
+        let from = Tree {
+            entries: vec![TreeEntry {
+                mode: PERM_DIR.to_string(),
+                name: "src".to_string(),
+                object_type: OBJ_TYPE_TREE.to_string(),
+                object_hash: src_hash,
+            }],
+        };
+        let to = Tree {
+            entries: vec![blob_entry("lib.rs", PERM_FILE)],
+        };
+
+        let flat = from.flatten(dir.path())?;
+        assert_eq!(flat.entries.len(), 1);
+        assert_eq!(flat.entries[0].name, "src/lib.rs");
+
+        let changes = Tree::compare_trees(&flat, &to.flatten(dir.path())?, dir.path())?;
+        let change = changes.get_entry(Path::new("lib.rs"));
+        assert_eq!(change.and_then(|c| c.old_path()), Some(Path::new("src/lib.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_detects_edited_renames_and_copies() -> Result<()> {
+        let dir = tempdir()?;
+        let save = |data: &str| Blob { data: data.as_bytes().to_vec() }.save(dir.path());
+        let lines: String = (1..=20).map(|n| format!("line {}\n", n)).collect();
+        let original = save(&lines)?;
+        let edited = save(&lines.replace("line 5\n", "line five\n"))?;
+        let copied = save(&format!("{}extra line\nextra line\n", lines))?;
+        let modified = save(&format!("{}line 21\n", lines))?;
+        let unrelated = save("something else\n")?;
+        let entry = |name: &str, hash: &str| TreeEntry {
+            object_hash: hash.to_string(),
+            ..blob_entry(name, PERM_FILE)
+        };
+
+        let from = Tree {
+            entries: vec![entry("a.txt", &original), entry("m.txt", &original)],
+        };
+        let to = Tree {
+            entries: vec![
+                entry("b.txt", &edited),
+                entry("c.txt", &copied),
+                entry("m.txt", &modified),
+                entry("new.txt", &unrelated),
+            ],
+        };
+
+        let changes = Tree::compare_trees(&from, &to, dir.path())?;
+        let b = changes.get_entry(Path::new("b.txt"));
+        assert!(matches!(b, Some(ChangeType::RENAMED { similarity: 93, .. })));
+        assert_eq!(b.and_then(|c| c.old_path()), Some(Path::new("a.txt")));
+        assert!(b.and_then(|c| c.summary()).is_some());
+        assert!(matches!(changes.get_entry(Path::new("c.txt")), Some(ChangeType::ADDED { .. })));
+
+        let strict = RenameDetection {
+            threshold: 99,
+            copies: Some(80),
+        };
+        let changes = Tree::compare_trees_with(&from, &to, dir.path(), &strict)?;
+        assert!(matches!(changes.get_entry(Path::new("a.txt")), Some(ChangeType::DELETED { .. })));
+        // Both sources had the same content, modified files are tried first
+        let c = changes.get_entry(Path::new("c.txt"));
+        assert!(matches!(c, Some(ChangeType::COPIED { similarity: 87, .. })));
+        assert_eq!(c.and_then(|c| c.old_path()), Some(Path::new("m.txt")));
+        assert!(matches!(changes.get_entry(Path::new("new.txt")), Some(ChangeType::ADDED { .. })));
+        Ok(())
+    }
+}
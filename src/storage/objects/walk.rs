@@ -0,0 +1,212 @@
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::load_commit;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Order a [`CommitWalker`] returns the commits in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalkOrder {
+    /// Most recently committed first, commits with the same date in the
+    /// order they were reached, so children made within the same second
+    /// still precede their parents
+    #[default]
+    Date,
+    /// Nearest to the starting commits first
+    BreadthFirst,
+}
+
+/// Walks history from some commits through their parents, returning every
+/// commit once
+///
+/// Commits reachable from a hidden commit are left out, so pushing `B` and
+/// hiding `A` walks the range `A..B`. Commits at the shallow boundary have no
+/// parents, see [`load_commit`].
+///
+/// ```no_run
+/// # use std::path::Path;
+/// # use vox::CommitWalker;
+/// # fn main() -> anyhow::Result<()> {
+/// # let (objects_dir, tip, upstream) = (Path::new(".vox/objects"), "b0b", "a1a");
+/// let mut walker = CommitWalker::new(objects_dir);
+/// walker.push(tip)?;
+/// walker.hide(upstream)?;
+/// for commit in walker {
+///     let (hash, commit) = commit?;
+///     println!("{} {}", hash, commit.message);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct CommitWalker {
+    objects_dir: PathBuf,
+    order: WalkOrder,
+    first_parent: bool,
+    /// Commits reachable from the hidden ones
+    hidden: HashSet<String>,
+    /// Commits queued so far, never queued again
+    seen: HashSet<String>,
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    queued: usize,
+    /// Queued commits, loaded to be ordered
+    commits: HashMap<String, Commit>,
+    /// Parents of the last returned commit, queued on the next step unless
+    /// they're skipped
+    parents: Vec<String>,
+}
+
+impl CommitWalker {
+    pub fn new(objects_dir: &Path) -> Self {
+        Self {
+            objects_dir: objects_dir.to_path_buf(),
+            order: WalkOrder::default(),
+            first_parent: false,
+            hidden: HashSet::new(),
+            seen: HashSet::new(),
+            queue: BinaryHeap::new(),
+            queued: 0,
+            commits: HashMap::new(),
+            parents: Vec::new(),
+        }
+    }
+
+    pub fn with_order(mut self, order: WalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Follows only the first parent of every commit
+    pub fn with_first_parent(mut self, first_parent: bool) -> Self {
+        self.first_parent = first_parent;
+        self
+    }
+
+    /// Starts walking from `hash` too
+    pub fn push(&mut self, hash: &str) -> Result<()> {
+        if !self.seen.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let commit = load_commit(hash, &self.objects_dir)?;
+        let key = match self.order {
+            WalkOrder::Date => commit.committer_timestamp.timestamp(),
+            WalkOrder::BreadthFirst => 0,
+        };
+        self.queue.push((key, Reverse(self.queued), hash.to_string()));
+        self.queued += 1;
+        self.commits.insert(hash.to_string(), commit);
+        Ok(())
+    }
+
+    /// Leaves out `hash` and every commit reachable from it
+    pub fn hide(&mut self, hash: &str) -> Result<()> {
+        if self.hidden.contains(hash) {
+            return Ok(());
+        }
+        let mut ancestors =
+            CommitWalker::new(&self.objects_dir).with_order(WalkOrder::BreadthFirst);
+        ancestors.push(hash)?;
+        while let Some(commit) = ancestors.next() {
+            let (hash, _) = commit?;
+            // The history below was hidden along with this commit
+            if !self.hidden.insert(hash) {
+                ancestors.skip_parents();
+            }
+        }
+        Ok(())
+    }
+
+    /// Doesn't walk through the parents of the commit just returned, unless
+    /// they're reached from another one
+    pub fn skip_parents(&mut self) {
+        self.parents.clear();
+    }
+
+    /// Returns true if `hash` is hidden
+    pub fn is_hidden(&self, hash: &str) -> bool {
+        self.hidden.contains(hash)
+    }
+
+    fn step(&mut self) -> Result<Option<(String, Commit)>> {
+        for parent in std::mem::take(&mut self.parents) {
+            self.push(&parent)?;
+        }
+        while let Some((_, _, hash)) = self.queue.pop() {
+            let commit = self.commits.remove(&hash).expect("queued commits are loaded");
+            // Everything a hidden commit reaches is hidden already
+            if self.hidden.contains(&hash) {
+                continue;
+            }
+            self.parents = commit.parents();
+            if self.first_parent {
+                self.parents.truncate(1);
+            }
+            return Ok(Some((hash, commit)));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for CommitWalker {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::Storable;
+    use chrono::{TimeZone, Utc};
+    use tempfile::tempdir;
+
+    fn commit(objects_dir: &Path, parent: Option<&str>, time: i64) -> Result<String> {
+        let mut commit = Commit::new(
+            "tree".to_string(),
+            parent.map(str::to_string),
+            "Author <author@example.com>".to_string(),
+            time.to_string(),
+        );
+        commit.committer_timestamp = Utc.timestamp_opt(time, 0).unwrap();
+        commit.save(objects_dir)
+    }
+
+    fn walk(walker: CommitWalker) -> Result<Vec<String>> {
+        walker.map(|commit| Ok(commit?.0)).collect()
+    }
+
+    #[test]
+    fn test_walk_orders_and_ranges() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path();
+
+        // root - base - left
+        //            \- right - right2, with right committed last
+        let root = commit(objects_dir, None, 100)?;
+        let base = commit(objects_dir, Some(&root), 200)?;
+        let left = commit(objects_dir, Some(&base), 300)?;
+        let right = commit(objects_dir, Some(&base), 500)?;
+        let right2 = commit(objects_dir, Some(&right), 400)?;
+
+        let mut walker = CommitWalker::new(objects_dir);
+        walker.push(&left)?;
+        walker.push(&right2)?;
+        let all = vec![right2.clone(), right.clone(), left.clone(), base.clone(), root.clone()];
+        assert_eq!(walk(walker)?, all);
+
+        let mut walker = CommitWalker::new(objects_dir).with_order(WalkOrder::BreadthFirst);
+        walker.push(&right2)?;
+        walker.push(&left)?;
+        assert_eq!(walk(walker)?, vec![right2.clone(), left.clone(), right.clone(), base, root]);
+
+        // left..right2
+        let mut walker = CommitWalker::new(objects_dir);
+        walker.push(&right2)?;
+        walker.hide(&left)?;
+        assert!(walker.is_hidden(&left));
+        assert_eq!(walk(walker)?, vec![right2, right]);
+        Ok(())
+    }
+}
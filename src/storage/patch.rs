@@ -0,0 +1,581 @@
+use crate::storage::attributes::diff_as_binary;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::{read_raw_object, Loadable};
+use crate::storage::objects::tree::{read_tree, Tree, TreeEntry};
+use crate::storage::utils::OBJ_TYPE_BLOB;
+use anyhow::{bail, Context, Result};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use similar::{ChangeTag, TextDiff};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Number of unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Length of the abbreviated hashes on `index` lines
+const INDEX_ABBREV: usize = 7;
+
+/// Renders the changes `commit` makes to its first parent as a unified diff
+/// in the `diff --git` format, which `vox am` and `git apply` understand
+///
+/// With `binary`, changed binary files are included as `GIT binary patch`
+/// sections rather than only named.
+pub fn commit_diff(commit: &Commit, objects_dir: &Path, binary: bool) -> Result<String> {
+    let new = read_tree(&commit.tree, objects_dir)?.flatten(objects_dir)?;
+    let old = match commit.parents().first() {
+        Some(parent) => {
+            let parent = Commit::load(parent, objects_dir)?;
+            read_tree(&parent.tree, objects_dir)?.flatten(objects_dir)?
+        }
+        None => Tree { entries: Vec::new() },
+    };
+    tree_diff(&old, &new, objects_dir, binary)
+}
+
+/// Renders the differences between two flattened trees as a unified diff,
+/// file by file in path order
+///
+/// Submodule entries are skipped, their content isn't in the object store.
+pub fn tree_diff(old: &Tree, new: &Tree, objects_dir: &Path, binary: bool) -> Result<String> {
+    let mut files: BTreeMap<&str, (Option<&TreeEntry>, Option<&TreeEntry>)> = BTreeMap::new();
+    for entry in old.entries.iter().filter(|e| e.object_type == OBJ_TYPE_BLOB) {
+        files.entry(&entry.name).or_default().0 = Some(entry);
+    }
+    for entry in new.entries.iter().filter(|e| e.object_type == OBJ_TYPE_BLOB) {
+        files.entry(&entry.name).or_default().1 = Some(entry);
+    }
+
+    let mut diff = String::new();
+    for (path, (old, new)) in files {
+        let unchanged = match (old, new) {
+            (Some(old), Some(new)) => old.object_hash == new.object_hash && old.mode == new.mode,
+            _ => false,
+        };
+        if !unchanged {
+            diff.push_str(&file_diff(path, old, new, objects_dir, binary)?);
+        }
+    }
+    Ok(diff)
+}
+
+/// Renders the diff of a single file, `None` standing for a missing side
+fn file_diff(
+    path: &str,
+    old: Option<&TreeEntry>,
+    new: Option<&TreeEntry>,
+    objects_dir: &Path,
+    binary: bool,
+) -> Result<String> {
+    let mut out = format!("diff --git a/{} b/{}\n", path, path);
+    match (old, new) {
+        (None, Some(new)) => out.push_str(&format!("new file mode {}\n", new.mode)),
+        (Some(old), None) => out.push_str(&format!("deleted file mode {}\n", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            out.push_str(&format!("old mode {}\nnew mode {}\n", old.mode, new.mode));
+        }
+        _ => {}
+    }
+    let same_mode = old.zip(new).filter(|(old, new)| old.mode == new.mode);
+    let content_changed = old.map(|e| &e.object_hash) != new.map(|e| &e.object_hash);
+    if !content_changed {
+        return Ok(out);
+    }
+
+    let read = |entry: Option<&TreeEntry>| -> Result<Vec<u8>> {
+        match entry {
+            Some(entry) => Ok(read_raw_object(&entry.object_hash, objects_dir)?.1),
+            None => Ok(Vec::new()),
+        }
+    };
+    let (old_content, new_content) = (read(old)?, read(new)?);
+    // The `diff` attribute overrides what the content looks like
+    let texts = match diff_as_binary(Path::new(path)) {
+        Some(true) => None,
+        Some(false) => Some((
+            String::from_utf8_lossy(&old_content),
+            String::from_utf8_lossy(&new_content),
+        )),
+        None => text(&old_content).map(Cow::Borrowed).zip(text(&new_content).map(Cow::Borrowed)),
+    };
+
+    // Binary patches are only applied against the exact old content, so
+    // they name it by its full hash
+    let hash_len = match texts.is_none() && binary {
+        true => old.or(new).map_or(INDEX_ABBREV, |entry| entry.object_hash.len()),
+        false => INDEX_ABBREV,
+    };
+    let abbrev = |entry: Option<&TreeEntry>| match entry {
+        Some(entry) => entry.object_hash[..hash_len].to_string(),
+        None => "0".repeat(hash_len),
+    };
+    out.push_str(&format!("index {}..{}", abbrev(old), abbrev(new)));
+    match same_mode {
+        Some((_, new)) => out.push_str(&format!(" {}\n", new.mode)),
+        None => out.push('\n'),
+    }
+
+    let old_name = old.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+    let new_name = new.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+
+    match texts {
+        Some((old_text, new_text)) => {
+            let diff = TextDiff::from_lines(old_text.as_ref(), new_text.as_ref());
+            let unified = diff
+                .unified_diff()
+                .context_radius(CONTEXT_LINES)
+                .header(&old_name, &new_name)
+                .to_string();
+            out.push_str(&unified);
+        }
+        // The new content first, then the old one so the patch can be reversed
+        _ if binary => {
+            out.push_str("GIT binary patch\n");
+            out.push_str(&binary_literal(&new_content)?);
+            out.push_str(&binary_literal(&old_content)?);
+        }
+        _ => out.push_str(&format!("Binary files {} and {} differ\n", old_name, new_name)),
+    }
+    Ok(out)
+}
+
+/// Characters of the base85 encoding of binary patches, in digit order
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Bytes encoded on each line of a binary patch
+const BINARY_LINE_BYTES: usize = 52;
+
+/// Renders a `literal` hunk of a binary patch: the deflated content in
+/// base85, each line prefixed with a letter giving its length in bytes
+/// (`A`-`Z` for 1 to 26, `a`-`z` for 27 to 52), then an empty line
+fn binary_literal(content: &[u8]) -> Result<String> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    let deflated = encoder.finish()?;
+
+    let mut out = format!("literal {}\n", content.len());
+    for chunk in deflated.chunks(BINARY_LINE_BYTES) {
+        out.push(match chunk.len() {
+            len @ 1..=26 => (b'A' + len as u8 - 1) as char,
+            len => (b'a' + len as u8 - 27) as char,
+        });
+        for group in chunk.chunks(4) {
+            let mut word = [0u8; 4];
+            word[..group.len()].copy_from_slice(group);
+            let mut value = u32::from_be_bytes(word);
+            let mut digits = [0u8; 5];
+            for digit in digits.iter_mut().rev() {
+                *digit = BASE85[(value % 85) as usize];
+                value /= 85;
+            }
+            out.extend(digits.iter().map(|&digit| digit as char));
+        }
+        out.push('\n');
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// Decodes one line of a binary patch hunk, see [`binary_literal`]
+fn decode_binary_line(line: &str) -> Result<Vec<u8>> {
+    let bytes = line.as_bytes();
+    let len = match bytes.first() {
+        Some(&c @ b'A'..=b'Z') => (c - b'A') as usize + 1,
+        Some(&c @ b'a'..=b'z') => (c - b'a') as usize + 27,
+        _ => bail!("Invalid binary patch line: {}", line),
+    };
+    let digits = &bytes[1..];
+    if digits.len() != len.div_ceil(4) * 5 {
+        bail!("Invalid binary patch line: {}", line);
+    }
+
+    let mut data = Vec::with_capacity(digits.len() / 5 * 4);
+    for group in digits.chunks(5) {
+        let mut value: u64 = 0;
+        for &c in group {
+            let digit = BASE85
+                .iter()
+                .position(|&d| d == c)
+                .with_context(|| format!("Invalid base85 character in {}", line))?;
+            value = value * 85 + digit as u64;
+        }
+        let value = u32::try_from(value).context("Invalid base85 group in binary patch")?;
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Inflates the data of a `literal` hunk, checking it has the size it names
+fn inflate_literal(deflated: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut content = Vec::with_capacity(size);
+    ZlibDecoder::new(deflated)
+        .read_to_end(&mut content)
+        .context("Corrupt binary patch")?;
+    if content.len() != size {
+        bail!("Binary patch is {} bytes, expected {}", content.len(), size);
+    }
+    Ok(content)
+}
+
+/// Returns true if the content doesn't look like text
+pub fn is_binary(content: &[u8]) -> bool {
+    text(content).is_none()
+}
+
+/// Counts the lines inserted and deleted between two versions of a file
+///
+/// # Returns
+///
+/// `(insertions, deletions)`, or `None` if either version is binary
+pub fn line_changes(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let (old, new) = (text(old)?, text(new)?);
+    let mut counts = (0, 0);
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => counts.0 += 1,
+            ChangeTag::Delete => counts.1 += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    Some(counts)
+}
+
+/// Returns the content as text, unless it looks binary
+fn text(content: &[u8]) -> Option<&str> {
+    if content.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
+
+/// A change to one file, parsed from a `diff --git` section
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    /// Path before the change, `None` for a new file
+    pub old_path: Option<String>,
+    /// Path after the change, `None` for a deleted file
+    pub new_path: Option<String>,
+    /// Mode of the file after the change, if the patch sets it
+    pub new_mode: Option<String>,
+    /// Binary changes have no hunks
+    pub binary: bool,
+    /// Content of a binary file after the change, when the patch includes
+    /// it as a `GIT binary patch`
+    pub binary_content: Option<Vec<u8>>,
+    /// Changes to the content, in file order
+    pub hunks: Vec<Hunk>,
+}
+
+/// A block of changed lines with their context
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Hunk {
+    /// Line the hunk starts at in the old content, 1-based
+    pub old_start: usize,
+    /// Lines prefixed with ' ', '-' or '+', each with its line terminator
+    /// unless it's the last line of a file without a final newline
+    pub lines: Vec<String>,
+}
+
+impl Hunk {
+    /// Returns the lines the hunk expects (`' '` and `'-'`, when `old`) or
+    /// produces (`' '` and `'+'`), without their prefix
+    fn side(&self, old: bool) -> Vec<&str> {
+        let kept = if old { '-' } else { '+' };
+        self.lines
+            .iter()
+            .filter(|line| line.starts_with(' ') || line.starts_with(kept))
+            .map(|line| &line[1..])
+            .collect()
+    }
+}
+
+/// Parses the `diff --git` sections of a unified diff
+///
+/// Anything before the first section and after the last hunk of each file
+/// (such as a mail signature) is ignored.
+pub fn parse_diff(diff: &str) -> Result<Vec<FilePatch>> {
+    let mut patches: Vec<FilePatch> = Vec::new();
+    // Lines still expected in the current hunk, on the old and new side
+    let mut remaining = (0usize, 0usize);
+    // Size and data read so far of the literal of a binary patch
+    let mut literal: Option<(usize, Vec<u8>)> = None;
+
+    for line in diff.split_inclusive('\n') {
+        let text = line.trim_end_matches('\n');
+        let in_hunk = remaining != (0, 0);
+
+        if let Some(paths) = text.strip_prefix("diff --git ") {
+            let (old, new) = paths
+                .split_once(" b/")
+                .with_context(|| format!("Invalid diff header: {}", text))?;
+            patches.push(FilePatch {
+                old_path: Some(old.trim_start_matches("a/").to_string()),
+                new_path: Some(new.to_string()),
+                ..FilePatch::default()
+            });
+            remaining = (0, 0);
+            literal = None;
+            continue;
+        }
+        let Some(patch) = patches.last_mut() else {
+            continue;
+        };
+
+        // Only the first literal, the new content, is needed to apply
+        if let Some((size, data)) = &mut literal {
+            match text {
+                "" => {
+                    patch.binary_content = Some(inflate_literal(data, *size)?);
+                    literal = None;
+                }
+                _ => data.extend(decode_binary_line(text)?),
+            }
+            continue;
+        }
+        if patch.binary && patch.binary_content.is_none()
+            && let Some(size) = text.strip_prefix("literal ")
+        {
+            let size = size
+                .parse()
+                .with_context(|| format!("Invalid binary patch size: {}", size))?;
+            literal = Some((size, Vec::new()));
+            continue;
+        }
+
+        if text.starts_with("\\ ") {
+            // "\ No newline at end of file" applies to the line before it
+            if let Some(last) = patch.hunks.last_mut().and_then(|hunk| hunk.lines.last_mut()) {
+                last.truncate(last.trim_end_matches('\n').len());
+            }
+            continue;
+        }
+        if in_hunk {
+            let line = match text {
+                // Some mailers strip the trailing space of empty context lines
+                "" => " \n",
+                _ => line,
+            };
+            let (old, new) = match line.chars().next() {
+                Some(' ') => (1, 1),
+                Some('-') => (1, 0),
+                Some('+') => (0, 1),
+                _ => bail!("Invalid line in hunk: {}", text),
+            };
+            remaining = (remaining.0.saturating_sub(old), remaining.1.saturating_sub(new));
+            if let Some(hunk) = patch.hunks.last_mut() {
+                hunk.lines.push(line.to_string());
+            }
+            continue;
+        }
+
+        if let Some(range) = text.strip_prefix("@@ -") {
+            let (old, rest) = range
+                .split_once(" +")
+                .with_context(|| format!("Invalid hunk header: {}", text))?;
+            let new = rest.split(' ').next().unwrap_or_default();
+            let (old_start, old_len) = parse_range(old)?;
+            let (_, new_len) = parse_range(new)?;
+            remaining = (old_len, new_len);
+            patch.hunks.push(Hunk {
+                old_start,
+                lines: Vec::new(),
+            });
+        } else if let Some(mode) = text.strip_prefix("new file mode ") {
+            patch.old_path = None;
+            patch.new_mode = Some(mode.to_string());
+        } else if text.starts_with("deleted file mode ") {
+            patch.new_path = None;
+        } else if let Some(mode) = text.strip_prefix("new mode ") {
+            patch.new_mode = Some(mode.to_string());
+        } else if let Some(path) = text.strip_prefix("rename from ") {
+            patch.old_path = Some(path.to_string());
+        } else if let Some(path) = text.strip_prefix("rename to ") {
+            patch.new_path = Some(path.to_string());
+        } else if text.starts_with("Binary files ") || text == "GIT binary patch" {
+            patch.binary = true;
+        }
+    }
+    Ok(patches)
+}
+
+/// Parses a hunk range such as `12,3` or `12` (a single line)
+fn parse_range(range: &str) -> Result<(usize, usize)> {
+    let (start, len) = range.split_once(',').unwrap_or((range, "1"));
+    let parse = |n: &str| {
+        n.parse::<usize>()
+            .with_context(|| format!("Invalid hunk range {}", range))
+    };
+    Ok((parse(start)?, parse(len)?))
+}
+
+/// Applies the hunks of a file patch to `content`
+///
+/// Each hunk is looked for at the line it names first, then at the closest
+/// line it matches at, so patches still apply after unrelated lines were
+/// added or removed above them.
+///
+/// # Errors
+///
+/// Returns an error if the lines a hunk changes aren't in `content`
+pub fn apply_hunks(content: &str, hunks: &[Hunk]) -> Result<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut result = String::new();
+    let mut position = 0;
+
+    for hunk in hunks {
+        let expected = hunk.side(true);
+        // A hunk without old lines inserts after `old_start`
+        let target = match expected.is_empty() {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+        let matches_at = |at: usize| lines.get(at..at + expected.len()) == Some(&expected[..]);
+        let found = (0..lines.len() + 1)
+            .flat_map(|offset| [target.checked_add(offset), target.checked_sub(offset)])
+            .flatten()
+            .find(|&at| at >= position && at <= lines.len() && matches_at(at))
+            .with_context(|| format!("Hunk at line {} does not apply", hunk.old_start))?;
+
+        result.extend(lines[position..found].iter().copied());
+        result.extend(hunk.side(false));
+        position = found + expected.len();
+    }
+    result.extend(lines[position..].iter().copied());
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::Storable;
+    use crate::storage::utils::{PERM_EXEC, PERM_FILE};
+    use tempfile::tempdir;
+
+    fn entry(name: &str, mode: &str, hash: &str) -> TreeEntry {
+        TreeEntry {
+            mode: mode.to_string(),
+            object_type: OBJ_TYPE_BLOB.to_string(),
+            object_hash: hash.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_tree_diff() -> Result<()> {
+        let dir = tempdir()?;
+        let save = |data: &str| Blob { data: data.as_bytes().to_vec() }.save(dir.path());
+        let (v1, v2, added) = (save("one\ntwo\n")?, save("one\n2\n")?, save("new\n")?);
+
+        let old = Tree {
+            entries: vec![entry("a.txt", PERM_FILE, &v1), entry("run.sh", PERM_FILE, &v1)],
+        };
+        let new = Tree {
+            entries: vec![
+                entry("a.txt", PERM_FILE, &v2),
+                entry("dir/b.txt", PERM_FILE, &added),
+                entry("run.sh", PERM_EXEC, &v1),
+            ],
+        };
+
+        let diff = tree_diff(&old, &new, dir.path(), false)?;
+        let expected = format!(
+            "diff --git a/a.txt b/a.txt\n\
+             index {}..{} 100644\n\
+             --- a/a.txt\n\
+             +++ b/a.txt\n\
+             @@ -1,2 +1,2 @@\n one\n-two\n+2\n\
+             diff --git a/dir/b.txt b/dir/b.txt\n\
+             new file mode 100644\n\
+             index 0000000..{}\n\
+             --- /dev/null\n\
+             +++ b/dir/b.txt\n\
+             @@ -0,0 +1 @@\n+new\n\
+             diff --git a/run.sh b/run.sh\n\
+             old mode 100644\n\
+             new mode 100755\n",
+            &v1[..7],
+            &v2[..7],
+            &added[..7]
+        );
+        assert_eq!(diff, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_and_apply() -> Result<()> {
+        let diff = "diff --git a/f b/f\n\
+                    index 1111111..2222222 100644\n\
+                    --- a/f\n\
+                    +++ b/f\n\
+                    @@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n\
+                    @@ -6 +6 @@\n-f\n\\ No newline at end of file\n+F\n\
+                    diff --git a/gone b/gone\n\
+                    deleted file mode 100644\n\
+                    -- \nvox 0.1.0\n";
+        let patches = parse_diff(diff)?;
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].hunks[1].lines, vec!["-f", "+F\n"]);
+        assert_eq!(patches[1].new_path, None);
+        assert!(patches[1].hunks.is_empty());
+
+        // Still applies with a line added above the hunks
+        let patched = apply_hunks("new\na\nb\nc\nd\ne\nf", &patches[0].hunks)?;
+        assert_eq!(patched, "new\na\nb\nC\nd\ne\nF\n");
+        assert!(apply_hunks("a\nb\nX\nd\n", &patches[0].hunks).is_err());
+
+        let added = parse_diff("diff --git a/n b/n\nnew file mode 100755\n@@ -0,0 +1 @@\n+x\n")?;
+        assert_eq!(added[0].old_path, None);
+        assert_eq!(added[0].new_mode.as_deref(), Some("100755"));
+        assert_eq!(apply_hunks("", &added[0].hunks)?, "x\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_patch() -> Result<()> {
+        let dir = tempdir()?;
+        let old_content: Vec<u8> = (0..=255).collect();
+        let new_content: Vec<u8> = (0..300u32).map(|i| (i * 7 % 256) as u8).collect();
+        let old_hash = Blob { data: old_content }.save(dir.path())?;
+        let new_hash = Blob { data: new_content.clone() }.save(dir.path())?;
+        let old = Tree {
+            entries: vec![entry("logo.png", PERM_FILE, &old_hash)],
+        };
+        let new = Tree {
+            entries: vec![entry("logo.png", PERM_FILE, &new_hash)],
+        };
+
+        let diff = tree_diff(&old, &new, dir.path(), false)?;
+        assert!(diff.ends_with("Binary files a/logo.png and b/logo.png differ\n"));
+        let patches = parse_diff(&diff)?;
+        assert!(patches[0].binary);
+        assert_eq!(patches[0].binary_content, None);
+
+        let diff = tree_diff(&old, &new, dir.path(), true)?;
+        assert!(diff.contains("GIT binary patch\nliteral 300\n"));
+        assert!(diff.contains("\n\nliteral 256\n"));
+        let patches = parse_diff(&diff)?;
+        assert_eq!(patches[0].binary_content, Some(new_content));
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_line_roundtrip() -> Result<()> {
+        for len in [1, 4, 5, 26, 27, 52] {
+            let data: Vec<u8> = (0..len as u8).map(|i| i.wrapping_mul(97)).collect();
+            let literal = binary_literal(&data)?;
+            let mut deflated = Vec::new();
+            for line in literal.lines().skip(1).filter(|line| !line.is_empty()) {
+                deflated.extend(decode_binary_line(line)?);
+            }
+            assert_eq!(inflate_literal(&deflated, len)?, data);
+        }
+        assert!(decode_binary_line("Bxyz").is_err());
+        Ok(())
+    }
+}
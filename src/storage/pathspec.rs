@@ -0,0 +1,178 @@
+use crate::storage::platform::to_index_path;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// A single pattern of a pathspec
+#[derive(Debug, Clone)]
+enum Pattern {
+    /// A path without wildcards, matching itself and everything below it
+    Prefix(PathBuf),
+    /// A shell glob, `*` and `?` match `/` as well, like in git
+    Glob(Regex),
+}
+
+impl Pattern {
+    fn parse(spec: &str) -> Result<Self> {
+        if !spec.contains(['*', '?', '[']) {
+            return Ok(Self::Prefix(to_index_path(Path::new(spec))));
+        }
+        let spec = spec.strip_prefix("./").unwrap_or(spec);
+        let regex = Regex::new(&glob_to_regex(spec))
+            .with_context(|| format!("Invalid pathspec '{}'", spec))?;
+        Ok(Self::Glob(regex))
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        match self {
+            Self::Prefix(prefix) => path.starts_with(prefix),
+            Self::Glob(regex) => regex.is_match(&path.to_string_lossy()),
+        }
+    }
+}
+
+/// Limits a command to some of the files of the repository
+///
+/// Each pattern is either:
+/// - a path, matching the file itself or every file in the directory
+/// - a glob such as `src/*.rs`, where `*` also matches across directories
+/// - an exclusion, `:!pattern`, `:^pattern` or `:(exclude)pattern`, removing
+///   the files matched by `pattern`
+///
+/// An empty pathspec, or one made only of exclusions, starts from every file.
+/// Paths are relative to the repository root.
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Pathspec {
+    /// Parses the patterns given on the command line
+    pub fn new<S: AsRef<str>>(specs: &[S]) -> Result<Self> {
+        let mut pathspec = Self::default();
+        for spec in specs {
+            let spec = spec.as_ref();
+            let excluded = [":!", ":^", ":(exclude)"]
+                .iter()
+                .find_map(|magic| spec.strip_prefix(magic));
+            match excluded {
+                Some(pattern) => pathspec.exclude.push(Pattern::parse(pattern)?),
+                None => pathspec.include.push(Pattern::parse(spec)?),
+            }
+        }
+        Ok(pathspec)
+    }
+
+    /// Returns true if the pathspec matches every file
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Returns the paths the selected files are all in or below, when every
+    /// included pattern is a path without wildcards
+    ///
+    /// A file none of these paths lead to is never selected.
+    pub fn literal_paths(&self) -> Option<Vec<&Path>> {
+        let mut paths = Vec::new();
+        for pattern in &self.include {
+            match pattern {
+                Pattern::Prefix(prefix) if !prefix.as_os_str().is_empty() => paths.push(&**prefix),
+                _ => return None,
+            }
+        }
+        (!paths.is_empty()).then_some(paths)
+    }
+
+    /// Returns true if the file at `path` is selected
+    pub fn matches(&self, path: &Path) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(path));
+        included && !self.exclude.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// Translates a shell glob into an anchored regular expression
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let (mut class, mut raw) = (String::new(), String::from("["));
+                if let Some(negation) = chars.next_if(|&c| c == '!' || c == '^') {
+                    class.push('^');
+                    raw.push(negation);
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' && !class.trim_start_matches('^').is_empty() {
+                        closed = true;
+                        break;
+                    }
+                    // Characters regex classes give a meaning to are literal
+                    if matches!(c, '\\' | '[' | ']' | '&' | '~') {
+                        class.push('\\');
+                    }
+                    class.push(c);
+                    raw.push(c);
+                }
+                match closed {
+                    true => regex.push_str(&format!("[{}]", class)),
+                    // An unclosed bracket is a literal character
+                    false => regex.push_str(&regex::escape(&raw)),
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    // Like a plain path, a glob matching a directory matches what it holds
+    regex.push_str("(/.*)?$");
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pathspec_patterns() -> Result<()> {
+        let matches = |specs: &[&str], path: &str| -> Result<bool> {
+            Ok(Pathspec::new(specs)?.matches(Path::new(path)))
+        };
+
+        assert!(matches(&[], "src/main.rs")?);
+        assert!(matches(&["."], "src/main.rs")?);
+        assert!(matches(&["src"], "src/main.rs")?);
+        assert!(matches(&["./src/"], "src/main.rs")?);
+        assert!(!matches(&["src"], "src2/lib.rs")?);
+        assert!(matches(&["*.rs"], "src/main.rs")?);
+        assert!(matches(&["src/*.rs"], "src/commands/log.rs")?);
+        assert!(!matches(&["src/*.rs"], "README.md")?);
+        assert!(matches(&["src/ma?n.rs"], "src/main.rs")?);
+        assert!(matches(&["[rR]EADME.*"], "README.md")?);
+        assert!(!matches(&["[!rR]EADME.*"], "README.md")?);
+        assert!(matches(&["s*"], "src/main.rs")?);
+        assert!(matches(&["a+b.txt"], "a+b.txt")?);
+        assert!(matches(&["[unclosed"], "[unclosed")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pathspec_exclusions() -> Result<()> {
+        let pathspec = Pathspec::new(&[":!*.md", ":(exclude)tests"])?;
+        assert!(pathspec.matches(Path::new("src/main.rs")));
+        assert!(!pathspec.matches(Path::new("docs/guide.md")));
+        assert!(!pathspec.matches(Path::new("tests/cli.rs")));
+
+        let pathspec = Pathspec::new(&["src", ":^src/generated"])?;
+        assert!(pathspec.matches(Path::new("src/main.rs")));
+        assert!(!pathspec.matches(Path::new("src/generated/api.rs")));
+        assert!(!pathspec.matches(Path::new("README.md")));
+        assert_eq!(pathspec.literal_paths(), Some(vec![Path::new("src")]));
+        assert_eq!(Pathspec::new(&["src/*.rs"])?.literal_paths(), None);
+        Ok(())
+    }
+}
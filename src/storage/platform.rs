@@ -0,0 +1,303 @@
+use anyhow::{Context, Result};
+use std::fs::{self, Metadata};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Mode of a regular file
+pub const MODE_FILE: u32 = 0o100644;
+/// Mode of an executable file
+pub const MODE_EXEC: u32 = 0o100755;
+/// Mode of a directory
+pub const MODE_DIR: u32 = 0o040000;
+/// Mode of a symbolic link, whose blob holds the link target
+pub const MODE_SYMLINK: u32 = 0o120000;
+/// Mode of a submodule entry, whose hash is a commit of the nested repository
+pub const MODE_GITLINK: u32 = 0o160000;
+/// Bits of a mode that encode the file type
+pub const MODE_TYPE_MASK: u32 = 0o170000;
+
+/// File metadata recorded in the index, independent of the platform
+///
+/// Unix provides every field. Elsewhere `dev`, `ino`, `uid` and `gid` aren't
+/// available and are always 0, and the mode is synthesized from the file type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStat {
+    pub mtime: u64,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl FileStat {
+    /// Reads the metadata of the file at `path`, without following symlinks
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let metadata = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to read metadata of {}", path.display()))?;
+        Ok(Self::from_metadata(&metadata))
+    }
+
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            mtime: metadata.mtime() as u64,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode: metadata.mode(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            mtime: mtime_secs(metadata),
+            mode: if metadata.is_symlink() {
+                MODE_SYMLINK
+            } else if metadata.is_dir() {
+                MODE_DIR
+            } else {
+                MODE_FILE
+            },
+            size: metadata.len() as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the file looks unchanged compared to `other`
+    ///
+    /// Only mtime, size and file type are compared, the other fields aren't
+    /// stable across platforms and file systems
+    pub fn matches(&self, other: &FileStat) -> bool {
+        self.mtime == other.mtime
+            && self.size == other.size
+            && self.is_symlink() == other.is_symlink()
+            && self.is_executable() == other.is_executable()
+    }
+
+    /// Returns true if the file is a regular file with an execute bit set
+    pub fn is_executable(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_FILE & MODE_TYPE_MASK && self.mode & 0o111 != 0
+    }
+
+    /// Returns the mode recorded in trees for this file: `100644`, `100755`,
+    /// `120000` or `160000`
+    pub fn tree_mode(&self) -> u32 {
+        if self.mode & MODE_TYPE_MASK == MODE_GITLINK {
+            MODE_GITLINK
+        } else if self.is_symlink() {
+            MODE_SYMLINK
+        } else if self.is_executable() {
+            MODE_EXEC
+        } else {
+            MODE_FILE
+        }
+    }
+
+    /// Returns true if the file is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_SYMLINK
+    }
+}
+
+/// Returns the modification time of `metadata` in whole seconds
+pub fn mtime_secs(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Converts a repository-relative path into the form stored in the index
+///
+/// Components are always separated by `/`, whatever the platform, and `.`
+/// components are dropped
+pub fn to_index_path(path: &Path) -> PathBuf {
+    let components: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            Component::ParentDir => Some("..".into()),
+            _ => None,
+        })
+        .collect();
+
+    PathBuf::from(components.join("/"))
+}
+
+/// Returns true if the file described by `metadata` is executable
+///
+/// Windows has no execute bit, so files are never executable there
+pub fn is_executable(metadata: &Metadata) -> bool {
+    FileStat::from_metadata(metadata).is_executable()
+}
+
+/// Sets or clears the execute bits of the file at `path`
+///
+/// Execute bits are added wherever the file is readable, like `chmod +x`
+#[cfg(unix)]
+pub fn set_executable(path: &Path, executable: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+        .permissions();
+    let mode = permissions.mode();
+    let new_mode = if executable {
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode & !0o111
+    };
+
+    if new_mode != mode {
+        permissions.set_mode(new_mode);
+        fs::set_permissions(path, permissions)
+            .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Sets or clears the execute bits of the file at `path`
+///
+/// Windows has no execute bit, so this does nothing
+#[cfg(not(unix))]
+pub fn set_executable(_path: &Path, _executable: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Returns true if `path` is a symbolic link, dangling or not
+pub fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink())
+}
+
+/// Reads the target of the symbolic link at `path`
+///
+/// The target is stored with `/` separators so the blob is the same on
+/// every platform
+pub fn read_link_target(path: &Path) -> Result<String> {
+    let target = fs::read_link(path)
+        .with_context(|| format!("Failed to read symlink {}", path.display()))?;
+    let target = target
+        .to_str()
+        .with_context(|| format!("Invalid UTF-8 symlink target in {}", path.display()))?;
+
+    Ok(if cfg!(windows) {
+        target.replace('\\', "/")
+    } else {
+        target.to_string()
+    })
+}
+
+/// Creates a symbolic link at `path` pointing to `target`
+#[cfg(unix)]
+pub fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("Failed to create symlink {}", path.display()))
+}
+
+/// Creates a symbolic link at `path` pointing to `target`
+///
+/// Creating symlinks needs special privileges on Windows, so when that fails
+/// the link is checked out as a plain file containing the target
+#[cfg(windows)]
+pub fn create_symlink(target: &str, path: &Path) -> Result<()> {
+    let native = target.replace('/', "\\");
+    let is_dir = path
+        .parent()
+        .map(|parent| parent.join(&native).is_dir())
+        .unwrap_or(false);
+    let linked = if is_dir {
+        std::os::windows::fs::symlink_dir(&native, path)
+    } else {
+        std::os::windows::fs::symlink_file(&native, path)
+    };
+
+    match linked {
+        Ok(()) => Ok(()),
+        Err(_) => fs::write(path, target)
+            .with_context(|| format!("Failed to write {}", path.display())),
+    }
+}
+
+/// Returns the user's home directory
+pub fn home_dir() -> Result<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .context("Couldn't find the home directory ($HOME or %USERPROFILE%)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_to_index_path() {
+        assert_eq!(to_index_path(Path::new("./src/main.rs")), PathBuf::from("src/main.rs"));
+        assert_eq!(to_index_path(Path::new("a.txt")), PathBuf::from("a.txt"));
+        assert_eq!(
+            to_index_path(&Path::new("src").join("index").join("mod.rs")),
+            PathBuf::from("src/index/mod.rs")
+        );
+    }
+
+    #[test]
+    fn test_file_stat() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "hello")?;
+
+        let stat = FileStat::from_path(&path)?;
+        assert_eq!(stat.size, 5);
+        assert_eq!(stat.mode & 0o170000, MODE_FILE & 0o170000);
+        assert_eq!(stat.mtime, mtime_secs(&fs::metadata(&path)?));
+        assert!(stat.matches(&FileStat::from_path(&path)?));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_executable_bit() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("run.sh");
+        fs::write(&path, "#!/bin/sh\n")?;
+
+        let before = FileStat::from_path(&path)?;
+        assert_eq!(before.tree_mode(), MODE_FILE);
+
+        set_executable(&path, true)?;
+        let after = FileStat::from_path(&path)?;
+        assert_eq!(after.tree_mode(), MODE_EXEC);
+        assert!(!after.matches(&before));
+
+        set_executable(&path, false)?;
+        assert_eq!(FileStat::from_path(&path)?.tree_mode(), MODE_FILE);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let link = dir.path().join("link");
+        create_symlink("missing/target.txt", &link)?;
+
+        assert!(is_symlink(&link));
+        assert!(!link.exists());
+        assert_eq!(read_link_target(&link)?, "missing/target.txt");
+
+        let stat = FileStat::from_path(&link)?;
+        assert!(stat.is_symlink());
+        assert_eq!(stat.size as usize, "missing/target.txt".len());
+        Ok(())
+    }
+}
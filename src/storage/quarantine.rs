@@ -0,0 +1,187 @@
+use crate::commands::clone::clone::local_repository_path;
+use crate::commands::config::config::{Config, PersistentConfig};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::graph::walk_reachable;
+use crate::storage::objects::pack::Packfile;
+use crate::storage::objects::{loose_object_path, verify_loose_object, ObjectStorage};
+use crate::storage::refs::{list_all_refs, resolve_head};
+use crate::storage::repo_config::find_vox_dir;
+use anyhow::{Context, Result};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Directory of the `.vox` directory corrupt objects are moved to
+pub const LOST_FOUND_DIR: &str = "lost-found";
+
+thread_local! {
+    static RECOVERING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns true while the refs affected by a corrupt object are looked up,
+/// so that running into another one doesn't start over
+pub fn is_recovering() -> bool {
+    RECOVERING.with(Cell::get)
+}
+
+/// Moves the loose object `hash` of `objects_dir` to `.vox/lost-found/<hash>`
+///
+/// # Returns
+///
+/// Where the object went, `None` if `objects_dir` doesn't have it: an object
+/// read from an alternate belongs to another repository and stays there
+pub fn quarantine(hash: &str, objects_dir: &Path) -> Result<Option<PathBuf>> {
+    let path = objects_dir.join(&hash[..2]).join(&hash[2..]);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let vox_dir = objects_dir.parent().context("Invalid objects directory")?;
+    let lost_found = vox_dir.join(LOST_FOUND_DIR);
+    fs::create_dir_all(&lost_found)
+        .with_context(|| format!("Failed to create {}", lost_found.display()))?;
+
+    let target = lost_found.join(hash);
+    fs::rename(&path, &target)
+        .with_context(|| format!("Failed to move object {} to {}", hash, target.display()))?;
+    Ok(Some(target))
+}
+
+/// Stores a good copy of the object `hash` in `objects_dir`, taken from its
+/// alternates, its packs or the repositories of its remotes, in that order
+///
+/// # Returns
+///
+/// Where the copy came from, `None` if no good copy was found
+pub fn recover(hash: &str, objects_dir: &Path) -> Result<Option<String>> {
+    // A good copy in an alternate is read once the local one is gone
+    if let Some(path) = loose_object_path(hash, objects_dir)?
+        && verify_loose_object(hash, objects_dir).is_ok()
+    {
+        return Ok(Some(path.display().to_string()));
+    }
+
+    let format = ObjectFormat::for_objects_dir(objects_dir)?;
+    if let Some(pack) = unpack_from_packs(hash, objects_dir, objects_dir, format)? {
+        return Ok(Some(pack.display().to_string()));
+    }
+
+    for (name, remote_objects) in remote_objects_dirs(objects_dir)? {
+        if let Some(source) = loose_object_path(hash, &remote_objects)?
+            && verify_loose_object(hash, &remote_objects).is_ok()
+        {
+            let path = objects_dir.join(&hash[..2]).join(&hash[2..]);
+            fs::create_dir_all(path.parent().context("Invalid object path")?)?;
+            fs::copy(&source, &path)
+                .with_context(|| format!("Failed to copy object {} from {}", hash, name))?;
+            return Ok(Some(format!("remote '{}'", name)));
+        }
+        if unpack_from_packs(hash, &remote_objects, objects_dir, format)?.is_some() {
+            return Ok(Some(format!("remote '{}'", name)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the names of HEAD and the refs, of every namespace, whose history
+/// reaches the object `hash`
+pub fn affected_refs(hash: &str, vox_dir: &Path, objects_dir: &Path) -> Result<Vec<String>> {
+    let mut tips: Vec<(String, String)> =
+        resolve_head(vox_dir)?.map(|head| ("HEAD".to_string(), head)).into_iter().collect();
+    tips.extend(list_all_refs(vox_dir)?);
+
+    let previous = RECOVERING.with(|recovering| recovering.replace(true));
+    // History that can't be read past another corrupt object doesn't count
+    let affected = tips
+        .into_iter()
+        .filter(|(_, tip)| {
+            walk_reachable(vec![tip.clone()], objects_dir, None)
+                .is_ok_and(|reachable| reachable.contains(hash))
+        })
+        .map(|(name, _)| name)
+        .collect();
+    RECOVERING.with(|recovering| recovering.set(previous));
+    Ok(affected)
+}
+
+/// Stores `hash` in `objects_dir` from the first pack of `packs_dir` that has
+/// it, returning that pack
+fn unpack_from_packs(
+    hash: &str,
+    packs_dir: &Path,
+    objects_dir: &Path,
+    format: ObjectFormat,
+) -> Result<Option<PathBuf>> {
+    for pack in ObjectStorage::at(packs_dir).packs()? {
+        let file =
+            File::open(&pack).with_context(|| format!("Failed to read {}", pack.display()))?;
+        // A damaged pack is no help, others may still have the object
+        let unpacked = Packfile::unpack_object(BufReader::new(file), hash, objects_dir, format);
+        if unpacked.unwrap_or(false) {
+            return Ok(Some(pack));
+        }
+    }
+    Ok(None)
+}
+
+/// Objects directories of the remotes of the repository of `objects_dir`
+/// that are local repositories, by remote name
+fn remote_objects_dirs(objects_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let config_path = match objects_dir.parent() {
+        Some(vox_dir) => vox_dir.join(".voxconfig"),
+        None => return Ok(Vec::new()),
+    };
+    if !config_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let config = Config::read_from_file(&config_path)?;
+    let own = fs::canonicalize(objects_dir).ok();
+    Ok(config
+        .remotes()
+        .iter()
+        .filter_map(|remote| {
+            let path = local_repository_path(remote.url()?.as_str()).ok()?;
+            let remote_objects = find_vox_dir(&path)?.join("objects");
+            let same = fs::canonicalize(&remote_objects).ok() == own;
+            (!same).then(|| (remote.name().to_string(), remote_objects))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::blob::Blob;
+    use crate::storage::objects::{read_raw_object, write_alternates, Storable};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_quarantine_and_recover() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        let backup = tempdir()?;
+        let hash = Blob {
+            data: b"precious".to_vec(),
+        }
+        .save(&objects_dir)?;
+        Blob {
+            data: b"precious".to_vec(),
+        }
+        .save(backup.path())?;
+
+        let path = objects_dir.join(&hash[..2]).join(&hash[2..]);
+        fs::write(&path, b"not zlib")?;
+        let moved = quarantine(&hash, &objects_dir)?.unwrap();
+        assert_eq!(moved, dir.path().join(LOST_FOUND_DIR).join(&hash));
+        assert!(!path.exists());
+        assert_eq!(recover(&hash, &objects_dir)?, None);
+
+        // Reading the corrupt object recovers it from the alternate
+        fs::write(&path, b"not zlib")?;
+        write_alternates(&objects_dir, &[backup.path().to_path_buf()])?;
+        assert_eq!(read_raw_object(&hash, &objects_dir)?.1, b"precious");
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use crate::storage::reflog::{append_reflog, delete_reflog, ReflogEntry};
+use crate::storage::refs::ref_path;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Value a ref must have for an update to be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedValue {
+    /// Don't check the current value
+    Any,
+    /// The ref must not exist yet
+    Missing,
+    /// The ref must point to this hash
+    Hash(String),
+}
+
+struct RefUpdate {
+    ref_name: String,
+    expected: ExpectedValue,
+    /// New value of the ref, `None` deletes it
+    new_hash: Option<String>,
+    message: String,
+}
+
+/// A set of ref updates that are applied all-or-nothing
+///
+/// Every ref is locked by creating `<ref>.lock` next to it. Once all locks are
+/// held the current values are compared against the expected ones, the new
+/// values are written to the lock files and then renamed into place. If
+/// anything fails before that point, the locks are released and no ref changes.
+///
+/// # Example
+///
+/// ```no_run
+/// # use vox::storage::ref_transaction::{ExpectedValue, RefTransaction};
+/// # use vox::storage::utils::VOX_DIR;
+/// # fn main() -> anyhow::Result<()> {
+/// # let (old, new) = ("a1a".to_string(), "b0b");
+/// let mut transaction = RefTransaction::new(&VOX_DIR);
+/// transaction.update("refs/heads/main", new, ExpectedValue::Hash(old), "commit: Fix typo");
+/// transaction.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RefTransaction {
+    vox_dir: PathBuf,
+    updates: Vec<RefUpdate>,
+}
+
+impl RefTransaction {
+    /// Starts an empty transaction on the repository at `vox_dir`
+    pub fn new(vox_dir: &Path) -> Self {
+        Self {
+            vox_dir: vox_dir.to_path_buf(),
+            updates: Vec::new(),
+        }
+    }
+
+    /// Queues setting `ref_name` to `new_hash` if it currently matches `expected`
+    pub fn update(
+        &mut self,
+        ref_name: &str,
+        new_hash: &str,
+        expected: ExpectedValue,
+        message: &str,
+    ) -> &mut Self {
+        self.updates.push(RefUpdate {
+            ref_name: ref_name.to_string(),
+            expected,
+            new_hash: Some(new_hash.to_string()),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// Queues creating `ref_name`, failing if it already exists
+    pub fn create(&mut self, ref_name: &str, new_hash: &str, message: &str) -> &mut Self {
+        self.update(ref_name, new_hash, ExpectedValue::Missing, message)
+    }
+
+    /// Queues removing `ref_name` together with its reflog
+    pub fn delete(&mut self, ref_name: &str, expected: ExpectedValue) -> &mut Self {
+        self.updates.push(RefUpdate {
+            ref_name: ref_name.to_string(),
+            expected,
+            new_hash: None,
+            message: String::new(),
+        });
+        self
+    }
+
+    /// Applies every queued update
+    ///
+    /// Updates are also recorded in the reflog. When the branch HEAD points to
+    /// is updated, the change is logged for HEAD as well.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error without changing any ref if a ref is already locked by
+    /// another process or doesn't have its expected value
+    pub fn commit(self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for update in &self.updates {
+            check_ref_name(&update.ref_name)?;
+            if !seen.insert(update.ref_name.as_str()) {
+                return Err(anyhow!(
+                    "Ref '{}' is updated twice in one transaction",
+                    update.ref_name
+                ));
+            }
+        }
+
+        // Lock everything first, so no one can change the refs while they're verified
+        let mut locks = Vec::with_capacity(self.updates.len());
+        for update in &self.updates {
+            locks.push(LockFile::acquire(&ref_path(&self.vox_dir, &update.ref_name))?);
+        }
+
+        let mut old_values = Vec::with_capacity(self.updates.len());
+        for update in &self.updates {
+            let current = read_ref_value(&ref_path(&self.vox_dir, &update.ref_name))?;
+            verify(&update.ref_name, &update.expected, current.as_deref())?;
+            old_values.push(current);
+        }
+
+        for (update, lock) in self.updates.iter().zip(locks.iter_mut()) {
+            if let Some(new_hash) = &update.new_hash {
+                lock.write(new_hash)?;
+            }
+        }
+
+        // Nothing can be rejected anymore, move the new values into place
+        for (update, lock) in self.updates.iter().zip(locks) {
+            match update.new_hash {
+                Some(_) => lock.commit()?,
+                None => {
+                    let path = ref_path(&self.vox_dir, &update.ref_name);
+                    if path.exists() {
+                        fs::remove_file(&path).with_context(|| {
+                            format!("Failed to delete ref: {}", update.ref_name)
+                        })?;
+                    }
+                }
+            }
+        }
+
+        self.write_reflogs(&old_values)
+    }
+
+    fn write_reflogs(&self, old_values: &[Option<String>]) -> Result<()> {
+        let head_target = symbolic_head(&self.vox_dir)?;
+        let head_updated = self.updates.iter().any(|u| u.ref_name == "HEAD");
+
+        for (update, old_hash) in self.updates.iter().zip(old_values) {
+            let Some(new_hash) = &update.new_hash else {
+                delete_reflog(&self.vox_dir, &update.ref_name)?;
+                continue;
+            };
+
+            let entry =
+                ReflogEntry::new(&self.vox_dir, old_hash.as_deref(), new_hash, &update.message)?;
+            append_reflog(&self.vox_dir, &update.ref_name, &entry)?;
+
+            if !head_updated && head_target.as_deref() == Some(update.ref_name.as_str()) {
+                append_reflog(&self.vox_dir, "HEAD", &entry)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A `<path>.lock` file that is removed again unless committed
+struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    committed: bool,
+}
+
+impl LockFile {
+    fn acquire(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut lock_name = path.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let lock_path = PathBuf::from(lock_name);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => anyhow!(
+                    "Unable to lock '{}': it already exists. Another vox process may be running",
+                    lock_path.display()
+                ),
+                _ => anyhow!("Unable to lock '{}': {}", lock_path.display(), e),
+            })?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+            committed: false,
+        })
+    }
+
+    fn write(&mut self, hash: &str) -> Result<()> {
+        let file = self.file.as_mut().context("Lock file is already closed")?;
+        file.write_all(format!("{}\n", hash).as_bytes())?;
+        file.sync_all()
+            .with_context(|| format!("Failed to write {}", self.lock_path.display()))
+    }
+
+    fn commit(mut self) -> Result<()> {
+        // Close the handle first, renaming an open file fails on Windows
+        self.file.take();
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("Failed to update ref: {}", self.path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.file.take();
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Rejects ref names that could escape the `.vox` directory or clash with lock files
+fn check_ref_name(ref_name: &str) -> Result<()> {
+    let valid = !ref_name.is_empty()
+        && !ref_name.starts_with('/')
+        && !ref_name.ends_with('/')
+        && !ref_name.ends_with(".lock")
+        && ref_name
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+        && (ref_name == "HEAD" || ref_name.starts_with("refs/"));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid ref name: '{}'", ref_name))
+    }
+}
+
+fn read_ref_value(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let value = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ref: {}", path.display()))?;
+    let value = value.trim();
+    Ok((!value.is_empty()).then(|| value.to_string()))
+}
+
+fn verify(ref_name: &str, expected: &ExpectedValue, current: Option<&str>) -> Result<()> {
+    match (expected, current) {
+        (ExpectedValue::Any, _) | (ExpectedValue::Missing, None) => Ok(()),
+        (ExpectedValue::Missing, Some(_)) => Err(anyhow!("Ref '{}' already exists", ref_name)),
+        (ExpectedValue::Hash(hash), Some(current)) if hash == current => Ok(()),
+        (ExpectedValue::Hash(hash), current) => Err(anyhow!(
+            "Ref '{}' was changed concurrently: expected {}, found {}",
+            ref_name,
+            hash,
+            current.unwrap_or("nothing")
+        )),
+    }
+}
+
+/// Returns the ref HEAD points to, or `None` if HEAD is detached
+fn symbolic_head(vox_dir: &Path) -> Result<Option<String>> {
+    Ok(read_ref_value(&vox_dir.join("HEAD"))?
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::reflog::read_reflog;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_create_and_update() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("HEAD"), "ref: refs/heads/main\n")?;
+
+        let mut transaction = RefTransaction::new(dir.path());
+        transaction.create("refs/heads/main", "aaaa", "commit (initial): a");
+        transaction.commit()?;
+
+        let mut transaction = RefTransaction::new(dir.path());
+        transaction.update(
+            "refs/heads/main",
+            "bbbb",
+            ExpectedValue::Hash("aaaa".to_string()),
+            "commit: b",
+        );
+        transaction.commit()?;
+
+        let main = dir.path().join("refs/heads/main");
+        assert_eq!(fs::read_to_string(&main)?, "bbbb\n");
+        assert!(!dir.path().join("refs/heads/main.lock").exists());
+        assert_eq!(read_reflog(dir.path(), "refs/heads/main")?.len(), 2);
+        assert_eq!(read_reflog(dir.path(), "HEAD")?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_transaction_changes_nothing() -> Result<()> {
+        let dir = tempdir()?;
+        let mut transaction = RefTransaction::new(dir.path());
+        transaction
+            .create("refs/heads/main", "aaaa", "branch: Created")
+            .create("refs/heads/dev", "aaaa", "branch: Created");
+        transaction.commit()?;
+
+        let mut transaction = RefTransaction::new(dir.path());
+        transaction
+            .update("refs/heads/dev", "cccc", ExpectedValue::Any, "reset")
+            .update(
+                "refs/heads/main",
+                "cccc",
+                ExpectedValue::Hash("bbbb".to_string()),
+                "commit: c",
+            );
+        assert!(transaction.commit().is_err());
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("refs/heads/dev"))?,
+            "aaaa\n"
+        );
+        assert!(!dir.path().join("refs/heads/dev.lock").exists());
+        assert!(!dir.path().join("refs/heads/main.lock").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_locked_ref_and_invalid_names() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("refs/heads"))?;
+        fs::write(dir.path().join("refs/heads/main.lock"), "")?;
+
+        let mut transaction = RefTransaction::new(dir.path());
+        transaction.update("refs/heads/main", "aaaa", ExpectedValue::Any, "commit: a");
+        assert!(transaction.commit().is_err());
+        assert!(dir.path().join("refs/heads/main.lock").exists());
+
+        for name in ["refs/heads/../../x", "objects/ab", "refs/heads/x.lock"] {
+            let mut transaction = RefTransaction::new(dir.path());
+            transaction.update(name, "aaaa", ExpectedValue::Any, "");
+            assert!(transaction.commit().is_err());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use crate::commands::config::commands::user_identity;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::refs::namespaced;
+use crate::storage::utils::VOX_DIR;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A single entry of a ref's log
+///
+/// Stored one per line in `.vox/logs/<ref>` as:
+/// `<old> <new> <identity> <timestamp> +0000\t<message>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// Hash the ref pointed to before the update (all zeros if it didn't exist)
+    pub old_hash: String,
+    /// Hash the ref points to after the update
+    pub new_hash: String,
+    /// Who performed the update ("Name <email>")
+    pub identity: String,
+    /// When the update happened
+    pub timestamp: DateTime<Utc>,
+    /// Short description of the operation (e.g. "commit: Fix typo")
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Creates an entry for an update made now by the configured user
+    ///
+    /// # Arguments
+    ///
+    /// * `vox_dir` - Path to the `.vox` directory, used to pick the null hash
+    /// * `old_hash` - Previous value of the ref, `None` if it was just created
+    /// * `new_hash` - New value of the ref
+    /// * `message` - Description of the operation
+    pub fn new(vox_dir: &Path, old_hash: Option<&str>, new_hash: &str, message: &str) -> Result<Self> {
+        let old_hash = match old_hash {
+            Some(hash) => hash.to_string(),
+            None => ObjectFormat::for_vox_dir(vox_dir)?.null_id().to_hex(),
+        };
+
+        Ok(Self {
+            old_hash,
+            new_hash: new_hash.to_string(),
+            identity: user_identity(),
+            timestamp: Utc::now(),
+            message: message.to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {} +0000\t{}\n",
+            self.old_hash,
+            self.new_hash,
+            self.identity,
+            self.timestamp.timestamp(),
+            self.message.lines().next().unwrap_or("")
+        )
+    }
+
+    fn parse(line: &str) -> Result<Self> {
+        let (header, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let mut hashes = header.splitn(3, ' ');
+        let old_hash = hashes.next().unwrap_or_default();
+        let new_hash = hashes.next().context("Missing new hash in reflog entry")?;
+        let rest = hashes.next().context("Missing identity in reflog entry")?;
+
+        // The identity may contain spaces, so the time fields are taken from the end
+        let mut tail = rest.rsplitn(3, ' ');
+        let _tz = tail.next();
+        let timestamp = tail
+            .next()
+            .and_then(|ts| ts.parse::<i64>().ok())
+            .and_then(|ts| Utc.timestamp_opt(ts, 0).single())
+            .context("Invalid timestamp in reflog entry")?;
+        let identity = tail.next().unwrap_or_default();
+
+        Ok(Self {
+            old_hash: old_hash.to_string(),
+            new_hash: new_hash.to_string(),
+            identity: identity.to_string(),
+            timestamp,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// Path of the log file for `ref_name` (e.g. "HEAD" or "refs/heads/main")
+///
+/// Logs of namespaced refs live under the namespace too.
+pub fn reflog_path(vox_dir: &Path, ref_name: &str) -> PathBuf {
+    vox_dir.join("logs").join(namespaced(ref_name))
+}
+
+/// Expands a short ref name into the name its log is stored under
+///
+/// "HEAD" and names starting with "refs/" are kept as is, everything else is
+/// treated as a branch name
+pub fn full_ref_name(name: &str) -> String {
+    if name.is_empty() {
+        "HEAD".to_string()
+    } else if name == "HEAD" || name.starts_with("refs/") {
+        name.to_string()
+    } else {
+        format!("refs/heads/{}", name)
+    }
+}
+
+/// Appends an entry to the log of `ref_name`
+///
+/// # Arguments
+///
+/// * `vox_dir` - Path to the `.vox` directory
+/// * `ref_name` - Full name of the updated ref
+/// * `entry` - The entry to record
+pub fn append_reflog(vox_dir: &Path, ref_name: &str, entry: &ReflogEntry) -> Result<()> {
+    let path = reflog_path(vox_dir, ref_name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open reflog: {}", path.display()))?;
+    file.write_all(entry.to_line().as_bytes())
+        .with_context(|| format!("Failed to write reflog: {}", path.display()))
+}
+
+/// Records an update of `ref_name` in the current repository
+///
+/// # Arguments
+///
+/// * `ref_name` - Full name of the updated ref
+/// * `old_hash` - Previous value of the ref, `None` if it was just created
+/// * `new_hash` - New value of the ref
+/// * `message` - Description of the operation
+pub fn record_ref_update(
+    ref_name: &str,
+    old_hash: Option<&str>,
+    new_hash: &str,
+    message: &str,
+) -> Result<()> {
+    let entry = ReflogEntry::new(&VOX_DIR, old_hash, new_hash, message)?;
+    append_reflog(&VOX_DIR, ref_name, &entry)
+}
+
+/// Reads the log of `ref_name`, oldest entry first
+///
+/// A ref without a log yields an empty list
+pub fn read_reflog(vox_dir: &Path, ref_name: &str) -> Result<Vec<ReflogEntry>> {
+    read_log_file(&reflog_path(vox_dir, ref_name))
+}
+
+fn read_log_file(path: &Path) -> Result<Vec<ReflogEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read reflog: {}", path.display()))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+/// Removes the log of `ref_name`, if any
+pub fn delete_reflog(vox_dir: &Path, ref_name: &str) -> Result<()> {
+    let path = reflog_path(vox_dir, ref_name);
+    if path.exists() {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove reflog: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Returns every hash mentioned in any log, those of every namespace
+/// included, so history reachable only through the reflog isn't considered
+/// garbage
+pub fn reflog_hashes(vox_dir: &Path) -> Result<Vec<String>> {
+    let logs_dir = vox_dir.join("logs");
+    let mut hashes = Vec::new();
+
+    if !logs_dir.exists() {
+        return Ok(hashes);
+    }
+
+    for file in WalkDir::new(&logs_dir).min_depth(1) {
+        let file = file.context("Failed to read logs directory")?;
+        if !file.file_type().is_file() {
+            continue;
+        }
+
+        for entry in read_log_file(file.path())? {
+            for hash in [entry.old_hash, entry.new_hash] {
+                if hash.chars().any(|c| c != '0') {
+                    hashes.push(hash);
+                }
+            }
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Splits a `<ref>@{<n>}` selector into the ref name and the entry index
+///
+/// # Examples
+///
+/// "HEAD@{2}" => Some(("HEAD", 2))
+/// "main" => None
+pub fn parse_reflog_selector(spec: &str) -> Option<(&str, usize)> {
+    let (name, rest) = spec.split_once("@{")?;
+    let index = rest.strip_suffix('}')?.parse().ok()?;
+    Some((name, index))
+}
+
+/// Resolves a `<ref>@{<n>}` selector to the hash the ref pointed to `n`
+/// updates ago
+///
+/// # Returns
+///
+/// `None` if `spec` isn't a reflog selector
+///
+/// # Errors
+///
+/// Returns an error if the log doesn't have enough entries
+pub fn resolve_reflog_selector(vox_dir: &Path, spec: &str) -> Result<Option<String>> {
+    let Some((name, index)) = parse_reflog_selector(spec) else {
+        return Ok(None);
+    };
+
+    let ref_name = full_ref_name(name);
+    let entries = read_reflog(vox_dir, &ref_name)?;
+
+    entries
+        .iter()
+        .rev()
+        .nth(index)
+        .map(|entry| Some(entry.new_hash.clone()))
+        .ok_or_else(|| {
+            anyhow!(
+                "Log for '{}' only has {} entries",
+                if name.is_empty() { "HEAD" } else { name },
+                entries.len()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(old: &str, new: &str, message: &str) -> ReflogEntry {
+        ReflogEntry {
+            old_hash: old.to_string(),
+            new_hash: new.to_string(),
+            identity: "Jane Doe <jane@example.com>".to_string(),
+            timestamp: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_append_and_read() -> Result<()> {
+        let dir = tempdir()?;
+        let first = entry("0000", "aaaa", "commit (initial): first");
+        let second = entry("aaaa", "bbbb", "commit: second");
+
+        append_reflog(dir.path(), "refs/heads/main", &first)?;
+        append_reflog(dir.path(), "refs/heads/main", &second)?;
+
+        assert_eq!(
+            read_reflog(dir.path(), "refs/heads/main")?,
+            vec![first, second]
+        );
+        assert!(read_reflog(dir.path(), "HEAD")?.is_empty());
+        assert_eq!(reflog_hashes(dir.path())?, vec!["aaaa", "aaaa", "bbbb"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_selector() -> Result<()> {
+        let dir = tempdir()?;
+        append_reflog(dir.path(), "HEAD", &entry("0000", "aaaa", "commit: a"))?;
+        append_reflog(dir.path(), "HEAD", &entry("aaaa", "bbbb", "commit: b"))?;
+
+        assert_eq!(parse_reflog_selector("main@{3}"), Some(("main", 3)));
+        assert_eq!(parse_reflog_selector("main"), None);
+        assert_eq!(
+            resolve_reflog_selector(dir.path(), "HEAD@{0}")?,
+            Some("bbbb".to_string())
+        );
+        assert_eq!(
+            resolve_reflog_selector(dir.path(), "HEAD@{1}")?,
+            Some("aaaa".to_string())
+        );
+        assert!(resolve_reflog_selector(dir.path(), "HEAD@{2}").is_err());
+        assert_eq!(resolve_reflog_selector(dir.path(), "HEAD")?, None);
+        Ok(())
+    }
+}
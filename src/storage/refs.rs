@@ -1,17 +1,98 @@
-use anyhow::Context;
-use std::path::Path;
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use tokio::fs;
-use tokio::io;
+use tokio::io::{self, AsyncWriteExt};
+use walkdir::WalkDir;
+
+/// Prefix of the refs of every namespace
+pub const NAMESPACES_DIR: &str = "refs/namespaces/";
+
+static NAMESPACE: OnceLock<Option<String>> = OnceLock::new();
+
+/// Sets the namespace the refs are read from and written to
+///
+/// Called once at startup with `--namespace`, falling back to `VOX_NAMESPACE`
+/// when it isn't given. Several repositories can then share one object store,
+/// each keeping its refs under `refs/namespaces/<namespace>/`.
+pub fn set_namespace(namespace: Option<String>) -> anyhow::Result<()> {
+    let namespace = namespace
+        .or_else(|| std::env::var("VOX_NAMESPACE").ok())
+        .map(|namespace| namespace.trim_matches('/').to_string())
+        .filter(|namespace| !namespace.is_empty());
+    if let Some(namespace) = &namespace {
+        let valid = namespace.split('/').all(|part| {
+            !part.is_empty() && part != "." && part != ".." && !part.ends_with(".lock")
+        });
+        if !valid {
+            bail!("Invalid namespace: {}", namespace);
+        }
+    }
+    let _ = NAMESPACE.set(namespace);
+    Ok(())
+}
+
+/// The current namespace, `None` when refs aren't namespaced
+pub fn namespace() -> Option<&'static str> {
+    NAMESPACE.get_or_init(|| None).as_deref()
+}
+
+/// Name under which `ref_name` is stored in the current namespace
+///
+/// Only names under `refs/` are namespaced, `HEAD` and the other pseudo refs
+/// stay shared. Nested namespaces like "a/b" become
+/// `refs/namespaces/a/refs/namespaces/b/`.
+pub fn namespaced(ref_name: &str) -> String {
+    namespace_ref(namespace(), ref_name)
+}
+
+fn namespace_ref(namespace: Option<&str>, ref_name: &str) -> String {
+    match (namespace, ref_name.strip_prefix("refs/")) {
+        (Some(namespace), Some(_)) => {
+            let prefix: String = namespace
+                .split('/')
+                .map(|part| format!("{}{}/", NAMESPACES_DIR, part))
+                .collect();
+            format!("{}{}", prefix, ref_name)
+        }
+        _ => ref_name.to_string(),
+    }
+}
+
+/// Path of the file holding `ref_name` in the current namespace
+pub fn ref_path(vox_dir: &Path, ref_name: &str) -> PathBuf {
+    vox_dir.join(namespaced(ref_name))
+}
 
 /// Write to ref directory with given name and hash
+///
+/// The hash is written to `<ref>.lock` first and then renamed into place, so
+/// readers never see a partially written ref
 pub async fn write_ref(refs_dir: &Path, ref_name: &str, commit_hash: &str) -> io::Result<()> {
     let ref_path = refs_dir.join(ref_name);
     if let Some(parent) = ref_path.parent() {
         fs::create_dir_all(parent).await?;
     }
 
-    fs::write(&ref_path, format!("{commit_hash}\n")).await?;
-    Ok(())
+    let lock_path = refs_dir.join(format!("{ref_name}.lock"));
+    let mut lock = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .await?;
+
+    let written = async {
+        lock.write_all(format!("{commit_hash}\n").as_bytes()).await?;
+        lock.sync_all().await?;
+        drop(lock);
+        fs::rename(&lock_path, &ref_path).await
+    }
+    .await;
+
+    if written.is_err() {
+        let _ = fs::remove_file(&lock_path).await;
+    }
+    written
 }
 
 /// Read ref directory
@@ -26,6 +107,84 @@ pub async fn read_ref(refs_dir: &Path, ref_name: &str) -> anyhow::Result<String>
     Ok(content.trim().to_string())
 }
 
+/// Resolves `HEAD` of the repository at `vox_dir` to a commit hash
+///
+/// # Returns
+///
+/// `None` if HEAD points to a branch that has no commits yet
+pub fn resolve_head(vox_dir: &Path) -> anyhow::Result<Option<String>> {
+    let head_content =
+        std::fs::read_to_string(vox_dir.join("HEAD")).context("Failed to read HEAD file")?;
+
+    match head_content.strip_prefix("ref: ") {
+        Some(branch_ref) => {
+            let ref_path = ref_path(vox_dir, branch_ref.trim());
+            if !ref_path.exists() {
+                return Ok(None);
+            }
+            let hash = std::fs::read_to_string(&ref_path)
+                .context("Failed to read branch reference")?;
+            Ok(Some(hash.trim().to_string()))
+        }
+        None => Ok(Some(head_content.trim().to_string())),
+    }
+}
+
+/// Lists every ref of the current namespace
+///
+/// # Returns
+///
+/// Pairs of full ref names (e.g. "refs/heads/main") and the hashes they point to,
+/// sorted by ref name. Names are those seen inside the namespace, without the
+/// `refs/namespaces/<namespace>/` prefix.
+pub fn list_refs(vox_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    list_refs_under(&ref_path(vox_dir, "refs/"))
+}
+
+/// Lists every ref stored under `<vox_dir>/refs`, those of every namespace
+/// included under their full names
+///
+/// Objects are shared by all namespaces, so whatever decides which of them
+/// are reachable must look at all the refs.
+pub fn list_all_refs(vox_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    list_refs_under(&vox_dir.join("refs"))
+}
+
+/// Lists the refs stored under `refs_dir`, named as if it was `refs`
+fn list_refs_under(refs_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+
+    if !refs_dir.exists() {
+        return Ok(refs);
+    }
+
+    for entry in WalkDir::new(refs_dir).min_depth(1) {
+        let entry = entry.context("Failed to read refs directory")?;
+        if !entry.file_type().is_file() || entry.path().extension().is_some_and(|e| e == "lock") {
+            continue;
+        }
+
+        let name = entry
+            .path()
+            .strip_prefix(refs_dir)?
+            .to_str()
+            .context("Ref name contains invalid UTF-8")?
+            .replace('\\', "/");
+        let name = format!("refs/{}", name);
+        let hash = std::fs::read_to_string(entry.path())
+            .with_context(|| format!("Failed to read ref: {}", name))?
+            .trim()
+            .to_string();
+
+        if !hash.is_empty() {
+            refs.push((name, hash));
+        }
+    }
+
+    refs.sort();
+    Ok(refs)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -43,4 +202,47 @@ mod tests {
 
         assert_eq!(read, commit_hash);
     }
+
+    #[tokio::test]
+    async fn test_list_refs() {
+        let tmp_dir = tempdir().unwrap();
+        let vox_dir = tmp_dir.path();
+
+        write_ref(&vox_dir.join("refs/heads"), "main", "aaaa").await.unwrap();
+        write_ref(&vox_dir.join("refs/heads"), "feature/x", "bbbb").await.unwrap();
+
+        let refs = list_refs(vox_dir).unwrap();
+        assert_eq!(
+            refs,
+            vec![
+                ("refs/heads/feature/x".to_string(), "bbbb".to_string()),
+                ("refs/heads/main".to_string(), "aaaa".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_namespaced_refs() {
+        assert_eq!(namespace_ref(None, "refs/heads/main"), "refs/heads/main");
+        assert_eq!(
+            namespace_ref(Some("team"), "refs/heads/main"),
+            "refs/namespaces/team/refs/heads/main"
+        );
+        assert_eq!(
+            namespace_ref(Some("a/b"), "refs/tags/v1"),
+            "refs/namespaces/a/refs/namespaces/b/refs/tags/v1"
+        );
+        assert_eq!(namespace_ref(Some("team"), "HEAD"), "HEAD");
+
+        let tmp_dir = tempdir().unwrap();
+        let vox_dir = tmp_dir.path();
+        let path = vox_dir.join(namespace_ref(Some("team"), "refs/heads/main"));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "aaaa\n").unwrap();
+
+        let all = list_all_refs(vox_dir).unwrap();
+        assert_eq!(all[0].0, "refs/namespaces/team/refs/heads/main");
+        let inside = list_refs_under(&vox_dir.join(NAMESPACES_DIR).join("team/refs")).unwrap();
+        assert_eq!(inside, vec![("refs/heads/main".to_string(), "aaaa".to_string())]);
+    }
 }
@@ -0,0 +1,88 @@
+use crate::storage::refs::ref_path;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+/// Namespace of the replace refs, `refs/replace/<object>` holds the hash of
+/// the object read in its place
+pub const REPLACE_REFS: &str = "refs/replace/";
+
+lazy_static! {
+    /// Set `VOX_NO_REPLACE_OBJECTS` to read every object as it is
+    static ref REPLACE_DISABLED: bool = std::env::var_os("VOX_NO_REPLACE_OBJECTS").is_some();
+}
+
+thread_local! {
+    static BYPASSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` reading objects as they are, ignoring the replace refs
+///
+/// Reachability walks need the real objects: the history of a replaced
+/// commit must be kept even though it's hidden.
+pub fn without_replacements<T>(f: impl FnOnce() -> T) -> T {
+    let bypassed = BYPASSED.with(|bypassed| bypassed.replace(true));
+    let result = f();
+    BYPASSED.with(|cell| cell.set(bypassed));
+    result
+}
+
+/// Returns the object to read in place of `hash`, if it's replaced
+///
+/// # Arguments
+///
+/// * `vox_dir` - Path to the `.vox` directory
+/// * `hash` - The object being read
+pub fn replacement(vox_dir: &Path, hash: &str) -> Result<Option<String>> {
+    if *REPLACE_DISABLED || BYPASSED.with(Cell::get) {
+        return Ok(None);
+    }
+    // Hashes are plain hex, anything else can't name a replace ref
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    let path = ref_path(vox_dir, &format!("{}{}", REPLACE_REFS, hash));
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let target = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read the replace ref of {}", hash))?;
+    Ok(Some(target.trim().to_string()))
+}
+
+/// Returns the hash of the object actually read for `hash` in `objects_dir`
+pub fn replaced(hash: &str, objects_dir: &Path) -> Result<String> {
+    let replacement = match objects_dir.parent() {
+        Some(vox_dir) => replacement(vox_dir, hash)?,
+        None => None,
+    };
+    Ok(replacement.unwrap_or_else(|| hash.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_replacement() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        let original = "ab".repeat(20);
+        let fixed = "cd".repeat(20);
+        assert_eq!(replacement(vox_dir, &original)?, None);
+
+        fs::create_dir_all(vox_dir.join(REPLACE_REFS))?;
+        fs::write(vox_dir.join(REPLACE_REFS).join(&original), format!("{}\n", fixed))?;
+        assert_eq!(replacement(vox_dir, &original)?, Some(fixed.clone()));
+        assert_eq!(replaced(&original, &vox_dir.join("objects"))?, fixed);
+        assert_eq!(replacement(vox_dir, "../HEAD")?, None);
+
+        let bypassed = without_replacements(|| replacement(vox_dir, &original))?;
+        assert_eq!(bypassed, None);
+        assert_eq!(replacement(vox_dir, &original)?, Some(fixed));
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
-use crate::storage::utils::{HEAD_DIR, OBJ_DIR, REFS_DIR, VOX_DIR};
+use crate::storage::hash::ObjectFormat;
+use crate::storage::repo_config::{find_vox_dir, RepoConfig};
+use crate::vox_repo::VoxRepo;
 use serde::{Deserialize, Serialize};
+use std::io;
 use std::path::{Path, PathBuf};
-use tokio::{fs, io};
 use url::Url;
 
 /// Represents the type of the repository
@@ -25,7 +27,10 @@ impl Repository {
     /// Creates a new local repository
     /// # Example:
     /// ```
+    /// use vox::Repository;
+    ///
     /// let repo = Repository::new_local("my_repo", "/path/to/my_repo/");
+    /// assert!(repo.url().is_none());
     /// ```
     ///
     pub fn new_local(name: impl Into<String>, workdir: impl Into<PathBuf>) -> Self {
@@ -39,8 +44,12 @@ impl Repository {
     /// Creates a new remote repository with a URL
     /// # Example:
     /// ```
+    /// use url::Url;
+    /// use vox::Repository;
+    ///
     /// let url = Url::parse("https://github.com/user/my_remote_repo.git").unwrap();
     /// let repo = Repository::new_remote("my_remote_repo", "path/to/clone/", url);
+    /// assert_eq!(repo.name(), "my_remote_repo");
     /// ```
     pub fn new_remote(name: impl Into<String>, workdir: impl Into<PathBuf>, url: Url) -> Self {
         Self {
@@ -68,29 +77,25 @@ impl Repository {
         &self.workdir
     }
 
-    /// Initialize a new repository at the given path
-    /// Creates necessary directory structure and files
-    pub async fn init(path: &Path) -> Result<Self, io::Error> {
-        let repo = Self {
+    /// Initialize a new repository at the given path, as `vox init` does
+    ///
+    /// The repository is created by [`VoxRepo::create`], inside `.vox` or,
+    /// for a bare repository without a working tree, at the top level, with
+    /// the default object format.
+    pub async fn init(path: &Path, bare: bool) -> Result<Self, io::Error> {
+        let config = RepoConfig::new(ObjectFormat::default()).with_bare(bare);
+        VoxRepo::create(path, config).map_err(io::Error::other)?;
+
+        Ok(Self {
             name: String::new(),
             workdir: path.to_path_buf(),
             repo_type: RepoType::Local,
-        };
-
-        fs::create_dir_all(&*VOX_DIR).await?; // Main Vox directory
-        fs::create_dir_all(&*OBJ_DIR).await?; // Objects storage
-        fs::create_dir_all(&*REFS_DIR).await?; // References storage
-
-        // Initialize HEAD file pointing to main branch
-        fs::write(&*HEAD_DIR, "ref: refs/heads/main\n").await?;
-
-        Ok(repo)
+        })
     }
 
     /// Checks if a repository is already initialized at the given path
     pub async fn is_initialized(path: &Path) -> Result<bool, io::Error> {
-        let vox_dir = path.join(".vox");
-        Ok(vox_dir.exists())
+        Ok(find_vox_dir(path).is_some())
     }
 }
 
@@ -108,3 +113,24 @@ where
     let s = String::deserialize(deserializer)?;
     Url::parse(&s).map_err(serde::de::Error::custom)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_init_creates_a_full_repository() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        Repository::init(dir.path(), false).await?;
+        let vox_dir = dir.path().join(".vox");
+        assert!(vox_dir.join("index").is_file());
+        assert!(vox_dir.join("hooks").is_dir());
+        assert_eq!(RepoConfig::load(&vox_dir)?, RepoConfig::new(ObjectFormat::Sha1));
+        assert!(Repository::is_initialized(dir.path()).await?);
+
+        // An existing repository isn't overwritten
+        assert!(Repository::init(dir.path(), false).await.is_err());
+        Ok(())
+    }
+}
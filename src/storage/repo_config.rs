@@ -0,0 +1,319 @@
+use crate::error::VoxError;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::objects::compression::Codec;
+use crate::storage::utils::VOX_DIR;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Version of the repository format new repositories are created with
+///
+/// Version 0 repositories predate it and may have extensions this vox knows
+/// nothing about, from version 1 on every extension must be understood before
+/// the repository is touched.
+pub const REPOSITORY_FORMAT_VERSION: u32 = 1;
+
+/// Repository-level settings stored in `.vox/config`
+///
+/// Unlike the user configuration (`.voxconfig`), these settings describe the
+/// on-disk format of the repository and are fixed when it's created
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RepoConfig {
+    #[serde(default)]
+    core: Core,
+    #[serde(default)]
+    extensions: Extensions,
+}
+
+/// Layout of the repository
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Core {
+    /// The repository has no working tree: objects, refs and HEAD live at
+    /// the top level instead of inside `.vox`
+    #[serde(default)]
+    bare: bool,
+    #[serde(default)]
+    repository_format_version: u32,
+}
+
+/// Format extensions the repository relies on
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Extensions {
+    #[serde(default)]
+    object_format: ObjectFormat,
+    /// Id of the key the objects are encrypted with, see
+    /// [`crate::storage::encryption`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<String>,
+    /// Codec new loose objects are compressed with, only written when it
+    /// isn't zlib so that versions of vox that can't read zstd refuse the
+    /// repository
+    #[serde(default, skip_serializing_if = "is_zlib")]
+    compression: Codec,
+    /// Extensions added by newer versions of vox
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+impl RepoConfig {
+    /// Creates a config for a new repository using the given object format
+    pub fn new(object_format: ObjectFormat) -> Self {
+        Self {
+            core: Core {
+                bare: false,
+                repository_format_version: REPOSITORY_FORMAT_VERSION,
+            },
+            extensions: Extensions {
+                object_format,
+                encryption: None,
+                compression: Codec::Zlib,
+                unknown: BTreeMap::new(),
+            },
+        }
+    }
+
+    /// Names the objects of the repository with `object_format`
+    ///
+    /// Repositories from before the format version are upgraded, so that
+    /// binaries checking the extensions don't misread the objects.
+    pub fn with_object_format(mut self, object_format: ObjectFormat) -> Self {
+        self.extensions.object_format = object_format;
+        self.core.repository_format_version =
+            self.core.repository_format_version.max(REPOSITORY_FORMAT_VERSION);
+        self
+    }
+
+    /// Encrypts the objects of the repository with the key `key_id`
+    pub fn with_encryption(mut self, key_id: Option<String>) -> Self {
+        self.extensions.encryption = key_id;
+        self
+    }
+
+    /// Compresses the new loose objects of the repository with `codec`
+    pub fn with_compression(mut self, codec: Codec) -> Self {
+        self.extensions.compression = codec;
+        self.core.repository_format_version =
+            self.core.repository_format_version.max(REPOSITORY_FORMAT_VERSION);
+        self
+    }
+
+    /// Marks the repository as bare
+    pub fn with_bare(mut self, bare: bool) -> Self {
+        self.core.bare = bare;
+        self
+    }
+
+    /// Path of the config file inside a `.vox` directory
+    pub fn path(vox_dir: &Path) -> PathBuf {
+        vox_dir.join("config")
+    }
+
+    /// Loads `<vox_dir>/config`, falling back to defaults if it doesn't exist
+    pub fn load(vox_dir: &Path) -> Result<Self> {
+        let path = Self::path(vox_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read repository config: {}", path.display()))?;
+        toml::from_str(&data)
+            .with_context(|| format!("Failed to parse repository config: {}", path.display()))
+    }
+
+    /// Writes the config to `<vox_dir>/config`
+    pub fn save(&self, vox_dir: &Path) -> Result<()> {
+        let path = Self::path(vox_dir);
+        let data = toml::to_string(self).context("Failed to serialize repository config")?;
+        fs::write(&path, data)
+            .with_context(|| format!("Failed to write repository config: {}", path.display()))
+    }
+
+    pub fn object_format(&self) -> ObjectFormat {
+        self.extensions.object_format
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.core.bare
+    }
+
+    /// Id of the key the objects are encrypted with, if they are
+    pub fn encryption(&self) -> Option<&str> {
+        self.extensions.encryption.as_deref()
+    }
+
+    /// Codec new loose objects are compressed with
+    pub fn compression(&self) -> Codec {
+        self.extensions.compression
+    }
+
+    pub fn repository_format_version(&self) -> u32 {
+        self.core.repository_format_version
+    }
+
+    /// Fails if the repository is in a format this vox doesn't understand
+    pub fn check_supported(&self) -> Result<()> {
+        let version = self.core.repository_format_version;
+        if version > REPOSITORY_FORMAT_VERSION {
+            bail!(VoxError::UnsupportedFormat(format!(
+                "Unsupported repository format version {} (up to {} is supported), \
+                 upgrade vox to use this repository",
+                version, REPOSITORY_FORMAT_VERSION
+            )));
+        }
+        if version >= 1 && !self.extensions.unknown.is_empty() {
+            let names: Vec<&str> = self.extensions.unknown.keys().map(String::as_str).collect();
+            bail!(VoxError::UnsupportedFormat(format!(
+                "Unsupported repository extensions: {}, upgrade vox to use this repository",
+                names.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn is_zlib(codec: &Codec) -> bool {
+    *codec == Codec::Zlib
+}
+
+/// Fails if the repository at `vox_dir` is in a format this vox doesn't
+/// understand, checked before any command reads or writes it
+pub fn check_repository_format(vox_dir: &Path) -> Result<()> {
+    RepoConfig::load(vox_dir)?.check_supported()
+}
+
+/// Returns true if `dir` itself is a bare repository
+pub fn is_bare_repository(dir: &Path) -> bool {
+    dir.join("HEAD").is_file()
+        && dir.join("objects").is_dir()
+        && RepoConfig::load(dir).is_ok_and(|config| config.is_bare())
+}
+
+/// Returns the directory holding the objects and refs of the repository at
+/// `path`: `<path>/.vox`, or `path` itself for a bare repository
+pub fn find_vox_dir(path: &Path) -> Option<PathBuf> {
+    let vox_dir = path.join(".vox");
+    if vox_dir.is_dir() {
+        Some(vox_dir)
+    } else if is_bare_repository(path) {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Fails with [`VoxError::NotARepository`] outside a repository, for
+/// commands operating on the current one
+pub fn ensure_repository() -> Result<()> {
+    if !VOX_DIR.exists() {
+        bail!(VoxError::NotARepository);
+    }
+    Ok(())
+}
+
+/// Fails if the current repository is bare, for commands that read or write
+/// the working tree or the index
+pub fn ensure_work_tree() -> Result<()> {
+    if RepoConfig::load(&VOX_DIR)?.is_bare() {
+        bail!("This operation must be run in a work tree, the repository is bare");
+    }
+    Ok(())
+}
+
+/// Fails if the current repository is in a format this vox doesn't
+/// understand, before any command reads or writes it
+pub fn ensure_repository_format() -> Result<()> {
+    if !VOX_DIR.exists() {
+        return Ok(());
+    }
+    check_repository_format(&VOX_DIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = tempdir()?;
+
+        assert_eq!(RepoConfig::load(dir.path())?.object_format(), ObjectFormat::Sha1);
+
+        RepoConfig::new(ObjectFormat::Sha256).save(dir.path())?;
+        let data = fs::read_to_string(RepoConfig::path(dir.path()))?;
+        assert!(data.contains("objectFormat = \"sha256\""));
+        assert_eq!(RepoConfig::load(dir.path())?.object_format(), ObjectFormat::Sha256);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bare_repository() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("objects"))?;
+        fs::write(dir.path().join("HEAD"), "ref: refs/heads/main\n")?;
+        RepoConfig::new(ObjectFormat::Sha1).save(dir.path())?;
+        assert!(!is_bare_repository(dir.path()));
+        assert_eq!(find_vox_dir(dir.path()), None);
+
+        RepoConfig::new(ObjectFormat::Sha1)
+            .with_bare(true)
+            .save(dir.path())?;
+        assert!(RepoConfig::load(dir.path())?.is_bare());
+        assert!(is_bare_repository(dir.path()));
+        assert_eq!(find_vox_dir(dir.path()), Some(dir.path().to_path_buf()));
+
+        fs::create_dir_all(dir.path().join("work/.vox"))?;
+        assert_eq!(
+            find_vox_dir(&dir.path().join("work")),
+            Some(dir.path().join("work/.vox"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_repository_format() -> Result<()> {
+        let dir = tempdir()?;
+        RepoConfig::new(ObjectFormat::Sha1).save(dir.path())?;
+        let data = fs::read_to_string(RepoConfig::path(dir.path()))?;
+        assert!(data.contains("repositoryFormatVersion = 1"));
+        check_repository_format(dir.path())?;
+
+        // Repositories from before the version may have anything
+        fs::write(RepoConfig::path(dir.path()), "[extensions]\nsplitIndex = true\n")?;
+        assert_eq!(RepoConfig::load(dir.path())?.repository_format_version(), 0);
+        check_repository_format(dir.path())?;
+
+        let newer = "[core]\nrepositoryFormatVersion = 1\n[extensions]\nsplitIndex = true\n";
+        fs::write(RepoConfig::path(dir.path()), newer)?;
+        let error = check_repository_format(dir.path()).unwrap_err();
+        assert!(error.to_string().contains("splitIndex"));
+
+        fs::write(RepoConfig::path(dir.path()), "[core]\nrepositoryFormatVersion = 2\n")?;
+        assert!(check_repository_format(dir.path()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compression_extension() -> Result<()> {
+        let dir = tempdir()?;
+        RepoConfig::new(ObjectFormat::Sha1).save(dir.path())?;
+        let data = fs::read_to_string(RepoConfig::path(dir.path()))?;
+        assert!(!data.contains("compression"));
+        assert_eq!(RepoConfig::load(dir.path())?.compression(), Codec::Zlib);
+
+        RepoConfig::new(ObjectFormat::Sha1)
+            .with_compression(Codec::Zstd)
+            .save(dir.path())?;
+        let data = fs::read_to_string(RepoConfig::path(dir.path()))?;
+        assert!(data.contains("compression = \"zstd\""));
+        assert_eq!(RepoConfig::load(dir.path())?.compression(), Codec::Zstd);
+        check_repository_format(dir.path())?;
+        Ok(())
+    }
+}
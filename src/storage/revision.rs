@@ -0,0 +1,404 @@
+use crate::error::VoxError;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::graph::load_commit;
+use crate::storage::objects::tag::Tag;
+use crate::storage::objects::{read_object_header, Loadable, ObjectStorage};
+use crate::storage::reflog::resolve_reflog_selector;
+use crate::storage::refs::{ref_path, resolve_head};
+use crate::storage::utils::{OBJ_TYPE_COMMIT, OBJ_TYPE_TAG};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Shortest hash prefix accepted as an abbreviated object name
+pub const MIN_ABBREV_LEN: usize = 4;
+
+/// Where the names a ref can be given by are looked up, in order
+const REF_PREFIXES: [&str; 5] = ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"];
+
+/// A step from one commit to another, written after the base of a revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// `^<n>`, the n-th parent, `^0` being the commit itself
+    Parent(usize),
+    /// `~<n>`, the n-th first-parent ancestor
+    Ancestor(usize),
+}
+
+/// Resolves a revision to the hash of the object it names
+///
+/// A revision is a base optionally followed by navigation steps. The base is
+/// one of:
+/// - `HEAD` or `@`
+/// - a reflog selector such as `HEAD@{2}`, `main@{1}` or `@{1}`
+/// - a ref, given in full (`refs/heads/main`) or as a branch or tag name
+/// - a pseudo-ref written by a command, such as `ORIG_HEAD`
+/// - a full or abbreviated object hash
+///
+/// The steps are `^` / `^<n>` for the n-th parent and `~` / `~<n>` for the
+/// n-th first-parent ancestor, so `HEAD~2^` is the parent of the grandparent
+/// of HEAD. Tags are peeled to the commit they point to before a step.
+///
+/// # Errors
+///
+/// Returns an error if the base doesn't name an object or a step leads past
+/// the first commit
+pub fn resolve_revision(vox_dir: &Path, spec: &str) -> Result<String> {
+    let (base, steps) = split_revision(spec)?;
+    let objects_dir = vox_dir.join("objects");
+
+    let mut hash = resolve_base(vox_dir, base)
+        .map_err(|error| error.context(VoxError::InvalidRevision(spec.to_string())))?;
+
+    for step in steps {
+        hash = peel_to_commit(&hash, &objects_dir)?;
+        let generations = match step {
+            Step::Parent(0) => 0,
+            Step::Parent(1) => 1,
+            Step::Ancestor(count) => count,
+            Step::Parent(n) => bail!("Revision '{}': commit {} has no parent {}", spec, hash, n),
+        };
+        for _ in 0..generations {
+            hash = load_commit(&hash, &objects_dir)?
+                .parent
+                .ok_or_else(|| anyhow!("Revision '{}' goes past the first commit", spec))?;
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Resolves a revision to a commit hash, peeling tags
+///
+/// # Errors
+///
+/// Returns an error if the revision can't be resolved or names something
+/// other than a commit or a tag of one
+pub fn resolve_commit(vox_dir: &Path, spec: &str) -> Result<String> {
+    let hash = resolve_revision(vox_dir, spec)?;
+    peel_to_commit(&hash, &vox_dir.join("objects"))
+        .with_context(|| format!("Revision '{}' is not a commit", spec))
+}
+
+/// Follows tag objects until reaching a commit
+fn peel_to_commit(hash: &str, objects_dir: &Path) -> Result<String> {
+    let mut hash = hash.to_string();
+    loop {
+        let (object_type, _) = read_object_header(&hash, objects_dir)?;
+        match object_type.as_str() {
+            OBJ_TYPE_COMMIT => return Ok(hash),
+            OBJ_TYPE_TAG => hash = Tag::load(&hash, objects_dir)?.object,
+            other => bail!("Object {} is a {}, not a commit", hash, other),
+        }
+    }
+}
+
+/// Splits a revision into its base and the navigation steps following it
+fn split_revision(spec: &str) -> Result<(&str, Vec<Step>)> {
+    // Ref names can't contain '^' or '~', so the first one ends the base
+    let base_end = spec.find(['^', '~']).unwrap_or(spec.len());
+    let (base, mut rest) = spec.split_at(base_end);
+
+    let mut steps = Vec::new();
+    while let Some(operator) = rest.chars().next() {
+        rest = &rest[1..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let count = match &rest[..digits] {
+            "" => 1,
+            number => number
+                .parse()
+                .map_err(|_| anyhow!("Invalid revision: '{}'", spec))?,
+        };
+        rest = &rest[digits..];
+
+        steps.push(match operator {
+            '^' => Step::Parent(count),
+            '~' => Step::Ancestor(count),
+            _ => bail!("Invalid revision: '{}'", spec),
+        });
+    }
+
+    Ok((base, steps))
+}
+
+fn resolve_base(vox_dir: &Path, base: &str) -> Result<String> {
+    if base.is_empty() || base == "HEAD" || base == "@" {
+        return resolve_head(vox_dir)?.context("HEAD does not point to a commit yet");
+    }
+
+    if let Some(hash) = resolve_reflog_selector(vox_dir, base)? {
+        return Ok(hash);
+    }
+
+    if let Some(hash) = resolve_ref(vox_dir, base)? {
+        return Ok(hash);
+    }
+
+    find_object_by_prefix(&vox_dir.join("objects"), base)?
+        .ok_or_else(|| anyhow!("'{}' is neither a ref nor an object name", base))
+}
+
+/// Looks `name` up as a ref, trying the prefixes in [`REF_PREFIXES`]
+fn resolve_ref(vox_dir: &Path, name: &str) -> Result<Option<String>> {
+    for prefix in REF_PREFIXES {
+        let path = ref_path(vox_dir, &format!("{}{}", prefix, name));
+        // "objects", "hooks" and the like are directories, never refs
+        let top_level = name.starts_with("refs/") || is_pseudo_ref(name);
+        if !path.is_file() || (prefix.is_empty() && !top_level) {
+            continue;
+        }
+
+        let hash = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read ref: {}", name))?;
+        return Ok(Some(hash.trim().to_string()));
+    }
+    Ok(None)
+}
+
+/// Returns true for names like `ORIG_HEAD`, refs kept directly in the vox
+/// directory by commands that move HEAD
+fn is_pseudo_ref(name: &str) -> bool {
+    name.ends_with("HEAD") && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// Shortens `hash` to the shortest prefix of at least `min_len` characters
+/// that no other object shares
+pub fn abbreviate(objects_dir: &Path, hash: &str, min_len: usize) -> Result<String> {
+    let storage = ObjectStorage {
+        dir: objects_dir.to_path_buf(),
+    };
+    let others: Vec<String> = storage
+        .find_by_prefix(&hash[..min_len.clamp(MIN_ABBREV_LEN, hash.len())])?
+        .into_iter()
+        .filter(|other| other != hash)
+        .collect();
+
+    // The prefix must go one character past the longest one shared with another object
+    let shared = others
+        .iter()
+        .map(|other| {
+            hash.chars()
+                .zip(other.chars())
+                .take_while(|(a, b)| a == b)
+                .count()
+        })
+        .max()
+        .unwrap_or(0);
+    let len = min_len.max(shared + 1).clamp(MIN_ABBREV_LEN, hash.len());
+    Ok(hash[..len].to_string())
+}
+
+/// Returns the short name of the ref `spec` refers to, such as `main` for
+/// `HEAD` when the main branch is checked out
+///
+/// A detached HEAD is named `HEAD`.
+///
+/// # Errors
+///
+/// Returns an error if `spec` isn't a ref
+pub fn abbreviated_ref_name(vox_dir: &Path, spec: &str) -> Result<String> {
+    let full_name = if spec == "HEAD" || spec == "@" {
+        let head = fs::read_to_string(vox_dir.join("HEAD")).context("Failed to read HEAD file")?;
+        match head.strip_prefix("ref: ") {
+            Some(target) => target.trim().to_string(),
+            None => return Ok("HEAD".to_string()),
+        }
+    } else {
+        REF_PREFIXES
+            .iter()
+            .map(|prefix| format!("{}{}", prefix, spec))
+            .find(|name| name.starts_with("refs/") && ref_path(vox_dir, name).is_file())
+            .ok_or_else(|| anyhow!("'{}' is not a ref", spec))?
+    };
+
+    let short_name = ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| full_name.strip_prefix(prefix))
+        .unwrap_or(&full_name);
+    Ok(short_name.to_string())
+}
+
+/// Finds the object, loose or packed, whose hash starts with `prefix`
+///
+/// # Returns
+///
+/// `None` if `prefix` isn't a hex string of at least [`MIN_ABBREV_LEN`]
+/// characters or no object matches it
+///
+/// # Errors
+///
+/// Returns an error listing the candidates if several objects match
+pub fn find_object_by_prefix(objects_dir: &Path, prefix: &str) -> Result<Option<String>> {
+    if prefix.len() < MIN_ABBREV_LEN || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    let storage = ObjectStorage {
+        dir: objects_dir.to_path_buf(),
+    };
+    let mut matches = storage.find_by_prefix(prefix)?;
+
+    match matches.len() {
+        0 => Ok(None),
+        1 => Ok(matches.pop()),
+        _ => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .map(|hash| format!("  {}", describe_candidate(hash, objects_dir)))
+                .collect();
+            bail!(
+                "Short object ID {} is ambiguous, the candidates are:\n{}",
+                prefix,
+                candidates.join("\n")
+            )
+        }
+    }
+}
+
+/// Describes an object as `<hash> <type>`, followed by the subject of commits
+fn describe_candidate(hash: &str, objects_dir: &Path) -> String {
+    let Ok((object_type, _)) = read_object_header(hash, objects_dir) else {
+        // Packed objects can't be inspected without unpacking the whole pack
+        return format!("{} (packed)", hash);
+    };
+
+    let subject = (object_type == OBJ_TYPE_COMMIT)
+        .then(|| Commit::load(hash, objects_dir).ok())
+        .flatten()
+        .map(|commit| commit.message.lines().next().unwrap_or_default().to_string());
+    match subject {
+        Some(subject) => format!("{} {} - {}", hash, object_type, subject),
+        None => format!("{} {}", hash, object_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::Storable;
+    use crate::storage::shallow::write_shallow;
+    use std::collections::BTreeSet;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_split_revision() -> Result<()> {
+        assert_eq!(split_revision("main")?, ("main", vec![]));
+        assert_eq!(
+            split_revision("HEAD~3^2^")?,
+            (
+                "HEAD",
+                vec![Step::Ancestor(3), Step::Parent(2), Step::Parent(1)]
+            )
+        );
+        assert_eq!(
+            split_revision("main@{1}~")?,
+            ("main@{1}", vec![Step::Ancestor(1)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_revision() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        let objects_dir = vox_dir.join("objects");
+        fs::create_dir_all(vox_dir.join("refs/heads"))?;
+
+        let mut parent = None;
+        let mut hashes = Vec::new();
+        for message in ["first", "second", "third"] {
+            let commit = Commit::new(
+                "tree".to_string(),
+                parent.clone(),
+                "A <a@x.io>".to_string(),
+                message.to_string(),
+            );
+            let hash = commit.save(&objects_dir)?;
+            parent = Some(hash.clone());
+            hashes.push(hash);
+        }
+        fs::write(vox_dir.join("refs/heads/main"), format!("{}\n", hashes[2]))?;
+        fs::write(vox_dir.join("HEAD"), "ref: refs/heads/main\n")?;
+
+        assert_eq!(resolve_revision(vox_dir, "HEAD")?, hashes[2]);
+        assert_eq!(resolve_revision(vox_dir, "main~")?, hashes[1]);
+        assert_eq!(resolve_revision(vox_dir, "HEAD~2")?, hashes[0]);
+        assert_eq!(resolve_revision(vox_dir, "@^^")?, hashes[0]);
+        assert_eq!(resolve_revision(vox_dir, "refs/heads/main^0")?, hashes[2]);
+        assert_eq!(resolve_revision(vox_dir, &hashes[1][..8])?, hashes[1]);
+        assert!(resolve_revision(vox_dir, "HEAD~3").is_err());
+        assert!(resolve_revision(vox_dir, "HEAD^2").is_err());
+        assert!(resolve_revision(vox_dir, "nope").is_err());
+
+        fs::write(vox_dir.join("ORIG_HEAD"), format!("{}\n", hashes[0]))?;
+        assert_eq!(resolve_revision(vox_dir, "ORIG_HEAD~0")?, hashes[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_navigation_stops_at_shallow_boundary() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        let objects_dir = vox_dir.join("objects");
+        fs::create_dir_all(vox_dir.join("refs/heads"))?;
+
+        let author = || "A <a@x.io>".to_string();
+        let first = Commit::new("tree".into(), None, author(), "first".into()).save(&objects_dir)?;
+        let second = Commit::new("tree".into(), Some(first.clone()), author(), "second".into())
+            .save(&objects_dir)?;
+        fs::remove_file(objects_dir.join(&first[..2]).join(&first[2..]))?;
+        write_shallow(vox_dir, &BTreeSet::from([second.clone()]))?;
+        fs::write(vox_dir.join("refs/heads/main"), format!("{}\n", second))?;
+
+        assert_eq!(resolve_revision(vox_dir, "main~0")?, second);
+        let error = resolve_revision(vox_dir, "main~1").unwrap_err();
+        assert!(error.to_string().contains("goes past the first commit"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_prefix() -> Result<()> {
+        let dir = tempdir()?;
+        let objects_dir = dir.path().join("objects");
+        fs::create_dir_all(objects_dir.join("ab"))?;
+        fs::write(objects_dir.join("ab/cdef01"), b"")?;
+        fs::write(objects_dir.join("ab/cdef02"), b"")?;
+
+        assert_eq!(
+            find_object_by_prefix(&objects_dir, "abcdef01")?.as_deref(),
+            Some("abcdef01")
+        );
+        assert_eq!(find_object_by_prefix(&objects_dir, "abc")?, None);
+
+        let error = find_object_by_prefix(&objects_dir, "abcd").unwrap_err().to_string();
+        assert!(error.contains("abcdef01") && error.contains("abcdef02"));
+
+        assert_eq!(abbreviate(&objects_dir, "abcdef01", 4)?, "abcdef01");
+        fs::write(objects_dir.join("ab/cd9999"), b"")?;
+        assert_eq!(abbreviate(&objects_dir, "abcd9999", 4)?, "abcd9");
+        assert_eq!(abbreviate(&objects_dir, "abcd9999", 7)?, "abcd999");
+        Ok(())
+    }
+
+    #[test]
+    fn test_abbreviated_ref_name() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        fs::create_dir_all(vox_dir.join("refs/heads/feature"))?;
+        fs::create_dir_all(vox_dir.join("refs/tags"))?;
+        fs::write(vox_dir.join("refs/heads/feature/x"), "aaaa\n")?;
+        fs::write(vox_dir.join("refs/tags/v1"), "bbbb\n")?;
+
+        fs::write(vox_dir.join("HEAD"), "ref: refs/heads/feature/x\n")?;
+        assert_eq!(abbreviated_ref_name(vox_dir, "HEAD")?, "feature/x");
+        assert_eq!(abbreviated_ref_name(vox_dir, "refs/tags/v1")?, "v1");
+        assert_eq!(abbreviated_ref_name(vox_dir, "v1")?, "v1");
+        assert!(abbreviated_ref_name(vox_dir, "nope").is_err());
+
+        fs::write(vox_dir.join("HEAD"), "aaaa\n")?;
+        assert_eq!(abbreviated_ref_name(vox_dir, "HEAD")?, "HEAD");
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// File in the vox directory listing the commits at the shallow boundary
+pub const SHALLOW_FILE: &str = "shallow";
+
+/// Returns the commits whose parents are intentionally absent, empty unless
+/// the repository is shallow
+///
+/// # Arguments
+///
+/// * `vox_dir` - Path to the `.vox` directory
+pub fn read_shallow(vox_dir: &Path) -> Result<BTreeSet<String>> {
+    let path = vox_dir.join(SHALLOW_FILE);
+    if !path.exists() {
+        return Ok(BTreeSet::new());
+    }
+    let content = fs::read_to_string(&path).context("Failed to read the shallow file")?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Records the shallow boundary, removing the file when it's empty
+pub fn write_shallow(vox_dir: &Path, commits: &BTreeSet<String>) -> Result<()> {
+    let path = vox_dir.join(SHALLOW_FILE);
+    if commits.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove the shallow file")?;
+        }
+        return Ok(());
+    }
+
+    let content: String = commits.iter().map(|hash| format!("{}\n", hash)).collect();
+    fs::write(&path, content).context("Failed to write the shallow file")
+}
+
+/// Returns true if the parents of `commit` are cut off by the shallow boundary
+pub fn is_shallow(vox_dir: &Path, commit: &str) -> Result<bool> {
+    Ok(read_shallow(vox_dir)?.contains(commit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_shallow_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        let vox_dir = dir.path();
+        assert!(read_shallow(vox_dir)?.is_empty());
+
+        let commits = BTreeSet::from(["ab".repeat(20), "cd".repeat(20)]);
+        write_shallow(vox_dir, &commits)?;
+        assert_eq!(read_shallow(vox_dir)?, commits);
+        assert!(is_shallow(vox_dir, &"cd".repeat(20))?);
+        assert!(!is_shallow(vox_dir, &"ef".repeat(20))?);
+
+        write_shallow(vox_dir, &BTreeSet::new())?;
+        assert!(!vox_dir.join(SHALLOW_FILE).exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use tempfile::tempdir;
+
+/// Namespace SSH signatures are made in, so they can't be replayed as
+/// signatures for another purpose
+pub const SSH_NAMESPACE: &str = "vox";
+
+/// Tool used to sign objects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    /// Armored detached signatures made by `gpg`
+    #[default]
+    #[value(name = "openpgp")]
+    OpenPgp,
+    /// SSH signatures made by `ssh-keygen -Y sign`
+    Ssh,
+}
+
+/// Produces an armored detached signature of `payload`
+///
+/// For OpenPGP `key` is the key id to sign with, `gpg`'s default key is used
+/// when it's not set. For SSH it's the path of the private key and required.
+///
+/// # Errors
+///
+/// Returns an error if the signing program can't be run or fails
+pub fn sign(payload: &[u8], format: SigningFormat, key: Option<&str>) -> Result<String> {
+    let signature = match format {
+        SigningFormat::OpenPgp => sign_openpgp(payload, key)?,
+        SigningFormat::Ssh => {
+            let key = key
+                .context("An SSH signing key is required (use 'vox config set-signing-key')")?;
+            sign_ssh(payload, key)?
+        }
+    };
+
+    let signature = signature.trim_end();
+    if signature.is_empty() {
+        bail!("The signing program produced no signature");
+    }
+    Ok(signature.to_string())
+}
+
+fn sign_openpgp(payload: &[u8], key: Option<&str>) -> Result<String> {
+    let mut command = Command::new("gpg");
+    command.args(["--batch", "--detach-sign", "--armor"]);
+    if let Some(key) = key {
+        command.args(["--local-user", key]);
+    }
+
+    let output = run_with_input(command, payload, "gpg")?;
+    if !output.status.success() {
+        bail!(
+            "gpg failed to sign the data:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    String::from_utf8(output.stdout).context("gpg produced an invalid signature")
+}
+
+/// `ssh-keygen` signs files, writing the signature next to them as `.sig`
+fn sign_ssh(payload: &[u8], key: &str) -> Result<String> {
+    let dir = tempdir().context("Failed to create a temporary directory")?;
+    let payload_path = dir.path().join("payload");
+    fs::write(&payload_path, payload).context("Failed to write the object to sign")?;
+
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+        .arg(Path::new(key))
+        .arg(&payload_path)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run ssh-keygen")?;
+    if !output.status.success() {
+        bail!(
+            "ssh-keygen failed to sign the data:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+
+    fs::read_to_string(dir.path().join("payload.sig")).context("Failed to read the SSH signature")
+}
+
+/// Files signatures are verified against
+#[derive(Debug, Default)]
+pub struct Keyrings {
+    /// GPG keyring, gpg's default keyring is used when it's not set
+    pub gpg: Option<PathBuf>,
+    /// SSH allowed signers file, required to verify SSH signatures
+    pub allowed_signers: Option<PathBuf>,
+}
+
+/// Outcome of checking a signature
+#[derive(Debug)]
+pub struct Verification {
+    pub format: SigningFormat,
+    /// Whether the signature is valid for the signed data
+    pub good: bool,
+    /// Who made the signature, if the verifying program could tell
+    pub signer: Option<String>,
+}
+
+impl Verification {
+    /// One-line description such as `Good signature from "A <a@x.io>"`
+    pub fn describe(&self) -> String {
+        let validity = if self.good { "Good" } else { "BAD" };
+        match &self.signer {
+            Some(signer) => format!("{} signature from \"{}\"", validity, signer),
+            None => format!("{} signature", validity),
+        }
+    }
+}
+
+/// Tells the format of an armored signature from its first line
+pub fn signature_format(signature: &str) -> SigningFormat {
+    if signature.starts_with("-----BEGIN SSH SIGNATURE-----") {
+        SigningFormat::Ssh
+    } else {
+        SigningFormat::OpenPgp
+    }
+}
+
+/// Checks `signature` against `payload` using the keys in `keyrings`
+///
+/// # Errors
+///
+/// Returns an error if the verifying program can't be run or can't check
+/// the signature at all, for example because the key is unknown. A signature
+/// that doesn't match the data is reported as a bad [`Verification`].
+pub fn verify(payload: &[u8], signature: &str, keyrings: &Keyrings) -> Result<Verification> {
+    let dir = tempdir().context("Failed to create a temporary directory")?;
+    let signature_path = dir.path().join("signature");
+    fs::write(&signature_path, format!("{}\n", signature))
+        .context("Failed to write the signature")?;
+
+    match signature_format(signature) {
+        SigningFormat::OpenPgp => verify_openpgp(payload, &signature_path, keyrings),
+        SigningFormat::Ssh => verify_ssh(payload, &signature_path, keyrings),
+    }
+}
+
+fn verify_openpgp(payload: &[u8], signature_path: &Path, keyrings: &Keyrings) -> Result<Verification> {
+    let mut command = Command::new("gpg");
+    command.args(["--batch", "--status-fd=1"]);
+    if let Some(keyring) = &keyrings.gpg {
+        command.arg("--no-default-keyring").arg("--keyring").arg(keyring);
+    }
+    command.arg("--verify").arg(signature_path).arg("-");
+
+    let output = run_with_input(command, payload, "gpg")?;
+    let status = String::from_utf8_lossy(&output.stdout);
+
+    // gpg reports the outcome on its status lines, "[GNUPG:] GOODSIG <key id> <user id>"
+    for line in status.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let good = match keyword {
+            "GOODSIG" => true,
+            "BADSIG" => false,
+            _ => continue,
+        };
+        let signer = rest.split_once(' ').map(|(_, user)| user.to_string());
+        return Ok(Verification {
+            format: SigningFormat::OpenPgp,
+            good,
+            signer,
+        });
+    }
+
+    bail!(
+        "gpg couldn't check the signature:\n{}",
+        String::from_utf8_lossy(&output.stderr).trim_end()
+    )
+}
+
+/// `ssh-keygen` needs the signer's identity to verify, so it's looked up in
+/// the allowed signers file first
+fn verify_ssh(payload: &[u8], signature_path: &Path, keyrings: &Keyrings) -> Result<Verification> {
+    let allowed_signers = keyrings.allowed_signers.as_ref().context(
+        "Verifying SSH signatures needs an allowed signers file (use 'vox config set-keyring --format ssh')",
+    )?;
+
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "find-principals", "-f"])
+        .arg(allowed_signers)
+        .arg("-s")
+        .arg(signature_path)
+        .output()
+        .context("Failed to run ssh-keygen")?;
+    let principals = String::from_utf8_lossy(&output.stdout);
+    let Some(principal) = principals.lines().next().filter(|_| output.status.success()) else {
+        bail!("No allowed signer matches the key of the SSH signature");
+    };
+
+    let mut command = Command::new("ssh-keygen");
+    command
+        .args(["-Y", "verify", "-n", SSH_NAMESPACE, "-f"])
+        .arg(allowed_signers)
+        .args(["-I", principal, "-s"])
+        .arg(signature_path);
+    let output = run_with_input(command, payload, "ssh-keygen")?;
+
+    Ok(Verification {
+        format: SigningFormat::Ssh,
+        good: output.status.success(),
+        signer: Some(principal.to_string()),
+    })
+}
+
+fn run_with_input(mut command: Command, input: &[u8], program: &str) -> Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .with_context(|| format!("Failed to pass the object to {}", program))?;
+    }
+    child
+        .wait_with_output()
+        .with_context(|| format!("Failed to wait for {}", program))
+}
@@ -0,0 +1,118 @@
+use crate::storage::refs::resolve_head;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Name of the file at the repository root that declares the submodules
+pub const VOXMODULES_FILE: &str = ".voxmodules";
+
+/// A submodule as declared in `.voxmodules`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    /// Path of the nested repository, relative to the repository root
+    pub path: String,
+    /// Where the nested repository is cloned from
+    pub url: String,
+}
+
+/// Contents of the `.voxmodules` file
+///
+/// Submodules are keyed by name:
+///
+/// ```toml
+/// [submodule.libfoo]
+/// path = "vendor/libfoo"
+/// url = "../libfoo"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Voxmodules {
+    #[serde(default)]
+    pub submodule: BTreeMap<String, Submodule>,
+}
+
+impl Voxmodules {
+    /// Reads `.voxmodules` from the repository at `repo_root`, empty if the
+    /// file doesn't exist
+    pub fn load(repo_root: &Path) -> Result<Self> {
+        let path = repo_root.join(VOXMODULES_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Writes `.voxmodules` to the repository at `repo_root`
+    pub fn save(&self, repo_root: &Path) -> Result<()> {
+        let path = repo_root.join(VOXMODULES_FILE);
+        let data = toml::to_string(self).context("Failed to serialize submodules")?;
+        fs::write(&path, data).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Declares a new submodule
+    pub fn add(&mut self, name: &str, path: &str, url: &str) -> Result<()> {
+        if self.submodule.contains_key(name) {
+            anyhow::bail!("A submodule named '{}' already exists", name);
+        }
+        if self.find_by_path(path).is_some() {
+            anyhow::bail!("'{}' already exists in the index as a submodule", path);
+        }
+
+        self.submodule.insert(
+            name.to_string(),
+            Submodule {
+                path: path.to_string(),
+                url: url.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns the name and declaration of the submodule at `path`
+    pub fn find_by_path(&self, path: &str) -> Option<(&String, &Submodule)> {
+        self.submodule
+            .iter()
+            .find(|(_, submodule)| submodule.path == path)
+    }
+}
+
+/// Returns true if `path` is the working tree of a nested repository
+pub fn is_nested_repository(path: &Path) -> bool {
+    path.join(".vox").is_dir()
+}
+
+/// Returns the commit checked out in the nested repository at `path`
+pub fn submodule_head(path: &Path) -> Result<Option<String>> {
+    resolve_head(&path.join(".vox"))
+        .with_context(|| format!("Failed to read HEAD of submodule {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_voxmodules_roundtrip() -> Result<()> {
+        let dir = tempdir()?;
+        assert_eq!(Voxmodules::load(dir.path())?, Voxmodules::default());
+
+        let mut modules = Voxmodules::default();
+        modules.add("libfoo", "vendor/libfoo", "../libfoo")?;
+        assert!(modules.add("libfoo", "other", "../other").is_err());
+        assert!(modules.add("other", "vendor/libfoo", "../other").is_err());
+        modules.save(dir.path())?;
+
+        let loaded = Voxmodules::load(dir.path())?;
+        assert_eq!(loaded, modules);
+        assert_eq!(
+            loaded.find_by_path("vendor/libfoo").map(|(name, _)| name.as_str()),
+            Some("libfoo")
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
+use crate::storage::repo_config::is_bare_repository;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use lazy_static::lazy_static;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 lazy_static! {
-    pub static ref VOX_DIR: PathBuf = PathBuf::from(".vox");
+    pub static ref VOX_DIR: PathBuf = locate_vox_dir();
     pub static ref OBJ_DIR: PathBuf = VOX_DIR.join("objects");
     pub static ref REFS_DIR: PathBuf = VOX_DIR.join("refs");
     pub static ref HEAD_DIR: PathBuf = VOX_DIR.join("HEAD");
@@ -17,6 +20,112 @@ pub const OBJ_TYPE_CHANGE: &str = "change";
 pub const UNKNOWN_TYPE: &str = "unknown type";
 
 pub const PERM_FILE: &str = "100644";
+pub const PERM_EXEC: &str = "100755";
 pub const PERM_DIR: &str = "40000";
+pub const PERM_SYMLINK: &str = "120000";
+pub const PERM_GITLINK: &str = "160000";
 
 pub mod errors {}
+
+/// Returns `.vox`, or the current directory itself if it's a bare repository
+fn locate_vox_dir() -> PathBuf {
+    let vox_dir = PathBuf::from(".vox");
+    if !vox_dir.is_dir() && is_bare_repository(Path::new(".")) {
+        PathBuf::from(".")
+    } else {
+        vox_dir
+    }
+}
+
+/// Runs `f` with `dir` as the current directory
+///
+/// Commands work relative to the current directory, so this is how they are
+/// pointed at another repository, such as a submodule or a new clone
+pub fn in_directory<T>(dir: &Path, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let original = std::env::current_dir().context("Couldn't get current directory")?;
+    std::env::set_current_dir(dir).with_context(|| format!("Failed to enter {}", dir.display()))?;
+
+    let result = f();
+    std::env::set_current_dir(&original)
+        .with_context(|| format!("Failed to return to {}", original.display()))?;
+    result
+}
+
+/// Parses a user-supplied date such as `now`, `2.weeks.ago`, `3 days ago`,
+/// `2024-01-31`, an RFC 3339 timestamp or `@<seconds since the epoch>`
+pub fn parse_date(input: &str) -> Result<DateTime<Utc>> {
+    let input = input.trim();
+
+    if input.eq_ignore_ascii_case("now") {
+        return Ok(Utc::now());
+    }
+
+    if let Some(seconds) = input.strip_prefix('@') {
+        let seconds: i64 = seconds
+            .parse()
+            .map_err(|_| anyhow!("Invalid date: {}", input))?;
+        return DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| anyhow!("Invalid date: {}", input));
+    }
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(input) {
+        return Ok(dt.to_utc());
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(|| anyhow!("Invalid date: {}", input))?
+            .and_utc());
+    }
+
+    // Relative dates: "<N>.<unit>.ago" or "<N> <unit> ago"
+    let parts: Vec<&str> = input
+        .split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    if let [amount, unit, "ago"] = parts.as_slice() {
+        let amount: i64 = amount
+            .parse()
+            .map_err(|_| anyhow!("Invalid date: {}", input))?;
+        let unit = unit.trim_end_matches('s');
+        let duration = match unit {
+            "second" => Duration::seconds(amount),
+            "minute" => Duration::minutes(amount),
+            "hour" => Duration::hours(amount),
+            "day" => Duration::days(amount),
+            "week" => Duration::weeks(amount),
+            "month" => Duration::days(amount * 30),
+            "year" => Duration::days(amount * 365),
+            _ => return Err(anyhow!("Unknown time unit in date: {}", input)),
+        };
+        return Ok(Utc::now() - duration);
+    }
+
+    Err(anyhow!("Invalid date: {}", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let date = parse_date("2024-01-31").unwrap();
+        assert_eq!(date.to_rfc3339(), "2024-01-31T00:00:00+00:00");
+
+        let date = parse_date("2024-01-31T12:00:00+02:00").unwrap();
+        assert_eq!(date.to_rfc3339(), "2024-01-31T10:00:00+00:00");
+
+        let two_weeks = parse_date("2.weeks.ago").unwrap();
+        let expected = Utc::now() - Duration::weeks(2);
+        assert!((expected - two_weeks).num_seconds().abs() < 5);
+
+        let date = parse_date("@1700000000").unwrap();
+        assert_eq!(date.timestamp(), 1_700_000_000);
+
+        assert!(parse_date("3 days ago").is_ok());
+        assert!(parse_date("yesterday-ish").is_err());
+    }
+}
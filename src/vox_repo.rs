@@ -0,0 +1,264 @@
+use crate::commands::index::index::Index;
+use crate::error::VoxError;
+use crate::storage::hash::ObjectFormat;
+use crate::storage::hooks::write_hook_samples;
+use crate::storage::objects::blob::Blob;
+use crate::storage::objects::change::ChangeSet;
+use crate::storage::objects::commit::Commit;
+use crate::storage::objects::tree::{read_tree, Tree};
+use crate::storage::objects::walk::CommitWalker;
+use crate::storage::objects::{read_raw_object, Loadable};
+use crate::storage::refs::{list_refs, resolve_head};
+use crate::storage::repo_config::{check_repository_format, find_vox_dir, RepoConfig};
+use crate::storage::revision::{resolve_commit, resolve_revision};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A vox repository opened at a path, for programs embedding vox
+///
+/// Unlike the commands, which work on the repository of the current
+/// directory, objects and refs are read and written through the paths of
+/// this repository, so a program can hold several at once. Settings aren't:
+/// `core.compressionLevel`, `core.verifyObjects` and the user settings come
+/// from the config of the current directory's repository, read once per
+/// process.
+#[derive(Debug, Clone)]
+pub struct VoxRepo {
+    vox_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+}
+
+impl VoxRepo {
+    /// Opens the repository containing `path`, looking in its parents too
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's no repository there or it's in a format
+    /// this vox doesn't understand
+    pub fn open(path: &Path) -> Result<Self> {
+        let vox_dir = path
+            .ancestors()
+            .find_map(find_vox_dir)
+            .ok_or(VoxError::NotARepository)
+            .with_context(|| format!("No repository at {}", path.display()))?;
+        check_repository_format(&vox_dir)?;
+
+        let work_tree = match RepoConfig::load(&vox_dir)?.is_bare() {
+            true => None,
+            false => vox_dir.parent().map(Path::to_path_buf),
+        };
+        Ok(Self { vox_dir, work_tree })
+    }
+
+    /// Creates an empty repository at `path`, in `path/.vox` unless it's bare
+    pub fn init(path: &Path, object_format: ObjectFormat, bare: bool) -> Result<Self> {
+        Self::create(path, RepoConfig::new(object_format).with_bare(bare))
+    }
+
+    /// Creates an empty repository at `path` with the settings of `config`,
+    /// in `path/.vox` unless it's bare
+    ///
+    /// The config is written before anything else that depends on it, the
+    /// object format must be known before anything is hashed and the key
+    /// before anything is stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there's already a repository there, its config
+    /// would be overwritten
+    pub fn create(path: &Path, config: RepoConfig) -> Result<Self> {
+        let bare = config.is_bare();
+        let vox_dir = match bare {
+            true => path.to_path_buf(),
+            false => path.join(".vox"),
+        };
+        if RepoConfig::path(&vox_dir).exists() {
+            bail!("{} is already a vox repository", vox_dir.display());
+        }
+        for dir in [vox_dir.join("objects"), vox_dir.join("refs")] {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::write(vox_dir.join("HEAD"), "ref: refs/heads/main\n")
+            .context("Failed to write HEAD file")?;
+        config.save(&vox_dir).context("Failed to write config file")?;
+        write_hook_samples(&vox_dir)?;
+
+        let work_tree = match bare {
+            true => None,
+            false => {
+                Index::new()
+                    .write_to_file(&vox_dir.join("index"))
+                    .context("Failed to create index file")?;
+                Some(path.to_path_buf())
+            }
+        };
+        Ok(Self { vox_dir, work_tree })
+    }
+
+    /// Reinitializes the repository at `path`, as `git init` does in an
+    /// existing repository
+    ///
+    /// The directories and hook samples that went missing are created again;
+    /// the config, HEAD and index are left as they are.
+    pub fn reinit(path: &Path, bare: bool) -> Result<Self> {
+        let vox_dir = match bare {
+            true => path.to_path_buf(),
+            false => path.join(".vox"),
+        };
+        for dir in [vox_dir.join("objects"), vox_dir.join("refs")] {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        write_hook_samples(&vox_dir)?;
+        Self::open(path)
+    }
+
+    /// The `.vox` directory, the repository itself when it's bare
+    pub fn vox_dir(&self) -> &Path {
+        &self.vox_dir
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        self.vox_dir.join("objects")
+    }
+
+    /// The working tree, `None` for a bare repository
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn object_format(&self) -> Result<ObjectFormat> {
+        ObjectFormat::for_vox_dir(&self.vox_dir)
+    }
+
+    /// Returns the commit HEAD points to, `None` on an unborn branch
+    pub fn head(&self) -> Result<Option<String>> {
+        resolve_head(&self.vox_dir)
+    }
+
+    /// Lists the refs as `(name, hash)` pairs
+    pub fn refs(&self) -> Result<Vec<(String, String)>> {
+        list_refs(&self.vox_dir)
+    }
+
+    /// Resolves a revision (a hash, a prefix of one, a ref, `HEAD~2`...) to
+    /// the hash of the object it names
+    pub fn resolve(&self, spec: &str) -> Result<String> {
+        resolve_revision(&self.vox_dir, spec)
+    }
+
+    /// Reads an object, returning its type and content
+    pub fn read_object(&self, hash: &str) -> Result<(String, Vec<u8>)> {
+        read_raw_object(hash, &self.objects_dir())
+    }
+
+    pub fn commit(&self, hash: &str) -> Result<Commit> {
+        Commit::load(hash, &self.objects_dir())
+    }
+
+    pub fn tree(&self, hash: &str) -> Result<Tree> {
+        read_tree(hash, &self.objects_dir())
+    }
+
+    pub fn blob(&self, hash: &str) -> Result<Blob> {
+        Blob::load(hash, &self.objects_dir())
+    }
+
+    /// Returns the commits reached from the revision `spec` following
+    /// parents, most recently committed first, at most `limit` of them if
+    /// given
+    pub fn log(&self, spec: &str, limit: Option<usize>) -> Result<Vec<(String, Commit)>> {
+        let mut walker = CommitWalker::new(&self.objects_dir());
+        walker.push(&resolve_commit(&self.vox_dir, spec)?)?;
+        walker.take(limit.unwrap_or(usize::MAX)).collect()
+    }
+
+    /// Compares the trees of the revisions `from` and `to`
+    pub fn diff(&self, from: &str, to: &str) -> Result<ChangeSet> {
+        let tree_of = |spec: &str| -> Result<Tree> {
+            let commit = self.commit(&resolve_commit(&self.vox_dir, spec)?)?;
+            self.tree(&commit.tree)
+        };
+        Tree::compare_trees(&tree_of(from)?, &tree_of(to)?, &self.objects_dir())
+    }
+
+    /// Reads the index, empty for a bare repository
+    pub fn index(&self) -> Result<Index> {
+        let mut index = Index::new();
+        let path = self.vox_dir.join("index");
+        if path.exists() {
+            index.read_from_file(&path)?;
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::objects::tree::TreeEntry;
+    use crate::storage::objects::Storable;
+    use crate::storage::utils::{OBJ_TYPE_BLOB, PERM_FILE};
+    use tempfile::tempdir;
+
+    fn commit_file(repo: &VoxRepo, content: &str, parent: Option<String>) -> Result<String> {
+        let objects_dir = repo.objects_dir();
+        let blob = Blob {
+            data: content.as_bytes().to_vec(),
+        }
+        .save(&objects_dir)?;
+        let tree = Tree {
+            entries: vec![TreeEntry {
+                mode: PERM_FILE.to_string(),
+                object_type: OBJ_TYPE_BLOB.to_string(),
+                object_hash: blob,
+                name: "file.txt".to_string(),
+            }],
+        }
+        .save(&objects_dir)?;
+        let hash = Commit::new(tree, parent, "A <a@x>".into(), content.into()).save(&objects_dir)?;
+        fs::create_dir_all(repo.vox_dir().join("refs/heads"))?;
+        fs::write(repo.vox_dir().join("refs/heads/main"), format!("{}\n", hash))?;
+        Ok(hash)
+    }
+
+    #[test]
+    fn test_open_and_read_history() -> Result<()> {
+        let dir = tempdir()?;
+        let repo = VoxRepo::init(dir.path(), ObjectFormat::Sha1, false)?;
+        assert_eq!(repo.head()?, None);
+
+        let first = commit_file(&repo, "one", None)?;
+        let second = commit_file(&repo, "two", Some(first.clone()))?;
+
+        let nested = dir.path().join("nested");
+        fs::create_dir_all(&nested)?;
+        let repo = VoxRepo::open(&nested)?;
+        assert_eq!(repo.work_tree(), Some(dir.path()));
+        assert_eq!(repo.head()?, Some(second.clone()));
+        assert_eq!(repo.resolve("HEAD~1")?, first);
+
+        let log: Vec<String> = repo.log("main", None)?.into_iter().map(|(hash, _)| hash).collect();
+        assert_eq!(log, vec![second, first]);
+        assert_eq!(repo.log("main", Some(1))?.len(), 1);
+        assert_eq!(repo.diff("HEAD~1", "HEAD")?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reinit_keeps_the_repository() -> Result<()> {
+        let dir = tempdir()?;
+        let repo = VoxRepo::init(dir.path(), ObjectFormat::Sha256, false)?;
+        fs::write(repo.vox_dir().join("HEAD"), "ref: refs/heads/topic\n")?;
+
+        assert!(VoxRepo::init(dir.path(), ObjectFormat::Sha1, false).is_err());
+        fs::remove_dir_all(repo.vox_dir().join("hooks"))?;
+        let repo = VoxRepo::reinit(dir.path(), false)?;
+        assert_eq!(repo.object_format()?, ObjectFormat::Sha256);
+        assert_eq!(fs::read_to_string(repo.vox_dir().join("HEAD"))?, "ref: refs/heads/topic\n");
+        assert!(repo.vox_dir().join("hooks").is_dir());
+        Ok(())
+    }
+}